@@ -0,0 +1,14 @@
+use vergen::EmitBuilder;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Outside of a git checkout, such as in a source tarball, the commit is
+    // left out rather than failing the build
+    EmitBuilder::builder()
+        .cargo_target_triple()
+        .cargo_features()
+        .git_sha(true)
+        .git_dirty(false)
+        .quiet()
+        .emit()?;
+    Ok(())
+}
@@ -19,7 +19,12 @@ fn main() {
         let rustflags = env::var("RUSTFLAGS").unwrap_or_default();
         let is_static_crt = rustflags.contains("target-feature=+crt-static") || is_musl;
 
-        if target.contains("apple") || target.contains("freebsd") || target.contains("openbsd") {
+        if target.contains("emscripten") {
+            // emcc links its own libc++ into WebAssembly builds
+        } else if target.contains("apple")
+            || target.contains("freebsd")
+            || target.contains("openbsd")
+        {
             println!("cargo:rustc-link-lib=dylib=c++");
         } else if is_static_crt {
             println!("cargo:rustc-link-lib=static=stdc++");
@@ -7,12 +7,15 @@ use openmpt_sys;
 use std::os::raw::c_char;
 use std::ptr;
 
+use crate::index::{ChannelIndex, InstrumentIndex};
 use crate::module::ctls::Ctl;
 use crate::module::{Logger, Module};
 
 /// Opaque struct representing an extended module with additional functionality
 pub struct ModuleExt {
     inner: *mut openmpt_sys::openmpt_module_ext,
+    // Kept alive for as long as libopenmpt may log through it
+    _logger: Logger,
 }
 
 impl Drop for ModuleExt {
@@ -26,18 +29,18 @@ impl Drop for ModuleExt {
 impl ModuleExt {
     /// Creates a new extended module from memory data
     #[allow(clippy::result_unit_err)]
-    pub fn from_memory(buffer: &[u8], logger: Logger, _init_ctls: &[Ctl]) -> Result<Self, ()> {
+    pub fn from_memory(buffer: &[u8], logger: Logger, init_ctls: &[Ctl]) -> Result<Self, ()> {
         let module_ext_ptr = unsafe {
             openmpt_sys::openmpt_module_ext_create_from_memory(
                 buffer.as_ptr() as *const _,
                 buffer.len(),
                 logger.log_func(),
-                ptr::null_mut(),
+                logger.log_user(),
                 None, // errfunc
                 ptr::null_mut(),
                 ptr::null_mut(), // error
                 ptr::null_mut(), // error_message
-                ptr::null(),     // ctls - we'll set them manually
+                ptr::null(),     // ctls (Setting those manually below.)
             )
         };
 
@@ -47,10 +50,14 @@ impl ModuleExt {
 
         let module_ext = ModuleExt {
             inner: module_ext_ptr,
+            _logger: logger,
         };
 
-        // Set each init ctl by hand - this would require a more complex implementation
-        // For now, we'll skip this step
+        // Set each init ctl by hand, as Module::create_from_memory does
+        let mut module = module_ext.get_module();
+        for init_ctl in init_ctls {
+            module.enum_ctl_set(init_ctl);
+        }
 
         Ok(module_ext)
     }
@@ -190,6 +197,131 @@ impl ModuleExt {
         }
     }
 
+    /// Render audio data as interleaved stereo, in floating point.
+    ///
+    /// Samples are in the [-1.0..1.0] nominal range, without being clipped to it.
+    ///
+    /// ### Parameters
+    /// * `sample_rate` : Sample rate to render output. Should be in [8000,192000], but this is not enforced.
+    /// * `interleaved_stereo` : Pointer to a buffer for the interleaved stereo output (order : L,R) that will receive an amount of audio frames equal to its capacity divided by the number of channels.
+    ///
+    /// ### Returns
+    /// The number of frames actually rendered (up to half of the buffer's capacity), or 0 if the end of song has been reached.
+    pub fn read_interleaved_float_stereo(
+        &self,
+        sample_rate: i32,
+        interleaved_stereo: &mut [f32],
+    ) -> usize {
+        let count = interleaved_stereo.len() >> 1; // Buffer needs to be of at least size count*2
+
+        let raw_module = unsafe { openmpt_sys::openmpt_module_ext_get_module(self.inner) };
+
+        unsafe {
+            openmpt_sys::openmpt_module_read_interleaved_float_stereo(
+                raw_module,
+                sample_rate,
+                count,
+                interleaved_stereo.as_mut_ptr(),
+            )
+        }
+    }
+
+    /// Render audio data as mono.
+    ///
+    /// ### Parameters
+    /// * `sample_rate` : Sample rate to render output. Should be in [8000,192000], but this is not enforced.
+    /// * `mono` : Pointer to a buffer for the mono/center output that will receive an amount of audio frames equal to its capacity.
+    ///
+    /// ### Returns
+    /// The number of frames actually rendered, or 0 if the end of song has been reached.
+    pub fn read_mono(&self, sample_rate: i32, mono: &mut [i16]) -> usize {
+        let raw_module = unsafe { openmpt_sys::openmpt_module_ext_get_module(self.inner) };
+
+        unsafe {
+            openmpt_sys::openmpt_module_read_mono(
+                raw_module,
+                sample_rate,
+                mono.len(),
+                mono.as_mut_ptr(),
+            )
+        }
+    }
+
+    /// Render audio data as mono, in floating point.
+    ///
+    /// Samples are in the [-1.0..1.0] nominal range, without being clipped to it.
+    ///
+    /// ### Parameters
+    /// * `sample_rate` : Sample rate to render output. Should be in [8000,192000], but this is not enforced.
+    /// * `mono` : Pointer to a buffer for the mono/center output that will receive an amount of audio frames equal to its capacity.
+    ///
+    /// ### Returns
+    /// The number of frames actually rendered, or 0 if the end of song has been reached.
+    pub fn read_float_mono(&self, sample_rate: i32, mono: &mut [f32]) -> usize {
+        let raw_module = unsafe { openmpt_sys::openmpt_module_ext_get_module(self.inner) };
+
+        unsafe {
+            openmpt_sys::openmpt_module_read_float_mono(
+                raw_module,
+                sample_rate,
+                mono.len(),
+                mono.as_mut_ptr(),
+            )
+        }
+    }
+
+    /// Render audio data as interleaved quad.
+    ///
+    /// ### Parameters
+    /// * `sample_rate` : Sample rate to render output. Should be in [8000,192000], but this is not enforced.
+    /// * `interleaved_quad` : Pointer to a buffer for the interleaved quad output (order : L,R,RL,RR) that will receive an amount of audio frames equal to its capacity divided by the number of channels.
+    ///
+    /// ### Returns
+    /// The number of frames actually rendered (up to one fourth of the buffer's capacity), or 0 if the end of song has been reached.
+    pub fn read_interleaved_quad(&self, sample_rate: i32, interleaved_quad: &mut [i16]) -> usize {
+        let count = interleaved_quad.len() >> 2; // Buffer needs to be of at least size count*4
+
+        let raw_module = unsafe { openmpt_sys::openmpt_module_ext_get_module(self.inner) };
+
+        unsafe {
+            openmpt_sys::openmpt_module_read_interleaved_quad(
+                raw_module,
+                sample_rate,
+                count,
+                interleaved_quad.as_mut_ptr(),
+            )
+        }
+    }
+
+    /// Render audio data as interleaved quad, in floating point.
+    ///
+    /// Samples are in the [-1.0..1.0] nominal range, without being clipped to it.
+    ///
+    /// ### Parameters
+    /// * `sample_rate` : Sample rate to render output. Should be in [8000,192000], but this is not enforced.
+    /// * `interleaved_quad` : Pointer to a buffer for the interleaved quad output (order : L,R,RL,RR) that will receive an amount of audio frames equal to its capacity divided by the number of channels.
+    ///
+    /// ### Returns
+    /// The number of frames actually rendered (up to one fourth of the buffer's capacity), or 0 if the end of song has been reached.
+    pub fn read_interleaved_float_quad(
+        &self,
+        sample_rate: i32,
+        interleaved_quad: &mut [f32],
+    ) -> usize {
+        let count = interleaved_quad.len() >> 2; // Buffer needs to be of at least size count*4
+
+        let raw_module = unsafe { openmpt_sys::openmpt_module_ext_get_module(self.inner) };
+
+        unsafe {
+            openmpt_sys::openmpt_module_read_interleaved_float_quad(
+                raw_module,
+                sample_rate,
+                count,
+                interleaved_quad.as_mut_ptr(),
+            )
+        }
+    }
+
     /// Get current song position in seconds.
     ///
     /// ### Returns
@@ -224,10 +356,10 @@ impl<'a> PatternVisInterface<'a> {
         module_ext: &ModuleExt,
         pattern: i32,
         row: i32,
-        channel: i32,
+        channel: ChannelIndex,
     ) -> i32 {
         if let Some(func) = self.inner.get_pattern_row_channel_volume_effect_type {
-            unsafe { func(module_ext.inner, pattern, row, channel) }
+            unsafe { func(module_ext.inner, pattern, row, channel.get()) }
         } else {
             0 // Default to unknown
         }
@@ -239,10 +371,10 @@ impl<'a> PatternVisInterface<'a> {
         module_ext: &ModuleExt,
         pattern: i32,
         row: i32,
-        channel: i32,
+        channel: ChannelIndex,
     ) -> i32 {
         if let Some(func) = self.inner.get_pattern_row_channel_effect_type {
-            unsafe { func(module_ext.inner, pattern, row, channel) }
+            unsafe { func(module_ext.inner, pattern, row, channel.get()) }
         } else {
             0 // Default to unknown
         }
@@ -329,18 +461,23 @@ impl<'a> InteractiveInterface<'a> {
     }
 
     /// Set the current channel volume for a channel
-    pub fn set_channel_volume(&self, module_ext: &ModuleExt, channel: i32, volume: f64) -> bool {
+    pub fn set_channel_volume(
+        &self,
+        module_ext: &ModuleExt,
+        channel: ChannelIndex,
+        volume: f64,
+    ) -> bool {
         if let Some(func) = self.inner.set_channel_volume {
-            unsafe { func(module_ext.inner, channel, volume) == 1 }
+            unsafe { func(module_ext.inner, channel.get(), volume) == 1 }
         } else {
             false
         }
     }
 
     /// Get the current channel volume for a channel
-    pub fn get_channel_volume(&self, module_ext: &ModuleExt, channel: i32) -> f64 {
+    pub fn get_channel_volume(&self, module_ext: &ModuleExt, channel: ChannelIndex) -> f64 {
         if let Some(func) = self.inner.get_channel_volume {
-            unsafe { func(module_ext.inner, channel) }
+            unsafe { func(module_ext.inner, channel.get()) }
         } else {
             0.0 // Default
         }
@@ -350,20 +487,24 @@ impl<'a> InteractiveInterface<'a> {
     pub fn set_channel_mute_status(
         &self,
         module_ext: &ModuleExt,
-        channel: i32,
+        channel: ChannelIndex,
         mute: bool,
     ) -> bool {
         if let Some(func) = self.inner.set_channel_mute_status {
-            unsafe { func(module_ext.inner, channel, if mute { 1 } else { 0 }) == 1 }
+            unsafe { func(module_ext.inner, channel.get(), if mute { 1 } else { 0 }) == 1 }
         } else {
             false
         }
     }
 
     /// Get the current mute status for a channel
-    pub fn get_channel_mute_status(&self, module_ext: &ModuleExt, channel: i32) -> Option<bool> {
+    pub fn get_channel_mute_status(
+        &self,
+        module_ext: &ModuleExt,
+        channel: ChannelIndex,
+    ) -> Option<bool> {
         if let Some(func) = self.inner.get_channel_mute_status {
-            let result = unsafe { func(module_ext.inner, channel) };
+            let result = unsafe { func(module_ext.inner, channel.get()) };
 
             match result {
                 1 => Some(true),
@@ -379,11 +520,11 @@ impl<'a> InteractiveInterface<'a> {
     pub fn set_instrument_mute_status(
         &self,
         module_ext: &ModuleExt,
-        instrument: i32,
+        instrument: InstrumentIndex,
         mute: bool,
     ) -> bool {
         if let Some(func) = self.inner.set_instrument_mute_status {
-            unsafe { func(module_ext.inner, instrument, if mute { 1 } else { 0 }) == 1 }
+            unsafe { func(module_ext.inner, instrument.get(), if mute { 1 } else { 0 }) == 1 }
         } else {
             false
         }
@@ -393,10 +534,10 @@ impl<'a> InteractiveInterface<'a> {
     pub fn get_instrument_mute_status(
         &self,
         module_ext: &ModuleExt,
-        instrument: i32,
+        instrument: InstrumentIndex,
     ) -> Option<bool> {
         if let Some(func) = self.inner.get_instrument_mute_status {
-            let result = unsafe { func(module_ext.inner, instrument) };
+            let result = unsafe { func(module_ext.inner, instrument.get()) };
 
             match result {
                 1 => Some(true),
@@ -412,16 +553,17 @@ impl<'a> InteractiveInterface<'a> {
     pub fn play_note(
         &self,
         module_ext: &ModuleExt,
-        instrument: i32,
+        instrument: InstrumentIndex,
         note: i32,
         volume: f64,
         panning: f64,
-    ) -> Option<i32> {
+    ) -> Option<ChannelIndex> {
         if let Some(func) = self.inner.play_note {
-            let channel = unsafe { func(module_ext.inner, instrument, note, volume, panning) };
+            let channel =
+                unsafe { func(module_ext.inner, instrument.get(), note, volume, panning) };
 
             if channel >= 0 {
-                Some(channel)
+                Some(ChannelIndex::new(channel))
             } else {
                 None // Failed to allocate channel
             }
@@ -431,9 +573,9 @@ impl<'a> InteractiveInterface<'a> {
     }
 
     /// Stop the note playing on the specified channel
-    pub fn stop_note(&self, module_ext: &ModuleExt, channel: i32) -> bool {
+    pub fn stop_note(&self, module_ext: &ModuleExt, channel: ChannelIndex) -> bool {
         if let Some(func) = self.inner.stop_note {
-            unsafe { func(module_ext.inner, channel) == 1 }
+            unsafe { func(module_ext.inner, channel.get()) == 1 }
         } else {
             false
         }
@@ -448,54 +590,64 @@ pub struct Interactive2Interface<'a> {
 
 impl<'a> Interactive2Interface<'a> {
     /// Sends a key-off command for the note playing on the specified channel
-    pub fn note_off(&self, module_ext: &ModuleExt, channel: i32) -> bool {
+    pub fn note_off(&self, module_ext: &ModuleExt, channel: ChannelIndex) -> bool {
         if let Some(func) = self.inner.note_off {
-            unsafe { func(module_ext.inner, channel) == 1 }
+            unsafe { func(module_ext.inner, channel.get()) == 1 }
         } else {
             false
         }
     }
 
     /// Sends a note fade command for the note playing on the specified channel
-    pub fn note_fade(&self, module_ext: &ModuleExt, channel: i32) -> bool {
+    pub fn note_fade(&self, module_ext: &ModuleExt, channel: ChannelIndex) -> bool {
         if let Some(func) = self.inner.note_fade {
-            unsafe { func(module_ext.inner, channel) == 1 }
+            unsafe { func(module_ext.inner, channel.get()) == 1 }
         } else {
             false
         }
     }
 
     /// Set the current panning for a channel
-    pub fn set_channel_panning(&self, module_ext: &ModuleExt, channel: i32, panning: f64) -> bool {
+    pub fn set_channel_panning(
+        &self,
+        module_ext: &ModuleExt,
+        channel: ChannelIndex,
+        panning: f64,
+    ) -> bool {
         if let Some(func) = self.inner.set_channel_panning {
-            unsafe { func(module_ext.inner, channel, panning) == 1 }
+            unsafe { func(module_ext.inner, channel.get(), panning) == 1 }
         } else {
             false
         }
     }
 
     /// Get the current panning position for a channel
-    pub fn get_channel_panning(&self, module_ext: &ModuleExt, channel: i32) -> f64 {
+    pub fn get_channel_panning(&self, module_ext: &ModuleExt, channel: ChannelIndex) -> f64 {
         if let Some(func) = self.inner.get_channel_panning {
-            unsafe { func(module_ext.inner, channel) }
+            unsafe { func(module_ext.inner, channel.get()) }
         } else {
             0.0 // Center
         }
     }
 
     /// Set the finetune for the currently playing note on a channel
-    pub fn set_note_finetune(&self, module_ext: &ModuleExt, channel: i32, finetune: f64) -> bool {
+    pub fn set_note_finetune(
+        &self,
+        module_ext: &ModuleExt,
+        channel: ChannelIndex,
+        finetune: f64,
+    ) -> bool {
         if let Some(func) = self.inner.set_note_finetune {
-            unsafe { func(module_ext.inner, channel, finetune) == 1 }
+            unsafe { func(module_ext.inner, channel.get(), finetune) == 1 }
         } else {
             false
         }
     }
 
     /// Get the finetune for the currently playing note on a channel
-    pub fn get_note_finetune(&self, module_ext: &ModuleExt, channel: i32) -> f64 {
+    pub fn get_note_finetune(&self, module_ext: &ModuleExt, channel: ChannelIndex) -> f64 {
         if let Some(func) = self.inner.get_note_finetune {
-            unsafe { func(module_ext.inner, channel) }
+            unsafe { func(module_ext.inner, channel.get()) }
         } else {
             0.0 // No finetune
         }
@@ -0,0 +1,101 @@
+//! Typed indices of channels, instruments, samples and sub-songs.
+//!
+//! libopenmpt numbers everything from 0, while trackers and users number
+//! instruments and samples from 1. These types hold the 0-based index passed
+//! to libopenmpt, and only show the 1-based number when displayed or
+//! converted with `number`.
+
+use std::fmt;
+
+macro_rules! index_type {
+    ( $(#[$meta:meta])* $name:ident ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(i32);
+
+        impl $name {
+            /// Index from its 0-based value, as used by libopenmpt.
+            pub const fn new(index: i32) -> Self {
+                $name(index)
+            }
+
+            /// Index from its 1-based number, as shown to users.
+            ///
+            /// Returns `None` for numbers below 1.
+            pub fn from_number(number: i32) -> Option<Self> {
+                (number >= 1).then(|| $name(number - 1))
+            }
+
+            /// 0-based value, as used by libopenmpt.
+            pub const fn get(self) -> i32 {
+                self.0
+            }
+
+            /// 1-based number, as shown to users.
+            pub const fn number(self) -> i32 {
+                self.0 + 1
+            }
+
+            /// All indices below `count`, in ascending order.
+            pub fn all(count: i32) -> impl Iterator<Item = Self> {
+                (0..count).map($name)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", self.number())
+            }
+        }
+    };
+}
+
+index_type! {
+    /// Index of a pattern channel.
+    ChannelIndex
+}
+
+index_type! {
+    /// Index of an instrument.
+    ///
+    /// In modules without instruments, libopenmpt's instrument functions
+    /// address samples instead, see the conversion from [`SampleIndex`].
+    InstrumentIndex
+}
+
+index_type! {
+    /// Index of a sample.
+    SampleIndex
+}
+
+index_type! {
+    /// Index of a sub-song.
+    SubsongIndex
+}
+
+/// Modules without instruments play their samples directly, and libopenmpt's
+/// instrument functions (such as muting) then take sample indices.
+impl From<SampleIndex> for InstrumentIndex {
+    fn from(sample: SampleIndex) -> Self {
+        InstrumentIndex(sample.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_are_one_based() {
+        let instrument = InstrumentIndex::from_number(3).unwrap();
+        assert_eq!(instrument.get(), 2);
+        assert_eq!(instrument.number(), 3);
+        assert_eq!(instrument.to_string(), "3");
+        assert_eq!(InstrumentIndex::from_number(0), None);
+
+        let samples: Vec<i32> = SampleIndex::all(3).map(SampleIndex::get).collect();
+        assert_eq!(samples, vec![0, 1, 2]);
+        assert_eq!(InstrumentIndex::from(SampleIndex::new(4)).number(), 5);
+        assert_eq!(ChannelIndex::new(0).to_string(), "1");
+    }
+}
@@ -7,6 +7,7 @@ extern crate openmpt_sys;
 #[macro_use]
 mod string_helper;
 pub mod ext;
+pub mod index;
 pub mod info;
 pub mod mod_command;
 pub mod module;
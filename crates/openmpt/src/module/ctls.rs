@@ -14,6 +14,7 @@ const SEEK_SYNC_SAMPLES: &str = "seek.sync_samples";
 const PLAY_TEMPO_FACTOR: &str = "play.tempo_factor";
 const PLAY_PITCH_FACTOR: &str = "play.pitch_factor";
 const DITHER: &str = "dither";
+const PLAY_AT_END: &str = "play.at_end";
 
 #[derive(PartialEq, Debug)]
 pub enum DitherMode {
@@ -41,6 +42,31 @@ impl FromStr for DitherMode {
     }
 }
 
+/// What happens once the end of the song is reached, after the number of
+/// repetitions set with `set_repeat_count`. Requires libopenmpt 0.7 or later.
+#[derive(PartialEq, Debug)]
+pub enum PlayAtEnd {
+    /// Default mode. Fade out for a short while, then return 0 rendered frames.
+    Fadeout,
+    /// Return 0 rendered frames once, then continue playing from the loop start.
+    Continue,
+    /// Return 0 rendered frames from then on.
+    Stop,
+}
+
+impl FromStr for PlayAtEnd {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fadeout" => Ok(PlayAtEnd::Fadeout),
+            "continue" => Ok(PlayAtEnd::Continue),
+            "stop" => Ok(PlayAtEnd::Stop),
+            _ => Err("Failed to parse return value as known end of song behaviour"),
+        }
+    }
+}
+
 /// Ctls to use with `create`, `create_from_memory`, and
 /// `could_open_propability` in lists of initial ctls.
 pub enum Ctl {
@@ -63,6 +89,11 @@ pub enum Ctl {
     PlaybackPitchFactor(c_double),
     /// Set the dither algorithm that is used for the 16 bit versions of the rendering methods.
     DitherMode16Bit(DitherMode),
+    /// Set what happens once the end of the song is reached.
+    PlaybackAtEnd(PlayAtEnd),
+    /// Set any other ctl, by its key and value as strings, such as
+    /// `render.resampler.emulate_amiga` and `1`. See `get_ctls` for the keys.
+    Custom(String, String),
 }
 
 impl Ctl {
@@ -76,6 +107,8 @@ impl Ctl {
             Ctl::PlaybackTempoFactor(_) => PLAY_TEMPO_FACTOR,
             Ctl::PlaybackPitchFactor(_) => PLAY_PITCH_FACTOR,
             Ctl::DitherMode16Bit(_) => DITHER,
+            Ctl::PlaybackAtEnd(_) => PLAY_AT_END,
+            Ctl::Custom(ref key, _) => key.as_str(),
         }
         .to_owned()
     }
@@ -97,6 +130,13 @@ impl Ctl {
                 DitherMode::Simple => "3",
             }
             .to_owned(),
+            PlaybackAtEnd(ref param) => match *param {
+                PlayAtEnd::Fadeout => "fadeout",
+                PlayAtEnd::Continue => "continue",
+                PlayAtEnd::Stop => "stop",
+            }
+            .to_owned(),
+            Custom(_, ref value) => value.clone(),
         }
     }
 }
@@ -230,6 +270,22 @@ impl Module {
         self.enum_ctl_set(&Ctl::DitherMode16Bit(value))
     }
 
+    /// Get what happens once the end of the song is reached.
+    pub fn ctl_get_play_at_end(&mut self) -> Option<PlayAtEnd> {
+        let return_val = self.ctl_get(PLAY_AT_END);
+
+        if let Some(ref str_val) = return_val {
+            PlayAtEnd::from_str(str_val).ok()
+        } else {
+            None
+        }
+    }
+
+    /// Set what happens once the end of the song is reached.
+    pub fn ctl_set_play_at_end(&mut self, value: PlayAtEnd) -> bool {
+        self.enum_ctl_set(&Ctl::PlaybackAtEnd(value))
+    }
+
     /// Get ctl value directly, as a string.
     ///
     /// ### Parameters
@@ -243,7 +299,7 @@ impl Module {
         })
     }
 
-    pub(super) fn enum_ctl_set(&mut self, ctl: &Ctl) -> bool {
+    pub(crate) fn enum_ctl_set(&mut self, ctl: &Ctl) -> bool {
         let key = ctl.key_to_str();
         let val = ctl.param_to_str();
 
@@ -345,6 +401,26 @@ mod tests {
         assert_eq!(module.ctl_get_dither().unwrap(), DitherMode::Simple);
     }
 
+    #[test]
+    fn custom_initial_ctls_are_respected() {
+        let initial_ctls = vec![Ctl::Custom(
+            "render.resampler.emulate_amiga".to_owned(),
+            "1".to_owned(),
+        )];
+
+        let mut module = test_helper::load_file_as_module_with_ctls(
+            "empty_module.xm",
+            Logger::None,
+            &initial_ctls,
+        )
+        .unwrap();
+
+        assert_eq!(
+            module.ctl_get("render.resampler.emulate_amiga").unwrap(),
+            "1"
+        );
+    }
+
     #[test]
     fn clean_result_for_getting_unknown_ctl() {
         let mut module = test_helper::load_file_as_module("empty_module.xm").unwrap();
@@ -2,6 +2,7 @@
 //! the current state of the module
 
 use super::Module;
+use crate::index::ChannelIndex;
 use openmpt_sys;
 use std::os::raw::*;
 
@@ -25,6 +26,14 @@ impl Module {
         return_code != 0
     }
 
+    /// Get the currently selected sub-song from a multi-song module.
+    ///
+    /// ### Returns
+    /// The index of the selected sub-song, or -1 if all sub-songs are played consecutively.
+    pub fn get_selected_subsong(&mut self) -> i32 {
+        unsafe { openmpt_sys::openmpt_module_get_selected_subsong(self.inner) }
+    }
+
     /// Set approximate current song position.
     ///
     /// ### Parameters
@@ -104,6 +113,14 @@ impl Module {
         unsafe { openmpt_sys::openmpt_module_get_current_tempo(self.inner) }
     }
 
+    /// Get the current tempo, including its fractional part.
+    ///
+    /// ### Returns
+    /// The current tempo in tracker units. The exact meaning of this value depends on the tempo mode being used.
+    pub fn get_current_tempo2(&mut self) -> c_double {
+        unsafe { openmpt_sys::openmpt_module_get_current_tempo2(self.inner) }
+    }
+
     /// Get the current amount of playing channels.
     ///
     /// ### Returns
@@ -132,8 +149,10 @@ impl Module {
     /// ### Remarks
     /// The returned value is solely based on the note velocity and
     /// does not take the actual waveform of the playing sample into account.
-    pub fn get_current_channel_vu_mono(&mut self, channel_num: i32) -> c_float {
-        unsafe { openmpt_sys::openmpt_module_get_current_channel_vu_mono(self.inner, channel_num) }
+    pub fn get_current_channel_vu_mono(&mut self, channel_num: ChannelIndex) -> c_float {
+        unsafe {
+            openmpt_sys::openmpt_module_get_current_channel_vu_mono(self.inner, channel_num.get())
+        }
     }
 
     /// Get an approximate indication of the channel volume on the front-left speaker.
@@ -147,8 +166,10 @@ impl Module {
     /// ### Remarks
     /// The returned value is solely based on the note velocity and
     /// does not take the actual waveform of the playing sample into account.
-    pub fn get_current_channel_vu_left(&mut self, channel_num: i32) -> c_float {
-        unsafe { openmpt_sys::openmpt_module_get_current_channel_vu_left(self.inner, channel_num) }
+    pub fn get_current_channel_vu_left(&mut self, channel_num: ChannelIndex) -> c_float {
+        unsafe {
+            openmpt_sys::openmpt_module_get_current_channel_vu_left(self.inner, channel_num.get())
+        }
     }
 
     /// Get an approximate indication of the channel volume on the front-right speaker.
@@ -162,8 +183,10 @@ impl Module {
     /// ### Remarks
     /// The returned value is solely based on the note velocity and
     /// does not take the actual waveform of the playing sample into account.
-    pub fn get_current_channel_vu_right(&mut self, channel_num: i32) -> c_float {
-        unsafe { openmpt_sys::openmpt_module_get_current_channel_vu_right(self.inner, channel_num) }
+    pub fn get_current_channel_vu_right(&mut self, channel_num: ChannelIndex) -> c_float {
+        unsafe {
+            openmpt_sys::openmpt_module_get_current_channel_vu_right(self.inner, channel_num.get())
+        }
     }
 
     /// Get an approximate indication of the channel volume on the rear-left speaker.
@@ -177,9 +200,12 @@ impl Module {
     /// ### Remarks
     /// The returned value is solely based on the note velocity and
     /// does not take the actual waveform of the playing sample into account.
-    pub fn get_current_channel_vu_rear_left(&mut self, channel_num: i32) -> c_float {
+    pub fn get_current_channel_vu_rear_left(&mut self, channel_num: ChannelIndex) -> c_float {
         unsafe {
-            openmpt_sys::openmpt_module_get_current_channel_vu_rear_left(self.inner, channel_num)
+            openmpt_sys::openmpt_module_get_current_channel_vu_rear_left(
+                self.inner,
+                channel_num.get(),
+            )
         }
     }
 
@@ -194,9 +220,12 @@ impl Module {
     /// ### Remarks
     /// The returned value is solely based on the note velocity and
     /// does not take the actual waveform of the playing sample into account.
-    pub fn get_current_channel_vu_rear_right(&mut self, channel_num: i32) -> c_float {
+    pub fn get_current_channel_vu_rear_right(&mut self, channel_num: ChannelIndex) -> c_float {
         unsafe {
-            openmpt_sys::openmpt_module_get_current_channel_vu_rear_right(self.inner, channel_num)
+            openmpt_sys::openmpt_module_get_current_channel_vu_rear_right(
+                self.inner,
+                channel_num.get(),
+            )
         }
     }
 }
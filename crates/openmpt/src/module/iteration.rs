@@ -3,6 +3,7 @@
 
 use super::super::mod_command::ModCommand;
 use super::Module;
+use crate::index::{ChannelIndex, InstrumentIndex, SampleIndex, SubsongIndex};
 use openmpt_sys;
 use std::os::raw::c_int;
 
@@ -62,6 +63,37 @@ impl Module {
         }
     }
 
+    /// Get the index of the pattern at an order position, without borrowing
+    /// the module the way `get_pattern_by_order` does.
+    ///
+    /// ### Parameters
+    /// * `order_num` : The order position whose pattern index should be retrieved.
+    ///
+    /// ### Returns
+    /// The index of the pattern at the given order position of the current sequence,
+    /// or None for positions past the end and for separator or end markers.
+    pub fn get_order_pattern(&mut self, order_num: i32) -> Option<i32> {
+        let pattern_num =
+            unsafe { openmpt_sys::openmpt_module_get_order_pattern(self.inner, order_num) };
+
+        if pattern_num < 0 || pattern_num >= self.get_num_patterns() {
+            None
+        } else {
+            Some(pattern_num)
+        }
+    }
+
+    /// Get the number of rows of a pattern, by index.
+    ///
+    /// ### Parameters
+    /// * `pattern_num` : The index of the pattern whose rows should be counted.
+    ///
+    /// ### Returns
+    /// The number of rows in the pattern, or 0 if no such pattern exists.
+    pub fn get_pattern_num_rows(&mut self, pattern_num: i32) -> i32 {
+        unsafe { openmpt_sys::openmpt_module_get_pattern_num_rows(self.inner, pattern_num) }
+    }
+
     /// Get the number of distinct patterns for that module.
     ///
     /// ### Returns
@@ -127,9 +159,9 @@ impl Module {
     ///
     /// ### Returns
     /// The instrument name.
-    pub fn get_instrument_name(&mut self, instrument_num: i32) -> String {
+    pub fn get_instrument_name(&mut self, instrument_num: InstrumentIndex) -> String {
         let opt_string = get_string! {
-            openmpt_sys::openmpt_module_get_instrument_name(self.inner, instrument_num)
+            openmpt_sys::openmpt_module_get_instrument_name(self.inner, instrument_num.get())
         };
 
         opt_string.expect("Got null pointer instead of string")
@@ -142,9 +174,9 @@ impl Module {
     ///
     /// ### Returns
     /// The sample name.
-    pub fn get_sample_name(&mut self, sample_num: i32) -> String {
+    pub fn get_sample_name(&mut self, sample_num: SampleIndex) -> String {
         let opt_string = get_string! {
-            openmpt_sys::openmpt_module_get_sample_name(self.inner, sample_num)
+            openmpt_sys::openmpt_module_get_sample_name(self.inner, sample_num.get())
         };
 
         opt_string.expect("Got null pointer instead of string")
@@ -157,9 +189,9 @@ impl Module {
     ///
     /// ### Returns
     /// The channel name.
-    pub fn get_channel_name(&mut self, channel_num: i32) -> String {
+    pub fn get_channel_name(&mut self, channel_num: ChannelIndex) -> String {
         let opt_string = get_string! {
-            openmpt_sys::openmpt_module_get_channel_name(self.inner, channel_num)
+            openmpt_sys::openmpt_module_get_channel_name(self.inner, channel_num.get())
         };
 
         opt_string.expect("Got null pointer instead of string")
@@ -172,9 +204,9 @@ impl Module {
     ///
     /// ### Returns
     /// The sub-song name.
-    pub fn get_subsong_name(&mut self, subsong_num: i32) -> String {
+    pub fn get_subsong_name(&mut self, subsong_num: SubsongIndex) -> String {
         let opt_string = get_string! {
-            openmpt_sys::openmpt_module_get_subsong_name(self.inner, subsong_num)
+            openmpt_sys::openmpt_module_get_subsong_name(self.inner, subsong_num.get())
         };
 
         opt_string.expect("Got null pointer instead of string")
@@ -204,6 +236,11 @@ impl<'m> Pattern<'m> {
         }
     }
 
+    /// Get the index of this pattern.
+    pub fn get_number(&self) -> i32 {
+        self.num
+    }
+
     /// Get name for this pattern/order.
     ///
     /// ### Returns
@@ -230,15 +267,19 @@ impl<'p, 'm> Row<'p, 'm> {
     /// Get pattern cell by pattern channel.
     ///
     /// ### Parameters
-    /// * `channel_num` : The index of the pattern channel at which the cell should be retrieved.
+    /// * `channel` : The pattern channel at which the cell should be retrieved.
     ///
     /// ### Returns
     /// A Cell wrapper for the cell, or None if the channel doesn't exist.
-    pub fn get_cell_by_channel<'r>(&'r mut self, channel_num: i32) -> Option<Cell<'r, 'p, 'm>> {
+    pub fn get_cell_by_channel<'r>(
+        &'r mut self,
+        channel: ChannelIndex,
+    ) -> Option<Cell<'r, 'p, 'm>> {
         assert!(self.num < self.pattern.get_num_rows());
         assert!(self.num >= 0);
 
         let num_channels = self.pattern.module.get_num_channels();
+        let channel_num = channel.get();
 
         if channel_num < 0 || channel_num >= num_channels {
             None
@@ -402,6 +443,7 @@ impl ModuleCommandIndex {
 #[cfg(test)]
 mod tests {
     use super::super::test_helper;
+    use crate::index::{ChannelIndex, InstrumentIndex, SampleIndex, SubsongIndex};
 
     #[test]
     fn empty_module_list_names() {
@@ -414,22 +456,31 @@ mod tests {
         }
 
         if module.get_num_channels() > 0 {
-            println!("Name of Channel #0 : {:?}", module.get_channel_name(0));
+            println!(
+                "Name of Channel #0 : {:?}",
+                module.get_channel_name(ChannelIndex::new(0))
+            );
         }
 
         if module.get_num_instruments() > 0 {
             println!(
                 "Name of Instrument #0 : {:?}",
-                module.get_instrument_name(0)
+                module.get_instrument_name(InstrumentIndex::new(0))
             );
         }
 
         if module.get_num_samples() > 0 {
-            println!("Name of Sample #0 : {:?}", module.get_sample_name(0));
+            println!(
+                "Name of Sample #0 : {:?}",
+                module.get_sample_name(SampleIndex::new(0))
+            );
         }
 
         if module.get_num_subsongs() > 0 {
-            println!("Name of Subsong #0 : {:?}", module.get_subsong_name(0));
+            println!(
+                "Name of Subsong #0 : {:?}",
+                module.get_subsong_name(SubsongIndex::new(0))
+            );
         }
     }
 
@@ -438,6 +489,21 @@ mod tests {
         iterative_reading("UNATCO.it");
     }
 
+    #[test]
+    fn unatco_order_patterns() {
+        let mut module = test_helper::load_file_as_module("UNATCO.it").unwrap();
+        let num_orders = module.get_num_orders();
+
+        for order_num in 0..num_orders {
+            let pattern_num = module.get_order_pattern(order_num).unwrap();
+            let pattern_rows = module.get_pattern_num_rows(pattern_num);
+            let mut pattern = module.get_pattern_by_order(order_num).unwrap();
+            assert_eq!(pattern.get_number(), pattern_num);
+            assert_eq!(pattern.get_num_rows(), pattern_rows);
+        }
+        assert_eq!(module.get_order_pattern(num_orders), None);
+    }
+
     fn iterative_reading(file_name: &str) {
         let mut module = test_helper::load_file_as_module(file_name).unwrap();
         let num_orders = module.get_num_orders();
@@ -456,11 +522,11 @@ mod tests {
                 let mut row = pattern.get_row_by_number(row_num).unwrap();
                 let mut row_string = String::new();
 
-                for channel_num in 0..num_channels {
-                    let mut cell = row.get_cell_by_channel(channel_num).unwrap();
+                for channel in ChannelIndex::all(num_channels) {
+                    let mut cell = row.get_cell_by_channel(channel).unwrap();
                     assert!(cell.get_data().is_ok());
 
-                    if channel_num != 0 {
+                    if channel.get() != 0 {
                         row_string.push_str("|");
                     }
                     row_string.push_str(cell.get_formatted(0, false).as_str());
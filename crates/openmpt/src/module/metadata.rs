@@ -13,6 +13,10 @@ pub enum MetadataKey {
     ContainerExt,
     /// Full container name if the module is embedded in a container (e.g. Unreal Music)
     ContainerName,
+    /// Format extension of the original file, if the module was converted on load (e.g. mod for an imported format)
+    OriginalTypeExt,
+    /// Tracker name of the original file, if the module was converted on load
+    OriginalTypeName,
     /// Module title
     ModuleTitle,
     /// Author of the module
@@ -37,6 +41,8 @@ impl MetadataKey {
             TypeName => "type_long",
             ContainerExt => "container",
             ContainerName => "container_long",
+            OriginalTypeExt => "originaltype",
+            OriginalTypeName => "originaltype_long",
             ModuleTitle => "title",
             ModuleArtist => "artist",
             ModuleTracker => "tracker",
@@ -48,27 +54,71 @@ impl MetadataKey {
     }
 }
 
+impl AsRef<str> for MetadataKey {
+    fn as_ref(&self) -> &str {
+        self.to_str()
+    }
+}
+
 impl Module {
     /// Get a metadata item value.
     ///
     /// ### Parameters
-    /// * `key` : Metadata item key to query, from the `MetadataKey` enum.
+    /// * `key` : Metadata item key to query, from the `MetadataKey` enum or
+    ///   any key returned by `get_metadata_keys`.
     ///
     /// ### Returns
     /// The associated value for key, or None in case of error.
-    pub fn get_metadata(&mut self, key: MetadataKey) -> Option<String> {
-        let key = key.to_str();
+    pub fn get_metadata<K: AsRef<str>>(&mut self, key: K) -> Option<String> {
+        let key = key.as_ref();
         get_string_with_string!(key, {
             openmpt_sys::openmpt_module_get_metadata(self.inner, key)
         })
     }
 
-    pub fn get_metadata_keys(&mut self) -> String {
+    /// Get the metadata item keys supported by the module.
+    ///
+    /// ### Returns
+    /// The keys that can be passed to `get_metadata`, which may include keys
+    /// not covered by the `MetadataKey` enum in newer libopenmpt versions.
+    pub fn get_metadata_keys(&mut self) -> Vec<String> {
         let opt_string = get_string! {
             openmpt_sys::openmpt_module_get_metadata_keys(self.inner)
         };
 
-        opt_string.expect("Got null pointer instead of string")
+        opt_string
+            .expect("Got null pointer instead of string")
+            .split(';')
+            .filter(|key| !key.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Get a metadata item, or None if the module leaves it empty.
+    fn get_metadata_value(&mut self, key: MetadataKey) -> Option<String> {
+        self.get_metadata(key)
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    }
+
+    /// Get the module title, if it has one.
+    pub fn title(&mut self) -> Option<String> {
+        self.get_metadata_value(MetadataKey::ModuleTitle)
+    }
+
+    /// Get the author of the module, if known.
+    pub fn artist(&mut self) -> Option<String> {
+        self.get_metadata_value(MetadataKey::ModuleArtist)
+    }
+
+    /// Get the tracker that (most likely) saved the module, if known.
+    pub fn tracker(&mut self) -> Option<String> {
+        self.get_metadata_value(MetadataKey::ModuleTracker)
+    }
+
+    /// Get the song message, if the module has one.
+    pub fn message(&mut self) -> Option<String> {
+        self.get_metadata_value(MetadataKey::SongMessage)
     }
 }
 
@@ -83,6 +133,15 @@ mod tests {
         assert_eq!(module.get_metadata(MetadataKey::TypeExt).unwrap(), "xm");
         let type_name = module.get_metadata(MetadataKey::TypeName).unwrap();
         assert!(type_name.contains("FastTracker"));
+        assert_eq!(module.get_metadata("type").unwrap(), "xm");
+    }
+
+    #[test]
+    fn empty_metadata_is_none() {
+        let mut module = test_helper::load_file_as_module("empty_module.xm").unwrap();
+        let title = module.get_metadata(MetadataKey::ModuleTitle).unwrap();
+        assert_eq!(module.title().is_some(), !title.trim().is_empty());
+        assert_eq!(module.message(), None);
     }
 
     #[test]
@@ -90,16 +149,34 @@ mod tests {
         let mut module = test_helper::load_file_as_module("empty_module.xm").unwrap();
         let keys = module.get_metadata_keys();
 
-        assert!(keys.contains(MetadataKey::TypeExt.to_str()));
-        assert!(keys.contains(MetadataKey::TypeName.to_str()));
-        assert!(keys.contains(MetadataKey::ContainerExt.to_str()));
-        assert!(keys.contains(MetadataKey::ContainerName.to_str()));
-        assert!(keys.contains(MetadataKey::ModuleTitle.to_str()));
-        assert!(keys.contains(MetadataKey::ModuleArtist.to_str()));
-        assert!(keys.contains(MetadataKey::ModuleTracker.to_str()));
-        assert!(keys.contains(MetadataKey::ModuleSaveDate.to_str()));
-        assert!(keys.contains(MetadataKey::SongMessage.to_str()));
-        assert!(keys.contains(MetadataKey::SongMessageOrInstruments.to_str()));
-        assert!(keys.contains(MetadataKey::LoadWarnings.to_str()));
+        assert!(keys.iter().any(|key| key == MetadataKey::TypeExt.to_str()));
+        assert!(keys.iter().any(|key| key == MetadataKey::TypeName.to_str()));
+        assert!(keys
+            .iter()
+            .any(|key| key == MetadataKey::ContainerExt.to_str()));
+        assert!(keys
+            .iter()
+            .any(|key| key == MetadataKey::ContainerName.to_str()));
+        assert!(keys
+            .iter()
+            .any(|key| key == MetadataKey::ModuleTitle.to_str()));
+        assert!(keys
+            .iter()
+            .any(|key| key == MetadataKey::ModuleArtist.to_str()));
+        assert!(keys
+            .iter()
+            .any(|key| key == MetadataKey::ModuleTracker.to_str()));
+        assert!(keys
+            .iter()
+            .any(|key| key == MetadataKey::ModuleSaveDate.to_str()));
+        assert!(keys
+            .iter()
+            .any(|key| key == MetadataKey::SongMessage.to_str()));
+        assert!(keys
+            .iter()
+            .any(|key| key == MetadataKey::SongMessageOrInstruments.to_str()));
+        assert!(keys
+            .iter()
+            .any(|key| key == MetadataKey::LoadWarnings.to_str()));
     }
 }
@@ -17,8 +17,10 @@
 //! the underlying object can ever be made.
 
 use openmpt_sys;
+use std::ffi::CStr;
 use std::os::raw::*;
 use std::ptr;
+use std::sync::{Arc, Mutex};
 
 pub mod ctls;
 pub mod current;
@@ -32,6 +34,8 @@ mod test_helper;
 pub struct Module {
     inner: *mut openmpt_sys::openmpt_module,
     owned: bool,
+    // Kept alive for as long as libopenmpt may log through it
+    _logger: Logger,
 }
 
 impl Drop for Module {
@@ -68,8 +72,8 @@ impl Module {
                 buffer.as_ptr() as *const _,
                 buffer.len(),
                 logger.log_func(),
-                ptr::null_mut(), // user (As unsafe as it gets! Not touching this.)
-                ptr::null(),     // init_ctls (Setting those manually below.)
+                logger.log_user(),
+                ptr::null(), // init_ctls (Setting those manually below.)
             )
         };
 
@@ -80,6 +84,7 @@ impl Module {
         let mut module = Module {
             inner: module_ptr,
             owned: true,
+            _logger: logger,
         };
 
         // Set each init ctl by hand, lists of stucts of FFI string pointers are too much of a nightmare to deal with in Rust
@@ -115,8 +120,8 @@ impl Module {
                 T::get_file_callbacks(),
                 stream_ptr as *mut _,
                 logger.log_func(),
-                ptr::null_mut(), // user (As unsafe as it gets! Not touching this.)
-                ptr::null(),     // init_ctls (Setting those manually below.)
+                logger.log_user(),
+                ptr::null(), // init_ctls (Setting those manually below.)
             )
         };
 
@@ -127,6 +132,7 @@ impl Module {
         let mut module = Module {
             inner: module_ptr,
             owned: true,
+            _logger: logger,
         };
 
         // Set each init ctl by hand, lists of stucts of FFI string pointers are too much of a nightmare to deal with in Rust
@@ -143,7 +149,11 @@ impl Module {
     /// This function is unsafe because it takes a raw pointer.
     /// The `owned` parameter specifies if the Module should destroy the pointer on drop.
     pub(crate) fn from_raw_pointer(ptr: *mut openmpt_sys::openmpt_module, owned: bool) -> Self {
-        Module { inner: ptr, owned }
+        Module {
+            inner: ptr,
+            owned,
+            _logger: Logger::None,
+        }
     }
 }
 
@@ -178,17 +188,68 @@ impl CouldOpenEffort {
 pub enum Logger {
     StdErr,
     None,
+    /// Collect the messages into a `LogCapture`
+    Capture(LogCapture),
 }
 
 impl Logger {
-    pub(super) fn log_func(&self) -> openmpt_sys::openmpt_log_func {
+    pub(crate) fn log_func(&self) -> openmpt_sys::openmpt_log_func {
         match *self {
             Logger::StdErr => Some(openmpt_sys::openmpt_log_func_default),
             Logger::None => Some(openmpt_sys::openmpt_log_func_silent),
+            Logger::Capture(_) => Some(capture_log_func),
+        }
+    }
+
+    /// The user pointer to hand to libopenmpt along with `log_func`.
+    pub(crate) fn log_user(&self) -> *mut c_void {
+        match self {
+            Logger::Capture(capture) => Arc::as_ptr(&capture.messages) as *mut c_void,
+            _ => ptr::null_mut(),
         }
     }
 }
 
+/// Messages logged by libopenmpt for the modules created with `Logger::Capture`.
+///
+/// Clones share the same messages, so one can be kept to read what a module
+/// logged while it was loaded or played.
+#[derive(Debug, Clone, Default)]
+pub struct LogCapture {
+    messages: Arc<Mutex<Vec<String>>>,
+}
+
+impl LogCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The messages logged so far, oldest first.
+    pub fn messages(&self) -> Vec<String> {
+        self.messages.lock().map(|m| m.clone()).unwrap_or_default()
+    }
+
+    /// Return the messages logged so far and forget them.
+    pub fn take(&self) -> Vec<String> {
+        self.messages
+            .lock()
+            .map(|mut m| std::mem::take(&mut *m))
+            .unwrap_or_default()
+    }
+}
+
+unsafe extern "C" fn capture_log_func(message: *const c_char, user: *mut c_void) {
+    if message.is_null() || user.is_null() {
+        return;
+    }
+    // `user` comes from `Logger::log_user`, and the module keeps the capture alive
+    let messages = &*(user as *const Mutex<Vec<String>>);
+    let message = CStr::from_ptr(message).to_string_lossy().into_owned();
+    if let Ok(mut messages) = messages.lock() {
+        messages.push(message);
+    }
+}
+
 /// Roughly scan the input stream to find out whether libopenmpt might be able to open it.
 ///
 /// ### Parameters
@@ -223,9 +284,55 @@ pub fn could_open_propability<T: stream::ModuleStream>(
             stream_ptr as *mut _,
             effort.value(),
             logger.log_func(),
+            logger.log_user(),
+        )
+    }
+}
+
+/// Roughly scan the input stream to find out whether libopenmpt might be able to open it,
+/// like `could_open_propability`, and report why the scan failed if it did.
+///
+/// ### Parameters
+/// * `stream` : Input stream to scan. Must implement the `Read` trait.
+/// * `effort` : Effort to make when validating stream, from the `CouldOpenEffort` enum.
+/// * `logger` : The logging function to use, from the `Logger` enum.
+///
+/// ### Returns
+/// Probability between 0.0 and 1.0, read as for `could_open_propability`,
+/// or the error message of libopenmpt if the stream could not be scanned (e.g. out of memory).
+pub fn could_open_probability<T: stream::ModuleStream>(
+    stream: &mut T,
+    effort: CouldOpenEffort,
+    logger: Logger,
+) -> Result<f64, String> {
+    let stream_ptr: *mut T = stream;
+
+    // Use the callbacks from the stream implementation
+    let callbacks = T::get_file_callbacks();
+
+    let mut error: c_int = openmpt_sys::OPENMPT_ERROR_OK as c_int;
+    let mut error_message: *const c_char = ptr::null();
+    let probability = unsafe {
+        openmpt_sys::openmpt_could_open_probability2(
+            callbacks,
+            stream_ptr as *mut _,
+            effort.value(),
+            logger.log_func(),
+            logger.log_user(),
+            Some(openmpt_sys::openmpt_error_func_store),
             ptr::null_mut(),
+            &mut error,
+            &mut error_message,
         )
+    };
+    let message = get_string!(error_message);
+
+    if error != openmpt_sys::OPENMPT_ERROR_OK as c_int {
+        return Err(message
+            .or_else(|| get_string!(openmpt_sys::openmpt_error_string(error)))
+            .unwrap_or_else(|| format!("error {}", error)));
     }
+    Ok(probability)
 }
 
 #[cfg(test)]
@@ -268,6 +375,24 @@ mod tests {
         assert!(load_complete_prob == 0.0);
     }
 
+    #[test]
+    fn module_file_is_likely_to_open() {
+        let module = std::fs::read("empty_module.xm").expect("unable to open file");
+        let module_prob = could_open_probability(
+            &mut Cursor::new(module),
+            CouldOpenEffort::LoadCompleteModule,
+            Logger::None,
+        );
+        let empty_prob = could_open_probability(
+            &mut Cursor::new(Vec::new()),
+            CouldOpenEffort::VerifyHeader,
+            Logger::None,
+        );
+
+        assert_eq!(module_prob, Ok(1.0));
+        assert_eq!(empty_prob, Ok(0.0));
+    }
+
     #[test]
     fn text_file_fails_to_load() {
         let module = test_helper::load_file_as_module("Cargo.toml");
@@ -285,4 +410,18 @@ mod tests {
         let module = test_helper::stream_file_as_module("empty_module.xm");
         assert!(module.is_ok());
     }
+
+    #[test]
+    fn capture_logger_collects_messages() {
+        let capture = LogCapture::new();
+        let logger = Logger::Capture(capture.clone());
+        let log_func = logger.log_func().unwrap();
+        for message in [c"first", c"second"] {
+            unsafe { log_func(message.as_ptr(), logger.log_user()) };
+        }
+
+        assert_eq!(capture.messages(), vec!["first", "second"]);
+        assert_eq!(capture.take().len(), 2);
+        assert!(capture.messages().is_empty());
+    }
 }
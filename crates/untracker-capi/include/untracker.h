@@ -0,0 +1,237 @@
+/* C API of untracker. Generated by cbindgen from crates/untracker-capi; do not edit. */
+
+#ifndef UNTRACKER_H
+#define UNTRACKER_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * A module loaded from a file or memory, with the names of its stems.
+ */
+typedef struct UntrackerModule UntrackerModule;
+
+/**
+ * How stems are rendered, filled in with the defaults by
+ * [`untracker_options_default`].
+ */
+typedef struct {
+  /**
+   * Name of the output format, such as "wav" or "flac", for files; NULL
+   * for WAV. The formats available are those the library was built with
+   */
+  const char *format;
+  uint32_t sample_rate;
+  /**
+   * 1 (mono) or 2 (stereo)
+   */
+  uint32_t channels;
+  /**
+   * Bits per sample of files: 16 or 24
+   */
+  uint32_t bit_depth;
+  /**
+   * Sub-song to render, or -1 for the module's default one
+   */
+  int32_t subsong;
+  /**
+   * Only render this many seconds from the start, fading out at the end,
+   * or the whole song if 0
+   */
+  double preview_seconds;
+  /**
+   * Fixed gain in dB
+   */
+  double gain_db;
+  /**
+   * Scale each stem file so its loudest sample reaches -1 dBFS
+   */
+  bool normalize;
+  /**
+   * Make files byte-identical across runs
+   */
+  bool reproducible;
+} UntrackerOptions;
+
+/**
+ * What rendering a stem to a file gave.
+ */
+typedef struct {
+  /**
+   * File the stem was written to, valid until the next render of the
+   * module or until it is freed
+   */
+  const char *path;
+  /**
+   * Number of frames (samples per channel)
+   */
+  uint64_t frames;
+  double duration;
+  /**
+   * Highest absolute sample value, as a fraction of full scale
+   */
+  double peak;
+} UntrackerStemResult;
+
+/**
+ * Receives the audio of a stem in chunks of interleaved 16-bit samples:
+ * `count` values, `channels` to a frame. Returns 0 to carry on, or
+ * anything else to stop the render.
+ */
+typedef int32_t (*UntrackerPcmCallback)(const int16_t *samples, size_t count, void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Message of the last error of a function called on this thread, or NULL
+ * if none failed. Valid until the next failure on the thread.
+ */
+const char *untracker_last_error(void);
+
+/**
+ * Version of untracker, such as "0.1.0".
+ */
+const char *untracker_version(void);
+
+/**
+ * Fill `options` with the defaults: 16-bit stereo WAV at 44.1 kHz of the
+ * whole song.
+ *
+ * # Safety
+ *
+ * `options` must point to an `UntrackerOptions`.
+ */
+void untracker_options_default(UntrackerOptions *options);
+
+/**
+ * Load the module in the `size` bytes at `data`, which are copied. Returns
+ * NULL if it isn't a module libopenmpt can load.
+ *
+ * # Safety
+ *
+ * `data` must point to `size` readable bytes.
+ */
+UntrackerModule *untracker_load(const uint8_t *data, size_t size);
+
+/**
+ * Load the module file at `path`, unpacking it if it is an archive the
+ * library was built to read. Returns NULL if it can't be read or loaded.
+ *
+ * # Safety
+ *
+ * `path` must point to a NUL-terminated string.
+ */
+UntrackerModule *untracker_load_file(const char *path);
+
+/**
+ * Free a module. Does nothing with NULL.
+ *
+ * # Safety
+ *
+ * `module` must be NULL or come from [`untracker_load`] and not be freed
+ * already.
+ */
+void untracker_free(UntrackerModule *module);
+
+/**
+ * Number of stems of the module: its instruments, or its samples if it
+ * has none. Returns -1 if `module` is NULL.
+ *
+ * # Safety
+ *
+ * `module` must be NULL or a module that isn't freed.
+ */
+int32_t untracker_stem_count(UntrackerModule *module);
+
+/**
+ * Whether the stems of the module are instruments (1) rather than samples
+ * (0). Returns -1 if `module` is NULL.
+ *
+ * # Safety
+ *
+ * `module` must be NULL or a module that isn't freed.
+ */
+int32_t untracker_stems_are_instruments(UntrackerModule *module);
+
+/**
+ * Name of stem `stem` in the module, trimmed, valid until the module is
+ * freed. Returns NULL if there is no such stem.
+ *
+ * # Safety
+ *
+ * `module` must be NULL or a module that isn't freed.
+ */
+const char *untracker_stem_name(UntrackerModule *module, int32_t stem);
+
+/**
+ * Render stem `stem` with `options` to its file in `output_dir`, named
+ * from `base_name` as the command-line tool names stems, and fill in
+ * `result` if it isn't NULL. Returns 0, or -1 on failure.
+ *
+ * # Safety
+ *
+ * `module` must be NULL or a module that isn't freed, `options` must point
+ * to an `UntrackerOptions`, `output_dir` and `base_name` to NUL-terminated
+ * strings, and `result` must be NULL or point to an `UntrackerStemResult`.
+ */
+int32_t untracker_render_stem(UntrackerModule *module,
+                              int32_t stem,
+                              const UntrackerOptions *options,
+                              const char *output_dir,
+                              const char *base_name,
+                              UntrackerStemResult *result);
+
+/**
+ * Render stem `stem` with `options` without writing a file, handing the
+ * audio to `callback` with `user_data` as it is rendered. The format, bit
+ * depth and normalization of `options` are left out: the audio is 16-bit,
+ * as libopenmpt renders it. Returns the number of frames rendered, or -1 on
+ * failure or when the callback stopped the render.
+ *
+ * # Safety
+ *
+ * `module` must be NULL or a module that isn't freed, `options` must point
+ * to an `UntrackerOptions`, and `callback` must be safe to call with
+ * `user_data`.
+ */
+int64_t untracker_render_stem_pcm(UntrackerModule *module,
+                                  int32_t stem,
+                                  const UntrackerOptions *options,
+                                  UntrackerPcmCallback callback,
+                                  void *user_data);
+
+#if defined(UNTRACKER_MEMFS)
+/**
+ * Take the stem file written to `path` out of memory, in builds keeping
+ * files there (the `memfs` feature), and set `size` to its length. Returns
+ * NULL if there is no such file. Free it with [`untracker_free_file`].
+ *
+ * # Safety
+ *
+ * `path` must point to a NUL-terminated string, and `size` to a `size_t`.
+ */
+uint8_t *untracker_take_file(const char *path, size_t *size);
+#endif
+
+#if defined(UNTRACKER_MEMFS)
+/**
+ * Free a file taken with [`untracker_take_file`]. Does nothing with NULL.
+ *
+ * # Safety
+ *
+ * `data` must be NULL or come from [`untracker_take_file`] with `size`,
+ * and not be freed already.
+ */
+void untracker_free_file(uint8_t *data, size_t size);
+#endif
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* UNTRACKER_H */
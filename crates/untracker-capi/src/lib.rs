@@ -0,0 +1,501 @@
+//! C API of untracker, so that programs in other languages can extract stems
+//! without running the command-line tool.
+//!
+//! The library builds as `libuntracker_capi` (shared and static), with the
+//! header generated into `include/untracker.h` by cbindgen on every build.
+//!
+//! Functions that can fail return NULL or a negative number, and leave a
+//! message for [`untracker_last_error`]. Stems are numbered from 0.
+
+use anyhow::{anyhow, Result};
+use openmpt::index::{InstrumentIndex, SubsongIndex};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use untracker_core::{
+    render_stem, unpack, ExportOptions, Extractor, NameTemplate, NormalizeMode, StemLabels,
+};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// A module loaded from a file or memory, with the names of its stems.
+pub struct UntrackerModule {
+    extractor: Extractor,
+    names: Vec<CString>,
+    /// Path of the last stem written, handed out by [`untracker_render_stem`]
+    last_path: Option<CString>,
+}
+
+/// How stems are rendered, filled in with the defaults by
+/// [`untracker_options_default`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UntrackerOptions {
+    /// Name of the output format, such as "wav" or "flac", for files; NULL
+    /// for WAV. The formats available are those the library was built with
+    pub format: *const c_char,
+    pub sample_rate: u32,
+    /// 1 (mono) or 2 (stereo)
+    pub channels: u32,
+    /// Bits per sample of files: 16 or 24
+    pub bit_depth: u32,
+    /// Sub-song to render, or -1 for the module's default one
+    pub subsong: i32,
+    /// Only render this many seconds from the start, fading out at the end,
+    /// or the whole song if 0
+    pub preview_seconds: f64,
+    /// Fixed gain in dB
+    pub gain_db: f64,
+    /// Scale each stem file so its loudest sample reaches -1 dBFS
+    pub normalize: bool,
+    /// Make files byte-identical across runs
+    pub reproducible: bool,
+}
+
+/// What rendering a stem to a file gave.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UntrackerStemResult {
+    /// File the stem was written to, valid until the next render of the
+    /// module or until it is freed
+    pub path: *const c_char,
+    /// Number of frames (samples per channel)
+    pub frames: u64,
+    pub duration: f64,
+    /// Highest absolute sample value, as a fraction of full scale
+    pub peak: f64,
+}
+
+/// Receives the audio of a stem in chunks of interleaved 16-bit samples:
+/// `count` values, `channels` to a frame. Returns 0 to carry on, or
+/// anything else to stop the render.
+pub type UntrackerPcmCallback =
+    Option<unsafe extern "C" fn(samples: *const i16, count: usize, user_data: *mut c_void) -> i32>;
+
+/// Run `f`, turning its errors and panics into `failed` and a message for
+/// [`untracker_last_error`].
+fn guard<T>(failed: T, f: impl FnOnce() -> Result<T>) -> T {
+    let error = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => return value,
+        Ok(Err(e)) => e.to_string(),
+        Err(_) => "untracker panicked".to_string(),
+    };
+    LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(error.replace('\0', " ")).ok());
+    failed
+}
+
+/// The string at `s`, which may be NULL.
+///
+/// # Safety
+///
+/// `s` must be NULL or point to a NUL-terminated string.
+unsafe fn string<'a>(s: *const c_char, what: &str) -> Result<Option<&'a str>> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map(Some)
+        .map_err(|_| anyhow!("{} is not valid UTF-8", what))
+}
+
+impl UntrackerOptions {
+    /// The export options these stand for.
+    ///
+    /// # Safety
+    ///
+    /// `format` must be NULL or point to a NUL-terminated string.
+    unsafe fn to_export_options(self) -> Result<ExportOptions> {
+        let mut options = ExportOptions {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            bit_depth: self.bit_depth,
+            subsong: (self.subsong >= 0).then(|| SubsongIndex::new(self.subsong)),
+            preview: (self.preview_seconds > 0.0).then_some(self.preview_seconds),
+            gain_db: self.gain_db,
+            normalize: if self.normalize {
+                NormalizeMode::Peak
+            } else {
+                NormalizeMode::Off
+            },
+            reproducible: self.reproducible,
+            ..ExportOptions::default()
+        };
+        if let Some(format) = string(self.format, "format")? {
+            options.format = format.parse()?;
+        }
+        options.validate()?;
+        Ok(options)
+    }
+}
+
+fn load(buffer: Vec<u8>) -> Result<*mut UntrackerModule> {
+    let extractor = Extractor::new(buffer)?;
+    let names = extractor
+        .stems()
+        .iter()
+        .map(|stem| CString::new(stem.name.replace('\0', " ")))
+        .collect::<Result<_, _>>()?;
+    Ok(Box::into_raw(Box::new(UntrackerModule {
+        extractor,
+        names,
+        last_path: None,
+    })))
+}
+
+/// The module at `module`, or an error if it is NULL.
+///
+/// # Safety
+///
+/// `module` must be NULL or come from [`untracker_load`] and not be freed.
+unsafe fn module<'a>(module: *mut UntrackerModule) -> Result<&'a mut UntrackerModule> {
+    module.as_mut().ok_or_else(|| anyhow!("module is NULL"))
+}
+
+/// Stem `stem` of `module`.
+fn stem_index(module: &UntrackerModule, stem: i32) -> Result<InstrumentIndex> {
+    module
+        .extractor
+        .stems()
+        .get(usize::try_from(stem).unwrap_or(usize::MAX))
+        .map(|stem| stem.index)
+        .ok_or_else(|| {
+            anyhow!(
+                "Stem {} selected, but the module only has {} stems",
+                stem,
+                module.names.len()
+            )
+        })
+}
+
+/// Message of the last error of a function called on this thread, or NULL
+/// if none failed. Valid until the next failure on the thread.
+#[no_mangle]
+pub extern "C" fn untracker_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |error| error.as_ptr())
+    })
+}
+
+/// Version of untracker, such as "0.1.0".
+#[no_mangle]
+pub extern "C" fn untracker_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast()
+}
+
+/// Fill `options` with the defaults: 16-bit stereo WAV at 44.1 kHz of the
+/// whole song.
+///
+/// # Safety
+///
+/// `options` must point to an `UntrackerOptions`.
+#[no_mangle]
+pub unsafe extern "C" fn untracker_options_default(options: *mut UntrackerOptions) {
+    let defaults = ExportOptions::default();
+    if let Some(options) = options.as_mut() {
+        *options = UntrackerOptions {
+            format: std::ptr::null(),
+            sample_rate: defaults.sample_rate,
+            channels: defaults.channels,
+            bit_depth: defaults.bit_depth,
+            subsong: -1,
+            preview_seconds: 0.0,
+            gain_db: defaults.gain_db,
+            normalize: false,
+            reproducible: defaults.reproducible,
+        };
+    }
+}
+
+/// Load the module in the `size` bytes at `data`, which are copied. Returns
+/// NULL if it isn't a module libopenmpt can load.
+///
+/// # Safety
+///
+/// `data` must point to `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn untracker_load(data: *const u8, size: usize) -> *mut UntrackerModule {
+    guard(std::ptr::null_mut(), || {
+        if data.is_null() {
+            return Err(anyhow!("data is NULL"));
+        }
+        load(std::slice::from_raw_parts(data, size).to_vec())
+    })
+}
+
+/// Load the module file at `path`, unpacking it if it is an archive the
+/// library was built to read. Returns NULL if it can't be read or loaded.
+///
+/// # Safety
+///
+/// `path` must point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn untracker_load_file(path: *const c_char) -> *mut UntrackerModule {
+    guard(std::ptr::null_mut(), || {
+        let path = Path::new(string(path, "path")?.ok_or_else(|| anyhow!("path is NULL"))?);
+        let data =
+            std::fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        load(unpack::unpack(path, data)?)
+    })
+}
+
+/// Free a module. Does nothing with NULL.
+///
+/// # Safety
+///
+/// `module` must be NULL or come from [`untracker_load`] and not be freed
+/// already.
+#[no_mangle]
+pub unsafe extern "C" fn untracker_free(module: *mut UntrackerModule) {
+    if !module.is_null() {
+        drop(Box::from_raw(module));
+    }
+}
+
+/// Number of stems of the module: its instruments, or its samples if it
+/// has none. Returns -1 if `module` is NULL.
+///
+/// # Safety
+///
+/// `module` must be NULL or a module that isn't freed.
+#[no_mangle]
+pub unsafe extern "C" fn untracker_stem_count(module: *mut UntrackerModule) -> i32 {
+    guard(-1, || Ok(self::module(module)?.names.len() as i32))
+}
+
+/// Whether the stems of the module are instruments (1) rather than samples
+/// (0). Returns -1 if `module` is NULL.
+///
+/// # Safety
+///
+/// `module` must be NULL or a module that isn't freed.
+#[no_mangle]
+pub unsafe extern "C" fn untracker_stems_are_instruments(module: *mut UntrackerModule) -> i32 {
+    guard(-1, || {
+        let module = self::module(module)?;
+        Ok(module
+            .extractor
+            .stems()
+            .first()
+            .is_some_and(|stem| stem.is_instrument) as i32)
+    })
+}
+
+/// Name of stem `stem` in the module, trimmed, valid until the module is
+/// freed. Returns NULL if there is no such stem.
+///
+/// # Safety
+///
+/// `module` must be NULL or a module that isn't freed.
+#[no_mangle]
+pub unsafe extern "C" fn untracker_stem_name(
+    module: *mut UntrackerModule,
+    stem: i32,
+) -> *const c_char {
+    guard(std::ptr::null(), || {
+        let module = self::module(module)?;
+        stem_index(module, stem)?;
+        Ok(module.names[stem as usize].as_ptr())
+    })
+}
+
+/// Render stem `stem` with `options` to its file in `output_dir`, named
+/// from `base_name` as the command-line tool names stems, and fill in
+/// `result` if it isn't NULL. Returns 0, or -1 on failure.
+///
+/// # Safety
+///
+/// `module` must be NULL or a module that isn't freed, `options` must point
+/// to an `UntrackerOptions`, `output_dir` and `base_name` to NUL-terminated
+/// strings, and `result` must be NULL or point to an `UntrackerStemResult`.
+#[no_mangle]
+pub unsafe extern "C" fn untracker_render_stem(
+    module: *mut UntrackerModule,
+    stem: i32,
+    options: *const UntrackerOptions,
+    output_dir: *const c_char,
+    base_name: *const c_char,
+    result: *mut UntrackerStemResult,
+) -> i32 {
+    guard(-1, || {
+        let module = self::module(module)?;
+        let index = stem_index(module, stem)?;
+        let options = options
+            .as_ref()
+            .ok_or_else(|| anyhow!("options is NULL"))?
+            .to_export_options()?;
+        let output_dir =
+            string(output_dir, "output_dir")?.ok_or_else(|| anyhow!("output_dir is NULL"))?;
+        let base_name = string(base_name, "base_name")?.unwrap_or("stem");
+        let is_instrument = module.extractor.stems()[stem as usize].is_instrument;
+
+        // Files in memory need no directory
+        #[cfg(not(feature = "memfs"))]
+        std::fs::create_dir_all(output_dir)?;
+        let rendered = render_stem(
+            module.extractor.buffer(),
+            index,
+            is_instrument,
+            output_dir,
+            base_name,
+            &options,
+            &NameTemplate::default(),
+            &StemLabels::default(),
+            &[],
+            None,
+        )?;
+        let path = module
+            .last_path
+            .insert(CString::new(rendered.path.to_string_lossy().into_owned())?);
+        if let Some(result) = result.as_mut() {
+            *result = UntrackerStemResult {
+                path: path.as_ptr(),
+                frames: rendered.frames,
+                duration: rendered.duration,
+                peak: rendered.peak,
+            };
+        }
+        Ok(0)
+    })
+}
+
+/// Render stem `stem` with `options` without writing a file, handing the
+/// audio to `callback` with `user_data` as it is rendered. The format, bit
+/// depth and normalization of `options` are left out: the audio is 16-bit,
+/// as libopenmpt renders it. Returns the number of frames rendered, or -1 on
+/// failure or when the callback stopped the render.
+///
+/// # Safety
+///
+/// `module` must be NULL or a module that isn't freed, `options` must point
+/// to an `UntrackerOptions`, and `callback` must be safe to call with
+/// `user_data`.
+#[no_mangle]
+pub unsafe extern "C" fn untracker_render_stem_pcm(
+    module: *mut UntrackerModule,
+    stem: i32,
+    options: *const UntrackerOptions,
+    callback: UntrackerPcmCallback,
+    user_data: *mut c_void,
+) -> i64 {
+    guard(-1, || {
+        let module = self::module(module)?;
+        let index = stem_index(module, stem)?;
+        let options = options
+            .as_ref()
+            .ok_or_else(|| anyhow!("options is NULL"))?
+            .to_export_options()?;
+        let callback = callback.ok_or_else(|| anyhow!("callback is NULL"))?;
+        let frames = module.extractor.render(index, &options, |chunk| {
+            match callback(chunk.as_ptr(), chunk.len(), user_data) {
+                0 => Ok(()),
+                _ => Err(anyhow!("Render stopped by the callback")),
+            }
+        })?;
+        Ok(frames as i64)
+    })
+}
+
+/// Take the stem file written to `path` out of memory, in builds keeping
+/// files there (the `memfs` feature), and set `size` to its length. Returns
+/// NULL if there is no such file. Free it with [`untracker_free_file`].
+///
+/// # Safety
+///
+/// `path` must point to a NUL-terminated string, and `size` to a `size_t`.
+#[cfg(feature = "memfs")]
+#[no_mangle]
+pub unsafe extern "C" fn untracker_take_file(path: *const c_char, size: *mut usize) -> *mut u8 {
+    guard(std::ptr::null_mut(), || {
+        let path = string(path, "path")?.ok_or_else(|| anyhow!("path is NULL"))?;
+        let size = size.as_mut().ok_or_else(|| anyhow!("size is NULL"))?;
+        let data = untracker_core::memfs::take(path)
+            .ok_or_else(|| anyhow!("{} is not in memory", path))?
+            .into_boxed_slice();
+        *size = data.len();
+        Ok(Box::into_raw(data).cast())
+    })
+}
+
+/// Free a file taken with [`untracker_take_file`]. Does nothing with NULL.
+///
+/// # Safety
+///
+/// `data` must be NULL or come from [`untracker_take_file`] with `size`,
+/// and not be freed already.
+#[cfg(feature = "memfs")]
+#[no_mangle]
+pub unsafe extern "C" fn untracker_free_file(data: *mut u8, size: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+            data, size,
+        )));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODULE: &[u8] = include_bytes!("../../../tests/modules/cndmcrrp.mod");
+
+    unsafe extern "C" fn count_samples(_: *const i16, count: usize, user_data: *mut c_void) -> i32 {
+        *(user_data as *mut usize) += count;
+        0
+    }
+
+    #[test]
+    fn test_render_through_c_api() {
+        unsafe {
+            let module = untracker_load(MODULE.as_ptr(), MODULE.len());
+            assert!(!module.is_null());
+            assert!(untracker_stem_count(module) > 1);
+            assert_eq!(untracker_stems_are_instruments(module), 0);
+            assert!(!untracker_stem_name(module, 0).is_null());
+
+            let mut options = std::mem::zeroed();
+            untracker_options_default(&mut options);
+            options.preview_seconds = 1.0;
+            let mut samples = 0usize;
+            let frames = untracker_render_stem_pcm(
+                module,
+                0,
+                &options,
+                Some(count_samples),
+                &mut samples as *mut usize as *mut c_void,
+            );
+            assert_eq!(frames, 44100);
+            assert_eq!(samples, 2 * 44100);
+
+            let dir = tempfile::tempdir().unwrap();
+            let output_dir = CString::new(dir.path().to_str().unwrap()).unwrap();
+            let mut result = std::mem::zeroed::<UntrackerStemResult>();
+            assert_eq!(
+                untracker_render_stem(
+                    module,
+                    1,
+                    &options,
+                    output_dir.as_ptr(),
+                    std::ptr::null(),
+                    &mut result,
+                ),
+                0
+            );
+            assert_eq!(result.frames, 44100);
+            let path = CStr::from_ptr(result.path).to_str().unwrap();
+            assert!(Path::new(path).starts_with(dir.path()));
+
+            assert!(untracker_stem_name(module, 999).is_null());
+            let error = CStr::from_ptr(untracker_last_error()).to_str().unwrap();
+            assert!(error.contains("only has"), "{}", error);
+            untracker_free(module);
+
+            assert!(untracker_load(b"not a module".as_ptr(), 12).is_null());
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! Sample conversions between the render and the encoders.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use untracker_core::audio::dsp::{Quantizer, Sample};
+
+/// A second of stereo 44.1 kHz audio: a 440 Hz tone at -6 dBFS.
+fn tone() -> Vec<f32> {
+    (0..44100 * 2)
+        .map(|i| {
+            let t = (i / 2) as f32 / 44100.0;
+            0.5 * (t * 440.0 * std::f32::consts::TAU).sin()
+        })
+        .collect()
+}
+
+fn convert(c: &mut Criterion) {
+    let float = tone();
+    let int: Vec<i16> = float.iter().map(|&s| i16::from_f64(s.to_f64())).collect();
+
+    let mut group = c.benchmark_group("convert");
+    group.throughput(Throughput::Elements(float.len() as u64));
+
+    group.bench_function("i16 to f32", |b| {
+        b.iter(|| {
+            black_box(&int)
+                .iter()
+                .map(|&s| f32::from_f64(s.to_f64()))
+                .collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("i16 to i32 (24-bit)", |b| {
+        b.iter(|| {
+            let mut quantizer = Quantizer::new(24, false);
+            black_box(&int)
+                .iter()
+                .map(|&s| s.to_int(&mut quantizer))
+                .collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("f32 to i16", |b| {
+        b.iter(|| f32::quantize(black_box(&float)).into_owned())
+    });
+    for (name, bits, dither) in [
+        ("f32 to i32 (16-bit)", 16, false),
+        ("f32 to i32 (24-bit)", 24, false),
+        ("f32 to i32 (24-bit, dithered)", 24, true),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut quantizer = Quantizer::new(bits, dither);
+                black_box(&float)
+                    .iter()
+                    .map(|&s| s.to_int(&mut quantizer))
+                    .collect::<Vec<_>>()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, convert);
+criterion_main!(benches);
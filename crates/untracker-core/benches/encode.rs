@@ -0,0 +1,68 @@
+//! Encoders, fed the same few seconds of a fixture song.
+//!
+//! Every format compiled in is measured; enable them with `--features all_formats`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use untracker_core::fixture::FixtureModule;
+use untracker_core::{
+    AudioFormat, AudioWriter, EncoderOptions, ExportOptions, RenderSession, SampleFormat,
+};
+
+/// Seconds of audio encoded per iteration.
+const SECONDS: usize = 5;
+
+fn encode(c: &mut Criterion) {
+    // 48 kHz suits every encoder, Opus included
+    let options = ExportOptions {
+        sample_rate: 48000,
+        ..ExportOptions::default()
+    };
+    let buffer = FixtureModule::song(8, 1).to_mod();
+    let mut session = RenderSession::new(&buffer, &options).unwrap();
+    let mut int = vec![0i16; SECONDS * 48000 * 2];
+    let mut float = vec![0f32; int.len()];
+    session.read(&mut int);
+    session.rewind().unwrap();
+    session.read_float(&mut float);
+    let options = options.encoder();
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut group = c.benchmark_group("encode");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements((SECONDS * 48000) as u64));
+
+    for format in AudioFormat::available() {
+        let options = EncoderOptions { format, ..options };
+        let path = dir.path().join(format!("bench.{}", format.extension()));
+        group.bench_function(format.name(), |b| {
+            b.iter(|| {
+                let mut writer = AudioWriter::create(&path, &options).unwrap();
+                writer.write(&int).unwrap();
+                writer.finish().unwrap();
+            })
+        });
+    }
+
+    for (name, bit_depth, sample_format) in [
+        ("wav 24-bit from float", 24, SampleFormat::Int),
+        ("wav float", 32, SampleFormat::Float),
+    ] {
+        let options = EncoderOptions {
+            bit_depth,
+            sample_format,
+            ..options
+        };
+        let path = dir.path().join("bench_float.wav");
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut writer = AudioWriter::create(&path, &options).unwrap();
+                writer.write_samples(&float).unwrap();
+                writer.finish().unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encode);
+criterion_main!(benches);
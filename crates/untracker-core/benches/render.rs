@@ -0,0 +1,51 @@
+//! Throughput of the render loop: whole songs and stems, in 16-bit and float.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use openmpt::index::InstrumentIndex;
+use untracker_core::fixture::FixtureModule;
+use untracker_core::{render_stem_streaming, ExportOptions, RenderSession};
+
+fn render_song(c: &mut Criterion) {
+    let buffer = FixtureModule::song(8, 1).to_mod();
+    let options = ExportOptions::default();
+    let frames = {
+        let session = RenderSession::new(&buffer, &options).unwrap();
+        (session.duration_seconds() * options.sample_rate as f64) as u64
+    };
+
+    let mut group = c.benchmark_group("render");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(frames));
+
+    group.bench_function("mix i16", |b| {
+        let mut buf = vec![0i16; 16384 * 2];
+        b.iter(|| {
+            let mut session = RenderSession::new(&buffer, &options).unwrap();
+            while session.read(&mut buf) > 0 {}
+        })
+    });
+    group.bench_function("mix f32", |b| {
+        let mut buf = vec![0f32; 16384 * 2];
+        b.iter(|| {
+            let mut session = RenderSession::new(&buffer, &options).unwrap();
+            while session.read_float(&mut buf) > 0 {}
+        })
+    });
+    group.bench_function("stem", |b| {
+        b.iter(|| {
+            render_stem_streaming(
+                &buffer,
+                InstrumentIndex::new(0),
+                false,
+                &options,
+                None,
+                |_| Ok(()),
+            )
+            .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, render_song);
+criterion_main!(benches);
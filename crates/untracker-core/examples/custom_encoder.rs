@@ -0,0 +1,39 @@
+//! Write a stem in a format untracker doesn't support, here headerless
+//! little-endian PCM, by handing the rendered audio to your own encoder.
+//!
+//!     cargo run -p untracker-core --example custom_encoder -- song.xm 1 stem.raw
+
+use anyhow::{anyhow, Result};
+use openmpt::index::InstrumentIndex;
+use std::io::{BufWriter, Write};
+use untracker_core::{render_stem_streaming, ExportOptions};
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (Some(input), Some(number), Some(output)) = (args.next(), args.next(), args.next()) else {
+        return Err(anyhow!(
+            "usage: custom_encoder <module> <instrument number> <output>"
+        ));
+    };
+    let index = InstrumentIndex::from_number(number.parse()?)
+        .ok_or_else(|| anyhow!("Instruments are numbered from 1"))?;
+
+    let buffer = std::fs::read(&input)?;
+    let options = ExportOptions::default();
+    let mut file = BufWriter::new(std::fs::File::create(&output)?);
+
+    // The sink sees interleaved 16-bit frames as they are rendered
+    let frames = render_stem_streaming(&buffer, index, true, &options, None, |chunk| {
+        for sample in chunk {
+            file.write_all(&sample.to_le_bytes())?;
+        }
+        Ok(())
+    })?;
+    file.flush()?;
+
+    println!(
+        "Wrote {} frames of {} Hz stereo to {}",
+        frames, options.sample_rate, output
+    );
+    Ok(())
+}
@@ -0,0 +1,52 @@
+//! Extract every instrument (or sample) of a module into WAV files.
+//!
+//!     cargo run -p untracker-core --example minimal_embed -- song.xm stems/
+
+use anyhow::{anyhow, Result};
+use openmpt::index::InstrumentIndex;
+use untracker_core::{render_stem, ExportOptions, NameTemplate, RenderSession, StemLabels};
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (Some(input), Some(output_dir)) = (args.next(), args.next()) else {
+        return Err(anyhow!("usage: minimal_embed <module> <output dir>"));
+    };
+
+    let buffer = std::fs::read(&input)?;
+    std::fs::create_dir_all(&output_dir)?;
+    let options = ExportOptions::default();
+
+    // Modules without instruments are split by sample instead
+    let session = RenderSession::new(&buffer, &options)?;
+    let mut module = session.module_ext().get_module();
+    let num_instruments = module.get_num_instruments();
+    let is_instrument = num_instruments > 0;
+    let count = if is_instrument {
+        num_instruments
+    } else {
+        module.get_num_samples()
+    };
+
+    for index in InstrumentIndex::all(count) {
+        let result = render_stem(
+            &buffer,
+            index,
+            is_instrument,
+            &output_dir,
+            "stem",
+            &options,
+            &NameTemplate::default(),
+            &StemLabels::default(),
+            &[],
+            None,
+        )?;
+        println!(
+            "{} ({:.1} s, peak {:.1} dBFS)",
+            result.path.display(),
+            result.duration,
+            result.peak_dbfs()
+        );
+    }
+
+    Ok(())
+}
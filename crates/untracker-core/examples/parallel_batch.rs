@@ -0,0 +1,44 @@
+//! Render the full mix of every module in a directory, four at a time.
+//!
+//!     cargo run -p untracker-core --example parallel_batch -- modules/ mixes/
+
+use anyhow::{anyhow, Result};
+use indicatif::ProgressBar;
+use std::path::PathBuf;
+use untracker_core::{parallel, render_full_mix, ExportOptions};
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (Some(input_dir), Some(output_dir)) = (args.next(), args.next()) else {
+        return Err(anyhow!("usage: parallel_batch <module dir> <output dir>"));
+    };
+
+    let mut modules: Vec<PathBuf> = std::fs::read_dir(&input_dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<_>>()?;
+    modules.retain(|path| {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(openmpt::info::is_extension_supported)
+    });
+    modules.sort();
+    std::fs::create_dir_all(&output_dir)?;
+
+    let options = ExportOptions::default();
+    let progress = ProgressBar::new(modules.len() as u64);
+    // Each worker gets its own bar; the outcomes come back in module order
+    let results = parallel::run(&modules, 4, Some(&progress), |path, pb| {
+        let buffer = std::fs::read(path)?;
+        let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("mix");
+        render_full_mix(&buffer, &output_dir, name, &options, &[], pb)
+    })?;
+    progress.finish_and_clear();
+
+    for (path, result) in modules.iter().zip(results) {
+        match result {
+            Ok(mix) => println!("{} -> {}", path.display(), mix.path.display()),
+            Err(e) => eprintln!("{}: {}", path.display(), e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,43 @@
+//! Follow the pattern rows of a module as it renders, like the pattern view
+//! of a tracker, while streaming the audio somewhere else.
+//!
+//!     cargo run -p untracker-core --example pattern_follower -- song.xm
+
+use anyhow::{anyhow, Result};
+use std::sync::mpsc;
+use untracker_core::{ExportOptions, RenderSession};
+
+fn main() -> Result<()> {
+    let Some(input) = std::env::args().nth(1) else {
+        return Err(anyhow!("usage: pattern_follower <module>"));
+    };
+
+    let buffer = std::fs::read(&input)?;
+    let options = ExportOptions::default();
+    let mut session = RenderSession::new(&buffer, &options)?;
+
+    // The callback runs on the rendering thread; a player would send the
+    // positions to its display, timed by the frames its audio device played
+    let (sender, positions) = mpsc::channel();
+    session.on_position(options.sample_rate as usize / 100, move |position| {
+        let _ = sender.send(position);
+    });
+
+    let mut audio = vec![0i16; 4096 * options.channels as usize];
+    let mut last_row = None;
+    while session.read(&mut audio) > 0 {
+        for position in positions.try_iter() {
+            if last_row != Some((position.order, position.row)) {
+                last_row = Some((position.order, position.row));
+                println!(
+                    "{:9.3} s  order {:3}  pattern {:3}  row {:3}",
+                    position.frame as f64 / options.sample_rate as f64,
+                    position.order,
+                    position.pattern,
+                    position.row
+                );
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,33 @@
+//! Render the full mix of a module, reporting progress your own way instead
+//! of with an indicatif progress bar.
+//!
+//!     cargo run -p untracker-core --example progress_sink -- song.xm mixes/
+
+use anyhow::{anyhow, Result};
+use untracker_core::progress::RenderProgress;
+use untracker_core::{render_full_mix, ExportOptions};
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (Some(input), Some(output_dir)) = (args.next(), args.next()) else {
+        return Err(anyhow!("usage: progress_sink <module> <output dir>"));
+    };
+
+    let buffer = std::fs::read(&input)?;
+    std::fs::create_dir_all(&output_dir)?;
+    let options = ExportOptions::default();
+
+    // Any closure taking a RenderProgress is a progress sink; a GUI would
+    // send these to its progress widget instead
+    let sink = |progress: RenderProgress| match progress {
+        RenderProgress::Started { label } => println!("Rendering {}", label),
+        RenderProgress::Rendering { seconds, .. } => {
+            println!("{:5.1}%  {:6.1} s", progress.fraction() * 100.0, seconds)
+        }
+        RenderProgress::Finished { path, .. } => println!("Wrote {}", path.display()),
+    };
+    let result = render_full_mix(&buffer, &output_dir, "mix", &options, &[], Some(&sink))?;
+
+    println!("Peak level: {:.1} dBFS", result.peak_dbfs());
+    Ok(())
+}
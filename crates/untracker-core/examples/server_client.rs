@@ -0,0 +1,117 @@
+//! Upload a module to `untracker serve`, list its stems and download the
+//! first one, over plain HTTP.
+//!
+//!     untracker serve --port 8080 &
+//!     cargo run -p untracker-core --example server_client -- localhost:8080 song.xm stems/
+
+use anyhow::{anyhow, Result};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+
+/// Send a request to the server at `address` and return the status and body
+/// of the answer.
+fn request(address: &str, method: &str, path: &str, body: &[u8]) -> Result<(u16, Vec<u8>)> {
+    let mut stream = TcpStream::connect(address)?;
+    // HTTP/1.0 answers end with the connection rather than in chunks
+    write!(
+        stream,
+        "{} {} HTTP/1.0\r\nHost: {}\r\nContent-Length: {}\r\n\r\n",
+        method,
+        path,
+        address,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+
+    let mut answer = Vec::new();
+    stream.read_to_end(&mut answer)?;
+    let end = answer
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| anyhow!("The answer has no end of headers"))?;
+    let head = String::from_utf8_lossy(&answer[..end]);
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| anyhow!("Invalid status line: {}", head.lines().next().unwrap_or("")))?;
+    Ok((status, answer[end + 4..].to_vec()))
+}
+
+/// Send a request that answers with JSON, failing on errors.
+fn request_json(address: &str, method: &str, path: &str, body: &[u8]) -> Result<serde_json::Value> {
+    let (status, body) = request(address, method, path, body)?;
+    let json: serde_json::Value = serde_json::from_slice(&body)?;
+    if status >= 400 {
+        return Err(anyhow!("{} {}: {} {}", method, path, status, json["error"]));
+    }
+    Ok(json)
+}
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (Some(address), Some(input), Some(output_dir)) = (args.next(), args.next(), args.next())
+    else {
+        return Err(anyhow!(
+            "usage: server_client <host:port> <module> <output dir>"
+        ));
+    };
+
+    let name = Path::new(&input)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("Invalid module path {}", input))?;
+    // Names with other characters would need percent-encoding
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+    {
+        return Err(anyhow!(
+            "{}: only letters, digits, dots and dashes are sent as they are",
+            name
+        ));
+    }
+    let module = request_json(
+        &address,
+        "POST",
+        &format!("/modules?name={}", name),
+        &std::fs::read(&input)?,
+    )?;
+    let id = module["id"]
+        .as_str()
+        .ok_or_else(|| anyhow!("The listing has no id"))?;
+    println!("Uploaded {} as {}", name, id);
+    let stems = module["stems"]
+        .as_array()
+        .ok_or_else(|| anyhow!("The listing has no stems"))?;
+    for stem in stems {
+        println!("  {}: {}", stem["number"], stem["name"]);
+    }
+
+    let Some(url) = stems.first().and_then(|stem| stem["url"].as_str()) else {
+        return Ok(());
+    };
+    let (status, audio) = request(
+        &address,
+        "GET",
+        &format!("{}?format=wav&preview=10", url),
+        &[],
+    )?;
+    if status != 200 {
+        return Err(anyhow!(
+            "GET {}: {} {}",
+            url,
+            status,
+            String::from_utf8_lossy(&audio)
+        ));
+    }
+    std::fs::create_dir_all(&output_dir)?;
+    let path = Path::new(&output_dir).join("stem_1.wav");
+    std::fs::write(&path, audio)?;
+    println!("Wrote the first 10 seconds of stem 1 to {}", path.display());
+
+    // The server keeps a few dozen modules; free the slot once done
+    request(&address, "DELETE", &format!("/modules/{}", id), &[])?;
+    Ok(())
+}
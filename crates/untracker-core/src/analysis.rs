@@ -0,0 +1,298 @@
+//! Analysis of rendered stems, used for storage planning reports.
+
+use serde::{Deserialize, Serialize};
+
+/// Window used to decide whether a stretch of audio is silent.
+const SILENCE_WINDOW_FRAMES: usize = 1024;
+/// Peak level (in 16-bit sample units) at or below which a window counts as silent.
+const SILENCE_THRESHOLD: i16 = 1;
+/// Block size used by the FLAC size estimate, matching the reference encoder default.
+const FLAC_BLOCK_FRAMES: usize = 4096;
+/// Approximate per-subframe overhead of a constant (silent) FLAC subframe, in bits.
+const FLAC_CONSTANT_SUBFRAME_BITS: u64 = 8 + 16;
+/// Approximate per-frame header and footer overhead of a FLAC frame, in bits.
+const FLAC_FRAME_OVERHEAD_BITS: u64 = 16 * 8;
+/// Size of a canonical WAV header.
+const WAV_HEADER_BYTES: u64 = 44;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StemAnalysis {
+    /// Number of rendered frames
+    pub frames: u64,
+    /// Fraction of the stem (0.0-1.0) that is silent
+    pub silence_fraction: f64,
+    /// Size of the stem as an uncompressed WAV file
+    pub wav_bytes: u64,
+    /// Estimated size of the stem as a FLAC file
+    pub estimated_flac_bytes: u64,
+}
+
+impl StemAnalysis {
+    /// Analyze interleaved 16-bit audio as it will be written at `bit_depth`.
+    pub fn new(samples: &[i16], channels: usize, bit_depth: u32) -> Self {
+        let mut analyzer = StemAnalyzer::new(channels, bit_depth);
+        analyzer.add(samples);
+        analyzer.finish()
+    }
+
+    /// Fraction of the WAV size saved by storing the stem as FLAC instead.
+    pub fn flac_savings(&self) -> f64 {
+        if self.wav_bytes == 0 {
+            0.0
+        } else {
+            1.0 - self.estimated_flac_bytes as f64 / self.wav_bytes as f64
+        }
+    }
+}
+
+/// Builds a [`StemAnalysis`] from audio that arrives in chunks of any length.
+pub struct StemAnalyzer {
+    channels: usize,
+    bit_depth: u32,
+    /// Samples short of a whole FLAC block, carried over to the next chunk
+    pending: Vec<i16>,
+    frames: u64,
+    silent_frames: u64,
+    flac_bits: u64,
+}
+
+impl StemAnalyzer {
+    pub fn new(channels: usize, bit_depth: u32) -> Self {
+        StemAnalyzer {
+            channels,
+            bit_depth,
+            pending: Vec::with_capacity(FLAC_BLOCK_FRAMES * channels),
+            frames: 0,
+            silent_frames: 0,
+            flac_bits: 0,
+        }
+    }
+
+    pub fn add(&mut self, mut samples: &[i16]) {
+        let block_samples = FLAC_BLOCK_FRAMES * self.channels;
+
+        // Complete the block started by the previous chunk first
+        if !self.pending.is_empty() {
+            let missing = (block_samples - self.pending.len()).min(samples.len());
+            self.pending.extend_from_slice(&samples[..missing]);
+            samples = &samples[missing..];
+            if self.pending.len() < block_samples {
+                return;
+            }
+            let block = std::mem::take(&mut self.pending);
+            self.add_block(&block);
+        }
+
+        let mut blocks = samples.chunks_exact(block_samples);
+        for block in &mut blocks {
+            self.add_block(block);
+        }
+        self.pending.extend_from_slice(blocks.remainder());
+    }
+
+    fn add_block(&mut self, block: &[i16]) {
+        self.frames += (block.len() / self.channels) as u64;
+        self.silent_frames += silent_frames(block, self.channels) as u64;
+        self.flac_bits += flac_block_bits(block, self.channels, self.bit_depth);
+    }
+
+    pub fn finish(mut self) -> StemAnalysis {
+        if !self.pending.is_empty() {
+            let block = std::mem::take(&mut self.pending);
+            self.add_block(&block);
+        }
+
+        let bytes_per_sample = (self.bit_depth / 8) as u64;
+        StemAnalysis {
+            frames: self.frames,
+            silence_fraction: if self.frames == 0 {
+                1.0
+            } else {
+                self.silent_frames as f64 / self.frames as f64
+            },
+            wav_bytes: WAV_HEADER_BYTES + self.frames * self.channels as u64 * bytes_per_sample,
+            estimated_flac_bytes: self.flac_bits.div_ceil(8),
+        }
+    }
+}
+
+/// Number of frames in silent windows of `samples`.
+fn silent_frames(samples: &[i16], channels: usize) -> usize {
+    samples
+        .chunks(SILENCE_WINDOW_FRAMES * channels)
+        .filter(|window| {
+            window
+                .iter()
+                .all(|s| s.unsigned_abs() <= SILENCE_THRESHOLD as u16)
+        })
+        .map(|window| window.len() / channels)
+        .sum()
+}
+
+/// Estimate the size of one FLAC frame using a second order fixed predictor and
+/// Rice coded residuals, which is close to what the reference encoder achieves
+/// at its fast settings.
+fn flac_block_bits(block: &[i16], channels: usize, bit_depth: u32) -> u64 {
+    let shift = bit_depth.saturating_sub(16);
+    let mut bits = FLAC_FRAME_OVERHEAD_BITS;
+    let block_frames = block.len() / channels;
+
+    for channel in 0..channels {
+        let channel_samples = || {
+            block
+                .iter()
+                .skip(channel)
+                .step_by(channels)
+                .map(|&s| (s as i64) << shift)
+        };
+
+        let first = channel_samples().next().unwrap_or(0);
+        if channel_samples().all(|s| s == first) {
+            bits += FLAC_CONSTANT_SUBFRAME_BITS;
+            continue;
+        }
+
+        let residuals: Vec<u64> = channel_samples()
+            .collect::<Vec<_>>()
+            .windows(3)
+            .map(|w| (w[2] - 2 * w[1] + w[0]).unsigned_abs())
+            .collect();
+        let mean = residuals.iter().sum::<u64>() as f64 / residuals.len().max(1) as f64;
+        let rice_k = if mean > 1.0 {
+            mean.log2().floor() as u32
+        } else {
+            0
+        };
+
+        // Warm-up samples are stored verbatim, residuals as Rice codes
+        let warmup_bits = 2 * (bit_depth as u64);
+        let residual_bits: u64 = residuals
+            .iter()
+            .map(|&r| 1 + rice_k as u64 + ((r << 1) >> rice_k))
+            .sum();
+        // The encoder falls back to a verbatim subframe when prediction doesn't pay off
+        let verbatim_bits = block_frames as u64 * bit_depth as u64;
+        bits += 8 + (warmup_bits + residual_bits).min(verbatim_bits);
+    }
+
+    bits
+}
+
+/// Per-stem entry of an [`AnalysisReport`].
+#[derive(Debug, Clone, Serialize)]
+pub struct StemAnalysisEntry {
+    /// Output file name of the stem
+    pub file: String,
+    /// 1-based instrument or sample number
+    pub index: i32,
+    /// "instrument" or "sample"
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(flatten)]
+    pub analysis: StemAnalysis,
+    pub flac_savings: f64,
+}
+
+/// Storage planning report for one module, written as `analysis.json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AnalysisReport {
+    pub module: String,
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub bit_depth: u32,
+    pub stems: Vec<StemAnalysisEntry>,
+    pub total_wav_bytes: u64,
+    pub total_estimated_flac_bytes: u64,
+    /// Silence fraction over all stems, weighted by length
+    pub silence_fraction: f64,
+}
+
+impl AnalysisReport {
+    pub fn new(
+        module: &str,
+        sample_rate: u32,
+        channels: u32,
+        bit_depth: u32,
+        stems: Vec<StemAnalysisEntry>,
+    ) -> Self {
+        let total_wav_bytes = stems.iter().map(|s| s.analysis.wav_bytes).sum();
+        let total_estimated_flac_bytes =
+            stems.iter().map(|s| s.analysis.estimated_flac_bytes).sum();
+        let total_frames: u64 = stems.iter().map(|s| s.analysis.frames).sum();
+        let silent_frames: f64 = stems
+            .iter()
+            .map(|s| s.analysis.silence_fraction * s.analysis.frames as f64)
+            .sum();
+
+        AnalysisReport {
+            module: module.to_string(),
+            sample_rate,
+            channels,
+            bit_depth,
+            stems,
+            total_wav_bytes,
+            total_estimated_flac_bytes,
+            silence_fraction: if total_frames > 0 {
+                silent_frames / total_frames as f64
+            } else {
+                0.0
+            },
+        }
+    }
+
+    /// Fraction of the total WAV size saved by storing all stems as FLAC.
+    pub fn flac_savings(&self) -> f64 {
+        if self.total_wav_bytes == 0 {
+            0.0
+        } else {
+            1.0 - self.total_estimated_flac_bytes as f64 / self.total_wav_bytes as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_silent_stem() {
+        let samples = vec![0i16; 44100 * 2];
+        let analysis = StemAnalysis::new(&samples, 2, 16);
+
+        assert_eq!(analysis.frames, 44100);
+        assert_eq!(analysis.silence_fraction, 1.0);
+        assert_eq!(analysis.wav_bytes, 44 + 44100 * 4);
+        assert!(analysis.flac_savings() > 0.95);
+    }
+
+    #[test]
+    fn test_half_silent_stem() {
+        let mut samples = vec![0i16; 8192 * 2];
+        for (i, sample) in samples[8192..].iter_mut().enumerate() {
+            *sample = ((i as f32 * 0.05).sin() * 10000.0) as i16;
+        }
+        let analysis = StemAnalysis::new(&samples, 2, 16);
+
+        assert!((analysis.silence_fraction - 0.5).abs() < 0.01);
+        assert!(analysis.estimated_flac_bytes < analysis.wav_bytes);
+    }
+
+    #[test]
+    fn test_chunked_analysis_matches() {
+        let samples: Vec<i16> = (0..20000 * 2)
+            .map(|i| {
+                if i < 9000 {
+                    0
+                } else {
+                    ((i as f32 * 0.03).sin() * 8000.0) as i16
+                }
+            })
+            .collect();
+
+        let mut analyzer = StemAnalyzer::new(2, 24);
+        for chunk in samples.chunks(3001 * 2) {
+            analyzer.add(chunk);
+        }
+        assert_eq!(analyzer.finish(), StemAnalysis::new(&samples, 2, 24));
+    }
+}
@@ -0,0 +1,251 @@
+//! Combining and querying the manifests of batch extractions, for `untracker manifest`.
+//!
+//! Batch runs and `watch` keep a `manifest.jsonl` with a line per module, and
+//! `--write-metadata` adds a `manifest.json` with the stems of each module. A
+//! merged manifest is a `manifest.jsonl` whose lines carry both.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::manifest::{StemManifest, STEM_MANIFEST_FILE};
+use crate::watch::{ManifestEntry, MANIFEST_FILE};
+
+/// A module of a batch manifest, with the stems from its `manifest.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleRecord {
+    #[serde(flatten)]
+    pub entry: ManifestEntry,
+    /// Contents of the module's `manifest.json`, if `--write-metadata` wrote one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<StemManifest>,
+}
+
+/// Read the records of a batch or merged manifest, or of the `manifest.jsonl`
+/// in directory `path`.
+pub fn load(path: &Path) -> Result<Vec<ModuleRecord>> {
+    let path = if path.is_dir() {
+        path.join(MANIFEST_FILE)
+    } else {
+        path.to_path_buf()
+    };
+    let text = fs::read_to_string(&path)
+        .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let mut records = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut record: ModuleRecord = serde_json::from_str(line)
+            .map_err(|e| anyhow!("{}:{}: {}", path.display(), number + 1, e))?;
+        if record.manifest.is_none() {
+            record.manifest = find_stem_manifest(&record.entry.output_dir, base)?;
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Load the `manifest.json` in `output_dir`.
+///
+/// Output directories are recorded as given on the command line, so they are
+/// also looked for next to the batch manifest, where they are found once a
+/// shard has been copied elsewhere.
+fn find_stem_manifest(output_dir: &Path, base: &Path) -> Result<Option<StemManifest>> {
+    let mut candidates = vec![output_dir.to_path_buf()];
+    if let Some(name) = output_dir.file_name() {
+        candidates.push(base.join(name));
+    }
+
+    for dir in candidates {
+        let path = dir.join(STEM_MANIFEST_FILE);
+        if path.is_file() {
+            let manifest = serde_json::from_str(&fs::read_to_string(&path)?)
+                .map_err(|e| anyhow!("Invalid manifest {}: {}", path.display(), e))?;
+            return Ok(Some(manifest));
+        }
+    }
+    Ok(None)
+}
+
+/// Combine the records of several manifests, in order.
+///
+/// A module extracted more than once into the same directory, such as when
+/// failures are retried, keeps its latest record.
+pub fn merge(records: impl IntoIterator<Item = ModuleRecord>) -> Vec<ModuleRecord> {
+    let mut merged: Vec<ModuleRecord> = Vec::new();
+    let mut positions: HashMap<(String, PathBuf), usize> = HashMap::new();
+    for record in records {
+        let key = (record.entry.module.clone(), record.entry.output_dir.clone());
+        match positions.get(&key) {
+            Some(&i) => {
+                if !is_older(&record, &merged[i]) {
+                    merged[i] = record;
+                }
+            }
+            None => {
+                positions.insert(key, merged.len());
+                merged.push(record);
+            }
+        }
+    }
+    merged
+}
+
+/// Whether `a` finished before `b`. Records with unreadable times are never older.
+fn is_older(a: &ModuleRecord, b: &ModuleRecord) -> bool {
+    let time = |r: &ModuleRecord| chrono::DateTime::parse_from_rfc3339(&r.entry.finished_at).ok();
+    matches!((time(a), time(b)), (Some(a), Some(b)) if a < b)
+}
+
+/// Write `records` to `path` as a manifest with a line of JSON per module.
+pub fn write(records: &[ModuleRecord], path: &Path) -> Result<()> {
+    let mut file = std::io::BufWriter::new(fs::File::create(path)?);
+    for record in records {
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// A stem that reached full scale, and may be clipped.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClippingStem {
+    pub module: String,
+    pub file: String,
+    pub peak_dbfs: f64,
+}
+
+/// Stems kept at or above full scale, by module.
+pub fn clipping_stems(records: &[ModuleRecord]) -> Vec<ClippingStem> {
+    records
+        .iter()
+        .filter_map(|record| record.manifest.as_ref())
+        .flat_map(|manifest| {
+            manifest
+                .stems
+                .iter()
+                .filter(|stem| stem.skipped.is_none())
+                .filter_map(move |stem| {
+                    let peak_dbfs = stem.peak_dbfs.filter(|&peak| peak >= 0.0)?;
+                    Some(ClippingStem {
+                        module: manifest.module.clone(),
+                        file: stem.file.clone(),
+                        peak_dbfs,
+                    })
+                })
+        })
+        .collect()
+}
+
+/// Output files of one format.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FormatTotal {
+    pub format: String,
+    pub files: usize,
+    /// Total size in bytes. Manifests written before sizes were recorded add nothing
+    pub bytes: u64,
+}
+
+/// Number and size of the kept stems of each output format, by format name.
+pub fn size_by_format(records: &[ModuleRecord]) -> Vec<FormatTotal> {
+    let mut totals: BTreeMap<&str, FormatTotal> = BTreeMap::new();
+    for manifest in records.iter().filter_map(|record| record.manifest.as_ref()) {
+        let total = totals
+            .entry(&manifest.settings.format)
+            .or_insert_with(|| FormatTotal {
+                format: manifest.settings.format.clone(),
+                ..FormatTotal::default()
+            });
+        for stem in manifest.stems.iter().filter(|stem| stem.skipped.is_none()) {
+            total.files += 1;
+            total.bytes += stem.size.unwrap_or(0);
+        }
+    }
+    totals.into_values().collect()
+}
+
+/// A module that could not be extracted.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FailedModule {
+    pub module: String,
+    pub output_dir: PathBuf,
+    pub error: String,
+}
+
+/// Modules whose latest extraction failed.
+pub fn failed_modules(records: &[ModuleRecord]) -> Vec<FailedModule> {
+    records
+        .iter()
+        .filter(|record| record.entry.status == "failed")
+        .map(|record| FailedModule {
+            module: record.entry.module.clone(),
+            output_dir: record.entry.output_dir.clone(),
+            error: record.entry.error.clone().unwrap_or_default(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(module: &str, status: &str, finished_at: &str) -> ModuleRecord {
+        ModuleRecord {
+            entry: ManifestEntry {
+                module: module.to_string(),
+                status: status.to_string(),
+                output_dir: PathBuf::from("out").join(module),
+                stems: 0,
+                error: (status == "failed").then(|| "Failed to load module".to_string()),
+                warnings: Vec::new(),
+                metadata: BTreeMap::new(),
+                finished_at: finished_at.to_string(),
+            },
+            manifest: None,
+        }
+    }
+
+    #[test]
+    fn test_merge_keeps_latest_record() {
+        let records = vec![
+            record("a.xm", "failed", "2024-05-01T10:00:00+02:00"),
+            record("b.mod", "done", "2024-05-01T10:01:00+02:00"),
+            // Retried from another shard, in another time zone
+            record("a.xm", "done", "2024-05-01T09:30:00+00:00"),
+            record("b.mod", "failed", "2024-05-01T07:00:00+00:00"),
+        ];
+        let merged = merge(records);
+
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].entry.module, "a.xm");
+        assert_eq!(merged[0].entry.status, "done");
+        assert_eq!(merged[1].entry.status, "done");
+        assert!(failed_modules(&merged).is_empty());
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut original = record("a.xm", "failed", "2024-05-01T10:00:00+02:00");
+        original.entry.warnings.push("unknown effect".to_string());
+
+        let line = serde_json::to_string(&original).unwrap();
+        // Lines of a batch manifest are records without stems
+        assert!(!line.contains("\"manifest\""));
+        let record: ModuleRecord = serde_json::from_str(&line).unwrap();
+        assert_eq!(record.entry.warnings, ["unknown effect"]);
+        assert_eq!(
+            failed_modules(&[record]),
+            [FailedModule {
+                module: "a.xm".to_string(),
+                output_dir: PathBuf::from("out/a.xm"),
+                error: "Failed to load module".to_string(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! Conversion of interleaved audio between channel layouts, so the channels
+//! libopenmpt renders don't have to be the channels written.
+//!
+//! Layouts are known by their number of channels: mono, stereo, and quad,
+//! whose channels are front left, front right, rear left and rear right, in
+//! the order libopenmpt renders them.
+
+use anyhow::{anyhow, Result};
+
+use super::dsp::Sample;
+
+/// Channel counts libopenmpt renders.
+pub const RENDER_CHANNELS: [u32; 3] = [1, 2, 4];
+
+/// Gain of channels folded together: -3 dB, the pan law that keeps the power
+/// of a sound wherever it is panned.
+const FOLD_GAIN: f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+/// Converter of interleaved audio from one channel layout to another.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelConverter {
+    from: usize,
+    to: usize,
+    /// Gain of each input channel in each output channel, by output channel
+    matrix: Vec<Vec<f64>>,
+}
+
+impl ChannelConverter {
+    /// A converter from `from` channels to `to`, each 1, 2 or 4.
+    ///
+    /// Channels are folded down at -3 dB: stereo to mono, quad to stereo (each
+    /// rear channel into the front one on its side), and quad to mono through
+    /// stereo. Fewer channels are spread over more by copying them, the
+    /// stereo pair to both the front and the rear of quad.
+    pub fn new(from: u32, to: u32) -> Result<Self> {
+        let g = FOLD_GAIN;
+        let matrix = match (from, to) {
+            (from, to) if from == to && RENDER_CHANNELS.contains(&from) => (0..to)
+                .map(|out| {
+                    (0..from)
+                        .map(|i| if i == out { 1.0 } else { 0.0 })
+                        .collect()
+                })
+                .collect(),
+            (2, 1) => vec![vec![g, g]],
+            (4, 1) => vec![vec![g * g; 4]],
+            (4, 2) => vec![vec![g, 0.0, g, 0.0], vec![0.0, g, 0.0, g]],
+            (1, 2) => vec![vec![1.0]; 2],
+            (1, 4) => vec![vec![1.0]; 4],
+            (2, 4) => vec![
+                vec![1.0, 0.0],
+                vec![0.0, 1.0],
+                vec![1.0, 0.0],
+                vec![0.0, 1.0],
+            ],
+            _ => {
+                return Err(anyhow!(
+                    "Cannot convert {} channels to {}: only 1, 2 and 4 channels are supported",
+                    from,
+                    to
+                ))
+            }
+        };
+        Ok(ChannelConverter {
+            from: from as usize,
+            to: to as usize,
+            matrix,
+        })
+    }
+
+    pub fn from_channels(&self) -> usize {
+        self.from
+    }
+
+    pub fn to_channels(&self) -> usize {
+        self.to
+    }
+
+    /// Convert the whole frames of `input` into `output`, as far as it holds them.
+    pub fn convert<S: Sample>(&self, input: &[S], output: &mut [S]) {
+        let frames = input
+            .chunks_exact(self.from)
+            .zip(output.chunks_exact_mut(self.to));
+        for (input, output) in frames {
+            for (sample, gains) in output.iter_mut().zip(&self.matrix) {
+                let value: f64 = gains.iter().zip(input).map(|(g, s)| g * s.to_f64()).sum();
+                *sample = S::from_f64(value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(from: u32, to: u32, input: &[f32]) -> Vec<f32> {
+        let converter = ChannelConverter::new(from, to).unwrap();
+        let mut output = vec![0.0; input.len() / from as usize * to as usize];
+        converter.convert(input, &mut output);
+        output
+    }
+
+    #[test]
+    fn test_channel_conversion() {
+        let g = FOLD_GAIN as f32;
+        // A sound in the center at -3 dB on each side comes out of mono at full level
+        let mono = convert(2, 1, &[g * 0.5, g * 0.5, 0.5, 0.0]);
+        assert!((mono[0] - 0.5).abs() < 1e-6);
+        assert!((mono[1] - 0.5 * g).abs() < 1e-6);
+
+        assert_eq!(convert(1, 2, &[0.25, -0.5]), [0.25, 0.25, -0.5, -0.5]);
+        let stereo = convert(4, 2, &[0.5, 0.0, 0.5, 0.25]);
+        assert!((stereo[0] - 2.0 * g * 0.5).abs() < 1e-6);
+        assert!((stereo[1] - g * 0.25).abs() < 1e-6);
+        assert_eq!(convert(2, 2, &[0.1, 0.2]), [0.1, 0.2]);
+
+        // Integer samples saturate rather than wrap
+        let converter = ChannelConverter::new(2, 1).unwrap();
+        let mut loud = [0i16; 1];
+        converter.convert(&[i16::MAX, i16::MAX], &mut loud);
+        assert_eq!(loud[0], i16::MAX);
+
+        assert!(ChannelConverter::new(3, 2).is_err());
+        assert!(ChannelConverter::new(2, 6).is_err());
+    }
+}
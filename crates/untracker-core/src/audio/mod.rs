@@ -0,0 +1,384 @@
+use anyhow::{anyhow, Result};
+use openmpt::index::SubsongIndex;
+use std::sync::Arc;
+
+pub mod channels;
+
+use crate::automation::MuteAutomation;
+use crate::mix_automation::MixAutomation;
+use crate::session::CtlSetting;
+// The file writers, their formats and sample conversions
+pub use untracker_encoders::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResampleMethod {
+    Nearest,
+    Linear,
+    Cubic,
+    Sinc,
+}
+
+impl std::str::FromStr for ResampleMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "nearest" => Ok(ResampleMethod::Nearest),
+            "linear" => Ok(ResampleMethod::Linear),
+            "cubic" => Ok(ResampleMethod::Cubic),
+            "sinc" => Ok(ResampleMethod::Sinc),
+            _ => Err(anyhow!("Unknown resampling method: {}", s)),
+        }
+    }
+}
+
+impl ResampleMethod {
+    pub fn to_openmpt_filter_length(self) -> i32 {
+        match self {
+            ResampleMethod::Nearest => 1,
+            ResampleMethod::Linear => 2,
+            ResampleMethod::Cubic => 4,
+            ResampleMethod::Sinc => 8,
+        }
+    }
+}
+
+/// Level adjustment applied to rendered audio before encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalizeMode {
+    /// Keep the level produced by libopenmpt
+    Off,
+    /// Scale so the loudest sample reaches the target level
+    Peak,
+    /// Scale so the RMS level reaches the target level, as far as the peak allows
+    Rms,
+}
+
+/// Silence removed from rendered audio before encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimSilence {
+    /// Keep the full length of the song
+    Off,
+    /// Drop the silence after the last sound, which keeps stems aligned
+    End,
+    /// Drop the silence before the first sound too; each stem then starts at its own offset
+    Both,
+}
+
+/// Section of the song to render.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SongRange {
+    /// From the start of order `start` to the end of order `end`, or to the end
+    /// of the song. Orders are numbered from 0, as in trackers
+    Orders { start: i32, end: Option<i32> },
+    /// From `start` to `end` seconds, or to the end of the song
+    Seconds { start: f64, end: Option<f64> },
+}
+
+impl std::fmt::Display for SongRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            SongRange::Orders {
+                start,
+                end: Some(end),
+            } => write!(f, "orders {}-{}", start, end),
+            SongRange::Orders { start, end: None } => write!(f, "orders {}-end", start),
+            SongRange::Seconds {
+                start,
+                end: Some(end),
+            } => write!(f, "{:.3}s-{:.3}s", start, end),
+            SongRange::Seconds { start, end: None } => write!(f, "{:.3}s-end", start),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub format: AudioFormat,
+    pub sample_rate: u32,
+    pub channels: u32,
+    /// Channels libopenmpt renders, 1, 2 or 4 (quad), converted to `channels`
+    /// as they are read. The same as `channels` if unset
+    pub render_channels: Option<u32>,
+    /// Bits per sample: 16 or 24 for integer samples, 32 for float samples
+    pub bit_depth: u32,
+    pub sample_format: SampleFormat,
+    /// Add TPDF dither when audio rendered in floating point is converted to
+    /// integer samples, rather than rounding it
+    pub dither: bool,
+    #[allow(dead_code)]
+    pub opus_bitrate: u32,
+    pub opus_vbr: OpusVbr,
+    /// Opus encoder complexity from 0 (fastest) to 10 (best)
+    pub opus_complexity: u32,
+    pub opus_application: OpusApplication,
+    #[allow(dead_code)]
+    pub vorbis_quality: u32,
+    /// Constant MP3 bitrate in kbps, used unless `mp3_vbr_quality` is set
+    pub mp3_bitrate: u32,
+    /// LAME VBR quality from 0 (best) to 9, selecting variable bitrate MP3
+    pub mp3_vbr_quality: Option<u32>,
+    pub resample: ResampleMethod,
+    pub stereo_separation: i32,
+    /// Gain of the libopenmpt mixer in dB, applied while rendering, before
+    /// samples are clipped to the output format
+    pub master_gain_db: f64,
+    /// Volume ramping strength of the libopenmpt mixer, from 0 (none, which
+    /// can click) to 10 (softest), libopenmpt's default if unset
+    pub volume_ramping: Option<i32>,
+    /// Sub-song to render, libopenmpt's default one if unset
+    pub subsong: Option<SubsongIndex>,
+    /// Only render this many seconds from the start, fading out at the end
+    pub preview: Option<f64>,
+    /// Times the song is repeated after playing through once
+    pub loop_count: u32,
+    /// Stop rendering after this many seconds, for songs that loop for too long
+    pub max_duration: Option<f64>,
+    /// Only render this section of the song
+    pub range: Option<SongRange>,
+    /// Render the song once, then this many seconds more as it loops back, and
+    /// crossfade them into the start of the loop so the file repeats seamlessly
+    pub loop_crossfade: Option<f64>,
+    /// Channels to mute and unmute at set times of the render, shared by the
+    /// options of every stem
+    pub mute_automation: Option<Arc<MuteAutomation>>,
+    /// Volume and panning curves of channels, applied during the render
+    pub mix_automation: Option<Arc<MixAutomation>>,
+    /// libopenmpt ctls set on the module before rendering
+    pub ctls: Arc<[CtlSetting]>,
+    pub normalize: NormalizeMode,
+    /// Target level for normalization, in dBFS
+    pub normalize_target: f64,
+    /// Fixed gain in dB, applied before normalization
+    pub gain_db: f64,
+    pub trim_silence: TrimSilence,
+    /// Pad renders that end earlier with silence up to this many frames, so
+    /// that stems line up
+    pub pad_to_frames: Option<u64>,
+    /// Measure the loudness of every file and tag it with its ReplayGain
+    pub replaygain: bool,
+    /// Make output byte-identical across runs: no dithering, fixed Ogg stream
+    /// serials and no version strings or timestamps in metadata
+    pub reproducible: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            format: AudioFormat::Wav,
+            sample_rate: 44100,
+            channels: 2,
+            render_channels: None,
+            bit_depth: 16,
+            sample_format: SampleFormat::Int,
+            dither: false,
+            opus_bitrate: 128,
+            opus_vbr: OpusVbr::On,
+            opus_complexity: 10,
+            opus_application: OpusApplication::Audio,
+            vorbis_quality: 5,
+            mp3_bitrate: 192,
+            mp3_vbr_quality: None,
+            resample: ResampleMethod::Sinc,
+            stereo_separation: 100,
+            master_gain_db: 0.0,
+            volume_ramping: None,
+            subsong: None,
+            preview: None,
+            loop_count: 0,
+            max_duration: None,
+            range: None,
+            loop_crossfade: None,
+            mute_automation: None,
+            mix_automation: None,
+            ctls: Arc::new([]),
+            normalize: NormalizeMode::Off,
+            normalize_target: -1.0,
+            gain_db: 0.0,
+            trim_silence: TrimSilence::Off,
+            pad_to_frames: None,
+            replaygain: false,
+            reproducible: false,
+        }
+    }
+}
+
+impl ExportOptions {
+    /// Whether audio is rendered in floating point rather than as libopenmpt's
+    /// 16-bit integers: for float samples, for integers of more than 16 bits
+    /// and to dither.
+    pub fn render_float(&self) -> bool {
+        self.sample_format == SampleFormat::Float || self.bit_depth > 16 || self.dither
+    }
+
+    /// The part of the options the file writers take.
+    pub fn encoder(&self) -> EncoderOptions {
+        EncoderOptions {
+            format: self.format,
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            bit_depth: self.bit_depth,
+            sample_format: self.sample_format,
+            dither: self.dither,
+            opus_bitrate: self.opus_bitrate,
+            opus_vbr: self.opus_vbr,
+            opus_complexity: self.opus_complexity,
+            opus_application: self.opus_application,
+            vorbis_quality: self.vorbis_quality,
+            mp3_bitrate: self.mp3_bitrate,
+            mp3_vbr_quality: self.mp3_vbr_quality,
+            reproducible: self.reproducible,
+        }
+    }
+
+    /// What [`ExportOptions::safe_fallback`] renders as.
+    pub const SAFE_FALLBACK: &'static str = "16-bit WAV at 44100 Hz";
+
+    /// The options with the output every build can write, 16-bit WAV at
+    /// 44100 Hz without dither, for `--retry-safe` to render a stem again
+    /// with after it failed. Everything else is kept.
+    pub fn safe_fallback(&self) -> Self {
+        ExportOptions {
+            format: AudioFormat::Wav,
+            sample_rate: 44100,
+            bit_depth: 16,
+            sample_format: SampleFormat::Int,
+            dither: false,
+            ..self.clone()
+        }
+    }
+
+    /// Check that the options are within the ranges supported by libopenmpt and the encoders.
+    pub fn validate(&self) -> Result<()> {
+        if self.channels != 1 && self.channels != 2 {
+            return Err(anyhow!(
+                "Only 1 (mono) or 2 (stereo) channels are supported"
+            ));
+        }
+        if let Some(channels) = self
+            .render_channels
+            .filter(|c| !channels::RENDER_CHANNELS.contains(c))
+        {
+            return Err(anyhow!(
+                "libopenmpt renders 1 (mono), 2 (stereo) or 4 (quad) channels, not {}",
+                channels
+            ));
+        }
+
+        self.encoder().validate()?;
+
+        if self.sample_rate < 8000 || self.sample_rate > 192000 {
+            return Err(anyhow!(
+                "Sample rate must be between 8000 and 192000 Hz (got {})",
+                self.sample_rate
+            ));
+        }
+
+        if !(0..=200).contains(&self.stereo_separation) {
+            return Err(anyhow!(
+                "Stereo separation must be between 0 and 200 percent (got {})",
+                self.stereo_separation
+            ));
+        }
+
+        if !self.master_gain_db.is_finite() {
+            return Err(anyhow!("Master gain must be a number of dB"));
+        }
+
+        if let Some(ramping) = self.volume_ramping.filter(|r| !(0..=10).contains(r)) {
+            return Err(anyhow!(
+                "Volume ramping must be between 0 and 10 (got {})",
+                ramping
+            ));
+        }
+
+        if self.normalize_target > 0.0 {
+            return Err(anyhow!(
+                "Normalization target must be at or below 0 dBFS (got {})",
+                self.normalize_target
+            ));
+        }
+
+        if self.max_duration.is_some_and(|seconds| seconds <= 0.0) {
+            return Err(anyhow!("Maximum duration must be greater than zero"));
+        }
+
+        if let Some(crossfade) = self.loop_crossfade {
+            if crossfade <= 0.0 {
+                return Err(anyhow!("Loop crossfade must be greater than zero"));
+            }
+            // A loop is the whole song played once, ending where it loops back
+            if self.preview.is_some()
+                || self.range.is_some()
+                || self.loop_count > 0
+                || self.max_duration.is_some()
+                || self.pad_to_frames.is_some()
+                || self.trim_silence != TrimSilence::Off
+            {
+                return Err(anyhow!(
+                    "Looping renders cover the whole song once: a loop crossfade cannot be \
+                     combined with a preview, range, loop count, maximum duration, padding \
+                     or trimming"
+                ));
+            }
+        }
+
+        let valid_range = match self.range {
+            Some(SongRange::Orders { start, end }) => {
+                start >= 0 && end.is_none_or(|end| end >= start)
+            }
+            Some(SongRange::Seconds { start, end }) => {
+                start >= 0.0 && end.is_none_or(|end| end > start)
+            }
+            None => true,
+        };
+        if !valid_range {
+            return Err(anyhow!(
+                "The range to render must start at 0 or later and end after it starts"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Seconds after which rendering stops, from `preview` and `max_duration`.
+    pub fn time_limit(&self) -> Option<f64> {
+        match (self.preview, self.max_duration) {
+            (Some(preview), Some(max)) => Some(preview.min(max)),
+            (limit, None) | (None, limit) => limit,
+        }
+    }
+
+    /// Expected length of a render in seconds, for a song of `song_duration`
+    /// seconds (0 if unknown).
+    pub fn render_duration(&self, song_duration: f64) -> f64 {
+        let duration = song_duration * f64::from(self.loop_count + 1);
+        match self.time_limit() {
+            Some(limit) if duration > 0.0 => limit.min(duration),
+            Some(limit) => limit,
+            None => duration,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safe_fallback() {
+        let options = ExportOptions {
+            sample_rate: 96000,
+            sample_format: SampleFormat::Float,
+            bit_depth: 32,
+            stereo_separation: 50,
+            ..ExportOptions::default()
+        };
+        let fallback = options.safe_fallback();
+        assert_eq!(fallback.format, AudioFormat::Wav);
+        assert_eq!(fallback.sample_format, SampleFormat::Int);
+        assert_eq!((fallback.sample_rate, fallback.bit_depth), (44100, 16));
+        assert_eq!(fallback.stereo_separation, 50);
+        assert!(fallback.validate().is_ok());
+    }
+}
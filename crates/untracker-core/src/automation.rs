@@ -0,0 +1,197 @@
+//! Channels muted and unmuted at set times of a render, as read from the
+//! file given to `--mute-automation`:
+//!
+//! ```text
+//! # Drop the lead for the second chorus
+//! mute channel 3 from 1:00 to 1:30
+//! mute channel 5 at 2:00
+//! unmute channel 5 at 2:45
+//! ```
+//!
+//! Times count from the start of the render, so from the start of `--range`
+//! if one is given, and keep counting through repeats of the song.
+
+use anyhow::{anyhow, Result};
+use openmpt::index::ChannelIndex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::time::parse_seconds;
+
+/// A channel muted or unmuted at a point of the render.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MuteEvent {
+    /// Seconds from the start of the render
+    pub seconds: f64,
+    /// Written as the channel number, from 1
+    #[serde(with = "channel_number")]
+    pub channel: ChannelIndex,
+    pub mute: bool,
+}
+
+/// Parse a channel number of an automation file, from 1.
+pub(crate) fn parse_channel(text: &str) -> Result<ChannelIndex> {
+    text.parse()
+        .ok()
+        .and_then(ChannelIndex::from_number)
+        .ok_or_else(|| anyhow!("invalid channel '{}': channels are numbered from 1", text))
+}
+
+/// Write a [`ChannelIndex`] as its number, from 1, like automation files give
+/// it, and check the number when reading it back.
+pub(crate) mod channel_number {
+    use openmpt::index::ChannelIndex;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        channel: &ChannelIndex,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i32(channel.number())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<ChannelIndex, D::Error> {
+        let number = i32::deserialize(deserializer)?;
+        ChannelIndex::from_number(number).ok_or_else(|| {
+            D::Error::custom(format!(
+                "invalid channel {}: channels are numbered from 1",
+                number
+            ))
+        })
+    }
+}
+
+/// Mute events in the order they happen.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MuteAutomation {
+    events: Vec<MuteEvent>,
+}
+
+impl MuteAutomation {
+    /// Read the automation file at `path`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| anyhow!("Invalid automation file {}: {}", path.display(), e))
+    }
+
+    /// Parse automation lines such as `mute channel 3 from 1:00 to 1:30` or
+    /// `unmute channel 3 at 1:30`. Blank lines and lines starting with `#` are
+    /// skipped.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut events = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            parse_line(line, &mut events).map_err(|e| anyhow!("line {}: {}", number + 1, e))?;
+        }
+        // Events at the same time keep the order of the file
+        events.sort_by(|a, b| a.seconds.total_cmp(&b.seconds));
+        Ok(MuteAutomation { events })
+    }
+
+    pub fn events(&self) -> &[MuteEvent] {
+        &self.events
+    }
+
+    /// Fail if an event names a channel past the `num_channels` of the module.
+    pub fn check_channels(&self, num_channels: i32) -> Result<()> {
+        match self.events.iter().find(|e| e.channel.get() >= num_channels) {
+            Some(event) => Err(anyhow!(
+                "Channel {} is automated, but the module only has {} channels",
+                event.channel,
+                num_channels
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_line(line: &str, events: &mut Vec<MuteEvent>) -> Result<()> {
+    let expected = || {
+        anyhow!(
+            "expected 'mute channel 3 from 1:00 to 1:30' or 'unmute channel 3 at 1:30', got '{}'",
+            line
+        )
+    };
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let mute = match words.first() {
+        Some(&"mute") => true,
+        Some(&"unmute") => false,
+        _ => return Err(expected()),
+    };
+    if words.get(1) != Some(&"channel") {
+        return Err(expected());
+    }
+    let channel = parse_channel(words.get(2).ok_or_else(expected)?)?;
+    let event = |seconds: &str, mute: bool| -> Result<MuteEvent> {
+        Ok(MuteEvent {
+            seconds: parse_seconds(seconds)?,
+            channel,
+            mute,
+        })
+    };
+
+    match words[3..] {
+        ["at", time] | ["from", time] => events.push(event(time, mute)?),
+        ["from", start, "to", end] => {
+            let (start, end) = (event(start, mute)?, event(end, !mute)?);
+            if end.seconds <= start.seconds {
+                return Err(anyhow!("'{}' ends before it starts", line));
+            }
+            events.extend([start, end]);
+        }
+        _ => return Err(expected()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_automation() {
+        let automation = MuteAutomation::parse(
+            "# Bootleg edit\n\
+             mute channel 3 from 1:00 to 1:30\n\
+             \n\
+             unmute channel 1 at 10s\n\
+             mute channel 2 from 0:30\n",
+        )
+        .unwrap();
+        let events: Vec<(f64, i32, bool)> = automation
+            .events()
+            .iter()
+            .map(|e| (e.seconds, e.channel.number(), e.mute))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (10.0, 1, false),
+                (30.0, 2, true),
+                (60.0, 3, true),
+                (90.0, 3, false)
+            ]
+        );
+        assert_eq!(automation.events()[0].channel.get(), 0);
+        assert!(automation.check_channels(3).is_ok());
+        assert!(automation.check_channels(2).is_err());
+
+        let error = MuteAutomation::parse("mute channel 3\n").unwrap_err();
+        assert!(
+            error.to_string().starts_with("line 1: expected"),
+            "{}",
+            error
+        );
+        assert!(MuteAutomation::parse("mute channel 0 at 1:00").is_err());
+        assert!(MuteAutomation::parse("mute instrument 2 at 1:00").is_err());
+        assert!(MuteAutomation::parse("mute channel 2 from 1:30 to 1:00").is_err());
+        assert!(MuteAutomation::parse("mute channel 2 at soon").is_err());
+    }
+}
@@ -0,0 +1,256 @@
+//! The stems of a module as `untracker tui` shows them: their names, when
+//! they play and how busy they are, read from the pattern data without
+//! rendering anything, and which of them to extract.
+
+use openmpt::index::{ChannelIndex, InstrumentIndex, SampleIndex};
+use openmpt::mod_command::Note;
+use openmpt::module::Module;
+
+/// Equal parts of the song the activity of a stem is counted over.
+pub const ACTIVITY_BINS: usize = 24;
+
+/// One instrument (or sample) of the module.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrowserStem {
+    pub index: InstrumentIndex,
+    pub name: String,
+    /// Notes played in the song
+    pub notes: usize,
+    /// When the first and last notes play, in seconds
+    pub span: Option<(f64, f64)>,
+    /// Notes played in each of [`ACTIVITY_BINS`] equal parts of the song
+    pub activity: Vec<usize>,
+    /// Whether to extract the stem
+    pub selected: bool,
+}
+
+impl BrowserStem {
+    /// The activity as a row of bars, one per part of the song, from blank
+    /// for parts without notes to full for the busiest part of any stem.
+    pub fn activity_bar(&self, busiest: usize) -> String {
+        const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+        self.activity
+            .iter()
+            .map(|&notes| {
+                if notes == 0 {
+                    return ' ';
+                }
+                let level = (notes * BARS.len()).div_ceil(busiest.max(1));
+                BARS[level.clamp(1, BARS.len()) - 1]
+            })
+            .collect()
+    }
+}
+
+/// The stems of a module, with the one under the cursor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StemBrowser {
+    pub title: String,
+    /// Length of the song in seconds
+    pub duration: f64,
+    /// Whether the stems are instruments, or samples for modules without instruments
+    pub is_instrument: bool,
+    pub stems: Vec<BrowserStem>,
+    pub cursor: usize,
+}
+
+impl StemBrowser {
+    /// List the stems of `module` and survey when they play, from the
+    /// patterns of its order list. All stems start out selected.
+    pub fn survey(module: &mut Module) -> Self {
+        let num_instruments = module.get_num_instruments();
+        let is_instrument = num_instruments > 0;
+        let count = if is_instrument {
+            num_instruments
+        } else {
+            module.get_num_samples()
+        };
+        let duration = module.get_duration_seconds();
+        let mut stems: Vec<BrowserStem> = InstrumentIndex::all(count)
+            .map(|index| BrowserStem {
+                index,
+                name: if is_instrument {
+                    module.get_instrument_name(index)
+                } else {
+                    module.get_sample_name(SampleIndex::new(index.get()))
+                },
+                notes: 0,
+                span: None,
+                activity: vec![0; ACTIVITY_BINS],
+                selected: true,
+            })
+            .collect();
+
+        let channels = module.get_num_channels();
+        // A note without an instrument plays the last one set on its channel
+        let mut instruments = vec![0u8; channels.max(0) as usize];
+        let num_orders = module.get_num_orders();
+        // Seeking to each order tells when it starts
+        let starts: Vec<f64> = (0..num_orders)
+            .map(|order| module.set_position_order_row(order, 0))
+            .collect();
+
+        for order in 0..num_orders {
+            let Some(mut pattern) = module.get_pattern_by_order(order) else {
+                continue;
+            };
+            let num_rows = pattern.get_num_rows();
+            if num_rows <= 0 {
+                continue;
+            }
+            let start = starts[order as usize];
+            let end = starts
+                .get(order as usize + 1)
+                .copied()
+                .filter(|&end| end > start)
+                .unwrap_or(duration);
+            for row in 0..num_rows {
+                let Some(mut cells) = pattern.get_row_by_number(row) else {
+                    continue;
+                };
+                let seconds = start + (end - start) * row as f64 / num_rows as f64;
+                for channel in ChannelIndex::all(channels) {
+                    let Some(Ok(command)) =
+                        cells.get_cell_by_channel(channel).map(|mut c| c.get_data())
+                    else {
+                        continue;
+                    };
+                    let n = channel.get() as usize;
+                    if command.instr != 0 {
+                        instruments[n] = command.instr;
+                    }
+                    let Note::Note(_) = command.note else {
+                        continue;
+                    };
+                    let Some(stem) = (instruments[n] as usize)
+                        .checked_sub(1)
+                        .and_then(|i| stems.get_mut(i))
+                    else {
+                        continue;
+                    };
+                    stem.notes += 1;
+                    stem.span = Some(match stem.span {
+                        Some((first, last)) => (first.min(seconds), last.max(seconds)),
+                        None => (seconds, seconds),
+                    });
+                    let bin = if duration > 0.0 {
+                        (seconds / duration * ACTIVITY_BINS as f64) as usize
+                    } else {
+                        0
+                    };
+                    stem.activity[bin.min(ACTIVITY_BINS - 1)] += 1;
+                }
+            }
+        }
+        module.set_position_seconds(0.0);
+
+        StemBrowser {
+            title: module.get_metadata("title").unwrap_or_default(),
+            duration,
+            is_instrument,
+            stems,
+            cursor: 0,
+        }
+    }
+
+    /// Most notes played by a stem in one part of the song.
+    pub fn busiest(&self) -> usize {
+        self.stems
+            .iter()
+            .flat_map(|stem| stem.activity.iter().copied())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Move the cursor `delta` stems down, or up when negative, stopping at
+    /// the first and last stems.
+    pub fn move_cursor(&mut self, delta: isize) {
+        let last = self.stems.len().saturating_sub(1);
+        self.cursor = self.cursor.saturating_add_signed(delta).min(last);
+    }
+
+    /// Select the stem under the cursor, or deselect it if it was selected.
+    pub fn toggle(&mut self) {
+        if let Some(stem) = self.stems.get_mut(self.cursor) {
+            stem.selected = !stem.selected;
+        }
+    }
+
+    pub fn select_all(&mut self, selected: bool) {
+        self.stems
+            .iter_mut()
+            .for_each(|stem| stem.selected = selected);
+    }
+
+    /// Select the stems the song plays, and deselect the others.
+    pub fn select_played(&mut self) {
+        self.stems
+            .iter_mut()
+            .for_each(|stem| stem.selected = stem.notes > 0);
+    }
+
+    pub fn selected(&self) -> Vec<InstrumentIndex> {
+        self.stems
+            .iter()
+            .filter(|stem| stem.selected)
+            .map(|stem| stem.index)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{FixtureModule, FixtureNote, FixtureSample};
+    use openmpt::module::Logger;
+
+    #[test]
+    fn test_survey_and_select() {
+        // The lead plays through the first pattern, and the bass the second
+        let buffer = FixtureModule::new("Browsed")
+            .sample(FixtureSample::square("lead", 16))
+            .sample(FixtureSample::square("bass", 64))
+            .sample(FixtureSample::square("unused", 32))
+            .pattern(
+                (0..16)
+                    .map(|step| FixtureNote::new(step * 4, 0, 1))
+                    .collect(),
+            )
+            .pattern(
+                (0..16)
+                    .map(|step| FixtureNote::new(step * 4, 1, 2))
+                    .collect(),
+            )
+            .to_mod();
+        let mut module = Module::create_from_memory(&buffer, Logger::None, &[]).unwrap();
+        let mut browser = StemBrowser::survey(&mut module);
+
+        assert!(!browser.is_instrument);
+        assert_eq!(browser.title, "Browsed");
+        let names: Vec<&str> = browser.stems.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["lead", "bass", "unused"]);
+        let notes: Vec<usize> = browser.stems.iter().map(|s| s.notes).collect();
+        assert_eq!(notes, [16, 16, 0]);
+        let (first, last) = browser.stems[1].span.unwrap();
+        assert!((first - browser.duration / 2.0).abs() < 0.1, "{}", first);
+        assert!(last < browser.duration);
+
+        let busiest = browser.busiest();
+        let lead = browser.stems[0].activity_bar(busiest);
+        assert_eq!(lead.chars().count(), ACTIVITY_BINS);
+        assert!(lead.starts_with('█') && lead.ends_with(' '));
+        assert!(browser.stems[1].activity_bar(busiest).starts_with(' '));
+        assert_eq!(browser.stems[2].activity_bar(busiest).trim(), "");
+
+        browser.select_played();
+        browser.move_cursor(-1);
+        browser.toggle();
+        browser.move_cursor(5);
+        assert_eq!(browser.cursor, 2);
+        browser.toggle();
+        let selected: Vec<i32> = browser.selected().iter().map(|i| i.number()).collect();
+        assert_eq!(selected, [2, 3]);
+        browser.select_all(false);
+        assert!(browser.selected().is_empty());
+    }
+}
@@ -0,0 +1,220 @@
+//! On-disk cache of rendered files, keyed by module contents and render options.
+
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::audio::ExportOptions;
+use crate::StemRenderResult;
+
+/// Extension of the file holding the [`StemRenderResult`] of a cache entry.
+const RESULT_EXTENSION: &str = "json";
+
+/// A directory of previously rendered files with a size limit. Once the limit
+/// is exceeded, the least recently used entries are evicted.
+pub struct RenderCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl RenderCache {
+    pub fn new(dir: &Path, max_bytes: u64) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .map_err(|e| anyhow!("Failed to create cache directory {}: {}", dir.display(), e))?;
+        Ok(RenderCache {
+            dir: dir.to_path_buf(),
+            max_bytes,
+        })
+    }
+
+    /// Cache key of the render of `stem` (e.g. "instrument 3") from `module` with `options`.
+    pub fn key(module: &[u8], stem: &str, options: &ExportOptions) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(module);
+        // The version is part of the key since renders may change between releases
+        hasher
+            .update(format!("\0{}\0{}\0{:?}", env!("CARGO_PKG_VERSION"), stem, options).as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Return the cached render for `key`, copied to `destination`, or render
+    /// it with `render` and add it to the cache.
+    pub fn get_or_render(
+        &self,
+        key: &str,
+        destination: &Path,
+        render: impl FnOnce() -> Result<StemRenderResult>,
+    ) -> Result<StemRenderResult> {
+        if let Some(result) = self.fetch(key, destination) {
+            log::info!("Using cached render for {}", destination.display());
+            return Ok(result);
+        }
+
+        let result = render()?;
+        // A failure to cache shouldn't fail the render itself
+        if let Err(e) = self.store(key, &result) {
+            log::warn!("Failed to cache {}: {}", result.path.display(), e);
+        }
+        Ok(result)
+    }
+
+    fn audio_path(&self, key: &str, destination: &Path) -> PathBuf {
+        let extension = destination
+            .extension()
+            .map(|e| e.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.dir.join(key).with_extension(extension)
+    }
+
+    fn fetch(&self, key: &str, destination: &Path) -> Option<StemRenderResult> {
+        let audio_path = self.audio_path(key, destination);
+        let result_path = self.dir.join(key).with_extension(RESULT_EXTENSION);
+
+        let mut result: StemRenderResult =
+            serde_json::from_str(&fs::read_to_string(&result_path).ok()?).ok()?;
+        fs::copy(&audio_path, destination).ok()?;
+
+        // Eviction is by modification time, so mark the entry as recently used
+        if let Ok(file) = fs::File::options().write(true).open(&audio_path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+
+        result.path = destination.to_path_buf();
+        Some(result)
+    }
+
+    fn store(&self, key: &str, result: &StemRenderResult) -> Result<()> {
+        let audio_path = self.audio_path(key, &result.path);
+        let result_path = self.dir.join(key).with_extension(RESULT_EXTENSION);
+
+        // Write under temporary names so concurrent renders never see half an entry
+        let partial = |path: &Path| path.with_extension(format!("partial-{}", std::process::id()));
+        fs::copy(&result.path, partial(&audio_path))?;
+        fs::rename(partial(&audio_path), &audio_path)?;
+        fs::write(partial(&result_path), serde_json::to_string(result)?)?;
+        fs::rename(partial(&result_path), &result_path)?;
+
+        self.evict()
+    }
+
+    /// Remove the least recently used entries until the cache fits its size limit.
+    fn evict(&self) -> Result<()> {
+        let mut entries = Vec::new();
+        let mut total = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let metadata = entry.metadata()?;
+            total += metadata.len();
+            let extension = path.extension().unwrap_or_default().to_string_lossy();
+            // Entries being written by other renders are left alone
+            if extension != RESULT_EXTENSION && !extension.starts_with("partial") {
+                entries.push((metadata.modified()?, metadata.len(), path));
+            }
+        }
+
+        entries.sort();
+        for (_, size, path) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            log::debug!("Evicting {} from the render cache", path.display());
+            fs::remove_file(&path)?;
+            let result_path = path.with_extension(RESULT_EXTENSION);
+            total -= size + fs::metadata(&result_path).map(|m| m.len()).unwrap_or(0);
+            let _ = fs::remove_file(result_path);
+        }
+        Ok(())
+    }
+}
+
+/// Parse a size such as `500M`, `2G` or `1048576` into bytes.
+pub fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let (number, multiplier) = match s.char_indices().last() {
+        Some((i, unit)) if unit.is_ascii_alphabetic() => {
+            let multiplier = match unit.to_ascii_uppercase() {
+                'K' => 1 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                'T' => 1 << 40,
+                _ => return Err(anyhow!("Invalid size unit in '{}'", s)),
+            };
+            (&s[..i], multiplier)
+        }
+        _ => (s, 1),
+    };
+
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Invalid size '{}'", s))?;
+    if number < 0.0 {
+        return Err(anyhow!("Size cannot be negative"));
+    }
+    Ok((number * multiplier as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StemAnalysis;
+
+    fn rendered(path: &Path, size: usize) -> StemRenderResult {
+        fs::write(path, vec![1u8; size]).unwrap();
+        StemRenderResult {
+            path: path.to_path_buf(),
+            frames: 100,
+            duration: 1.0,
+            peak: 0.5,
+            start_offset: 0.0,
+            gain_db: 0.0,
+            loudness: None,
+            loop_start: None,
+            skipped_reason: None,
+            analysis: StemAnalysis::new(&[0; 4], 2, 16),
+        }
+    }
+
+    #[test]
+    fn test_cache_hit_and_eviction() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = RenderCache::new(&dir.path().join("cache"), 3000).unwrap();
+        let options = ExportOptions::default();
+
+        let key_a = RenderCache::key(b"module", "sample 1", &options);
+        assert_ne!(key_a, RenderCache::key(b"module", "sample 2", &options));
+
+        let out_a = dir.path().join("a.wav");
+        cache
+            .get_or_render(&key_a, &out_a, || Ok(rendered(&out_a, 1000)))
+            .unwrap();
+        let copy = dir.path().join("copy.wav");
+        let result = cache
+            .get_or_render(&key_a, &copy, || panic!("should be cached"))
+            .unwrap();
+        assert_eq!(result.path, copy);
+        assert_eq!(result.frames, 100);
+        assert_eq!(fs::read(&copy).unwrap().len(), 1000);
+
+        // Pushes the cache over its limit, evicting the oldest entry
+        let key_b = RenderCache::key(b"module", "sample 2", &options);
+        let out_b = dir.path().join("b.wav");
+        cache
+            .get_or_render(&key_b, &out_b, || Ok(rendered(&out_b, 2500)))
+            .unwrap();
+        assert!(cache.fetch(&key_a, &copy).is_none());
+        assert!(cache.fetch(&key_b, &copy).is_some());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("500M").unwrap(), 500 << 20);
+        assert_eq!(parse_size("1.5g").unwrap(), 3 << 29);
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("").is_err());
+    }
+}
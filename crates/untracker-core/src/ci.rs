@@ -0,0 +1,201 @@
+//! Results of `--ci` runs, for validating module archives as a test job of a
+//! build pipeline: one line per module on the console, and a report of the
+//! run as JUnit XML, which CI servers show as test results, or JSON.
+//!
+//! Modules are reported in the order they were extracted, which is sorted by
+//! path, so two runs over the same archive print and write the same thing
+//! but for the timings.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::daw_session::xml_escape;
+
+/// How the extraction of one module went.
+#[derive(Debug, Clone, Serialize)]
+pub struct CiResult {
+    pub module: PathBuf,
+    /// "passed", "failed", or "skipped" for modules `--resume` left alone
+    pub status: String,
+    /// Number of stems written
+    pub stems: usize,
+    /// Messages libopenmpt logged while loading the module
+    pub warnings: usize,
+    pub seconds: f64,
+    pub error: Option<String>,
+}
+
+impl CiResult {
+    pub fn new(module: &Path, result: &Result<usize>, warnings: usize, elapsed: Duration) -> Self {
+        CiResult {
+            module: module.to_path_buf(),
+            status: if result.is_ok() { "passed" } else { "failed" }.to_string(),
+            stems: *result.as_ref().unwrap_or(&0),
+            warnings,
+            seconds: elapsed.as_secs_f64(),
+            // One line, as the error is printed on the module's line
+            error: result
+                .as_ref()
+                .err()
+                .map(|e| format!("{:#}", e).replace('\n', " ")),
+        }
+    }
+
+    pub fn skipped(module: &Path) -> Self {
+        CiResult {
+            module: module.to_path_buf(),
+            status: "skipped".to_string(),
+            stems: 0,
+            warnings: 0,
+            seconds: 0.0,
+            error: None,
+        }
+    }
+
+    /// The line printed for the module.
+    pub fn line(&self) -> String {
+        let mut line = format!("{:<7} {}", self.status, self.module.display());
+        match &self.error {
+            Some(error) => write!(line, ": {}", error).unwrap(),
+            None if self.status == "skipped" => line.push_str(": already extracted"),
+            None => write!(line, ": {} stems in {:.1}s", self.stems, self.seconds).unwrap(),
+        }
+        if self.warnings > 0 {
+            write!(line, ", {} libopenmpt warnings", self.warnings).unwrap();
+        }
+        line
+    }
+}
+
+/// Every module of a `--ci` run.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CiReport {
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub modules: Vec<CiResult>,
+}
+
+impl CiReport {
+    pub fn push(&mut self, result: CiResult) {
+        match result.status.as_str() {
+            "passed" => self.passed += 1,
+            "failed" => self.failed += 1,
+            _ => self.skipped += 1,
+        }
+        self.modules.push(result);
+    }
+
+    fn seconds(&self) -> f64 {
+        self.modules.iter().map(|module| module.seconds).sum()
+    }
+
+    /// The report as a JUnit test suite with a test case per module.
+    pub fn to_junit(&self) -> String {
+        let mut out = String::new();
+        let counts = format!(
+            "tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{:.3}\"",
+            self.modules.len(),
+            self.failed,
+            self.skipped,
+            self.seconds()
+        );
+        writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+        writeln!(out, "<testsuites name=\"untracker\" {}>", counts).unwrap();
+        writeln!(out, "  <testsuite name=\"untracker\" {}>", counts).unwrap();
+        for module in &self.modules {
+            let path = xml_escape(&module.module.to_string_lossy());
+            write!(
+                out,
+                "    <testcase classname=\"untracker\" name=\"{}\" file=\"{}\" time=\"{:.3}\"",
+                path, path, module.seconds
+            )
+            .unwrap();
+            match (module.status.as_str(), &module.error) {
+                ("failed", error) => {
+                    let error = xml_escape(error.as_deref().unwrap_or_default());
+                    writeln!(out, ">").unwrap();
+                    writeln!(
+                        out,
+                        "      <failure message=\"{}\">{}</failure>",
+                        error, error
+                    )
+                    .unwrap();
+                    writeln!(out, "    </testcase>").unwrap();
+                }
+                ("skipped", _) => {
+                    writeln!(out, ">").unwrap();
+                    writeln!(out, "      <skipped message=\"already extracted\"/>").unwrap();
+                    writeln!(out, "    </testcase>").unwrap();
+                }
+                _ => writeln!(out, "/>").unwrap(),
+            }
+        }
+        writeln!(out, "  </testsuite>").unwrap();
+        writeln!(out, "</testsuites>").unwrap();
+        out
+    }
+
+    /// Write the report to `path`: JUnit XML if it ends in `.xml`, JSON otherwise.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let is_xml = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("xml"));
+        let text = if is_xml {
+            self.to_junit()
+        } else {
+            serde_json::to_string_pretty(self)?
+        };
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_ci_report() {
+        let mut report = CiReport::default();
+        let second = Duration::from_secs(1);
+        report.push(CiResult::new(Path::new("a.mod"), &Ok(4), 0, second));
+        report.push(CiResult::new(
+            Path::new("b&c.xm"),
+            &Err(anyhow!("Failed to load module")),
+            2,
+            second,
+        ));
+        report.push(CiResult::skipped(Path::new("d.it")));
+        assert_eq!((report.passed, report.failed, report.skipped), (1, 1, 1));
+
+        let lines: Vec<String> = report.modules.iter().map(CiResult::line).collect();
+        assert_eq!(
+            lines,
+            [
+                "passed  a.mod: 4 stems in 1.0s",
+                "failed  b&c.xm: Failed to load module, 2 libopenmpt warnings",
+                "skipped d.it: already extracted",
+            ]
+        );
+
+        let junit = report.to_junit();
+        assert!(junit.contains("<testsuite name=\"untracker\" tests=\"3\" failures=\"1\""));
+        assert!(junit.contains("name=\"b&amp;c.xm\""));
+        assert!(junit.contains("<failure message=\"Failed to load module\">"));
+        assert!(junit.contains("<skipped message=\"already extracted\"/>"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        report.write(&path).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["failed"], 1);
+        assert_eq!(json["modules"][1]["error"], "Failed to load module");
+    }
+}
@@ -0,0 +1,145 @@
+//! Colors and group tags of stems, recorded in the manifest so that sessions
+//! built from it open with related tracks grouped and colored alike rather
+//! than as rows of identical gray tracks.
+//!
+//! Stems are tagged with the group of the `standard` preset their name falls
+//! in (drums, bass, chords, lead, fx or other), and group stems with their own
+//! group. Each group gets a fixed color, which `--colors` can replace.
+
+use anyhow::{anyhow, Result};
+
+use crate::groups::{GroupPreset, OTHER_GROUP};
+
+/// A color as 8-bit red, green and blue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b }
+    }
+}
+
+impl std::fmt::Display for Color {
+    /// `#rrggbb`, as most DAWs and web tools take colors.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+impl std::str::FromStr for Color {
+    type Err = anyhow::Error;
+
+    /// Parse `#rrggbb`, or `rrggbb`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim().trim_start_matches('#');
+        let value = (hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+            .then(|| u32::from_str_radix(hex, 16).ok())
+            .flatten()
+            .ok_or_else(|| anyhow!("Invalid color '{}': expected #rrggbb", s.trim()))?;
+        Ok(Color::new(
+            (value >> 16) as u8,
+            (value >> 8) as u8,
+            value as u8,
+        ))
+    }
+}
+
+/// Colors of the groups of the `standard` preset.
+const GROUP_COLORS: &[(&str, Color)] = &[
+    ("drums", Color::new(0xd9, 0x48, 0x3b)),
+    ("bass", Color::new(0x3b, 0x6e, 0xd9)),
+    ("chords", Color::new(0x8e, 0x5b, 0xc9)),
+    ("lead", Color::new(0xe8, 0xa3, 0x2e)),
+    ("fx", Color::new(0x3b, 0xa5, 0x6a)),
+    (OTHER_GROUP, Color::new(0x8a, 0x8a, 0x8a)),
+];
+
+/// Colors picked from for groups without one of their own, by their name.
+const SPARE_COLORS: &[Color] = &[
+    Color::new(0xd9, 0x5b, 0x9a),
+    Color::new(0x2e, 0xb5, 0xc0),
+    Color::new(0xa6, 0xc2, 0x3a),
+    Color::new(0xc9, 0x7b, 0x4a),
+    Color::new(0x5d, 0x5b, 0xc9),
+    Color::new(0x6f, 0x9e, 0x8f),
+];
+
+/// Group tag of a stem of an instrument or sample named `name`.
+pub fn stem_group(name: &str) -> &'static str {
+    GroupPreset::Standard.classify(name).unwrap_or(OTHER_GROUP)
+}
+
+/// The color of each group: the built-in ones, then those given to
+/// `--colors`, such as `drums=#ff0000,vox=#00aaff`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StemColors {
+    overrides: Vec<(String, Color)>,
+}
+
+impl StemColors {
+    /// Color of `group`. Groups outside the `standard` preset, such as those
+    /// of `--groups`, get one picked from their name, so it stays the same
+    /// from one module to the next.
+    pub fn color(&self, group: &str) -> Color {
+        if let Some((_, color)) = self.overrides.iter().find(|(name, _)| name == group) {
+            return *color;
+        }
+        if let Some((_, color)) = GROUP_COLORS.iter().find(|(name, _)| *name == group) {
+            return *color;
+        }
+        // FNV-1a, which unlike the standard hasher is the same in every build
+        let hash = group.bytes().fold(0x811c9dc5u32, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x01000193)
+        });
+        SPARE_COLORS[hash as usize % SPARE_COLORS.len()]
+    }
+}
+
+impl std::str::FromStr for StemColors {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let overrides = s
+            .split(',')
+            .map(|part| {
+                let (group, color) = part
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("Expected GROUP=#rrggbb, got '{}'", part.trim()))?;
+                Ok((group.trim().to_string(), color.parse()?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(StemColors { overrides })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stem_colors() {
+        assert_eq!(stem_group("BassDrum"), "drums");
+        assert_eq!(stem_group("Sub bass"), "bass");
+        assert_eq!(stem_group("untitled"), OTHER_GROUP);
+
+        let colors = StemColors::default();
+        assert_eq!(colors.color("drums").to_string(), "#d9483b");
+        // Other groups keep their color from run to run
+        assert_eq!(colors.color("vox"), colors.color("vox"));
+        assert!(SPARE_COLORS.contains(&colors.color("vox")));
+
+        let colors: StemColors = "drums=#FF0000, vox=00aaff".parse().unwrap();
+        assert_eq!(colors.color("drums"), Color::new(255, 0, 0));
+        assert_eq!(colors.color("vox").to_string(), "#00aaff");
+        assert_eq!(colors.color("bass").to_string(), "#3b6ed9");
+
+        assert!("drums".parse::<StemColors>().is_err());
+        assert!("drums=red".parse::<StemColors>().is_err());
+        assert!("drums=#ff00".parse::<StemColors>().is_err());
+    }
+}
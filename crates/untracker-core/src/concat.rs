@@ -0,0 +1,345 @@
+//! Concatenation of stem sets, for `untracker concat`: the parts of a
+//! megamix split across modules, extracted one module at a time, joined into
+//! one continuous stem set.
+//!
+//! Stems are matched across sets by name, and each set keeps its length on
+//! the joined timeline whichever of its stems are silent or missing, so the
+//! joins land on the same frame in every stem. Sets have to be WAV: the
+//! encoder delay and padding of lossy formats would shift the joins.
+
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::audio::dsp;
+use crate::audio::{AudioFormat, ExportOptions, SampleFormat};
+use crate::container::file_name_from_title;
+use crate::manifest::{ManifestStem, StemManifest, STEM_MANIFEST_FILE};
+
+/// How consecutive sets are joined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Join {
+    /// Seconds of silence between sets, none to butt them together
+    Gap(f64),
+    /// Seconds over which the end of a set fades into the start of the next
+    Crossfade(f64),
+}
+
+impl Default for Join {
+    fn default() -> Self {
+        Join::Gap(0.0)
+    }
+}
+
+/// The stems extracted from one module, with the manifest written next to
+/// them by `--write-metadata`.
+#[derive(Debug, Clone)]
+pub struct StemSet {
+    pub dir: PathBuf,
+    pub manifest: StemManifest,
+    /// Length of the set on the joined timeline
+    pub frames: u64,
+}
+
+impl StemSet {
+    /// Read the manifest of the stem set in `dir`, and the headers of its
+    /// files to find its length.
+    pub fn open(dir: &Path) -> Result<Self> {
+        let manifest_path = dir.join(STEM_MANIFEST_FILE);
+        let manifest: StemManifest = serde_json::from_str(
+            &fs::read_to_string(&manifest_path)
+                .map_err(|e| anyhow!("Cannot read {}: {}", manifest_path.display(), e))?,
+        )
+        .map_err(|e| anyhow!("Invalid manifest {}: {}", manifest_path.display(), e))?;
+        if manifest.settings.format != "wav" {
+            return Err(anyhow!(
+                "Cannot join the stems in {}: they are {}, and only WAV stems join on the exact frame",
+                dir.display(),
+                manifest.settings.format
+            ));
+        }
+
+        let sample_rate = manifest.settings.sample_rate as f64;
+        let mut frames = 0;
+        for stem in kept(&manifest) {
+            let path = dir.join(&stem.file);
+            let reader = hound::WavReader::open(&path)
+                .map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?;
+            let start = (stem.start_offset * sample_rate).round() as u64;
+            frames = frames.max(start + reader.duration() as u64);
+        }
+        // Stems trimmed at the end stop at their last sound, short of the
+        // end of the song
+        if manifest.settings.trim_silence != "off" {
+            let song = [
+                Some(manifest.duration),
+                manifest.settings.preview,
+                manifest.settings.max_duration,
+            ]
+            .into_iter()
+            .flatten()
+            .reduce(f64::min)
+            .unwrap_or_default();
+            frames = frames.max((song * sample_rate).round() as u64);
+        }
+
+        Ok(StemSet {
+            dir: dir.to_path_buf(),
+            manifest,
+            frames,
+        })
+    }
+
+    /// The stems of the set by the names they are matched by: their name in
+    /// the module, or their kind and number if they have none. Names that
+    /// come up more than once are numbered from the second one on.
+    pub fn stems(&self) -> Vec<(String, &ManifestStem)> {
+        let mut seen: BTreeMap<String, usize> = BTreeMap::new();
+        self.manifest
+            .stems
+            .iter()
+            .map(|stem| {
+                let name = if stem.name.is_empty() {
+                    match stem.members.as_slice() {
+                        [number] => format!("{} {}", stem.kind, number),
+                        _ => stem.kind.clone(),
+                    }
+                } else {
+                    stem.name.clone()
+                };
+                let count = seen.entry(name.to_lowercase()).or_default();
+                *count += 1;
+                let name = match *count {
+                    1 => name,
+                    n => format!("{} {}", name, n),
+                };
+                (name, stem)
+            })
+            .collect()
+    }
+
+    fn stem(&self, name: &str) -> Option<&ManifestStem> {
+        self.stems()
+            .into_iter()
+            .find(|(stem_name, _)| stem_name.eq_ignore_ascii_case(name))
+            .map(|(_, stem)| stem)
+    }
+}
+
+/// Stems whose file was kept.
+fn kept(manifest: &StemManifest) -> impl Iterator<Item = &ManifestStem> {
+    manifest.stems.iter().filter(|stem| stem.skipped.is_none())
+}
+
+/// Names of the stems of all `sets`, in the order they first come up.
+pub fn stem_names(sets: &[StemSet]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for set in sets {
+        for (name, _) in set.stems() {
+            if !names.iter().any(|n| n.eq_ignore_ascii_case(&name)) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+/// File name of a joined stem: `<base_name>_<name>.wav`.
+pub fn concat_file_name(base_name: &str, name: &str) -> String {
+    let name = file_name_from_title(name).unwrap_or_else(|| "stem".to_string());
+    crate::custom_file_name(base_name, &name, AudioFormat::Wav)
+}
+
+/// Options to write the joined stems with: WAV at the sample rate and
+/// channels all `sets` share, and the bit depth of the first one.
+pub fn output_options(sets: &[StemSet]) -> Result<ExportOptions> {
+    let first = &sets
+        .first()
+        .ok_or_else(|| anyhow!("No stem sets to join"))?
+        .manifest
+        .settings;
+    for set in sets {
+        let settings = &set.manifest.settings;
+        if (settings.sample_rate, settings.channels) != (first.sample_rate, first.channels) {
+            return Err(anyhow!(
+                "Cannot join {}: its stems are {} Hz, {} channels, where the first set's are {} Hz, {} channels",
+                set.dir.display(),
+                settings.sample_rate,
+                settings.channels,
+                first.sample_rate,
+                first.channels
+            ));
+        }
+    }
+    Ok(ExportOptions {
+        format: AudioFormat::Wav,
+        sample_rate: first.sample_rate,
+        channels: first.channels,
+        bit_depth: first.bit_depth,
+        sample_format: if first.sample_format == "float" {
+            SampleFormat::Float
+        } else {
+            SampleFormat::Int
+        },
+        dither: false,
+        ..ExportOptions::default()
+    })
+}
+
+fn seconds_to_frames(seconds: f64, sample_rate: u32) -> u64 {
+    (seconds.max(0.0) * sample_rate as f64).round() as u64
+}
+
+/// Frame at which each set starts on the joined timeline.
+pub fn part_starts(sets: &[StemSet], join: Join, sample_rate: u32) -> Vec<u64> {
+    let mut starts = Vec::with_capacity(sets.len());
+    let mut end = 0u64;
+    for (i, set) in sets.iter().enumerate() {
+        let start = match join {
+            _ if i == 0 => 0,
+            Join::Gap(seconds) => end + seconds_to_frames(seconds, sample_rate),
+            // A crossfade is no longer than either of the sets
+            Join::Crossfade(seconds) => {
+                let overlap = seconds_to_frames(seconds, sample_rate)
+                    .min(sets[i - 1].frames)
+                    .min(set.frames);
+                end - overlap
+            }
+        };
+        starts.push(start);
+        end = start + set.frames;
+    }
+    starts
+}
+
+/// Join the stem matched by `name` across `sets`, as interleaved samples
+/// written with `options`. Sets without the stem, or where it was skipped
+/// as silent, are silence.
+pub fn concat_stem(
+    sets: &[StemSet],
+    name: &str,
+    join: Join,
+    options: &ExportOptions,
+) -> Result<Vec<f32>> {
+    let channels = options.channels as usize;
+    let starts = part_starts(sets, join, options.sample_rate);
+    let mut joined: Vec<f32> = Vec::new();
+    for (set, &start) in sets.iter().zip(&starts) {
+        let mut part = vec![0f32; set.frames as usize * channels];
+        if let Some(stem) = set.stem(name).filter(|stem| stem.skipped.is_none()) {
+            let path = set.dir.join(&stem.file);
+            let audio = read_wav(&path)?;
+            let offset = seconds_to_frames(stem.start_offset, options.sample_rate) as usize;
+            let offset = (offset * channels).min(part.len());
+            let len = audio.len().min(part.len() - offset);
+            part[offset..offset + len].copy_from_slice(&audio[..len]);
+        }
+
+        let overlap = joined.len().saturating_sub(start as usize * channels);
+        if overlap > 0 {
+            let tail = joined.split_off(joined.len() - overlap);
+            dsp::crossfade(&mut part, &tail, channels);
+        } else {
+            joined.resize(start as usize * channels, 0.0);
+        }
+        joined.extend_from_slice(&part);
+    }
+    Ok(joined)
+}
+
+/// Samples of a WAV file, as fractions of full scale.
+fn read_wav(path: &Path) -> Result<Vec<f32>> {
+    let fail = |e: hound::Error| anyhow!("Cannot read {}: {}", path.display(), e);
+    let mut reader = hound::WavReader::open(path).map_err(fail)?;
+    let spec = reader.spec();
+    match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().map(|s| s.map_err(fail)).collect(),
+        hound::SampleFormat::Int => {
+            let scale = (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / scale).map_err(fail))
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::write_audio_file;
+
+    /// A stem set of one stem per `(name, start frame, samples)`, at 1000 Hz mono.
+    fn stem_set(dir: &Path, stems: &[(&str, u64, Vec<i16>)]) -> StemSet {
+        let options = ExportOptions {
+            sample_rate: 1000,
+            channels: 1,
+            ..ExportOptions::default()
+        };
+        fs::create_dir_all(dir).unwrap();
+        let stems = stems
+            .iter()
+            .enumerate()
+            .map(|(i, (name, start, samples))| {
+                let file = format!("part_{}.wav", i);
+                write_audio_file(samples, dir.join(&file), &options.encoder()).unwrap();
+                serde_json::from_value(serde_json::json!({
+                    "file": file,
+                    "kind": "instrument",
+                    "name": name,
+                    "members": [i + 1],
+                    "duration": samples.len() as f64 / 1000.0,
+                    "start_offset": *start as f64 / 1000.0,
+                    "peak_dbfs": null,
+                    "size": null,
+                    "skipped": null,
+                }))
+                .unwrap()
+            })
+            .collect();
+        let manifest = StemManifest::new("part.mod", &BTreeMap::new(), 0.0, &options, stems);
+        fs::write(
+            dir.join(STEM_MANIFEST_FILE),
+            serde_json::to_string(&manifest).unwrap(),
+        )
+        .unwrap();
+        StemSet::open(dir).unwrap()
+    }
+
+    #[test]
+    fn test_concat_stems() {
+        let dir = tempfile::tempdir().unwrap();
+        let sets = [
+            stem_set(
+                &dir.path().join("a"),
+                &[("Bass", 0, vec![1000; 10]), ("", 0, vec![2000; 8])],
+            ),
+            stem_set(&dir.path().join("b"), &[("bass", 2, vec![3000; 4])]),
+        ];
+        assert_eq!((sets[0].frames, sets[1].frames), (10, 6));
+        assert_eq!(stem_names(&sets), ["Bass", "instrument 2"]);
+        assert_eq!(
+            concat_file_name("mix", "instrument 2"),
+            "mix_instrument_2.wav"
+        );
+        let options = output_options(&sets).unwrap();
+
+        // Butted together, the second set's stem lands at its offset in the set
+        let bass = concat_stem(&sets, "Bass", Join::default(), &options).unwrap();
+        assert_eq!(bass.len(), 16);
+        let level = 3000.0 / 32768.0;
+        assert_eq!(&bass[10..], &[0.0, 0.0, level, level, level, level]);
+
+        // Sets without the stem are silent
+        let lead = concat_stem(&sets, "instrument 2", Join::Gap(0.004), &options).unwrap();
+        assert_eq!(part_starts(&sets, Join::Gap(0.004), 1000), [0, 14]);
+        assert_eq!(lead.len(), 20);
+        assert!(lead[8..].iter().all(|&s| s == 0.0));
+
+        let crossfaded = concat_stem(&sets, "Bass", Join::Crossfade(1.0), &options).unwrap();
+        assert_eq!(part_starts(&sets, Join::Crossfade(1.0), 1000), [0, 4]);
+        assert_eq!(crossfaded.len(), 10);
+        assert_eq!(crossfaded[4], 1000.0 / 32768.0);
+    }
+}
@@ -0,0 +1,242 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::audio::ExportOptions;
+use openmpt::module::Module;
+
+/// Suffix of the sidecar file holding per-module overrides (`song.xm` -> `song.untracker.toml`).
+pub const SIDECAR_SUFFIX: &str = ".untracker.toml";
+
+/// Options read from a module's sidecar file. Every field is optional and only
+/// the ones present override the global command line options.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ModuleOverrides {
+    pub format: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    pub render_channels: Option<u32>,
+    pub resample: Option<String>,
+    pub bit_depth: Option<u32>,
+    pub opus_bitrate: Option<u32>,
+    pub opus_vbr: Option<String>,
+    pub opus_complexity: Option<u32>,
+    pub opus_application: Option<String>,
+    pub vorbis_quality: Option<u32>,
+    pub mp3_bitrate: Option<u32>,
+    pub mp3_vbr_quality: Option<u32>,
+    pub stereo_separation: Option<i32>,
+}
+
+impl ModuleOverrides {
+    /// Path of the sidecar file that belongs to `input`.
+    pub fn sidecar_path(input: &Path) -> PathBuf {
+        let stem = crate::unpack::module_stem(input).unwrap_or_default();
+        input.with_file_name(format!("{}{}", stem, SIDECAR_SUFFIX))
+    }
+
+    /// Load the sidecar next to `input`, if there is one.
+    pub fn load_for(input: &Path) -> Result<Option<Self>> {
+        let path = Self::sidecar_path(input);
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        log::info!("Loading module overrides from {}", path.display());
+        let text = fs::read_to_string(&path)?;
+        let overrides = Self::parse(&text)
+            .map_err(|e| anyhow!("Invalid override file {}: {}", path.display(), e))?;
+        Ok(Some(overrides))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Return a copy of `options` with the overridden fields replaced.
+    pub fn apply(&self, options: &ExportOptions) -> Result<ExportOptions> {
+        let mut options = options.clone();
+
+        if let Some(format) = &self.format {
+            options.format = format.parse()?;
+        }
+        if let Some(resample) = &self.resample {
+            options.resample = resample.parse()?;
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            options.sample_rate = sample_rate;
+        }
+        if let Some(channels) = self.channels {
+            options.channels = channels;
+        }
+        if self.render_channels.is_some() {
+            options.render_channels = self.render_channels;
+        }
+        if let Some(bit_depth) = self.bit_depth {
+            options.bit_depth = bit_depth;
+        }
+        if let Some(opus_bitrate) = self.opus_bitrate {
+            options.opus_bitrate = opus_bitrate;
+        }
+        if let Some(opus_vbr) = &self.opus_vbr {
+            options.opus_vbr = opus_vbr.parse()?;
+        }
+        if let Some(opus_complexity) = self.opus_complexity {
+            options.opus_complexity = opus_complexity;
+        }
+        if let Some(opus_application) = &self.opus_application {
+            options.opus_application = opus_application.parse()?;
+        }
+        if let Some(vorbis_quality) = self.vorbis_quality {
+            options.vorbis_quality = vorbis_quality;
+        }
+        if let Some(mp3_bitrate) = self.mp3_bitrate {
+            options.mp3_bitrate = mp3_bitrate;
+        }
+        if self.mp3_vbr_quality.is_some() {
+            options.mp3_vbr_quality = self.mp3_vbr_quality;
+        }
+        if let Some(stereo_separation) = self.stereo_separation {
+            options.stereo_separation = stereo_separation;
+        }
+
+        Ok(options)
+    }
+
+    /// Names of the options the file sets, as written in it.
+    pub fn keys(&self) -> Vec<&'static str> {
+        [
+            ("format", self.format.is_some()),
+            ("sample-rate", self.sample_rate.is_some()),
+            ("channels", self.channels.is_some()),
+            ("render-channels", self.render_channels.is_some()),
+            ("resample", self.resample.is_some()),
+            ("bit-depth", self.bit_depth.is_some()),
+            ("opus-bitrate", self.opus_bitrate.is_some()),
+            ("opus-vbr", self.opus_vbr.is_some()),
+            ("opus-complexity", self.opus_complexity.is_some()),
+            ("opus-application", self.opus_application.is_some()),
+            ("vorbis-quality", self.vorbis_quality.is_some()),
+            ("mp3-bitrate", self.mp3_bitrate.is_some()),
+            ("mp3-vbr-quality", self.mp3_vbr_quality.is_some()),
+            ("stereo-separation", self.stereo_separation.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(key, given)| given.then_some(key))
+        .collect()
+    }
+}
+
+/// Module formats of the Amiga, whose 4 channels are panned hard left and right.
+const AMIGA_FORMATS: &[&str] = &["mod", "stk", "m15", "st26", "ice", "pt36"];
+
+/// Options chosen for each module from its format when neither the command
+/// line nor the module's override file sets them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModuleDefaults {
+    pub stereo_separation: bool,
+}
+
+impl ModuleDefaults {
+    /// Whether any option is left to the module.
+    pub fn any(&self) -> bool {
+        self.stereo_separation
+    }
+
+    /// The defaults left after `overrides` set some of the options.
+    pub fn without(self, overrides: &ModuleOverrides) -> Self {
+        ModuleDefaults {
+            stereo_separation: self.stereo_separation && overrides.stereo_separation.is_none(),
+        }
+    }
+
+    /// Set the options left to `module`, returning why each value was chosen.
+    pub fn apply(&self, options: &mut ExportOptions, module: &mut Module) -> Vec<String> {
+        let mut reasons = Vec::new();
+        if self.stereo_separation {
+            let format = module.get_metadata("type").unwrap_or_default();
+            let (separation, reason) = stereo_separation_for(&format, module.get_num_channels());
+            options.stereo_separation = separation;
+            reasons.push(format!("stereo separation {}%: {}", separation, reason));
+        }
+        reasons
+    }
+}
+
+/// Stereo separation for a module of `format` with `channels` channels, and why.
+///
+/// Amiga modules keep their full separation, which is part of their sound.
+/// Other modules are mixed with half of it: with more channels, instruments
+/// panned hard to one side stand out more than their composers heard them
+/// on most trackers.
+pub fn stereo_separation_for(format: &str, channels: i32) -> (i32, String) {
+    if channels == 4 && AMIGA_FORMATS.contains(&format) {
+        (100, format!("4-channel Amiga module ({})", format))
+    } else {
+        (50, format!("{}-channel {} module", channels, format))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{OpusVbr, ResampleMethod};
+
+    fn base_options() -> ExportOptions {
+        ExportOptions::default()
+    }
+
+    #[test]
+    fn test_sidecar_path() {
+        let path = ModuleOverrides::sidecar_path(Path::new("music/song.xm"));
+        assert_eq!(path, Path::new("music/song.untracker.toml"));
+    }
+
+    #[test]
+    fn test_overrides_apply_only_present_fields() {
+        let overrides = ModuleOverrides::parse(
+            "sample-rate = 22050\nresample = \"nearest\"\nstereo-separation = 0\nopus-vbr = \"off\"\n",
+        )
+        .unwrap();
+        let options = overrides.apply(&base_options()).unwrap();
+
+        assert_eq!(options.sample_rate, 22050);
+        assert_eq!(options.resample, ResampleMethod::Nearest);
+        assert_eq!(options.stereo_separation, 0);
+        assert_eq!(options.opus_vbr, OpusVbr::Off);
+        assert_eq!(options.channels, 2);
+        assert_eq!(options.bit_depth, 16);
+    }
+
+    #[test]
+    fn test_module_defaults() {
+        assert_eq!(stereo_separation_for("mod", 4).0, 100);
+        assert_eq!(stereo_separation_for("mod", 8).0, 50);
+        assert_eq!(stereo_separation_for("s3m", 4).0, 50);
+
+        let overrides = ModuleOverrides::parse("stereo-separation = 80\n").unwrap();
+        assert_eq!(overrides.keys(), ["stereo-separation"]);
+        let defaults = ModuleDefaults {
+            stereo_separation: true,
+        };
+        assert!(!defaults.without(&overrides).any());
+
+        for (file, expected) in [("cndmcrrp.mod", 100), ("nova.s3m", 50)] {
+            let buffer = fs::read(Path::new("../../tests/modules").join(file)).unwrap();
+            let module_ext =
+                openmpt::ext::ModuleExt::from_memory(&buffer, openmpt::module::Logger::None, &[])
+                    .unwrap();
+            let mut options = base_options();
+            let reasons = defaults.apply(&mut options, &mut module_ext.get_module());
+            assert_eq!(options.stereo_separation, expected);
+            assert!(reasons[0].starts_with(&format!("stereo separation {}%", expected)));
+        }
+    }
+
+    #[test]
+    fn test_unknown_override_key_is_rejected() {
+        assert!(ModuleOverrides::parse("sample_rat = 22050\n").is_err());
+    }
+}
@@ -0,0 +1,181 @@
+//! Modules that come packed in another file: embedded in a container such as
+//! Unreal Music (UMX) packages, compressed by an Amiga or DOS packer, or
+//! stored as MO3.
+//!
+//! libopenmpt unpacks these on load. The file name then often says little
+//! about the music, such as the package a game level's music comes in, so
+//! outputs are named after the module inside.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Containers that only compress the module they hold.
+const PACKERS: &[&str] = &["mmcmp", "pp20", "xpk"];
+
+/// How a module file is packaged, from the metadata of the module.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleContainer {
+    /// Container the module is embedded in, e.g. "umx"
+    pub container: Option<String>,
+    /// Name of the container format, e.g. "Unreal Music"
+    pub container_name: Option<String>,
+    /// Format of the module itself, e.g. "it" for an MO3 of an Impulse Tracker module
+    pub format: Option<String>,
+    /// Tracker the format of the module belongs to, e.g. "Impulse Tracker"
+    pub format_name: Option<String>,
+    /// How the module is compressed, e.g. "MO3" or "PowerPacker", if it is
+    pub compression: Option<String>,
+}
+
+impl ModuleContainer {
+    /// Read the packaging of a module from its metadata, as returned by
+    /// [`crate::module_metadata`].
+    pub fn from_metadata(metadata: &BTreeMap<String, String>) -> Self {
+        let field = |key: &str| metadata.get(key).cloned();
+        let container = field("container");
+        let container_name = field("container_long");
+
+        // MO3 files load as their own format, and remember the one they were made from
+        if field("type").as_deref() == Some("mo3") {
+            return ModuleContainer {
+                container,
+                container_name,
+                format: field("originaltype").or_else(|| field("type")),
+                format_name: field("originaltype_long").or_else(|| field("type_long")),
+                compression: Some("MO3".to_string()),
+            };
+        }
+
+        let compression = container
+            .as_deref()
+            .filter(|container| PACKERS.contains(container))
+            .and(container_name.clone().or_else(|| container.clone()));
+        ModuleContainer {
+            container,
+            container_name,
+            format: field("type"),
+            format_name: field("type_long"),
+            compression,
+        }
+    }
+
+    /// Whether the module came out of a container or was decompressed.
+    pub fn is_packed(&self) -> bool {
+        self.container.is_some() || self.compression.is_some()
+    }
+
+    /// One-line description for people, e.g. "Scream Tracker 3 module inside
+    /// Unreal Music", or `None` for a plain module file.
+    pub fn describe(&self) -> Option<String> {
+        if !self.is_packed() {
+            return None;
+        }
+        let format = self
+            .format_name
+            .as_deref()
+            .or(self.format.as_deref())
+            .unwrap_or("unknown");
+        let mut description = format!("{} module", format);
+        let is_packer = self
+            .container
+            .as_deref()
+            .is_some_and(|container| PACKERS.contains(&container));
+        // Packers are already named as the compression
+        let container = self.container_name.as_ref().or(self.container.as_ref());
+        if let Some(container) = container.filter(|_| !is_packer) {
+            description += &format!(" inside {}", container);
+        }
+        if let Some(compression) = &self.compression {
+            description += &format!(", compressed with {}", compression);
+        }
+        Some(description)
+    }
+}
+
+/// Base name of the output files of a module whose file is named `file_stem`.
+///
+/// Packed modules are named after the title of the module inside, if it has
+/// one that makes a file name.
+pub fn output_base_name(file_stem: &str, metadata: &BTreeMap<String, String>) -> String {
+    if !ModuleContainer::from_metadata(metadata).is_packed() {
+        return file_stem.to_string();
+    }
+    metadata
+        .get("title")
+        .and_then(|title| file_name_from_title(title))
+        .unwrap_or_else(|| file_stem.to_string())
+}
+
+/// `title` with characters that don't belong in file names replaced by
+/// underscores, or `None` if nothing is left.
+pub(crate) fn file_name_from_title(title: &str) -> Option<String> {
+    let mut name = String::new();
+    for c in title.trim().chars() {
+        if c.is_alphanumeric() || c == '-' || c == '.' {
+            name.push(c);
+        } else if !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    let name = name.trim_matches(|c| c == '_' || c == '.');
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|&(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_containers() {
+        let plain = metadata(&[("type", "xm"), ("title", "Song")]);
+        assert!(!ModuleContainer::from_metadata(&plain).is_packed());
+        assert_eq!(output_base_name("song", &plain), "song");
+
+        let umx = metadata(&[
+            ("container", "umx"),
+            ("container_long", "Unreal Music"),
+            ("type", "s3m"),
+            ("type_long", "Scream Tracker 3"),
+            ("title", "Mech 8: Boss / Fight!"),
+        ]);
+        let container = ModuleContainer::from_metadata(&umx);
+        assert_eq!(container.compression, None);
+        assert_eq!(
+            container.describe().unwrap(),
+            "Scream Tracker 3 module inside Unreal Music"
+        );
+        assert_eq!(output_base_name("Level01", &umx), "Mech_8_Boss_Fight");
+
+        let mo3 = metadata(&[
+            ("type", "mo3"),
+            ("type_long", "MO3"),
+            ("originaltype", "it"),
+            ("originaltype_long", "Impulse Tracker"),
+            ("title", "  "),
+        ]);
+        let container = ModuleContainer::from_metadata(&mo3);
+        assert_eq!(container.format.as_deref(), Some("it"));
+        assert_eq!(container.compression.as_deref(), Some("MO3"));
+        assert_eq!(
+            container.describe().unwrap(),
+            "Impulse Tracker module, compressed with MO3"
+        );
+        // Without a usable title, the file name is kept
+        assert_eq!(output_base_name("track01", &mo3), "track01");
+
+        let packed = metadata(&[("container", "pp20"), ("container_long", "PowerPacker")]);
+        let container = ModuleContainer::from_metadata(&packed);
+        assert_eq!(container.compression.as_deref(), Some("PowerPacker"));
+        assert_eq!(
+            container.describe().unwrap(),
+            "unknown module, compressed with PowerPacker"
+        );
+    }
+}
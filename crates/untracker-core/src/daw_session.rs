@@ -0,0 +1,446 @@
+//! DAW sessions opening the stems of a module as aligned tracks, written by
+//! `--export-session`: a Reaper project, or an Ardour session.
+//!
+//! Each stem gets a track holding one item that starts where the stem starts
+//! in the song, named and colored like in the manifest, and the tempo changes
+//! of the song go into the tempo map.
+
+use anyhow::{anyhow, Result};
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use crate::audio::AudioFormat;
+use crate::colors::Color;
+use crate::markers::TempoChange;
+use crate::remix_kit::bpm;
+
+/// Session file formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionFormat {
+    /// Reaper project (.RPP), referencing the stems next to it
+    Reaper,
+    /// Ardour 6 session, which later versions convert when opening it
+    Ardour,
+}
+
+impl std::str::FromStr for SessionFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reaper" | "rpp" => Ok(SessionFormat::Reaper),
+            "ardour" => Ok(SessionFormat::Ardour),
+            _ => Err(anyhow!("Unknown session format: {}", s)),
+        }
+    }
+}
+
+impl SessionFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            SessionFormat::Reaper => "RPP",
+            SessionFormat::Ardour => "ardour",
+        }
+    }
+}
+
+/// A stem on its track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionTrack {
+    pub name: String,
+    /// File name of the stem, in the session's directory
+    pub file: String,
+    /// Where the stem starts in the song, in seconds
+    pub start_offset: f64,
+    pub frames: u64,
+    pub color: Option<Color>,
+}
+
+/// A session with a track per stem.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DawSession {
+    /// Project name, the module title
+    pub title: String,
+    pub artist: String,
+    /// Directory of the stems and the session file. Ardour sessions refer to
+    /// the stems by absolute path, so it has to be absolute for them
+    pub dir: PathBuf,
+    pub format: AudioFormat,
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub tracks: Vec<SessionTrack>,
+    pub tempo_changes: Vec<TempoChange>,
+}
+
+impl DawSession {
+    /// Length of the session in seconds, up to the end of the last stem.
+    pub fn length(&self) -> f64 {
+        self.tracks
+            .iter()
+            .map(|track| track.start_offset + track.frames as f64 / self.sample_rate as f64)
+            .fold(0.0, f64::max)
+    }
+
+    /// The session file in `format`.
+    pub fn write(&self, format: SessionFormat) -> String {
+        match format {
+            SessionFormat::Reaper => self.write_reaper(),
+            SessionFormat::Ardour => self.write_ardour(),
+        }
+    }
+
+    fn write_reaper(&self) -> String {
+        let quote = |s: &str| s.replace('"', "'");
+        let source = match self.format.name() {
+            "flac" => "FLAC",
+            "vorbis" => "VORBIS",
+            "opus" => "OPUS",
+            "mp3" => "MP3",
+            _ => "WAVE",
+        };
+        let initial_bpm = self
+            .tempo_changes
+            .first()
+            .map_or(120.0, |change| bpm(change.tempo, change.speed));
+
+        let mut out = String::new();
+        writeln!(out, "<REAPER_PROJECT 0.1 \"6.0\" 0").unwrap();
+        writeln!(out, "  TITLE \"{}\"", quote(&self.title)).unwrap();
+        writeln!(out, "  AUTHOR \"{}\"", quote(&self.artist)).unwrap();
+        writeln!(out, "  TEMPO {} 4 4", round_bpm(initial_bpm)).unwrap();
+        writeln!(out, "  SAMPLERATE {} 0 0", self.sample_rate).unwrap();
+        if self.tempo_changes.len() > 1 {
+            writeln!(out, "  <TEMPOENVEX").unwrap();
+            writeln!(out, "    ACT 1 -1").unwrap();
+            for change in &self.tempo_changes {
+                // Shape 1 holds the tempo until the next point
+                writeln!(
+                    out,
+                    "    PT {:.6} {} 1",
+                    change.time,
+                    round_bpm(bpm(change.tempo, change.speed))
+                )
+                .unwrap();
+            }
+            writeln!(out, "  >").unwrap();
+        }
+        for track in &self.tracks {
+            writeln!(out, "  <TRACK").unwrap();
+            writeln!(out, "    NAME \"{}\"", quote(&track.name)).unwrap();
+            if let Some(color) = track.color {
+                // A Windows COLORREF, with the flag that marks it as set
+                let colorref =
+                    0x100_0000 | (color.b as u32) << 16 | (color.g as u32) << 8 | color.r as u32;
+                writeln!(out, "    PEAKCOL {}", colorref).unwrap();
+            }
+            writeln!(out, "    <ITEM").unwrap();
+            writeln!(out, "      POSITION {:.6}", track.start_offset).unwrap();
+            writeln!(
+                out,
+                "      LENGTH {:.6}",
+                track.frames as f64 / self.sample_rate as f64
+            )
+            .unwrap();
+            writeln!(out, "      NAME \"{}\"", quote(&track.name)).unwrap();
+            writeln!(out, "      <SOURCE {}", source).unwrap();
+            writeln!(out, "        FILE \"{}\"", quote(&track.file)).unwrap();
+            writeln!(out, "      >").unwrap();
+            writeln!(out, "    >").unwrap();
+            writeln!(out, "  >").unwrap();
+        }
+        writeln!(out, ">").unwrap();
+        out
+    }
+
+    fn write_ardour(&self) -> String {
+        let mut ids = 1000u64..;
+        let mut next_id = || ids.next().unwrap();
+        let frames = |seconds: f64| (seconds * self.sample_rate as f64).round() as u64;
+        let channels = self.channels as usize;
+        let length = frames(self.length());
+
+        let mut sources = String::new();
+        let mut routes = String::new();
+        let mut playlists = String::new();
+        let master_id = next_id();
+        for (i, track) in self.tracks.iter().enumerate() {
+            let source_ids: Vec<u64> = (0..channels).map(|_| next_id()).collect();
+            let path = self.dir.join(&track.file);
+            for (channel, id) in source_ids.iter().enumerate() {
+                writeln!(
+                    sources,
+                    "    <Source name=\"{}\" type=\"audio\" flags=\"\" id=\"{}\" captured-for=\"\" channel=\"{}\" origin=\"\" gain=\"1\"/>",
+                    xml_escape(&path.to_string_lossy()),
+                    id,
+                    channel
+                )
+                .unwrap();
+            }
+
+            let (route_id, playlist_id, region_id) = (next_id(), next_id(), next_id());
+            let name = xml_escape(&track.name);
+            let mut region = format!(
+                "<Region name=\"{}\" muted=\"0\" opaque=\"1\" locked=\"0\" video-locked=\"0\" automatic=\"0\" whole-file=\"0\" import=\"0\" external=\"1\" sync-marked=\"0\" left-of-split=\"0\" right-of-split=\"0\" hidden=\"0\" position-locked=\"0\" valid-transients=\"0\" start=\"0\" length=\"{}\" position=\"{}\" beat=\"0\" sync-position=\"0\" ancestral-start=\"0\" ancestral-length=\"0\" stretch=\"1\" shift=\"1\" positional-lock-style=\"AudioTime\" layering-index=\"0\" envelope-active=\"0\" default-fade-in=\"1\" default-fade-out=\"1\" fade-in-active=\"1\" fade-out-active=\"1\" scale-amplitude=\"1\" id=\"{}\" type=\"audio\" first-edit=\"nothing\" channels=\"{}\"",
+                name,
+                track.frames,
+                frames(track.start_offset),
+                region_id,
+                channels
+            );
+            for (channel, id) in source_ids.iter().enumerate() {
+                write!(
+                    region,
+                    " source-{0}=\"{1}\" master-source-{0}=\"{1}\"",
+                    channel, id
+                )
+                .unwrap();
+            }
+            region.push_str("/>");
+            writeln!(
+                playlists,
+                "    <Playlist id=\"{}\" name=\"{}\" type=\"audio\" orig-track-id=\"{}\" shared-with-ids=\"\" frozen=\"0\" combine-ops=\"0\">\n      {}\n    </Playlist>",
+                playlist_id, name, route_id, region
+            )
+            .unwrap();
+
+            let color = track.color.map_or(0, |color| {
+                (color.r as u32) << 24 | (color.g as u32) << 16 | (color.b as u32) << 8 | 0xff
+            });
+            let (input_id, output_id) = (next_id(), next_id());
+            writeln!(
+                routes,
+                "    <Route version=\"6000\" id=\"{}\" name=\"{}\" default-type=\"audio\" strict-io=\"1\" active=\"1\" denormal-protection=\"0\" meter-point=\"MeterPostFader\" disk-io-point=\"DiskIOPreFader\" meter-type=\"MeterPeak\" audio-playlist=\"{}\" saved-meter-point=\"MeterPostFader\" alignment-choice=\"Automatic\" playlist=\"{}\" mode=\"Normal\">",
+                route_id, name, playlist_id, name
+            )
+            .unwrap();
+            writeln!(
+                routes,
+                "      <PresentationInfo order=\"{}\" flags=\"AudioTrack,OrderSet\" color=\"{}\"/>",
+                i, color
+            )
+            .unwrap();
+            write_io(&mut routes, &name, input_id, "Input", channels, None);
+            write_io(
+                &mut routes,
+                &name,
+                output_id,
+                "Output",
+                channels,
+                Some("Master"),
+            );
+            writeln!(routes, "    </Route>").unwrap();
+        }
+
+        let mut master = String::new();
+        writeln!(
+            master,
+            "    <Route version=\"6000\" id=\"{}\" name=\"Master\" default-type=\"audio\" strict-io=\"1\" active=\"1\" denormal-protection=\"0\" meter-point=\"MeterPostFader\" disk-io-point=\"DiskIOPreFader\" meter-type=\"MeterK20\">",
+            master_id
+        )
+        .unwrap();
+        writeln!(
+            master,
+            "      <PresentationInfo order=\"0\" flags=\"MasterOut,OrderSet\" color=\"0\"/>"
+        )
+        .unwrap();
+        write_io(&mut master, "Master", next_id(), "Input", channels, None);
+        write_io(
+            &mut master,
+            "Master",
+            next_id(),
+            "Output",
+            channels,
+            Some("system:playback"),
+        );
+        writeln!(master, "    </Route>").unwrap();
+
+        let mut tempo_map = String::new();
+        let mut beats = 0.0;
+        let mut previous: Option<(f64, f64)> = None;
+        for change in &self.tempo_changes {
+            let change_bpm = round_bpm(bpm(change.tempo, change.speed));
+            if let Some((time, bpm)) = previous {
+                beats += (change.time - time) * bpm / 60.0;
+            }
+            previous = Some((change.time, change_bpm));
+            writeln!(
+                tempo_map,
+                "    <Tempo pulse=\"{}\" frame=\"{}\" movable=\"{}\" lock-style=\"AudioTime\" beats-per-minute=\"{}\" note-type=\"4\" clamped=\"0\" end-beats-per-minute=\"{}\" active=\"1\" locked-to-meter=\"0\" continuing=\"0\"/>",
+                beats / 4.0,
+                frames(change.time),
+                u8::from(change.time > 0.0),
+                change_bpm,
+                change_bpm
+            )
+            .unwrap();
+        }
+        if self.tempo_changes.is_empty() {
+            tempo_map.push_str("    <Tempo pulse=\"0\" frame=\"0\" movable=\"0\" lock-style=\"AudioTime\" beats-per-minute=\"120\" note-type=\"4\" clamped=\"0\" end-beats-per-minute=\"120\" active=\"1\" locked-to-meter=\"0\" continuing=\"0\"/>\n");
+        }
+        tempo_map.push_str("    <Meter pulse=\"0\" frame=\"0\" movable=\"0\" lock-style=\"AudioTime\" bbt=\"1|1|0\" beat=\"0\" note-value=\"4\" divisions-per-bar=\"4\"/>\n");
+
+        let name = xml_escape(&self.title);
+        let mut out = String::new();
+        writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+        writeln!(
+            out,
+            "<Session version=\"6000\" name=\"{}\" sample-rate=\"{}\" end-is-free=\"0\" id-counter=\"{}\" name-counter=\"1\" event-counter=\"0\" vca-counter=\"1\">",
+            name,
+            self.sample_rate,
+            next_id()
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "  <ProgramVersion created-with=\"untracker {0}\" modified-with=\"untracker {0}\"/>",
+            env!("CARGO_PKG_VERSION")
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "  <Metadata>\n    <title>{}</title>\n    <artist>{}</artist>\n  </Metadata>",
+            name,
+            xml_escape(&self.artist)
+        )
+        .unwrap();
+        writeln!(out, "  <Sources>\n{}  </Sources>", sources).unwrap();
+        writeln!(
+            out,
+            "  <Locations>\n    <Location id=\"{}\" name=\"session\" start=\"0\" end=\"{}\" flags=\"IsSessionRange\" locked=\"0\" timestamp=\"0\" cue=\"0\"/>\n  </Locations>",
+            next_id(),
+            length
+        )
+        .unwrap();
+        writeln!(out, "  <Routes>\n{}{}  </Routes>", master, routes).unwrap();
+        writeln!(out, "  <Playlists>\n{}  </Playlists>", playlists).unwrap();
+        writeln!(out, "  <UnusedPlaylists/>").unwrap();
+        writeln!(out, "  <TempoMap>\n{}  </TempoMap>", tempo_map).unwrap();
+        writeln!(out, "</Session>").unwrap();
+        out
+    }
+}
+
+/// Tempo for the tempo maps of DAWs, which keep a few decimals.
+fn round_bpm(bpm: f64) -> f64 {
+    (bpm * 1000.0).round() / 1000.0
+}
+
+/// An input or output of an Ardour route, its ports connected to the ports
+/// of `connect_to` if given.
+fn write_io(
+    out: &mut String,
+    name: &str,
+    id: u64,
+    direction: &str,
+    channels: usize,
+    connect_to: Option<&str>,
+) {
+    let kind = if direction == "Input" { "in" } else { "out" };
+    writeln!(
+        out,
+        "      <IO name=\"{}\" id=\"{}\" direction=\"{}\" default-type=\"audio\" user-latency=\"0\">",
+        name, id, direction
+    )
+    .unwrap();
+    for port in 1..=channels {
+        let connection = match connect_to {
+            Some("system:playback") => format!("<Connection other=\"system:playback_{}\"/>", port),
+            Some(route) => format!("<Connection other=\"{}/audio_in {}\"/>", route, port),
+            None => String::new(),
+        };
+        writeln!(
+            out,
+            "        <Port type=\"audio\" name=\"{}/audio_{} {}\">{}</Port>",
+            name, kind, port, connection
+        )
+        .unwrap();
+    }
+    writeln!(out, "      </IO>").unwrap();
+}
+
+pub(crate) fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn session() -> DawSession {
+        DawSession {
+            title: "Karate <Muffins>".to_string(),
+            artist: "zalza".to_string(),
+            dir: PathBuf::from("/stems"),
+            format: AudioFormat::Wav,
+            sample_rate: 44100,
+            channels: 2,
+            tracks: vec![
+                SessionTrack {
+                    name: "Drums".to_string(),
+                    file: "song_group_drums.wav".to_string(),
+                    start_offset: 0.0,
+                    frames: 44100 * 10,
+                    color: Some(Color::new(0xd9, 0x48, 0x3b)),
+                },
+                SessionTrack {
+                    name: "Lead \"hook\"".to_string(),
+                    file: "song_sample_002.wav".to_string(),
+                    start_offset: 2.5,
+                    frames: 44100 * 4,
+                    color: None,
+                },
+            ],
+            tempo_changes: vec![
+                TempoChange {
+                    time: 0.0,
+                    tempo: 125.0,
+                    speed: 6,
+                },
+                TempoChange {
+                    time: 7.68,
+                    tempo: 125.0,
+                    speed: 3,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_reaper_project() {
+        let project = session().write(SessionFormat::Reaper);
+        assert!(
+            project.starts_with("<REAPER_PROJECT 0.1 \"6.0\" 0\n  TITLE \"Karate <Muffins>\"\n")
+        );
+        assert!(project.contains("  TEMPO 125 4 4\n"));
+        assert!(project.contains("    PT 7.680000 250 1\n"));
+        assert!(project.contains("    NAME \"Lead 'hook'\"\n"));
+        assert!(project.contains("      POSITION 2.500000\n      LENGTH 4.000000\n"));
+        assert!(project.contains("        FILE \"song_group_drums.wav\"\n"));
+        assert!(project.contains(&format!("    PEAKCOL {}\n", 0x13b48d9)));
+        assert_eq!(project.matches("<TRACK").count(), 2);
+    }
+
+    #[test]
+    fn test_ardour_session() {
+        let session = session();
+        assert_eq!(session.length(), 10.0);
+        let xml = session.write(SessionFormat::Ardour);
+        assert!(xml.contains("<Session version=\"6000\" name=\"Karate &lt;Muffins&gt;\""));
+        let source = Path::new("/stems").join("song_sample_002.wav");
+        assert_eq!(
+            xml.matches(&format!("name=\"{}\"", source.display()))
+                .count(),
+            2
+        );
+        assert!(xml.contains("position=\"110250\""));
+        assert!(xml.contains("name=\"Lead &quot;hook&quot;\""));
+        assert!(xml.contains("end=\"441000\" flags=\"IsSessionRange\""));
+        // 16 beats at 125 BPM before the tempo doubles
+        assert!(xml.contains("<Tempo pulse=\"4\" frame=\"338688\" movable=\"1\""));
+        assert!(xml.contains("<Connection other=\"Master/audio_in 2\"/>"));
+    }
+}
@@ -0,0 +1,272 @@
+//! Decoding of audio files with symphonia, for `untracker diff` and
+//! `--verify-output`.
+//!
+//! Nothing here shares code with the encoders: files are read back by an
+//! independent decoder, so an encoder bug can't hide itself by reading its
+//! own output the way it wrote it.
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::audio::ExportOptions;
+
+/// Audio decoded from a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedAudio {
+    pub sample_rate: u32,
+    pub channels: u32,
+    /// Interleaved samples, as fractions of full scale
+    pub samples: Vec<f32>,
+}
+
+impl DecodedAudio {
+    /// Decode the file at `path`: WAV, FLAC, Vorbis or MP3, whichever
+    /// encoders this build has.
+    pub fn read(path: &Path) -> Result<Self> {
+        let fail = |e: SymphoniaError| anyhow!("Cannot decode {}: {}", path.display(), e);
+        let file =
+            fs::File::open(path).map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?;
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(extension);
+        }
+        let mut format = symphonia::default::get_probe()
+            .format(
+                &hint,
+                stream,
+                // Leave out the encoder delay and padding of lossy formats
+                &FormatOptions {
+                    enable_gapless: true,
+                    ..FormatOptions::default()
+                },
+                &MetadataOptions::default(),
+            )
+            .map_err(fail)?
+            .format;
+        let track = format
+            .default_track()
+            .ok_or_else(|| anyhow!("{} holds no audio", path.display()))?;
+        let track_id = track.id;
+        let sample_rate = track
+            .codec_params
+            .sample_rate
+            .ok_or_else(|| anyhow!("{} has no sample rate", path.display()))?;
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .map_err(fail)?;
+
+        let mut channels = track.codec_params.channels.map_or(0, |c| c.count() as u32);
+        let mut samples = Vec::new();
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    break
+                }
+                Err(e) => return Err(fail(e)),
+            };
+            if packet.track_id() != track_id {
+                continue;
+            }
+            let audio = decoder.decode(&packet).map_err(fail)?;
+            channels = audio.spec().channels.count() as u32;
+            let mut buffer = SampleBuffer::<f32>::new(audio.capacity() as u64, *audio.spec());
+            buffer.copy_interleaved_ref(audio);
+            samples.extend_from_slice(buffer.samples());
+        }
+
+        Ok(DecodedAudio {
+            sample_rate,
+            channels,
+            samples,
+        })
+    }
+
+    pub fn frames(&self) -> u64 {
+        match self.channels {
+            0 => 0,
+            channels => self.samples.len() as u64 / channels as u64,
+        }
+    }
+
+    pub fn seconds(&self) -> f64 {
+        self.frames() as f64 / self.sample_rate as f64
+    }
+}
+
+/// How far a file is from a reference, sample by sample.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct AudioDiff {
+    pub frames: u64,
+    pub reference_frames: u64,
+    /// RMS level of the reference, as a fraction of full scale
+    pub reference_rms: f64,
+    /// Peak of the difference, as a fraction of full scale
+    pub difference_peak: f64,
+    /// RMS level of the difference, as a fraction of full scale
+    pub difference_rms: f64,
+}
+
+impl AudioDiff {
+    /// Compare `audio` to `reference`. Where one is longer than the other,
+    /// its end counts against silence.
+    pub fn between(audio: &DecodedAudio, reference: &DecodedAudio) -> Result<Self> {
+        if (audio.sample_rate, audio.channels) != (reference.sample_rate, reference.channels) {
+            return Err(anyhow!(
+                "Can't compare {} Hz, {} channel audio to a {} Hz, {} channel reference",
+                audio.sample_rate,
+                audio.channels,
+                reference.sample_rate,
+                reference.channels
+            ));
+        }
+
+        let sample = |samples: &[f32], i: usize| samples.get(i).copied().unwrap_or(0.0) as f64;
+        let len = audio.samples.len().max(reference.samples.len());
+        let mut difference_peak: f64 = 0.0;
+        let (mut difference_sum, mut reference_sum) = (0.0, 0.0);
+        for i in 0..len {
+            let reference = sample(&reference.samples, i);
+            let difference = sample(&audio.samples, i) - reference;
+            difference_peak = difference_peak.max(difference.abs());
+            difference_sum += difference * difference;
+            reference_sum += reference * reference;
+        }
+        let rms = |sum: f64| {
+            if len == 0 {
+                0.0
+            } else {
+                (sum / len as f64).sqrt()
+            }
+        };
+
+        Ok(AudioDiff {
+            frames: audio.frames(),
+            reference_frames: reference.frames(),
+            reference_rms: rms(reference_sum),
+            difference_peak,
+            difference_rms: rms(difference_sum),
+        })
+    }
+
+    /// RMS level of the difference relative to that of the reference, in dB,
+    /// or negative infinity if the files are identical.
+    pub fn difference_db(&self) -> f64 {
+        20.0 * (self.difference_rms / self.reference_rms).log10()
+    }
+
+    /// Peak of the difference in dBFS.
+    pub fn difference_peak_dbfs(&self) -> f64 {
+        20.0 * self.difference_peak.log10()
+    }
+
+    pub fn is_identical(&self) -> bool {
+        self.difference_peak == 0.0 && self.frames == self.reference_frames
+    }
+}
+
+/// Frames of audio the output of a lossy encoder can be off by, once its
+/// delay and padding are left out: a tenth of a second.
+fn lossy_tolerance(sample_rate: u32) -> u64 {
+    sample_rate as u64 / 10
+}
+
+/// Check that the file at `path`, written with `options`, decodes to `frames`
+/// frames of audio at the sample rate and with the channels it should have.
+///
+/// Opus files are left unchecked, since symphonia has no Opus decoder.
+pub fn verify_output(path: &Path, options: &ExportOptions, frames: u64) -> Result<()> {
+    let format = options.format.name();
+    if format == "opus" {
+        log::info!("Not verifying {}: Opus can't be decoded", path.display());
+        return Ok(());
+    }
+
+    let audio = DecodedAudio::read(path)?;
+    if (audio.sample_rate, audio.channels) != (options.sample_rate, options.channels) {
+        return Err(anyhow!(
+            "{} decodes to {} Hz, {} channel audio instead of {} Hz, {} channels",
+            path.display(),
+            audio.sample_rate,
+            audio.channels,
+            options.sample_rate,
+            options.channels
+        ));
+    }
+    let tolerance = match format {
+        "wav" | "flac" => 0,
+        _ => lossy_tolerance(options.sample_rate),
+    };
+    if audio.frames().abs_diff(frames) > tolerance {
+        return Err(anyhow!(
+            "{} decodes to {} frames instead of {}",
+            path.display(),
+            audio.frames(),
+            frames
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::write_audio_file;
+
+    #[test]
+    fn test_decode_and_diff() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = ExportOptions::default();
+        let tone: Vec<i16> = (0..44100 * 2)
+            .map(|i| (((i / 2) as f32 * 0.05).sin() * 8000.0) as i16)
+            .collect();
+        let path = dir.path().join("tone.wav");
+        write_audio_file(&tone, &path, &options.encoder()).unwrap();
+
+        let audio = DecodedAudio::read(&path).unwrap();
+        assert_eq!((audio.sample_rate, audio.channels), (44100, 2));
+        assert_eq!(audio.frames(), 44100);
+        assert!(verify_output(&path, &options, 44100).is_ok());
+        assert!(verify_output(&path, &options, 44000).is_err());
+        let mono = ExportOptions {
+            channels: 1,
+            ..options
+        };
+        assert!(verify_output(&path, &mono, 44100).is_err());
+
+        let same = AudioDiff::between(&audio, &audio).unwrap();
+        assert!(same.is_identical());
+        assert_eq!(same.difference_db(), f64::NEG_INFINITY);
+
+        // Half the level leaves a difference 6 dB below the reference
+        let quieter = DecodedAudio {
+            samples: audio.samples.iter().map(|s| s / 2.0).collect(),
+            ..audio.clone()
+        };
+        let diff = AudioDiff::between(&quieter, &audio).unwrap();
+        assert!((diff.difference_db() + 6.02).abs() < 0.01, "{:?}", diff);
+
+        let short = DecodedAudio {
+            samples: audio.samples[..1000].to_vec(),
+            ..audio.clone()
+        };
+        assert!(!AudioDiff::between(&short, &audio).unwrap().is_identical());
+        let resampled = DecodedAudio {
+            sample_rate: 48000,
+            ..audio
+        };
+        assert!(AudioDiff::between(&resampled, &short).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+//! Estimates of how long an extraction takes and how much space its output
+//! needs, printed before rendering starts.
+//!
+//! Every stem is a render of the whole song with everything else muted, so a
+//! module with a hundred instruments renders the song a hundred times.
+
+use anyhow::Result;
+use std::time::Instant;
+
+use crate::audio::{AudioFormat, ExportOptions, NormalizeMode};
+use crate::session::RenderSession;
+
+/// Seconds of audio rendered to measure how fast a module renders.
+const SAMPLE_SECONDS: f64 = 2.0;
+
+/// What rendering some stems of a module will cost.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderEstimate {
+    pub stems: usize,
+    /// Length of each stem in seconds
+    pub seconds: f64,
+    /// Total size of the output files in bytes
+    pub bytes: u64,
+    /// Time to render all stems on one thread, in seconds
+    pub render_seconds: f64,
+}
+
+impl RenderEstimate {
+    /// Estimate the cost of rendering `stems` stems in `session`, by timing
+    /// the render of the first seconds of the song.
+    ///
+    /// The session is left at the start of the rendered range.
+    pub fn measure(session: &mut RenderSession, stems: usize) -> Result<Self> {
+        let options = session.options().clone();
+        let seconds = session.render_length();
+
+        let channels = options.channels as usize;
+        let sample_frames = (SAMPLE_SECONDS.min(seconds) * options.sample_rate as f64) as usize;
+        let mut buf = vec![0i16; 4096 * channels];
+        let started = Instant::now();
+        let mut rendered = 0;
+        while rendered < sample_frames {
+            let frames = session.read(&mut buf);
+            if frames == 0 {
+                break;
+            }
+            rendered += frames;
+        }
+        let elapsed = started.elapsed().as_secs_f64();
+        session.rewind()?;
+
+        let rendered_seconds = rendered as f64 / options.sample_rate as f64;
+        let speed = if elapsed > 0.0 && rendered_seconds > 0.0 {
+            rendered_seconds / elapsed
+        } else {
+            f64::INFINITY
+        };
+
+        Ok(RenderEstimate {
+            stems,
+            seconds,
+            bytes: (bytes_per_second(&options) * seconds * stems as f64) as u64,
+            render_seconds: seconds * stems as f64 / speed,
+        })
+    }
+
+    /// Time to render all stems with `jobs` worker threads, in seconds.
+    pub fn render_time(&self, jobs: usize) -> f64 {
+        self.render_seconds / jobs.clamp(1, self.stems.max(1)) as f64
+    }
+}
+
+/// Approximate size of a second of output audio in bytes.
+///
+/// Lossy formats are counted at their nominal bitrate, and FLAC at a typical
+/// ratio for tracker music, which has long stretches of silence.
+pub fn bytes_per_second(options: &ExportOptions) -> f64 {
+    let pcm = (options.sample_rate * options.channels * options.bit_depth / 8) as f64;
+    // A kbps is 125 bytes per second
+    #[allow(unreachable_patterns)]
+    match options.format {
+        AudioFormat::Wav => pcm,
+        #[cfg(feature = "flac")]
+        AudioFormat::Flac => pcm * 0.4,
+        #[cfg(feature = "opus")]
+        AudioFormat::Opus => options.opus_bitrate as f64 * 125.0,
+        #[cfg(feature = "vorbis")]
+        AudioFormat::Vorbis => {
+            const VORBIS_KBPS: [u32; 11] = [64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 500];
+            VORBIS_KBPS[options.vorbis_quality.min(10) as usize] as f64 * 125.0
+        }
+        #[cfg(feature = "mp3")]
+        AudioFormat::Mp3 => match options.mp3_vbr_quality {
+            Some(quality) => {
+                const LAME_VBR_KBPS: [u32; 10] = [245, 225, 190, 175, 165, 130, 115, 100, 85, 65];
+                LAME_VBR_KBPS[quality.min(9) as usize] as f64 * 125.0
+            }
+            None => options.mp3_bitrate as f64 * 125.0,
+        },
+        _ => pcm,
+    }
+}
+
+/// Memory a render of `seconds` of a module of `module_bytes` bytes takes
+/// at its peak, for [`crate::parallel::MemoryBudget`].
+///
+/// Renders that need the whole song before writing it, to normalize or loop
+/// it, hold all of its samples; others stream it to the encoder a chunk at a
+/// time. libopenmpt's copy of the module counts too, at
+/// twice the size of the file for its unpacked samples.
+pub fn render_memory(options: &ExportOptions, seconds: f64, module_bytes: u64) -> u64 {
+    let samples = (seconds.max(0.0) * options.sample_rate as f64) as u64 * options.channels as u64;
+    let sample_bytes = if options.render_float() { 4 } else { 2 };
+    let in_memory = options.loop_crossfade.is_some()
+        || options.normalize != NormalizeMode::Off
+        || options.replaygain;
+    let mut bytes = 2 * module_bytes + STREAMING_BYTES;
+    if in_memory {
+        bytes += samples * sample_bytes;
+    }
+    bytes
+}
+
+/// Memory of the buffers of a streamed render.
+const STREAMING_BYTES: u64 = 1 << 20;
+
+/// A size in bytes for people, such as "12.5 MB".
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// A duration for people, such as "1h05m", "3m20s" or "12s".
+pub fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.ceil() as u64;
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, _) => format!("{}h{:02}m", h, m),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        let options = ExportOptions {
+            sample_rate: 22050,
+            channels: 1,
+            ..ExportOptions::default()
+        };
+        assert_eq!(bytes_per_second(&options), 44100.0);
+
+        let buffer = include_bytes!("../../../tests/modules/nova.s3m");
+        let mut session = RenderSession::new(buffer, &options).unwrap();
+        let estimate = RenderEstimate::measure(&mut session, 31).unwrap();
+        assert_eq!(estimate.stems, 31);
+        assert!((estimate.seconds - session.duration_seconds()).abs() < 0.01);
+        assert_eq!(estimate.bytes, (44100.0 * estimate.seconds * 31.0) as u64);
+        assert!(estimate.render_time(4) <= estimate.render_seconds / 4.0);
+        // The render starts over from the beginning
+        assert!(session.position().seconds < 0.01);
+
+        // Streamed renders hold little more than the module
+        assert_eq!(render_memory(&options, 100.0, 1000), 2000 + STREAMING_BYTES);
+        let normalized = ExportOptions {
+            normalize: NormalizeMode::Peak,
+            ..options
+        };
+        assert_eq!(
+            render_memory(&normalized, 100.0, 1000),
+            2000 + STREAMING_BYTES + 22050 * 100 * 2
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_bytes(512), "512 bytes");
+        assert_eq!(format_bytes(3 << 20), "3.0 MB");
+        assert_eq!(format_bytes(1536 << 30), "1.5 TB");
+        assert_eq!(format_duration(0.2), "1s");
+        assert_eq!(format_duration(200.0), "3m20s");
+        assert_eq!(format_duration(3900.0), "1h05m");
+    }
+}
@@ -0,0 +1,497 @@
+//! Modules synthesized from code, for benchmarks and tests that shouldn't
+//! depend on real-world (and copyrighted) modules.
+//!
+//! [`FixtureModule`] writes ProTracker MOD or FastTracker 2 XM files with
+//! generated samples and the notes it is given, so what every sample or
+//! instrument plays, and when, is known.
+
+/// Rows in a pattern.
+pub const ROWS: usize = 64;
+
+/// C-4, the note that plays samples at their own rate, as numbered in XM
+/// files: from 1 for C-0.
+pub const C4: u8 = 49;
+
+/// Amiga periods of the notes MOD files can play, from C-3 to B-5 (ProTracker's C-1 to B-3).
+const PERIODS: [u16; 36] = [
+    856, 808, 762, 720, 678, 640, 604, 570, 538, 508, 480, 453, //
+    428, 404, 381, 360, 339, 320, 302, 285, 269, 254, 240, 226, //
+    214, 202, 190, 180, 170, 160, 151, 143, 135, 127, 120, 113,
+];
+
+/// Note of the first of [`PERIODS`].
+const FIRST_PERIOD_NOTE: u8 = C4 - 12;
+
+/// A sample of a fixture module, as signed 8-bit audio.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixtureSample {
+    pub name: String,
+    pub data: Vec<i8>,
+    /// Volume from 0 to 64
+    pub volume: u8,
+    /// Whether the whole sample loops, so that notes ring until they are cut
+    pub looped: bool,
+}
+
+impl FixtureSample {
+    /// A looped square wave with a period of `period` bytes.
+    pub fn square(name: &str, period: usize) -> Self {
+        let period = period.max(2);
+        FixtureSample {
+            name: name.to_string(),
+            data: (0..period * (1024 / period).max(1))
+                .map(|i| if i % period < period / 2 { 64 } else { -64 })
+                .collect(),
+            volume: 64,
+            looped: true,
+        }
+    }
+
+    /// An unlooped burst of noise `length` bytes long, like a drum hit.
+    pub fn noise(name: &str, length: usize, seed: u32) -> Self {
+        let mut state = seed | 1;
+        FixtureSample {
+            name: name.to_string(),
+            data: (0..length)
+                .map(|i| {
+                    // xorshift32, fading out over the sample
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    let fade = 1.0 - i as f64 / length as f64;
+                    ((state >> 24) as i8 as f64 * fade) as i8
+                })
+                .collect(),
+            volume: 64,
+            looped: false,
+        }
+    }
+}
+
+/// An instrument of an XM fixture module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixtureInstrument {
+    pub name: String,
+    /// The sample every note of the instrument plays, or `None` for an
+    /// instrument without samples, which plays nothing
+    pub sample: Option<FixtureSample>,
+}
+
+impl FixtureInstrument {
+    pub fn new(name: &str, sample: FixtureSample) -> Self {
+        FixtureInstrument {
+            name: name.to_string(),
+            sample: Some(sample),
+        }
+    }
+
+    /// An instrument without samples.
+    pub fn empty(name: &str) -> Self {
+        FixtureInstrument {
+            name: name.to_string(),
+            sample: None,
+        }
+    }
+}
+
+/// A note in a pattern of a fixture module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixtureNote {
+    pub row: usize,
+    pub channel: usize,
+    /// Sample number in MOD files and instrument number in XM files, from 1,
+    /// or 0 to keep the one playing on the channel
+    pub sample: u8,
+    /// Note numbered as in XM files, from 1 for C-0, or 0 for none
+    pub note: u8,
+    pub effect: u8,
+    pub param: u8,
+}
+
+impl FixtureNote {
+    /// Sample (or instrument) `sample` played at C-4.
+    pub fn new(row: usize, channel: usize, sample: u8) -> Self {
+        FixtureNote {
+            row,
+            channel,
+            sample,
+            note: C4,
+            effect: 0,
+            param: 0,
+        }
+    }
+
+    /// An effect command on its own, such as `0xB, 0` to jump back to the start.
+    pub fn command(row: usize, channel: usize, effect: u8, param: u8) -> Self {
+        FixtureNote {
+            row,
+            channel,
+            sample: 0,
+            note: 0,
+            effect,
+            param,
+        }
+    }
+
+    /// The note with an effect command, such as `0xC, 0` to cut it.
+    pub fn effect(self, effect: u8, param: u8) -> Self {
+        FixtureNote {
+            effect,
+            param,
+            ..self
+        }
+    }
+
+    /// The note `semitones` above (or below) the current one.
+    pub fn transpose(self, semitones: i8) -> Self {
+        FixtureNote {
+            note: self.note.saturating_add_signed(semitones),
+            ..self
+        }
+    }
+
+    /// Amiga period of the note for MOD files, 0 for no note or one out of range.
+    fn period(&self) -> u16 {
+        self.note
+            .checked_sub(FIRST_PERIOD_NOTE)
+            .and_then(|i| PERIODS.get(i as usize))
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// A module to synthesize.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixtureModule {
+    pub title: String,
+    /// Number of channels: up to 32, and even in MOD files unless 4
+    pub channels: usize,
+    /// Samples of MOD files, and of XM files without instruments, where each
+    /// becomes an instrument of its own
+    pub samples: Vec<FixtureSample>,
+    /// Instruments of XM files
+    pub instruments: Vec<FixtureInstrument>,
+    /// Notes of each pattern
+    pub patterns: Vec<Vec<FixtureNote>>,
+    /// Patterns in the order they play
+    pub order: Vec<usize>,
+}
+
+impl FixtureModule {
+    /// An empty 4-channel module.
+    pub fn new(title: &str) -> Self {
+        FixtureModule {
+            title: title.to_string(),
+            channels: 4,
+            samples: Vec::new(),
+            instruments: Vec::new(),
+            patterns: Vec::new(),
+            order: Vec::new(),
+        }
+    }
+
+    /// Add a sample, numbered from 1 in the order they are added.
+    pub fn sample(mut self, sample: FixtureSample) -> Self {
+        self.samples.push(sample);
+        self
+    }
+
+    /// Add an instrument, numbered from 1 in the order they are added.
+    pub fn instrument(mut self, instrument: FixtureInstrument) -> Self {
+        self.instruments.push(instrument);
+        self
+    }
+
+    /// Add a pattern and play it after the ones before.
+    pub fn pattern(mut self, notes: Vec<FixtureNote>) -> Self {
+        self.order.push(self.patterns.len());
+        self.patterns.push(notes);
+        self
+    }
+
+    /// A song of `patterns` patterns with `samples` square wave samples, where
+    /// the samples take turns on the channels, a new note every 4 rows.
+    ///
+    /// At the default speed and tempo, a pattern lasts 7.68 seconds.
+    pub fn song(samples: usize, patterns: usize) -> Self {
+        let mut module = FixtureModule::new("fixture");
+        for i in 0..samples {
+            module = module.sample(FixtureSample::square(
+                &format!("square {}", i + 1),
+                16 + 4 * i,
+            ));
+        }
+        for pattern in 0..patterns {
+            let notes = (0..ROWS / 4)
+                .filter(|_| samples > 0)
+                .map(|step| {
+                    let n = pattern * ROWS / 4 + step;
+                    FixtureNote::new(step * 4, n % 4, (n % samples) as u8 + 1)
+                })
+                .collect();
+            module = module.pattern(notes);
+        }
+        module
+    }
+
+    /// The module with its samples turned into instruments of the same name,
+    /// for XM files.
+    pub fn with_instruments(mut self) -> Self {
+        let samples = std::mem::take(&mut self.samples);
+        self.instruments
+            .extend(samples.into_iter().map(|sample| FixtureInstrument {
+                name: sample.name.clone(),
+                sample: Some(sample),
+            }));
+        self
+    }
+
+    /// The module with a jump back to the first order at the end of the last
+    /// pattern, so that the song loops forever.
+    pub fn looped(mut self) -> Self {
+        if let Some(&last) = self.order.last() {
+            self.patterns[last].push(FixtureNote::command(ROWS - 1, 0, 0xB, 0));
+        }
+        self
+    }
+
+    /// The module as a ProTracker MOD file.
+    pub fn to_mod(&self) -> Vec<u8> {
+        assert!(self.samples.len() <= 31, "MOD files hold up to 31 samples");
+        assert!(self.instruments.is_empty(), "MOD files have no instruments");
+        assert!(!self.order.is_empty(), "a module needs a pattern to play");
+
+        let mut bytes = Vec::new();
+        push_name(&mut bytes, &self.title, 20);
+        for i in 0..31 {
+            match self.samples.get(i) {
+                Some(sample) => {
+                    push_name(&mut bytes, &sample.name, 22);
+                    let words = (sample.data.len() / 2) as u16;
+                    bytes.extend_from_slice(&words.to_be_bytes());
+                    bytes.push(0); // finetune
+                    bytes.push(sample.volume.min(64));
+                    // The loop starts at the beginning; a length of 1 word means no loop
+                    let loop_words: u16 = if sample.looped { words } else { 1 };
+                    bytes.extend_from_slice(&[0, 0]);
+                    bytes.extend_from_slice(&loop_words.to_be_bytes());
+                }
+                None => {
+                    push_name(&mut bytes, "", 22);
+                    bytes.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+                }
+            }
+        }
+
+        bytes.push(self.order.len() as u8);
+        bytes.push(127);
+        let mut order = [0u8; 128];
+        for (slot, &pattern) in order.iter_mut().zip(&self.order) {
+            *slot = pattern as u8;
+        }
+        bytes.extend_from_slice(&order);
+        bytes.extend_from_slice(
+            match self.channels {
+                4 => *b"M.K.",
+                channels => {
+                    let tag = format!("{}CH{}", channels, if channels < 10 { "N" } else { "" });
+                    tag.into_bytes().try_into().expect("up to 32 channels")
+                }
+            }
+            .as_slice(),
+        );
+
+        for notes in &self.patterns {
+            let mut cells = vec![[0u8; 4]; ROWS * self.channels];
+            for note in notes {
+                let period = note.period();
+                cells[note.row * self.channels + note.channel] = [
+                    (note.sample & 0xf0) | (period >> 8) as u8,
+                    period as u8,
+                    (note.sample << 4) | (note.effect & 0x0f),
+                    note.param,
+                ];
+            }
+            bytes.extend(cells.into_iter().flatten());
+        }
+
+        for sample in &self.samples {
+            // Odd lengths don't fit in the length in words
+            let length = sample.data.len() / 2 * 2;
+            bytes.extend(sample.data[..length].iter().map(|&s| s as u8));
+        }
+        bytes
+    }
+
+    /// The module as a FastTracker 2 XM file. Samples are turned into
+    /// instruments if the module has none.
+    pub fn to_xm(&self) -> Vec<u8> {
+        if self.instruments.is_empty() && !self.samples.is_empty() {
+            return self.clone().with_instruments().to_xm();
+        }
+        assert!(!self.order.is_empty(), "a module needs a pattern to play");
+
+        let mut bytes = b"Extended Module: ".to_vec();
+        push_name(&mut bytes, &self.title, 20);
+        bytes.push(0x1a);
+        push_name(&mut bytes, "untracker fixture", 20);
+        bytes.extend_from_slice(&0x0104u16.to_le_bytes());
+
+        // The header size counts from its own field to the end of the order table
+        bytes.extend_from_slice(&276u32.to_le_bytes());
+        for value in [
+            self.order.len(),
+            0, // restart position
+            self.channels,
+            self.patterns.len(),
+            self.instruments.len(),
+            1, // linear frequency table
+            6, // speed
+            125,
+        ] {
+            bytes.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+        let mut order = [0u8; 256];
+        for (slot, &pattern) in order.iter_mut().zip(&self.order) {
+            *slot = pattern as u8;
+        }
+        bytes.extend_from_slice(&order);
+
+        for notes in &self.patterns {
+            let mut cells = vec![[0u8; 5]; ROWS * self.channels];
+            for note in notes {
+                cells[note.row * self.channels + note.channel] =
+                    [note.note, note.sample, 0, note.effect, note.param];
+            }
+            // Empty cells are packed into a single byte, others are stored whole
+            let mut data = Vec::new();
+            for cell in cells {
+                if cell == [0; 5] {
+                    data.push(0x80);
+                } else {
+                    data.extend_from_slice(&cell);
+                }
+            }
+            bytes.extend_from_slice(&9u32.to_le_bytes());
+            bytes.push(0); // packing type
+            bytes.extend_from_slice(&(ROWS as u16).to_le_bytes());
+            bytes.extend_from_slice(&(data.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(&data);
+        }
+
+        for instrument in &self.instruments {
+            let Some(sample) = &instrument.sample else {
+                bytes.extend_from_slice(&29u32.to_le_bytes());
+                push_name(&mut bytes, &instrument.name, 22);
+                bytes.push(0); // type
+                bytes.extend_from_slice(&0u16.to_le_bytes());
+                continue;
+            };
+
+            bytes.extend_from_slice(&263u32.to_le_bytes());
+            push_name(&mut bytes, &instrument.name, 22);
+            bytes.push(0); // type
+            bytes.extend_from_slice(&1u16.to_le_bytes());
+            bytes.extend_from_slice(&40u32.to_le_bytes()); // sample header size
+                                                           // Every note plays the first sample; no envelopes, vibrato or fadeout
+            bytes.extend_from_slice(&[0; 96 + 48 + 48 + 2 + 6 + 2 + 4 + 2 + 22]);
+
+            let length = sample.data.len() as u32;
+            bytes.extend_from_slice(&length.to_le_bytes());
+            bytes.extend_from_slice(&0u32.to_le_bytes()); // loop start
+            bytes.extend_from_slice(&(if sample.looped { length } else { 0 }).to_le_bytes());
+            bytes.push(sample.volume.min(64));
+            bytes.push(0); // finetune
+            bytes.push(sample.looped as u8); // forward loop, 8-bit
+            bytes.push(128); // panning
+            bytes.push(0); // relative note
+            bytes.push(0); // reserved
+            push_name(&mut bytes, &sample.name, 22);
+
+            // Sample data is stored as the difference from the previous sample
+            let mut previous = 0i8;
+            for &s in &sample.data {
+                bytes.push(s.wrapping_sub(previous) as u8);
+                previous = s;
+            }
+        }
+        bytes
+    }
+}
+
+/// Append `name` padded with zeros to `length` bytes, cut short if longer.
+fn push_name(bytes: &mut Vec<u8>, name: &str, length: usize) {
+    let mut field = name.as_bytes().to_vec();
+    field.resize(length, 0);
+    bytes.extend_from_slice(&field);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ExportOptions, RenderSession};
+    use openmpt::index::{InstrumentIndex, SampleIndex};
+
+    #[test]
+    fn test_song_loads() {
+        let buffer = FixtureModule::song(6, 2).to_mod();
+        let session = RenderSession::new(&buffer, &ExportOptions::default()).unwrap();
+        let mut module = session.module_ext().get_module();
+
+        assert_eq!(module.get_metadata("type").as_deref(), Some("mod"));
+        assert_eq!(module.get_num_channels(), 4);
+        assert_eq!(module.get_num_samples(), 6);
+        assert_eq!(module.get_sample_name(SampleIndex::new(1)), "square 2");
+        assert!((session.duration_seconds() - 2.0 * 7.68).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_xm_instruments() {
+        let buffer = FixtureModule::new("instruments")
+            .instrument(FixtureInstrument::new(
+                "lead",
+                FixtureSample::square("lead", 32),
+            ))
+            .instrument(FixtureInstrument::empty("nothing"))
+            .instrument(FixtureInstrument::new(
+                "hat",
+                FixtureSample::noise("hat", 2000, 7),
+            ))
+            .pattern(vec![
+                FixtureNote::new(0, 0, 1),
+                FixtureNote::new(0, 1, 2),
+                FixtureNote::new(8, 2, 3).transpose(12),
+            ])
+            .to_xm();
+        let mut session = RenderSession::new(&buffer, &ExportOptions::default()).unwrap();
+        let mut module = session.module_ext().get_module();
+
+        assert_eq!(module.get_metadata("type").as_deref(), Some("xm"));
+        assert_eq!(module.get_num_instruments(), 3);
+        assert_eq!(module.get_num_samples(), 2);
+        assert_eq!(
+            module.get_instrument_name(InstrumentIndex::new(1)),
+            "nothing"
+        );
+        assert!((session.duration_seconds() - 7.68).abs() < 0.01);
+
+        let mut buf = vec![0i16; 4096];
+        session.read(&mut buf);
+        assert!(buf.iter().any(|&s| s != 0));
+    }
+
+    #[test]
+    fn test_looped_song() {
+        let module = FixtureModule::song(2, 1).looped();
+        for buffer in [module.to_mod(), module.to_xm()] {
+            let options = ExportOptions {
+                loop_count: 1,
+                ..ExportOptions::default()
+            };
+            let session = RenderSession::new(&buffer, &options).unwrap();
+            assert!((session.duration_seconds() - 7.68).abs() < 0.01);
+            // Played twice
+            assert!((session.render_length() - 2.0 * 7.68).abs() < 0.05);
+        }
+    }
+}
@@ -0,0 +1,342 @@
+//! Grouping of instruments or samples into combined stems, as given to `--groups`.
+
+use anyhow::{anyhow, Result};
+use openmpt::index::InstrumentIndex;
+use std::fs;
+use std::path::Path;
+
+use crate::StemSelection;
+
+/// Name of the group collecting whatever a preset can't classify.
+pub const OTHER_GROUP: &str = "other";
+
+/// Instruments or samples rendered together into one stem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StemGroup {
+    pub name: String,
+    /// Instruments, or samples of modules without instruments, in ascending order
+    pub members: Vec<InstrumentIndex>,
+}
+
+/// Built-in grouping driven by instrument and sample names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupPreset {
+    /// drums, bass, chords, lead and fx
+    Standard,
+}
+
+impl GroupPreset {
+    /// Keywords of each group, checked in order. A name matches a keyword when
+    /// one of its words starts with it, so "bass drum" lands in drums.
+    fn keywords(self) -> &'static [(&'static str, &'static [&'static str])] {
+        match self {
+            GroupPreset::Standard => &[
+                (
+                    "drums",
+                    &[
+                        "kick", "bd", "bassdrum", "snare", "sd", "hihat", "hat", "hh", "cymbal",
+                        "crash", "ride", "tom", "clap", "drum", "perc", "shaker", "tamb", "conga",
+                        "bongo", "rim", "break", "loop", "808", "909",
+                    ],
+                ),
+                ("bass", &["bass", "sub", "303", "bs"]),
+                (
+                    "chords",
+                    &[
+                        "chord", "pad", "string", "organ", "piano", "rhodes", "choir", "key",
+                        "stab", "brass",
+                    ],
+                ),
+                (
+                    "lead",
+                    &[
+                        "lead", "solo", "melody", "arp", "pluck", "synth", "saw", "square", "sqr",
+                        "flute", "sax", "guitar", "gtr", "bell", "vox", "vocal",
+                    ],
+                ),
+                (
+                    "fx",
+                    &[
+                        "fx", "sfx", "noise", "sweep", "riser", "zap", "laser", "effect", "speech",
+                        "voice",
+                    ],
+                ),
+            ],
+        }
+    }
+
+    /// Group a name belongs to, if any of the preset's keywords match it.
+    pub fn classify(self, name: &str) -> Option<&'static str> {
+        let name = name.to_lowercase();
+        // Split "BassDrum2" style names into words as well as "bass drum 2"
+        let mut words = Vec::new();
+        let mut word = String::new();
+        for c in name.chars() {
+            let boundary = !c.is_alphanumeric()
+                || word
+                    .chars()
+                    .last()
+                    .is_some_and(|last| last.is_ascii_digit() != c.is_ascii_digit());
+            if boundary && !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            if c.is_alphanumeric() {
+                word.push(c);
+            }
+        }
+        words.push(word);
+
+        self.keywords()
+            .iter()
+            .find(|(_, keywords)| {
+                words
+                    .iter()
+                    .any(|word| keywords.iter().any(|keyword| word.starts_with(keyword)))
+            })
+            .map(|(group, _)| *group)
+    }
+}
+
+/// How to group stems: a preset or an explicit list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupSpec {
+    /// `preset:<name>`
+    Preset(GroupPreset),
+    /// `drums=1-4;bass=5,9`, or a `groups.toml` file
+    Custom(Vec<(String, StemSelection)>),
+}
+
+impl GroupSpec {
+    /// Resolve the groups of a module, given the names of its instruments or samples.
+    ///
+    /// Groups without members are left out. Custom groups fail if they name a
+    /// stem the module doesn't have.
+    pub fn resolve(&self, names: &[String]) -> Result<Vec<StemGroup>> {
+        let count = names.len() as i32;
+        let groups = match self {
+            GroupSpec::Preset(preset) => {
+                let mut groups: Vec<StemGroup> = preset
+                    .keywords()
+                    .iter()
+                    .map(|(name, _)| name)
+                    .chain([&OTHER_GROUP])
+                    .map(|name| StemGroup {
+                        name: name.to_string(),
+                        members: Vec::new(),
+                    })
+                    .collect();
+                for (index, name) in names.iter().enumerate() {
+                    let group = preset.classify(name).unwrap_or(OTHER_GROUP);
+                    if let Some(group) = groups.iter_mut().find(|g| g.name == group) {
+                        group.members.push(InstrumentIndex::new(index as i32));
+                    }
+                }
+                groups
+            }
+            GroupSpec::Custom(groups) => groups
+                .iter()
+                .map(|(name, selection)| {
+                    Ok(StemGroup {
+                        name: name.clone(),
+                        members: selection.indices(count)?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?,
+        };
+
+        Ok(groups
+            .into_iter()
+            .filter(|group| !group.members.is_empty())
+            .collect())
+    }
+
+    pub fn is_preset(&self) -> bool {
+        matches!(self, GroupSpec::Preset(_))
+    }
+
+    /// Read the groups of the TOML file at `path`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?;
+        Self::parse_toml(&text)
+            .map_err(|e| anyhow!("Invalid groups file {}: {}", path.display(), e))
+    }
+
+    /// Parse groups written as TOML, each name taking its stems as a list
+    /// like those of `--stems` or as an array of numbers:
+    ///
+    /// ```toml
+    /// drums = "1-4"
+    /// bass = [5, 9]
+    /// lead = "6-8,12"
+    /// ```
+    ///
+    /// Groups come out in order of their names.
+    pub fn parse_toml(text: &str) -> Result<Self> {
+        let table: toml::Table = toml::from_str(text)?;
+        let groups = table
+            .into_iter()
+            .map(|(name, stems)| {
+                check_group_name(&name)?;
+                let selection = match &stems {
+                    toml::Value::String(list) => list.clone(),
+                    toml::Value::Integer(number) => number.to_string(),
+                    toml::Value::Array(numbers) => numbers
+                        .iter()
+                        .map(|number| match number {
+                            toml::Value::Integer(number) => Ok(number.to_string()),
+                            _ => Err(anyhow!("stems of group '{}' must be numbers", name)),
+                        })
+                        .collect::<Result<Vec<_>>>()?
+                        .join(","),
+                    _ => {
+                        return Err(anyhow!(
+                            "expected stems such as \"1-4\" or [5, 9] for group '{}'",
+                            name
+                        ))
+                    }
+                };
+                Ok((name, selection.parse()?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if groups.is_empty() {
+            return Err(anyhow!("no groups"));
+        }
+        Ok(GroupSpec::Custom(groups))
+    }
+}
+
+/// Fail unless `name` can go into a file name.
+fn check_group_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(anyhow!(
+            "Invalid group name '{}': use letters, digits, '-' and '_'",
+            name
+        ));
+    }
+    Ok(())
+}
+
+impl std::str::FromStr for GroupSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(preset) = s.strip_prefix("preset:") {
+            return match preset.trim().to_lowercase().as_str() {
+                "standard" => Ok(GroupSpec::Preset(GroupPreset::Standard)),
+                _ => Err(anyhow!(
+                    "Unknown group preset '{}', available presets: standard",
+                    preset.trim()
+                )),
+            };
+        }
+        if !s.contains('=') && s.trim_end().ends_with(".toml") {
+            return Self::load(Path::new(s.trim()));
+        }
+
+        let groups = s
+            .split(';')
+            .map(|group| {
+                let (name, selection) = group.split_once('=').ok_or_else(|| {
+                    anyhow!(
+                        "Invalid group '{}': expected <name>=<stems> (e.g. drums=1-4) or preset:standard",
+                        group.trim()
+                    )
+                })?;
+                let name = name.trim();
+                check_group_name(name)?;
+                Ok((name.to_string(), selection.parse()?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(GroupSpec::Custom(groups))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members(indices: &[i32]) -> Vec<InstrumentIndex> {
+        indices.iter().map(|&i| InstrumentIndex::new(i)).collect()
+    }
+
+    #[test]
+    fn test_standard_preset() {
+        let preset = GroupPreset::Standard;
+        assert_eq!(preset.classify("Bass Drum"), Some("drums"));
+        assert_eq!(preset.classify("BD1"), Some("drums"));
+        assert_eq!(preset.classify("hihat.wav"), Some("drums"));
+        assert_eq!(preset.classify("303 acid"), Some("bass"));
+        assert_eq!(preset.classify("Strings"), Some("chords"));
+        assert_eq!(preset.classify("lead2"), Some("lead"));
+        assert_eq!(preset.classify("noise sweep"), Some("fx"));
+        assert_eq!(preset.classify("ripped by someone"), None);
+
+        let names: Vec<String> = ["kick", "", "bass", "snare"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let groups = GroupSpec::Preset(preset).resolve(&names).unwrap();
+        assert_eq!(
+            groups,
+            vec![
+                StemGroup {
+                    name: "drums".to_string(),
+                    members: members(&[0, 3])
+                },
+                StemGroup {
+                    name: "bass".to_string(),
+                    members: members(&[2])
+                },
+                StemGroup {
+                    name: OTHER_GROUP.to_string(),
+                    members: members(&[1])
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_custom_groups() {
+        let spec: GroupSpec = "drums=1-3; keys=5".parse().unwrap();
+        let names = vec![String::new(); 6];
+        let groups = spec.resolve(&names).unwrap();
+        assert_eq!(groups[0].members, members(&[0, 1, 2]));
+        assert_eq!(groups[1].name, "keys");
+        assert!(spec.resolve(&names[..4]).is_err());
+
+        assert!("preset:standard".parse::<GroupSpec>().unwrap().is_preset());
+        assert!("preset:jazz".parse::<GroupSpec>().is_err());
+        assert!("drums".parse::<GroupSpec>().is_err());
+        assert!("../x=1".parse::<GroupSpec>().is_err());
+    }
+
+    #[test]
+    fn test_groups_file() {
+        let spec =
+            GroupSpec::parse_toml("lead = \"6-8\"\ndrums = \"1-3\"\nbass = [4, 6]\n").unwrap();
+        let groups = spec.resolve(&vec![String::new(); 8]).unwrap();
+        let names: Vec<&str> = groups.iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(names, ["bass", "drums", "lead"]);
+        assert_eq!(groups[0].members, members(&[3, 5]));
+
+        assert!(GroupSpec::parse_toml("").is_err());
+        assert!(GroupSpec::parse_toml("drums = [\"kick\"]").is_err());
+        assert!(GroupSpec::parse_toml("\"../drums\" = \"1\"").is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("groups.toml");
+        fs::write(&path, "drums = \"1-2\"\n").unwrap();
+        let spec: GroupSpec = path.to_str().unwrap().parse().unwrap();
+        assert_eq!(
+            spec,
+            GroupSpec::Custom(vec![("drums".to_string(), "1-2".parse().unwrap())])
+        );
+        assert!("missing.toml".parse::<GroupSpec>().is_err());
+    }
+}
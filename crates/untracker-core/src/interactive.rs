@@ -0,0 +1,122 @@
+//! Review of the planned outputs before rendering, for `--interactive`.
+
+use anyhow::Result;
+use std::io::{BufRead, Write};
+
+use crate::StemSelection;
+
+/// One output file that is about to be rendered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanEntry {
+    pub file: String,
+    /// What goes into the file, e.g. "instrument 3 (Kick)"
+    pub contents: String,
+    pub enabled: bool,
+}
+
+impl PlanEntry {
+    pub fn new(file: String, contents: String) -> Self {
+        PlanEntry {
+            file,
+            contents,
+            enabled: true,
+        }
+    }
+}
+
+/// Show the plan and let the user toggle entries until they confirm it.
+///
+/// Each line read from `input` is a command: entry numbers or ranges to
+/// toggle (e.g. `2,5-7`), `a` to enable all, `n` to disable all, `q` to quit,
+/// or an empty line to accept the plan. Returns whether the plan was accepted;
+/// the end of `input` counts as quitting.
+pub fn edit_plan(
+    entries: &mut [PlanEntry],
+    header: &str,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> Result<bool> {
+    let file_width = entries.iter().map(|e| e.file.len()).max().unwrap_or(0);
+
+    loop {
+        writeln!(output, "{}", header)?;
+        for (i, entry) in entries.iter().enumerate() {
+            writeln!(
+                output,
+                "  {:>3}  [{}]  {:<width$}  {}",
+                i + 1,
+                if entry.enabled { 'x' } else { ' ' },
+                entry.file,
+                entry.contents,
+                width = file_width
+            )?;
+        }
+        write!(
+            output,
+            "Toggle entries (e.g. 2,5-7), a = all, n = none, q = quit, Enter = render: "
+        )?;
+        output.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(false);
+        }
+
+        match line.trim().to_lowercase().as_str() {
+            "" if entries.iter().any(|e| e.enabled) => return Ok(true),
+            "" => writeln!(output, "Nothing is selected")?,
+            "q" => return Ok(false),
+            "a" => entries.iter_mut().for_each(|e| e.enabled = true),
+            "n" => entries.iter_mut().for_each(|e| e.enabled = false),
+            command => match command.parse::<StemSelection>() {
+                Ok(selection) => {
+                    let count = entries.len() as i32;
+                    if let Err(e) = selection.indices(count) {
+                        writeln!(output, "{}", e)?;
+                        continue;
+                    }
+                    for (i, entry) in entries.iter_mut().enumerate() {
+                        if selection.contains(i as i32 + 1) {
+                            entry.enabled = !entry.enabled;
+                        }
+                    }
+                }
+                Err(e) => writeln!(output, "{}", e)?,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<PlanEntry> {
+        (1..=5)
+            .map(|i| PlanEntry::new(format!("song_{}.wav", i), format!("instrument {}", i)))
+            .collect()
+    }
+
+    #[test]
+    fn test_toggle_and_accept() {
+        let mut plan = entries();
+        let mut output = Vec::new();
+        let accepted =
+            edit_plan(&mut plan, "Plan:", &b"2,4-5\nx\n9\n4\n\n"[..], &mut output).unwrap();
+
+        assert!(accepted);
+        let enabled: Vec<bool> = plan.iter().map(|e| e.enabled).collect();
+        assert_eq!(enabled, vec![true, false, true, true, false]);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("  2  [ ]  song_2.wav  instrument 2"));
+        assert!(output.contains("only has 5 stems"));
+    }
+
+    #[test]
+    fn test_nothing_selected_and_quit() {
+        let mut plan = entries();
+        assert!(!edit_plan(&mut plan, "", &b"n\n\nq\n"[..], Vec::new()).unwrap());
+        assert!(!edit_plan(&mut plan, "", &b"a\n"[..], Vec::new()).unwrap());
+        assert!(plan.iter().all(|e| e.enabled));
+    }
+}
@@ -0,0 +1,236 @@
+//! Job files of `untracker batch --job-file`, for conversions driven by
+//! another program: a JSON array of jobs, each naming a module, where its
+//! stems go, the options that differ for it and the stems to render.
+//!
+//! ```json
+//! [
+//!   {"input": "mods/song.xm", "output": "song", "options": {"format": "flac"}, "stems": "1-4"},
+//!   {"input": "mods/other.it"}
+//! ]
+//! ```
+//!
+//! Options take the keys of the override file next to a module. How every
+//! job went is written to a results file once the queue is done.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::ModuleOverrides;
+use crate::selection::StemSelection;
+
+/// Name of the file the results of a job file are written to, in the
+/// output directory.
+pub const JOB_RESULTS_FILE: &str = "job-results.json";
+
+/// A module to extract, as described in a job file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    /// Module file, relative to the directory of the job file
+    pub input: PathBuf,
+    /// Directory of the stems, relative to the output directory [default:
+    /// the subdirectory `untracker batch` gives the module]
+    #[serde(default)]
+    pub output: Option<PathBuf>,
+    /// Options replacing those of the command line for this module
+    #[serde(default)]
+    pub options: ModuleOverrides,
+    /// Stems to render, as given to `--stems` [default: those of the command line]
+    #[serde(default, deserialize_with = "stem_selection")]
+    pub stems: Option<StemSelection>,
+}
+
+fn stem_selection<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<StemSelection>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|stems| stems.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Parse the jobs of a job file, with inputs relative to `dir`.
+pub fn parse(text: &str, dir: &Path) -> Result<Vec<Job>> {
+    let mut jobs: Vec<Job> = serde_json::from_str(text)?;
+    for job in &mut jobs {
+        job.input = dir.join(&job.input);
+        // Options are checked now rather than once the queue reaches the job
+        job.options.apply(&Default::default())?;
+    }
+    Ok(jobs)
+}
+
+/// Load the job file at `path`.
+pub fn load(path: &Path) -> Result<Vec<Job>> {
+    let text =
+        fs::read_to_string(path).map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let jobs =
+        parse(&text, dir).map_err(|e| anyhow!("Invalid job file {}: {:#}", path.display(), e))?;
+    if jobs.is_empty() {
+        return Err(anyhow!("No jobs in {}", path.display()));
+    }
+    Ok(jobs)
+}
+
+/// How one job went.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobResult {
+    pub input: PathBuf,
+    pub output_dir: PathBuf,
+    /// "done", "failed", or "skipped" for modules `--resume` left alone
+    pub status: String,
+    /// Number of stems written
+    pub stems: usize,
+    /// Messages libopenmpt logged while loading the module
+    pub warnings: usize,
+    pub seconds: f64,
+    pub error: Option<String>,
+}
+
+impl JobResult {
+    pub fn new(
+        job: &Job,
+        output_dir: &Path,
+        result: &Result<usize>,
+        warnings: usize,
+        elapsed: Duration,
+    ) -> Self {
+        JobResult {
+            input: job.input.clone(),
+            output_dir: output_dir.to_path_buf(),
+            status: if result.is_ok() { "done" } else { "failed" }.to_string(),
+            stems: *result.as_ref().unwrap_or(&0),
+            warnings,
+            seconds: elapsed.as_secs_f64(),
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+        }
+    }
+
+    pub fn skipped(job: &Job, output_dir: &Path) -> Self {
+        JobResult {
+            input: job.input.clone(),
+            output_dir: output_dir.to_path_buf(),
+            status: "skipped".to_string(),
+            stems: 0,
+            warnings: 0,
+            seconds: 0.0,
+            error: None,
+        }
+    }
+
+    /// The line printed once the job is over.
+    pub fn line(&self) -> String {
+        let mut line = format!("{:<7} {}", self.status, self.input.display());
+        match &self.error {
+            Some(error) => write!(line, ": {}", error.replace('\n', " ")).unwrap(),
+            None if self.status == "skipped" => line.push_str(": already extracted"),
+            None => write!(line, ": {} stems in {:.1}s", self.stems, self.seconds).unwrap(),
+        }
+        line
+    }
+}
+
+/// Every job of a job file that was run, in the order of the file.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct JobResults {
+    pub done: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub jobs: Vec<JobResult>,
+}
+
+impl JobResults {
+    pub fn push(&mut self, result: JobResult) {
+        match result.status.as_str() {
+            "done" => self.done += 1,
+            "failed" => self.failed += 1,
+            _ => self.skipped += 1,
+        }
+        self.jobs.push(result);
+    }
+
+    /// Write the results to `path` as JSON.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .map_err(|e| anyhow!("Cannot write {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_jobs() {
+        let text = r#"[
+            {"input": "song.xm", "output": "/stems/song", "options": {"format": "flac", "sample-rate": 48000}, "stems": "1,3-4"},
+            {"input": "/mods/other.it"}
+        ]"#;
+        let jobs = parse(text, Path::new("queue")).unwrap();
+        assert_eq!(jobs[0].input, Path::new("queue/song.xm"));
+        assert_eq!(jobs[0].output.as_deref(), Some(Path::new("/stems/song")));
+        assert_eq!(jobs[0].options.sample_rate, Some(48000));
+        assert!(jobs[0].stems.as_ref().unwrap().contains(3));
+        assert!(!jobs[0].stems.as_ref().unwrap().contains(2));
+        assert_eq!(jobs[1].input, Path::new("/mods/other.it"));
+        assert!(jobs[1].output.is_none() && jobs[1].stems.is_none());
+
+        assert!(parse(r#"[{"input": "a.mod", "stems": "x"}]"#, Path::new("")).is_err());
+        assert!(parse(r#"[{"input": "a.mod", "gain": 2}]"#, Path::new("")).is_err());
+        assert!(parse(
+            r#"[{"input": "a.mod", "options": {"format": "aiff"}}]"#,
+            Path::new("")
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_job_results() {
+        let jobs = parse(
+            r#"[{"input": "a.mod"}, {"input": "b.xm"}, {"input": "c.it"}]"#,
+            Path::new(""),
+        )
+        .unwrap();
+        let second = Duration::from_secs(1);
+        let mut results = JobResults::default();
+        results.push(JobResult::new(
+            &jobs[0],
+            Path::new("out/a"),
+            &Ok(4),
+            0,
+            second,
+        ));
+        results.push(JobResult::new(
+            &jobs[1],
+            Path::new("out/b"),
+            &Err(anyhow!("Failed to load module")),
+            1,
+            second,
+        ));
+        results.push(JobResult::skipped(&jobs[2], Path::new("out/c")));
+        assert_eq!((results.done, results.failed, results.skipped), (1, 1, 1));
+
+        let lines: Vec<String> = results.jobs.iter().map(JobResult::line).collect();
+        assert_eq!(
+            lines,
+            [
+                "done    a.mod: 4 stems in 1.0s",
+                "failed  b.xm: Failed to load module",
+                "skipped c.it: already extracted",
+            ]
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(JOB_RESULTS_FILE);
+        results.write(&path).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["failed"], 1);
+        assert_eq!(json["jobs"][1]["error"], "Failed to load module");
+        assert_eq!(json["jobs"][0]["output_dir"], "out/a");
+    }
+}
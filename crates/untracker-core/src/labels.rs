@@ -0,0 +1,100 @@
+//! Words naming the kind of a stem in its file name, as given to `--labels`.
+
+use anyhow::{anyhow, Result};
+
+/// Labels of instrument and sample stems, which are `instrument` and `sample`
+/// unless set otherwise. An empty label leaves it out of file names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StemLabels {
+    pub instrument: String,
+    pub sample: String,
+}
+
+impl Default for StemLabels {
+    fn default() -> Self {
+        StemLabels {
+            instrument: "instrument".to_string(),
+            sample: "sample".to_string(),
+        }
+    }
+}
+
+impl StemLabels {
+    /// Labels that leave the kind of stem out of file names: `song_003.wav`.
+    pub fn none() -> Self {
+        StemLabels {
+            instrument: String::new(),
+            sample: String::new(),
+        }
+    }
+
+    /// Label of instrument stems, or of sample stems.
+    pub fn label(&self, is_instrument: bool) -> &str {
+        if is_instrument {
+            &self.instrument
+        } else {
+            &self.sample
+        }
+    }
+}
+
+impl std::str::FromStr for StemLabels {
+    type Err = anyhow::Error;
+
+    /// Parse `none`, or a list such as `instr=ins,sample=smp`. Labels not in
+    /// the list keep their default.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim() == "none" {
+            return Ok(StemLabels::none());
+        }
+
+        let mut labels = StemLabels::default();
+        for part in s.split(',') {
+            let (kind, label) = part
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Expected KIND=label, got '{}'", part.trim()))?;
+            let label = label.trim();
+            if !label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(anyhow!(
+                    "Invalid stem label '{}': use letters, digits, '-' and '_'",
+                    label
+                ));
+            }
+            match kind.trim() {
+                "instr" | "instrument" => labels.instrument = label.to_string(),
+                "sample" => labels.sample = label.to_string(),
+                kind => {
+                    return Err(anyhow!(
+                        "Unknown kind of stem '{}': expected instr or sample",
+                        kind
+                    ))
+                }
+            }
+        }
+        Ok(labels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_labels() {
+        let labels: StemLabels = "instr=ins, sample=smp".parse().unwrap();
+        assert_eq!(labels.label(true), "ins");
+        assert_eq!(labels.label(false), "smp");
+
+        let labels: StemLabels = "sample=".parse().unwrap();
+        assert_eq!(labels.instrument, "instrument");
+        assert_eq!(labels.sample, "");
+        assert_eq!("none".parse::<StemLabels>().unwrap(), StemLabels::none());
+
+        assert!("channel=ch".parse::<StemLabels>().is_err());
+        assert!("instr".parse::<StemLabels>().is_err());
+        assert!("instr=../x".parse::<StemLabels>().is_err());
+    }
+}
@@ -0,0 +1,1553 @@
+pub mod analysis;
+pub mod archive;
+pub mod audio;
+pub mod automation;
+pub mod browser;
+pub mod cache;
+pub mod ci;
+pub mod colors;
+pub mod concat;
+pub mod config;
+pub mod container;
+pub mod daw_session;
+#[cfg(feature = "decode")]
+pub mod decode;
+pub mod estimate;
+pub mod fixture;
+pub mod groups;
+pub mod interactive;
+pub mod jobs;
+pub mod labels;
+pub mod listing;
+pub mod loudness;
+pub mod manifest;
+pub mod markers;
+pub mod mask;
+pub mod mix_automation;
+pub mod naming;
+pub mod null_test;
+pub mod parallel;
+pub mod playback;
+pub mod probe;
+pub mod progress;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod rank;
+pub mod raw_samples;
+pub mod remix_kit;
+pub mod render_info;
+pub mod selection;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod service;
+pub mod session;
+pub mod single_pass;
+pub mod staging;
+pub mod tags;
+pub mod time;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod unpack;
+pub mod watch;
+
+pub use analysis::{AnalysisReport, StemAnalysis, StemAnalysisEntry, StemAnalyzer};
+use anyhow::{anyhow, Result};
+pub use audio::{
+    write_audio, write_audio_file, AudioFormat, AudioWriter, EncoderOptions, ExportOptions,
+    NormalizeMode, OpusApplication, OpusVbr, ResampleMethod, Resampler, SampleFormat,
+    SeekableOutput, SongRange, Tag, TrimSilence, STDOUT_PATH,
+};
+pub use config::{ModuleDefaults, ModuleOverrides};
+pub use groups::{GroupSpec, StemGroup};
+pub use labels::StemLabels;
+pub use loudness::{Loudness, LoudnessMeter};
+pub use mask::MuteMask;
+pub use naming::{NameTemplate, StemNameFields};
+pub use selection::StemSelection;
+pub use session::{Position, RenderSample, RenderSession, Seek};
+#[cfg(feature = "memfs")]
+pub use untracker_encoders::memfs;
+
+use openmpt::ext::ModuleExt;
+use openmpt::index::{InstrumentIndex, SampleIndex, SubsongIndex};
+use openmpt::module::Module;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use audio::dsp::{self, Sample, SilenceTrimmer};
+use progress::{ProgressSink, RenderProgress};
+
+/// Length of the fade applied at the end of `--preview` renders
+const PREVIEW_FADE_SECONDS: f64 = 1.0;
+
+/// Everything known about a stem once it has been rendered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StemRenderResult {
+    /// File the stem was written to
+    pub path: PathBuf,
+    /// Number of frames (samples per channel) in the stem
+    pub frames: u64,
+    /// Length of the stem in seconds
+    pub duration: f64,
+    /// Highest absolute sample value, as a fraction of full scale (0.0-1.0)
+    pub peak: f64,
+    /// Where the stem starts in the song, in seconds, when leading silence was trimmed
+    #[serde(default)]
+    pub start_offset: f64,
+    /// Gain applied to the rendered audio in dB, fixed gain and normalization
+    /// together
+    #[serde(default)]
+    pub gain_db: f64,
+    /// Loudness of the written audio, if `replaygain` had it measured
+    #[serde(default)]
+    pub loudness: Option<Loudness>,
+    /// First frame of the loop of a `loop_crossfade` render, which lasts
+    /// until the end of the stem
+    #[serde(default)]
+    pub loop_start: Option<u64>,
+    /// Why the stem was not written, if it was skipped
+    pub skipped_reason: Option<String>,
+    /// Silence and storage figures for the stem
+    pub analysis: StemAnalysis,
+}
+
+impl StemRenderResult {
+    /// Peak level in dBFS, or negative infinity for a silent stem.
+    pub fn peak_dbfs(&self) -> f64 {
+        20.0 * self.peak.log10()
+    }
+
+    pub fn is_skipped(&self) -> bool {
+        self.skipped_reason.is_some()
+    }
+}
+
+/// Select `subsong` for playback, failing if the module doesn't have it.
+pub fn select_subsong(module: &mut Module, subsong: SubsongIndex) -> Result<()> {
+    let count = module.get_num_subsongs();
+    if subsong.get() >= count || !module.select_subsong(subsong.get()) {
+        return Err(anyhow!(
+            "Subsong {} selected, but the module only has {}",
+            subsong,
+            count
+        ));
+    }
+    Ok(())
+}
+
+/// Buffers audio is rendered into by [`render_streaming`], which renders that
+/// follow each other can share rather than allocate again.
+#[derive(Default)]
+struct StreamBuffers<S> {
+    samples: Vec<S>,
+    /// The tail of a preview, held back until it is faded out
+    held: Vec<S>,
+}
+
+/// Render the song from the current position with the current mute state,
+/// handing the audio to `sink` a chunk at a time.
+///
+/// Returns the number of frames rendered.
+fn render_streaming<S: RenderSample>(
+    session: &mut RenderSession,
+    label: &str,
+    progress: Option<&dyn ProgressSink>,
+    sink: impl FnMut(&[S]) -> Result<()>,
+) -> Result<u64> {
+    render_streaming_with(
+        session,
+        label,
+        progress,
+        &mut StreamBuffers::default(),
+        sink,
+    )
+}
+
+/// [`render_streaming`] into `buffers`.
+fn render_streaming_with<S: RenderSample>(
+    session: &mut RenderSession,
+    label: &str,
+    progress: Option<&dyn ProgressSink>,
+    buffers: &mut StreamBuffers<S>,
+    mut sink: impl FnMut(&[S]) -> Result<()>,
+) -> Result<u64> {
+    let pad_to_frames = session.options().pad_to_frames;
+    let mut render = StreamRender::new(session, label, progress, buffers);
+    while render.step(&mut sink)? {}
+    render.finish(pad_to_frames, &mut sink)
+}
+
+/// A render of a session from its current position, advanced a chunk at a
+/// time so that several sessions can be rendered side by side.
+struct StreamRender<'a, S> {
+    session: &'a mut RenderSession,
+    label: &'a str,
+    progress: Option<&'a dyn ProgressSink>,
+    buffers: &'a mut StreamBuffers<S>,
+    options: ExportOptions,
+    channels: usize,
+    total_duration: f64,
+    time_limit: Option<f64>,
+    sample_limit: Option<usize>,
+    /// Frames at the end of a preview that fade out
+    fade_frames: usize,
+    total_samples: usize,
+    last_percentage: u64,
+}
+
+impl<'a, S: RenderSample> StreamRender<'a, S> {
+    fn new(
+        session: &'a mut RenderSession,
+        label: &'a str,
+        progress: Option<&'a dyn ProgressSink>,
+        buffers: &'a mut StreamBuffers<S>,
+    ) -> Self {
+        let options = session.options().clone();
+        let channels = options.channels as usize;
+
+        // Use a larger buffer to reduce FFI overhead and improve throughput
+        buffers.samples.clear();
+        buffers.samples.resize(16384 * channels, S::default());
+
+        let total_duration = session.render_length();
+        let time_limit = session.time_limit();
+        let sample_limit = time_limit
+            .map(|limit| (limit * options.sample_rate as f64).round() as usize * channels);
+
+        // The end of a preview fades out, so its tail is held back until the end is known
+        let fade_frames = options
+            .preview
+            .map(|preview| {
+                (PREVIEW_FADE_SECONDS.min(preview / 2.0) * options.sample_rate as f64) as usize
+            })
+            .unwrap_or(0);
+        buffers.held.clear();
+        buffers
+            .held
+            .reserve((fade_frames * channels).max(buffers.samples.len()));
+
+        StreamRender {
+            session,
+            label,
+            progress,
+            buffers,
+            options,
+            channels,
+            total_duration,
+            time_limit,
+            sample_limit,
+            fade_frames,
+            total_samples: 0,
+            last_percentage: 0,
+        }
+    }
+
+    /// Frames rendered so far.
+    fn frames(&self) -> u64 {
+        (self.total_samples / self.channels) as u64
+    }
+
+    /// Render the next chunk and hand what is ready of it to `sink`.
+    /// Returns false once the render has ended.
+    fn step(&mut self, sink: &mut impl FnMut(&[S]) -> Result<()>) -> Result<bool> {
+        let (options, channels) = (&self.options, self.channels);
+        let StreamBuffers { samples, held } = &mut *self.buffers;
+        let rendered = S::read(self.session, samples);
+
+        if rendered == 0 {
+            return Ok(false);
+        }
+
+        let mut chunk = &samples[..rendered * channels];
+        let mut reached_limit = false;
+        if let Some(limit) = self.sample_limit {
+            if self.total_samples + chunk.len() >= limit {
+                chunk = &chunk[..limit - self.total_samples];
+                reached_limit = true;
+            }
+        }
+        self.total_samples += chunk.len();
+
+        held.extend_from_slice(chunk);
+        if held.len() > self.fade_frames * channels {
+            let ready = held.len() - self.fade_frames * channels;
+            sink(&held[..ready])?;
+            held.drain(..ready);
+        }
+        if reached_limit {
+            if let Some(max) = options
+                .max_duration
+                .filter(|&max| self.time_limit == Some(max) && options.preview != Some(max))
+            {
+                log::warn!(
+                    "{}: stopped at the maximum duration of {:.1} seconds",
+                    self.label,
+                    max
+                );
+            }
+            return Ok(false);
+        }
+
+        // The end of the song is where reads return no frames: libopenmpt
+        // detects loops back into the song, so this also ends infinite loops.
+        // The position jumps back when the song repeats, so progress is
+        // measured from the audio rendered so far
+        if let Some(progress) = self.progress {
+            let rendered_seconds =
+                self.total_samples as f64 / channels as f64 / options.sample_rate as f64;
+            let update = RenderProgress::Rendering {
+                label: self.label,
+                seconds: rendered_seconds,
+                total_seconds: self.total_duration,
+            };
+
+            // Only report progress when the rounded percentage changes
+            let rounded_percentage = (update.fraction() * 100.0) as u64;
+            if rounded_percentage > self.last_percentage {
+                self.last_percentage = rounded_percentage;
+                progress.report(update);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Hand the rest of the render to `sink` once it has ended: the tail
+    /// held back, faded out for previews, then silence up to `pad_to_frames`.
+    /// Returns the number of frames rendered.
+    fn finish(
+        self,
+        pad_to_frames: Option<u64>,
+        sink: &mut impl FnMut(&[S]) -> Result<()>,
+    ) -> Result<u64> {
+        let channels = self.channels;
+        let StreamBuffers { samples, held } = self.buffers;
+        let mut total_samples = self.total_samples;
+
+        if self.options.preview.is_some() {
+            dsp::fade_out(held, channels, self.fade_frames);
+        }
+        if !held.is_empty() {
+            sink(held)?;
+        }
+
+        if let Some(pad_to_frames) = pad_to_frames {
+            let mut missing = (pad_to_frames as usize * channels).saturating_sub(total_samples);
+            samples.fill(S::default());
+            while missing > 0 {
+                let chunk = &samples[..missing.min(samples.len())];
+                sink(chunk)?;
+                missing -= chunk.len();
+                total_samples += chunk.len();
+            }
+        }
+
+        Ok((total_samples / channels) as u64)
+    }
+}
+
+/// Render the song from the current position with the current mute state into memory.
+fn render_to_memory<S: RenderSample>(
+    session: &mut RenderSession,
+    label: &str,
+    progress: Option<&dyn ProgressSink>,
+) -> Result<Vec<S>> {
+    let options = session.options().clone();
+
+    // Pre-allocate the audio vector based on the estimated duration to avoid multiple reallocations
+    let total_duration = session.render_length();
+    let estimated_samples = if total_duration > 0.0 {
+        (total_duration * options.sample_rate as f64 * options.channels as f64).ceil() as usize
+    } else {
+        0
+    };
+    let mut all_audio = Vec::with_capacity(estimated_samples);
+
+    render_streaming(session, label, progress, |chunk| {
+        all_audio.extend_from_slice(chunk);
+        Ok(())
+    })?;
+    Ok(all_audio)
+}
+
+/// Encodes rendered audio to a file as it arrives, collecting the facts about it.
+struct StemWriter<S> {
+    writer: AudioWriter<'static>,
+    analyzer: StemAnalyzer,
+    peak: f64,
+    /// Copy of the current chunk with `gain_db` applied
+    scratch: Vec<S>,
+    trimmer: Option<SilenceTrimmer<S>>,
+    output_path: PathBuf,
+    options: ExportOptions,
+}
+
+impl<S: Sample> StemWriter<S> {
+    fn create(output_path: PathBuf, options: &ExportOptions, tags: &[Tag]) -> Result<Self> {
+        Ok(StemWriter {
+            writer: AudioWriter::create_tagged(&output_path, &options.encoder(), tags)?,
+            analyzer: StemAnalyzer::new(options.channels as usize, options.bit_depth),
+            peak: 0.0,
+            scratch: Vec::new(),
+            trimmer: match options.trim_silence {
+                TrimSilence::Off => None,
+                TrimSilence::End => Some(SilenceTrimmer::new(options.channels as usize, false)),
+                TrimSilence::Both => Some(SilenceTrimmer::new(options.channels as usize, true)),
+            },
+            output_path,
+            options: options.clone(),
+        })
+    }
+
+    fn write(&mut self, samples: &[S]) -> Result<()> {
+        let samples = if self.options.gain_db != 0.0 {
+            self.scratch.clear();
+            self.scratch.extend_from_slice(samples);
+            dsp::apply_gain(&mut self.scratch, self.options.gain_db);
+            &self.scratch
+        } else {
+            samples
+        };
+
+        let (analyzer, peak, writer) = (&mut self.analyzer, &mut self.peak, &mut self.writer);
+        let mut write = |samples: &[S]| {
+            analyzer.add(&S::quantize(samples));
+            *peak = peak.max(dsp::peak(samples));
+            writer.write_samples(samples)
+        };
+        match &mut self.trimmer {
+            Some(trimmer) => trimmer.push(samples, write),
+            None => write(samples),
+        }
+    }
+
+    fn finish(self) -> Result<StemRenderResult> {
+        self.writer.finish()?;
+        let analysis = self.analyzer.finish();
+        let sample_rate = self.options.sample_rate as f64;
+        Ok(StemRenderResult {
+            path: self.output_path,
+            frames: analysis.frames,
+            duration: analysis.frames as f64 / sample_rate,
+            peak: self.peak,
+            start_offset: self.trimmer.map_or(0, |trimmer| trimmer.leading_frames()) as f64
+                / sample_rate,
+            gain_db: self.options.gain_db,
+            loudness: None,
+            loop_start: None,
+            skipped_reason: None,
+            analysis,
+        })
+    }
+}
+
+/// Render the session to `output_path` tagged with `tags`, streaming it to
+/// the encoder unless the whole render is needed first.
+fn render_to_file(
+    session: &mut RenderSession,
+    label: &str,
+    progress: Option<&dyn ProgressSink>,
+    output_path: PathBuf,
+    options: &ExportOptions,
+    tags: &[Tag],
+) -> Result<StemRenderResult> {
+    if options.render_float() {
+        render_samples_to_file::<f32>(session, label, progress, output_path, options, tags)
+    } else {
+        render_samples_to_file::<i16>(session, label, progress, output_path, options, tags)
+    }
+}
+
+fn render_samples_to_file<S: RenderSample>(
+    session: &mut RenderSession,
+    label: &str,
+    progress: Option<&dyn ProgressSink>,
+    output_path: PathBuf,
+    options: &ExportOptions,
+    tags: &[Tag],
+) -> Result<StemRenderResult> {
+    if options.loop_crossfade.is_some() {
+        return render_loop::<S>(session, label, progress, output_path, options, tags);
+    }
+    if options.normalize == NormalizeMode::Off && !options.replaygain {
+        let mut writer = StemWriter::<S>::create(output_path, options, tags)?;
+        render_streaming(session, label, progress, |chunk| writer.write(chunk))?;
+        return writer.finish();
+    }
+
+    // The normalization gain and the loudness depend on the whole render
+    let all_audio = render_to_memory::<S>(session, label, progress)?;
+    write_from_memory(all_audio, output_path, options, tags)
+}
+
+/// Render the song once into memory, then the start of its next repetition,
+/// and crossfade that into the start of the loop so the audio repeats
+/// seamlessly from there to its end. WAV files get the loop in a `smpl`
+/// chunk, encoded ones `LOOPSTART` and `LOOPLENGTH` tags in frames.
+fn render_loop<S: RenderSample>(
+    session: &mut RenderSession,
+    label: &str,
+    progress: Option<&dyn ProgressSink>,
+    output_path: PathBuf,
+    options: &ExportOptions,
+    tags: &[Tag],
+) -> Result<StemRenderResult> {
+    let channels = options.channels as usize;
+    let sample_rate = options.sample_rate as f64;
+    let mut all_audio = render_to_memory::<S>(session, label, progress)?;
+    let frames = (all_audio.len() / channels) as u64;
+
+    // Past the end, libopenmpt plays on from where the song loops back to,
+    // which the position tells once the first frame of it is read
+    let crossfade = options.loop_crossfade.unwrap_or_default();
+    let mut tail = vec![S::default(); (crossfade * sample_rate).round() as usize * channels];
+    let mut tail_frames = S::read(session, &mut tail[..channels]);
+    let restart = session.position();
+    while tail_frames > 0 && tail_frames * channels < tail.len() {
+        match S::read(session, &mut tail[tail_frames * channels..]) {
+            0 => break,
+            rendered => tail_frames += rendered,
+        }
+    }
+    tail.truncate(tail_frames * channels);
+
+    // Seeking to the row gives the exact time it starts at
+    let loop_seconds = session.seek(Seek::OrderRow {
+        order: restart.order,
+        row: restart.row,
+    });
+    let loop_start = match loop_seconds {
+        Ok(seconds) if tail_frames > 0 => (seconds * sample_rate).round() as u64,
+        _ => return Err(anyhow!("{}: the song does not loop back", label)),
+    };
+    if loop_start >= frames {
+        return Err(anyhow!(
+            "{}: the song loops back to its end, {:.1} seconds in",
+            label,
+            loop_start as f64 / sample_rate
+        ));
+    }
+    dsp::crossfade(
+        &mut all_audio[loop_start as usize * channels..],
+        &tail,
+        channels,
+    );
+
+    let mut tags = tags.to_vec();
+    tags.push(("LOOPSTART".to_string(), loop_start.to_string()));
+    tags.push(("LOOPLENGTH".to_string(), (frames - loop_start).to_string()));
+    let result = write_from_memory(all_audio, output_path, options, &tags)?;
+    if options.format == AudioFormat::Wav {
+        let smpl = audio::smpl_chunk(options.sample_rate, loop_start, frames - 1);
+        audio::append_wav_chunks(&result.path, &[(b"smpl", &smpl)])?;
+    }
+    Ok(StemRenderResult {
+        loop_start: Some(loop_start),
+        ..result
+    })
+}
+
+/// Write audio rendered into memory to `output_path` tagged with `tags`,
+/// applying gain and normalization, then measuring its loudness if
+/// `replaygain` is set.
+fn write_from_memory<S: Sample>(
+    mut all_audio: Vec<S>,
+    output_path: PathBuf,
+    options: &ExportOptions,
+    tags: &[Tag],
+) -> Result<StemRenderResult> {
+    if options.gain_db != 0.0 {
+        dsp::apply_gain(&mut all_audio, options.gain_db);
+    }
+    let gain_db = match options.normalize {
+        NormalizeMode::Off => 0.0,
+        NormalizeMode::Peak => dsp::normalize_peak(&mut all_audio, options.normalize_target),
+        NormalizeMode::Rms => dsp::normalize_rms(&mut all_audio, options.normalize_target),
+    };
+    log::info!(
+        "Applied {:.2} dB of gain to {}",
+        gain_db,
+        output_path.display()
+    );
+
+    let loudness = options.replaygain.then(|| {
+        Loudness::measure(
+            &S::quantize(&all_audio),
+            options.channels as usize,
+            options.sample_rate,
+        )
+    });
+    let mut tags = tags.to_vec();
+    if let Some(loudness) = &loudness {
+        tags.extend(loudness::replaygain_tags(loudness, options.format));
+    }
+
+    let total_gain_db = options.gain_db + gain_db;
+    let options = ExportOptions {
+        gain_db: 0.0,
+        ..options.clone()
+    };
+    let mut writer = StemWriter::create(output_path, &options, &tags)?;
+    writer.write(&all_audio)?;
+    Ok(StemRenderResult {
+        gain_db: total_gain_db,
+        loudness,
+        ..writer.finish()?
+    })
+}
+
+/// Metadata keys left out of [`module_metadata`]: load warnings are captured
+/// through the logger, and the raw message repeats the instrument names.
+const SKIPPED_METADATA_KEYS: &[&str] = &["warnings", "message_raw"];
+
+/// The non-empty metadata libopenmpt has for a module, by key (e.g. `type_long`,
+/// `originaltype`, `container`, `artist`, `date`).
+pub fn module_metadata(module: &mut Module) -> BTreeMap<String, String> {
+    module
+        .get_metadata_keys()
+        .into_iter()
+        .filter(|key| !SKIPPED_METADATA_KEYS.contains(&key.as_str()))
+        .filter_map(|key| {
+            let value = module.get_metadata(&key)?.trim().to_string();
+            (!value.is_empty()).then_some((key, value))
+        })
+        .collect()
+}
+
+/// File name of a stem laid out by `template`, `<base_name>_<label>_<number>.<ext>`
+/// by default, where the label is taken from `labels` and `name` is the name
+/// of the instrument or sample in the module.
+pub fn stem_file_name(
+    template: &NameTemplate,
+    base_name: &str,
+    is_instrument: bool,
+    index: InstrumentIndex,
+    name: &str,
+    format: AudioFormat,
+    labels: &StemLabels,
+) -> String {
+    let stem = StemNameFields {
+        module: base_name,
+        label: labels.label(is_instrument),
+        number: index.number(),
+        name,
+    };
+    format!("{}.{}", template.fill(&stem), format.extension())
+}
+
+/// Error of renders that need to mute parts of the song when libopenmpt
+/// doesn't expose its interactive interface.
+pub const NO_ISOLATION_ERROR: &str =
+    "libopenmpt's interactive interface is not available, so instruments can't be muted";
+
+/// Whether parts of the song can be muted, which stems need. Some builds of
+/// libopenmpt leave out the interactive interface this takes, and can only
+/// render the full mix.
+pub fn can_isolate(module_ext: &ModuleExt) -> bool {
+    module_ext.get_interactive_interface().is_some()
+}
+
+/// Open a session on the module with every instrument or sample muted except `unmuted`.
+///
+/// Samples of modules without instruments are muted through the instrument
+/// functions, which take sample indices for such modules.
+fn stem_session(
+    buffer: &[u8],
+    unmuted: &[InstrumentIndex],
+    is_instrument: bool,
+    options: &ExportOptions,
+) -> Result<RenderSession> {
+    let session = RenderSession::new(buffer, options)?;
+    isolate(&session, unmuted, is_instrument)?;
+    Ok(session)
+}
+
+/// Mute every instrument or sample of the module of `session` except `unmuted`.
+fn isolate(
+    session: &RenderSession,
+    unmuted: &[InstrumentIndex],
+    is_instrument: bool,
+) -> Result<()> {
+    let module_ext = session.module_ext();
+
+    let interactive = module_ext
+        .get_interactive_interface()
+        .ok_or_else(|| anyhow!(NO_ISOLATION_ERROR))?;
+
+    let mut module = module_ext.get_module();
+
+    let count = if is_instrument {
+        module.get_num_instruments()
+    } else {
+        module.get_num_samples()
+    };
+
+    // Mute everything except the targets
+    for i in InstrumentIndex::all(count) {
+        interactive.set_instrument_mute_status(module_ext, i, !unmuted.contains(&i));
+    }
+
+    Ok(())
+}
+
+/// Render a stem without writing it anywhere, handing the audio to `sink` in
+/// chunks as it is rendered so memory use doesn't grow with the stem length.
+///
+/// The audio is passed on as rendered, including the `--preview` fade:
+/// `gain_db` and normalization are left to the sink. Returns the number of
+/// frames rendered.
+pub fn render_stem_streaming(
+    buffer: &[u8],
+    index: InstrumentIndex,
+    is_instrument: bool,
+    options: &ExportOptions,
+    progress: Option<&dyn ProgressSink>,
+    sink: impl FnMut(&[i16]) -> Result<()>,
+) -> Result<u64> {
+    let mut session = stem_session(buffer, &[index], is_instrument, options)?;
+    let type_label = if is_instrument {
+        "instrument"
+    } else {
+        "sample"
+    };
+    let label = format!("{} {}", type_label, index);
+    render_streaming(&mut session, &label, progress, sink)
+}
+
+/// An instrument, or a sample of a module without instruments: what a stem
+/// is rendered from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stem {
+    pub index: InstrumentIndex,
+    /// Whether the stem is an instrument rather than a sample
+    pub is_instrument: bool,
+    /// Name of the instrument or sample in the module, trimmed
+    pub name: String,
+}
+
+/// A module loaded once to render its stems one after the other, for
+/// embedders extracting stems without managing indices and buffers.
+///
+/// Renders with the same options as the one before reuse the loaded module,
+/// rewound and with the next stem unmuted, and the buffers audio is rendered
+/// into; other options load the module again.
+pub struct Extractor {
+    buffer: Vec<u8>,
+    stems: Vec<Stem>,
+    /// The session of the last render, with its options formatted as in
+    /// [`cache::RenderCache::key`]
+    session: (String, RenderSession),
+    buffers: StreamBuffers<i16>,
+}
+
+impl Extractor {
+    /// Load the module in `buffer`, with the default options.
+    pub fn new(buffer: impl Into<Vec<u8>>) -> Result<Self> {
+        let buffer = buffer.into();
+        let options = ExportOptions::default();
+        let session = RenderSession::new(&buffer, &options)?;
+        let stems = {
+            let mut module = session.module_ext().get_module();
+            let num_instruments = module.get_num_instruments();
+            if num_instruments > 0 {
+                InstrumentIndex::all(num_instruments)
+                    .map(|index| Stem {
+                        index,
+                        is_instrument: true,
+                        name: module.get_instrument_name(index).trim().to_string(),
+                    })
+                    .collect()
+            } else {
+                SampleIndex::all(module.get_num_samples())
+                    .map(|index| Stem {
+                        index: InstrumentIndex::new(index.get()),
+                        is_instrument: false,
+                        name: module.get_sample_name(index).trim().to_string(),
+                    })
+                    .collect()
+            }
+        };
+        Ok(Extractor {
+            buffer,
+            stems,
+            session: (format!("{:?}", options), session),
+            buffers: StreamBuffers::default(),
+        })
+    }
+
+    /// The stems of the module: its instruments, or its samples if it has
+    /// none, in order.
+    pub fn stems(&self) -> &[Stem] {
+        &self.stems
+    }
+
+    /// The module file the extractor was created from, for the functions
+    /// taking one, such as [`render_stem`].
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// The module as loaded for the last render, to read its metadata
+    /// without loading it again.
+    pub fn module_ext(&self) -> &ModuleExt {
+        self.session.1.module_ext()
+    }
+
+    /// Render stem `index` with `options`, handing the audio to `sink` in
+    /// chunks as [`render_stem_streaming`] does. Returns the number of frames
+    /// rendered.
+    pub fn render(
+        &mut self,
+        index: InstrumentIndex,
+        options: &ExportOptions,
+        sink: impl FnMut(&[i16]) -> Result<()>,
+    ) -> Result<u64> {
+        let Some(stem) = self.stems.iter().find(|stem| stem.index == index) else {
+            return Err(anyhow!(
+                "Stem {} selected, but the module only has {} stems",
+                index,
+                self.stems.len()
+            ));
+        };
+        let is_instrument = stem.is_instrument;
+        let label = format!(
+            "{} {}",
+            if is_instrument {
+                "instrument"
+            } else {
+                "sample"
+            },
+            index
+        );
+
+        let key = format!("{:?}", options);
+        if self.session.0 == key {
+            self.session.1.rewind()?;
+        } else {
+            self.session = (key, RenderSession::new(&self.buffer, options)?);
+        }
+        let session = &mut self.session.1;
+        isolate(session, &[index], is_instrument)?;
+        render_streaming_with(session, &label, None, &mut self.buffers, sink)
+    }
+}
+
+/// Render instrument (or sample) `index` to its stem file in `output_dir`,
+/// named by `template` with `labels`.
+///
+/// Encoded files are tagged with the name of the instrument, the title and
+/// artist of the module and the number of the instrument, then `tags`.
+#[allow(clippy::too_many_arguments)]
+pub fn render_stem(
+    buffer: &[u8],
+    index: InstrumentIndex,
+    is_instrument: bool,
+    output_dir: &str,
+    base_name: &str,
+    options: &ExportOptions,
+    template: &NameTemplate,
+    labels: &StemLabels,
+    tags: &[Tag],
+    progress: Option<&dyn ProgressSink>,
+) -> Result<StemRenderResult> {
+    let type_label = if is_instrument {
+        "instrument"
+    } else {
+        "sample"
+    };
+
+    let label = format!("{} {}", type_label, index);
+    if let Some(progress) = progress {
+        progress.report(RenderProgress::Started { label: &label });
+    } else if cfg!(test) {
+        // Only print to stdout in test mode for compatibility
+        println!("  Rendering {} {}...", type_label, index);
+    }
+    // In non-test mode with no progress bar, don't print individual messages to avoid console spam
+
+    log::info!(
+        "Starting to render {} {} to {}",
+        type_label,
+        index,
+        output_dir
+    );
+
+    let mut session = stem_session(buffer, &[index], is_instrument, options)?;
+    let (name, tags) = {
+        let mut module = session.module_ext().get_module();
+        let name = if is_instrument {
+            module.get_instrument_name(index)
+        } else {
+            module.get_sample_name(SampleIndex::new(index.get()))
+        };
+        let title = tags::stem_title(&mut module, index, is_instrument);
+        (
+            name,
+            tags::file_tags(&mut module, &title, Some(index.number()), tags),
+        )
+    };
+
+    let output_path = output_path(
+        output_dir,
+        &stem_file_name(
+            template,
+            base_name,
+            is_instrument,
+            index,
+            &name,
+            options.format,
+            labels,
+        ),
+    );
+
+    log::debug!("Writing to: {}", output_path.display());
+
+    let result = render_to_file(&mut session, &label, progress, output_path, options, &tags)?;
+    log::info!(
+        "Successfully rendered {} {} to {}",
+        type_label,
+        index,
+        result.path.display()
+    );
+
+    if let Some(progress) = progress {
+        progress.report(RenderProgress::Finished {
+            label: &label,
+            path: &result.path,
+        });
+    }
+
+    Ok(result)
+}
+
+/// Path of `file_name` in `output_dir`, or stdout when `output_dir` is
+/// [`STDOUT_PATH`], which takes one render.
+fn output_path(output_dir: &str, file_name: &str) -> PathBuf {
+    if output_dir == STDOUT_PATH {
+        PathBuf::from(STDOUT_PATH)
+    } else {
+        Path::new(output_dir).join(file_name)
+    }
+}
+
+/// File name of the full mix: `<base_name>.<ext>`, keeping any dots in
+/// `base_name`.
+pub fn mix_file_name(base_name: &str, format: AudioFormat) -> String {
+    format!("{}.{}", base_name, format.extension())
+}
+
+/// File name of a group stem: `<base_name>_<group name>.<ext>`.
+pub fn group_file_name(base_name: &str, group: &StemGroup, format: AudioFormat) -> String {
+    custom_file_name(base_name, &group.name, format)
+}
+
+/// File name of a render named by the caller: `<base_name>_<name>.<ext>`.
+pub fn custom_file_name(base_name: &str, name: &str, format: AudioFormat) -> String {
+    format!("{}_{}.{}", base_name, name, format.extension())
+}
+
+/// Render the members of `group` together into one stem, titled after the
+/// group in encoded files and followed by `tags`.
+#[allow(clippy::too_many_arguments)]
+pub fn render_group(
+    buffer: &[u8],
+    group: &StemGroup,
+    is_instrument: bool,
+    output_dir: &str,
+    base_name: &str,
+    options: &ExportOptions,
+    tags: &[Tag],
+    progress: Option<&dyn ProgressSink>,
+) -> Result<StemRenderResult> {
+    let label = format!("group {}", group.name);
+    if let Some(progress) = progress {
+        progress.report(RenderProgress::Started { label: &label });
+    }
+    log::info!(
+        "Starting to render group {} ({} {}s) to {}",
+        group.name,
+        group.members.len(),
+        if is_instrument {
+            "instrument"
+        } else {
+            "sample"
+        },
+        output_dir
+    );
+
+    let mut session = stem_session(buffer, &group.members, is_instrument, options)?;
+    let tags = tags::file_tags(
+        &mut session.module_ext().get_module(),
+        &group.name,
+        None,
+        tags,
+    );
+
+    let output_path = output_path(
+        output_dir,
+        &group_file_name(base_name, group, options.format),
+    );
+    log::debug!("Writing to: {}", output_path.display());
+
+    let result = render_to_file(&mut session, &label, progress, output_path, options, &tags)?;
+    log::info!(
+        "Successfully rendered group {} to {}",
+        group.name,
+        result.path.display()
+    );
+
+    if let Some(progress) = progress {
+        progress.report(RenderProgress::Finished {
+            label: &label,
+            path: &result.path,
+        });
+    }
+
+    Ok(result)
+}
+
+/// Render the whole module, with nothing muted, to `<output_dir>/<base_name>.<ext>`.
+///
+/// Encoded files are titled after the module, or `base_name` if it has no
+/// title, and tagged with `tags` after that.
+pub fn render_full_mix(
+    buffer: &[u8],
+    output_dir: &str,
+    base_name: &str,
+    options: &ExportOptions,
+    tags: &[Tag],
+    progress: Option<&dyn ProgressSink>,
+) -> Result<StemRenderResult> {
+    if let Some(progress) = progress {
+        progress.report(RenderProgress::Started { label: base_name });
+    }
+    log::info!(
+        "Starting to render full mix of {} to {}",
+        base_name,
+        output_dir
+    );
+
+    let mut session = RenderSession::new(buffer, options)?;
+    let tags = {
+        let mut module = session.module_ext().get_module();
+        let title = module.title().unwrap_or_else(|| base_name.to_string());
+        tags::file_tags(&mut module, &title, None, tags)
+    };
+
+    let output_path = output_path(output_dir, &mix_file_name(base_name, options.format));
+    log::debug!("Writing to: {}", output_path.display());
+
+    let result = render_to_file(
+        &mut session,
+        base_name,
+        progress,
+        output_path,
+        options,
+        &tags,
+    )?;
+    log::info!(
+        "Successfully rendered full mix to {}",
+        result.path.display()
+    );
+
+    if let Some(progress) = progress {
+        progress.report(RenderProgress::Finished {
+            label: base_name,
+            path: &result.path,
+        });
+    }
+
+    Ok(result)
+}
+
+/// Render the whole module, with nothing muted, and measure its loudness
+/// without writing anything.
+pub fn measure_full_mix(
+    buffer: &[u8],
+    options: &ExportOptions,
+    progress: Option<&dyn ProgressSink>,
+) -> Result<LoudnessMeter> {
+    let options = ExportOptions {
+        gain_db: 0.0,
+        normalize: NormalizeMode::Off,
+        ..options.clone()
+    };
+
+    let mut session = RenderSession::new(buffer, &options)?;
+    let all_audio: Vec<i16> = render_to_memory(&mut session, "measuring loudness", progress)?;
+
+    let mut meter = LoudnessMeter::default();
+    meter.add(&all_audio, options.channels as usize, options.sample_rate);
+    Ok(meter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_resample_method_mapping() {
+        assert_eq!(ResampleMethod::Nearest.to_openmpt_filter_length(), 1);
+        assert_eq!(ResampleMethod::Linear.to_openmpt_filter_length(), 2);
+        assert_eq!(ResampleMethod::Cubic.to_openmpt_filter_length(), 4);
+        assert_eq!(ResampleMethod::Sinc.to_openmpt_filter_length(), 8);
+    }
+
+    #[test]
+    fn test_audio_format_parsing() {
+        assert!("wav".parse::<AudioFormat>().is_ok());
+        assert!("WAV".parse::<AudioFormat>().is_ok());
+        #[cfg(feature = "vorbis")]
+        {
+            assert!("vorbis".parse::<AudioFormat>().is_ok());
+            assert!("ogg".parse::<AudioFormat>().is_ok());
+        }
+        #[cfg(feature = "opus")]
+        assert!("opus".parse::<AudioFormat>().is_ok());
+        #[cfg(feature = "flac")]
+        assert!("flac".parse::<AudioFormat>().is_ok());
+        assert!("invalid".parse::<AudioFormat>().is_err());
+    }
+
+    #[test]
+    fn test_export_options_struct() {
+        let options = ExportOptions {
+            format: AudioFormat::Wav,
+            sample_rate: 44100,
+            channels: 2,
+            render_channels: None,
+            bit_depth: 16,
+            sample_format: SampleFormat::Int,
+            dither: false,
+            opus_bitrate: 128,
+            opus_vbr: OpusVbr::On,
+            opus_complexity: 10,
+            opus_application: OpusApplication::Audio,
+            vorbis_quality: 5,
+            mp3_bitrate: 192,
+            mp3_vbr_quality: None,
+            resample: ResampleMethod::Sinc,
+            stereo_separation: 100,
+            master_gain_db: 0.0,
+            volume_ramping: None,
+            subsong: None,
+            preview: None,
+            loop_count: 0,
+            max_duration: None,
+            range: None,
+            loop_crossfade: None,
+            mute_automation: None,
+            mix_automation: None,
+            ctls: Arc::new([]),
+            normalize: NormalizeMode::Off,
+            normalize_target: -1.0,
+            gain_db: 0.0,
+            trim_silence: TrimSilence::Off,
+            pad_to_frames: None,
+            replaygain: false,
+            reproducible: false,
+        };
+        assert_eq!(options.sample_rate, 44100);
+        assert_eq!(options.channels, 2);
+    }
+
+    #[test]
+    fn test_stem_render_result_peak_dbfs() {
+        let result = StemRenderResult {
+            path: PathBuf::from("stem.wav"),
+            frames: 44100,
+            duration: 1.0,
+            peak: 0.5,
+            start_offset: 0.0,
+            gain_db: 0.0,
+            loudness: None,
+            loop_start: None,
+            skipped_reason: None,
+            analysis: StemAnalysis::new(&[0; 4], 2, 16),
+        };
+        assert!((result.peak_dbfs() + 6.02).abs() < 0.01);
+        assert!(!result.is_skipped());
+    }
+
+    #[test]
+    fn test_render_stem_invalid_buffer() {
+        let options = ExportOptions {
+            format: AudioFormat::Wav,
+            sample_rate: 44100,
+            channels: 2,
+            render_channels: None,
+            bit_depth: 16,
+            sample_format: SampleFormat::Int,
+            dither: false,
+            opus_bitrate: 128,
+            opus_vbr: OpusVbr::On,
+            opus_complexity: 10,
+            opus_application: OpusApplication::Audio,
+            vorbis_quality: 5,
+            mp3_bitrate: 192,
+            mp3_vbr_quality: None,
+            resample: ResampleMethod::Sinc,
+            stereo_separation: 100,
+            master_gain_db: 0.0,
+            volume_ramping: None,
+            subsong: None,
+            preview: None,
+            loop_count: 0,
+            max_duration: None,
+            range: None,
+            loop_crossfade: None,
+            mute_automation: None,
+            mix_automation: None,
+            ctls: Arc::new([]),
+            normalize: NormalizeMode::Off,
+            normalize_target: -1.0,
+            gain_db: 0.0,
+            trim_silence: TrimSilence::Off,
+            pad_to_frames: None,
+            replaygain: false,
+            reproducible: false,
+        };
+        let result = render_stem(
+            &[],
+            InstrumentIndex::new(0),
+            false,
+            ".",
+            "test",
+            &options,
+            &NameTemplate::default(),
+            &StemLabels::default(),
+            &[],
+            None,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_render_stem_streaming_matches_file() {
+        let buffer = include_bytes!("../../../tests/modules/cndmcrrp.mod");
+        let options = ExportOptions {
+            preview: Some(2.0),
+            reproducible: true,
+            ..ExportOptions::default()
+        };
+
+        let mut streamed = Vec::new();
+        let frames = render_stem_streaming(
+            buffer,
+            InstrumentIndex::new(0),
+            false,
+            &options,
+            None,
+            |chunk| {
+                streamed.extend_from_slice(chunk);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(frames, 2 * 44100);
+        assert_eq!(streamed.len(), 2 * 44100 * 2);
+        // The preview fades out to silence
+        assert_eq!(&streamed[streamed.len() - 2..], &[0, 0]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let result = render_stem(
+            buffer,
+            InstrumentIndex::new(0),
+            false,
+            dir.path().to_str().unwrap(),
+            "test",
+            &options,
+            &NameTemplate::default(),
+            &StemLabels::default(),
+            &[],
+            None,
+        )
+        .unwrap();
+        assert_eq!(result.frames, frames);
+        assert_eq!(result.peak, dsp::peak(&streamed));
+    }
+
+    #[test]
+    fn test_extractor() {
+        let buffer = include_bytes!("../../../tests/modules/cndmcrrp.mod");
+        let options = ExportOptions {
+            preview: Some(2.0),
+            reproducible: true,
+            ..ExportOptions::default()
+        };
+        let render = |extractor: &mut Extractor, index: i32, options: &ExportOptions| {
+            let mut audio = Vec::new();
+            extractor
+                .render(InstrumentIndex::new(index), options, |chunk| {
+                    audio.extend_from_slice(chunk);
+                    Ok(())
+                })
+                .unwrap();
+            audio
+        };
+
+        let mut extractor = Extractor::new(&buffer[..]).unwrap();
+        assert!(!extractor.stems().is_empty());
+        assert!(extractor.stems().iter().all(|stem| !stem.is_instrument));
+        assert_eq!(extractor.stems()[0].index, InstrumentIndex::new(0));
+
+        // Renders that follow each other on the same module match fresh ones
+        let first = render(&mut extractor, 0, &options);
+        let second = render(&mut extractor, 1, &options);
+        let mut fresh = Vec::new();
+        render_stem_streaming(
+            buffer,
+            InstrumentIndex::new(1),
+            false,
+            &options,
+            None,
+            |chunk| {
+                fresh.extend_from_slice(chunk);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(second, fresh);
+        assert_eq!(render(&mut extractor, 0, &options), first);
+
+        let mono = ExportOptions {
+            channels: 1,
+            ..options.clone()
+        };
+        assert_eq!(render(&mut extractor, 0, &mono).len(), first.len() / 2);
+        let missing = InstrumentIndex::new(extractor.stems().len() as i32);
+        assert!(extractor.render(missing, &options, |_| Ok(())).is_err());
+    }
+
+    #[test]
+    fn test_render_stem_progress() {
+        let buffer = include_bytes!("../../../tests/modules/cndmcrrp.mod");
+        let options = ExportOptions {
+            preview: Some(2.0),
+            ..ExportOptions::default()
+        };
+        let dir = tempfile::tempdir().unwrap();
+
+        let reported = std::sync::Mutex::new(Vec::new());
+        let sink = |progress: RenderProgress| {
+            let event = match progress {
+                RenderProgress::Started { label } => format!("started {}", label),
+                RenderProgress::Rendering { .. } => "rendering".to_string(),
+                RenderProgress::Finished { path, .. } => {
+                    format!("finished {}", path.file_name().unwrap().to_string_lossy())
+                }
+            };
+            reported.lock().unwrap().push((event, progress.fraction()));
+        };
+        render_stem(
+            buffer,
+            InstrumentIndex::new(0),
+            false,
+            dir.path().to_str().unwrap(),
+            "test",
+            &options,
+            &NameTemplate::default(),
+            &StemLabels::default(),
+            &[],
+            Some(&sink),
+        )
+        .unwrap();
+
+        let reported = reported.into_inner().unwrap();
+        assert_eq!(reported[0], ("started sample 1".to_string(), 0.0));
+        assert_eq!(
+            reported[reported.len() - 1],
+            ("finished test_sample_001.wav".to_string(), 1.0)
+        );
+        let fractions: Vec<f64> = reported[1..reported.len() - 1]
+            .iter()
+            .map(|(event, fraction)| {
+                assert_eq!(event, "rendering");
+                *fraction
+            })
+            .collect();
+        // Reported as the render moves on, a chunk at a time
+        assert!(fractions.len() > 2, "{:?}", fractions);
+        assert!(fractions.windows(2).all(|w| w[0] < w[1]), "{:?}", fractions);
+    }
+
+    #[test]
+    fn test_render_ends_with_the_song() {
+        let options = ExportOptions {
+            sample_rate: 22050,
+            channels: 1,
+            reproducible: true,
+            ..ExportOptions::default()
+        };
+        // Both have tempo changes, which a position in seconds can't follow exactly
+        let modules: [&[u8]; 2] = [
+            include_bytes!("../../../tests/modules/nova.s3m"),
+            include_bytes!("../../../tests/modules/zalza-karate_muffins.xm"),
+        ];
+
+        for buffer in modules {
+            let mut session = RenderSession::new(buffer, &options).unwrap();
+            let duration = session.duration_seconds();
+            let frames = render_streaming(&mut session, "test", None, |_: &[i16]| Ok(())).unwrap();
+
+            // The length libopenmpt computes for the song, give or take rounding,
+            // well within the length of a row
+            let rendered = frames as f64 / options.sample_rate as f64;
+            assert!(
+                (rendered - duration).abs() < 0.02,
+                "{} != {}",
+                rendered,
+                duration
+            );
+            // and nothing plays after it
+            assert_eq!(session.read(&mut [0; 1024]), 0);
+        }
+    }
+
+    #[test]
+    fn test_loop_count_and_max_duration() {
+        let buffer = include_bytes!("../../../tests/modules/nova.s3m");
+        let options = ExportOptions {
+            sample_rate: 22050,
+            channels: 1,
+            loop_count: 1,
+            reproducible: true,
+            ..ExportOptions::default()
+        };
+
+        // The repeat starts from the song's restart position, so it may be shorter
+        let mut session = RenderSession::new(buffer, &options).unwrap();
+        let duration = session.duration_seconds();
+        let frames = render_streaming(&mut session, "test", None, |_: &[i16]| Ok(())).unwrap();
+        assert!(frames as f64 / options.sample_rate as f64 > duration + 1.0);
+
+        let options = ExportOptions {
+            max_duration: Some(1.5),
+            ..options
+        };
+        let mut session = RenderSession::new(buffer, &options).unwrap();
+        let frames = render_streaming(&mut session, "test", None, |_: &[i16]| Ok(())).unwrap();
+        assert_eq!(frames, 33075);
+    }
+
+    #[test]
+    fn test_loop_crossfade() {
+        let dir = tempfile::tempdir().unwrap();
+        let buffer = include_bytes!("../../../tests/modules/nova.s3m");
+        let options = ExportOptions {
+            sample_rate: 22050,
+            channels: 1,
+            loop_crossfade: Some(0.5),
+            ..ExportOptions::default()
+        };
+        let mut session = RenderSession::new(buffer, &options).unwrap();
+        let duration = session.duration_seconds();
+        let path = dir.path().join("loop.wav");
+        let result =
+            render_to_file(&mut session, "test", None, path.clone(), &options, &[]).unwrap();
+
+        // One play of the song, looping back from its end to the restart position
+        assert!((result.duration - duration).abs() < 0.02);
+        let loop_start = result.loop_start.unwrap();
+        assert!(loop_start < result.frames);
+        let data = std::fs::read(&path).unwrap();
+        let smpl = data.windows(4).position(|id| id == b"smpl").unwrap();
+        let loop_end = u32::from_le_bytes(data[smpl + 56..smpl + 60].try_into().unwrap());
+        assert_eq!(loop_end as u64, result.frames - 1);
+
+        let preview = ExportOptions {
+            preview: Some(5.0),
+            ..options
+        };
+        assert!(preview.validate().is_err());
+    }
+
+    #[test]
+    fn test_pad_to_frames() {
+        let buffer = include_bytes!("../../../tests/modules/nova.s3m");
+        let options = ExportOptions {
+            sample_rate: 22050,
+            channels: 1,
+            max_duration: Some(1.0),
+            pad_to_frames: Some(30000),
+            ..ExportOptions::default()
+        };
+        let mut session = RenderSession::new(buffer, &options).unwrap();
+        let mut audio = Vec::new();
+        let frames = render_streaming(&mut session, "test", None, |chunk: &[i16]| {
+            audio.extend_from_slice(chunk);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(frames, 30000);
+        assert_eq!(audio.len(), 30000);
+        assert!(audio[22050..].iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn test_replaygain_after_gain() {
+        let dir = tempfile::tempdir().unwrap();
+        // A 997 Hz sine at -20 dBFS, which measures -20 LUFS
+        let tone: Vec<i16> = (0..48000 * 5)
+            .flat_map(|i| {
+                let phase = 2.0 * std::f64::consts::PI * 997.0 * i as f64 / 48000.0;
+                let s = (phase.sin() * 0.1 * 32767.0) as i16;
+                [s, s]
+            })
+            .collect();
+        let options = ExportOptions {
+            sample_rate: 48000,
+            gain_db: 6.0,
+            replaygain: true,
+            ..ExportOptions::default()
+        };
+        let result = write_from_memory(tone, dir.path().join("tone.wav"), &options, &[]).unwrap();
+
+        // Measured as written, after the gain
+        let loudness = result.loudness.unwrap();
+        assert!((loudness.integrated_lufs.unwrap() + 14.0).abs() < 0.1);
+        assert!((loudness.replaygain_db().unwrap() + 4.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_module_metadata() {
+        let buffer = include_bytes!("../../../tests/modules/cndmcrrp.mod");
+        let session = RenderSession::new(buffer, &ExportOptions::default()).unwrap();
+        let metadata = module_metadata(&mut session.module_ext().get_module());
+
+        assert_eq!(metadata["type"], "mod");
+        assert!(metadata.contains_key("type_long"));
+        assert!(!metadata.contains_key("message_raw"));
+        assert!(metadata.values().all(|value| !value.is_empty()));
+    }
+
+    #[cfg(feature = "memfs")]
+    #[test]
+    fn test_render_to_memory() {
+        let buffer = include_bytes!("../../../tests/modules/cndmcrrp.mod");
+        let options = ExportOptions {
+            preview: Some(1.0),
+            ..ExportOptions::default()
+        };
+        let result = render_stem(
+            buffer,
+            InstrumentIndex::new(0),
+            false,
+            "memfs-test",
+            "song",
+            &options,
+            &NameTemplate::default(),
+            &StemLabels::default(),
+            &[],
+            None,
+        )
+        .unwrap();
+
+        // Nothing was written to disk
+        assert!(!result.path.exists());
+        assert!(memfs::paths().contains(&result.path));
+        let wav = memfs::take(&result.path).unwrap();
+        assert_eq!(&wav[..4], b"RIFF");
+    }
+}
@@ -0,0 +1,156 @@
+//! What a module holds and what extracting it would write, found without
+//! rendering anything, for `--dry-run`.
+
+use openmpt::index::{ChannelIndex, InstrumentIndex, SampleIndex, SubsongIndex};
+use openmpt::module::Module;
+use serde::Serialize;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use crate::estimate::format_duration;
+
+/// A sub-song of a module.
+#[derive(Debug, Clone, Serialize)]
+pub struct ListedSubsong {
+    /// Number of the sub-song, from 1
+    pub number: i32,
+    pub name: String,
+    /// Length in seconds
+    pub duration: f64,
+}
+
+/// The contents of a module, and the files extracting it would write.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModuleListing {
+    pub module: PathBuf,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    /// Format of the module, e.g. "xm"
+    pub format: Option<String>,
+    /// Length in seconds of the song, or of the selected sub-song
+    pub duration: f64,
+    /// Names of the channels, numbered from 1, empty for unnamed ones
+    pub channels: Vec<String>,
+    pub instruments: Vec<String>,
+    pub samples: Vec<String>,
+    pub subsongs: Vec<ListedSubsong>,
+    /// Files an extraction would write, relative to the output directory.
+    /// Stems `--skip-silent` drops are listed too, since that is only known
+    /// once they are rendered
+    pub outputs: Vec<PathBuf>,
+}
+
+impl ModuleListing {
+    /// List the contents of `module`, loaded from `path`, with the sub-song
+    /// to render already selected. Outputs are left for the caller to add.
+    pub fn new(path: &Path, module: &mut Module) -> Self {
+        let duration = module.get_duration_seconds();
+        let channels = ChannelIndex::all(module.get_num_channels())
+            .map(|i| module.get_channel_name(i).trim().to_string())
+            .collect();
+        let instruments = InstrumentIndex::all(module.get_num_instruments())
+            .map(|i| module.get_instrument_name(i).trim().to_string())
+            .collect();
+        let samples = SampleIndex::all(module.get_num_samples())
+            .map(|i| module.get_sample_name(i).trim().to_string())
+            .collect();
+        // Selecting a sub-song is the only way to learn its length
+        let subsongs = SubsongIndex::all(module.get_num_subsongs())
+            .map(|i| ListedSubsong {
+                number: i.number(),
+                name: module.get_subsong_name(i).trim().to_string(),
+                duration: if module.select_subsong(i.get()) {
+                    module.get_duration_seconds()
+                } else {
+                    0.0
+                },
+            })
+            .collect();
+
+        ModuleListing {
+            module: path.to_path_buf(),
+            title: module.title(),
+            artist: module.artist(),
+            format: module.get_metadata("type"),
+            duration,
+            channels,
+            instruments,
+            samples,
+            subsongs,
+            outputs: Vec::new(),
+        }
+    }
+
+    /// The listing for people: a summary of the module followed by the files.
+    pub fn to_text(&self) -> String {
+        let mut text = self.module.display().to_string();
+        if let Some(title) = &self.title {
+            let _ = write!(text, ": {}", title);
+        }
+        if let Some(artist) = &self.artist {
+            let _ = write!(text, " by {}", artist);
+        }
+        let _ = writeln!(
+            text,
+            " ({}, {})",
+            self.format.as_deref().unwrap_or("unknown format"),
+            format_duration(self.duration)
+        );
+        let _ = writeln!(
+            text,
+            "  {} channels, {} instruments, {} samples, {} sub-songs",
+            self.channels.len(),
+            self.instruments.len(),
+            self.samples.len(),
+            self.subsongs.len()
+        );
+        if self.subsongs.len() > 1 {
+            for subsong in &self.subsongs {
+                let _ = writeln!(
+                    text,
+                    "    sub-song {}: {} {}",
+                    subsong.number,
+                    format_duration(subsong.duration),
+                    subsong.name
+                );
+            }
+        }
+        let _ = writeln!(text, "  Would write {} files:", self.outputs.len());
+        for output in &self.outputs {
+            let _ = writeln!(text, "    {}", output.display());
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{FixtureInstrument, FixtureModule, FixtureSample};
+    use crate::{ExportOptions, RenderSession};
+
+    #[test]
+    fn test_listing() {
+        let buffer = FixtureModule::new("Karate")
+            .instrument(FixtureInstrument::new(
+                "Kick",
+                FixtureSample::square("kick", 32),
+            ))
+            .instrument(FixtureInstrument::empty(""))
+            .to_xm();
+        let session = RenderSession::new(&buffer, &ExportOptions::default()).unwrap();
+        let mut module = session.module_ext().get_module();
+
+        let mut listing = ModuleListing::new(Path::new("karate.xm"), &mut module);
+        listing.outputs = vec![PathBuf::from("karate_instrument_001.wav")];
+        assert_eq!(listing.instruments, ["Kick", ""]);
+        assert_eq!(listing.subsongs.len(), 1);
+        assert_eq!(listing.format.as_deref(), Some("xm"));
+
+        let text = listing.to_text();
+        assert!(text.starts_with("karate.xm: Karate (xm, "), "{}", text);
+        assert!(text.ends_with("  Would write 1 files:\n    karate_instrument_001.wav\n"));
+        let json = serde_json::to_value(&listing).unwrap();
+        assert_eq!(json["outputs"][0], "karate_instrument_001.wav");
+    }
+}
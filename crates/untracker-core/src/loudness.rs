@@ -0,0 +1,379 @@
+//! Loudness measurement following ITU-R BS.1770 / EBU R128.
+
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::path::PathBuf;
+
+use crate::audio::{AudioFormat, Tag};
+
+/// Gating blocks are 400 ms long and overlap by 75%.
+const BLOCK_STEP_SECONDS: f64 = 0.1;
+const STEPS_PER_BLOCK: usize = 4;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+/// Highest true peak the album gain may raise a track to.
+pub const ALBUM_TRUE_PEAK_CEILING_DBTP: f64 = -1.0;
+/// Name of the album loudness summary written by `mix --album`.
+pub const ALBUM_REPORT_FILE: &str = "album.json";
+/// Oversampling factor used to find inter-sample peaks.
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+/// Taps of the interpolation filter on each side of an interpolated point.
+const TRUE_PEAK_HALF_TAPS: usize = 6;
+/// Loudness ReplayGain 2.0 brings tracks to.
+pub const REPLAYGAIN_REFERENCE_LUFS: f64 = -18.0;
+/// Loudness the `R128_TRACK_GAIN` tag of Opus files brings tracks to (RFC 7845).
+const OPUS_REFERENCE_LUFS: f64 = -23.0;
+
+/// Integrated loudness and true peak of some audio.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS, `None` if the audio is silent
+    pub integrated_lufs: Option<f64>,
+    /// True peak in dBTP, `None` if the audio is silent
+    pub true_peak_dbtp: Option<f64>,
+}
+
+impl Loudness {
+    pub fn measure(samples: &[i16], channels: usize, sample_rate: u32) -> Self {
+        let mut meter = LoudnessMeter::default();
+        meter.add(samples, channels, sample_rate);
+        meter.loudness()
+    }
+
+    /// Gain bringing the audio to the ReplayGain reference loudness, in dB,
+    /// or `None` if the audio is silent.
+    pub fn replaygain_db(&self) -> Option<f64> {
+        self.integrated_lufs
+            .map(|lufs| REPLAYGAIN_REFERENCE_LUFS - lufs)
+    }
+
+    /// Loudness of the same audio after applying `gain_db`.
+    pub fn with_gain(&self, gain_db: f64) -> Self {
+        Loudness {
+            integrated_lufs: self.integrated_lufs.map(|lufs| lufs + gain_db),
+            true_peak_dbtp: self.true_peak_dbtp.map(|peak| peak + gain_db),
+        }
+    }
+}
+
+/// Gain bringing an album measured at `album` to `target_lufs`, lowered if
+/// needed so that its true peak stays at or below `ceiling_dbtp`.
+///
+/// Returns the gain in dB and whether it was lowered. A silent album gets no gain.
+pub fn album_gain(album: &Loudness, target_lufs: f64, ceiling_dbtp: f64) -> (f64, bool) {
+    let Some(lufs) = album.integrated_lufs else {
+        return (0.0, false);
+    };
+    let gain_db = target_lufs - lufs;
+    match album.true_peak_dbtp {
+        Some(peak) if peak + gain_db > ceiling_dbtp => (ceiling_dbtp - peak, true),
+        _ => (gain_db, false),
+    }
+}
+
+/// ReplayGain tags of a track measured at `loudness`, as players expect them
+/// in files of `format`. Silent tracks get none.
+///
+/// Opus files carry an `R128_TRACK_GAIN` in Q7.8 fixed point relative to -23
+/// LUFS instead, since their players ignore the `REPLAYGAIN_*` tags.
+pub fn replaygain_tags(loudness: &Loudness, format: AudioFormat) -> Vec<Tag> {
+    let Some(lufs) = loudness.integrated_lufs else {
+        return Vec::new();
+    };
+    if format.name() == "opus" {
+        let gain = ((OPUS_REFERENCE_LUFS - lufs) * 256.0)
+            .round()
+            .clamp(i16::MIN as f64, i16::MAX as f64);
+        return vec![("R128_TRACK_GAIN".to_string(), (gain as i16).to_string())];
+    }
+
+    let mut tags = vec![(
+        "REPLAYGAIN_TRACK_GAIN".to_string(),
+        format!("{:.2} dB", REPLAYGAIN_REFERENCE_LUFS - lufs),
+    )];
+    if let Some(peak) = loudness.true_peak_dbtp {
+        tags.push((
+            "REPLAYGAIN_TRACK_PEAK".to_string(),
+            format!("{:.6}", 10f64.powf(peak / 20.0)),
+        ));
+    }
+    tags
+}
+
+/// Loudness summary of an album, written as `album.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlbumReport {
+    pub target_lufs: f64,
+    pub true_peak_ceiling_dbtp: f64,
+    /// All tracks measured together, before the album gain
+    pub album: Loudness,
+    /// Gain applied to every track
+    pub album_gain_db: f64,
+    /// Whether the gain was lowered to keep true peaks under the ceiling
+    pub peak_limited: bool,
+    pub tracks: Vec<AlbumTrack>,
+}
+
+/// One track of an [`AlbumReport`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlbumTrack {
+    pub module: String,
+    pub path: PathBuf,
+    /// Loudness as rendered by libopenmpt
+    pub measured: Loudness,
+    /// Loudness of the written file, after the album gain
+    pub output: Loudness,
+}
+
+/// Collects the gating blocks of one or more pieces of audio, so that the
+/// loudness of a whole album can be measured over all of its tracks.
+#[derive(Debug, Clone, Default)]
+pub struct LoudnessMeter {
+    /// Mean square of the K-weighted audio in each gating block, summed over channels
+    blocks: Vec<f64>,
+    /// Highest interpolated absolute sample value, as a fraction of full scale
+    true_peak: f64,
+}
+
+impl LoudnessMeter {
+    pub fn add(&mut self, samples: &[i16], channels: usize, sample_rate: u32) {
+        let frames = samples.len() / channels;
+        let step_frames = (BLOCK_STEP_SECONDS * sample_rate as f64).round() as usize;
+        let block_frames = step_frames * STEPS_PER_BLOCK;
+
+        let mut block_sums = vec![0.0; frames.saturating_sub(block_frames) / step_frames + 1];
+        for channel in 0..channels {
+            let channel_samples: Vec<f64> = samples
+                .iter()
+                .skip(channel)
+                .step_by(channels)
+                .map(|&s| s as f64 / 32768.0)
+                .collect();
+
+            self.true_peak = self.true_peak.max(true_peak(&channel_samples));
+
+            // Squared K-weighted samples, summed per step so blocks are cheap to add up
+            let weighted = k_weight(&channel_samples, sample_rate);
+            let steps: Vec<f64> = weighted
+                .chunks(step_frames)
+                .map(|step| step.iter().map(|s| s * s).sum())
+                .collect();
+            if frames < block_frames {
+                continue;
+            }
+            for (i, sum) in block_sums.iter_mut().enumerate() {
+                *sum += steps[i..i + STEPS_PER_BLOCK].iter().sum::<f64>() / block_frames as f64;
+            }
+        }
+
+        if frames >= block_frames {
+            self.blocks.extend(block_sums);
+        }
+    }
+
+    /// Add the blocks measured by another meter.
+    pub fn merge(&mut self, other: &LoudnessMeter) {
+        self.blocks.extend_from_slice(&other.blocks);
+        self.true_peak = self.true_peak.max(other.true_peak);
+    }
+
+    pub fn loudness(&self) -> Loudness {
+        let loudness = |power: f64| -0.691 + 10.0 * power.log10();
+        let gated_mean = |threshold: f64| {
+            let gated: Vec<f64> = self
+                .blocks
+                .iter()
+                .copied()
+                .filter(|&power| power > 0.0 && loudness(power) > threshold)
+                .collect();
+            (!gated.is_empty()).then(|| gated.iter().sum::<f64>() / gated.len() as f64)
+        };
+
+        let integrated_lufs = gated_mean(ABSOLUTE_GATE_LUFS)
+            .and_then(|power| gated_mean(loudness(power) + RELATIVE_GATE_LU))
+            .map(loudness);
+
+        Loudness {
+            integrated_lufs,
+            true_peak_dbtp: (self.true_peak > 0.0).then(|| 20.0 * self.true_peak.log10()),
+        }
+    }
+}
+
+/// A second order IIR filter section.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+}
+
+impl Biquad {
+    fn process(&self, input: &[f64]) -> Vec<f64> {
+        let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+        input
+            .iter()
+            .map(|&x| {
+                let y = self.b[0] * x + self.b[1] * x1 + self.b[2] * x2
+                    - self.a[1] * y1
+                    - self.a[2] * y2;
+                (x2, x1, y2, y1) = (x1, x, y1, y);
+                y
+            })
+            .collect()
+    }
+}
+
+/// Apply the K-weighting filter, with coefficients derived for any sample rate
+/// the way libebur128 does.
+fn k_weight(samples: &[f64], sample_rate: u32) -> Vec<f64> {
+    let rate = sample_rate as f64;
+
+    // High shelf modelling the acoustic effect of the head
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+
+    // RLB high-pass
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+
+    high_pass.process(&shelf.process(samples))
+}
+
+/// Highest absolute value of the signal after oversampling, which catches
+/// peaks between samples that a DAC or lossy encoder would reproduce.
+fn true_peak(samples: &[f64]) -> f64 {
+    // Hann windowed sinc interpolation kernels, one per fractional position
+    let kernels: Vec<Vec<f64>> = (1..TRUE_PEAK_OVERSAMPLING)
+        .map(|phase| {
+            let fraction = phase as f64 / TRUE_PEAK_OVERSAMPLING as f64;
+            (0..2 * TRUE_PEAK_HALF_TAPS)
+                .map(|tap| {
+                    let distance = TRUE_PEAK_HALF_TAPS as f64 - 1.0 + fraction - tap as f64;
+                    let window =
+                        0.5 + 0.5 * (PI * distance / (TRUE_PEAK_HALF_TAPS as f64 + 1.0)).cos();
+                    let sinc = if distance == 0.0 {
+                        1.0
+                    } else {
+                        (PI * distance).sin() / (PI * distance)
+                    };
+                    sinc * window
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut peak = samples.iter().fold(0.0f64, |peak, s| peak.max(s.abs()));
+    for window in samples.windows(2 * TRUE_PEAK_HALF_TAPS) {
+        for kernel in &kernels {
+            let value: f64 = window.iter().zip(kernel).map(|(s, k)| s * k).sum();
+            peak = peak.max(value.abs());
+        }
+    }
+    peak
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, amplitude: f64, sample_rate: u32, seconds: f64) -> Vec<i16> {
+        let frames = (sample_rate as f64 * seconds) as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let s = (2.0 * PI * frequency * i as f64 / sample_rate as f64).sin() * amplitude;
+                let s = (s * 32767.0) as i16;
+                [s, s]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_reference_tone() {
+        // A 997 Hz sine at -20 dBFS in both channels of a stereo signal
+        // measures -20 LUFS (within rounding) per EBU Tech 3341
+        let samples = sine(997.0, 0.1, 48000, 5.0);
+        let loudness = Loudness::measure(&samples, 2, 48000);
+        let lufs = loudness.integrated_lufs.unwrap();
+        assert!((lufs + 20.0).abs() < 0.1, "measured {} LUFS", lufs);
+        assert!((loudness.true_peak_dbtp.unwrap() + 20.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_inter_sample_peak() {
+        // A sine at a quarter of the sample rate, sampled 45 degrees off its
+        // peaks, has sample values 3 dB below its true peak
+        let samples: Vec<i16> = (0..48000)
+            .flat_map(|i| {
+                let s = (PI / 2.0 * i as f64 + PI / 4.0).sin() * 0.5;
+                let s = (s * 32767.0) as i16;
+                [s, s]
+            })
+            .collect();
+        let loudness = Loudness::measure(&samples, 2, 48000);
+        assert!((loudness.true_peak_dbtp.unwrap() - 20.0 * 0.5f64.log10()).abs() < 0.3);
+    }
+
+    #[test]
+    fn test_replaygain_tags() {
+        let loudness = Loudness {
+            integrated_lufs: Some(-12.5),
+            true_peak_dbtp: Some(-6.0),
+        };
+        assert_eq!(loudness.replaygain_db(), Some(-5.5));
+        assert_eq!(
+            replaygain_tags(&loudness, AudioFormat::Wav),
+            [
+                ("REPLAYGAIN_TRACK_GAIN".to_string(), "-5.50 dB".to_string()),
+                ("REPLAYGAIN_TRACK_PEAK".to_string(), "0.501187".to_string()),
+            ]
+        );
+        #[cfg(feature = "opus")]
+        assert_eq!(
+            replaygain_tags(&loudness, AudioFormat::Opus),
+            [("R128_TRACK_GAIN".to_string(), "-2688".to_string())]
+        );
+
+        let silence = Loudness::measure(&[0; 48000 * 2], 2, 48000);
+        assert!(replaygain_tags(&silence, AudioFormat::Wav).is_empty());
+    }
+
+    #[test]
+    fn test_silence_and_album() {
+        let silence = vec![0i16; 48000 * 2];
+        assert_eq!(Loudness::measure(&silence, 2, 48000).integrated_lufs, None);
+
+        let mut album = LoudnessMeter::default();
+        album.add(&sine(997.0, 0.1, 48000, 5.0), 2, 48000);
+        album.add(&silence, 2, 48000);
+        // Silent blocks are gated out of the album loudness
+        let album = album.loudness();
+        assert!((album.integrated_lufs.unwrap() + 20.0).abs() < 0.1);
+
+        let (gain_db, limited) = album_gain(&album, -14.0, -1.0);
+        assert!((gain_db - 6.0).abs() < 0.1 && !limited);
+        let (gain_db, limited) = album_gain(&album, -14.0, -16.0);
+        assert!((gain_db - 4.0).abs() < 0.1 && limited);
+        assert_eq!(
+            album_gain(&Loudness::measure(&silence, 2, 48000), -14.0, -1.0),
+            (0.0, false)
+        );
+    }
+}
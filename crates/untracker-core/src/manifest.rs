@@ -0,0 +1,367 @@
+//! Machine-readable description of an extraction, written by `--write-metadata`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::audio::{ExportOptions, NormalizeMode, SampleFormat, Tag, TrimSilence};
+use crate::colors::StemColors;
+use crate::container::ModuleContainer;
+use crate::StemRenderResult;
+
+/// Name of the file describing the stems, written next to them.
+pub const STEM_MANIFEST_FILE: &str = "manifest.json";
+
+/// The options a module was rendered with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenderSettings {
+    pub format: String,
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub bit_depth: u32,
+    /// "int" or "float"
+    #[serde(default = "default_sample_format")]
+    pub sample_format: String,
+    /// Whether integer samples were dithered from a floating point render
+    #[serde(default)]
+    pub dither: bool,
+    pub resample: String,
+    pub stereo_separation: i32,
+    /// Gain of the libopenmpt mixer in dB
+    #[serde(default)]
+    pub master_gain_db: f64,
+    /// Volume ramping strength of the libopenmpt mixer, `null` for its default
+    #[serde(default)]
+    pub volume_ramping: Option<i32>,
+    /// libopenmpt ctls set with `--ctl`, by key
+    #[serde(default)]
+    pub ctls: BTreeMap<String, String>,
+    /// Number of the rendered sub-song, from 1, if one was selected
+    pub subsong: Option<i32>,
+    /// Length of the preview in seconds, if only the start of the song was rendered
+    pub preview: Option<f64>,
+    /// Times the song was repeated after playing through once
+    #[serde(default)]
+    pub loop_count: u32,
+    /// Length in seconds of the crossfade into the loop, for looping stems
+    #[serde(default)]
+    pub loop_crossfade: Option<f64>,
+    /// Length in seconds after which rendering stopped, if limited
+    pub max_duration: Option<f64>,
+    /// Section of the song that was rendered, such as "orders 4-7" or "30.000s-45.000s"
+    pub range: Option<String>,
+    /// "off", "peak" or "rms"
+    #[serde(default = "default_normalize")]
+    pub normalize: String,
+    /// Peak or RMS level in dBFS every stem was normalized to, if any
+    pub normalize_target: Option<f64>,
+    pub gain_db: f64,
+    /// "off", "end" or "both"
+    pub trim_silence: String,
+    /// Whether the loudness of every stem was measured and tagged
+    #[serde(default)]
+    pub replaygain: bool,
+}
+
+impl From<&ExportOptions> for RenderSettings {
+    fn from(options: &ExportOptions) -> Self {
+        RenderSettings {
+            format: options.format.name().to_string(),
+            sample_rate: options.sample_rate,
+            channels: options.channels,
+            bit_depth: options.bit_depth,
+            sample_format: match options.sample_format {
+                SampleFormat::Int => "int",
+                SampleFormat::Float => "float",
+            }
+            .to_string(),
+            dither: options.dither,
+            resample: format!("{:?}", options.resample).to_lowercase(),
+            stereo_separation: options.stereo_separation,
+            master_gain_db: options.master_gain_db,
+            volume_ramping: options.volume_ramping,
+            ctls: options.ctls.iter().cloned().collect(),
+            subsong: options.subsong.map(|subsong| subsong.number()),
+            preview: options.preview,
+            loop_count: options.loop_count,
+            loop_crossfade: options.loop_crossfade,
+            max_duration: options.max_duration,
+            range: options.range.map(|range| range.to_string()),
+            normalize: match options.normalize {
+                NormalizeMode::Off => "off",
+                NormalizeMode::Peak => "peak",
+                NormalizeMode::Rms => "rms",
+            }
+            .to_string(),
+            normalize_target: (options.normalize != NormalizeMode::Off)
+                .then_some(options.normalize_target),
+            gain_db: options.gain_db,
+            trim_silence: match options.trim_silence {
+                TrimSilence::Off => "off",
+                TrimSilence::End => "end",
+                TrimSilence::Both => "both",
+            }
+            .to_string(),
+            replaygain: options.replaygain,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Names of the settings that differ from `other`, such as `normalize`,
+    /// telling whether files rendered with `other` are out of date.
+    pub fn changes(&self, other: &RenderSettings) -> Vec<String> {
+        let (Ok(Value::Object(ours)), Ok(Value::Object(theirs))) =
+            (serde_json::to_value(self), serde_json::to_value(other))
+        else {
+            return Vec::new();
+        };
+        ours.iter()
+            .filter(|(name, value)| theirs.get(*name) != Some(*value))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+}
+
+/// Manifests written before float output only hold integer samples.
+fn default_sample_format() -> String {
+    "int".to_string()
+}
+
+/// Manifests written before the mode was recorded read as not normalized.
+fn default_normalize() -> String {
+    "off".to_string()
+}
+
+/// One output file in the manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestStem {
+    /// Output file name, relative to the manifest
+    pub file: String,
+    /// "instrument", "sample", "group", "custom" for a [`crate::MuteMask`]
+    /// render, or "mix" for the full mix written when stems can't be isolated
+    pub kind: String,
+    /// Name of the instrument or sample in the module, or of the group
+    pub name: String,
+    /// Instrument or sample numbers, from 1, that went into the file
+    pub members: Vec<i32>,
+    /// Length in seconds
+    pub duration: f64,
+    /// Where the stem starts in the song, in seconds
+    pub start_offset: f64,
+    /// Peak level in dBFS, `null` for a silent stem
+    pub peak_dbfs: Option<f64>,
+    /// Gain applied to the stem in dB; subtracting it restores the balance
+    /// between the stems as rendered
+    #[serde(default)]
+    pub gain_db: f64,
+    /// Integrated loudness in LUFS, with `--replaygain`, `null` otherwise or
+    /// for a silent stem
+    #[serde(default)]
+    pub loudness_lufs: Option<f64>,
+    /// True peak in dBTP, with `--replaygain`
+    #[serde(default)]
+    pub true_peak_dbtp: Option<f64>,
+    /// ReplayGain of the stem in dB, bringing it to -18 LUFS, with
+    /// `--replaygain`. Encoded files also carry it as a tag, WAV files only here
+    #[serde(default)]
+    pub replaygain_db: Option<f64>,
+    /// First frame of the loop of a looping stem, which plays from there to
+    /// its end, with `--loop-crossfade`
+    #[serde(default)]
+    pub loop_start: Option<u64>,
+    /// Length of the loop in frames, with `--loop-crossfade`
+    #[serde(default)]
+    pub loop_frames: Option<u64>,
+    /// Group the stem is tagged with, such as "drums", for organizing tracks
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Color of the group, as `#rrggbb`
+    #[serde(default)]
+    pub color: Option<String>,
+    /// Size of the file in bytes, `null` if it was skipped
+    pub size: Option<u64>,
+    /// Why the file was not kept, if it was skipped
+    pub skipped: Option<String>,
+    /// Why the stem was rendered again with the safe fallback options of
+    /// `--retry-safe`, if it was
+    #[serde(default)]
+    pub fallback: Option<String>,
+}
+
+impl ManifestStem {
+    pub fn new(result: &StemRenderResult, kind: &str, name: &str, members: Vec<i32>) -> Self {
+        ManifestStem {
+            file: result
+                .path
+                .file_name()
+                .map(|f| f.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            kind: kind.to_string(),
+            name: name.trim().to_string(),
+            members,
+            duration: result.duration,
+            start_offset: result.start_offset,
+            peak_dbfs: (result.peak > 0.0).then(|| result.peak_dbfs()),
+            gain_db: result.gain_db,
+            loudness_lufs: result.loudness.and_then(|l| l.integrated_lufs),
+            true_peak_dbtp: result.loudness.and_then(|l| l.true_peak_dbtp),
+            replaygain_db: result.loudness.and_then(|l| l.replaygain_db()),
+            loop_start: result.loop_start,
+            loop_frames: result.loop_start.map(|start| result.frames - start),
+            group: None,
+            color: None,
+            size: fs::metadata(&result.path).ok().map(|m| m.len()),
+            skipped: result.skipped_reason.clone(),
+            fallback: None,
+        }
+    }
+
+    /// The stem rendered again with the safe fallback options, after the
+    /// render with the requested ones failed with `error`.
+    pub fn with_fallback(self, error: Option<&str>) -> Self {
+        ManifestStem {
+            fallback: error.map(|e| format!("{} ({})", ExportOptions::SAFE_FALLBACK, e)),
+            ..self
+        }
+    }
+
+    /// The stem tagged with `group`, in its color from `colors`.
+    pub fn in_group(self, group: &str, colors: &StemColors) -> Self {
+        ManifestStem {
+            group: Some(group.to_string()),
+            color: Some(colors.color(group).to_string()),
+            ..self
+        }
+    }
+}
+
+/// Everything about the stems of one module, written as `manifest.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StemManifest {
+    /// File name of the module
+    pub module: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    /// Format of the module, e.g. "xm", inside any container or compression
+    pub format: Option<String>,
+    /// Tracker the format belongs to, e.g. "FastTracker 2"
+    pub format_name: Option<String>,
+    /// Container the module was embedded in, e.g. "umx"
+    #[serde(default)]
+    pub container: Option<String>,
+    /// Name of the container format, e.g. "Unreal Music"
+    #[serde(default)]
+    pub container_name: Option<String>,
+    /// How the module file was compressed, e.g. "MO3" or "PowerPacker"
+    #[serde(default)]
+    pub compression: Option<String>,
+    /// Length of the song in seconds
+    pub duration: f64,
+    /// All metadata libopenmpt has for the module
+    pub metadata: BTreeMap<String, String>,
+    pub settings: RenderSettings,
+    /// Attribution given with `--meta`, such as `license` and `source-url`
+    #[serde(default)]
+    pub meta: BTreeMap<String, String>,
+    pub stems: Vec<ManifestStem>,
+}
+
+impl StemManifest {
+    pub fn new(
+        module: &str,
+        metadata: &BTreeMap<String, String>,
+        duration: f64,
+        options: &ExportOptions,
+        stems: Vec<ManifestStem>,
+    ) -> Self {
+        let field = |key: &str| metadata.get(key).cloned();
+        let container = ModuleContainer::from_metadata(metadata);
+        StemManifest {
+            module: module.to_string(),
+            title: field("title"),
+            artist: field("artist"),
+            format: container.format,
+            format_name: container.format_name,
+            container: container.container,
+            container_name: container.container_name,
+            compression: container.compression,
+            duration,
+            metadata: metadata.clone(),
+            settings: options.into(),
+            meta: BTreeMap::new(),
+            stems,
+        }
+    }
+
+    /// Record `meta`, as given to `--meta`, with its keys in lower case.
+    pub fn with_meta(mut self, meta: &[Tag]) -> Self {
+        self.meta.extend(
+            meta.iter()
+                .map(|(key, value)| (key.to_lowercase(), value.clone())),
+        );
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StemAnalysis;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_manifest_json() {
+        let result = StemRenderResult {
+            path: PathBuf::from("stage/song_instrument_02.wav"),
+            frames: 44100,
+            duration: 1.0,
+            peak: 0.5,
+            start_offset: 0.0,
+            gain_db: 4.5,
+            loudness: None,
+            loop_start: None,
+            skipped_reason: None,
+            analysis: StemAnalysis::new(&[0; 4], 2, 16),
+        };
+        let metadata = BTreeMap::from([
+            ("title".to_string(), "Song".to_string()),
+            ("type".to_string(), "xm".to_string()),
+        ]);
+        let stems = vec![ManifestStem::new(&result, "instrument", " Kick ", vec![2])
+            .in_group("drums", &StemColors::default())];
+        let meta = [("LICENSE".to_string(), "CC-BY".to_string())];
+        let manifest =
+            StemManifest::new("song.xm", &metadata, 90.0, &ExportOptions::default(), stems)
+                .with_meta(&meta);
+
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert_eq!(json["title"], "Song");
+        assert_eq!(json["artist"], serde_json::Value::Null);
+        assert_eq!(json["format"], "xm");
+        assert_eq!(json["compression"], serde_json::Value::Null);
+        assert_eq!(json["settings"]["format"], "wav");
+        assert_eq!(json["settings"]["resample"], "sinc");
+        assert_eq!(json["settings"]["trim_silence"], "off");
+        assert_eq!(json["settings"]["normalize"], "off");
+        assert_eq!(json["meta"]["license"], "CC-BY");
+        assert_eq!(json["stems"][0]["gain_db"], 4.5);
+        assert_eq!(json["stems"][0]["loudness_lufs"], serde_json::Value::Null);
+        assert_eq!(json["stems"][0]["file"], "song_instrument_02.wav");
+        assert_eq!(json["stems"][0]["group"], "drums");
+        assert_eq!(json["stems"][0]["color"], "#d9483b");
+        assert_eq!(json["stems"][0]["name"], "Kick");
+        let normalized = ExportOptions {
+            normalize: NormalizeMode::Peak,
+            gain_db: 3.0,
+            ..ExportOptions::default()
+        };
+        assert_eq!(
+            manifest.settings.changes(&(&normalized).into()),
+            ["gain_db", "normalize", "normalize_target"]
+        );
+        assert!(manifest.settings.changes(&manifest.settings).is_empty());
+        assert!((json["stems"][0]["peak_dbfs"].as_f64().unwrap() + 6.02).abs() < 0.01);
+    }
+}
@@ -0,0 +1,352 @@
+//! Marker files that mirror the arrangement of a module on a DAW timeline.
+
+use anyhow::{anyhow, Result};
+use std::fmt::Write;
+
+use crate::remix_kit::bpm;
+use crate::session::RenderSession;
+
+/// A region of the song played by one order position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker {
+    pub order: i32,
+    pub pattern: i32,
+    /// Pattern name, empty if the pattern has none
+    pub name: String,
+    /// Start and end in seconds
+    pub start: f64,
+    pub end: f64,
+}
+
+impl Marker {
+    pub fn label(&self) -> String {
+        let mut label = format!("Order {} - Pattern {}", self.order, self.pattern);
+        if !self.name.trim().is_empty() {
+            write!(label, ": {}", self.name.trim()).unwrap();
+        }
+        label
+    }
+}
+
+/// A change of tempo or speed, marked at the point it happens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoChange {
+    /// Time in seconds
+    pub time: f64,
+    pub tempo: f64,
+    /// Ticks per row
+    pub speed: i32,
+}
+
+impl TempoChange {
+    pub fn label(&self) -> String {
+        format!(
+            "{:.1} BPM (tempo {}, speed {})",
+            bpm(self.tempo, self.speed),
+            self.tempo,
+            self.speed
+        )
+    }
+}
+
+/// The arrangement of the song as it plays: a region per order position and
+/// the tempo changes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Markers {
+    pub regions: Vec<Marker>,
+    pub tempo_changes: Vec<TempoChange>,
+}
+
+/// Marker file formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkerFormat {
+    /// Region list that Reaper imports through the region/marker manager
+    Reaper,
+    /// Label track, which Audacity and most editors import
+    Audacity,
+    /// Cue sheet of the full mix, with a track per order position
+    Cue,
+}
+
+impl std::str::FromStr for MarkerFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reaper" => Ok(MarkerFormat::Reaper),
+            "audacity" | "labels" => Ok(MarkerFormat::Audacity),
+            "cue" => Ok(MarkerFormat::Cue),
+            _ => Err(anyhow!("Unknown marker format: {}", s)),
+        }
+    }
+}
+
+/// Tracks a cue sheet can hold.
+const MAX_CUE_TRACKS: usize = 99;
+
+impl MarkerFormat {
+    /// Suffix of the marker file, appended to the module name. Cue sheets
+    /// are named like the full mix they describe.
+    pub fn file_suffix(self) -> &'static str {
+        match self {
+            MarkerFormat::Reaper => "_markers.csv",
+            MarkerFormat::Audacity => "_markers.txt",
+            MarkerFormat::Cue => ".cue",
+        }
+    }
+
+    /// The marker file, for the full mix `mix_file` in the case of cue sheets.
+    pub fn write(self, markers: &Markers, mix_file: &str) -> String {
+        let mut out = String::new();
+        match self {
+            MarkerFormat::Reaper => {
+                out.push_str("#,Name,Start,End,Length\n");
+                for (i, marker) in markers.regions.iter().enumerate() {
+                    writeln!(
+                        out,
+                        "R{},\"{}\",{},{},{}",
+                        i + 1,
+                        marker.label().replace('"', "\"\""),
+                        reaper_time(marker.start),
+                        reaper_time(marker.end),
+                        reaper_time(marker.end - marker.start)
+                    )
+                    .unwrap();
+                }
+                for (i, change) in markers.tempo_changes.iter().enumerate() {
+                    writeln!(
+                        out,
+                        "M{},\"{}\",{},,",
+                        i + 1,
+                        change.label(),
+                        reaper_time(change.time)
+                    )
+                    .unwrap();
+                }
+            }
+            MarkerFormat::Audacity => {
+                for marker in &markers.regions {
+                    writeln!(
+                        out,
+                        "{:.6}\t{:.6}\t{}",
+                        marker.start,
+                        marker.end,
+                        marker.label()
+                    )
+                    .unwrap();
+                }
+                // Labels that start and end at the same time are points
+                for change in &markers.tempo_changes {
+                    writeln!(
+                        out,
+                        "{:.6}\t{:.6}\t{}",
+                        change.time,
+                        change.time,
+                        change.label()
+                    )
+                    .unwrap();
+                }
+            }
+            MarkerFormat::Cue => {
+                let file_type = if mix_file.ends_with(".mp3") {
+                    "MP3"
+                } else {
+                    "WAVE"
+                };
+                writeln!(out, "FILE \"{}\" {}", mix_file.replace('"', "'"), file_type).unwrap();
+                if markers.regions.len() > MAX_CUE_TRACKS {
+                    log::warn!(
+                        "The song plays {} order positions, more than the {} tracks a cue sheet holds: \
+                         leaving out the rest",
+                        markers.regions.len(),
+                        MAX_CUE_TRACKS
+                    );
+                }
+                // Cue sheets have no place for tempo changes
+                for (i, marker) in markers.regions.iter().take(MAX_CUE_TRACKS).enumerate() {
+                    writeln!(out, "  TRACK {:02} AUDIO", i + 1).unwrap();
+                    writeln!(out, "    TITLE \"{}\"", marker.label().replace('"', "'")).unwrap();
+                    writeln!(out, "    INDEX 01 {}", cue_time(marker.start)).unwrap();
+                }
+            }
+        }
+        out
+    }
+}
+
+fn reaper_time(seconds: f64) -> String {
+    let millis = (seconds * 1000.0).round() as u64;
+    format!(
+        "{}:{:02}.{:03}",
+        millis / 60_000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Time in a cue sheet: minutes, seconds and frames of 1/75 second.
+fn cue_time(seconds: f64) -> String {
+    let frames = (seconds * 75.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        frames / (75 * 60),
+        frames / 75 % 60,
+        frames % 75
+    )
+}
+
+/// Frames rendered between checks of the playback position: a millisecond
+/// and a half at 44.1 kHz, which is how far off markers can be.
+const TRACKING_FRAMES: usize = 64;
+
+/// The regions and tempo changes of the song, found by rendering it and
+/// following the playback position, up to `limit` seconds if given.
+///
+/// Times are relative to the start of the session's range. Orders get a
+/// region each time they play, such as when the song jumps back or is
+/// played more than once, and orders that never play get none. The session
+/// is rewound before and after.
+pub fn track_markers(session: &mut RenderSession, limit: Option<f64>) -> Result<Markers> {
+    session.rewind()?;
+    let sample_rate = session.options().sample_rate as f64;
+    let mut buf = vec![0i16; TRACKING_FRAMES * session.options().channels as usize];
+    let mut markers = Markers::default();
+    let mut frames = 0u64;
+    loop {
+        let time = frames as f64 / sample_rate;
+        if limit.is_some_and(|limit| time >= limit) {
+            break;
+        }
+
+        let mut module = session.module_ext().get_module();
+        let order = module.get_current_order();
+        if markers.regions.last().map(|region| region.order) != Some(order) {
+            let pattern = module.get_current_pattern();
+            let name = module
+                .get_pattern_by_number(pattern)
+                .map(|mut p| p.get_name())
+                .unwrap_or_default();
+            if let Some(region) = markers.regions.last_mut() {
+                region.end = time;
+            }
+            markers.regions.push(Marker {
+                order,
+                pattern,
+                name,
+                start: time,
+                end: time,
+            });
+        }
+        let (tempo, speed) = (module.get_current_tempo2(), module.get_current_speed());
+        if markers
+            .tempo_changes
+            .last()
+            .map(|change| (change.tempo, change.speed))
+            != Some((tempo, speed))
+        {
+            markers
+                .tempo_changes
+                .push(TempoChange { time, tempo, speed });
+        }
+
+        let read = session.read(&mut buf);
+        if read == 0 {
+            break;
+        }
+        frames += read as u64;
+    }
+
+    let end = frames as f64 / sample_rate;
+    let end = limit.map_or(end, |limit| end.min(limit));
+    if let Some(region) = markers.regions.last_mut() {
+        region.end = end;
+    }
+    markers.regions.retain(|region| region.end > region.start);
+    session.rewind()?;
+    Ok(markers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::FixtureModule;
+    use crate::ExportOptions;
+
+    #[test]
+    fn test_marker_formats() {
+        let markers = Markers {
+            regions: vec![
+                Marker {
+                    order: 0,
+                    pattern: 2,
+                    name: String::new(),
+                    start: 0.0,
+                    end: 7.68,
+                },
+                Marker {
+                    order: 1,
+                    pattern: 0,
+                    name: "Chorus \"A\"".to_string(),
+                    start: 7.68,
+                    end: 75.5,
+                },
+            ],
+            tempo_changes: vec![
+                TempoChange {
+                    time: 0.0,
+                    tempo: 125.0,
+                    speed: 6,
+                },
+                TempoChange {
+                    time: 7.68,
+                    tempo: 125.0,
+                    speed: 3,
+                },
+            ],
+        };
+
+        assert_eq!(
+            MarkerFormat::Reaper.write(&markers, "song.wav"),
+            "#,Name,Start,End,Length\n\
+             R1,\"Order 0 - Pattern 2\",0:00.000,0:07.680,0:07.680\n\
+             R2,\"Order 1 - Pattern 0: Chorus \"\"A\"\"\",0:07.680,1:15.500,1:07.820\n\
+             M1,\"125.0 BPM (tempo 125, speed 6)\",0:00.000,,\n\
+             M2,\"250.0 BPM (tempo 125, speed 3)\",0:07.680,,\n"
+        );
+        assert_eq!(
+            MarkerFormat::Audacity.write(&markers, "song.wav"),
+            "0.000000\t7.680000\tOrder 0 - Pattern 2\n\
+             7.680000\t75.500000\tOrder 1 - Pattern 0: Chorus \"A\"\n\
+             0.000000\t0.000000\t125.0 BPM (tempo 125, speed 6)\n\
+             7.680000\t7.680000\t250.0 BPM (tempo 125, speed 3)\n"
+        );
+        assert_eq!(
+            MarkerFormat::Cue.write(&markers, "song.mp3"),
+            "FILE \"song.mp3\" MP3\n\
+             \x20 TRACK 01 AUDIO\n\
+             \x20   TITLE \"Order 0 - Pattern 2\"\n\
+             \x20   INDEX 01 00:00:00\n\
+             \x20 TRACK 02 AUDIO\n\
+             \x20   TITLE \"Order 1 - Pattern 0: Chorus 'A'\"\n\
+             \x20   INDEX 01 00:07:51\n"
+        );
+    }
+
+    #[test]
+    fn test_track_markers() {
+        let buffer = FixtureModule::song(2, 3).to_mod();
+        let mut session = RenderSession::new(&buffer, &ExportOptions::default()).unwrap();
+        let markers = track_markers(&mut session, None).unwrap();
+        let orders: Vec<i32> = markers.regions.iter().map(|r| r.order).collect();
+        assert_eq!(orders, vec![0, 1, 2]);
+        assert_eq!(markers.regions[0].start, 0.0);
+        for pair in markers.regions.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+        assert_eq!(markers.tempo_changes.len(), 1);
+        assert_eq!(markers.tempo_changes[0].speed, 6);
+
+        let limited = track_markers(&mut session, Some(1.0)).unwrap();
+        assert!(limited.regions.last().unwrap().end <= 1.0);
+    }
+}
@@ -0,0 +1,182 @@
+//! Arbitrary combinations of channels and instruments to render together.
+
+use openmpt::index::{ChannelIndex, InstrumentIndex};
+use std::collections::BTreeSet;
+
+/// Notes played on any of `channels` with any of `instruments`, `None`
+/// standing for all of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct MaskTerm {
+    channels: Option<BTreeSet<ChannelIndex>>,
+    instruments: Option<BTreeSet<InstrumentIndex>>,
+}
+
+/// What to leave audible in a render, such as "channels 1-2 plus instrument 9".
+///
+/// A mask is a union of selections. Instruments stand for samples in modules
+/// without instruments, as elsewhere.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MuteMask {
+    terms: Vec<MaskTerm>,
+}
+
+/// Channels and instruments left audible in one render pass of a [`MuteMask`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MutePass {
+    pub channels: BTreeSet<ChannelIndex>,
+    pub instruments: BTreeSet<InstrumentIndex>,
+}
+
+impl MuteMask {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add everything played on `channels`.
+    pub fn channels(mut self, channels: impl IntoIterator<Item = ChannelIndex>) -> Self {
+        self.terms.push(MaskTerm {
+            channels: Some(channels.into_iter().collect()),
+            instruments: None,
+        });
+        self
+    }
+
+    /// Add everything played with `instruments`.
+    pub fn instruments(mut self, instruments: impl IntoIterator<Item = InstrumentIndex>) -> Self {
+        self.terms.push(MaskTerm {
+            channels: None,
+            instruments: Some(instruments.into_iter().collect()),
+        });
+        self
+    }
+
+    /// Add what `instruments` play on `channels`.
+    pub fn channels_with_instruments(
+        mut self,
+        channels: impl IntoIterator<Item = ChannelIndex>,
+        instruments: impl IntoIterator<Item = InstrumentIndex>,
+    ) -> Self {
+        self.terms.push(MaskTerm {
+            channels: Some(channels.into_iter().collect()),
+            instruments: Some(instruments.into_iter().collect()),
+        });
+        self
+    }
+
+    /// Split the mask into render passes for a module with the given number
+    /// of channels and instruments.
+    ///
+    /// libopenmpt only plays a note if both its channel and its instrument are
+    /// unmuted, so a pass can leave a set of channels with a set of instruments
+    /// audible. The passes don't overlap, so rendering each of them and summing
+    /// the audio plays every selected note once.
+    pub fn passes(&self, num_channels: i32, num_instruments: i32) -> Vec<MutePass> {
+        let mut passes: Vec<MutePass> = Vec::new();
+        for term in &self.terms {
+            let term = MutePass {
+                channels: match &term.channels {
+                    Some(channels) => channels
+                        .iter()
+                        .copied()
+                        .filter(|c| c.get() < num_channels)
+                        .collect(),
+                    None => ChannelIndex::all(num_channels).collect(),
+                },
+                instruments: match &term.instruments {
+                    Some(instruments) => instruments
+                        .iter()
+                        .copied()
+                        .filter(|i| i.get() < num_instruments)
+                        .collect(),
+                    None => InstrumentIndex::all(num_instruments).collect(),
+                },
+            };
+
+            let mut pieces = vec![term];
+            for pass in &passes {
+                pieces = pieces
+                    .into_iter()
+                    .flat_map(|piece| piece.without(pass))
+                    .collect();
+            }
+            passes.extend(pieces.into_iter().filter(|piece| !piece.is_empty()));
+        }
+        passes
+    }
+}
+
+impl MutePass {
+    fn is_empty(&self) -> bool {
+        self.channels.is_empty() || self.instruments.is_empty()
+    }
+
+    /// The notes of `self` that `other` doesn't play, as passes.
+    fn without(self, other: &MutePass) -> Vec<MutePass> {
+        let shared_channels: BTreeSet<_> = self
+            .channels
+            .intersection(&other.channels)
+            .copied()
+            .collect();
+        if shared_channels.is_empty() || self.instruments.is_disjoint(&other.instruments) {
+            return vec![self];
+        }
+
+        [
+            MutePass {
+                channels: self
+                    .channels
+                    .difference(&shared_channels)
+                    .copied()
+                    .collect(),
+                instruments: self.instruments.clone(),
+            },
+            MutePass {
+                channels: shared_channels,
+                instruments: self
+                    .instruments
+                    .difference(&other.instruments)
+                    .copied()
+                    .collect(),
+            },
+        ]
+        .into_iter()
+        .filter(|piece| !piece.is_empty())
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pass(channels: &[i32], instruments: &[i32]) -> MutePass {
+        MutePass {
+            channels: channels.iter().map(|&c| ChannelIndex::new(c)).collect(),
+            instruments: instruments
+                .iter()
+                .map(|&i| InstrumentIndex::new(i))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_passes_do_not_overlap() {
+        // Channels 1-2 plus instrument 3, in a module with 4 channels and 3 instruments
+        let mask = MuteMask::new()
+            .channels([ChannelIndex::new(0), ChannelIndex::new(1)])
+            .instruments([InstrumentIndex::new(2)]);
+        assert_eq!(
+            mask.passes(4, 3),
+            vec![pass(&[0, 1], &[0, 1, 2]), pass(&[2, 3], &[2])]
+        );
+
+        // Selections already covered add nothing, and indices past the end are ignored
+        let mask = MuteMask::new()
+            .instruments([InstrumentIndex::new(1)])
+            .channels_with_instruments([ChannelIndex::new(0)], [InstrumentIndex::new(1)])
+            .instruments([InstrumentIndex::new(9)]);
+        assert_eq!(mask.passes(2, 3), vec![pass(&[0, 1], &[1])]);
+
+        assert!(MuteMask::new().passes(4, 3).is_empty());
+    }
+}
@@ -0,0 +1,297 @@
+//! Channel volume and panning curves applied during a render, as read from
+//! the timeline given to `--mix-automation`.
+//!
+//! A timeline is a CSV file of points, one per line, which each curve passes
+//! through in straight lines:
+//!
+//! ```text
+//! # time, channel, volume or pan, value
+//! 0:00, 3, volume, 1
+//! 0:04, 3, volume, -12dB
+//! 1:00, 2, pan, -1
+//! 1:10, 2, pan, 0.5
+//! ```
+//!
+//! or a JSON file with a list of the same points, such as
+//! `[{"seconds": 60, "channel": 2, "param": "pan", "value": -1}]`.
+//!
+//! Before its first point a curve holds the value of that point, and after
+//! its last point the value of that one. Times count from the start of the
+//! render, like those of [`crate::automation`].
+
+use anyhow::{anyhow, Result};
+use openmpt::index::ChannelIndex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::automation::{channel_number, parse_channel};
+use crate::time::parse_seconds;
+
+/// What a curve sets on its channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MixParam {
+    /// Channel volume, from 0.0 (silent) to 1.0 (full, where most songs keep
+    /// their channels)
+    Volume,
+    /// Panning, from -1.0 (left) to 1.0 (right)
+    Pan,
+}
+
+impl MixParam {
+    fn range(self) -> (f64, f64) {
+        match self {
+            MixParam::Volume => (0.0, 1.0),
+            MixParam::Pan => (-1.0, 1.0),
+        }
+    }
+}
+
+/// A point of a curve.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MixPoint {
+    /// Seconds from the start of the render
+    pub seconds: f64,
+    /// Written as the channel number, from 1
+    #[serde(with = "channel_number")]
+    pub channel: ChannelIndex,
+    pub param: MixParam,
+    pub value: f64,
+}
+
+/// The volume or panning of a channel over time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MixCurve {
+    pub channel: ChannelIndex,
+    pub param: MixParam,
+    /// Times in seconds and values, in order of time
+    points: Vec<(f64, f64)>,
+}
+
+impl MixCurve {
+    /// Value of the curve `seconds` into the render.
+    pub fn value_at(&self, seconds: f64) -> f64 {
+        let next = self.points.partition_point(|&(time, _)| time <= seconds);
+        match (self.points.get(next.wrapping_sub(1)), self.points.get(next)) {
+            (Some(&(t0, v0)), Some(&(t1, v1))) => v0 + (v1 - v0) * (seconds - t0) / (t1 - t0),
+            (Some(&(_, value)), None) | (None, Some(&(_, value))) => value,
+            (None, None) => 1.0,
+        }
+    }
+
+    /// Time of the first point after `seconds`.
+    fn next_point(&self, seconds: f64) -> Option<f64> {
+        let next = self.points.partition_point(|&(time, _)| time <= seconds);
+        self.points.get(next).map(|&(time, _)| time)
+    }
+}
+
+/// Volume and panning curves of channels.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MixAutomation {
+    curves: Vec<MixCurve>,
+}
+
+impl MixAutomation {
+    /// Read the timeline at `path`, as JSON if its extension is `.json` and
+    /// as CSV otherwise.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?;
+        let is_json = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+        let automation = if is_json {
+            serde_json::from_str(&text)
+                .map_err(anyhow::Error::from)
+                .and_then(Self::from_points)
+        } else {
+            Self::parse_csv(&text)
+        };
+        automation.map_err(|e| anyhow!("Invalid automation file {}: {}", path.display(), e))
+    }
+
+    /// Parse lines of `time, channel, volume|pan, value`. Volumes can be given
+    /// in dB, such as `-6dB`. Blank lines, lines starting with `#` and a
+    /// `time,channel,param,value` header are skipped.
+    pub fn parse_csv(text: &str) -> Result<Self> {
+        let mut points = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("time") {
+                continue;
+            }
+            let point = parse_point(line).map_err(|e| anyhow!("line {}: {}", number + 1, e))?;
+            points.push(point);
+        }
+        Self::from_points(points)
+    }
+
+    /// Gather points into a curve per channel and parameter.
+    pub fn from_points(mut points: Vec<MixPoint>) -> Result<Self> {
+        for point in &points {
+            let (min, max) = point.param.range();
+            if !point.seconds.is_finite() || point.seconds < 0.0 {
+                return Err(anyhow!("invalid time {}", point.seconds));
+            }
+            if !(min..=max).contains(&point.value) {
+                return Err(anyhow!(
+                    "{:?} {} of channel {} is outside {} to {}",
+                    point.param,
+                    point.value,
+                    point.channel,
+                    min,
+                    max
+                ));
+            }
+        }
+
+        // Points at the same time keep the order of the file, so a curve can jump
+        points.sort_by(|a, b| {
+            (a.channel, a.param)
+                .cmp(&(b.channel, b.param))
+                .then(a.seconds.total_cmp(&b.seconds))
+        });
+        let mut curves: Vec<MixCurve> = Vec::new();
+        for point in points {
+            match curves.last_mut() {
+                Some(curve) if (curve.channel, curve.param) == (point.channel, point.param) => {
+                    curve.points.push((point.seconds, point.value))
+                }
+                _ => curves.push(MixCurve {
+                    channel: point.channel,
+                    param: point.param,
+                    points: vec![(point.seconds, point.value)],
+                }),
+            }
+        }
+        Ok(MixAutomation { curves })
+    }
+
+    pub fn curves(&self) -> &[MixCurve] {
+        &self.curves
+    }
+
+    /// Every point of every curve, as they can be written back to JSON.
+    pub fn points(&self) -> Vec<MixPoint> {
+        self.curves
+            .iter()
+            .flat_map(|curve| {
+                curve.points.iter().map(|&(seconds, value)| MixPoint {
+                    seconds,
+                    channel: curve.channel,
+                    param: curve.param,
+                    value,
+                })
+            })
+            .collect()
+    }
+
+    /// Time of the first point of any curve after `seconds`.
+    pub fn next_point(&self, seconds: f64) -> Option<f64> {
+        self.curves
+            .iter()
+            .filter_map(|curve| curve.next_point(seconds))
+            .reduce(f64::min)
+    }
+
+    /// Fail if a curve is for a channel past the `num_channels` of the module.
+    pub fn check_channels(&self, num_channels: i32) -> Result<()> {
+        match self.curves.iter().find(|c| c.channel.get() >= num_channels) {
+            Some(curve) => Err(anyhow!(
+                "Channel {} is automated, but the module only has {} channels",
+                curve.channel,
+                num_channels
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_point(line: &str) -> Result<MixPoint> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    let [time, channel, param, value] = fields[..] else {
+        return Err(anyhow!(
+            "expected 'time, channel, volume or pan, value', got '{}'",
+            line
+        ));
+    };
+    let param = match param {
+        "volume" | "vol" => MixParam::Volume,
+        "pan" | "panning" => MixParam::Pan,
+        _ => return Err(anyhow!("expected volume or pan, got '{}'", param)),
+    };
+    let value = match value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+    {
+        Some(db) if param == MixParam::Volume => db
+            .trim()
+            .parse::<f64>()
+            .map(|db| 10f64.powf(db / 20.0))
+            .ok(),
+        _ => value.parse().ok(),
+    };
+    Ok(MixPoint {
+        seconds: parse_seconds(time)?,
+        channel: parse_channel(channel)?,
+        param,
+        value: value.ok_or_else(|| anyhow!("invalid value in '{}'", line))?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mix_automation() {
+        let automation = MixAutomation::parse_csv(
+            "time,channel,param,value\n\
+             # Fade channel 3 out\n\
+             0:00, 3, volume, 1\n\
+             0:10, 3, volume, 0\n\
+             \n\
+             1:00, 2, pan, -1\n\
+             0:30, 3, volume, -6dB\n",
+        )
+        .unwrap();
+        assert_eq!(automation.curves().len(), 2);
+        let pan = &automation.curves()[0];
+        assert_eq!((pan.channel.number(), pan.param), (2, MixParam::Pan));
+        assert_eq!(pan.value_at(0.0), -1.0);
+
+        let volume = &automation.curves()[1];
+        assert_eq!(volume.value_at(5.0), 0.5);
+        assert_eq!(volume.value_at(10.0), 0.0);
+        assert!((volume.value_at(60.0) - 0.501).abs() < 0.001);
+
+        assert_eq!(automation.next_point(0.0), Some(10.0));
+        assert_eq!(automation.next_point(30.0), Some(60.0));
+        assert_eq!(automation.next_point(60.0), None);
+
+        assert!(automation.check_channels(3).is_ok());
+        assert!(automation.check_channels(2).is_err());
+        assert_eq!(automation.points().len(), 4);
+
+        let json = serde_json::to_string(&automation.points()).unwrap();
+        let points: Vec<MixPoint> = serde_json::from_str(&json).unwrap();
+        assert_eq!(MixAutomation::from_points(points).unwrap(), automation);
+        assert!(serde_json::from_str::<Vec<MixPoint>>(
+            r#"[{"seconds": 0, "channel": 0, "param": "pan", "value": 0}]"#
+        )
+        .is_err());
+
+        let error = MixAutomation::parse_csv("0:00, 1, volume\n").unwrap_err();
+        assert!(
+            error.to_string().starts_with("line 1: expected"),
+            "{}",
+            error
+        );
+        assert!(MixAutomation::parse_csv("0:00, 0, volume, 1").is_err());
+        assert!(MixAutomation::parse_csv("0:00, 1, volume, 2").is_err());
+        assert!(MixAutomation::parse_csv("0:00, 1, pan, -6dB").is_err());
+        assert!(MixAutomation::parse_csv("0:00, 1, reverb, 1").is_err());
+    }
+}
@@ -0,0 +1,213 @@
+//! Layout of the file names of stems, as given to `--name-template`, such as
+//! `{module}_{type}_{index:03}_{name}`.
+
+use anyhow::{anyhow, Result};
+
+use crate::container::file_name_from_title;
+
+/// A field of a template, replaced by a property of each stem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    /// Base name of the output, from the module file name or title
+    Module,
+    /// Label of the kind of stem, from [`crate::StemLabels`]
+    Type,
+    /// Number of the instrument or sample, from 1, padded with zeros to
+    /// `width` digits
+    Index { width: usize },
+    /// Name of the instrument or sample in the module
+    Name,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// What the fields of a [`NameTemplate`] are filled with for one stem.
+#[derive(Debug, Clone, Copy)]
+pub struct StemNameFields<'a> {
+    /// Base name of the output
+    pub module: &'a str,
+    /// Label of the kind of stem, empty to leave it out
+    pub label: &'a str,
+    /// Number of the instrument or sample, from 1
+    pub number: i32,
+    /// Name of the instrument or sample in the module, as it is there
+    pub name: &'a str,
+}
+
+/// The file name of a stem, without its extension, made of text and the
+/// fields `{module}`, `{type}`, `{index}` (or `{index:03}` to pad it with
+/// zeros) and `{name}`.
+///
+/// A field that comes out empty, such as the name of an unnamed instrument,
+/// takes the `_`, `-`, `.` and spaces joining it to the rest of the name
+/// with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+impl Default for NameTemplate {
+    /// `{module}_{type}_{index:03}`, as in `song_instrument_003`.
+    fn default() -> Self {
+        NameTemplate {
+            parts: vec![
+                Part::Field(Field::Module),
+                Part::Text("_".to_string()),
+                Part::Field(Field::Type),
+                Part::Text("_".to_string()),
+                Part::Field(Field::Index { width: 3 }),
+            ],
+        }
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    matches!(c, '_' | '-' | '.' | ' ')
+}
+
+impl NameTemplate {
+    /// The file name of the stem described by `stem`, without extension.
+    /// Names from the module are stripped of characters that don't belong
+    /// in file names.
+    pub fn fill(&self, stem: &StemNameFields) -> String {
+        let mut file_name = String::new();
+        let mut drop_separator = false;
+        for part in &self.parts {
+            let value = match part {
+                Part::Text(text) if drop_separator => {
+                    text.trim_start_matches(is_separator).to_string()
+                }
+                Part::Text(text) => text.clone(),
+                Part::Field(Field::Module) => stem.module.to_string(),
+                Part::Field(Field::Type) => stem.label.to_string(),
+                Part::Field(Field::Index { width }) => {
+                    format!("{:0width$}", stem.number, width = width)
+                }
+                Part::Field(Field::Name) => file_name_from_title(stem.name).unwrap_or_default(),
+            };
+            drop_separator = false;
+            if value.is_empty() && matches!(part, Part::Field(_)) {
+                if file_name.ends_with(is_separator) {
+                    file_name.truncate(file_name.trim_end_matches(is_separator).len());
+                } else {
+                    drop_separator = true;
+                }
+            }
+            file_name.push_str(&value);
+        }
+        file_name
+    }
+}
+
+impl std::str::FromStr for NameTemplate {
+    type Err = anyhow::Error;
+
+    /// Parse a template such as `{module}_{type}_{index:03}_{name}`. It has
+    /// to hold `{index}`, so that every stem gets a file of its own.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                parts.push(Part::Text(rest[..open].to_string()));
+            }
+            let close = rest[open..]
+                .find('}')
+                .ok_or_else(|| anyhow!("Unclosed '{{' in name template '{}'", s))?
+                + open;
+            parts.push(Part::Field(parse_field(&rest[open + 1..close])?));
+            rest = &rest[close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+
+        for part in &parts {
+            if let Part::Text(text) = part {
+                if text.contains(['/', '\\', '}']) {
+                    return Err(anyhow!(
+                        "Invalid name template '{}': '/', '\\' and unopened '}}' are not allowed",
+                        s
+                    ));
+                }
+            }
+        }
+        if !parts
+            .iter()
+            .any(|part| matches!(part, Part::Field(Field::Index { .. })))
+        {
+            return Err(anyhow!(
+                "Name template '{}' needs {{index}}, so that every stem gets a file of its own",
+                s
+            ));
+        }
+        Ok(NameTemplate { parts })
+    }
+}
+
+fn parse_field(field: &str) -> Result<Field> {
+    match field.split_once(':') {
+        None => match field {
+            "module" => Ok(Field::Module),
+            "type" => Ok(Field::Type),
+            "index" => Ok(Field::Index { width: 0 }),
+            "name" => Ok(Field::Name),
+            _ => Err(anyhow!(
+                "Unknown field {{{}}} in name template: expected module, type, index or name",
+                field
+            )),
+        },
+        Some(("index", width)) => match width.parse() {
+            Ok(width) if width <= 9 => Ok(Field::Index { width }),
+            _ => Err(anyhow!(
+                "Invalid width in {{{}}}: expected a number of digits such as {{index:03}}",
+                field
+            )),
+        },
+        Some(_) => Err(anyhow!(
+            "Only {{index}} takes a width, as in {{index:03}}, not {{{}}}",
+            field
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_template() {
+        let stem = StemNameFields {
+            module: "song",
+            label: "instrument",
+            number: 7,
+            name: "Bass / Slap",
+        };
+        assert_eq!(NameTemplate::default().fill(&stem), "song_instrument_007");
+        let template: NameTemplate = "{module}_{type}_{index:03}_{name}".parse().unwrap();
+        assert_eq!(template.fill(&stem), "song_instrument_007_Bass_Slap");
+        let template: NameTemplate = "{name} - {index}".parse().unwrap();
+        assert_eq!(template.fill(&stem), "Bass_Slap - 7");
+
+        // Empty fields leave out their separator
+        let unnamed = StemNameFields {
+            label: "",
+            name: "  ",
+            ..stem
+        };
+        assert_eq!(NameTemplate::default().fill(&unnamed), "song_007");
+        assert_eq!(template.fill(&unnamed), "7");
+        let template: NameTemplate = "{name}_{index:2}".parse().unwrap();
+        assert_eq!(template.fill(&unnamed), "07");
+
+        assert!("{module}_{name}".parse::<NameTemplate>().is_err());
+        assert!("{module}_{kind}_{index}".parse::<NameTemplate>().is_err());
+        assert!("{module}/{index}".parse::<NameTemplate>().is_err());
+        assert!("{name:3}_{index}".parse::<NameTemplate>().is_err());
+        assert!("{index".parse::<NameTemplate>().is_err());
+    }
+}
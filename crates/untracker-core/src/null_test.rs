@@ -0,0 +1,136 @@
+//! Null test of an extraction: the stems summed together are subtracted from
+//! the full mix, and what is left shows how much of the song the stems miss.
+//!
+//! Stems are the song rendered with everything else muted, so in theory they
+//! add up to the full mix exactly. Instruments that bleed into each other, or
+//! effects that depend on what else is playing, leave a residual.
+
+use anyhow::Result;
+use indicatif::ProgressBar;
+use openmpt::index::InstrumentIndex;
+use serde::Serialize;
+use std::sync::Mutex;
+
+use crate::audio::dsp;
+use crate::audio::{ExportOptions, NormalizeMode};
+use crate::session::RenderSession;
+use crate::{parallel, render_streaming, render_to_memory, stem_session};
+
+/// Level of the residual at or above which stems don't add up to the mix, in
+/// dB relative to the mix: well above the rounding noise of float renders.
+pub const RESIDUAL_WARNING_DB: f64 = -60.0;
+
+/// Levels of the full mix and of what is left of it once the stems are subtracted.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct NullTest {
+    /// Peak of the full mix, as a fraction of full scale
+    pub mix_peak: f64,
+    /// RMS level of the full mix, as a fraction of full scale
+    pub mix_rms: f64,
+    /// Peak of the residual, as a fraction of full scale
+    pub residual_peak: f64,
+    /// RMS level of the residual, as a fraction of full scale
+    pub residual_rms: f64,
+}
+
+impl NullTest {
+    /// Peak of the residual in dBFS, or negative infinity if the stems cancel the mix out.
+    pub fn residual_peak_dbfs(&self) -> f64 {
+        20.0 * self.residual_peak.log10()
+    }
+
+    /// RMS level of the residual relative to the RMS level of the mix, in dB.
+    pub fn residual_db(&self) -> f64 {
+        20.0 * (self.residual_rms / self.mix_rms).log10()
+    }
+
+    /// Whether the stems fall audibly short of the full mix.
+    pub fn has_residual(&self) -> bool {
+        self.residual_rms > 0.0 && self.residual_db() >= RESIDUAL_WARNING_DB
+    }
+}
+
+/// Render the full mix and the stems made of `stems`, each a set of
+/// instruments (or samples) played together, and compare their sum to the mix.
+///
+/// Renders are in floating point, without gain or normalization, so only
+/// what the muting itself changes is left. Stems are rendered by up to `jobs`
+/// threads.
+pub fn null_test(
+    buffer: &[u8],
+    stems: &[Vec<InstrumentIndex>],
+    is_instrument: bool,
+    options: &ExportOptions,
+    jobs: usize,
+    progress_bar: Option<&ProgressBar>,
+) -> Result<NullTest> {
+    let options = ExportOptions {
+        gain_db: 0.0,
+        normalize: NormalizeMode::Off,
+        pad_to_frames: None,
+        ..options.clone()
+    };
+
+    let mut session = RenderSession::new(buffer, &options)?;
+    let mix: Vec<f32> = render_to_memory(&mut session, "null test mix", None)?;
+    let mix_peak = dsp::peak(&mix);
+    let mix_rms = dsp::rms(&mix);
+
+    // Stems are subtracted from the mix as they are rendered
+    let residual = Mutex::new(mix);
+    let rendered = parallel::run(stems, jobs, progress_bar, |members, _| {
+        let mut session = stem_session(buffer, members, is_instrument, &options)?;
+        let mut position = 0;
+        render_streaming(&mut session, "null test", None, |chunk: &[f32]| {
+            let mut residual = residual.lock().unwrap();
+            let end = position + chunk.len();
+            if residual.len() < end {
+                residual.resize(end, 0.0);
+            }
+            for (r, s) in residual[position..end].iter_mut().zip(chunk) {
+                *r -= s;
+            }
+            position = end;
+            Ok(())
+        })
+    })?;
+    for result in rendered {
+        result?;
+    }
+
+    let residual = residual.into_inner().unwrap();
+    Ok(NullTest {
+        mix_peak,
+        mix_rms,
+        residual_peak: dsp::peak(&residual),
+        residual_rms: dsp::rms(&residual),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stems_null_the_mix() {
+        let buffer = include_bytes!("../../../tests/modules/cndmcrrp.mod");
+        let options = ExportOptions {
+            sample_rate: 22050,
+            max_duration: Some(5.0),
+            ..ExportOptions::default()
+        };
+        let session = RenderSession::new(buffer, &options).unwrap();
+        let num_samples = session.module_ext().get_module().get_num_samples();
+
+        let stems: Vec<Vec<InstrumentIndex>> =
+            InstrumentIndex::all(num_samples).map(|i| vec![i]).collect();
+        let test = null_test(buffer, &stems, false, &options, 2, None).unwrap();
+        assert!(test.mix_rms > 0.0);
+        assert!(!test.has_residual(), "{:?}", test);
+
+        // Without stems, the whole mix is left
+        let test = null_test(buffer, &[], false, &options, 1, None).unwrap();
+        assert_eq!(test.residual_rms, test.mix_rms);
+        assert!(test.has_residual());
+    }
+}
@@ -0,0 +1,242 @@
+//! Rendering on a bounded pool of worker threads.
+
+use anyhow::{anyhow, Result};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use crate::progress::ProgressSink;
+
+/// Run `task` on every item, on up to `jobs` worker threads, and return the
+/// outcomes in item order.
+///
+/// With a `progress` bar, every finished item advances it, and each worker
+/// gets a bar of its own below it which is handed to `task` as the sink of
+/// what the worker renders. With a single job, items are processed in order
+/// on the calling thread and `task` gets `progress` itself.
+pub fn run<I, T>(
+    items: &[I],
+    jobs: usize,
+    progress: Option<&ProgressBar>,
+    task: impl Fn(&I, Option<&dyn ProgressSink>) -> T + Sync,
+) -> Result<Vec<T>>
+where
+    I: Sync,
+    T: Send,
+{
+    if jobs <= 1 {
+        return Ok(items
+            .iter()
+            .map(|item| {
+                let result = task(item, progress.map(|pb| pb as &dyn ProgressSink));
+                if let Some(pb) = progress {
+                    pb.inc(1);
+                }
+                result
+            })
+            .collect());
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .map_err(|e| anyhow!("Failed to start {} worker threads: {}", jobs, e))?;
+
+    let Some(progress) = progress else {
+        return Ok(pool.install(|| items.par_iter().map(|item| task(item, None)).collect()));
+    };
+
+    let multi = MultiProgress::new();
+    let overall = multi.add(progress.clone());
+    let style = ProgressStyle::default_spinner()
+        .template("  {spinner:.green} worker {prefix}: {msg}")
+        .unwrap();
+    let workers: Vec<ProgressBar> = (0..jobs.min(items.len()))
+        .map(|i| {
+            let pb = multi.add(ProgressBar::new_spinner());
+            pb.set_style(style.clone());
+            pb.set_prefix((i + 1).to_string());
+            pb.set_message("waiting");
+            pb.enable_steady_tick(Duration::from_millis(100));
+            pb
+        })
+        .collect();
+
+    let results = pool.install(|| {
+        items
+            .par_iter()
+            .map(|item| {
+                let worker = rayon::current_thread_index().and_then(|i| workers.get(i));
+                let result = task(item, Some(worker.unwrap_or(&overall)));
+                overall.inc(1);
+                if let Some(worker) = worker {
+                    worker.set_message("idle");
+                }
+                result
+            })
+            .collect()
+    });
+
+    for worker in workers {
+        worker.finish_and_clear();
+    }
+    Ok(results)
+}
+
+/// Like [`run`], for tasks that can fail. With `fail_fast`, items that
+/// haven't started once one of them failed are not run and come out as
+/// `None`; those already running on other workers are finished.
+pub fn run_fallible<I, T>(
+    items: &[I],
+    jobs: usize,
+    progress: Option<&ProgressBar>,
+    fail_fast: bool,
+    task: impl Fn(&I, Option<&dyn ProgressSink>) -> Result<T> + Sync,
+) -> Result<Vec<Option<Result<T>>>>
+where
+    I: Sync,
+    T: Send,
+{
+    let failed = AtomicBool::new(false);
+    run(items, jobs, progress, |item, pb| {
+        if fail_fast && failed.load(Ordering::SeqCst) {
+            return None;
+        }
+        let result = task(item, pb);
+        if result.is_err() {
+            failed.store(true, Ordering::SeqCst);
+        }
+        Some(result)
+    })
+}
+
+/// Memory that renders running at the same time may take together, so that
+/// huge modules don't run out of memory on many workers while small ones
+/// still keep every worker busy.
+///
+/// Workers reserve the memory a render needs before starting it, and wait
+/// while the budget is used up by the others.
+#[derive(Debug)]
+pub struct MemoryBudget {
+    bytes: u64,
+    used: Mutex<u64>,
+    released: Condvar,
+}
+
+impl MemoryBudget {
+    pub fn new(bytes: u64) -> Self {
+        MemoryBudget {
+            bytes,
+            used: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Wait until `bytes` fit in what is left of the budget, and hold them
+    /// until the reservation is dropped. A reservation larger than the whole
+    /// budget is made once nothing else is reserved, so it runs on its own.
+    pub fn reserve(&self, bytes: u64) -> Reservation<'_> {
+        let mut used = self.used.lock().unwrap();
+        while *used > 0 && *used + bytes > self.bytes {
+            used = self.released.wait(used).unwrap();
+        }
+        *used += bytes;
+        Reservation {
+            budget: self,
+            bytes,
+        }
+    }
+}
+
+/// Memory held from a [`MemoryBudget`] until dropped.
+#[derive(Debug)]
+pub struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        *self.budget.used.lock().unwrap() -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn test_run_bounds_workers_and_keeps_order() {
+        let items: Vec<usize> = (0..32).collect();
+        let running = AtomicUsize::new(0);
+        let most_running = AtomicUsize::new(0);
+
+        let results = run(&items, 3, None, |&item, _| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most_running.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(2));
+            running.fetch_sub(1, Ordering::SeqCst);
+            item * 2
+        })
+        .unwrap();
+
+        assert_eq!(results, items.iter().map(|i| i * 2).collect::<Vec<_>>());
+        assert!(most_running.load(Ordering::SeqCst) <= 3);
+
+        let pb = ProgressBar::hidden();
+        run(&items, 1, Some(&pb), |_, _| ()).unwrap();
+        assert_eq!(pb.position(), 32);
+    }
+
+    #[test]
+    fn test_run_fallible() {
+        let items: Vec<usize> = (0..8).collect();
+        let task = |&item: &usize, _: Option<&dyn ProgressSink>| match item {
+            2 => Err(anyhow!("item 2 failed")),
+            _ => Ok(item),
+        };
+
+        let results = run_fallible(&items, 1, None, false, task).unwrap();
+        assert_eq!(results.iter().filter(|r| r.is_some()).count(), 8);
+        assert!(results[2].as_ref().unwrap().is_err());
+
+        // Sequentially, nothing runs after the failure
+        let results = run_fallible(&items, 1, None, true, task).unwrap();
+        assert_eq!(results[1].as_ref().unwrap().as_ref().unwrap(), &1);
+        assert!(results[2].as_ref().unwrap().is_err());
+        assert!(results[3..].iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let items: Vec<u64> = vec![40, 40, 40, 40, 150, 10, 10, 10];
+        let budget = MemoryBudget::new(100);
+        let running = AtomicUsize::new(0);
+        let most_running = AtomicUsize::new(0);
+        let most_used = AtomicUsize::new(0);
+
+        run(&items, 4, None, |&bytes, _| {
+            let _reservation = budget.reserve(bytes);
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most_running.fetch_max(now, Ordering::SeqCst);
+            most_used.fetch_max(*budget.used.lock().unwrap() as usize, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(5));
+            running.fetch_sub(1, Ordering::SeqCst);
+        })
+        .unwrap();
+
+        // Items within the budget never take more than it together, and the
+        // one over it runs alone, or more than 150 would have been in use
+        assert!(most_running.load(Ordering::SeqCst) <= 4);
+        assert_eq!(most_used.load(Ordering::SeqCst), 150);
+        assert_eq!(*budget.used.lock().unwrap(), 0);
+    }
+}
@@ -0,0 +1,257 @@
+//! Playing a module through the sound card, for `untracker play`, to find
+//! out which instrument is which before extracting them.
+//!
+//! Channels are muted while the module plays by typing commands, as with
+//! `--interactive`: see [`ChannelMutes::command`]. The sound card output
+//! itself, [`AudioOutput`], needs the `playback` feature, which builds cpal
+//! and, on Linux, needs the ALSA development files.
+
+use anyhow::{anyhow, Result};
+use openmpt::ext::ModuleExt;
+use openmpt::index::ChannelIndex;
+#[cfg(feature = "playback")]
+use std::collections::VecDeque;
+#[cfg(feature = "playback")]
+use std::sync::{Arc, Mutex};
+
+use crate::StemSelection;
+
+/// What the player does after a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerAction {
+    /// Carry on playing, with the mutes the command set
+    Continue,
+    /// Pause, or resume if paused
+    TogglePause,
+    Quit,
+}
+
+/// Which channels of the playing module are muted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelMutes {
+    muted: Vec<bool>,
+}
+
+impl ChannelMutes {
+    pub fn new(num_channels: i32) -> Self {
+        ChannelMutes {
+            muted: vec![false; num_channels.max(0) as usize],
+        }
+    }
+
+    pub fn is_muted(&self, channel: ChannelIndex) -> bool {
+        self.muted
+            .get(channel.get() as usize)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Apply a command typed by the user: channel numbers or ranges to mute
+    /// or unmute (e.g. `2,5-7`), `s` and channels to hear only those (e.g.
+    /// `s 3`), `a` to unmute all, `p` to pause or resume, or `q` to quit.
+    pub fn command(&mut self, line: &str) -> Result<PlayerAction> {
+        match line.trim().to_lowercase().as_str() {
+            "" => {}
+            "q" => return Ok(PlayerAction::Quit),
+            "p" => return Ok(PlayerAction::TogglePause),
+            "a" => self.muted.iter_mut().for_each(|muted| *muted = false),
+            command => {
+                let (solo, channels) = match command.strip_prefix('s') {
+                    Some(channels) => (true, channels),
+                    None => (false, command),
+                };
+                let selection = self.selection(channels)?;
+                for (i, muted) in self.muted.iter_mut().enumerate() {
+                    let selected = selection.contains(i as i32 + 1);
+                    if solo {
+                        *muted = !selected;
+                    } else if selected {
+                        *muted = !*muted;
+                    }
+                }
+            }
+        }
+        Ok(PlayerAction::Continue)
+    }
+
+    fn selection(&self, channels: &str) -> Result<StemSelection> {
+        let selection: StemSelection = channels.parse().map_err(|_| {
+            anyhow!(
+                "Unknown command '{}': type channels to mute (e.g. 2,5-7), s and channels to solo, a, p or q",
+                channels.trim()
+            )
+        })?;
+        let count = self.muted.len() as i32;
+        selection
+            .indices(count)
+            .map_err(|_| anyhow!("The module only has {} channels", count))?;
+        Ok(selection)
+    }
+
+    /// Mute the channels of `module_ext` as set.
+    pub fn apply(&self, module_ext: &ModuleExt) -> Result<()> {
+        let interactive = module_ext
+            .get_interactive_interface()
+            .ok_or_else(|| anyhow!("This libopenmpt can't mute channels while playing"))?;
+        for (i, &muted) in self.muted.iter().enumerate() {
+            interactive.set_channel_mute_status(module_ext, ChannelIndex::new(i as i32), muted);
+        }
+        Ok(())
+    }
+
+    /// One line showing every channel by number, with muted ones as `-`.
+    pub fn to_text(&self) -> String {
+        let channels: Vec<String> = self
+            .muted
+            .iter()
+            .enumerate()
+            .map(|(i, &muted)| {
+                if muted {
+                    "-".repeat((i + 1).to_string().len())
+                } else {
+                    (i + 1).to_string()
+                }
+            })
+            .collect();
+        format!("Channels: {}", channels.join(" "))
+    }
+}
+
+/// Stereo audio played on the default output device of the sound card, as
+/// it is queued.
+#[cfg(feature = "playback")]
+pub struct AudioOutput {
+    stream: cpal::Stream,
+    /// Interleaved stereo samples waiting to be played
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    sample_rate: u32,
+}
+
+#[cfg(feature = "playback")]
+impl AudioOutput {
+    pub fn open() -> Result<Self> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or_else(|| anyhow!("No audio output device found"))?;
+        let supported = device.default_output_config()?;
+        let config = supported.config();
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        let stream = match supported.sample_format() {
+            cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config, queue.clone()),
+            cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config, queue.clone()),
+            cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config, queue.clone()),
+            cpal::SampleFormat::I32 => build_stream::<i32>(&device, &config, queue.clone()),
+            format => {
+                return Err(anyhow!(
+                    "The audio output device takes {} samples, which can't be played",
+                    format
+                ))
+            }
+        }?;
+        stream.play()?;
+        log::info!(
+            "Playing on {} at {} Hz",
+            device
+                .name()
+                .unwrap_or_else(|_| "the default device".to_string()),
+            config.sample_rate.0
+        );
+
+        Ok(AudioOutput {
+            stream,
+            queue,
+            sample_rate: config.sample_rate.0,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Number of frames queued and not played yet.
+    pub fn queued_frames(&self) -> usize {
+        self.queue.lock().unwrap().len() / 2
+    }
+
+    /// Queue interleaved stereo samples to be played.
+    pub fn push(&self, stereo: &[f32]) {
+        self.queue.lock().unwrap().extend(stereo);
+    }
+
+    pub fn set_paused(&self, paused: bool) -> Result<()> {
+        use cpal::traits::StreamTrait;
+
+        if paused {
+            self.stream.pause()?;
+        } else {
+            self.stream.play()?;
+        }
+        Ok(())
+    }
+}
+
+/// Stream playing stereo frames from `queue`, on the output channels of the
+/// device: averaged on mono devices, and on the first two of others.
+#[cfg(feature = "playback")]
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+) -> Result<cpal::Stream>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+{
+    use cpal::traits::DeviceTrait;
+
+    let channels = config.channels as usize;
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            let mut queue = queue.lock().unwrap();
+            for frame in data.chunks_mut(channels) {
+                // Silence when the queue runs dry rather than noise
+                let left = queue.pop_front().unwrap_or(0.0);
+                let right = queue.pop_front().unwrap_or(0.0);
+                for (channel, sample) in frame.iter_mut().enumerate() {
+                    *sample = T::from_sample(match (channels, channel) {
+                        (1, _) => (left + right) / 2.0,
+                        (_, 0) => left,
+                        (_, 1) => right,
+                        _ => 0.0,
+                    });
+                }
+            }
+        },
+        |e| log::error!("Audio output failed: {}", e),
+        None,
+    )?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_commands() {
+        let mut mutes = ChannelMutes::new(10);
+        assert_eq!(mutes.command("2,4-5").unwrap(), PlayerAction::Continue);
+        assert!(mutes.is_muted(ChannelIndex::new(1)));
+        assert!(!mutes.is_muted(ChannelIndex::new(2)));
+        assert_eq!(mutes.to_text(), "Channels: 1 - 3 - - 6 7 8 9 10");
+
+        mutes.command("2").unwrap();
+        assert!(!mutes.is_muted(ChannelIndex::new(1)));
+        mutes.command("s 10").unwrap();
+        assert_eq!(mutes.to_text(), "Channels: - - - - - - - - - 10");
+        mutes.command("A").unwrap();
+        assert_eq!(mutes, ChannelMutes::new(10));
+
+        assert_eq!(mutes.command("p").unwrap(), PlayerAction::TogglePause);
+        assert_eq!(mutes.command("q").unwrap(), PlayerAction::Quit);
+        assert!(mutes.command("11").is_err());
+        assert!(mutes.command("mute").is_err());
+    }
+}
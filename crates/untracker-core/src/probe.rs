@@ -0,0 +1,148 @@
+//! Checking whether a file is a module untracker can extract, and what it
+//! holds, for `untracker probe`, so that tools can sort files before handing
+//! them to an extraction.
+
+use openmpt::module::{could_open_probability, CouldOpenEffort, LogCapture, Logger, Module};
+use serde::Serialize;
+use std::fmt::Write;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use crate::container::ModuleContainer;
+use crate::estimate::format_duration;
+use crate::module_metadata;
+
+/// What probing a file found.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModuleProbe {
+    pub file: PathBuf,
+    /// Whether libopenmpt loads the file as a module
+    pub supported: bool,
+    /// libopenmpt's estimate from the file's header, from 0 to 1, that it
+    /// can load the file
+    pub probability: f64,
+    /// Format and packaging of the module, unknown for unsupported files
+    #[serde(flatten)]
+    pub container: ModuleContainer,
+    pub channels: Option<i32>,
+    pub instruments: Option<i32>,
+    pub samples: Option<i32>,
+    /// Length in seconds of the song
+    pub duration: Option<f64>,
+    /// Why the file isn't supported
+    pub error: Option<String>,
+}
+
+impl ModuleProbe {
+    /// Probe `data`, the contents of the file at `path`, unpacked if it is an
+    /// archive.
+    pub fn probe(path: &Path, data: &[u8]) -> Self {
+        let mut probe = ModuleProbe {
+            file: path.to_path_buf(),
+            supported: false,
+            probability: 0.0,
+            container: ModuleContainer::default(),
+            channels: None,
+            instruments: None,
+            samples: None,
+            duration: None,
+            error: None,
+        };
+        match could_open_probability(
+            &mut Cursor::new(data),
+            CouldOpenEffort::VerifyHeader,
+            Logger::None,
+        ) {
+            Ok(probability) => probe.probability = probability,
+            Err(e) => {
+                probe.error = Some(e);
+                return probe;
+            }
+        }
+        // Only 0 is definitive; anything else is worth a full load
+        if probe.probability == 0.0 {
+            probe.error = Some("not a module format libopenmpt knows".to_string());
+            return probe;
+        }
+
+        let capture = LogCapture::new();
+        let Ok(mut module) =
+            Module::create_from_memory(data, Logger::Capture(capture.clone()), &[])
+        else {
+            let messages = capture.take();
+            probe.error = Some(
+                messages
+                    .last()
+                    .cloned()
+                    .unwrap_or_else(|| "libopenmpt could not load the module".to_string()),
+            );
+            return probe;
+        };
+        probe.supported = true;
+        probe.container = ModuleContainer::from_metadata(&module_metadata(&mut module));
+        probe.channels = Some(module.get_num_channels());
+        probe.instruments = Some(module.get_num_instruments());
+        probe.samples = Some(module.get_num_samples());
+        probe.duration = Some(module.get_duration_seconds());
+        probe
+    }
+
+    /// The probe for people: whether the file is supported, then what it holds.
+    pub fn to_text(&self) -> String {
+        let mut text = self.file.display().to_string();
+        if !self.supported {
+            let _ = writeln!(
+                text,
+                ": not a supported module ({})",
+                self.error.as_deref().unwrap_or("unknown error")
+            );
+            return text;
+        }
+        let format = match (&self.container.format_name, &self.container.format) {
+            (Some(name), Some(format)) => format!("{} ({})", name, format),
+            (Some(name), None) => name.clone(),
+            (None, Some(format)) => format.clone(),
+            (None, None) => "unknown format".to_string(),
+        };
+        let _ = writeln!(text, ": {}", format);
+        if let Some(description) = self.container.describe() {
+            let _ = writeln!(text, "  {}", description);
+        }
+        let _ = writeln!(
+            text,
+            "  {} channels, {} instruments, {} samples, {}",
+            self.channels.unwrap_or(0),
+            self.instruments.unwrap_or(0),
+            self.samples.unwrap_or(0),
+            format_duration(self.duration.unwrap_or(0.0))
+        );
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{FixtureModule, FixtureSample};
+
+    #[test]
+    fn test_probe() {
+        let buffer = FixtureModule::new("Probed")
+            .sample(FixtureSample::square("lead", 32))
+            .to_mod();
+        let probe = ModuleProbe::probe(Path::new("probed.mod"), &buffer);
+        assert!(probe.supported, "{:?}", probe.error);
+        assert!(probe.probability > 0.0);
+        assert_eq!(probe.container.format.as_deref(), Some("mod"));
+        assert_eq!((probe.channels, probe.instruments), (Some(4), Some(0)));
+        assert!(probe.to_text().starts_with("probed.mod: "));
+        let json = serde_json::to_value(&probe).unwrap();
+        assert_eq!(json["format"], "mod");
+        assert_eq!(json["supported"], true);
+
+        let probe = ModuleProbe::probe(Path::new("notes.txt"), b"Not a module at all\n");
+        assert!(!probe.supported);
+        assert!(probe.error.is_some());
+        assert!(probe.to_text().contains("not a supported module"));
+    }
+}
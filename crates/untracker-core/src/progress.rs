@@ -0,0 +1,109 @@
+//! Progress of renders, for whatever shows it: an indicatif progress bar on
+//! the command line, or the widgets of a frontend embedding untracker.
+
+use indicatif::ProgressBar;
+use std::path::Path;
+
+/// Something a render reports while it runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderProgress<'a> {
+    /// A render started, such as "instrument 3" or "group drums"
+    Started { label: &'a str },
+    /// `seconds` of the `total_seconds` of audio of a render are done.
+    ///
+    /// Reported whenever another percent is done, and `total_seconds` is an
+    /// estimate: songs can run a little past it.
+    Rendering {
+        label: &'a str,
+        seconds: f64,
+        total_seconds: f64,
+    },
+    /// A render was written to `path`
+    Finished { label: &'a str, path: &'a Path },
+}
+
+impl RenderProgress<'_> {
+    /// Part of the render done, from 0.0 to 1.0: 0 until it renders, and 1 once finished.
+    pub fn fraction(&self) -> f64 {
+        match *self {
+            RenderProgress::Started { .. } => 0.0,
+            RenderProgress::Rendering {
+                seconds,
+                total_seconds,
+                ..
+            } if total_seconds > 0.0 => (seconds / total_seconds).min(1.0),
+            RenderProgress::Rendering { .. } => 0.0,
+            RenderProgress::Finished { .. } => 1.0,
+        }
+    }
+}
+
+/// Receives the progress of renders.
+///
+/// Implemented by indicatif's [`ProgressBar`], and by any `Fn(RenderProgress)`
+/// closure, so frontends can report progress their own way:
+///
+/// ```no_run
+/// # use untracker_core::progress::{ProgressSink, RenderProgress};
+/// let sink = |progress: RenderProgress| println!("{:.0}%", progress.fraction() * 100.0);
+/// # let _: &dyn ProgressSink = &sink;
+/// ```
+pub trait ProgressSink: Sync {
+    fn report(&self, progress: RenderProgress<'_>);
+}
+
+impl<F: Fn(RenderProgress<'_>) + Sync> ProgressSink for F {
+    fn report(&self, progress: RenderProgress<'_>) {
+        self(progress)
+    }
+}
+
+/// Shows what is rendering as the message of the bar, and prints the files written above it.
+impl ProgressSink for ProgressBar {
+    fn report(&self, progress: RenderProgress<'_>) {
+        match progress {
+            RenderProgress::Started { label } => {
+                self.set_message(format!("Rendering {}...", label))
+            }
+            RenderProgress::Rendering { label, .. } => self.set_message(format!(
+                "{} - {:.1}% complete",
+                label,
+                progress.fraction() * 100.0
+            )),
+            RenderProgress::Finished { path, .. } => {
+                self.println(format!("  Rendered {}", path.display()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_closure_sink() {
+        let reported = Mutex::new(Vec::new());
+        let sink = |progress: RenderProgress| reported.lock().unwrap().push(progress.fraction());
+        let sink: &dyn ProgressSink = &sink;
+
+        sink.report(RenderProgress::Started { label: "sample 1" });
+        sink.report(RenderProgress::Rendering {
+            label: "sample 1",
+            seconds: 30.0,
+            total_seconds: 120.0,
+        });
+        // Past the estimated length
+        sink.report(RenderProgress::Rendering {
+            label: "sample 1",
+            seconds: 121.0,
+            total_seconds: 120.0,
+        });
+        sink.report(RenderProgress::Finished {
+            label: "sample 1",
+            path: Path::new("song_sample_001.wav"),
+        });
+        assert_eq!(*reported.lock().unwrap(), [0.0, 0.25, 1.0, 1.0]);
+    }
+}
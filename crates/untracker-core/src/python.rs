@@ -0,0 +1,389 @@
+//! The `untracker` Python module, for extracting stems from Python without
+//! running the command-line tool, such as to feed them to a model.
+//!
+//! Built with maturin (see `pyproject.toml`). Stems are numbered from 0, and
+//! audio comes out as numpy arrays of 16-bit samples, one row per frame.
+
+use anyhow::anyhow;
+use numpy::ndarray::Array2;
+use numpy::{IntoPyArray, PyArray2};
+use openmpt::index::{InstrumentIndex, SubsongIndex};
+use pyo3::prelude::*;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+
+use crate::{
+    render_stem, render_stem_streaming, unpack, ExportOptions, Extractor, NameTemplate,
+    NormalizeMode, StemLabels,
+};
+
+/// Chunks a stem streamed by `Module.chunks` renders ahead of the reader.
+const CHUNKS_AHEAD: usize = 4;
+
+/// How stems are rendered: the defaults are 16-bit stereo WAV at 44.1 kHz
+/// of the whole song.
+#[pyclass(name = "ExportOptions", frozen)]
+#[derive(Debug, Clone)]
+pub struct PyExportOptions {
+    options: ExportOptions,
+}
+
+#[pymethods]
+impl PyExportOptions {
+    /// `format` names the format of files, such as "flac", `preview` only
+    /// renders that many seconds from the start, and `normalize` scales each
+    /// file so its loudest sample reaches -1 dBFS.
+    #[new]
+    #[pyo3(signature = (*, format=None, sample_rate=None, channels=None, bit_depth=None, subsong=None, preview=None, gain_db=None, normalize=false, reproducible=false))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        format: Option<&str>,
+        sample_rate: Option<u32>,
+        channels: Option<u32>,
+        bit_depth: Option<u32>,
+        subsong: Option<i32>,
+        preview: Option<f64>,
+        gain_db: Option<f64>,
+        normalize: bool,
+        reproducible: bool,
+    ) -> PyResult<Self> {
+        let defaults = ExportOptions::default();
+        let options = ExportOptions {
+            format: match format {
+                Some(format) => format.parse()?,
+                None => defaults.format,
+            },
+            sample_rate: sample_rate.unwrap_or(defaults.sample_rate),
+            channels: channels.unwrap_or(defaults.channels),
+            bit_depth: bit_depth.unwrap_or(defaults.bit_depth),
+            subsong: subsong.map(SubsongIndex::new),
+            preview,
+            gain_db: gain_db.unwrap_or(defaults.gain_db),
+            normalize: if normalize {
+                NormalizeMode::Peak
+            } else {
+                NormalizeMode::Off
+            },
+            reproducible,
+            ..defaults
+        };
+        options.validate()?;
+        Ok(PyExportOptions { options })
+    }
+
+    #[getter]
+    fn format(&self) -> &'static str {
+        self.options.format.name()
+    }
+
+    #[getter]
+    fn sample_rate(&self) -> u32 {
+        self.options.sample_rate
+    }
+
+    #[getter]
+    fn channels(&self) -> u32 {
+        self.options.channels
+    }
+
+    #[getter]
+    fn bit_depth(&self) -> u32 {
+        self.options.bit_depth
+    }
+
+    #[getter]
+    fn preview(&self) -> Option<f64> {
+        self.options.preview
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "ExportOptions(format={:?}, sample_rate={}, channels={}, bit_depth={})",
+            self.options.format.name(),
+            self.options.sample_rate,
+            self.options.channels,
+            self.options.bit_depth
+        )
+    }
+}
+
+/// What rendering a stem to a file gave.
+#[pyclass(get_all, frozen)]
+#[derive(Debug, Clone)]
+pub struct StemResult {
+    path: PathBuf,
+    /// Number of frames (samples per channel)
+    frames: u64,
+    duration: f64,
+    /// Highest absolute sample value, as a fraction of full scale
+    peak: f64,
+}
+
+/// A module loaded from a file or memory, to render its stems one after
+/// the other.
+#[pyclass(unsendable)]
+pub struct Module {
+    extractor: Extractor,
+}
+
+impl Module {
+    /// Stem `stem` of the module.
+    fn stem(&self, stem: usize) -> PyResult<&crate::Stem> {
+        let stems = self.extractor.stems();
+        Ok(stems.get(stem).ok_or_else(|| {
+            anyhow!(
+                "Stem {} selected, but the module only has {} stems",
+                stem,
+                stems.len()
+            )
+        })?)
+    }
+}
+
+/// `options`, or the defaults.
+fn export_options(options: Option<&PyExportOptions>) -> ExportOptions {
+    options
+        .map(|options| options.options.clone())
+        .unwrap_or_default()
+}
+
+/// `samples` as an array of `channels` columns.
+fn to_array(py: Python<'_>, samples: Vec<i16>, channels: u32) -> Bound<'_, PyArray2<i16>> {
+    let channels = channels as usize;
+    Array2::from_shape_vec((samples.len() / channels, channels), samples)
+        .expect("renders are whole frames")
+        .into_pyarray(py)
+}
+
+#[pymethods]
+impl Module {
+    /// Load the module in `data`.
+    #[new]
+    fn new(data: Vec<u8>) -> PyResult<Self> {
+        Ok(Module {
+            extractor: Extractor::new(data)?,
+        })
+    }
+
+    /// Load the module file at `path`, unpacking it if it is an archive
+    /// untracker was built to read.
+    #[staticmethod]
+    fn open(path: PathBuf) -> PyResult<Self> {
+        let data = std::fs::read(&path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        Self::new(unpack::unpack(&path, data)?)
+    }
+
+    /// Names of the stems: the instruments of the module, or its samples if
+    /// it has none.
+    #[getter]
+    fn stem_names(&self) -> Vec<String> {
+        let stems = self.extractor.stems();
+        stems.iter().map(|stem| stem.name.clone()).collect()
+    }
+
+    /// Whether the stems are instruments rather than samples.
+    #[getter]
+    fn stems_are_instruments(&self) -> bool {
+        let stems = self.extractor.stems();
+        stems.first().is_some_and(|stem| stem.is_instrument)
+    }
+
+    fn __len__(&self) -> usize {
+        self.extractor.stems().len()
+    }
+
+    /// Render stem `stem` to its file in `output_dir`, named from
+    /// `base_name` as the command-line tool names stems.
+    #[pyo3(signature = (stem, output_dir, base_name, options=None))]
+    fn render_stem(
+        &self,
+        py: Python<'_>,
+        stem: usize,
+        output_dir: PathBuf,
+        base_name: &str,
+        options: Option<&PyExportOptions>,
+    ) -> PyResult<StemResult> {
+        let stem = self.stem(stem)?;
+        let output_dir = output_dir
+            .to_str()
+            .ok_or_else(|| anyhow!("{} is not valid UTF-8", output_dir.display()))?;
+        let options = export_options(options);
+        let buffer = self.extractor.buffer();
+        let (index, is_instrument) = (stem.index, stem.is_instrument);
+        let result = py.detach(|| {
+            render_stem(
+                buffer,
+                index,
+                is_instrument,
+                output_dir,
+                base_name,
+                &options,
+                &NameTemplate::default(),
+                &StemLabels::default(),
+                &[],
+                None,
+            )
+        })?;
+        Ok(StemResult {
+            path: result.path,
+            frames: result.frames,
+            duration: result.duration,
+            peak: result.peak,
+        })
+    }
+
+    /// The audio of stem `stem`, one row per frame. The format, bit depth,
+    /// gain and normalization of `options` are left out: the audio is
+    /// 16-bit, as libopenmpt renders it.
+    #[pyo3(signature = (stem, options=None))]
+    fn render<'py>(
+        &mut self,
+        py: Python<'py>,
+        stem: usize,
+        options: Option<&PyExportOptions>,
+    ) -> PyResult<Bound<'py, PyArray2<i16>>> {
+        let index = self.stem(stem)?.index;
+        let options = export_options(options);
+        let mut samples = Vec::new();
+        self.extractor.render(index, &options, |chunk| {
+            samples.extend_from_slice(chunk);
+            Ok(())
+        })?;
+        Ok(to_array(py, samples, options.channels))
+    }
+
+    /// The audio of stem `stem` in chunks as it is rendered, as by
+    /// `render`, so memory use doesn't grow with the stem length.
+    #[pyo3(signature = (stem, options=None))]
+    fn chunks(&self, stem: usize, options: Option<&PyExportOptions>) -> PyResult<StemChunks> {
+        let stem = self.stem(stem)?;
+        let (index, is_instrument) = (stem.index, stem.is_instrument);
+        let options = export_options(options);
+        Ok(StemChunks::spawn(
+            self.extractor.buffer().to_vec(),
+            index,
+            is_instrument,
+            options,
+        ))
+    }
+}
+
+/// Chunks of the audio of a stem, rendered on a thread of their own a few
+/// chunks ahead of the reader. The render stops once the iterator is gone.
+#[pyclass]
+pub struct StemChunks {
+    chunks: Mutex<Receiver<anyhow::Result<Vec<i16>>>>,
+    channels: u32,
+}
+
+impl StemChunks {
+    fn spawn(
+        buffer: Vec<u8>,
+        index: InstrumentIndex,
+        is_instrument: bool,
+        options: ExportOptions,
+    ) -> Self {
+        let (sender, chunks) = mpsc::sync_channel(CHUNKS_AHEAD);
+        let channels = options.channels;
+        std::thread::spawn(move || {
+            let result =
+                render_stem_streaming(&buffer, index, is_instrument, &options, None, |chunk| {
+                    sender
+                        .send(Ok(chunk.to_vec()))
+                        .map_err(|_| anyhow!("The stem is no longer read"))
+                });
+            if let Err(e) = result {
+                // Nobody is told if the reader is gone
+                let _ = sender.send(Err(e));
+            }
+        });
+        StemChunks {
+            chunks: Mutex::new(chunks),
+            channels,
+        }
+    }
+}
+
+#[pymethods]
+impl StemChunks {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyArray2<i16>>>> {
+        let chunk = py.detach(|| self.chunks.lock().unwrap().recv());
+        match chunk {
+            Ok(Ok(samples)) => Ok(Some(to_array(py, samples, self.channels))),
+            Ok(Err(e)) => Err(e.into()),
+            // The render is over
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+#[pymodule]
+fn untracker(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add_class::<Module>()?;
+    m.add_class::<PyExportOptions>()?;
+    m.add_class::<StemResult>()?;
+    m.add_class::<StemChunks>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use numpy::PyUntypedArrayMethods;
+
+    #[test]
+    fn test_render_from_python() {
+        Python::initialize();
+        Python::attach(|py| {
+            let data = include_bytes!("../../../tests/modules/cndmcrrp.mod").to_vec();
+            let mut module = Module::new(data).unwrap();
+            assert!(!module.stems_are_instruments());
+            assert_eq!(module.stem_names().len(), module.__len__());
+
+            let options = PyExportOptions::new(
+                None,
+                Some(22050),
+                Some(1),
+                None,
+                None,
+                Some(2.0),
+                None,
+                false,
+                true,
+            )
+            .unwrap();
+            let audio = module.render(py, 1, Some(&options)).unwrap();
+            assert_eq!(audio.shape(), [2 * 22050, 1]);
+
+            // Streamed chunks add up to the whole render
+            let chunks = module.chunks(1, Some(&options)).unwrap();
+            let mut frames = 0;
+            while let Some(chunk) = chunks.__next__(py).unwrap() {
+                assert_eq!(chunk.shape()[1], 1);
+                frames += chunk.shape()[0];
+            }
+            assert_eq!(frames, 2 * 22050);
+
+            assert!(module.render(py, 1000, None).is_err());
+            assert!(PyExportOptions::new(
+                Some("aiff"),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                false,
+                false
+            )
+            .is_err());
+        });
+    }
+}
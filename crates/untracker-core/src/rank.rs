@@ -0,0 +1,242 @@
+//! Ranking of modules by how well they will split into stems, for
+//! `untracker rank`, so that curators facing thousands of modules can pick
+//! which to extract first.
+//!
+//! Modules are scored from 0 to 100 on their pattern data alone, without
+//! rendering anything:
+//!
+//! - up to 40 points for the instruments (or samples) played, in full from 8
+//! - up to 25 for how evenly the notes spread over the channels played
+//! - 15 for the absence of plugin events (parameter control notes and MIDI
+//!   macros), as plugins process instruments together and libopenmpt renders
+//!   few of them
+//! - up to 20 for instruments never played from a sample offset: a sample
+//!   played from several offsets usually holds several sounds, such as a whole
+//!   drum loop, which then end up in one stem
+
+use openmpt::index::ChannelIndex;
+use openmpt::mod_command::{EffectCommand, Note, SpecialNote, VolumeCommand};
+use openmpt::module::Module;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// Instruments played for a module to get the full score for them.
+const FULL_STEMS: usize = 8;
+
+/// How a module will split into stems.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StemWorthiness {
+    pub module: PathBuf,
+    /// From 0 to 100, higher for modules that split better
+    pub score: f64,
+    /// Instruments, or samples for modules without instruments
+    pub stems: i32,
+    /// Instruments (or samples) played in the song
+    pub played_stems: usize,
+    pub channels: i32,
+    /// Channels with notes
+    pub played_channels: usize,
+    /// How evenly notes spread over the channels played, from 0 when they
+    /// are all on one to 1
+    pub channel_spread: f64,
+    /// Parameter control notes and MIDI macro commands
+    pub plugin_events: usize,
+    /// Instruments (or samples) played from a sample offset
+    pub sliced_stems: usize,
+}
+
+impl StemWorthiness {
+    /// Score `module`, loaded from `path`, from the patterns of its order
+    /// list.
+    pub fn survey(path: &Path, module: &mut Module) -> Self {
+        let num_instruments = module.get_num_instruments();
+        let stems = if num_instruments > 0 {
+            num_instruments
+        } else {
+            module.get_num_samples()
+        };
+        let channels = module.get_num_channels();
+
+        let mut notes_per_channel = vec![0usize; channels.max(0) as usize];
+        // A note without an instrument plays the last one set on its channel
+        let mut instruments = vec![0u8; channels.max(0) as usize];
+        let mut played = BTreeSet::new();
+        let mut sliced = BTreeSet::new();
+        let mut plugin_events = 0;
+
+        for order in 0..module.get_num_orders() {
+            let Some(mut pattern) = module.get_pattern_by_order(order) else {
+                continue;
+            };
+            // Order list separators point at patterns without rows
+            let num_rows = pattern.get_num_rows();
+            if num_rows <= 0 {
+                continue;
+            }
+            for row in 0..num_rows {
+                let Some(mut row) = pattern.get_row_by_number(row) else {
+                    continue;
+                };
+                for channel in ChannelIndex::all(channels) {
+                    let Some(Ok(command)) =
+                        row.get_cell_by_channel(channel).map(|mut c| c.get_data())
+                    else {
+                        continue;
+                    };
+                    let n = channel.get() as usize;
+                    if command.instr != 0 {
+                        instruments[n] = command.instr;
+                    }
+                    let instrument = instruments[n];
+                    let is_stem = instrument != 0 && instrument as i32 <= stems;
+
+                    match command.note {
+                        Note::Note(_) => {
+                            notes_per_channel[n] += 1;
+                            if is_stem {
+                                played.insert(instrument);
+                            }
+                        }
+                        Note::Special(
+                            SpecialNote::ParamControl | SpecialNote::ParamControlSmooth,
+                        ) => plugin_events += 1,
+                        _ => {}
+                    }
+                    match command.command {
+                        EffectCommand::Midi(_) | EffectCommand::SmoothMidi(_) => plugin_events += 1,
+                        EffectCommand::Offset(_)
+                        | EffectCommand::OffsetPercentage(_)
+                        | EffectCommand::ReverseOffset(_)
+                            if is_stem =>
+                        {
+                            sliced.insert(instrument);
+                        }
+                        _ => {}
+                    }
+                    if matches!(command.volcmd, VolumeCommand::Offset(_)) && is_stem {
+                        sliced.insert(instrument);
+                    }
+                }
+            }
+        }
+
+        let mut worthiness = StemWorthiness {
+            module: path.to_path_buf(),
+            score: 0.0,
+            stems,
+            played_stems: played.len(),
+            channels,
+            played_channels: notes_per_channel.iter().filter(|&&n| n > 0).count(),
+            channel_spread: spread(&notes_per_channel),
+            plugin_events,
+            // Offsets set before an instrument's first note don't slice it
+            sliced_stems: sliced.intersection(&played).count(),
+        };
+        worthiness.score = worthiness.compute_score();
+        worthiness
+    }
+
+    fn compute_score(&self) -> f64 {
+        if self.played_stems == 0 {
+            return 0.0;
+        }
+        let stems = ((self.played_stems - 1) as f64 / (FULL_STEMS - 1) as f64).min(1.0);
+        let unsliced = 1.0 - self.sliced_stems as f64 / self.played_stems as f64;
+        let no_plugins = if self.plugin_events == 0 { 1.0 } else { 0.0 };
+        40.0 * stems + 25.0 * self.channel_spread + 15.0 * no_plugins + 20.0 * unsliced
+    }
+
+    /// One line with the score and what lowered it.
+    pub fn to_text(&self) -> String {
+        let mut line = format!(
+            "{:5.1}  {}: {} of {} stems played, on {} of {} channels",
+            self.score,
+            self.module.display(),
+            self.played_stems,
+            self.stems,
+            self.played_channels,
+            self.channels
+        );
+        if self.sliced_stems > 0 {
+            line.push_str(&format!(
+                ", {} played from sample offsets",
+                self.sliced_stems
+            ));
+        }
+        if self.plugin_events > 0 {
+            line.push_str(&format!(", {} plugin events", self.plugin_events));
+        }
+        line
+    }
+}
+
+/// Entropy of the share of notes on each channel, relative to that of an
+/// even spread over the channels played.
+fn spread(notes_per_channel: &[usize]) -> f64 {
+    let total: usize = notes_per_channel.iter().sum();
+    let played = notes_per_channel.iter().filter(|&&n| n > 0).count();
+    if played < 2 {
+        return 0.0;
+    }
+    let entropy: f64 = notes_per_channel
+        .iter()
+        .filter(|&&n| n > 0)
+        .map(|&n| {
+            let p = n as f64 / total as f64;
+            -p * p.ln()
+        })
+        .sum();
+    entropy / (played as f64).ln()
+}
+
+/// Sort `modules` from the most to the least worth extracting.
+pub fn rank(modules: &mut [StemWorthiness]) {
+    modules.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.module.cmp(&b.module)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{FixtureModule, FixtureNote, FixtureSample};
+    use crate::{ExportOptions, RenderSession};
+
+    fn survey(buffer: &[u8]) -> StemWorthiness {
+        let session = RenderSession::new(buffer, &ExportOptions::default()).unwrap();
+        let mut module = session.module_ext().get_module();
+        StemWorthiness::survey(Path::new("song.mod"), &mut module)
+    }
+
+    #[test]
+    fn test_stem_worthiness() {
+        assert_eq!(spread(&[0, 10, 0]), 0.0);
+        assert!((spread(&[5, 5, 5, 5]) - 1.0).abs() < 1e-9);
+        assert!(spread(&[30, 1, 1]) < 0.5);
+
+        // 8 samples spread evenly over 4 channels get the full score
+        let song = survey(&FixtureModule::song(8, 2).to_mod());
+        assert_eq!((song.stems, song.played_stems), (8, 8));
+        assert_eq!((song.channels, song.played_channels), (4, 4));
+        assert_eq!((song.plugin_events, song.sliced_stems), (0, 0));
+        assert!((song.score - 100.0).abs() < 1e-6, "{:?}", song);
+
+        // A drum loop sliced with 9xx on a single channel
+        let sliced = survey(
+            &FixtureModule::new("sliced")
+                .sample(FixtureSample::noise("loop", 4096, 1))
+                .pattern(vec![
+                    FixtureNote::new(0, 0, 1),
+                    FixtureNote::new(4, 0, 1).effect(0x9, 0x08),
+                ])
+                .to_mod(),
+        );
+        assert_eq!((sliced.played_stems, sliced.sliced_stems), (1, 1));
+        assert_eq!(sliced.channel_spread, 0.0);
+        assert!(sliced.score < 20.0, "{:?}", sliced);
+        assert!(sliced.to_text().contains("1 played from sample offsets"));
+
+        let mut ranked = vec![sliced, song];
+        rank(&mut ranked);
+        assert_eq!(ranked[0].played_stems, 8);
+    }
+}
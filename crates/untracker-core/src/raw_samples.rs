@@ -0,0 +1,591 @@
+//! The sample data stored in modules, read from the file rather than
+//! rendered, for `--mode raw-samples`.
+//!
+//! libopenmpt gives no access to sample data, so MOD, S3M, XM and IT files
+//! are parsed here, as far as their samples go. Samples are converted to
+//! 16 bits and kept at the rate they play their base note at, with their
+//! loop, so sample packs get the sounds exactly as the composer stored them.
+
+use anyhow::{anyhow, Result};
+use std::path::Path;
+
+use crate::audio::{append_wav_chunks, smpl_chunk, write_audio_file, EncoderOptions};
+use crate::container::file_name_from_title;
+
+/// Rate of the base note of MOD, S3M and XM samples without finetune.
+const BASE_RATE: f64 = 8363.0;
+
+/// A sample as stored in a module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawSample {
+    /// Number of the sample, from 1, as libopenmpt numbers them
+    pub number: i32,
+    pub name: String,
+    /// Rate the sample plays its base note at
+    pub sample_rate: u32,
+    pub channels: u32,
+    /// Interleaved 16-bit audio
+    pub data: Vec<i16>,
+    /// First and last frame of the loop, inclusive
+    pub loop_frames: Option<(u64, u64)>,
+}
+
+impl RawSample {
+    pub fn frames(&self) -> u64 {
+        self.data.len() as u64 / self.channels as u64
+    }
+
+    /// File name of the sample, without extension: `<module>_raw_003`, with
+    /// the name of the sample appended if it has one.
+    pub fn file_name(&self, module: &str) -> String {
+        match file_name_from_title(&self.name) {
+            Some(name) => format!("{}_raw_{:03}_{}", module, self.number, name),
+            None => format!("{}_raw_{:03}", module, self.number),
+        }
+    }
+
+    /// Write the sample to a 16-bit WAV file, with its loop in a `smpl`
+    /// chunk.
+    pub fn write_wav(&self, path: &Path) -> Result<()> {
+        let options = EncoderOptions {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            ..EncoderOptions::default()
+        };
+        write_audio_file(&self.data, path, &options)?;
+        if let Some((start, end)) = self.loop_frames {
+            append_wav_chunks(
+                path,
+                &[(b"smpl", &smpl_chunk(self.sample_rate, start, end))],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The samples of the module in `buffer` that hold audio, in order.
+///
+/// Fails for formats other than MOD, S3M, XM and IT, and for modules
+/// packed in another file.
+pub fn read_samples(buffer: &[u8]) -> Result<Vec<RawSample>> {
+    let samples = if buffer.starts_with(b"Extended Module: ") {
+        read_xm(buffer)?
+    } else if buffer.starts_with(b"IMPM") {
+        read_it(buffer)?
+    } else if buffer.get(0x2C..0x30) == Some(b"SCRM") {
+        read_s3m(buffer)?
+    } else if let Some(channels) = mod_channels(buffer) {
+        read_mod(buffer, channels)?
+    } else {
+        return Err(anyhow!(
+            "Raw samples can only be read from MOD, S3M, XM and IT files"
+        ));
+    };
+    Ok(samples
+        .into_iter()
+        .filter(|sample| !sample.data.is_empty())
+        .collect())
+}
+
+fn bytes(buffer: &[u8], offset: usize, len: usize) -> Result<&[u8]> {
+    buffer
+        .get(offset..offset.saturating_add(len))
+        .ok_or_else(|| anyhow!("The module is truncated at offset {}", offset))
+}
+
+fn u16_le(buffer: &[u8], offset: usize) -> Result<usize> {
+    let b = bytes(buffer, offset, 2)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]) as usize)
+}
+
+fn u16_be(buffer: &[u8], offset: usize) -> Result<usize> {
+    let b = bytes(buffer, offset, 2)?;
+    Ok(u16::from_be_bytes([b[0], b[1]]) as usize)
+}
+
+fn u32_le(buffer: &[u8], offset: usize) -> Result<usize> {
+    let b = bytes(buffer, offset, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+}
+
+/// Sample data of `len` bytes at `offset`, or as much of it as the file
+/// holds: modules with their last sample cut short are common.
+fn sample_data(buffer: &[u8], offset: usize, len: usize) -> &[u8] {
+    let start = offset.min(buffer.len());
+    let end = offset.saturating_add(len).min(buffer.len());
+    if end - start < len {
+        log::warn!(
+            "Sample data at offset {} is cut short, {} of {} bytes",
+            offset,
+            end - start,
+            len
+        );
+    }
+    &buffer[start..end]
+}
+
+/// Text of a fixed-size field, padded with zeros or spaces.
+fn text(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| if b == 0 { ' ' } else { b as char })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// How sample data is stored.
+#[derive(Debug, Clone, Copy)]
+struct Encoding {
+    sixteen_bit: bool,
+    signed: bool,
+    /// Each value is the difference from the previous one, as in XM files
+    delta: bool,
+}
+
+/// Decode `frames` frames of one channel.
+fn decode_channel(data: &[u8], frames: usize, encoding: Encoding) -> Vec<i16> {
+    let mut samples: Vec<i16> = if encoding.sixteen_bit {
+        data.chunks_exact(2)
+            .take(frames)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as i16)
+            .collect()
+    } else {
+        data.iter().take(frames).map(|&b| b as i8 as i16).collect()
+    };
+    if encoding.delta {
+        let mut value: i16 = 0;
+        for sample in &mut samples {
+            value = value.wrapping_add(*sample);
+            *sample = if encoding.sixteen_bit {
+                value
+            } else {
+                value as i8 as i16
+            };
+        }
+    }
+    for sample in &mut samples {
+        if !encoding.signed {
+            *sample ^= if encoding.sixteen_bit { -0x8000 } else { -0x80 };
+            if !encoding.sixteen_bit {
+                *sample = *sample as i8 as i16;
+            }
+        }
+        if !encoding.sixteen_bit {
+            *sample <<= 8;
+        }
+    }
+    samples
+}
+
+/// Interleave channels stored one after the other.
+fn interleave(channels: Vec<Vec<i16>>) -> Vec<i16> {
+    let frames = channels.iter().map(Vec::len).min().unwrap_or(0);
+    (0..frames)
+        .flat_map(|i| channels.iter().map(move |channel| channel[i]))
+        .collect()
+}
+
+/// Loop from `start` to `end`, exclusive, within `frames`.
+fn loop_frames(start: usize, end: usize, frames: u64) -> Option<(u64, u64)> {
+    let end = (end as u64).min(frames);
+    (end > start as u64 + 1).then(|| (start as u64, end - 1))
+}
+
+/// Number of channels of a ProTracker-style MOD file, from its tag.
+fn mod_channels(buffer: &[u8]) -> Option<usize> {
+    let digit = |b: u8| b.is_ascii_digit().then(|| (b - b'0') as usize);
+    match buffer.get(1080..1084)? {
+        b"M.K." | b"M!K!" | b"M&K!" | b"N.T." | b"FLT4" => Some(4),
+        b"FLT8" | b"CD81" | b"OKTA" | b"OCTA" => Some(8),
+        [n, b'C', b'H', b'N'] => digit(*n),
+        [tens, ones, b'C', b'H'] => Some(digit(*tens)? * 10 + digit(*ones)?),
+        _ => None,
+    }
+}
+
+fn read_mod(buffer: &[u8], channels: usize) -> Result<Vec<RawSample>> {
+    let patterns = bytes(buffer, 952, 128)?
+        .iter()
+        .max()
+        .map_or(0, |&p| p as usize + 1);
+    let mut offset = 1084 + patterns * 64 * channels * 4;
+    let mut samples = Vec::new();
+    for i in 0..31 {
+        let header = bytes(buffer, 20 + i * 30, 30)?;
+        let length = u16_be(header, 22)? * 2;
+        // Finetune is a signed nibble, in eighths of a semitone
+        let finetune = ((header[24] & 0x0f) as i8) << 4 >> 4;
+        let data = sample_data(buffer, offset, length);
+        offset += length;
+
+        let data = decode_channel(
+            data,
+            length,
+            Encoding {
+                sixteen_bit: false,
+                signed: true,
+                delta: false,
+            },
+        );
+        let loop_start = u16_be(header, 26)? * 2;
+        let loop_length = u16_be(header, 28)? * 2;
+        samples.push(RawSample {
+            number: i as i32 + 1,
+            name: text(&header[..22]),
+            sample_rate: (BASE_RATE * 2f64.powf(finetune as f64 / 96.0)).round() as u32,
+            channels: 1,
+            // A loop of one word means none
+            loop_frames: loop_frames(loop_start, loop_start + loop_length, data.len() as u64)
+                .filter(|_| loop_length > 2),
+            data,
+        });
+    }
+    Ok(samples)
+}
+
+fn read_s3m(buffer: &[u8]) -> Result<Vec<RawSample>> {
+    let orders = u16_le(buffer, 0x20)?;
+    let instruments = u16_le(buffer, 0x22)?;
+    // File format information: 1 for signed samples, 2 for unsigned ones
+    let signed = u16_le(buffer, 0x2A)? == 1;
+    let mut samples = Vec::new();
+    for i in 0..instruments {
+        let header = bytes(buffer, u16_le(buffer, 0x60 + orders + i * 2)? * 16, 80)?;
+        // 1 for samples, the others are AdLib instruments or empty
+        if header[0] != 1 {
+            continue;
+        }
+        let offset = ((header[13] as usize) << 16 | u16_le(header, 14)?) * 16;
+        let frames = u32_le(header, 16)?;
+        let flags = header[31];
+        let channels = if flags & 2 != 0 { 2 } else { 1 };
+        let encoding = Encoding {
+            sixteen_bit: flags & 4 != 0,
+            signed,
+            delta: false,
+        };
+        let channel_bytes = frames * if encoding.sixteen_bit { 2 } else { 1 };
+        let data = sample_data(buffer, offset, channel_bytes * channels);
+        let data = interleave(
+            (0..channels)
+                .map(|c| {
+                    let start = (c * channel_bytes).min(data.len());
+                    decode_channel(&data[start..], frames, encoding)
+                })
+                .collect(),
+        );
+        let frames = (data.len() / channels) as u64;
+        samples.push(RawSample {
+            number: i as i32 + 1,
+            name: text(&header[48..76]),
+            sample_rate: match u32_le(header, 32)? {
+                0 => BASE_RATE as u32,
+                rate => rate as u32,
+            },
+            channels: channels as u32,
+            loop_frames: loop_frames(u32_le(header, 20)?, u32_le(header, 24)?, frames)
+                .filter(|_| flags & 1 != 0),
+            data,
+        });
+    }
+    Ok(samples)
+}
+
+fn read_xm(buffer: &[u8]) -> Result<Vec<RawSample>> {
+    let patterns = u16_le(buffer, 70)?;
+    let instruments = u16_le(buffer, 72)?;
+    let mut offset = 60 + u32_le(buffer, 60)?;
+    for _ in 0..patterns {
+        offset += u32_le(buffer, offset)? + u16_le(buffer, offset + 7)?;
+    }
+
+    let mut samples = Vec::new();
+    for _ in 0..instruments {
+        let num_samples = u16_le(buffer, offset + 27)?;
+        let header_size = match num_samples {
+            0 => 40,
+            _ => u32_le(buffer, offset + 29)?,
+        };
+        offset += u32_le(buffer, offset)?;
+        let headers = offset;
+        offset += num_samples * header_size;
+
+        for s in 0..num_samples {
+            let header = bytes(buffer, headers + s * header_size, 40)?;
+            let length = u32_le(header, 0)?;
+            let flags = header[14];
+            let channels = if flags & 0x20 != 0 { 2 } else { 1 };
+            let encoding = Encoding {
+                sixteen_bit: flags & 0x10 != 0,
+                signed: true,
+                delta: true,
+            };
+            let data = sample_data(buffer, offset, length);
+            offset += length;
+
+            // Stereo samples hold the left channel, then the right one
+            let sample_bytes = if encoding.sixteen_bit { 2 } else { 1 };
+            let frames = length / sample_bytes / channels;
+            let data = interleave(
+                (0..channels)
+                    .map(|c| {
+                        let start = (c * frames * sample_bytes).min(data.len());
+                        decode_channel(&data[start..], frames, encoding)
+                    })
+                    .collect(),
+            );
+            let frame_bytes = sample_bytes * channels;
+            let loop_start = u32_le(header, 4)? / frame_bytes;
+            let loop_end = loop_start + u32_le(header, 8)? / frame_bytes;
+            let relative_note = header[16] as i8 as f64;
+            let finetune = header[13] as i8 as f64;
+            samples.push(RawSample {
+                number: samples.len() as i32 + 1,
+                name: text(&header[18..40]),
+                sample_rate: (BASE_RATE * 2f64.powf((relative_note * 128.0 + finetune) / 1536.0))
+                    .round() as u32,
+                channels: channels as u32,
+                loop_frames: loop_frames(loop_start, loop_end, (data.len() / channels) as u64)
+                    .filter(|_| flags & 3 != 0),
+                data,
+            });
+        }
+    }
+    Ok(samples)
+}
+
+fn read_it(buffer: &[u8]) -> Result<Vec<RawSample>> {
+    let orders = u16_le(buffer, 0x20)?;
+    let instruments = u16_le(buffer, 0x22)?;
+    let num_samples = u16_le(buffer, 0x24)?;
+    let pointers = 0xC0 + orders + instruments * 4;
+
+    let mut samples = Vec::new();
+    for i in 0..num_samples {
+        let header = bytes(buffer, u32_le(buffer, pointers + i * 4)?, 80)?;
+        if &header[..4] != b"IMPS" {
+            return Err(anyhow!("Sample {} of the module is damaged", i + 1));
+        }
+        let flags = header[18];
+        // Without data, as in empty slots
+        if flags & 1 == 0 {
+            continue;
+        }
+        let conversion = header[46];
+        let frames = u32_le(header, 48)?;
+        let offset = u32_le(header, 72)?;
+        let channels = if flags & 4 != 0 { 2 } else { 1 };
+        let encoding = Encoding {
+            sixteen_bit: flags & 2 != 0,
+            signed: conversion & 1 != 0,
+            delta: false,
+        };
+
+        let data = if flags & 8 != 0 {
+            // Compressed channels follow each other
+            let mut start = offset.min(buffer.len());
+            let mut decoded = Vec::new();
+            for _ in 0..channels {
+                let (channel, used) = it_decompress(
+                    &buffer[start..],
+                    frames,
+                    encoding.sixteen_bit,
+                    conversion & 4 != 0,
+                );
+                decoded.push(channel);
+                start += used;
+            }
+            interleave(decoded)
+        } else {
+            let channel_bytes = frames * if encoding.sixteen_bit { 2 } else { 1 };
+            let data = sample_data(buffer, offset, channel_bytes * channels);
+            interleave(
+                (0..channels)
+                    .map(|c| {
+                        let start = (c * channel_bytes).min(data.len());
+                        decode_channel(&data[start..], frames, encoding)
+                    })
+                    .collect(),
+            )
+        };
+        let frames = (data.len() / channels) as u64;
+        samples.push(RawSample {
+            number: i as i32 + 1,
+            name: text(&header[20..46]),
+            sample_rate: u32_le(header, 60)? as u32,
+            channels: channels as u32,
+            loop_frames: loop_frames(u32_le(header, 52)?, u32_le(header, 56)?, frames)
+                .filter(|_| flags & 0x10 != 0),
+            data,
+        });
+    }
+    Ok(samples)
+}
+
+/// Reads the bits of IT compressed samples, lowest first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, bits: u32) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..bits {
+            let byte = self.data.get(self.position / 8)?;
+            value |= ((byte >> (self.position % 8)) as u32 & 1) << i;
+            self.position += 1;
+        }
+        Some(value)
+    }
+}
+
+/// Decompress `frames` frames of one channel of an IT 2.14 compressed sample,
+/// or IT 2.15 with `it215`, from the start of `data`. Returns the samples and
+/// the bytes they took.
+///
+/// The data is split into blocks, each with its length and a stream of
+/// deltas of varying width; the width changes on the special values the
+/// stream holds besides deltas.
+fn it_decompress(data: &[u8], frames: usize, sixteen_bit: bool, it215: bool) -> (Vec<i16>, usize) {
+    let (block_frames, max_width, max, half) = if sixteen_bit {
+        (0x4000, 17, 0xFFFF, 8)
+    } else {
+        (0x8000, 9, 0xFF, 4)
+    };
+    let sample_bits = max_width - 1;
+    let mut samples = Vec::with_capacity(frames);
+    let mut position = 0;
+    'blocks: while samples.len() < frames {
+        let Some(length) = data.get(position..position + 2) else {
+            break;
+        };
+        let length = u16::from_le_bytes([length[0], length[1]]) as usize;
+        position += 2;
+        let mut bits = BitReader {
+            data: &data[position..(position + length).min(data.len())],
+            position: 0,
+        };
+        position += length;
+
+        let block_end = samples.len() + (frames - samples.len()).min(block_frames);
+        let mut width: u32 = max_width;
+        let (mut d1, mut d2) = (0i32, 0i32);
+        while samples.len() < block_end {
+            let Some(value) = bits.read(width) else {
+                break 'blocks;
+            };
+            if width < 7 {
+                if value == 1 << (width - 1) {
+                    let Some(new_width) = bits.read(if sixteen_bit { 4 } else { 3 }) else {
+                        break 'blocks;
+                    };
+                    let new_width = new_width + 1;
+                    width = if new_width < width {
+                        new_width
+                    } else {
+                        new_width + 1
+                    };
+                    continue;
+                }
+            } else if width < max_width {
+                let border = (max >> (max_width - width)) - half;
+                if value > border && value <= border + 2 * half {
+                    let new_width = value - border;
+                    width = if new_width < width {
+                        new_width
+                    } else {
+                        new_width + 1
+                    };
+                    continue;
+                }
+            } else if value & (1 << sample_bits) != 0 {
+                width = (value + 1) & 0xFF;
+                if width == 0 || width > max_width {
+                    break 'blocks;
+                }
+                continue;
+            }
+
+            let shift = 32 - width.min(sample_bits);
+            let delta = ((value << shift) as i32) >> shift;
+            d1 = d1.wrapping_add(delta);
+            d2 = d2.wrapping_add(d1);
+            let sample = if it215 { d2 } else { d1 };
+            samples.push(if sixteen_bit {
+                sample as i16
+            } else {
+                (sample as i8 as i16) << 8
+            });
+        }
+    }
+    (samples, position.min(data.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{FixtureModule, FixtureSample};
+
+    #[test]
+    fn test_read_mod_and_xm_samples() {
+        let module = FixtureModule::new("raw")
+            .sample(FixtureSample {
+                looped: true,
+                ..FixtureSample::square("Square", 16)
+            })
+            .sample(FixtureSample::noise("Noise", 300, 7))
+            .pattern(Vec::new());
+
+        let samples = read_samples(&module.to_mod()).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].name, "Square");
+        assert_eq!((samples[0].sample_rate, samples[0].channels), (8363, 1));
+        let square = &module.samples[0].data;
+        assert_eq!(samples[0].frames(), square.len() as u64);
+        assert_eq!(samples[0].data[0], (square[0] as i16) << 8);
+        assert_eq!(samples[0].loop_frames, Some((0, square.len() as u64 - 1)));
+        assert_eq!(samples[1].loop_frames, None);
+        assert_eq!(samples[1].file_name("song"), "song_raw_002_Noise");
+
+        // XM files store deltas, in instruments
+        let xm_samples = read_samples(&module.clone().with_instruments().to_xm()).unwrap();
+        assert_eq!(xm_samples.len(), 2);
+        for (xm, m) in xm_samples.iter().zip(&samples) {
+            assert_eq!((&xm.name, xm.sample_rate), (&m.name, m.sample_rate));
+            assert_eq!(xm.data[..m.data.len()], m.data[..]);
+        }
+
+        assert!(read_samples(b"not a module").is_err());
+    }
+
+    #[test]
+    fn test_it_decompress() {
+        // 9-bit deltas 1, 1, 1 and -1, a switch to 4 bits, then 1 and -1
+        let mut bits: Vec<(u32, u32)> = vec![(1, 9), (1, 9), (1, 9), (0xFF, 9), (0x103, 9)];
+        bits.extend([(1, 4), (0xF, 4)]);
+        let mut block = Vec::new();
+        let mut position = 0;
+        for (value, width) in bits {
+            for i in 0..width {
+                if position % 8 == 0 {
+                    block.push(0u8);
+                }
+                *block.last_mut().unwrap() |= (((value >> i) & 1) as u8) << (position % 8);
+                position += 1;
+            }
+        }
+        let mut data = (block.len() as u16).to_le_bytes().to_vec();
+        data.extend(&block);
+
+        let (samples, used) = it_decompress(&data, 6, false, false);
+        assert_eq!(used, data.len());
+        let expected: Vec<i16> = [1, 2, 3, 2, 3, 2].iter().map(|s| s << 8).collect();
+        assert_eq!(samples, expected);
+        // IT 2.15 integrates twice
+        let (samples, _) = it_decompress(&data, 6, false, true);
+        let expected: Vec<i16> = [1, 3, 6, 8, 11, 13].iter().map(|s| s << 8).collect();
+        assert_eq!(samples, expected);
+    }
+}
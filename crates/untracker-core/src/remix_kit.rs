@@ -0,0 +1,333 @@
+//! Sample pack output for `--remix-kit`: level-matched stems with loop points
+//! and tempo information, and a `README.json` describing them.
+
+use anyhow::{anyhow, Result};
+use openmpt::index::ChannelIndex;
+use openmpt::mod_command::Note;
+use openmpt::module::Module;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::audio::{
+    append_wav_chunks, smpl_chunk, AudioFormat, ExportOptions, NormalizeMode, TrimSilence,
+};
+
+/// Name of the file describing the kit, written next to the stems.
+pub const REMIX_KIT_FILE: &str = "README.json";
+
+/// RMS level in dBFS every stem of a kit is matched to.
+pub const RMS_TARGET: f64 = -18.0;
+
+/// Beats in a bar; module formats don't store a time signature.
+const BEATS_PER_BAR: u32 = 4;
+
+/// Rows per beat assumed when converting tracker tempo to BPM, as in classic trackers.
+const ROWS_PER_BEAT: f64 = 4.0;
+
+const PITCH_CLASSES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// Krumhansl-Kessler key profiles, starting from the tonic.
+const MAJOR_PROFILE: [f64; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f64; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+/// The options stems of a kit are rendered with: trimmed at both ends and
+/// matched in RMS level.
+///
+/// Fails for formats other than WAV, the only one loop points are written to.
+pub fn kit_options(options: &ExportOptions) -> Result<ExportOptions> {
+    if options.format != AudioFormat::Wav {
+        return Err(anyhow!(
+            "--remix-kit writes WAV files, {} is not supported",
+            options.format.name()
+        ));
+    }
+    Ok(ExportOptions {
+        trim_silence: TrimSilence::Both,
+        normalize: NormalizeMode::Rms,
+        normalize_target: RMS_TARGET,
+        ..options.clone()
+    })
+}
+
+/// Tempo in beats per minute from the tracker tempo and speed (ticks per row).
+pub fn bpm(tempo: f64, speed: i32) -> f64 {
+    if speed <= 0 {
+        return tempo;
+    }
+    // A tick lasts 2.5 / tempo seconds
+    60.0 / (ROWS_PER_BEAT * 2.5 * speed as f64 / tempo)
+}
+
+/// How often each pitch class is played in the song, from C to B.
+///
+/// Notes played with instruments `include` rejects are left out, so drums
+/// don't pull the key off. Instruments are numbered from 1, as in patterns.
+pub fn note_histogram(module: &mut Module, include: impl Fn(u8) -> bool) -> [f64; 12] {
+    let mut histogram = [0.0; 12];
+    let num_channels = module.get_num_channels();
+    // A note without an instrument plays the last one set on its channel
+    let mut instruments = vec![0u8; num_channels.max(0) as usize];
+
+    for order in 0..module.get_num_orders() {
+        let Some(mut pattern) = module.get_pattern_by_order(order) else {
+            continue;
+        };
+        // Order list separators point at patterns without rows
+        let num_rows = pattern.get_num_rows();
+        if num_rows <= 0 {
+            continue;
+        }
+        for row in 0..num_rows {
+            let Some(mut row) = pattern.get_row_by_number(row) else {
+                continue;
+            };
+            for channel in ChannelIndex::all(num_channels) {
+                let Some(Ok(command)) = row.get_cell_by_channel(channel).map(|mut c| c.get_data())
+                else {
+                    continue;
+                };
+                let instrument = &mut instruments[channel.get() as usize];
+                if command.instr != 0 {
+                    *instrument = command.instr;
+                }
+                if let Note::Note(note) = command.note {
+                    if include(*instrument) {
+                        histogram[(note as usize - 1) % 12] += 1.0;
+                    }
+                }
+            }
+        }
+    }
+    histogram
+}
+
+/// A musical key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    /// Pitch class of the tonic, 0 for C
+    pub tonic: usize,
+    pub minor: bool,
+}
+
+impl Key {
+    /// Most likely key of a pitch class histogram, `None` if it is empty.
+    pub fn estimate(histogram: &[f64; 12]) -> Option<Key> {
+        if histogram.iter().all(|&count| count == 0.0) {
+            return None;
+        }
+
+        let correlation = |profile: &[f64; 12], tonic: usize| {
+            let mean_h = histogram.iter().sum::<f64>() / 12.0;
+            let mean_p = profile.iter().sum::<f64>() / 12.0;
+            let (mut cov, mut var_h, mut var_p) = (0.0, 0.0, 0.0);
+            for (i, &p) in profile.iter().enumerate() {
+                let h = histogram[(tonic + i) % 12] - mean_h;
+                let p = p - mean_p;
+                cov += h * p;
+                var_h += h * h;
+                var_p += p * p;
+            }
+            if var_h == 0.0 {
+                0.0
+            } else {
+                cov / (var_h * var_p).sqrt()
+            }
+        };
+
+        (0..12)
+            .flat_map(|tonic| [(tonic, false), (tonic, true)])
+            .map(|(tonic, minor)| {
+                let profile = if minor {
+                    &MINOR_PROFILE
+                } else {
+                    &MAJOR_PROFILE
+                };
+                (Key { tonic, minor }, correlation(profile, tonic))
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(key, _)| key)
+    }
+}
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mode = if self.minor { "minor" } else { "major" };
+        write!(f, "{} {}", PITCH_CLASSES[self.tonic], mode)
+    }
+}
+
+/// One stem of a kit.
+#[derive(Debug, Clone, Serialize)]
+pub struct KitStem {
+    /// Output file name, relative to the README
+    pub file: String,
+    /// Name of the instrument, sample or group
+    pub name: String,
+    /// Where the stem starts in the song, in seconds
+    pub start_offset: f64,
+    /// Length in seconds
+    pub duration: f64,
+    /// Loop start and end in frames, the end exclusive
+    pub loop_start: u64,
+    pub loop_end: u64,
+    /// Whole bars in the loop, 0 for stems shorter than a bar
+    pub bars: u32,
+}
+
+/// Everything about a kit, written as `README.json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RemixKit {
+    /// File name of the module
+    pub module: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    /// Tempo at the start of the song
+    pub bpm: f64,
+    /// Key estimated from the notes of the song, e.g. "A minor"
+    pub key: Option<String>,
+    pub sample_rate: u32,
+    /// RMS level in dBFS the stems were matched to
+    pub rms_target: f64,
+    pub stems: Vec<KitStem>,
+}
+
+/// The loop of a stem: as many whole bars as fit, or the whole stem if it is
+/// shorter than a bar. Returns the loop length in frames and the bar count.
+pub fn loop_length(frames: u64, sample_rate: u32, bpm: f64) -> (u64, u32) {
+    let bar_frames = BEATS_PER_BAR as f64 * 60.0 / bpm * sample_rate as f64;
+    let bars = (frames as f64 / bar_frames).floor() as u32;
+    if bars == 0 {
+        (frames, 0)
+    } else {
+        (
+            ((bars as f64 * bar_frames).round() as u64).min(frames),
+            bars,
+        )
+    }
+}
+
+/// Length in beats of a loop of `bars` bars, or of a stem shorter than a bar.
+pub fn loop_beats(duration: f64, bars: u32, bpm: f64) -> u32 {
+    if bars > 0 {
+        bars * BEATS_PER_BAR
+    } else {
+        ((duration * bpm / 60.0).round() as u32).max(1)
+    }
+}
+
+/// Add a `smpl` chunk with a forward loop over the first `loop_frames`
+/// frames, and an `acid` chunk with the tempo, to a WAV file.
+///
+/// Samplers read the loop from the first, and ACID-style loop players the
+/// tempo and length in beats from the second.
+pub fn tag_wav(
+    path: &Path,
+    sample_rate: u32,
+    loop_frames: u64,
+    bpm: f64,
+    beats: u32,
+    key: Option<Key>,
+) -> Result<()> {
+    let smpl = smpl_chunk(sample_rate, 0, loop_frames.saturating_sub(1));
+
+    let mut acid = Vec::with_capacity(24);
+    // Flags: root note set (if known), stretch
+    let flags: u32 = if key.is_some() { 0x02 | 0x04 } else { 0x04 };
+    acid.extend_from_slice(&flags.to_le_bytes());
+    let root_note = key.map_or(0x3c, |key| 0x30 + key.tonic as u16);
+    acid.extend_from_slice(&root_note.to_le_bytes());
+    acid.extend_from_slice(&0x8000u16.to_le_bytes());
+    acid.extend_from_slice(&0f32.to_le_bytes());
+    acid.extend_from_slice(&beats.to_le_bytes());
+    acid.extend_from_slice(&4u16.to_le_bytes()); // meter denominator
+    acid.extend_from_slice(&(BEATS_PER_BAR as u16).to_le_bytes()); // meter numerator
+    acid.extend_from_slice(&(bpm as f32).to_le_bytes());
+
+    append_wav_chunks(path, &[(b"smpl", &smpl), (b"acid", &acid)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_bpm_and_loop_length() {
+        assert!((bpm(125.0, 6) - 125.0).abs() < 1e-9);
+        assert!((bpm(125.0, 3) - 250.0).abs() < 1e-9);
+
+        // A bar at 120 BPM lasts 2 seconds
+        assert_eq!(loop_length(44100 * 5, 44100, 120.0), (44100 * 4, 2));
+        assert_eq!(loop_length(1000, 44100, 120.0), (1000, 0));
+        assert_eq!(loop_beats(5.0, 2, 120.0), 8);
+        assert_eq!(loop_beats(1.0, 0, 120.0), 2);
+    }
+
+    #[test]
+    fn test_estimate_key() {
+        // Notes of a C major scale, with the tonic and dominant played most
+        let mut histogram = [0.0; 12];
+        for (pitch, count) in [
+            (0, 8.0),
+            (2, 3.0),
+            (4, 5.0),
+            (5, 3.0),
+            (7, 6.0),
+            (9, 3.0),
+            (11, 2.0),
+        ] {
+            histogram[pitch] = count;
+        }
+        let key = Key::estimate(&histogram).unwrap();
+        assert_eq!(key.to_string(), "C major");
+
+        // The same notes centred on A
+        histogram[0] = 4.0;
+        histogram[7] = 3.0;
+        histogram[9] = 8.0;
+        histogram[4] = 6.0;
+        assert_eq!(Key::estimate(&histogram).unwrap().to_string(), "A minor");
+
+        assert_eq!(Key::estimate(&[0.0; 12]), None);
+    }
+
+    #[test]
+    fn test_tag_wav() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("loop.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for i in 0..200 {
+            writer.write_sample(i as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        tag_wav(&path, 44100, 80, 120.0, 4, None).unwrap();
+
+        // The audio is still readable, and the chunks are accounted for in the RIFF size
+        let reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.duration(), 100);
+        let data = fs::read(&path).unwrap();
+        let riff_size = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+        assert_eq!(riff_size, data.len() - 8);
+        let smpl = data.windows(4).position(|w| w == b"smpl").unwrap();
+        let loop_end = u32::from_le_bytes(data[smpl + 8 + 48..smpl + 8 + 52].try_into().unwrap());
+        assert_eq!(loop_end, 79);
+        assert!(data.windows(4).any(|w| w == b"acid"));
+
+        let mp3 = dir.path().join("not.wav");
+        fs::write(&mp3, b"ID3").unwrap();
+        assert!(tag_wav(&mp3, 44100, 1, 120.0, 1, None).is_err());
+    }
+}
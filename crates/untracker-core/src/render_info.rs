@@ -0,0 +1,243 @@
+//! Provenance of an output directory, written by `--render-info`: the exact
+//! options a module was rendered with, once the command line, override files
+//! and module defaults are merged, which module it was and how long it took.
+//!
+//! Unlike the manifest, which describes the files, this describes the run, so
+//! the same stems can be rendered again years later.
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::audio::{ExportOptions, Tag};
+use crate::automation::MuteEvent;
+use crate::manifest::RenderSettings;
+use crate::mix_automation::{MixAutomation, MixPoint};
+
+/// Name of the file describing the run, written next to the stems.
+pub const RENDER_INFO_FILE: &str = "render-info.json";
+
+/// Every option a module was rendered with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolvedOptions {
+    #[serde(flatten)]
+    pub settings: RenderSettings,
+    pub opus_bitrate: u32,
+    /// Absent from files written before Opus encoder settings were configurable
+    #[serde(default)]
+    pub opus_vbr: Option<String>,
+    #[serde(default)]
+    pub opus_complexity: Option<u32>,
+    #[serde(default)]
+    pub opus_application: Option<String>,
+    /// Channels libopenmpt rendered, if not those written
+    #[serde(default)]
+    pub render_channels: Option<u32>,
+    pub vorbis_quality: u32,
+    pub mp3_bitrate: u32,
+    pub mp3_vbr_quality: Option<u32>,
+    /// Length in samples the stems were padded to, if they were
+    pub pad_to_frames: Option<u64>,
+    pub reproducible: bool,
+    /// Channels muted and unmuted during the render by `--mute-automation`
+    #[serde(default)]
+    pub mute_automation: Vec<MuteEvent>,
+    /// Points of the volume and panning curves of `--mix-automation`
+    #[serde(default)]
+    pub mix_automation: Vec<MixPoint>,
+}
+
+impl From<&ExportOptions> for ResolvedOptions {
+    fn from(options: &ExportOptions) -> Self {
+        ResolvedOptions {
+            settings: options.into(),
+            opus_bitrate: options.opus_bitrate,
+            opus_vbr: Some(options.opus_vbr.name().to_string()),
+            opus_complexity: Some(options.opus_complexity),
+            opus_application: Some(options.opus_application.name().to_string()),
+            render_channels: options.render_channels,
+            vorbis_quality: options.vorbis_quality,
+            mp3_bitrate: options.mp3_bitrate,
+            mp3_vbr_quality: options.mp3_vbr_quality,
+            pad_to_frames: options.pad_to_frames,
+            reproducible: options.reproducible,
+            mute_automation: options
+                .mute_automation
+                .as_deref()
+                .map(|automation| automation.events().to_vec())
+                .unwrap_or_default(),
+            mix_automation: options
+                .mix_automation
+                .as_deref()
+                .map(MixAutomation::points)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// The module file a run rendered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputFile {
+    pub path: PathBuf,
+    /// Size in bytes
+    pub size: u64,
+    /// SHA-256 of the file, in hex
+    pub sha256: String,
+}
+
+impl InputFile {
+    pub fn new(path: &Path, contents: &[u8]) -> Self {
+        InputFile {
+            path: path.to_path_buf(),
+            size: contents.len() as u64,
+            sha256: format!("{:x}", Sha256::digest(contents)),
+        }
+    }
+}
+
+/// When a run started and finished, in local time (RFC 3339).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenderTimings {
+    pub started: String,
+    pub finished: String,
+    pub elapsed_seconds: f64,
+}
+
+impl RenderTimings {
+    /// Timings of a run that started at `started` and finished now.
+    pub fn since(started: DateTime<Local>) -> Self {
+        let finished = Local::now();
+        RenderTimings {
+            started: started.to_rfc3339(),
+            finished: finished.to_rfc3339(),
+            elapsed_seconds: (finished - started).num_milliseconds() as f64 / 1000.0,
+        }
+    }
+}
+
+/// Everything about how an output directory was made, written as `render-info.json`.
+///
+/// With reproducible output, the version of untracker and the timings are
+/// left out, so that two runs write the same file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenderInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub untracker_version: Option<String>,
+    pub libopenmpt_version: Option<String>,
+    /// Arguments untracker was run with, starting with the program
+    pub command_line: Vec<String>,
+    pub input: InputFile,
+    /// Override file whose options were applied to the module, if any
+    pub overrides: Option<PathBuf>,
+    pub options: ResolvedOptions,
+    /// Tags added to encoded files with `--tag`
+    pub tags: Vec<Tag>,
+    /// Files written, relative to the directory
+    pub files: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timings: Option<RenderTimings>,
+}
+
+impl RenderInfo {
+    pub fn new(
+        input: InputFile,
+        overrides: Option<PathBuf>,
+        options: &ExportOptions,
+        tags: &[Tag],
+        timings: RenderTimings,
+    ) -> Self {
+        let reproducible = options.reproducible;
+        RenderInfo {
+            untracker_version: (!reproducible).then(|| env!("CARGO_PKG_VERSION").to_string()),
+            libopenmpt_version: openmpt::info::get_string(
+                &openmpt::info::InfoField::LibraryVersion,
+            ),
+            command_line: std::env::args().collect(),
+            input,
+            overrides,
+            options: options.into(),
+            tags: tags.to_vec(),
+            files: Vec::new(),
+            timings: (!reproducible).then_some(timings),
+        }
+    }
+
+    /// Write the info to `dir`, listing the files already in it other than
+    /// hidden ones.
+    pub fn write(mut self, dir: &Path) -> Result<PathBuf> {
+        let mut files = fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.file_name().to_string_lossy().into_owned()))
+            .filter(|name| !name.as_ref().is_ok_and(|name| name.starts_with('.')))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        files.sort();
+        self.files = files;
+
+        let path = dir.join(RENDER_INFO_FILE);
+        fs::write(&path, serde_json::to_string_pretty(&self)?)?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_info_json() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("song_sample_001.wav"), b"RIFF").unwrap();
+
+        let options = ExportOptions {
+            mp3_vbr_quality: Some(2),
+            ..ExportOptions::default()
+        };
+        let input = InputFile::new(Path::new("modules/song.mod"), b"abc");
+        let tags = [("GENRE".to_string(), "Chiptune".to_string())];
+        let info = RenderInfo::new(
+            input,
+            None,
+            &options,
+            &tags,
+            RenderTimings::since(Local::now()),
+        );
+        let path = info.write(dir.path()).unwrap();
+        assert_eq!(path, dir.path().join(RENDER_INFO_FILE));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            json["input"]["sha256"],
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(json["input"]["size"], 3);
+        // Settings of the manifest and the rest of the options side by side
+        assert_eq!(json["options"]["format"], "wav");
+        assert_eq!(json["options"]["mp3_vbr_quality"], 2);
+        assert_eq!(json["options"]["opus_vbr"], "on");
+        assert_eq!(json["tags"][0][1], "Chiptune");
+        assert_eq!(json["files"], serde_json::json!(["song_sample_001.wav"]));
+        assert!(json["timings"]["elapsed_seconds"].as_f64().unwrap() >= 0.0);
+        assert_eq!(json["untracker_version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn test_reproducible_render_info() {
+        let options = ExportOptions {
+            reproducible: true,
+            ..ExportOptions::default()
+        };
+        let write = |started| {
+            let dir = tempfile::tempdir().unwrap();
+            let input = InputFile::new(Path::new("modules/song.mod"), b"abc");
+            let info = RenderInfo::new(input, None, &options, &[], RenderTimings::since(started));
+            fs::read_to_string(info.write(dir.path()).unwrap()).unwrap()
+        };
+
+        let first = write(Local::now() - chrono::Duration::hours(1));
+        assert_eq!(first, write(Local::now()));
+        assert!(!first.contains("timings") && !first.contains("untracker_version"));
+    }
+}
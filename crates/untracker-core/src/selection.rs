@@ -0,0 +1,89 @@
+//! Selection of a subset of stems, as given to `--stems`.
+
+use anyhow::{anyhow, Result};
+use openmpt::index::InstrumentIndex;
+use std::ops::RangeInclusive;
+
+/// A set of 1-based stem numbers, such as `1,3,7-12`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StemSelection {
+    ranges: Vec<RangeInclusive<i32>>,
+}
+
+impl StemSelection {
+    pub fn contains(&self, number: i32) -> bool {
+        self.ranges.iter().any(|range| range.contains(&number))
+    }
+
+    /// Indices of the selected stems out of `count`, in ascending order.
+    ///
+    /// Fails if the selection names a stem the module doesn't have.
+    pub fn indices(&self, count: i32) -> Result<Vec<InstrumentIndex>> {
+        if let Some(range) = self.ranges.iter().find(|range| *range.end() > count) {
+            return Err(anyhow!(
+                "Stem {} selected, but the module only has {} stems",
+                range.end(),
+                count
+            ));
+        }
+
+        Ok(InstrumentIndex::all(count)
+            .filter(|i| self.contains(i.number()))
+            .collect())
+    }
+}
+
+impl std::str::FromStr for StemSelection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_number = |n: &str| -> Result<i32> {
+            match n.trim().parse::<i32>() {
+                Ok(number) if number >= 1 => Ok(number),
+                _ => Err(anyhow!(
+                    "Invalid stem number '{}': stems are numbered from 1",
+                    n.trim()
+                )),
+            }
+        };
+
+        let ranges = s
+            .split(',')
+            .map(|part| match part.split_once('-') {
+                Some((start, end)) => {
+                    let (start, end) = (parse_number(start)?, parse_number(end)?);
+                    if start > end {
+                        return Err(anyhow!("Invalid stem range '{}'", part.trim()));
+                    }
+                    Ok(start..=end)
+                }
+                None => parse_number(part).map(|number| number..=number),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(StemSelection { ranges })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_select() {
+        let selection: StemSelection = "1,3, 7-9".parse().unwrap();
+        let indices: Vec<i32> = selection
+            .indices(10)
+            .unwrap()
+            .into_iter()
+            .map(InstrumentIndex::get)
+            .collect();
+        assert_eq!(indices, vec![0, 2, 6, 7, 8]);
+        assert!(selection.indices(8).is_err());
+
+        assert!("".parse::<StemSelection>().is_err());
+        assert!("0".parse::<StemSelection>().is_err());
+        assert!("5-2".parse::<StemSelection>().is_err());
+        assert!("1,x".parse::<StemSelection>().is_err());
+    }
+}
@@ -0,0 +1,71 @@
+//! The HTTP server of `untracker serve`, built with tiny_http: every request
+//! is answered by the [`StemService`] on a thread of its own, so listings
+//! and uploads don't wait for renders.
+
+use anyhow::{anyhow, Result};
+use std::io::Read;
+use std::sync::Arc;
+use tiny_http::{Header, Request, Server};
+
+use crate::service::{StemService, MAX_UPLOAD_BYTES};
+
+/// Serve the REST API of `service` on `address`, such as `127.0.0.1:8080`,
+/// until the process is stopped.
+pub fn run(address: &str, service: StemService) -> Result<()> {
+    let server =
+        Server::http(address).map_err(|e| anyhow!("Failed to listen on {}: {}", address, e))?;
+    println!("Serving stems on http://{}", address);
+    let service = Arc::new(service);
+    for request in server.incoming_requests() {
+        let service = service.clone();
+        std::thread::spawn(move || answer(&service, request));
+    }
+    Ok(())
+}
+
+fn answer(service: &StemService, mut request: Request) {
+    let method = request.method().to_string();
+    let url = request.url().to_string();
+    // One byte over the limit is enough to refuse the upload
+    let mut body = Vec::new();
+    let response = match request
+        .as_reader()
+        .take(MAX_UPLOAD_BYTES as u64 + 1)
+        .read_to_end(&mut body)
+    {
+        Ok(_) => service.handle(&method, &url, body),
+        Err(e) => {
+            log::warn!("Failed to read the body of {} {}: {}", method, url, e);
+            return;
+        }
+    };
+    log::info!("{} {} {}", method, url, response.status);
+
+    let mut reply = tiny_http::Response::from_data(response.body)
+        .with_status_code(response.status)
+        .with_header(header("Content-Type", response.content_type));
+    if let Some(file_name) = response.file_name {
+        // Header values are ASCII, and quotes would end the name
+        let file_name: String = file_name
+            .chars()
+            .map(|c| {
+                if (c.is_ascii_graphic() && c != '"') || c == ' ' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        reply = reply.with_header(header(
+            "Content-Disposition",
+            &format!("attachment; filename=\"{}\"", file_name),
+        ));
+    }
+    if let Err(e) = request.respond(reply) {
+        log::warn!("Failed to answer {} {}: {}", method, url, e);
+    }
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name, value).expect("headers are ASCII")
+}
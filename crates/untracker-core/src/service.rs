@@ -0,0 +1,690 @@
+//! The REST API of `untracker serve`, apart from the HTTP server itself:
+//! modules uploaded to it, their stem listings and their rendered stems.
+//!
+//! - `POST /modules`, with the module file as the body and optionally
+//!   `?name=song.xm` (needed to unpack archives): keep the module, and answer
+//!   with its listing
+//! - `GET /modules/<id>`: the listing of a module, as JSON
+//! - `GET /modules/<id>/stems/<n>`: stem `n`, numbered from 1, rendered with
+//!   the options of the query (see [`render_options`])
+//! - `PATCH /modules/<id>`: change the options the stems of a module are
+//!   rendered with, as the query of a stem does, and render again the stems
+//!   already rendered that they change (see [`Rerender`])
+//! - `DELETE /modules/<id>`: forget a module
+//!
+//! Errors are answered with a JSON object holding an `error` message.
+
+use anyhow::{anyhow, Result};
+use openmpt::index::{InstrumentIndex, SubsongIndex};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::cache::RenderCache;
+use crate::container::output_base_name;
+use crate::listing::ModuleListing;
+use crate::manifest::RenderSettings;
+use crate::unpack::{module_stem, unpack};
+use crate::{
+    module_metadata, render_stem, stem_file_name, ExportOptions, Extractor, NameTemplate,
+    NormalizeMode, StemLabels,
+};
+
+/// Modules kept at once; uploading more forgets the oldest.
+pub const MAX_MODULES: usize = 64;
+
+/// Largest module file that can be uploaded.
+pub const MAX_UPLOAD_BYTES: usize = 64 * 1024 * 1024;
+
+/// An answer to a request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+    /// Name to save the body as, for rendered stems
+    pub file_name: Option<String>,
+}
+
+impl Response {
+    fn json(status: u16, value: &impl Serialize) -> Self {
+        Response {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_vec_pretty(value).unwrap_or_default(),
+            file_name: None,
+        }
+    }
+
+    fn error(status: u16, message: impl std::fmt::Display) -> Self {
+        #[derive(Serialize)]
+        struct Error {
+            error: String,
+        }
+        Response::json(
+            status,
+            &Error {
+                error: message.to_string(),
+            },
+        )
+    }
+}
+
+/// A stem of a served module.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServedStem {
+    /// Number of the stem, from 1
+    pub number: i32,
+    pub name: String,
+    /// Where to get the stem rendered
+    pub url: String,
+}
+
+/// The listing of a served module, with the stems that can be rendered.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServedModule {
+    pub id: String,
+    /// Whether the stems are instruments rather than samples
+    pub stems_are_instruments: bool,
+    pub stems: Vec<ServedStem>,
+    #[serde(flatten)]
+    pub listing: ModuleListing,
+}
+
+/// What a `PATCH` of a module did.
+#[derive(Debug, Clone, Serialize)]
+pub struct Rerender {
+    /// Names of the settings the request changed, as in manifests
+    pub changes: Vec<String>,
+    /// Numbers of the stems rendered again, into the render cache, since they
+    /// were last rendered with other settings. Without a cache, stems are
+    /// only rendered when next requested
+    pub rendered: Vec<i32>,
+}
+
+/// A module kept by the service.
+struct StoredModule {
+    buffer: Vec<u8>,
+    base_name: String,
+    served: ServedModule,
+    /// Options of its stems where the query doesn't say otherwise
+    options: Mutex<ExportOptions>,
+    /// Settings each stem was last rendered with, by number
+    rendered: Mutex<BTreeMap<usize, RenderSettings>>,
+}
+
+/// The modules kept, and the order they were uploaded in.
+#[derive(Default)]
+struct Modules {
+    by_id: BTreeMap<String, Arc<StoredModule>>,
+    order: VecDeque<String>,
+}
+
+/// A limit on the renders running at once: requests for more wait their turn.
+struct RenderSlots {
+    free: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl RenderSlots {
+    fn acquire(&self) -> RenderSlot<'_> {
+        let mut free = self.free.lock().unwrap();
+        while *free == 0 {
+            free = self.freed.wait(free).unwrap();
+        }
+        *free -= 1;
+        RenderSlot { slots: self }
+    }
+}
+
+/// A render allowed to run until dropped.
+struct RenderSlot<'a> {
+    slots: &'a RenderSlots,
+}
+
+impl Drop for RenderSlot<'_> {
+    fn drop(&mut self) {
+        *self.slots.free.lock().unwrap() += 1;
+        self.slots.freed.notify_one();
+    }
+}
+
+/// Uploaded modules, and their stems rendered on request.
+pub struct StemService {
+    defaults: ExportOptions,
+    modules: Mutex<Modules>,
+    renders: RenderSlots,
+    /// Directory stems are rendered into before they are sent
+    scratch_dir: PathBuf,
+    cache: Option<RenderCache>,
+    next_render: AtomicU64,
+}
+
+impl StemService {
+    /// Render stems with `defaults` where the query doesn't say otherwise,
+    /// `max_renders` at once, in `scratch_dir` and through `cache` if given.
+    pub fn new(
+        defaults: ExportOptions,
+        max_renders: usize,
+        scratch_dir: &Path,
+        cache: Option<RenderCache>,
+    ) -> Result<Self> {
+        fs::create_dir_all(scratch_dir)?;
+        Ok(StemService {
+            defaults,
+            modules: Mutex::default(),
+            renders: RenderSlots {
+                free: Mutex::new(max_renders.max(1)),
+                freed: Condvar::new(),
+            },
+            scratch_dir: scratch_dir.to_path_buf(),
+            cache,
+            next_render: AtomicU64::new(0),
+        })
+    }
+
+    /// Answer a request for `url` (a path and a query) with `body`.
+    pub fn handle(&self, method: &str, url: &str, body: Vec<u8>) -> Response {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let query = match parse_query(query) {
+            Ok(query) => query,
+            Err(e) => return Response::error(400, e),
+        };
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let response = match (method, segments.as_slice()) {
+            ("POST", ["modules"]) => self.upload(&query, body),
+            ("GET", ["modules", id]) => self
+                .module(id)
+                .map(|module| Response::json(200, &module.served)),
+            ("PATCH", ["modules", id]) => self.rerender(id, &query),
+            ("DELETE", ["modules", id]) => self.delete(id),
+            ("GET", ["modules", id, "stems", stem]) => self.render(id, stem, &query),
+            (_, ["modules"] | ["modules", _] | ["modules", _, "stems", _]) => Err(Response::error(
+                405,
+                format!("{} is not allowed here", method),
+            )),
+            _ => Err(Response::error(404, format!("No such resource: {}", path))),
+        };
+        response.unwrap_or_else(|error| error)
+    }
+
+    fn module(&self, id: &str) -> Result<Arc<StoredModule>, Response> {
+        let modules = self.modules.lock().unwrap();
+        modules
+            .by_id
+            .get(id)
+            .cloned()
+            .ok_or_else(|| Response::error(404, format!("No module {}", id)))
+    }
+
+    fn upload(
+        &self,
+        query: &BTreeMap<String, String>,
+        body: Vec<u8>,
+    ) -> Result<Response, Response> {
+        if body.len() > MAX_UPLOAD_BYTES {
+            return Err(Response::error(
+                413,
+                format!("Modules can be up to {} bytes", MAX_UPLOAD_BYTES),
+            ));
+        }
+        let name = query.get("name").map(String::as_str).unwrap_or("module");
+        let stored = store_module(Path::new(name), body, &self.defaults)
+            .map_err(|e| Response::error(422, e))?;
+        let served = stored.served.clone();
+
+        let mut modules = self.modules.lock().unwrap();
+        if !modules.by_id.contains_key(&served.id) {
+            while modules.order.len() >= MAX_MODULES {
+                if let Some(oldest) = modules.order.pop_front() {
+                    modules.by_id.remove(&oldest);
+                }
+            }
+            modules.order.push_back(served.id.clone());
+            modules.by_id.insert(served.id.clone(), Arc::new(stored));
+        }
+        log::info!("Serving module {} as {}", name, served.id);
+        Ok(Response::json(201, &served))
+    }
+
+    fn delete(&self, id: &str) -> Result<Response, Response> {
+        let mut modules = self.modules.lock().unwrap();
+        if modules.by_id.remove(id).is_none() {
+            return Err(Response::error(404, format!("No module {}", id)));
+        }
+        modules.order.retain(|kept| kept != id);
+        Ok(Response {
+            status: 204,
+            content_type: "application/json",
+            body: Vec::new(),
+            file_name: None,
+        })
+    }
+
+    fn render(
+        &self,
+        id: &str,
+        stem: &str,
+        query: &BTreeMap<String, String>,
+    ) -> Result<Response, Response> {
+        let module = self.module(id)?;
+        let stems = &module.served.stems;
+        let number: usize = stem
+            .parse()
+            .ok()
+            .filter(|number| (1..=stems.len()).contains(number))
+            .ok_or_else(|| {
+                Response::error(
+                    404,
+                    format!("No stem {}: the module has {} stems", stem, stems.len()),
+                )
+            })?;
+        let options = render_options(&module.options.lock().unwrap(), query)
+            .map_err(|e| Response::error(400, e))?;
+        let (body, path) = self
+            .render_file(&module, number, &options)
+            .map_err(|e| Response::error(500, e))?;
+
+        Ok(Response {
+            status: 200,
+            content_type: content_type(options.format.name()),
+            body,
+            file_name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+        })
+    }
+
+    /// Render stem `number` of `module` with `options`, through the cache if
+    /// there is one, and return the file and its name.
+    fn render_file(
+        &self,
+        module: &StoredModule,
+        number: usize,
+        options: &ExportOptions,
+    ) -> Result<(Vec<u8>, PathBuf)> {
+        let index = InstrumentIndex::new(number as i32 - 1);
+        let is_instrument = module.served.stems_are_instruments;
+        let stem = &module.served.stems[number - 1];
+
+        let _slot = self.renders.acquire();
+        let dir = self
+            .scratch_dir
+            .join(self.next_render.fetch_add(1, Ordering::Relaxed).to_string());
+        let render_in_dir = || -> Result<(Vec<u8>, PathBuf)> {
+            fs::create_dir_all(&dir)?;
+            let output_dir = dir
+                .to_str()
+                .ok_or_else(|| anyhow!("{} is not valid UTF-8", dir.display()))?;
+            let render = || {
+                render_stem(
+                    &module.buffer,
+                    index,
+                    is_instrument,
+                    output_dir,
+                    &module.base_name,
+                    options,
+                    &NameTemplate::default(),
+                    &StemLabels::default(),
+                    &[],
+                    None,
+                )
+            };
+            let result = match &self.cache {
+                Some(cache) => {
+                    let type_label = if is_instrument {
+                        "instrument"
+                    } else {
+                        "sample"
+                    };
+                    let key = RenderCache::key(
+                        &module.buffer,
+                        &format!("{} {}", type_label, index),
+                        options,
+                    );
+                    let destination = dir.join(stem_file_name(
+                        &NameTemplate::default(),
+                        &module.base_name,
+                        is_instrument,
+                        index,
+                        &stem.name,
+                        options.format,
+                        &StemLabels::default(),
+                    ));
+                    cache.get_or_render(&key, &destination, render)?
+                }
+                None => render()?,
+            };
+            Ok((fs::read(&result.path)?, result.path))
+        };
+        let result = render_in_dir();
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            log::warn!("Failed to remove {}: {}", dir.display(), e);
+        }
+        if result.is_ok() {
+            module
+                .rendered
+                .lock()
+                .unwrap()
+                .insert(number, RenderSettings::from(options));
+        }
+        result
+    }
+
+    /// Change the options of a module by the parameters of `query`, and
+    /// render again, into the cache, the stems they change.
+    fn rerender(&self, id: &str, query: &BTreeMap<String, String>) -> Result<Response, Response> {
+        let module = self.module(id)?;
+        let (changes, options) = {
+            let mut options = module.options.lock().unwrap();
+            let changed = render_options(&options, query).map_err(|e| Response::error(400, e))?;
+            let changes = RenderSettings::from(&*options).changes(&RenderSettings::from(&changed));
+            *options = changed.clone();
+            (changes, changed)
+        };
+
+        let settings = RenderSettings::from(&options);
+        let stale: Vec<usize> = match self.cache {
+            Some(_) => module
+                .rendered
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, rendered)| !rendered.changes(&settings).is_empty())
+                .map(|(&number, _)| number)
+                .collect(),
+            None => Vec::new(),
+        };
+        for &number in &stale {
+            self.render_file(&module, number, &options)
+                .map_err(|e| Response::error(500, e))?;
+        }
+        log::info!(
+            "Changed {:?} of module {}, rendered {} stems again",
+            changes,
+            id,
+            stale.len()
+        );
+        Ok(Response::json(
+            200,
+            &Rerender {
+                changes,
+                rendered: stale.iter().map(|&number| number as i32).collect(),
+            },
+        ))
+    }
+}
+
+/// Load the module in `data`, uploaded as `name`, and list it with the
+/// names its stems get rendered with `defaults`.
+fn store_module(name: &Path, data: Vec<u8>, defaults: &ExportOptions) -> Result<StoredModule> {
+    let buffer = unpack(name, data)?;
+    let extractor = Extractor::new(buffer.as_slice())?;
+    let mut module = extractor.module_ext().get_module();
+    let base_name = output_base_name(
+        module_stem(name).unwrap_or("module"),
+        &module_metadata(&mut module),
+    );
+    let id: String = format!("{:x}", Sha256::digest(&buffer))
+        .chars()
+        .take(16)
+        .collect();
+
+    let stems: Vec<ServedStem> = extractor
+        .stems()
+        .iter()
+        .map(|stem| ServedStem {
+            number: stem.index.number(),
+            name: stem.name.clone(),
+            url: format!("/modules/{}/stems/{}", id, stem.index.number()),
+        })
+        .collect();
+    let stems_are_instruments = extractor
+        .stems()
+        .first()
+        .is_some_and(|stem| stem.is_instrument);
+    let mut listing = ModuleListing::new(name, &mut module);
+    listing.outputs = extractor
+        .stems()
+        .iter()
+        .map(|stem| {
+            PathBuf::from(stem_file_name(
+                &NameTemplate::default(),
+                &base_name,
+                stem.is_instrument,
+                stem.index,
+                &stem.name,
+                defaults.format,
+                &StemLabels::default(),
+            ))
+        })
+        .collect();
+
+    Ok(StoredModule {
+        buffer,
+        base_name,
+        served: ServedModule {
+            id,
+            stems_are_instruments,
+            stems,
+            listing,
+        },
+        options: Mutex::new(defaults.clone()),
+        rendered: Mutex::default(),
+    })
+}
+
+/// The options to render a stem with: `defaults`, changed by the `format`,
+/// `rate`, `channels`, `bit_depth`, `subsong` (numbered from 1), `preview`
+/// (seconds) and `normalize` (`true` or `false`) parameters of `query`.
+pub fn render_options(
+    defaults: &ExportOptions,
+    query: &BTreeMap<String, String>,
+) -> Result<ExportOptions> {
+    let mut options = defaults.clone();
+    for (key, value) in query {
+        let invalid = || anyhow!("Invalid {}: {}", key, value);
+        match key.as_str() {
+            "format" => options.format = value.parse()?,
+            "rate" => options.sample_rate = value.parse().map_err(|_| invalid())?,
+            "channels" => options.channels = value.parse().map_err(|_| invalid())?,
+            "bit_depth" => options.bit_depth = value.parse().map_err(|_| invalid())?,
+            "subsong" => {
+                let number: i32 = value.parse().map_err(|_| invalid())?;
+                options.subsong = Some(SubsongIndex::new(number - 1));
+            }
+            "preview" => options.preview = Some(value.parse().map_err(|_| invalid())?),
+            "normalize" => {
+                options.normalize = match value.as_str() {
+                    "true" => NormalizeMode::Peak,
+                    "false" => NormalizeMode::Off,
+                    _ => return Err(invalid()),
+                }
+            }
+            _ => return Err(anyhow!("Unknown parameter: {}", key)),
+        }
+    }
+    options.validate()?;
+    Ok(options)
+}
+
+/// The parameters of a query such as `format=flac&rate=48000`, decoded.
+fn parse_query(query: &str) -> Result<BTreeMap<String, String>> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((decode(key)?, decode(value)?))
+        })
+        .collect()
+}
+
+/// A query parameter with its `+` and `%XX` escapes decoded.
+fn decode(s: &str) -> Result<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        bytes.push(match byte {
+            b'+' => b' ',
+            b'%' => {
+                let hex = rest
+                    .get(..2)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| anyhow!("Invalid escape in query: {}", s))?;
+                rest = &rest[2..];
+                hex
+            }
+            _ => byte,
+        });
+    }
+    String::from_utf8(bytes).map_err(|_| anyhow!("Query is not valid UTF-8: {}", s))
+}
+
+/// MIME type of files of the format named `format`.
+fn content_type(format: &str) -> &'static str {
+    match format {
+        "vorbis" => "audio/ogg",
+        "opus" => "audio/ogg; codecs=opus",
+        "flac" => "audio/flac",
+        "mp3" => "audio/mpeg",
+        _ => "audio/wav",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_options() {
+        let query =
+            parse_query("format=wav&rate=22050&channels=1&preview=2.5&normalize=true").unwrap();
+        let options = render_options(&ExportOptions::default(), &query).unwrap();
+        assert_eq!(options.sample_rate, 22050);
+        assert_eq!(options.channels, 1);
+        assert_eq!(options.preview, Some(2.5));
+        assert_eq!(options.normalize, NormalizeMode::Peak);
+
+        assert_eq!(
+            parse_query("name=my+song%21.xm").unwrap()["name"],
+            "my song!.xm"
+        );
+        assert!(parse_query("name=%4").is_err());
+        let bad = |query| render_options(&ExportOptions::default(), &parse_query(query).unwrap());
+        assert!(bad("rate=fast").is_err());
+        assert!(bad("channels=3").is_err());
+        assert!(bad("speed=2").is_err());
+    }
+
+    #[test]
+    fn test_serve_stems() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = StemService::new(ExportOptions::default(), 2, dir.path(), None).unwrap();
+        let module = include_bytes!("../../../tests/modules/cndmcrrp.mod").to_vec();
+
+        let response = service.handle("POST", "/modules?name=cndmcrrp.mod", module);
+        assert_eq!(response.status, 201);
+        let listing: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+        let id = listing["id"].as_str().unwrap();
+        assert!(!listing["stems_are_instruments"].as_bool().unwrap());
+        let url = listing["stems"][0]["url"].as_str().unwrap();
+        assert_eq!(url, format!("/modules/{}/stems/1", id));
+        assert_eq!(
+            service
+                .handle("GET", &format!("/modules/{}", id), vec![])
+                .status,
+            200
+        );
+
+        let stem = service.handle("GET", &format!("{}?preview=1&channels=1", url), vec![]);
+        assert_eq!(stem.status, 200, "{}", String::from_utf8_lossy(&stem.body));
+        assert_eq!(stem.content_type, "audio/wav");
+        assert_eq!(&stem.body[..4], b"RIFF");
+        assert!(stem.file_name.unwrap().starts_with("cndmcrrp"));
+        // Nothing is left behind in the scratch directory
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let status = |method, url: &str| service.handle(method, url, vec![]).status;
+        assert_eq!(status("GET", &format!("/modules/{}/stems/1000", id)), 404);
+        assert_eq!(status("GET", &format!("{}?format=aiff", url)), 400);
+        assert_eq!(status("PUT", &format!("/modules/{}", id)), 405);
+        assert_eq!(status("GET", "/stems"), 404);
+        assert_eq!(
+            service
+                .handle("POST", "/modules", b"not a module".to_vec())
+                .status,
+            422
+        );
+        assert_eq!(status("DELETE", &format!("/modules/{}", id)), 204);
+        assert_eq!(status("GET", &format!("/modules/{}", id)), 404);
+    }
+
+    #[test]
+    fn test_rerender_stems() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = RenderCache::new(&dir.path().join("cache"), 1 << 30).unwrap();
+        let service = StemService::new(
+            ExportOptions {
+                preview: Some(1.0),
+                ..ExportOptions::default()
+            },
+            2,
+            &dir.path().join("scratch"),
+            Some(cache),
+        )
+        .unwrap();
+        let module = include_bytes!("../../../tests/modules/cndmcrrp.mod").to_vec();
+        let response = service.handle("POST", "/modules?name=cndmcrrp.mod", module);
+        let listing: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+        let id = listing["id"].as_str().unwrap();
+        let url = listing["stems"][1]["url"].as_str().unwrap();
+        assert_eq!(service.handle("GET", url, vec![]).status, 200);
+        let cached = || fs::read_dir(dir.path().join("cache")).unwrap().count();
+        let before = cached();
+
+        let patch = |query: &str| {
+            let response = service.handle("PATCH", &format!("/modules/{}{}", id, query), vec![]);
+            assert_eq!(
+                response.status,
+                200,
+                "{}",
+                String::from_utf8_lossy(&response.body)
+            );
+            serde_json::from_slice::<serde_json::Value>(&response.body).unwrap()
+        };
+        // Only the stem that was requested is rendered again
+        let rerender = patch("?normalize=true");
+        assert!(rerender["changes"]
+            .as_array()
+            .unwrap()
+            .contains(&"normalize".into()));
+        assert_eq!(rerender["rendered"], serde_json::json!([2]));
+        let after = cached();
+        assert!(after > before);
+        // and is then served from the cache
+        let stem = service.handle("GET", url, vec![]);
+        assert_eq!(stem.status, 200);
+        assert_eq!(cached(), after);
+
+        let rerender = patch("?normalize=true");
+        assert_eq!(rerender["changes"], serde_json::json!([]));
+        assert_eq!(rerender["rendered"], serde_json::json!([]));
+        let status = |query: &str| {
+            service
+                .handle("PATCH", &format!("/modules/{}{}", id, query), vec![])
+                .status
+        };
+        assert_eq!(status("?rate=fast"), 400);
+        assert_eq!(
+            service.handle("PATCH", "/modules/nothing", vec![]).status,
+            404
+        );
+    }
+}
@@ -0,0 +1,1020 @@
+//! A loaded module, configured for rendering, that can be positioned before reading audio.
+
+use anyhow::{anyhow, Result};
+use openmpt::ext::ModuleExt;
+use openmpt::index::{ChannelIndex, InstrumentIndex};
+use openmpt::module::ctls::{Ctl, DitherMode, PlayAtEnd};
+use openmpt::module::Logger;
+use std::path::PathBuf;
+
+use crate::audio::channels::ChannelConverter;
+use crate::audio::dsp::Sample;
+use crate::audio::{ExportOptions, SongRange};
+use crate::automation::MuteAutomation;
+use crate::mask::MuteMask;
+use crate::mix_automation::{MixAutomation, MixParam};
+use crate::progress::ProgressSink;
+use crate::{custom_file_name, StemRenderResult};
+
+/// A libopenmpt ctl set before rendering, as a key and a value, such as
+/// `render.resampler.emulate_amiga` and `1`.
+pub type CtlSetting = (String, String);
+
+/// Parse a `key=value` ctl given on the command line.
+pub fn parse_ctl(s: &str) -> Result<CtlSetting> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected KEY=VALUE, got {:?}", s))?;
+    let key = key.trim();
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return Err(anyhow!("Invalid ctl name {:?}", key));
+    }
+    Ok((key.to_string(), value.trim().to_string()))
+}
+
+/// Load a module with `ctls` set, failing if libopenmpt doesn't know one of
+/// them rather than rendering without it.
+pub fn load_module(buffer: &[u8], logger: Logger, ctls: &[CtlSetting]) -> Result<ModuleExt> {
+    let init_ctls: Vec<Ctl> = ctls
+        .iter()
+        .map(|(key, value)| Ctl::Custom(key.clone(), value.clone()))
+        .collect();
+    let module_ext = ModuleExt::from_memory(buffer, logger, &init_ctls)
+        .map_err(|_| anyhow!("Failed to load module"))?;
+    if !ctls.is_empty() {
+        let known = module_ext.get_module().get_ctls();
+        if let Some((key, _)) = ctls
+            .iter()
+            .find(|(key, _)| !known.split(';').any(|known| known == key))
+        {
+            return Err(anyhow!(
+                "libopenmpt has no ctl '{}' (known ctls: {})",
+                key,
+                known.replace(';', ", ")
+            ));
+        }
+    }
+    Ok(module_ext)
+}
+
+/// Where to move the playback position of a [`RenderSession`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Seek {
+    /// Start of the song
+    Start,
+    /// Time in seconds from the start of the song.
+    ///
+    /// Approximate: libopenmpt lands on the row boundary at or just before the
+    /// requested time, and times past the end clamp to the end of the song. The
+    /// position actually reached is returned by [`RenderSession::seek`].
+    Seconds(f64),
+    /// Start of a row in the order list.
+    ///
+    /// Exact: rendering after the seek starts at the first tick of the row,
+    /// and matches rendering from the start of the song and discarding
+    /// everything before the row, up to the settling of interpolation and
+    /// volume ramping in the first milliseconds.
+    OrderRow { order: i32, row: i32 },
+}
+
+/// Playback position of a [`RenderSession`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    /// Frames rendered since the last rewind, which keeps counting when the
+    /// song jumps back
+    pub frame: u64,
+    /// Time in the song, which jumps back with it
+    pub seconds: f64,
+    pub order: i32,
+    pub row: i32,
+    pub pattern: i32,
+}
+
+/// Called with the playback position as a [`RenderSession`] renders, see
+/// [`RenderSession::on_position`].
+type PositionCallback = Box<dyn FnMut(Position) + Send>;
+
+/// A module loaded from memory with the render parameters from [`ExportOptions`] applied.
+pub struct RenderSession {
+    module_ext: ModuleExt,
+    options: ExportOptions,
+    /// Start of the range of the song that is rendered
+    start: Seek,
+    start_seconds: f64,
+    /// Length of the range in seconds, if it ends before the song does
+    length: Option<f64>,
+    /// Frames read since the last rewind, which is where mute automation
+    /// counts from
+    frames_read: u64,
+    /// Next event of the mute automation to apply
+    next_event: usize,
+    /// Channels muted by the automation, which are the only ones it unmutes
+    automation_muted: Vec<ChannelIndex>,
+    /// Frame at which the curves of the mix automation are applied next
+    next_mix_update: u64,
+    /// Frames between calls of the position callback, and the callback
+    position_callback: Option<(usize, PositionCallback)>,
+    /// Conversion of the channels rendered to the channels read, if they differ
+    converter: Option<ChannelConverter>,
+    /// Channels rendered, before they are converted
+    rendered: ChannelScratch,
+}
+
+/// Buffers the channels rendered by a [`RenderSession`] are kept in until
+/// they are converted, one per sample type.
+#[derive(Default)]
+pub struct ChannelScratch {
+    int: Vec<i16>,
+    float: Vec<f32>,
+}
+
+/// Frames between updates of the channel volumes and panning set by
+/// [`ExportOptions::mix_automation`]. They are set again even where curves
+/// are flat, since notes can reset the panning of their channel, and the steps
+/// of ramps are short enough for libopenmpt's volume ramping to smooth over.
+const MIX_STEP_FRAMES: u64 = 64;
+
+impl RenderSession {
+    pub fn new(buffer: &[u8], options: &ExportOptions) -> Result<Self> {
+        let module_ext = load_module(buffer, Logger::None, &options.ctls)?;
+
+        let mut module = module_ext.get_module();
+
+        // Configure render parameters
+        module.set_render_interpolation_filter_length(options.resample.to_openmpt_filter_length());
+        module.set_render_stereo_separation(options.stereo_separation);
+        // libopenmpt takes the gain in milliBel, hundredths of a dB
+        module.set_render_mastergain_millibel((options.master_gain_db * 100.0).round() as i32);
+        if let Some(ramping) = options.volume_ramping {
+            module.set_render_volume_ramping(ramping);
+        }
+        // Without this, samples that were already playing stay silent after a seek
+        module.ctl_set_seek_sync_samples(true);
+        // Play the song the requested number of times, and have reads return 0
+        // frames right at its end rather than after a fade-out (libopenmpt
+        // before 0.7 always stops there). Looping renders read on past the
+        // end, where the song continues from its loop point
+        module.set_repeat_count(i32::try_from(options.loop_count).unwrap_or(i32::MAX));
+        module.ctl_set_play_at_end(if options.loop_crossfade.is_some() {
+            PlayAtEnd::Continue
+        } else {
+            PlayAtEnd::Stop
+        });
+        if options.reproducible {
+            // The dither noise is seeded randomly for every module instance
+            module.ctl_set_dither(DitherMode::None);
+        }
+        if let Some(subsong) = options.subsong {
+            crate::select_subsong(&mut module, subsong)?;
+        }
+        let converter = match options.render_channels {
+            Some(channels) if channels != options.channels => {
+                Some(ChannelConverter::new(channels, options.channels)?)
+            }
+            _ => None,
+        };
+
+        let mut session = RenderSession {
+            module_ext,
+            options: options.clone(),
+            start: Seek::Start,
+            start_seconds: 0.0,
+            length: None,
+            frames_read: 0,
+            next_event: 0,
+            automation_muted: Vec::new(),
+            next_mix_update: 0,
+            position_callback: None,
+            converter,
+            rendered: ChannelScratch::default(),
+        };
+        if let Some(range) = options.range {
+            session.enter_range(range)?;
+        }
+        Ok(session)
+    }
+
+    /// Find where `range` starts and how long it lasts, and move to its start.
+    fn enter_range(&mut self, range: SongRange) -> Result<()> {
+        let duration = self.duration_seconds();
+        match range {
+            SongRange::Orders { start, end } => {
+                self.start = Seek::OrderRow {
+                    order: start,
+                    row: 0,
+                };
+                self.start_seconds = self.seek(self.start)?;
+                if let Some(end) = end {
+                    // The range ends where the next order that is played starts
+                    let num_orders = self.module_ext.get_module().get_num_orders();
+                    let end_seconds = (end + 1..num_orders)
+                        .find_map(|order| self.seek(Seek::OrderRow { order, row: 0 }).ok())
+                        .filter(|&seconds| seconds > self.start_seconds);
+                    self.length = end_seconds.map(|seconds| seconds - self.start_seconds);
+                }
+            }
+            SongRange::Seconds { start, end } => {
+                if start >= duration {
+                    return Err(anyhow!(
+                        "Cannot start at {:.1} seconds: the song lasts {:.1} seconds",
+                        start,
+                        duration
+                    ));
+                }
+                self.start = Seek::Seconds(start);
+                self.start_seconds = self.seek(self.start)?;
+                self.length = end.map(|end| end - self.start_seconds);
+            }
+        }
+        self.rewind()?;
+        Ok(())
+    }
+
+    pub fn module_ext(&self) -> &ModuleExt {
+        &self.module_ext
+    }
+
+    pub fn options(&self) -> &ExportOptions {
+        &self.options
+    }
+
+    /// Move the playback position, returning the new position in seconds.
+    ///
+    /// Fails if an [`Seek::OrderRow`] target is outside the song, in which case
+    /// the position is left unchanged.
+    pub fn seek(&mut self, target: Seek) -> Result<f64> {
+        let mut module = self.module_ext.get_module();
+
+        match target {
+            Seek::Start => Ok(module.set_position_order_row(0, 0)),
+            Seek::Seconds(seconds) => Ok(module.set_position_seconds(seconds.max(0.0))),
+            Seek::OrderRow { order, row } => {
+                let seconds = module.set_position_order_row(order, row);
+                if module.get_current_order() != order || module.get_current_row() != row {
+                    return Err(anyhow!(
+                        "Cannot seek to order {} row {}: position is outside the song",
+                        order,
+                        row
+                    ));
+                }
+                Ok(seconds)
+            }
+        }
+    }
+
+    pub fn position(&self) -> Position {
+        let mut module = self.module_ext.get_module();
+        Position {
+            frame: self.frames_read,
+            seconds: module.get_position_seconds(),
+            order: module.get_current_order(),
+            row: module.get_current_row(),
+            pattern: module.get_current_pattern(),
+        }
+    }
+
+    /// Call `callback` with the playback position before each read, for
+    /// visuals, subtitles or pattern displays that follow the audio.
+    ///
+    /// Reads render at most `interval` frames, so that the callback sees
+    /// every position it should, down to the frame with an interval of 1. It
+    /// is called for reads that render nothing at the end of the song too,
+    /// and replaces any callback set before.
+    pub fn on_position(
+        &mut self,
+        interval: usize,
+        callback: impl FnMut(Position) + Send + 'static,
+    ) {
+        self.position_callback = Some((interval.max(1), Box::new(callback)));
+    }
+
+    /// Approximate duration of the song in seconds.
+    pub fn duration_seconds(&self) -> f64 {
+        self.module_ext.get_duration_seconds()
+    }
+
+    /// Move back to the start of the rendered range, the start of the song
+    /// unless [`ExportOptions::range`] says otherwise.
+    ///
+    /// Mute automation starts over: the channels it muted are unmuted. Mix
+    /// automation starts over too.
+    pub fn rewind(&mut self) -> Result<f64> {
+        let seconds = self.seek(self.start)?;
+        if let Some(interactive) = self.module_ext.get_interactive_interface() {
+            for channel in self.automation_muted.drain(..) {
+                interactive.set_channel_mute_status(&self.module_ext, channel, false);
+            }
+        }
+        self.frames_read = 0;
+        self.next_event = 0;
+        self.next_mix_update = 0;
+        Ok(seconds)
+    }
+
+    /// Where the rendered range starts in the song, in seconds.
+    pub fn start_seconds(&self) -> f64 {
+        self.start_seconds
+    }
+
+    /// Approximate length of the rendered range in seconds, played once.
+    pub fn length_seconds(&self) -> f64 {
+        self.length
+            .unwrap_or_else(|| (self.duration_seconds() - self.start_seconds).max(0.0))
+    }
+
+    /// Seconds after which a render from the start of the range stops, from
+    /// the end of the range and the preview and maximum duration options.
+    pub fn time_limit(&self) -> Option<f64> {
+        [self.options.time_limit(), self.length]
+            .into_iter()
+            .flatten()
+            .reduce(f64::min)
+    }
+
+    /// Expected length in seconds of a render from the start of the range.
+    pub fn render_length(&self) -> f64 {
+        let length = self.options.render_duration(self.length_seconds());
+        self.length.map_or(length, |range| range.min(length))
+    }
+
+    /// Render the whole song with only what `mask` selects audible, to
+    /// `<output_dir>/<base_name>_<name>.<ext>` like a stem group.
+    ///
+    /// Masks that libopenmpt can't mute in one pass, such as channels plus an
+    /// instrument, are rendered a pass at a time into memory and summed, and
+    /// can't be looped with [`ExportOptions::loop_crossfade`]. The
+    /// session is left at the end of the song with the mutes of the last pass.
+    /// Encoded files are titled `name` and tagged like stems.
+    pub fn render_custom(
+        &mut self,
+        mask: &MuteMask,
+        name: &str,
+        output_dir: &str,
+        base_name: &str,
+        progress: Option<&dyn ProgressSink>,
+    ) -> Result<StemRenderResult> {
+        let options = self.options.clone();
+        let mut module = self.module_ext.get_module();
+        let num_channels = module.get_num_channels();
+        let num_instruments = match module.get_num_instruments() {
+            0 => module.get_num_samples(),
+            count => count,
+        };
+
+        let passes = mask.passes(num_channels, num_instruments);
+        if passes.is_empty() {
+            return Err(anyhow!("Nothing to render for {}: the mask is empty", name));
+        }
+        if passes.len() > 1 && options.loop_crossfade.is_some() {
+            return Err(anyhow!(
+                "Cannot loop {}: masks that take several passes are not looped",
+                name
+            ));
+        }
+
+        let output_path =
+            PathBuf::from(output_dir).join(custom_file_name(base_name, name, options.format));
+        let tags = crate::tags::file_tags(&mut module, name, None, &[]);
+        log::info!(
+            "Rendering {} in {} passes to {}",
+            name,
+            passes.len(),
+            output_path.display()
+        );
+
+        let mut mixed: Vec<f64> = Vec::new();
+        for pass in &passes {
+            self.rewind()?;
+            {
+                let interactive = self
+                    .module_ext
+                    .get_interactive_interface()
+                    .ok_or_else(|| anyhow!(crate::NO_ISOLATION_ERROR))?;
+                for channel in ChannelIndex::all(num_channels) {
+                    interactive.set_channel_mute_status(
+                        &self.module_ext,
+                        channel,
+                        !pass.channels.contains(&channel),
+                    );
+                }
+                for instrument in InstrumentIndex::all(num_instruments) {
+                    interactive.set_instrument_mute_status(
+                        &self.module_ext,
+                        instrument,
+                        !pass.instruments.contains(&instrument),
+                    );
+                }
+            }
+
+            if passes.len() == 1 {
+                return crate::render_to_file(self, name, progress, output_path, &options, &tags);
+            }
+            if options.render_float() {
+                self.mix_pass::<f32>(&mut mixed, name, progress)?;
+            } else {
+                self.mix_pass::<i16>(&mut mixed, name, progress)?;
+            }
+        }
+
+        if options.render_float() {
+            let mixed: Vec<f32> = mixed.into_iter().map(f32::from_f64).collect();
+            crate::write_from_memory(mixed, output_path, &options, &tags)
+        } else {
+            let mixed: Vec<i16> = mixed.into_iter().map(i16::from_f64).collect();
+            crate::write_from_memory(mixed, output_path, &options, &tags)
+        }
+    }
+
+    /// Render from the current position into memory and add the audio to `mixed`.
+    fn mix_pass<S: RenderSample>(
+        &mut self,
+        mixed: &mut Vec<f64>,
+        name: &str,
+        progress: Option<&dyn ProgressSink>,
+    ) -> Result<()> {
+        let audio: Vec<S> = crate::render_to_memory(self, name, progress)?;
+        if mixed.len() < audio.len() {
+            mixed.resize(audio.len(), 0.0);
+        }
+        for (sum, &sample) in mixed.iter_mut().zip(&audio) {
+            *sum += sample.to_f64();
+        }
+        Ok(())
+    }
+
+    /// Render interleaved audio with the configured channel count into `buf`.
+    ///
+    /// Returns the number of frames rendered, 0 once the song has ended. With
+    /// [`ExportOptions::mute_automation`] or [`ExportOptions::mix_automation`],
+    /// reads stop short at the next event or point so that it applies on the
+    /// exact frame.
+    pub fn read(&mut self, buf: &mut [i16]) -> usize {
+        self.read_samples(buf)
+    }
+
+    /// Render like [`RenderSession::read`], in floating point.
+    ///
+    /// libopenmpt mixes in floating point, so samples keep their precision
+    /// and aren't clipped at full scale.
+    pub fn read_float(&mut self, buf: &mut [f32]) -> usize {
+        self.read_samples(buf)
+    }
+
+    /// Render the channels of [`ExportOptions::render_channels`], and convert
+    /// them to those of [`ExportOptions::channels`] into `buf`.
+    fn read_samples<S: RenderSample>(&mut self, buf: &mut [S]) -> usize {
+        let sample_rate = self.options.sample_rate as i32;
+        let buf = self.automate(buf);
+        let frames = match &self.converter {
+            None => S::render(&self.module_ext, sample_rate, self.options.channels, buf),
+            Some(converter) => {
+                let channels = converter.from_channels();
+                let rendered = S::scratch(&mut self.rendered);
+                rendered.resize(buf.len() / converter.to_channels() * channels, S::default());
+                let frames = S::render(&self.module_ext, sample_rate, channels as u32, rendered);
+                converter.convert(&rendered[..frames * channels], buf);
+                frames
+            }
+        };
+        self.frames_read += frames as u64;
+        frames
+    }
+
+    /// Apply the automation that is due and report the position, and return
+    /// the part of `buf` that can be read before either is again.
+    fn automate<'a, S>(&mut self, buf: &'a mut [S]) -> &'a mut [S] {
+        let channels = self.options.channels as usize;
+        let mut frames = (buf.len() / channels) as u64;
+        if let Some(automation) = self.options.mute_automation.clone() {
+            frames = frames.min(self.apply_mutes(&automation));
+        }
+        if let Some(automation) = self.options.mix_automation.clone() {
+            frames = frames.min(self.apply_mix(&automation));
+        }
+        if let Some(interval) = self.position_callback.as_ref().map(|(i, _)| *i as u64) {
+            frames = frames.min(interval);
+            let position = self.position();
+            if let Some((_, callback)) = &mut self.position_callback {
+                callback(position);
+            }
+        }
+        &mut buf[..frames as usize * channels]
+    }
+
+    /// Apply the mute events that are due, and return the number of frames
+    /// until the next one.
+    ///
+    /// Channels that are already muted, such as by a [`MuteMask`], are left
+    /// muted by the automation.
+    fn apply_mutes(&mut self, automation: &MuteAutomation) -> u64 {
+        let sample_rate = self.options.sample_rate as f64;
+
+        while let Some(event) = automation.events().get(self.next_event) {
+            let frame = (event.seconds * sample_rate).round() as u64;
+            if frame > self.frames_read {
+                return frame - self.frames_read;
+            }
+            self.next_event += 1;
+
+            let Some(interactive) = self.module_ext.get_interactive_interface() else {
+                log::warn!("This build of libopenmpt can't mute channels, ignoring the automation");
+                continue;
+            };
+            let channel = event.channel;
+            if event.mute {
+                // Mutes of the song itself, or of a mask, aren't the automation's to undo
+                if interactive.get_channel_mute_status(&self.module_ext, channel) == Some(false)
+                    && interactive.set_channel_mute_status(&self.module_ext, channel, true)
+                {
+                    self.automation_muted.push(channel);
+                }
+            } else if let Some(n) = self.automation_muted.iter().position(|&c| c == channel) {
+                interactive.set_channel_mute_status(&self.module_ext, channel, false);
+                self.automation_muted.remove(n);
+            }
+        }
+        u64::MAX
+    }
+
+    /// Set the volume and panning of the automated channels if they are due,
+    /// and return the number of frames until they are next.
+    fn apply_mix(&mut self, automation: &MixAutomation) -> u64 {
+        if self.frames_read < self.next_mix_update {
+            return self.next_mix_update - self.frames_read;
+        }
+        let sample_rate = self.options.sample_rate as f64;
+        let seconds = self.frames_read as f64 / sample_rate;
+
+        let interactive = self.module_ext.get_interactive_interface();
+        let interactive2 = self.module_ext.get_interactive2_interface();
+        for curve in automation.curves() {
+            let channel = curve.channel;
+            let value = curve.value_at(seconds);
+            let applied = match curve.param {
+                MixParam::Volume => interactive
+                    .as_ref()
+                    .is_some_and(|i| i.set_channel_volume(&self.module_ext, channel, value)),
+                MixParam::Pan => interactive2
+                    .as_ref()
+                    .is_some_and(|i| i.set_channel_panning(&self.module_ext, channel, value)),
+            };
+            if !applied && self.frames_read == 0 {
+                log::warn!(
+                    "This build of libopenmpt can't set the {:?} of channels, ignoring its automation",
+                    curve.param
+                );
+            }
+        }
+
+        // Points land on their exact frame
+        let next_point = automation
+            .next_point(seconds)
+            .map_or(u64::MAX, |next| (next * sample_rate).round() as u64);
+        self.next_mix_update = (self.frames_read + MIX_STEP_FRAMES)
+            .min(next_point)
+            .max(self.frames_read + 1);
+        self.next_mix_update - self.frames_read
+    }
+}
+
+/// Sample types a [`RenderSession`] renders in.
+pub trait RenderSample: Sample {
+    /// Render interleaved audio of `channels` channels (1, 2 or 4) into `buf`
+    /// from libopenmpt, returning the number of frames rendered.
+    fn render(module_ext: &ModuleExt, sample_rate: i32, channels: u32, buf: &mut [Self]) -> usize;
+
+    /// The buffer of `scratch` these samples are rendered into when their
+    /// channels are converted.
+    fn scratch(scratch: &mut ChannelScratch) -> &mut Vec<Self>;
+
+    /// Render into `buf` from `session`, returning the number of frames rendered.
+    fn read(session: &mut RenderSession, buf: &mut [Self]) -> usize {
+        session.read_samples(buf)
+    }
+}
+
+impl RenderSample for i16 {
+    fn render(module_ext: &ModuleExt, sample_rate: i32, channels: u32, buf: &mut [Self]) -> usize {
+        match channels {
+            1 => module_ext.read_mono(sample_rate, buf),
+            4 => module_ext.read_interleaved_quad(sample_rate, buf),
+            _ => module_ext.read_interleaved_stereo(sample_rate, buf),
+        }
+    }
+
+    fn scratch(scratch: &mut ChannelScratch) -> &mut Vec<Self> {
+        &mut scratch.int
+    }
+}
+
+impl RenderSample for f32 {
+    fn render(module_ext: &ModuleExt, sample_rate: i32, channels: u32, buf: &mut [Self]) -> usize {
+        match channels {
+            1 => module_ext.read_float_mono(sample_rate, buf),
+            4 => module_ext.read_interleaved_float_quad(sample_rate, buf),
+            _ => module_ext.read_interleaved_float_stereo(sample_rate, buf),
+        }
+    }
+
+    fn scratch(scratch: &mut ChannelScratch) -> &mut Vec<Self> {
+        &mut scratch.float
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn session() -> RenderSession {
+        let buffer = std::fs::read("../../tests/modules/cndmcrrp.mod").unwrap();
+        RenderSession::new(&buffer, &ExportOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn test_render_custom() {
+        let buffer = std::fs::read("../../tests/modules/cndmcrrp.mod").unwrap();
+        let options = ExportOptions {
+            preview: Some(2.0),
+            ..ExportOptions::default()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().to_str().unwrap();
+
+        // A single instrument renders like its stem
+        let mut session = RenderSession::new(&buffer, &options).unwrap();
+        let mask = MuteMask::new().instruments([InstrumentIndex::new(1)]);
+        let custom = session
+            .render_custom(&mask, "two", output_dir, "song", None)
+            .unwrap();
+        assert_eq!(custom.path, dir.path().join("song_two.wav"));
+        let stem = crate::render_stem(
+            &buffer,
+            InstrumentIndex::new(1),
+            false,
+            output_dir,
+            "song",
+            &options,
+            &crate::NameTemplate::default(),
+            &crate::StemLabels::default(),
+            &[],
+            None,
+        )
+        .unwrap();
+        assert_eq!(custom.frames, stem.frames);
+        assert_eq!(custom.peak, stem.peak);
+
+        // Overlapping selections take several passes, but play every note once
+        let mask = MuteMask::new()
+            .channels([ChannelIndex::new(0)])
+            .instruments([InstrumentIndex::new(1)]);
+        let custom = session
+            .render_custom(&mask, "mixed", output_dir, "song", None)
+            .unwrap();
+        assert_eq!(custom.frames, stem.frames);
+        assert!(custom.peak > 0.0);
+
+        assert!(session
+            .render_custom(&MuteMask::new(), "empty", output_dir, "song", None)
+            .is_err());
+    }
+
+    #[test]
+    fn test_mute_automation() {
+        let buffer = std::fs::read("../../tests/modules/cndmcrrp.mod").unwrap();
+        let options = ExportOptions {
+            reproducible: true,
+            ..ExportOptions::default()
+        };
+        let render = |session: &mut RenderSession| {
+            let mut audio = vec![0i16; 44100 * 2];
+            let mut position = 0;
+            while position < audio.len() {
+                position += session.read(&mut audio[position..]) * 2;
+            }
+            audio
+        };
+        let plain = render(&mut RenderSession::new(&buffer, &options).unwrap());
+
+        // Every channel muted half a second in
+        let num_channels = session().module_ext().get_module().get_num_channels();
+        let text: String = (1..=num_channels)
+            .map(|channel| format!("mute channel {} at 0.5\n", channel))
+            .collect();
+        let automation = crate::automation::MuteAutomation::parse(&text).unwrap();
+        let options = ExportOptions {
+            mute_automation: Some(Arc::new(automation)),
+            ..options
+        };
+        let mut session = RenderSession::new(&buffer, &options).unwrap();
+        for _ in 0..2 {
+            let automated = render(&mut session);
+            assert_eq!(automated[..22050 * 2], plain[..22050 * 2]);
+            // Past the volume ramp of the mutes
+            assert!(automated[24000 * 2..].iter().all(|&s| s == 0));
+            // and the channels play again from the start
+            session.rewind().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_mix_automation() {
+        let buffer = std::fs::read("../../tests/modules/cndmcrrp.mod").unwrap();
+        let options = ExportOptions {
+            reproducible: true,
+            ..ExportOptions::default()
+        };
+        let render = |session: &mut RenderSession| {
+            let mut audio = vec![0i16; 44100 * 2];
+            let mut position = 0;
+            while position < audio.len() {
+                position += session.read(&mut audio[position..]) * 2;
+            }
+            audio
+        };
+        let plain = render(&mut RenderSession::new(&buffer, &options).unwrap());
+
+        // Every channel faded out from half a second to 0.6 seconds in
+        let num_channels = session().module_ext().get_module().get_num_channels();
+        let text: String = (1..=num_channels)
+            .map(|channel| format!("0.5, {0}, volume, 1\n0.6, {0}, volume, 0\n", channel))
+            .collect();
+        let automation = crate::mix_automation::MixAutomation::parse_csv(&text).unwrap();
+        let options = ExportOptions {
+            mix_automation: Some(Arc::new(automation)),
+            ..options
+        };
+        let mut session = RenderSession::new(&buffer, &options).unwrap();
+        for _ in 0..2 {
+            let automated = render(&mut session);
+            assert_eq!(automated[..22050 * 2], plain[..22050 * 2]);
+            let level = |audio: &[i16]| audio.iter().map(|&s| (s as i64).abs()).sum::<i64>();
+            let fading = 22050 * 2..26460 * 2;
+            assert!(level(&automated[fading.clone()]) < level(&plain[fading]));
+            // Past the volume ramp of the last step
+            assert!(automated[27000 * 2..].iter().all(|&s| s == 0));
+            session.rewind().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_master_gain() {
+        let buffer = std::fs::read("../../tests/modules/cndmcrrp.mod").unwrap();
+        let peak = |options: &ExportOptions| {
+            let mut session = RenderSession::new(&buffer, options).unwrap();
+            let mut buf = vec![0i16; 44100 * 2];
+            session.read(&mut buf);
+            buf.iter().map(|s| s.unsigned_abs()).max().unwrap() as f64
+        };
+        let quieter = ExportOptions {
+            master_gain_db: -6.0,
+            volume_ramping: Some(0),
+            ..ExportOptions::default()
+        };
+        let ratio = peak(&quieter) / peak(&ExportOptions::default());
+        assert!((ratio - 0.5).abs() < 0.05, "{}", ratio);
+
+        let options = ExportOptions {
+            volume_ramping: Some(11),
+            ..ExportOptions::default()
+        };
+        assert!(options.validate().is_err());
+    }
+
+    #[test]
+    fn test_ctls() {
+        assert_eq!(
+            parse_ctl("render.resampler.emulate_amiga=1").unwrap(),
+            (
+                "render.resampler.emulate_amiga".to_string(),
+                "1".to_string()
+            )
+        );
+        assert!(parse_ctl("render.resampler.emulate_amiga").is_err());
+        assert!(parse_ctl("=1").is_err());
+
+        let buffer = std::fs::read("../../tests/modules/cndmcrrp.mod").unwrap();
+        let ctls = [parse_ctl("render.resampler.emulate_amiga=1").unwrap()];
+        let module_ext = load_module(&buffer, Logger::None, &ctls).unwrap();
+        assert_eq!(
+            module_ext
+                .get_module()
+                .ctl_get("render.resampler.emulate_amiga")
+                .as_deref(),
+            Some("1")
+        );
+        let ctls = [parse_ctl("render.no_such_ctl=1").unwrap()];
+        assert!(load_module(&buffer, Logger::None, &ctls).is_err());
+    }
+
+    #[test]
+    fn test_order_range() {
+        let buffer = std::fs::read("../../tests/modules/cndmcrrp.mod").unwrap();
+        let mut song = session();
+        let order_1 = song.seek(Seek::OrderRow { order: 1, row: 0 }).unwrap();
+        let order_3 = song.seek(Seek::OrderRow { order: 3, row: 0 }).unwrap();
+
+        let options = ExportOptions {
+            range: Some(SongRange::Orders {
+                start: 1,
+                end: Some(2),
+            }),
+            ..ExportOptions::default()
+        };
+        let mut session = RenderSession::new(&buffer, &options).unwrap();
+        assert_eq!(session.position().order, 1);
+        assert!((session.start_seconds() - order_1).abs() < 1e-6);
+        assert!((session.length_seconds() - (order_3 - order_1)).abs() < 1e-6);
+        assert_eq!(session.time_limit(), Some(session.length_seconds()));
+
+        // Rewinding goes back to the start of the range, not of the song
+        let mut buf = [0i16; 4096];
+        session.read(&mut buf);
+        session.rewind().unwrap();
+        assert_eq!((session.position().order, session.position().row), (1, 0));
+
+        let options = ExportOptions {
+            range: Some(SongRange::Orders {
+                start: 10_000,
+                end: None,
+            }),
+            ..ExportOptions::default()
+        };
+        assert!(RenderSession::new(&buffer, &options).is_err());
+    }
+
+    #[test]
+    fn test_read_float_matches_int() {
+        let buffer = std::fs::read("../../tests/modules/cndmcrrp.mod").unwrap();
+        for channels in [1, 2] {
+            let options = ExportOptions {
+                channels,
+                reproducible: true,
+                ..ExportOptions::default()
+            };
+            let mut ints = vec![0i16; 44100 * channels as usize];
+            let mut floats = vec![0f32; 44100 * channels as usize];
+            let frames = RenderSession::new(&buffer, &options)
+                .unwrap()
+                .read(&mut ints);
+            let float_frames = RenderSession::new(&buffer, &options)
+                .unwrap()
+                .read_float(&mut floats);
+            assert_eq!(frames, float_frames);
+
+            // The same mix, before and after conversion to 16 bits
+            assert!(ints.iter().any(|&s| s != 0));
+            for (&int, &float) in ints.iter().zip(&floats) {
+                assert!((i16::from_f64(float.to_f64()) as i32 - int as i32).abs() <= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_channels() {
+        let buffer = std::fs::read("../../tests/modules/cndmcrrp.mod").unwrap();
+        let read = |channels, render_channels| {
+            let options = ExportOptions {
+                channels,
+                render_channels,
+                reproducible: true,
+                ..ExportOptions::default()
+            };
+            let mut buf = vec![0f32; 44100 * channels as usize];
+            let frames = RenderSession::new(&buffer, &options)
+                .unwrap()
+                .read_float(&mut buf);
+            buf.truncate(frames * channels as usize);
+            buf
+        };
+
+        // Mono folded down from the stereo render, rather than rendered as mono
+        let stereo = read(2, None);
+        let mono = read(1, Some(2));
+        assert_eq!(mono.len() * 2, stereo.len());
+        let g = std::f32::consts::FRAC_1_SQRT_2;
+        for (&sample, frame) in mono.iter().zip(stereo.chunks_exact(2)) {
+            assert!((sample - (frame[0] + frame[1]) * g).abs() < 1e-6);
+        }
+
+        // Quad folded down to stereo keeps the length and level of the song
+        let quad = read(2, Some(4));
+        assert_eq!(quad.len(), stereo.len());
+        assert!(quad.iter().any(|&s| s != 0.0));
+        assert!(RenderSession::new(
+            &buffer,
+            &ExportOptions {
+                render_channels: Some(3),
+                ..ExportOptions::default()
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_seek_order_row_is_exact() {
+        let mut session = session();
+        let seconds = session.seek(Seek::OrderRow { order: 1, row: 8 }).unwrap();
+
+        let position = session.position();
+        assert_eq!((position.order, position.row), (1, 8));
+        assert!((position.seconds - seconds).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_position_callback() {
+        let mut session = session();
+        let positions = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let reported = positions.clone();
+        session.on_position(256, move |position| reported.lock().unwrap().push(position));
+
+        // Reads stop at the interval, whatever the buffer holds
+        let mut buf = [0i16; 4096];
+        assert_eq!(session.read(&mut buf), 256);
+        let mut floats = [0f32; 4096];
+        assert_eq!(session.read_float(&mut floats), 256);
+
+        let positions = positions.lock().unwrap();
+        assert_eq!(positions.len(), 2);
+        assert_eq!(
+            (positions[0].frame, positions[0].order, positions[0].row),
+            (0, 0, 0)
+        );
+        assert_eq!(positions[1].frame, 256);
+        assert!(positions[1].seconds > 0.0);
+        assert_eq!(session.position().frame, 512);
+    }
+
+    #[test]
+    fn test_seek_outside_song_fails() {
+        let mut session = session();
+        session.seek(Seek::OrderRow { order: 0, row: 4 }).unwrap();
+
+        assert!(session
+            .seek(Seek::OrderRow {
+                order: 10_000,
+                row: 0
+            })
+            .is_err());
+        assert_eq!(session.position().row, 4);
+    }
+
+    #[test]
+    fn test_seek_matches_render_from_start() {
+        const ORDER: i32 = 0;
+        const ROW: i32 = 16;
+        const COMPARE_FRAMES: usize = 22050;
+        // Interpolation and volume ramping history differ right after a seek
+        const SETTLE_FRAMES: usize = 256;
+
+        // Render from the start one frame at a time to find the first frame of the row
+        let mut reference = session();
+        let mut frame = [0i16; 2];
+        let mut skipped = 0usize;
+        loop {
+            assert_eq!(reference.read(&mut frame), 1);
+            let position = reference.position();
+            if (position.order, position.row) == (ORDER, ROW) {
+                break;
+            }
+            skipped += 1;
+        }
+        let mut expected = vec![0i16; COMPARE_FRAMES * 2];
+        expected[..2].copy_from_slice(&frame);
+        let mut filled = 1;
+        while filled < COMPARE_FRAMES {
+            let rendered = reference.read(&mut expected[filled * 2..]);
+            assert!(rendered > 0);
+            filled += rendered;
+        }
+
+        let mut seeked = session();
+        let seconds = seeked
+            .seek(Seek::OrderRow {
+                order: ORDER,
+                row: ROW,
+            })
+            .unwrap();
+        let sample_rate = seeked.options().sample_rate as f64;
+        assert!((seconds - skipped as f64 / sample_rate).abs() < 0.001);
+
+        let mut actual = vec![0i16; COMPARE_FRAMES * 2];
+        let mut filled = 0;
+        while filled < COMPARE_FRAMES {
+            let rendered = seeked.read(&mut actual[filled * 2..]);
+            assert!(rendered > 0);
+            filled += rendered;
+        }
+
+        let expected = &expected[SETTLE_FRAMES * 2..];
+        let actual = &actual[SETTLE_FRAMES * 2..];
+        let signal: f64 = expected.iter().map(|&s| (s as f64).powi(2)).sum();
+        let difference: f64 = expected
+            .iter()
+            .zip(actual)
+            .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+            .sum();
+        assert!(signal > 0.0);
+        assert!(difference <= signal * 0.01);
+    }
+}
@@ -0,0 +1,351 @@
+//! Rendering the stems of a module in one pass over the song, for
+//! `--single-pass`.
+//!
+//! Each stem still gets a module instance of its own with everything else
+//! muted, as libopenmpt can only render what is unmuted. The stems are split
+//! among threads, each playing the instances of its share side by side a
+//! chunk at a time, and every stem streams to its file as it goes. Once all
+//! threads are through the song, all stems end on the same frame: stems whose
+//! instance stopped a few frames early are padded with silence.
+
+use anyhow::{anyhow, Result};
+use openmpt::index::{InstrumentIndex, SampleIndex};
+use std::sync::{Condvar, Mutex};
+
+use crate::progress::{ProgressSink, RenderProgress};
+use crate::{
+    output_path, stem_file_name, stem_session, tags, ExportOptions, NameTemplate, NormalizeMode,
+    RenderSample, RenderSession, StemLabels, StemRenderResult, StemWriter, StreamBuffers,
+    StreamRender, Tag,
+};
+
+/// Render instruments (or samples) `indices` to their stem files in
+/// `output_dir` in one pass over the song, named and tagged as by
+/// [`crate::render_stem`].
+///
+/// The stems are shared among `jobs` threads. Every instance still mixes the
+/// whole song, so the pass takes about as long as rendering the stems one at
+/// a time on as many workers.
+///
+/// The stems are streamed to their files, so options that need a whole
+/// render first, normalization, ReplayGain and loop crossfades, are refused.
+#[allow(clippy::too_many_arguments)]
+pub fn render_single_pass(
+    buffer: &[u8],
+    indices: &[InstrumentIndex],
+    is_instrument: bool,
+    output_dir: &str,
+    base_name: &str,
+    options: &ExportOptions,
+    template: &NameTemplate,
+    labels: &StemLabels,
+    tags: &[Tag],
+    jobs: usize,
+    progress: Option<&dyn ProgressSink>,
+) -> Result<Vec<StemRenderResult>> {
+    if options.normalize != NormalizeMode::Off
+        || options.replaygain
+        || options.loop_crossfade.is_some()
+    {
+        return Err(anyhow!(
+            "Single-pass renders stream stems to their files, so they can't be normalized, measured for ReplayGain or looped"
+        ));
+    }
+    let stems = Stems {
+        buffer,
+        indices,
+        is_instrument,
+        output_dir,
+        base_name,
+        template,
+        labels,
+        tags,
+    };
+    if options.render_float() {
+        render_pass::<f32>(&stems, options, jobs, progress)
+    } else {
+        render_pass::<i16>(&stems, options, jobs, progress)
+    }
+}
+
+/// The stems of a pass, and how their files are named and tagged.
+struct Stems<'a> {
+    buffer: &'a [u8],
+    indices: &'a [InstrumentIndex],
+    is_instrument: bool,
+    output_dir: &'a str,
+    base_name: &'a str,
+    template: &'a NameTemplate,
+    labels: &'a StemLabels,
+    tags: &'a [Tag],
+}
+
+/// The frame all stems of a pass end on: threads wait for each other once
+/// through the song, to pad their stems to the longest render of all.
+struct PassEnd {
+    /// Threads still rendering, and the longest render so far
+    state: Mutex<(usize, u64)>,
+    done: Condvar,
+}
+
+impl PassEnd {
+    fn new(threads: usize, frames: u64) -> Self {
+        PassEnd {
+            state: Mutex::new((threads, frames)),
+            done: Condvar::new(),
+        }
+    }
+
+    /// Tell that a thread is through the song, its longest render being
+    /// `frames`, and return the frame all stems end on once every thread is.
+    fn wait(&self, frames: u64) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.0 -= 1;
+        state.1 = state.1.max(frames);
+        if state.0 == 0 {
+            self.done.notify_all();
+        }
+        self.done
+            .wait_while(state, |(rendering, _)| *rendering > 0)
+            .unwrap()
+            .1
+    }
+}
+
+fn render_pass<S: RenderSample>(
+    stems: &Stems,
+    options: &ExportOptions,
+    jobs: usize,
+    progress: Option<&dyn ProgressSink>,
+) -> Result<Vec<StemRenderResult>> {
+    let label = format!("{} stems in one pass", stems.indices.len());
+    if let Some(progress) = progress {
+        progress.report(RenderProgress::Started { label: &label });
+    }
+
+    let batches: Vec<&[InstrumentIndex]> = stems
+        .indices
+        .chunks(stems.indices.len().div_ceil(jobs.max(1)).max(1))
+        .collect();
+    let end = PassEnd::new(batches.len(), options.pad_to_frames.unwrap_or(0));
+    let results = std::thread::scope(|scope| {
+        let threads: Vec<_> = batches
+            .iter()
+            .enumerate()
+            .map(|(n, &batch)| {
+                let (label, end) = (&label, &end);
+                scope.spawn(move || {
+                    render_batch::<S>(stems, batch, options, end, label, n == 0, progress)
+                })
+            })
+            .collect();
+        threads
+            .into_iter()
+            .map(|thread| {
+                thread
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect::<Result<Vec<_>>>()
+    })?;
+    let results: Vec<StemRenderResult> = results.into_iter().flatten().collect();
+    log::info!(
+        "Rendered {} stems in one pass of {} frames on {} threads",
+        results.len(),
+        results.first().map_or(0, |result| result.frames),
+        batches.len()
+    );
+    Ok(results)
+}
+
+/// Render the stems of instruments (or samples) `indices` side by side, and
+/// pad them to the end of the pass. The first batch reports the position of
+/// the pass, as all stems advance through the song at about the same pace.
+fn render_batch<S: RenderSample>(
+    stems: &Stems,
+    indices: &[InstrumentIndex],
+    options: &ExportOptions,
+    end: &PassEnd,
+    label: &str,
+    first: bool,
+    progress: Option<&dyn ProgressSink>,
+) -> Result<Vec<StemRenderResult>> {
+    let mut sessions = Vec::with_capacity(indices.len());
+    let mut writers = Vec::with_capacity(indices.len());
+    let opened: Result<()> = indices.iter().try_for_each(|&index| {
+        let session = stem_session(stems.buffer, &[index], stems.is_instrument, options)?;
+        writers.push(stem_writer::<S>(&session, index, stems, options)?);
+        sessions.push(session);
+        Ok(())
+    });
+    let mut buffers: Vec<StreamBuffers<S>> = indices.iter().map(|_| Default::default()).collect();
+
+    let mut renders: Vec<StreamRender<S>> = sessions
+        .iter_mut()
+        .zip(&mut buffers)
+        .enumerate()
+        .map(|(n, (session, buffers))| {
+            StreamRender::new(
+                session,
+                label,
+                progress.filter(|_| first && n == 0),
+                buffers,
+            )
+        })
+        .collect();
+    let rendered = opened.and_then(|()| {
+        let mut running = vec![true; renders.len()];
+        while running.contains(&true) {
+            for ((render, writer), running) in
+                renders.iter_mut().zip(&mut writers).zip(&mut running)
+            {
+                if *running {
+                    *running = render.step(&mut |chunk| writer.write(chunk))?;
+                }
+            }
+        }
+        Ok(())
+    });
+    // A thread that failed still tells the others it is done, or they would
+    // wait for it forever
+    let longest = renders.iter().map(|render| render.frames()).max();
+    let frames = end.wait(rendered.as_ref().ok().and(longest).unwrap_or(0));
+    rendered?;
+
+    let mut results = Vec::with_capacity(renders.len());
+    for (render, mut writer) in renders.into_iter().zip(writers) {
+        render.finish(Some(frames), &mut |chunk| writer.write(chunk))?;
+        let result = writer.finish()?;
+        if let Some(progress) = progress {
+            progress.report(RenderProgress::Finished {
+                label,
+                path: &result.path,
+            });
+        }
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// Writer of the stem of instrument (or sample) `index`, rendered by `session`.
+fn stem_writer<S: RenderSample>(
+    session: &RenderSession,
+    index: InstrumentIndex,
+    stems: &Stems,
+    options: &ExportOptions,
+) -> Result<StemWriter<S>> {
+    let mut module = session.module_ext().get_module();
+    let name = if stems.is_instrument {
+        module.get_instrument_name(index)
+    } else {
+        module.get_sample_name(SampleIndex::new(index.get()))
+    };
+    let title = tags::stem_title(&mut module, index, stems.is_instrument);
+    let file_tags = tags::file_tags(&mut module, &title, Some(index.number()), stems.tags);
+    let path = output_path(
+        stems.output_dir,
+        &stem_file_name(
+            stems.template,
+            stems.base_name,
+            stems.is_instrument,
+            index,
+            &name,
+            options.format,
+            stems.labels,
+        ),
+    );
+    StemWriter::create(path, options, &file_tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_stem;
+
+    #[test]
+    fn test_single_pass() {
+        let buffer = include_bytes!("../../../tests/modules/cndmcrrp.mod");
+        let options = ExportOptions {
+            preview: Some(2.0),
+            reproducible: true,
+            ..ExportOptions::default()
+        };
+        let indices = [InstrumentIndex::new(0), InstrumentIndex::new(1)];
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().to_str().unwrap();
+
+        let results = render_single_pass(
+            buffer,
+            &indices,
+            false,
+            output_dir,
+            "pass",
+            &options,
+            &NameTemplate::default(),
+            &StemLabels::default(),
+            &[],
+            // A thread per stem
+            2,
+            None,
+        )
+        .unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|result| result.frames == 2 * 44100));
+
+        // The stems match those rendered one at a time
+        let single = render_stem(
+            buffer,
+            indices[1],
+            false,
+            output_dir,
+            "single",
+            &options,
+            &NameTemplate::default(),
+            &StemLabels::default(),
+            &[],
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read(&results[1].path).unwrap(),
+            std::fs::read(&single.path).unwrap()
+        );
+
+        let normalized = ExportOptions {
+            normalize: NormalizeMode::Peak,
+            ..options
+        };
+        assert!(render_single_pass(
+            buffer,
+            &indices,
+            false,
+            output_dir,
+            "pass",
+            &normalized,
+            &NameTemplate::default(),
+            &StemLabels::default(),
+            &[],
+            1,
+            None,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_pass_end() {
+        let end = PassEnd::new(3, 100);
+        let frames: Vec<u64> = std::thread::scope(|scope| {
+            let threads: Vec<_> = [50, 300, 200]
+                .into_iter()
+                .map(|frames| {
+                    scope.spawn({
+                        let end = &end;
+                        move || end.wait(frames)
+                    })
+                })
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+        assert_eq!(frames, [300, 300, 300]);
+    }
+}
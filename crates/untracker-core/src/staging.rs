@@ -0,0 +1,335 @@
+//! Staging of output files, so a module's outputs only appear once all of them are complete.
+
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::StemRenderResult;
+
+/// Directory inside the staging directory recording the stems rendered so
+/// far, so an interrupted extraction can be resumed.
+const JOURNAL_DIR: &str = ".untracker-journal";
+
+/// What to do with output files that are already in the output directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExistingOutputs {
+    /// Fail before rendering anything
+    #[default]
+    Fail,
+    /// Replace them
+    Overwrite,
+    /// Keep them, and don't render them again
+    Skip,
+}
+
+/// A temporary directory that collects a module's outputs before they are
+/// moved into the real output directory.
+///
+/// The staging directory is removed on drop unless [`StagingDir::commit`]
+/// succeeded, so a failed extraction leaves nothing behind. One that was
+/// interrupted is left behind, and can be picked up with [`StagingDir::resume`].
+pub struct StagingDir {
+    path: PathBuf,
+    target: PathBuf,
+    keep_existing: bool,
+    committed: bool,
+}
+
+impl StagingDir {
+    /// Create a staging directory for `target`, inside `temp_dir` if given or
+    /// next to `target` otherwise, which keeps the final move a cheap rename.
+    pub fn new(target: &Path, temp_dir: Option<&Path>) -> Result<Self> {
+        let (parent, prefix) = staging_location(target, temp_dir);
+        let path = parent.join(format!("{}{}", prefix, std::process::id()));
+        Self::create(path, target)
+    }
+
+    /// Like [`StagingDir::new`], but take over the staging directory an
+    /// interrupted extraction into `target` left behind, if there is one, so
+    /// the stems it holds don't have to be rendered again.
+    pub fn resume(target: &Path, temp_dir: Option<&Path>) -> Result<Self> {
+        let (parent, prefix) = staging_location(target, temp_dir);
+        let path = parent.join(format!("{}{}", prefix, std::process::id()));
+
+        // The most recent one, if several extractions were interrupted
+        let mut leftovers = Vec::new();
+        for entry in fs::read_dir(&parent).into_iter().flatten() {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with(&prefix) && entry.path() != path {
+                leftovers.push((entry.metadata()?.modified()?, entry.path()));
+            }
+        }
+        leftovers.sort();
+        if let Some((_, leftover)) = leftovers.pop() {
+            println!(
+                "Resuming the interrupted extraction staged in {}",
+                leftover.display()
+            );
+            fs::rename(&leftover, &path)?;
+        }
+        Self::create(path, target)
+    }
+
+    fn create(path: PathBuf, target: &Path) -> Result<Self> {
+        fs::create_dir_all(&path).map_err(|e| {
+            anyhow!(
+                "Failed to create staging directory {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        log::debug!(
+            "Staging outputs for {} in {}",
+            target.display(),
+            path.display()
+        );
+
+        Ok(StagingDir {
+            path,
+            target: target.to_path_buf(),
+            keep_existing: false,
+            committed: false,
+        })
+    }
+
+    /// Leave files already in the target directory as they are on
+    /// [`StagingDir::commit`], instead of replacing them with staged ones.
+    pub fn keep_existing(mut self, keep_existing: bool) -> Self {
+        self.keep_existing = keep_existing;
+        self
+    }
+
+    /// Directory to write outputs to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Where a file written to the staging directory ends up after [`StagingDir::commit`].
+    pub fn final_path(&self, staged: &Path) -> PathBuf {
+        match staged.strip_prefix(&self.path) {
+            Ok(relative) => self.target.join(relative),
+            Err(_) => staged.to_path_buf(),
+        }
+    }
+
+    /// Return the stem recorded under `key` by an earlier [`StagingDir::render_once`]
+    /// of this or a resumed staging directory, or render it with `render` and
+    /// record it.
+    ///
+    /// `key` has to change with anything that changes the render, such as a
+    /// [`crate::cache::RenderCache::key`].
+    pub fn render_once(
+        &self,
+        key: &str,
+        render: impl FnOnce() -> Result<StemRenderResult>,
+    ) -> Result<StemRenderResult> {
+        let journal = self.path.join(JOURNAL_DIR);
+        let entry = journal.join(key).with_extension("json");
+        if let Some(result) = self.recorded(&entry) {
+            log::info!("Already rendered {}", result.path.display());
+            return Ok(result);
+        }
+
+        let result = render()?;
+        // Recorded under a temporary name, as the process can stop at any time
+        let partial = entry.with_extension("partial");
+        fs::create_dir_all(&journal)?;
+        fs::write(&partial, serde_json::to_string(&result)?)?;
+        fs::rename(&partial, &entry)?;
+        Ok(result)
+    }
+
+    fn recorded(&self, entry: &Path) -> Option<StemRenderResult> {
+        let mut result: StemRenderResult =
+            serde_json::from_str(&fs::read_to_string(entry).ok()?).ok()?;
+        // The staging directory was renamed when it was resumed
+        result.path = self.path.join(result.path.file_name()?);
+        result.path.exists().then_some(result)
+    }
+
+    /// Move the staged outputs into the target directory.
+    ///
+    /// A target that doesn't exist yet is created by renaming the whole staging
+    /// directory. Otherwise each file is moved in on its own, through a
+    /// temporary name when it has to be copied across filesystems.
+    pub fn commit(mut self) -> Result<()> {
+        let journal = self.path.join(JOURNAL_DIR);
+        if journal.exists() {
+            fs::remove_dir_all(&journal)?;
+        }
+        if !self.target.exists() && fs::rename(&self.path, &self.target).is_ok() {
+            self.committed = true;
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.target)?;
+        move_contents(&self.path, &self.target, self.keep_existing)?;
+        fs::remove_dir_all(&self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        if !self.committed {
+            if let Err(e) = fs::remove_dir_all(&self.path) {
+                log::warn!(
+                    "Failed to remove staging directory {}: {}",
+                    self.path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Directory to create the staging directory of `target` in, and the start of
+/// the name of staging directories of `target`, which end with a process ID.
+fn staging_location(target: &Path, temp_dir: Option<&Path>) -> (PathBuf, String) {
+    let target_name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "output".to_string());
+    let parent = match temp_dir {
+        Some(temp_dir) => temp_dir.to_path_buf(),
+        None => target
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .to_path_buf(),
+    };
+    (parent, format!(".{}.untracker-tmp-", target_name))
+}
+
+fn move_contents(from: &Path, to: &Path, keep_existing: bool) -> Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let destination = to.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            fs::create_dir_all(&destination)?;
+            move_contents(&entry.path(), &destination, keep_existing)?;
+            continue;
+        }
+        if keep_existing && destination.exists() {
+            log::info!("Keeping {}", destination.display());
+            continue;
+        }
+
+        if fs::rename(entry.path(), &destination).is_err() {
+            // Different filesystem: copy next to the destination, then rename
+            // so the file never appears half-written
+            let partial = to.join(format!(".{}.partial", entry.file_name().to_string_lossy()));
+            fs::copy(entry.path(), &partial)?;
+            fs::rename(&partial, &destination)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commit_into_new_and_existing_directories() {
+        let root = tempfile::tempdir().unwrap();
+        let target = root.path().join("stems");
+
+        let staging = StagingDir::new(&target, None).unwrap();
+        fs::write(staging.path().join("a.wav"), b"a").unwrap();
+        assert!(!target.exists());
+        staging.commit().unwrap();
+        assert_eq!(fs::read(target.join("a.wav")).unwrap(), b"a");
+
+        let temp = tempfile::tempdir().unwrap();
+        let staging = StagingDir::new(&target, Some(temp.path())).unwrap();
+        fs::write(staging.path().join("b.wav"), b"b").unwrap();
+        assert_eq!(
+            staging.final_path(&staging.path().join("b.wav")),
+            target.join("b.wav")
+        );
+        staging.commit().unwrap();
+        assert!(target.join("a.wav").exists());
+        assert!(target.join("b.wav").exists());
+        assert_eq!(fs::read_dir(temp.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_keep_existing() {
+        let root = tempfile::tempdir().unwrap();
+        let target = root.path().join("stems");
+        fs::create_dir(&target).unwrap();
+        fs::write(target.join("a.wav"), b"old").unwrap();
+
+        let staging = StagingDir::new(&target, None).unwrap().keep_existing(true);
+        fs::write(staging.path().join("a.wav"), b"new").unwrap();
+        fs::write(staging.path().join("b.wav"), b"b").unwrap();
+        staging.commit().unwrap();
+        assert_eq!(fs::read(target.join("a.wav")).unwrap(), b"old");
+        assert_eq!(fs::read(target.join("b.wav")).unwrap(), b"b");
+    }
+
+    #[test]
+    fn test_resume_interrupted_staging() {
+        let root = tempfile::tempdir().unwrap();
+        let target = root.path().join("stems");
+        let result = |path: PathBuf| StemRenderResult {
+            path,
+            frames: 1,
+            duration: 1.0,
+            peak: 0.5,
+            start_offset: 0.0,
+            gain_db: 0.0,
+            loudness: None,
+            loop_start: None,
+            skipped_reason: None,
+            analysis: crate::analysis::StemAnalysis::new(&[0, 0], 2, 16),
+        };
+
+        // An extraction that stopped after its first stem, from another process
+        let interrupted = root.path().join(".stems.untracker-tmp-1");
+        fs::create_dir(&interrupted).unwrap();
+        let staging = StagingDir::create(interrupted.clone(), &target).unwrap();
+        staging
+            .render_once("key-a", || {
+                fs::write(staging.path().join("a.wav"), b"a")?;
+                Ok(result(staging.path().join("a.wav")))
+            })
+            .unwrap();
+        std::mem::forget(staging);
+
+        let staging = StagingDir::resume(&target, None).unwrap();
+        assert!(!interrupted.exists());
+        let a = staging
+            .render_once("key-a", || panic!("rendered a again"))
+            .unwrap();
+        assert_eq!(a.path, staging.path().join("a.wav"));
+        // Different options give a different key, which is rendered again
+        let rendered = staging
+            .render_once("key-b", || Ok(result(staging.path().join("a.wav"))))
+            .unwrap();
+        assert_eq!(rendered.peak, 0.5);
+
+        staging.commit().unwrap();
+        let files: Vec<_> = fs::read_dir(&target)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(files, ["a.wav"]);
+    }
+
+    #[test]
+    fn test_dropped_staging_leaves_nothing() {
+        let root = tempfile::tempdir().unwrap();
+        let target = root.path().join("stems");
+
+        let staging = StagingDir::new(&target, None).unwrap();
+        fs::write(staging.path().join("a.wav"), b"a").unwrap();
+        drop(staging);
+
+        assert!(!target.exists());
+        assert_eq!(fs::read_dir(root.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,120 @@
+//! Tags written into FLAC, Vorbis and Opus files: what the module says about
+//! itself, and what is added with `--tag`.
+
+use anyhow::{anyhow, Result};
+use openmpt::index::{InstrumentIndex, SampleIndex};
+use openmpt::module::Module;
+
+use crate::audio::Tag;
+
+/// Tags of a file rendered from `module` and titled `title`: TITLE, ARTIST,
+/// ALBUM (the module title) and TRACKNUMBER if given, followed by `extra`.
+///
+/// Tags of `extra` replace the ones of the same key taken from the module.
+pub fn file_tags(module: &mut Module, title: &str, track: Option<i32>, extra: &[Tag]) -> Vec<Tag> {
+    let mut tags = vec![("TITLE".to_string(), title.to_string())];
+    if let Some(artist) = module.artist() {
+        tags.push(("ARTIST".to_string(), artist));
+    }
+    if let Some(album) = module.title() {
+        tags.push(("ALBUM".to_string(), album));
+    }
+    if let Some(track) = track {
+        tags.push(("TRACKNUMBER".to_string(), track.to_string()));
+    }
+
+    tags.retain(|(key, _)| {
+        !extra
+            .iter()
+            .any(|(extra, _)| extra.eq_ignore_ascii_case(key))
+    });
+    tags.extend_from_slice(extra);
+    tags
+}
+
+/// Title of the stem of instrument (or sample) `index`: its name in the
+/// module, or "Instrument 3" if it has none.
+pub fn stem_title(module: &mut Module, index: InstrumentIndex, is_instrument: bool) -> String {
+    let (name, type_label) = if is_instrument {
+        (module.get_instrument_name(index), "Instrument")
+    } else {
+        (
+            module.get_sample_name(SampleIndex::new(index.get())),
+            "Sample",
+        )
+    };
+    match name.trim() {
+        "" => format!("{} {}", type_label, index),
+        name => name.to_string(),
+    }
+}
+
+/// Parse a `KEY=value` tag given on the command line. Keys are upper-cased,
+/// and limited to the printable ASCII characters Vorbis comments allow.
+pub fn parse_tag(s: &str) -> Result<Tag> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected KEY=value, got {:?}", s))?;
+    if key.is_empty() || !key.chars().all(|c| (' '..='}').contains(&c)) {
+        return Err(anyhow!("Invalid tag name {:?}", key));
+    }
+    Ok((key.to_ascii_uppercase(), value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{FixtureInstrument, FixtureModule, FixtureSample};
+    use crate::{ExportOptions, RenderSession};
+
+    #[test]
+    fn test_file_tags() {
+        let buffer = FixtureModule::new("Karate")
+            .instrument(FixtureInstrument::new(
+                " Kick ",
+                FixtureSample::square("kick", 32),
+            ))
+            .instrument(FixtureInstrument::empty(""))
+            .to_xm();
+        let session = RenderSession::new(&buffer, &ExportOptions::default()).unwrap();
+        let mut module = session.module_ext().get_module();
+
+        let title = stem_title(&mut module, InstrumentIndex::new(0), true);
+        assert_eq!(title, "Kick");
+        assert_eq!(
+            stem_title(&mut module, InstrumentIndex::new(1), true),
+            "Instrument 2"
+        );
+
+        let extra = [
+            ("TRACKNUMBER".to_string(), "7".to_string()),
+            ("GENRE".to_string(), "Chiptune".to_string()),
+        ];
+        let tags = file_tags(&mut module, &title, Some(1), &extra);
+        let tags: Vec<(&str, &str)> = tags
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            tags,
+            [
+                ("TITLE", "Kick"),
+                ("ALBUM", "Karate"),
+                ("TRACKNUMBER", "7"),
+                ("GENRE", "Chiptune"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_tag() {
+        assert_eq!(
+            parse_tag("genre=Chip = tune").unwrap(),
+            ("GENRE".to_string(), "Chip = tune".to_string())
+        );
+        assert_eq!(parse_tag("COMMENT=").unwrap().1, "");
+        assert!(parse_tag("GENRE").is_err());
+        assert!(parse_tag("=value").is_err());
+        assert!(parse_tag("GENRÉ=value").is_err());
+    }
+}
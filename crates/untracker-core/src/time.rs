@@ -0,0 +1,74 @@
+use anyhow::{anyhow, Result};
+
+/// Parse a human friendly duration into seconds.
+///
+/// Accepts plain seconds (`30`, `2.5`), unit suffixes (`30s`, `500ms`, `2m`, `1m30s`)
+/// and clock notation (`1:30`, `1:02:03.5`).
+pub fn parse_seconds(s: &str) -> Result<f64> {
+    let s = s.trim();
+    let invalid = || anyhow!("Invalid duration: {:?}", s);
+
+    if s.is_empty() {
+        return Err(invalid());
+    }
+
+    let seconds = if s.contains(':') {
+        let mut total = 0.0;
+        for part in s.split(':') {
+            let value: f64 = part.parse().map_err(|_| invalid())?;
+            total = total * 60.0 + value;
+        }
+        total
+    } else if let Ok(value) = s.parse::<f64>() {
+        value
+    } else {
+        let mut total = 0.0;
+        let mut rest = s;
+        while !rest.is_empty() {
+            let split = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .ok_or_else(invalid)?;
+            let (number, tail) = rest.split_at(split);
+            let value: f64 = number.parse().map_err(|_| invalid())?;
+            let unit_len = tail
+                .find(|c: char| c.is_ascii_digit() || c == '.')
+                .unwrap_or(tail.len());
+            let (unit, tail) = tail.split_at(unit_len);
+            total += value
+                * match unit {
+                    "ms" => 0.001,
+                    "s" => 1.0,
+                    "m" | "min" => 60.0,
+                    "h" => 3600.0,
+                    _ => return Err(invalid()),
+                };
+            rest = tail;
+        }
+        total
+    };
+
+    if !seconds.is_finite() || seconds < 0.0 {
+        return Err(invalid());
+    }
+    Ok(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_seconds() {
+        assert_eq!(parse_seconds("30").unwrap(), 30.0);
+        assert_eq!(parse_seconds("2.5").unwrap(), 2.5);
+        assert_eq!(parse_seconds("30s").unwrap(), 30.0);
+        assert_eq!(parse_seconds("500ms").unwrap(), 0.5);
+        assert_eq!(parse_seconds("1m30s").unwrap(), 90.0);
+        assert_eq!(parse_seconds("1:30").unwrap(), 90.0);
+        assert_eq!(parse_seconds("1:00:01").unwrap(), 3601.0);
+        assert!(parse_seconds("").is_err());
+        assert!(parse_seconds("ten").is_err());
+        assert!(parse_seconds("3x").is_err());
+        assert!(parse_seconds("-1").is_err());
+    }
+}
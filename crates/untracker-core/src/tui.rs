@@ -0,0 +1,289 @@
+//! The terminal stem browser of `untracker tui`, built with ratatui: the
+//! stems of a module with their names, when they play and how busy they are,
+//! to pick the ones to extract by sight rather than by number, then watch
+//! them render.
+
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::widgets::{Block, Cell, Gauge, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::browser::StemBrowser;
+use crate::estimate::format_duration;
+use crate::progress::RenderProgress;
+use crate::{render_stem, ExportOptions, NameTemplate, StemLabels, Tag};
+
+/// Where the stems go, and how they are rendered and named.
+pub struct TuiExport {
+    pub output_dir: String,
+    pub base_name: String,
+    pub options: ExportOptions,
+    pub template: NameTemplate,
+    pub labels: StemLabels,
+    pub tags: Vec<Tag>,
+}
+
+/// How far the render of a stem got.
+#[derive(Debug, Clone, PartialEq)]
+enum StemState {
+    Idle,
+    /// Part rendered, from 0.0 to 1.0
+    Rendering(f64),
+    Written(PathBuf),
+    Failed(String),
+}
+
+/// Browse the stems of the module in `buffer` and render the selected ones
+/// with `export`. Returns the stems written, or nothing if the user quit
+/// before rendering.
+pub fn run(buffer: Vec<u8>, mut browser: StemBrowser, export: TuiExport) -> Result<Vec<PathBuf>> {
+    let mut terminal = ratatui::init();
+    let result = browse(&mut terminal, buffer, &mut browser, export);
+    ratatui::restore();
+    result
+}
+
+fn browse(
+    terminal: &mut DefaultTerminal,
+    buffer: Vec<u8>,
+    browser: &mut StemBrowser,
+    export: TuiExport,
+) -> Result<Vec<PathBuf>> {
+    let states = Arc::new(Mutex::new(vec![StemState::Idle; browser.stems.len()]));
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut worker = None;
+
+    loop {
+        let snapshot = states.lock().unwrap().clone();
+        let rendering = worker.is_some();
+        terminal.draw(|frame| draw(frame, browser, &export, &snapshot, rendering))?;
+
+        if !event::poll(Duration::from_millis(100))? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => break,
+            _ if rendering => {}
+            KeyCode::Up | KeyCode::Char('k') => browser.move_cursor(-1),
+            KeyCode::Down | KeyCode::Char('j') => browser.move_cursor(1),
+            KeyCode::PageUp => browser.move_cursor(-10),
+            KeyCode::PageDown => browser.move_cursor(10),
+            KeyCode::Char(' ') => browser.toggle(),
+            KeyCode::Char('a') => browser.select_all(true),
+            KeyCode::Char('n') => browser.select_all(false),
+            KeyCode::Char('p') => browser.select_played(),
+            KeyCode::Enter if !browser.selected().is_empty() => {
+                worker = Some(spawn_renders(
+                    &buffer,
+                    browser,
+                    &export,
+                    states.clone(),
+                    stop.clone(),
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    // Quitting while rendering waits for the stem being rendered
+    stop.store(true, Ordering::Relaxed);
+    if let Some(worker) = worker {
+        let _ = worker.join();
+    }
+    let states = states.lock().unwrap();
+    Ok(states
+        .iter()
+        .filter_map(|state| match state {
+            StemState::Written(path) => Some(path.clone()),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Render the selected stems one after the other on a thread of their own,
+/// recording their progress in `states`, until done or told to `stop`.
+fn spawn_renders(
+    buffer: &[u8],
+    browser: &StemBrowser,
+    export: &TuiExport,
+    states: Arc<Mutex<Vec<StemState>>>,
+    stop: Arc<AtomicBool>,
+) -> std::thread::JoinHandle<()> {
+    let buffer = buffer.to_vec();
+    let is_instrument = browser.is_instrument;
+    let selected = browser.selected();
+    let TuiExport {
+        output_dir,
+        base_name,
+        options,
+        template,
+        labels,
+        tags,
+    } = export;
+    let (output_dir, base_name, options) = (output_dir.clone(), base_name.clone(), options.clone());
+    let (template, labels, tags) = (template.clone(), labels.clone(), tags.clone());
+
+    std::thread::spawn(move || {
+        for index in selected {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            let n = index.get() as usize;
+            states.lock().unwrap()[n] = StemState::Rendering(0.0);
+            let progress = |progress: RenderProgress| {
+                if let RenderProgress::Rendering { .. } = progress {
+                    states.lock().unwrap()[n] = StemState::Rendering(progress.fraction());
+                }
+            };
+            let result = render_stem(
+                &buffer,
+                index,
+                is_instrument,
+                &output_dir,
+                &base_name,
+                &options,
+                &template,
+                &labels,
+                &tags,
+                Some(&progress),
+            );
+            states.lock().unwrap()[n] = match result {
+                Ok(result) => StemState::Written(result.path),
+                Err(e) => StemState::Failed(e.to_string()),
+            };
+        }
+    })
+}
+
+fn draw(
+    frame: &mut Frame,
+    browser: &StemBrowser,
+    export: &TuiExport,
+    states: &[StemState],
+    rendering: bool,
+) {
+    let [header, body, footer] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(3),
+        Constraint::Length(3),
+    ])
+    .areas(frame.area());
+
+    let selected = browser.selected().len();
+    let title = if browser.title.trim().is_empty() {
+        export.base_name.as_str()
+    } else {
+        browser.title.as_str()
+    };
+    frame.render_widget(
+        Paragraph::new(format!(
+            "{}, {} - {} of {} {} selected - to {}",
+            title,
+            format_duration(browser.duration),
+            selected,
+            browser.stems.len(),
+            if browser.is_instrument {
+                "instruments"
+            } else {
+                "samples"
+            },
+            export.output_dir
+        ))
+        .block(Block::bordered().title(" untracker ")),
+        header,
+    );
+
+    let busiest = browser.busiest();
+    let rows = browser.stems.iter().zip(states).map(|(stem, state)| {
+        let span = match stem.span {
+            Some((first, last)) => {
+                format!("{}-{}", format_duration(first), format_duration(last))
+            }
+            None => "never plays".to_string(),
+        };
+        let status = match state {
+            StemState::Idle => String::new(),
+            StemState::Rendering(fraction) => format!("{:.0}%", fraction * 100.0),
+            StemState::Written(_) => "done".to_string(),
+            StemState::Failed(e) => format!("failed: {}", e),
+        };
+        let row = Row::new(vec![
+            Cell::from(if stem.selected { "[x]" } else { "[ ]" }),
+            Cell::from(stem.index.number().to_string()),
+            Cell::from(stem.name.clone()),
+            Cell::from(span),
+            Cell::from(stem.notes.to_string()),
+            Cell::from(stem.activity_bar(busiest)),
+            Cell::from(status),
+        ]);
+        if stem.notes == 0 {
+            row.dim()
+        } else {
+            row
+        }
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(3),
+            Constraint::Length(4),
+            Constraint::Min(12),
+            Constraint::Length(15),
+            Constraint::Length(6),
+            Constraint::Length(crate::browser::ACTIVITY_BINS as u16),
+            Constraint::Min(8),
+        ],
+    )
+    .header(Row::new(["", "#", "Name", "Plays", "Notes", "Activity", ""]).bold())
+    .row_highlight_style(Style::new().reversed())
+    .block(Block::bordered());
+    let mut state = TableState::default().with_selected(Some(browser.cursor));
+    frame.render_stateful_widget(table, body, &mut state);
+
+    if rendering {
+        let done: f64 = states
+            .iter()
+            .map(|state| match state {
+                StemState::Idle => 0.0,
+                StemState::Rendering(fraction) => *fraction,
+                StemState::Written(_) | StemState::Failed(_) => 1.0,
+            })
+            .sum();
+        let finished = states
+            .iter()
+            .filter(|state| matches!(state, StemState::Written(_) | StemState::Failed(_)))
+            .count();
+        let label = if finished == selected {
+            format!("{} stems rendered - q to quit", finished)
+        } else {
+            format!("Rendering stem {} of {}", finished + 1, selected)
+        };
+        frame.render_widget(
+            Gauge::default()
+                .block(Block::bordered())
+                .ratio((done / selected.max(1) as f64).min(1.0))
+                .label(label),
+            footer,
+        );
+    } else {
+        frame.render_widget(
+            Paragraph::new(
+                "Up/Down move - Space toggle - a all - n none - p played - Enter render - q quit",
+            )
+            .block(Block::bordered()),
+            footer,
+        );
+    }
+}
@@ -0,0 +1,267 @@
+//! Modules shipped in archives, as module collections often are: `.zip` and
+//! `.lha` files, and gzipped modules. They are unpacked as they are read, so
+//! `untracker -i song.mod.zip` extracts the module inside.
+//!
+//! An archive should hold a single module, which is picked among its files by
+//! extension; archives holding several are refused rather than guessed at.
+//! Each format is a feature: `zip`, `gzip` and `lha`, or `archives` for all.
+
+use anyhow::{anyhow, Result};
+#[cfg(any(feature = "zip", feature = "gzip", feature = "lha"))]
+use std::io::Read;
+use std::path::Path;
+
+/// Largest module unpacked, so that a small archive can't fill the memory.
+pub const MAX_UNPACKED_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Archive formats modules are unpacked from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Gzip,
+    Lha,
+}
+
+impl ArchiveFormat {
+    /// Format of the file at `path`, going by its extension, if it is an archive.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "zip" => Some(ArchiveFormat::Zip),
+            "gz" => Some(ArchiveFormat::Gzip),
+            "lha" | "lzh" => Some(ArchiveFormat::Lha),
+            _ => None,
+        }
+    }
+
+    /// Name of the format, which is also the feature that unpacks it.
+    pub fn name(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::Gzip => "gzip",
+            ArchiveFormat::Lha => "lha",
+        }
+    }
+
+    /// Whether this build unpacks the format.
+    pub fn is_supported(self) -> bool {
+        match self {
+            ArchiveFormat::Zip => cfg!(feature = "zip"),
+            ArchiveFormat::Gzip => cfg!(feature = "gzip"),
+            ArchiveFormat::Lha => cfg!(feature = "lha"),
+        }
+    }
+}
+
+/// The module in `data`, the contents of the file at `path`: unpacked if the
+/// file is an archive, or `data` itself otherwise.
+pub fn unpack(path: &Path, data: Vec<u8>) -> Result<Vec<u8>> {
+    let Some(format) = ArchiveFormat::from_path(path) else {
+        return Ok(data);
+    };
+    let module: Result<Vec<u8>> = match format {
+        #[cfg(feature = "zip")]
+        ArchiveFormat::Zip => unzip(data),
+        #[cfg(feature = "gzip")]
+        ArchiveFormat::Gzip => read_limited(flate2::read::GzDecoder::new(&data[..])),
+        #[cfg(feature = "lha")]
+        ArchiveFormat::Lha => unlha(&data),
+        #[allow(unreachable_patterns)]
+        _ => Err(anyhow!(
+            "this build can't unpack {} archives; rebuild with --features {}",
+            format.name(),
+            format.name()
+        )),
+    };
+    let module = module.map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+    log::info!(
+        "Unpacked {} bytes from {} archive {}",
+        module.len(),
+        format.name(),
+        path.display()
+    );
+    Ok(module)
+}
+
+/// Name of the module in the file at `path`, which its outputs are named
+/// after: the file name without its extension, or without both extensions
+/// for archives named after the module they hold, such as `song.mod.zip`.
+pub fn module_stem(path: &Path) -> Option<&str> {
+    let stem = path.file_stem()?.to_str()?;
+    if ArchiveFormat::from_path(path).is_none() {
+        return Some(stem);
+    }
+    let inner = Path::new(stem);
+    match inner.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) if openmpt::info::is_extension_supported(extension) => {
+            inner.file_stem()?.to_str()
+        }
+        _ => Some(stem),
+    }
+}
+
+/// Whether the file named `name` in an archive looks like a module, rather
+/// than the text files or resource forks that come with them.
+#[cfg(any(feature = "zip", feature = "lha"))]
+fn is_module_name(name: &str) -> bool {
+    let path = Path::new(name);
+    let hidden = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    !hidden
+        && !name.starts_with("__MACOSX/")
+        && path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(openmpt::info::is_extension_supported)
+}
+
+/// Index of the only module among the files `names` of an archive.
+#[cfg(any(feature = "zip", feature = "lha"))]
+fn pick_module(names: &[String]) -> Result<usize> {
+    let modules: Vec<usize> = (0..names.len())
+        .filter(|&i| is_module_name(&names[i]))
+        .collect();
+    match modules[..] {
+        [index] => Ok(index),
+        [] => Err(anyhow!(
+            "no module in the archive, which holds {}",
+            if names.is_empty() {
+                "nothing".to_string()
+            } else {
+                names.join(", ")
+            }
+        )),
+        _ => Err(anyhow!(
+            "the archive holds {} modules ({}); unpack it and give the one to extract",
+            modules.len(),
+            modules
+                .iter()
+                .map(|&i| names[i].as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Read all of `reader`, up to [`MAX_UNPACKED_SIZE`].
+#[cfg(any(feature = "zip", feature = "gzip", feature = "lha"))]
+fn read_limited(reader: impl Read) -> Result<Vec<u8>> {
+    let mut module = Vec::new();
+    reader
+        .take(MAX_UNPACKED_SIZE + 1)
+        .read_to_end(&mut module)?;
+    if module.len() as u64 > MAX_UNPACKED_SIZE {
+        return Err(anyhow!(
+            "the module unpacks to more than {} bytes",
+            MAX_UNPACKED_SIZE
+        ));
+    }
+    Ok(module)
+}
+
+#[cfg(feature = "zip")]
+fn unzip(data: Vec<u8>) -> Result<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data))?;
+    let names = (0..archive.len())
+        .map(|i| Ok(archive.by_index_raw(i)?.name().to_string()))
+        .collect::<Result<Vec<String>>>()?;
+    let index = pick_module(&names)?;
+    let module = read_limited(archive.by_index(index)?)?;
+    Ok(module)
+}
+
+#[cfg(feature = "lha")]
+fn unlha(data: &[u8]) -> Result<Vec<u8>> {
+    // Files can only be read in order, so the modules are read as they come
+    // and the one to extract picked at the end
+    let mut reader =
+        delharc::LhaDecodeReader::new(data).map_err(|e| anyhow!("invalid LHA archive: {}", e))?;
+    let mut names = Vec::new();
+    let mut modules = Vec::new();
+    loop {
+        let name = reader
+            .header()
+            .parse_pathname()
+            .to_string_lossy()
+            .into_owned();
+        if is_module_name(&name) {
+            if !reader.is_decoder_supported() {
+                return Err(anyhow!(
+                    "{} is packed with a method this build can't unpack",
+                    name
+                ));
+            }
+            let module = read_limited(&mut reader)?;
+            reader
+                .crc_check()
+                .map_err(|e| anyhow!("{} is damaged: {}", name, e))?;
+            modules.push(module);
+        } else {
+            modules.push(Vec::new());
+        }
+        names.push(name);
+        if !reader
+            .next_file()
+            .map_err(|e| anyhow!("invalid LHA archive: {}", e))?
+        {
+            break;
+        }
+    }
+    let index = pick_module(&names)?;
+    Ok(modules.swap_remove(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_module_stem() {
+        assert_eq!(module_stem(Path::new("music/song.mod")), Some("song"));
+        assert_eq!(module_stem(Path::new("music/song.mod.zip")), Some("song"));
+        assert_eq!(module_stem(Path::new("music/pack.zip")), Some("pack"));
+        assert_eq!(module_stem(Path::new("song.xm.GZ")), Some("song"));
+        assert_eq!(
+            ArchiveFormat::from_path(Path::new("a.LZH")),
+            Some(ArchiveFormat::Lha)
+        );
+        assert_eq!(ArchiveFormat::from_path(Path::new("a.it")), None);
+    }
+
+    #[test]
+    #[cfg(feature = "zip")]
+    fn test_unzip() {
+        use std::io::Write;
+        use zip::write::SimpleFileOptions;
+
+        let module = std::fs::read("../../tests/modules/cndmcrrp.mod").unwrap();
+        let zip = |files: &[&str]| {
+            let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+            for name in files {
+                zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+                zip.write_all(&module).unwrap();
+            }
+            zip.finish().unwrap().into_inner()
+        };
+
+        let path = Path::new("song.zip");
+        let archive = zip(&["readme.txt", "__MACOSX/._song.mod", "song.mod"]);
+        assert_eq!(unpack(path, archive).unwrap(), module);
+        let error = unpack(path, zip(&["a.mod", "b.xm"])).unwrap_err();
+        assert!(error.to_string().contains("holds 2 modules (a.mod, b.xm)"));
+        assert!(unpack(path, zip(&["readme.txt"])).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn test_gunzip() {
+        use std::io::Write;
+
+        let module = std::fs::read("../../tests/modules/cndmcrrp.mod").unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&module).unwrap();
+        let archive = encoder.finish().unwrap();
+        assert_eq!(unpack(Path::new("song.mod.gz"), archive).unwrap(), module);
+    }
+}
@@ -0,0 +1,385 @@
+//! Drop folder handling for `untracker watch`, and watching a single module
+//! as a composer works on it.
+//!
+//! The drop folder is polled rather than watched through filesystem
+//! notifications, which are unreliable on the network shares render boxes
+//! usually serve. A module being composed is on the composer's own disk, so
+//! [`ModuleWatcher`] uses notifications and picks up saves right away.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+#[cfg(feature = "watch")]
+use std::{sync::mpsc, time::Duration};
+
+use crate::config::{ModuleOverrides, SIDECAR_SUFFIX};
+
+/// Name of the rolling manifest kept in the output directory.
+pub const MANIFEST_FILE: &str = "manifest.jsonl";
+
+/// A folder new modules are dropped into, with the folders processed modules are moved to.
+pub struct DropFolder {
+    dir: PathBuf,
+    done_dir: PathBuf,
+    failed_dir: PathBuf,
+    /// Size and modification time of files seen on the previous scan
+    seen: HashMap<PathBuf, (u64, SystemTime)>,
+    /// Processed modules that couldn't be moved out of the drop folder
+    stuck: HashSet<PathBuf>,
+}
+
+impl DropFolder {
+    /// Watch `dir`, moving processed modules to `done_dir` and `failed_dir`
+    /// (`done/` and `failed/` inside `dir` by default).
+    pub fn new(dir: &Path, done_dir: Option<PathBuf>, failed_dir: Option<PathBuf>) -> Result<Self> {
+        if !dir.is_dir() {
+            return Err(anyhow!("Drop folder {} does not exist", dir.display()));
+        }
+
+        let done_dir = done_dir.unwrap_or_else(|| dir.join("done"));
+        let failed_dir = failed_dir.unwrap_or_else(|| dir.join("failed"));
+        fs::create_dir_all(&done_dir)?;
+        fs::create_dir_all(&failed_dir)?;
+
+        Ok(DropFolder {
+            dir: dir.to_path_buf(),
+            done_dir,
+            failed_dir,
+            seen: HashMap::new(),
+            stuck: HashSet::new(),
+        })
+    }
+
+    /// Modules that are ready to be processed, in name order.
+    ///
+    /// With `wait_until_stable`, a file is only ready once its size and
+    /// modification time are unchanged since the previous scan, so files
+    /// that are still being copied in are left alone.
+    pub fn poll(&mut self, wait_until_stable: bool) -> Result<Vec<PathBuf>> {
+        let mut ready = Vec::new();
+        let mut seen = HashMap::new();
+
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() || !is_module_candidate(&path) || self.stuck.contains(&path) {
+                continue;
+            }
+
+            let state = (metadata.len(), metadata.modified()?);
+            if !wait_until_stable || self.seen.get(&path) == Some(&state) {
+                ready.push(path);
+            } else {
+                seen.insert(path, state);
+            }
+        }
+
+        self.seen = seen;
+        ready.sort();
+        Ok(ready)
+    }
+
+    /// Move a processed module, and its override file if it has one, out of the drop folder.
+    ///
+    /// A module of the same name moved there before is kept: the module gets
+    /// a number added to its name instead. Returns the new location of the
+    /// module. A module that can't be moved is left out of later scans, so
+    /// it isn't processed again.
+    pub fn finish(&mut self, input: &Path, succeeded: bool) -> Result<PathBuf> {
+        let destination_dir = if succeeded {
+            &self.done_dir
+        } else {
+            &self.failed_dir
+        };
+        let file_name = input
+            .file_name()
+            .ok_or_else(|| anyhow!("Invalid module path {}", input.display()))?;
+
+        let destination = unique_destination(destination_dir, Path::new(file_name));
+        let moved = move_file(input, &destination).map_err(|e| {
+            anyhow!(
+                "Cannot move {} to {}: {}",
+                input.display(),
+                destination.display(),
+                e
+            )
+        });
+        if let Err(e) = moved {
+            self.stuck.insert(input.to_path_buf());
+            return Err(e);
+        }
+
+        // The override file follows the new name of the module
+        let sidecar = ModuleOverrides::sidecar_path(input);
+        if sidecar.is_file() {
+            let sidecar_destination = ModuleOverrides::sidecar_path(&destination);
+            move_file(&sidecar, &sidecar_destination).map_err(|e| {
+                anyhow!(
+                    "Cannot move {} to {}: {}",
+                    sidecar.display(),
+                    sidecar_destination.display(),
+                    e
+                )
+            })?;
+        }
+        Ok(destination)
+    }
+}
+
+/// Path for `file_name` in `dir` that no file has yet: the name itself, or
+/// with `-1`, `-2`... added to its stem.
+fn unique_destination(dir: &Path, file_name: &Path) -> PathBuf {
+    let taken = |path: &Path| path.exists() || ModuleOverrides::sidecar_path(path).exists();
+    let destination = dir.join(file_name);
+    if !taken(&destination) {
+        return destination;
+    }
+    let stem = file_name.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = file_name
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| dir.join(format!("{}-{}{}", stem, n, extension)))
+        .find(|path| !taken(path))
+        .expect("some number is free")
+}
+
+/// Move `from` to `to`, copying it when they are on different filesystems,
+/// which a rename can't cross.
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+/// A module file that is re-extracted every time its tracker saves it.
+///
+/// Its directory is watched rather than the file itself, as trackers often
+/// save to a temporary file and rename it over the module, which would end a
+/// watch on the file.
+#[cfg(feature = "watch")]
+pub struct ModuleWatcher {
+    path: PathBuf,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    // Stops watching when dropped
+    _watcher: notify::RecommendedWatcher,
+}
+
+#[cfg(feature = "watch")]
+impl ModuleWatcher {
+    /// How long the module has to be left alone after a change before it is
+    /// considered saved, as a save can take several writes.
+    pub const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+    pub fn new(path: &Path) -> Result<Self> {
+        use notify::Watcher;
+
+        if !path.is_file() {
+            return Err(anyhow!("Module {} does not exist", path.display()));
+        }
+        let path = path.canonicalize()?;
+        let dir = path
+            .parent()
+            .ok_or_else(|| anyhow!("Invalid module path {}", path.display()))?;
+
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(dir, notify::RecursiveMode::NonRecursive)?;
+        Ok(ModuleWatcher {
+            path,
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    /// Wait until the module has been saved: changed, then left alone for
+    /// [`ModuleWatcher::SETTLE_TIME`], and still there.
+    pub fn wait_for_save(&self) -> Result<()> {
+        loop {
+            while !self.is_module_change(self.events.recv()?)? {}
+            loop {
+                match self.events.recv_timeout(Self::SETTLE_TIME) {
+                    Ok(event) => {
+                        self.is_module_change(event)?;
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            // Deleted rather than saved, or renamed away until the next save
+            if self.path.is_file() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn is_module_change(&self, event: notify::Result<notify::Event>) -> Result<bool> {
+        let event = event?;
+        Ok(!event.kind.is_access() && event.paths.contains(&self.path))
+    }
+}
+
+/// Move the files staged in `staged` whose contents differ from those of the
+/// same name in `target` into `target`, and return where they were moved.
+///
+/// Files that didn't change are left where they are, so that programs using
+/// the outputs, such as a DAW holding the stems of a song in progress, only
+/// reload those that did.
+pub fn replace_changed(staged: &Path, target: &Path) -> Result<Vec<PathBuf>> {
+    let mut changed = Vec::new();
+    fs::create_dir_all(target)?;
+    let mut entries = fs::read_dir(staged)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let destination = target.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            changed.extend(replace_changed(&entry.path(), &destination)?);
+        } else if fs::read(&destination).ok() != Some(fs::read(entry.path())?) {
+            fs::rename(entry.path(), &destination)?;
+            changed.push(destination);
+        }
+    }
+    Ok(changed)
+}
+
+fn is_module_candidate(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    // Hidden files include the partial files of most copy tools
+    !name.starts_with('.') && !name.ends_with(SIDECAR_SUFFIX)
+}
+
+/// One line of the rolling manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// File name of the module
+    pub module: String,
+    /// "done" or "failed"
+    pub status: String,
+    pub output_dir: PathBuf,
+    /// Number of stems written
+    pub stems: usize,
+    pub error: Option<String>,
+    /// Messages libopenmpt logged while loading the module, such as
+    /// unsupported effects or damaged sample data
+    pub warnings: Vec<String>,
+    /// Metadata of the module by libopenmpt key, such as `type_long`, `artist` or `date`
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    /// When processing finished, in RFC 3339 format
+    pub finished_at: String,
+}
+
+impl ManifestEntry {
+    pub fn new(
+        input: &Path,
+        output_dir: &Path,
+        result: &Result<usize>,
+        warnings: Vec<String>,
+    ) -> Self {
+        ManifestEntry {
+            module: input
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            status: if result.is_ok() { "done" } else { "failed" }.to_string(),
+            output_dir: output_dir.to_path_buf(),
+            stems: *result.as_ref().unwrap_or(&0),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            warnings,
+            metadata: BTreeMap::new(),
+            finished_at: chrono::Local::now().to_rfc3339(),
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: BTreeMap<String, String>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Append the entry to the manifest at `path` as a line of JSON.
+    pub fn append_to(&self, path: &Path) -> Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        writeln!(file, "{}", serde_json::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poll_waits_for_stable_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut folder = DropFolder::new(dir.path(), None, None).unwrap();
+        fs::write(dir.path().join("song.xm"), b"xm").unwrap();
+        fs::write(dir.path().join("song.untracker.toml"), b"").unwrap();
+        fs::write(dir.path().join(".song.mod.part"), b"").unwrap();
+
+        assert!(folder.poll(true).unwrap().is_empty());
+        assert_eq!(folder.poll(true).unwrap(), vec![dir.path().join("song.xm")]);
+
+        let done = folder.finish(&dir.path().join("song.xm"), true).unwrap();
+        assert_eq!(done, dir.path().join("done/song.xm"));
+        assert!(dir.path().join("done/song.untracker.toml").exists());
+        assert!(folder.poll(false).unwrap().is_empty());
+
+        // A module of the same name dropped again doesn't replace the first
+        fs::write(dir.path().join("song.xm"), b"xm again").unwrap();
+        fs::write(dir.path().join("song.untracker.toml"), b"").unwrap();
+        let done = folder.finish(&dir.path().join("song.xm"), true).unwrap();
+        assert_eq!(done, dir.path().join("done/song-1.xm"));
+        assert_eq!(fs::read(dir.path().join("done/song.xm")).unwrap(), b"xm");
+        assert!(dir.path().join("done/song-1.untracker.toml").exists());
+
+        // A module that can't be moved is not picked up again
+        fs::write(dir.path().join("other.mod"), b"mod").unwrap();
+        fs::remove_dir_all(dir.path().join("failed")).unwrap();
+        assert!(folder.finish(&dir.path().join("other.mod"), false).is_err());
+        assert!(dir.path().join("other.mod").exists());
+        assert!(folder.poll(false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_replace_changed() {
+        let staged = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        fs::create_dir(staged.path().join("subsong_01")).unwrap();
+        fs::write(staged.path().join("song_bass.wav"), b"bass").unwrap();
+        fs::write(staged.path().join("song_lead.wav"), b"new lead").unwrap();
+        fs::write(staged.path().join("subsong_01/song_pad.wav"), b"pad").unwrap();
+        fs::write(target.path().join("song_bass.wav"), b"bass").unwrap();
+        fs::write(target.path().join("song_lead.wav"), b"old lead").unwrap();
+
+        let changed = replace_changed(staged.path(), target.path()).unwrap();
+        assert_eq!(
+            changed,
+            vec![
+                target.path().join("song_lead.wav"),
+                target.path().join("subsong_01/song_pad.wav"),
+            ]
+        );
+        assert_eq!(
+            fs::read(target.path().join("song_lead.wav")).unwrap(),
+            b"new lead"
+        );
+        // Unchanged files stay staged
+        assert!(staged.path().join("song_bass.wav").exists());
+    }
+}
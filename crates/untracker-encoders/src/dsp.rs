@@ -0,0 +1,400 @@
+//! Small sample-level processing helpers applied to rendered audio before encoding.
+
+use std::borrow::Cow;
+use std::f64::consts::FRAC_PI_2;
+
+/// A type audio is rendered and processed in: 16-bit integers, or 32-bit
+/// floats for float output, where full scale is 1.0 but louder samples are kept.
+pub trait Sample: Copy + Default + PartialEq + Send + Sync + 'static {
+    /// Value as a fraction of full scale.
+    fn to_f64(self) -> f64;
+
+    /// Sample from a fraction of full scale, saturating if the type can't go further.
+    fn from_f64(value: f64) -> Self;
+
+    /// Whether the sample is louder than dither noise.
+    fn is_sound(self) -> bool;
+
+    /// The samples as 16-bit integers, for encoders and analysis that only take those.
+    fn quantize(samples: &[Self]) -> Cow<'_, [i16]>;
+
+    /// Sample as an integer of `quantizer.bits()` bits, for lossless encoders.
+    fn to_int(self, quantizer: &mut Quantizer) -> i32;
+}
+
+impl Sample for i16 {
+    fn to_f64(self) -> f64 {
+        self as f64 / 32768.0
+    }
+
+    fn from_f64(value: f64) -> Self {
+        (value * 32768.0)
+            .round()
+            .clamp(i16::MIN as f64, i16::MAX as f64) as i16
+    }
+
+    fn is_sound(self) -> bool {
+        self.unsigned_abs() > TRIM_THRESHOLD
+    }
+
+    fn quantize(samples: &[Self]) -> Cow<'_, [i16]> {
+        Cow::Borrowed(samples)
+    }
+
+    fn to_int(self, quantizer: &mut Quantizer) -> i32 {
+        // Already quantized, so extra bits stay zero and there is nothing to dither
+        (self as i32) << (quantizer.bits() - 16)
+    }
+}
+
+impl Sample for f32 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn is_sound(self) -> bool {
+        self.abs() > TRIM_THRESHOLD as f32 / 32768.0
+    }
+
+    fn quantize(samples: &[Self]) -> Cow<'_, [i16]> {
+        Cow::Owned(samples.iter().map(|&s| i16::from_f64(s as f64)).collect())
+    }
+
+    fn to_int(self, quantizer: &mut Quantizer) -> i32 {
+        quantizer.quantize(self as f64)
+    }
+}
+
+/// Converts floating point audio to integers of 16 to 24 bits, rounding to
+/// the nearest step or adding TPDF dither first.
+///
+/// Dither trades the distortion of rounding quiet sounds for a constant noise
+/// floor at the last bit. Its noise comes from a fixed seed, so output stays
+/// reproducible.
+pub struct Quantizer {
+    bits: u32,
+    /// State of the noise generator, if dithering
+    dither: Option<u64>,
+}
+
+impl Quantizer {
+    pub fn new(bits: u32, dither: bool) -> Self {
+        Quantizer {
+            bits,
+            dither: dither.then_some(0x9e37_79b9_7f4a_7c15),
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// Integer for a fraction of full scale, saturating at full scale.
+    pub fn quantize(&mut self, value: f64) -> i32 {
+        let scale = (1i64 << (self.bits - 1)) as f64;
+        let mut value = value * scale;
+        if let Some(state) = &mut self.dither {
+            // The sum of two uniform values in [-0.5, 0.5) has a triangular distribution
+            value += next_uniform(state) + next_uniform(state) - 1.0;
+        }
+        value.round().clamp(-scale, scale - 1.0) as i32
+    }
+}
+
+/// Next value in [0, 1) of an xorshift64 generator.
+fn next_uniform(state: &mut u64) -> f64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    (*state >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Apply a linear fade-out over the last `fade_frames` frames of interleaved audio.
+pub fn fade_out<S: Sample>(samples: &mut [S], channels: usize, fade_frames: usize) {
+    let total_frames = samples.len() / channels;
+    let fade_frames = fade_frames.min(total_frames);
+    if fade_frames == 0 {
+        return;
+    }
+
+    let start = total_frames - fade_frames;
+    for (i, frame) in samples[start * channels..].chunks_mut(channels).enumerate() {
+        let gain = 1.0 - (i + 1) as f64 / fade_frames as f64;
+        for sample in frame {
+            *sample = S::from_f64(sample.to_f64() * gain);
+        }
+    }
+}
+
+/// Crossfade `tail` into the start of `samples` with equal-power curves,
+/// `tail` fading out as `samples` fades in, over as many frames as the
+/// shorter of the two holds. Both are interleaved.
+pub fn crossfade<S: Sample>(samples: &mut [S], tail: &[S], channels: usize) {
+    let fade_frames = samples.len().min(tail.len()) / channels;
+    let frames = samples.chunks_mut(channels).zip(tail.chunks(channels));
+    for (i, (frame, tail)) in frames.take(fade_frames).enumerate() {
+        let (fade_in, fade_out) = (i as f64 / fade_frames as f64 * FRAC_PI_2).sin_cos();
+        for (sample, &faded) in frame.iter_mut().zip(tail) {
+            *sample = S::from_f64(sample.to_f64() * fade_in + faded.to_f64() * fade_out);
+        }
+    }
+}
+
+/// Highest absolute sample value, as a fraction of full scale.
+pub fn peak<S: Sample>(samples: &[S]) -> f64 {
+    samples.iter().map(|s| s.to_f64().abs()).fold(0.0, f64::max)
+}
+
+/// Scale audio so its peak reaches `target_dbfs`.
+///
+/// Returns the applied gain in dB. Silent audio is left untouched.
+pub fn normalize_peak<S: Sample>(samples: &mut [S], target_dbfs: f64) -> f64 {
+    let peak = peak(samples);
+    if peak == 0.0 {
+        return 0.0;
+    }
+
+    let gain_db = target_dbfs - 20.0 * peak.log10();
+    apply_gain(samples, gain_db);
+    gain_db
+}
+
+/// Root mean square level, as a fraction of full scale.
+pub fn rms<S: Sample>(samples: &[S]) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum: f64 = samples.iter().map(|s| s.to_f64().powi(2)).sum();
+    (sum / samples.len() as f64).sqrt()
+}
+
+/// Scale audio so its RMS level reaches `target_dbfs`, without pushing its
+/// peak past full scale.
+///
+/// The level is measured from the first to the last sound, so silence before
+/// and after it doesn't count. Returns the applied gain in dB. Silent audio is
+/// left untouched.
+pub fn normalize_rms<S: Sample>(samples: &mut [S], target_dbfs: f64) -> f64 {
+    let (Some(first), Some(last)) = (
+        samples.iter().position(|&s| s != S::default()),
+        samples.iter().rposition(|&s| s != S::default()),
+    ) else {
+        return 0.0;
+    };
+    let sound = &samples[first..=last];
+    let (rms, peak) = (rms(sound), peak(sound));
+
+    let gain_db = (target_dbfs - 20.0 * rms.log10()).min(-20.0 * peak.log10());
+    apply_gain(samples, gain_db);
+    gain_db
+}
+
+/// Apply a gain in dB, saturating at full scale for integer samples.
+pub fn apply_gain<S: Sample>(samples: &mut [S], gain_db: f64) {
+    let gain = 10f64.powf(gain_db / 20.0);
+    for sample in samples {
+        *sample = S::from_f64(sample.to_f64() * gain);
+    }
+}
+
+/// Level (in 16-bit sample units) at or below which a frame counts as silence
+/// when trimming, so dither noise doesn't count as sound.
+const TRIM_THRESHOLD: u16 = 1;
+
+/// Drops the silence at the end, and optionally at the start, of audio that
+/// arrives a chunk at a time.
+///
+/// Silence after the last sound heard so far is held back until more sound
+/// follows, and dropped if none does.
+pub struct SilenceTrimmer<S> {
+    channels: usize,
+    trim_start: bool,
+    /// Whether any sound has been seen yet
+    started: bool,
+    held: Vec<S>,
+    leading_frames: u64,
+}
+
+impl<S: Sample> SilenceTrimmer<S> {
+    pub fn new(channels: usize, trim_start: bool) -> Self {
+        SilenceTrimmer {
+            channels,
+            trim_start,
+            started: false,
+            held: Vec::new(),
+            leading_frames: 0,
+        }
+    }
+
+    /// Add a chunk of interleaved audio, handing whatever is sure to be kept to `sink`.
+    pub fn push<E>(
+        &mut self,
+        samples: &[S],
+        mut sink: impl FnMut(&[S]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let is_sound = |frame: &[S]| frame.iter().any(|s| s.is_sound());
+        let mut frames = samples.chunks(self.channels);
+        let Some(last) = frames.clone().rposition(is_sound) else {
+            if self.started || !self.trim_start {
+                self.held.extend_from_slice(samples);
+            } else {
+                self.leading_frames += (samples.len() / self.channels) as u64;
+            }
+            return Ok(());
+        };
+
+        let first = if self.started || !self.trim_start {
+            0
+        } else {
+            frames.position(is_sound).unwrap_or(0)
+        };
+        self.leading_frames += first as u64;
+        self.started = true;
+
+        if !self.held.is_empty() {
+            sink(&self.held)?;
+            self.held.clear();
+        }
+        sink(&samples[first * self.channels..(last + 1) * self.channels])?;
+        self.held
+            .extend_from_slice(&samples[(last + 1) * self.channels..]);
+        Ok(())
+    }
+
+    /// Number of silent frames dropped from the start.
+    pub fn leading_frames(&self) -> u64 {
+        self.leading_frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fade_out_reaches_silence() {
+        let mut samples = vec![1000i16; 20];
+        fade_out(&mut samples, 2, 5);
+        assert_eq!(&samples[..10], &[1000; 10]);
+        assert!(samples[10] < 1000 && samples[10] > 0);
+        assert_eq!(samples[18], 0);
+        assert_eq!(samples[19], 0);
+    }
+
+    #[test]
+    fn test_crossfade() {
+        let mut samples = vec![0.0f32; 12];
+        let tail = vec![0.5f32; 8];
+        crossfade(&mut samples, &tail, 2);
+        // Starts on the tail, then fades to the samples
+        assert_eq!(&samples[..2], &[0.5, 0.5]);
+        assert!(samples[4] < 0.5 && samples[4] > samples[6]);
+        assert_eq!(&samples[8..], &[0.0; 4]);
+
+        // Equal power: a signal crossfaded into itself gets louder midway
+        let mut samples = vec![0.5f32; 8];
+        crossfade(&mut samples, &[0.5; 8], 1);
+        assert!(samples[4] > 0.7);
+    }
+
+    #[test]
+    fn test_normalize_peak() {
+        let mut samples = vec![0i16, 1638, -3277, 100];
+        let gain_db = normalize_peak(&mut samples, -6.0);
+        assert!((gain_db - 14.0).abs() < 0.1);
+        assert!((peak(&samples) - 10f64.powf(-6.0 / 20.0)).abs() < 0.001);
+
+        let mut silence = vec![0i16; 8];
+        assert_eq!(normalize_peak(&mut silence, -1.0), 0.0);
+    }
+
+    #[test]
+    fn test_normalize_rms() {
+        let square: Vec<i16> = (0..100)
+            .map(|i| if i % 2 == 0 { 3277 } else { -3277 })
+            .collect();
+        // Silence around the sound doesn't lower its level
+        let mut samples = [vec![0; 50], square.clone(), vec![0; 50]].concat();
+        let gain_db = normalize_rms(&mut samples, -12.0);
+        assert!((gain_db - 8.0).abs() < 0.1);
+        assert!((20.0 * rms(&samples[50..150]).log10() + 12.0).abs() < 0.01);
+
+        // The gain stops where the peak reaches full scale
+        let mut samples = square;
+        let gain_db = normalize_rms(&mut samples, 6.0);
+        assert!((gain_db - 20.0).abs() < 0.01);
+        assert!(peak(&samples) > 0.99);
+
+        assert_eq!(normalize_rms(&mut [0i16; 8], -18.0), 0.0);
+    }
+
+    #[test]
+    fn test_float_samples_keep_headroom() {
+        let mut samples = vec![0.25f32, -0.5, 0.0];
+        apply_gain(&mut samples, 12.0);
+        // Floats are not clipped at full scale, integers are
+        assert!((samples[1] + 1.991).abs() < 0.001);
+        assert!(peak(&samples) > 1.99);
+        assert_eq!(f32::quantize(&samples)[1], i16::MIN);
+        assert_eq!(i16::from_f64(0.25f32.to_f64()), 8192);
+    }
+
+    #[test]
+    fn test_quantizer() {
+        let mut quantizer = Quantizer::new(24, false);
+        assert_eq!(0.5f32.to_int(&mut quantizer), 1 << 22);
+        assert_eq!(2.0f32.to_int(&mut quantizer), (1 << 23) - 1);
+        assert_eq!((-1i16).to_int(&mut quantizer), -256);
+        // Rounded, not truncated
+        assert_eq!(Quantizer::new(16, false).quantize(-2.6 / 32768.0), -3);
+
+        // Dither moves samples by at most a step, and averages out
+        let mut quantizer = Quantizer::new(16, true);
+        let value = 10.25 / 32768.0;
+        let dithered: Vec<i32> = (0..10000).map(|_| quantizer.quantize(value)).collect();
+        assert!(dithered.iter().all(|&s| (9..=11).contains(&s)));
+        let mean = dithered.iter().sum::<i32>() as f64 / dithered.len() as f64;
+        assert!((mean - 10.25).abs() < 0.05);
+    }
+
+    fn trim(chunks: &[&[i16]], trim_start: bool) -> (Vec<i16>, u64) {
+        let mut trimmer = SilenceTrimmer::new(2, trim_start);
+        let mut kept = Vec::new();
+        for chunk in chunks {
+            trimmer
+                .push(chunk, |samples| {
+                    kept.extend_from_slice(samples);
+                    Ok::<_, ()>(())
+                })
+                .unwrap();
+        }
+        (kept, trimmer.leading_frames())
+    }
+
+    #[test]
+    fn test_silence_trimmer() {
+        let chunks: [&[i16]; 4] = [
+            &[0, 1, 0, 0],
+            &[0, 0, 5, 0, 0, -1],
+            &[0, 0, 0, 0],
+            &[0, 9, 1, 0],
+        ];
+
+        // Silence between sounds is kept, trailing silence and dither are not
+        let (kept, leading) = trim(&chunks, false);
+        assert_eq!(kept, vec![0, 1, 0, 0, 0, 0, 5, 0, 0, -1, 0, 0, 0, 0, 0, 9]);
+        assert_eq!(leading, 0);
+
+        let (kept, leading) = trim(&chunks, true);
+        assert_eq!(kept, vec![5, 0, 0, -1, 0, 0, 0, 0, 0, 9]);
+        assert_eq!(leading, 3);
+
+        let (kept, leading) = trim(&[&[0, 0, 1, -1]], true);
+        assert!(kept.is_empty());
+        assert_eq!(leading, 2);
+    }
+}
@@ -0,0 +1,177 @@
+//! Registry of the output formats untracker knows about.
+//!
+//! Every format is listed here, including the ones left out of the current
+//! build, so that format names can be told apart from typos and the error can
+//! name the Cargo feature that enables them.
+
+use anyhow::{anyhow, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFormat {
+    Wav,
+    #[cfg(feature = "vorbis")]
+    Vorbis,
+    #[cfg(feature = "opus")]
+    Opus,
+    #[cfg(feature = "flac")]
+    Flac,
+    #[cfg(feature = "mp3")]
+    Mp3,
+}
+
+/// Static description of an output format.
+#[derive(Debug)]
+pub struct FormatInfo {
+    /// Canonical name, as accepted by `--format`
+    pub name: &'static str,
+    /// Other names accepted for the format
+    pub aliases: &'static [&'static str],
+    /// File extension used for output files
+    pub extension: &'static str,
+    /// Cargo feature that compiles the encoder in, if it is optional
+    pub feature: Option<&'static str>,
+}
+
+impl FormatInfo {
+    fn matches(&self, name: &str) -> bool {
+        self.name == name || self.aliases.contains(&name)
+    }
+
+    /// The format, if its encoder is part of this build.
+    pub fn format(&self) -> Option<AudioFormat> {
+        match self.name {
+            "wav" => Some(AudioFormat::Wav),
+            #[cfg(feature = "vorbis")]
+            "vorbis" => Some(AudioFormat::Vorbis),
+            #[cfg(feature = "opus")]
+            "opus" => Some(AudioFormat::Opus),
+            #[cfg(feature = "flac")]
+            "flac" => Some(AudioFormat::Flac),
+            #[cfg(feature = "mp3")]
+            "mp3" => Some(AudioFormat::Mp3),
+            _ => None,
+        }
+    }
+}
+
+/// All known formats, available or not.
+pub const FORMATS: &[FormatInfo] = &[
+    FormatInfo {
+        name: "wav",
+        aliases: &[],
+        extension: "wav",
+        feature: None,
+    },
+    FormatInfo {
+        name: "vorbis",
+        aliases: &["ogg"],
+        extension: "ogg",
+        feature: Some("vorbis"),
+    },
+    FormatInfo {
+        name: "opus",
+        aliases: &[],
+        extension: "opus",
+        feature: Some("opus"),
+    },
+    FormatInfo {
+        name: "flac",
+        aliases: &[],
+        extension: "flac",
+        feature: Some("flac"),
+    },
+    FormatInfo {
+        name: "mp3",
+        aliases: &[],
+        extension: "mp3",
+        feature: Some("mp3"),
+    },
+];
+
+impl AudioFormat {
+    /// Look up a format by name or alias, whether or not it is compiled in.
+    pub fn info_by_name(name: &str) -> Option<&'static FormatInfo> {
+        let name = name.trim().to_lowercase();
+        FORMATS.iter().find(|info| info.matches(&name))
+    }
+
+    /// Formats whose encoders are part of this build.
+    pub fn available() -> impl Iterator<Item = AudioFormat> {
+        FORMATS.iter().filter_map(FormatInfo::format)
+    }
+
+    pub fn info(self) -> &'static FormatInfo {
+        FORMATS
+            .iter()
+            .find(|info| info.format() == Some(self))
+            .expect("every format is registered")
+    }
+
+    pub fn name(self) -> &'static str {
+        self.info().name
+    }
+
+    /// File extension used for output files of this format.
+    pub fn extension(self) -> &'static str {
+        self.info().extension
+    }
+}
+
+impl std::str::FromStr for AudioFormat {
+    type Err = anyhow::Error;
+
+    /// Parse a format name, or a `|` separated list of names meaning "the
+    /// first of these that is compiled in" (e.g. `opus|vorbis|wav`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Check every name up front so a typo is reported even when an
+        // earlier format in the list is available
+        let infos = s
+            .split('|')
+            .map(|name| {
+                AudioFormat::info_by_name(name)
+                    .ok_or_else(|| anyhow!("Unknown audio format: {}", name.trim()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if let Some(format) = infos.iter().find_map(|info| info.format()) {
+            return Ok(format);
+        }
+
+        let missing_features: Vec<_> = infos.iter().filter_map(|info| info.feature).collect();
+        let available: Vec<_> = AudioFormat::available().map(AudioFormat::name).collect();
+        Err(anyhow!(
+            "Audio format {} is not available in this build (requires the {} feature; available: {})",
+            s,
+            missing_features.join("/"),
+            available.join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fallback_chain() {
+        assert_eq!("wav".parse::<AudioFormat>().unwrap(), AudioFormat::Wav);
+        let format: AudioFormat = "opus|vorbis|wav".parse().unwrap();
+        #[cfg(feature = "opus")]
+        assert_eq!(format, AudioFormat::Opus);
+        #[cfg(not(any(feature = "opus", feature = "vorbis")))]
+        assert_eq!(format, AudioFormat::Wav);
+
+        assert!("opus|mp4|wav".parse::<AudioFormat>().is_err());
+    }
+
+    #[test]
+    fn test_registry_is_consistent() {
+        for format in AudioFormat::available() {
+            assert_eq!(
+                AudioFormat::info_by_name(format.name()).unwrap().format(),
+                Some(format)
+            );
+        }
+        assert_eq!(AudioFormat::info_by_name("OGG").unwrap().name, "vorbis");
+    }
+}
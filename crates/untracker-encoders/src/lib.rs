@@ -0,0 +1,1528 @@
+//! The audio file writers of untracker: WAV, and Vorbis, Opus, FLAC and MP3
+//! behind the features of the same names, with the sample conversions they
+//! share. Builds that leave a format out don't link its encoder library.
+
+use anyhow::{anyhow, Result};
+use hound::{WavSpec, WavWriter};
+use log::info;
+// Files are written to memory instead of disk in `memfs` builds
+#[cfg(feature = "memfs")]
+use crate::memfs as fs;
+#[cfg(not(feature = "memfs"))]
+use std::fs;
+use std::io::{BufWriter, Seek, Write};
+use std::path::Path;
+
+pub mod dsp;
+pub mod format;
+#[cfg(feature = "memfs")]
+pub mod memfs;
+pub mod resample;
+
+use dsp::{Quantizer, Sample};
+pub use format::AudioFormat;
+pub use resample::Resampler;
+
+/// How samples are stored in output files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// Integers of `bit_depth` bits
+    Int,
+    /// 32-bit floats, rendered by libopenmpt in floating point and kept above
+    /// full scale. WAV only
+    Float,
+}
+
+/// Bitrate management of the Opus encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpusVbr {
+    /// Variable bitrate, averaging the target bitrate
+    On,
+    /// Constant bitrate
+    Off,
+    /// Variable bitrate that stays close to the target bitrate from packet
+    /// to packet, for streaming
+    Constrained,
+}
+
+impl std::str::FromStr for OpusVbr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "on" => Ok(OpusVbr::On),
+            "off" => Ok(OpusVbr::Off),
+            "constrained" => Ok(OpusVbr::Constrained),
+            _ => Err(anyhow!(
+                "Unknown Opus VBR mode: {} (on, off or constrained)",
+                s
+            )),
+        }
+    }
+}
+
+impl OpusVbr {
+    pub fn name(self) -> &'static str {
+        match self {
+            OpusVbr::On => "on",
+            OpusVbr::Off => "off",
+            OpusVbr::Constrained => "constrained",
+        }
+    }
+}
+
+/// What the Opus encoder tunes itself for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpusApplication {
+    /// Music and anything else that should decode as close to the input as possible
+    Audio,
+    /// Speech, favoring intelligibility
+    Voip,
+    /// The lowest delay, without the speech modes
+    LowDelay,
+}
+
+impl std::str::FromStr for OpusApplication {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "audio" => Ok(OpusApplication::Audio),
+            "voip" => Ok(OpusApplication::Voip),
+            "lowdelay" | "low-delay" => Ok(OpusApplication::LowDelay),
+            _ => Err(anyhow!(
+                "Unknown Opus application: {} (audio, voip or low-delay)",
+                s
+            )),
+        }
+    }
+}
+
+impl OpusApplication {
+    pub fn name(self) -> &'static str {
+        match self {
+            OpusApplication::Audio => "audio",
+            OpusApplication::Voip => "voip",
+            OpusApplication::LowDelay => "low-delay",
+        }
+    }
+}
+
+/// Sample rates the Opus encoder takes.
+pub const OPUS_SAMPLE_RATES: [u32; 5] = [8000, 12000, 16000, 24000, 48000];
+
+/// Rate Opus files of audio rendered at `sample_rate` are encoded at: the
+/// lowest rate Opus takes that keeps all of the audio's bandwidth, or 48 kHz,
+/// its highest.
+pub fn opus_sample_rate(sample_rate: u32) -> u32 {
+    OPUS_SAMPLE_RATES
+        .into_iter()
+        .find(|&rate| rate >= sample_rate)
+        .unwrap_or(48000)
+}
+
+/// Constant bitrates supported by LAME, in kbps.
+pub const MP3_BITRATES: [u32; 16] = [
+    8, 16, 24, 32, 40, 48, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+
+/// How an [`AudioWriter`] encodes a file: the part of the render options
+/// of untracker that concerns the output format.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncoderOptions {
+    pub format: AudioFormat,
+    pub sample_rate: u32,
+    pub channels: u32,
+    /// Bits per sample: 16 or 24 for integer samples, 32 for float samples
+    pub bit_depth: u32,
+    pub sample_format: SampleFormat,
+    /// Add TPDF dither when float samples are converted to integers, rather
+    /// than rounding them
+    pub dither: bool,
+    pub opus_bitrate: u32,
+    pub opus_vbr: OpusVbr,
+    /// Opus encoder complexity from 0 (fastest) to 10 (best)
+    pub opus_complexity: u32,
+    pub opus_application: OpusApplication,
+    pub vorbis_quality: u32,
+    /// Constant MP3 bitrate in kbps, used unless `mp3_vbr_quality` is set
+    pub mp3_bitrate: u32,
+    /// LAME VBR quality from 0 (best) to 9, selecting variable bitrate MP3
+    pub mp3_vbr_quality: Option<u32>,
+    /// Fixed Ogg stream serials and no version strings in metadata, so
+    /// that files are byte-identical across runs
+    pub reproducible: bool,
+}
+
+impl Default for EncoderOptions {
+    fn default() -> Self {
+        EncoderOptions {
+            format: AudioFormat::Wav,
+            sample_rate: 44100,
+            channels: 2,
+            bit_depth: 16,
+            sample_format: SampleFormat::Int,
+            dither: false,
+            opus_bitrate: 128,
+            opus_vbr: OpusVbr::On,
+            opus_complexity: 10,
+            opus_application: OpusApplication::Audio,
+            vorbis_quality: 5,
+            mp3_bitrate: 192,
+            mp3_vbr_quality: None,
+            reproducible: false,
+        }
+    }
+}
+
+impl EncoderOptions {
+    /// Check that the encoders support the options.
+    pub fn validate(&self) -> Result<()> {
+        match (self.sample_format, self.bit_depth) {
+            (SampleFormat::Int, 16 | 24) | (SampleFormat::Float, 32) => {}
+            (SampleFormat::Int, _) => {
+                return Err(anyhow!(
+                    "Only 16 or 24 bit depth is supported for integer samples (32 bit is float)"
+                ))
+            }
+            (SampleFormat::Float, _) => return Err(anyhow!("Float samples are 32 bit")),
+        }
+        if self.sample_format == SampleFormat::Float && self.format != AudioFormat::Wav {
+            return Err(anyhow!(
+                "Float samples are only supported for WAV output, not {}",
+                self.format.name()
+            ));
+        }
+        if self.sample_format == SampleFormat::Float && self.dither {
+            return Err(anyhow!("Only integer samples can be dithered"));
+        }
+
+        if self.opus_complexity > 10 {
+            return Err(anyhow!(
+                "Opus complexity must be between 0 and 10 (got {})",
+                self.opus_complexity
+            ));
+        }
+
+        if !MP3_BITRATES.contains(&self.mp3_bitrate) {
+            return Err(anyhow!(
+                "MP3 bitrate must be one of {:?} kbps (got {})",
+                MP3_BITRATES,
+                self.mp3_bitrate
+            ));
+        }
+
+        if self.mp3_vbr_quality.is_some_and(|quality| quality > 9) {
+            return Err(anyhow!("MP3 VBR quality must be between 0 and 9"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Write a whole rendered buffer to `filename`.
+pub fn write_audio_file<P: AsRef<Path>>(
+    samples: &[i16],
+    filename: P,
+    options: &EncoderOptions,
+) -> Result<()> {
+    let filename_path = filename.as_ref();
+    let filename_str = filename_path.to_string_lossy();
+
+    info!(
+        "Writing audio file: {} ({} samples, {}Hz)",
+        filename_str,
+        samples.len(),
+        options.sample_rate
+    );
+    let result = fs::File::create(filename_path)
+        .map_err(Into::into)
+        .and_then(|file| write_audio(samples, filename_path, file, options));
+
+    match &result {
+        Ok(_) => info!("Successfully wrote audio file: {}", filename_str),
+        Err(e) => log::error!("Failed to write audio file {}: {}", filename_str, e),
+    }
+
+    result
+}
+
+/// Write a whole rendered buffer to `output`, such as a file or a `Cursor`
+/// over memory, named `name` in the serial numbers of Ogg streams.
+pub fn write_audio(
+    samples: &[i16],
+    name: &Path,
+    output: impl SeekableOutput,
+    options: &EncoderOptions,
+) -> Result<()> {
+    let mut writer = AudioWriter::create_seekable(name, output, options, &[])?;
+    writer.write(samples)?;
+    writer.finish()
+}
+
+/// A metadata tag of an encoded file, such as `("TITLE", "Lead")`.
+pub type Tag = (String, String);
+
+/// Path standing for stdout: [`AudioWriter::create`] streams to it rather
+/// than writing a file, for piping a render into another program.
+pub const STDOUT_PATH: &str = "-";
+
+/// Target an [`AudioWriter`] can seek back in, such as a file or a `Cursor`
+/// over memory, so that WAV headers are completed once the length is known.
+pub trait SeekableOutput: Write + Seek + Send {}
+
+impl<T: Write + Seek + Send> SeekableOutput for T {}
+
+/// Stream an [`AudioWriter`] writes to without seeking.
+type OutputStream<'a> = Box<dyn Write + Send + 'a>;
+
+/// Where an [`AudioWriter`] writes its encoded audio.
+enum Output<'a> {
+    Seekable(Box<dyn SeekableOutput + 'a>),
+    Stream(OutputStream<'a>),
+}
+
+impl<'a> Output<'a> {
+    #[cfg(any(feature = "vorbis", feature = "opus", feature = "mp3"))]
+    fn into_stream(self) -> OutputStream<'a> {
+        match self {
+            Output::Seekable(output) => Box::new(output),
+            Output::Stream(stream) => stream,
+        }
+    }
+}
+
+/// Encoder writing interleaved 16-bit audio to a file, or any other target
+/// of [`std::io::Write`], as it is rendered.
+///
+/// Every format is encoded and flushed chunk by chunk; FLAC frames are
+/// written as each block of 4096 frames is complete.
+///
+/// Targets that can't seek are written the same way, except for the headers
+/// that would be completed once the length is known. WAV is written with the
+/// largest sizes it can hold, which players and `ffmpeg` take as "until the
+/// end of the stream", and FLAC leaves the length and MD5 sum unknown.
+pub struct AudioWriter<'a> {
+    encoder: Encoder<'a>,
+}
+
+enum Encoder<'a> {
+    Wav(WavStream<'a>),
+    #[cfg(feature = "vorbis")]
+    Vorbis(VorbisStream<'a>),
+    #[cfg(feature = "opus")]
+    Opus(OpusStream<'a>),
+    #[cfg(feature = "flac")]
+    Flac(Box<FlacStream<'a>>),
+    #[cfg(feature = "mp3")]
+    Mp3(Mp3Stream<'a>),
+}
+
+impl<'a> AudioWriter<'a> {
+    pub fn create(filename: &Path, options: &EncoderOptions) -> Result<Self> {
+        Self::create_tagged(filename, options, &[])
+    }
+
+    /// Start writing `filename`, or stdout if it is [`STDOUT_PATH`], with
+    /// `tags` in the comments of FLAC, Vorbis and Opus files. WAV and MP3
+    /// files are written without tags.
+    pub fn create_tagged(filename: &Path, options: &EncoderOptions, tags: &[Tag]) -> Result<Self> {
+        let output = if filename == Path::new(STDOUT_PATH) {
+            Output::Stream(Box::new(std::io::stdout()))
+        } else {
+            Output::Seekable(Box::new(fs::File::create(filename)?))
+        };
+        Self::create_output(filename, output, options, tags)
+    }
+
+    /// Start writing to `output`, such as a `Cursor` over memory, tagged as
+    /// [`AudioWriter::create_tagged`] does. `name` stands for the file name
+    /// in log messages and in the serial numbers of Ogg streams.
+    pub fn create_seekable(
+        name: &Path,
+        output: impl SeekableOutput + 'a,
+        options: &EncoderOptions,
+        tags: &[Tag],
+    ) -> Result<Self> {
+        Self::create_output(name, Output::Seekable(Box::new(output)), options, tags)
+    }
+
+    /// Start writing to `stream`, such as a socket or a pipe, which doesn't
+    /// have to seek. `name` is used as by [`AudioWriter::create_seekable`].
+    pub fn create_stream(
+        name: &Path,
+        stream: impl Write + Send + 'a,
+        options: &EncoderOptions,
+        tags: &[Tag],
+    ) -> Result<Self> {
+        Self::create_output(name, Output::Stream(Box::new(stream)), options, tags)
+    }
+
+    fn create_output(
+        name: &Path,
+        output: Output<'a>,
+        options: &EncoderOptions,
+        tags: &[Tag],
+    ) -> Result<Self> {
+        #[cfg(not(any(feature = "vorbis", feature = "opus", feature = "flac")))]
+        let _ = tags;
+        #[cfg(not(any(feature = "vorbis", feature = "opus")))]
+        let _ = name;
+        let encoder = match options.format {
+            AudioFormat::Wav => Encoder::Wav(WavStream::create(output, options)?),
+            #[cfg(feature = "vorbis")]
+            AudioFormat::Vorbis => {
+                Encoder::Vorbis(VorbisStream::create(name, output, options, tags)?)
+            }
+            #[cfg(feature = "opus")]
+            AudioFormat::Opus => Encoder::Opus(OpusStream::create(name, output, options, tags)?),
+            #[cfg(feature = "flac")]
+            AudioFormat::Flac => {
+                Encoder::Flac(Box::new(FlacStream::create(output, options, tags)?))
+            }
+            #[cfg(feature = "mp3")]
+            AudioFormat::Mp3 => Encoder::Mp3(Mp3Stream::create(output, options)?),
+        };
+        Ok(AudioWriter { encoder })
+    }
+
+    /// Encode the next chunk of audio in any sample type. WAV and FLAC files
+    /// get float samples at their bit depth, lossy encoders as 16-bit integers.
+    pub fn write_samples<S: Sample>(&mut self, samples: &[S]) -> Result<()> {
+        match &mut self.encoder {
+            Encoder::Wav(stream) => stream.write(samples),
+            #[cfg(feature = "flac")]
+            Encoder::Flac(stream) => stream.write(samples),
+            #[allow(unreachable_patterns)]
+            _ => self.write(&S::quantize(samples)),
+        }
+    }
+
+    /// Encode the next chunk of audio, which may be of any length.
+    pub fn write(&mut self, samples: &[i16]) -> Result<()> {
+        match &mut self.encoder {
+            Encoder::Wav(stream) => stream.write(samples),
+            #[cfg(feature = "vorbis")]
+            Encoder::Vorbis(stream) => stream.write(samples),
+            #[cfg(feature = "opus")]
+            Encoder::Opus(stream) => stream.write(samples),
+            #[cfg(feature = "flac")]
+            Encoder::Flac(stream) => stream.write(samples),
+            #[cfg(feature = "mp3")]
+            Encoder::Mp3(stream) => stream.write(samples),
+        }
+    }
+
+    /// Flush the remaining audio and complete the file.
+    pub fn finish(self) -> Result<()> {
+        match self.encoder {
+            Encoder::Wav(stream) => stream.finish(),
+            #[cfg(feature = "vorbis")]
+            Encoder::Vorbis(stream) => stream.finish(),
+            #[cfg(feature = "opus")]
+            Encoder::Opus(stream) => stream.finish(),
+            #[cfg(feature = "flac")]
+            Encoder::Flac(stream) => stream.finish(),
+            #[cfg(feature = "mp3")]
+            Encoder::Mp3(stream) => stream.finish(),
+        }
+    }
+}
+
+struct WavStream<'a> {
+    writer: WavSink<'a>,
+    sample_format: SampleFormat,
+    quantizer: Quantizer,
+}
+
+enum WavSink<'a> {
+    /// A target whose header hound completes once the length is known
+    Seekable(WavWriter<BufWriter<Box<dyn SeekableOutput + 'a>>>),
+    /// A stream, written sample by sample after a header of unknown length
+    Stream {
+        stream: BufWriter<OutputStream<'a>>,
+        bytes_per_sample: usize,
+    },
+}
+
+impl<'a> WavStream<'a> {
+    fn create(output: Output<'a>, options: &EncoderOptions) -> Result<Self> {
+        let spec = WavSpec {
+            channels: options.channels as u16,
+            sample_rate: options.sample_rate,
+            bits_per_sample: options.bit_depth as u16,
+            sample_format: match options.sample_format {
+                SampleFormat::Int => hound::SampleFormat::Int,
+                SampleFormat::Float => hound::SampleFormat::Float,
+            },
+        };
+
+        let writer = match output {
+            Output::Seekable(output) => {
+                WavSink::Seekable(WavWriter::new(BufWriter::new(output), spec)?)
+            }
+            Output::Stream(stream) => {
+                let mut stream = BufWriter::new(stream);
+                stream.write_all(&streaming_wav_header(&spec))?;
+                WavSink::Stream {
+                    stream,
+                    bytes_per_sample: options.bit_depth as usize / 8,
+                }
+            }
+        };
+        Ok(WavStream {
+            writer,
+            sample_format: options.sample_format,
+            quantizer: Quantizer::new(options.bit_depth, options.dither),
+        })
+    }
+
+    fn write<S: Sample>(&mut self, samples: &[S]) -> Result<()> {
+        let float = self.sample_format == SampleFormat::Float;
+        match &mut self.writer {
+            WavSink::Seekable(writer) if float => {
+                for &sample in samples {
+                    writer.write_sample(sample.to_f64() as f32)?;
+                }
+            }
+            // hound takes samples of any bit depth as i32
+            WavSink::Seekable(writer) => {
+                for &sample in samples {
+                    writer.write_sample(sample.to_int(&mut self.quantizer))?;
+                }
+            }
+            WavSink::Stream { stream, .. } if float => {
+                for &sample in samples {
+                    stream.write_all(&(sample.to_f64() as f32).to_le_bytes())?;
+                }
+            }
+            WavSink::Stream {
+                stream,
+                bytes_per_sample,
+            } => {
+                for &sample in samples {
+                    let bytes = sample.to_int(&mut self.quantizer).to_le_bytes();
+                    stream.write_all(&bytes[..*bytes_per_sample])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self.writer {
+            WavSink::Seekable(writer) => writer.finalize()?,
+            WavSink::Stream { mut stream, .. } => stream.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Header of a WAV stream of unknown length, whose RIFF and data chunks are
+/// as long as their sizes allow.
+fn streaming_wav_header(spec: &WavSpec) -> Vec<u8> {
+    let block_align = spec.channels * spec.bits_per_sample / 8;
+    let format: u16 = match spec.sample_format {
+        hound::SampleFormat::Int => 1,   // PCM
+        hound::SampleFormat::Float => 3, // IEEE float
+    };
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&format.to_le_bytes());
+    header.extend_from_slice(&spec.channels.to_le_bytes());
+    header.extend_from_slice(&spec.sample_rate.to_le_bytes());
+    header.extend_from_slice(&(spec.sample_rate * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&spec.bits_per_sample.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header
+}
+
+/// `smpl` chunk of a WAV file with a forward loop over frames `loop_start`
+/// to `loop_end` inclusive, as samplers and game engines read loop points.
+pub fn smpl_chunk(sample_rate: u32, loop_start: u64, loop_end: u64) -> Vec<u8> {
+    let mut smpl = Vec::with_capacity(60);
+    for value in [
+        0,                                         // manufacturer
+        0,                                         // product
+        (1e9 / sample_rate as f64).round() as u32, // sample period in ns
+        60,                                        // MIDI unity note
+        0,                                         // MIDI pitch fraction
+        0,                                         // SMPTE format
+        0,                                         // SMPTE offset
+        1,                                         // loop count
+        0,                                         // sampler data
+        0,                                         // cue point id
+        0,                                         // forward loop
+        loop_start as u32,                         // loop start
+        loop_end as u32,                           // loop end, inclusive
+        0,                                         // fraction
+        0,                                         // play count, 0 for endless
+    ] {
+        smpl.extend_from_slice(&value.to_le_bytes());
+    }
+    smpl
+}
+
+/// Append chunks, given as their id and contents, to the WAV file at `path`.
+pub fn append_wav_chunks(path: &Path, chunks: &[(&[u8; 4], &[u8])]) -> Result<()> {
+    let mut data = fs::read(path)?;
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err(anyhow!("{} is not a WAV file", path.display()));
+    }
+    for (id, chunk) in chunks {
+        // Chunks start on even offsets
+        if data.len() % 2 == 1 {
+            data.push(0);
+        }
+        data.extend_from_slice(*id);
+        data.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+        data.extend_from_slice(chunk);
+    }
+    let riff_size = (data.len() - 8) as u32;
+    data[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    fs::write(path, data)?;
+    Ok(())
+}
+
+/// Vendor string written into the metadata of encoded files.
+#[cfg(any(feature = "opus", feature = "flac"))]
+fn vendor_string(options: &EncoderOptions) -> String {
+    if options.reproducible {
+        "untracker".to_string()
+    } else {
+        format!("untracker {}", env!("CARGO_PKG_VERSION"))
+    }
+}
+
+/// Vorbis comment header as Opus and FLAC files hold it: the vendor string
+/// and `KEY=value` tags, each preceded by its length.
+#[cfg(any(feature = "opus", feature = "flac"))]
+fn vorbis_comment(vendor: &str, tags: &[Tag]) -> Vec<u8> {
+    let mut comment = Vec::new();
+    comment.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    comment.extend_from_slice(vendor.as_bytes());
+    comment.extend_from_slice(&(tags.len() as u32).to_le_bytes());
+    for (key, value) in tags {
+        let tag = format!("{}={}", key, value);
+        comment.extend_from_slice(&(tag.len() as u32).to_le_bytes());
+        comment.extend_from_slice(tag.as_bytes());
+    }
+    comment
+}
+
+/// Serial number of the Ogg stream written to `filename`.
+///
+/// Serials should differ between streams so files can be chained, so they are
+/// random unless the output has to be reproducible, in which case they are
+/// derived from the file name.
+#[cfg(any(feature = "vorbis", feature = "opus"))]
+fn ogg_serial(filename: &Path, options: &EncoderOptions) -> u32 {
+    use std::hash::{BuildHasher, Hash, Hasher};
+
+    let name = filename.file_name().unwrap_or_default().as_encoded_bytes();
+    if options.reproducible {
+        // FNV-1a, which unlike the std hashers is stable across Rust versions
+        return name.iter().fold(0x811c_9dc5u32, |hash, &byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        });
+    }
+
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    name.hash(&mut hasher);
+    std::time::SystemTime::now().hash(&mut hasher);
+    hasher.finish() as u32
+}
+
+#[cfg(feature = "vorbis")]
+struct VorbisStream<'a> {
+    encoder: vorbis_rs::VorbisEncoder<BufWriter<OutputStream<'a>>>,
+    channels: usize,
+    planar: Vec<Vec<f32>>,
+}
+
+#[cfg(feature = "vorbis")]
+impl<'a> VorbisStream<'a> {
+    /// Number of frames handed to the encoder at a time
+    const BLOCK_FRAMES: usize = 4096;
+
+    fn create(
+        filename: &Path,
+        output: Output<'a>,
+        options: &EncoderOptions,
+        tags: &[Tag],
+    ) -> Result<Self> {
+        use std::num::{NonZeroU32, NonZeroU8};
+        use vorbis_rs::{VorbisBitrateManagementStrategy, VorbisEncoderBuilder};
+
+        let sample_rate = NonZeroU32::new(options.sample_rate)
+            .ok_or_else(|| anyhow!("Invalid sample rate for Vorbis"))?;
+        let channel_count = NonZeroU8::new(options.channels as u8)
+            .ok_or_else(|| anyhow!("Invalid channel count for Vorbis"))?;
+
+        let stream = BufWriter::new(output.into_stream());
+        let mut builder = VorbisEncoderBuilder::new(sample_rate, channel_count, stream)?;
+        builder
+            .stream_serial(ogg_serial(filename, options) as i32)
+            .bitrate_management_strategy(VorbisBitrateManagementStrategy::QualityVbr {
+                // libvorbis qualities go from -0.1 to 1.0, our scale from 0 to 10
+                target_quality: options.vorbis_quality.min(10) as f32 / 10.0,
+            });
+        for (key, value) in tags {
+            builder.comment_tag(key, value);
+        }
+
+        let channels = options.channels as usize;
+        Ok(VorbisStream {
+            encoder: builder.build()?,
+            channels,
+            planar: vec![Vec::with_capacity(Self::BLOCK_FRAMES); channels],
+        })
+    }
+
+    fn write(&mut self, samples: &[i16]) -> Result<()> {
+        // The encoder takes planar floating point audio
+        for block in samples.chunks(Self::BLOCK_FRAMES * self.channels) {
+            for (channel, buffer) in self.planar.iter_mut().enumerate() {
+                buffer.clear();
+                buffer.extend(
+                    block
+                        .iter()
+                        .skip(channel)
+                        .step_by(self.channels)
+                        .map(|&s| s as f32 / 32768.0),
+                );
+            }
+            self.encoder.encode_audio_block(&self.planar)?;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        self.encoder.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "opus")]
+struct OpusStream<'a> {
+    encoder: opus::Encoder,
+    /// Converter to the encoder's rate, for renders at rates Opus doesn't take
+    resampler: Option<Box<Resampler>>,
+    packet_writer: ogg::PacketWriter<'static, BufWriter<OutputStream<'a>>>,
+    serial: u32,
+    channels: usize,
+    /// Interleaved samples in one 20ms Opus frame
+    samples_per_frame: usize,
+    granule_mult: u64,
+    granule_pos: u64,
+    /// Samples left over from the previous chunk, short of a whole frame
+    pending: Vec<i16>,
+}
+
+#[cfg(feature = "opus")]
+impl<'a> OpusStream<'a> {
+    fn create(
+        filename: &Path,
+        output: Output<'a>,
+        options: &EncoderOptions,
+        tags: &[Tag],
+    ) -> Result<Self> {
+        use ogg::{PacketWriteEndInfo, PacketWriter};
+        use opus::{Application, Channels, Encoder};
+
+        let channels = match options.channels {
+            1 => Channels::Mono,
+            2 => Channels::Stereo,
+            _ => return Err(anyhow!("Opus only supports 1 or 2 channels")),
+        };
+
+        // Opus takes 8, 12, 16, 24 or 48 kHz: other rates are converted to
+        // one of these, and the rate of the render is recorded in the header
+        let rate = opus_sample_rate(options.sample_rate);
+        let resampler = if rate != options.sample_rate {
+            info!(
+                "Resampling {} from {} to {} Hz for Opus",
+                filename.display(),
+                options.sample_rate,
+                rate
+            );
+            Some(Box::new(Resampler::new(
+                options.sample_rate,
+                rate,
+                options.channels as usize,
+            )?))
+        } else {
+            None
+        };
+
+        let application = match options.opus_application {
+            OpusApplication::Audio => Application::Audio,
+            OpusApplication::Voip => Application::Voip,
+            OpusApplication::LowDelay => Application::LowDelay,
+        };
+        let mut encoder = Encoder::new(rate, channels, application)?;
+        encoder.set_bitrate(opus::Bitrate::Bits(options.opus_bitrate as i32 * 1000))?;
+        encoder.set_vbr(options.opus_vbr != OpusVbr::Off)?;
+        encoder.set_vbr_constraint(options.opus_vbr == OpusVbr::Constrained)?;
+        encoder.set_complexity(options.opus_complexity as i32)?;
+
+        let mut packet_writer = PacketWriter::new(BufWriter::new(output.into_stream()));
+
+        let serial = ogg_serial(filename, options);
+        let pre_skip = 312u64;
+
+        // 1. OpusHead
+        let mut head = Vec::with_capacity(19);
+        head.extend_from_slice(b"OpusHead");
+        head.push(1); // version
+        head.push(options.channels as u8);
+        head.extend_from_slice(&(pre_skip as u16).to_le_bytes()); // pre-skip
+        head.extend_from_slice(&options.sample_rate.to_le_bytes()); // input sample rate
+        head.extend_from_slice(&0i16.to_le_bytes()); // gain
+        head.push(0); // mapping family
+
+        packet_writer.write_packet(head, serial, PacketWriteEndInfo::EndPage, 0)?;
+
+        // 2. OpusTags
+        let mut comment = Vec::new();
+        comment.extend_from_slice(b"OpusTags");
+        comment.extend_from_slice(&vorbis_comment(&vendor_string(options), tags));
+
+        packet_writer.write_packet(comment, serial, PacketWriteEndInfo::EndPage, 0)?;
+
+        // 3. Audio packets follow in write()
+        let frame_size = (rate / 50) as usize; // 20ms
+        let samples_per_frame = frame_size * options.channels as usize;
+        Ok(OpusStream {
+            encoder,
+            resampler,
+            packet_writer,
+            serial,
+            channels: options.channels as usize,
+            samples_per_frame,
+            granule_mult: (48000 / rate) as u64,
+            granule_pos: pre_skip,
+            pending: Vec::with_capacity(samples_per_frame),
+        })
+    }
+
+    fn write(&mut self, samples: &[i16]) -> Result<()> {
+        match &mut self.resampler {
+            Some(resampler) => {
+                let samples: Vec<f32> = samples.iter().map(|&s| s.to_f64() as f32).collect();
+                let resampled = resampler.process(&samples)?;
+                self.encode(&Self::quantize(&resampled))
+            }
+            None => self.encode(samples),
+        }
+    }
+
+    fn quantize(samples: &[f32]) -> Vec<i16> {
+        samples.iter().map(|&s| i16::from_f64(s as f64)).collect()
+    }
+
+    /// Encode audio at the encoder's rate.
+    fn encode(&mut self, mut samples: &[i16]) -> Result<()> {
+        // Complete the frame started by the previous chunk first
+        if !self.pending.is_empty() {
+            let missing = (self.samples_per_frame - self.pending.len()).min(samples.len());
+            self.pending.extend_from_slice(&samples[..missing]);
+            samples = &samples[missing..];
+            if self.pending.len() < self.samples_per_frame {
+                return Ok(());
+            }
+            let frame = std::mem::take(&mut self.pending);
+            self.write_frame(&frame, frame.len())?;
+        }
+
+        let mut frames = samples.chunks_exact(self.samples_per_frame);
+        for frame in &mut frames {
+            self.write_frame(frame, frame.len())?;
+        }
+        self.pending.extend_from_slice(frames.remainder());
+        Ok(())
+    }
+
+    /// Encode one frame, of which the first `length` samples are audio and the rest padding.
+    fn write_frame(&mut self, frame: &[i16], length: usize) -> Result<()> {
+        let packet = self.encoder.encode_vec(frame, 4000)?;
+        self.granule_pos += (length / self.channels) as u64 * self.granule_mult;
+        self.packet_writer.write_packet(
+            packet,
+            self.serial,
+            ogg::PacketWriteEndInfo::EndPage,
+            self.granule_pos,
+        )?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        if let Some(resampler) = self.resampler.take() {
+            let end = resampler.finish()?;
+            self.encode(&Self::quantize(&end))?;
+        }
+        if !self.pending.is_empty() {
+            let length = self.pending.len();
+            let mut padded = std::mem::take(&mut self.pending);
+            padded.resize(self.samples_per_frame, 0);
+            self.write_frame(&padded, length)?;
+        }
+        self.packet_writer.into_inner().flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "flac")]
+struct FlacStream<'a> {
+    sink: FlacSink<'a>,
+    config: flacenc::error::Verified<flacenc::config::Encoder>,
+    /// Block and frame sizes and length of the frames written so far
+    stream_info: flacenc::component::StreamInfo,
+    /// Block being encoded, and the MD5 sum of the audio so far
+    block: (flacenc::source::FrameBuf, flacenc::source::Context),
+    quantizer: Quantizer,
+    /// Interleaved samples of the next block, until it is complete
+    pending: Vec<i32>,
+    /// Bytes of the frame being written
+    bytes: flacenc::bitsink::ByteSink,
+}
+
+#[cfg(feature = "flac")]
+enum FlacSink<'a> {
+    /// A target whose STREAMINFO block is completed once the length is known,
+    /// and the position that block starts at
+    Seekable(BufWriter<Box<dyn SeekableOutput + 'a>>, u64),
+    /// A stream, whose STREAMINFO block leaves the length, frame sizes and
+    /// MD5 sum unknown
+    Stream(BufWriter<OutputStream<'a>>),
+}
+
+#[cfg(feature = "flac")]
+impl<'a> FlacStream<'a> {
+    fn create(output: Output<'a>, options: &EncoderOptions, tags: &[Tag]) -> Result<Self> {
+        use flacenc::component::{BitRepr, MetadataBlockData, Stream, StreamInfo};
+        use flacenc::error::Verify;
+        use flacenc::source::{Context, FrameBuf};
+
+        let config = flacenc::config::Encoder::default()
+            .into_verified()
+            .map_err(|(_, e)| anyhow!("Invalid FLAC encoder configuration: {:?}", e))?;
+        let channels = options.channels as usize;
+        let bit_depth = options.bit_depth as usize;
+        let invalid = |e| anyhow!("Invalid FLAC stream: {:?}", e);
+        let mut stream_info =
+            StreamInfo::new(options.sample_rate as usize, channels, bit_depth).map_err(invalid)?;
+        stream_info
+            .set_block_sizes(config.block_size, config.block_size)
+            .map_err(invalid)?;
+
+        // The header goes first, with the frame sizes not known yet
+        let mut header_info = stream_info.clone();
+        header_info.set_frame_sizes(0, 0).map_err(invalid)?;
+        let mut header = Stream::with_stream_info(header_info);
+        if !tags.is_empty() {
+            // Metadata block type 4 holds a Vorbis comment
+            let comment = vorbis_comment(&vendor_string(options), tags);
+            let block = MetadataBlockData::new_unknown(4, &comment)
+                .map_err(|e| anyhow!("Invalid FLAC tags: {:?}", e))?;
+            header.add_metadata_block(block);
+        }
+        let mut bytes = flacenc::bitsink::ByteSink::new();
+        header
+            .write(&mut bytes)
+            .map_err(|e| anyhow!("Failed to serialize FLAC header: {:?}", e))?;
+
+        let mut sink = match output {
+            Output::Seekable(output) => {
+                let mut output = BufWriter::new(output);
+                // STREAMINFO follows the "fLaC" marker and its block header
+                let start = output.stream_position()? + 8;
+                FlacSink::Seekable(output, start)
+            }
+            Output::Stream(stream) => FlacSink::Stream(BufWriter::new(stream)),
+        };
+        sink.write_all(bytes.as_slice())?;
+
+        let block = (
+            FrameBuf::with_size(channels, config.block_size).map_err(invalid)?,
+            Context::new(bit_depth, channels),
+        );
+        Ok(FlacStream {
+            sink,
+            config,
+            stream_info,
+            block,
+            quantizer: Quantizer::new(options.bit_depth, options.dither),
+            pending: Vec::new(),
+            bytes,
+        })
+    }
+
+    fn write<S: Sample>(&mut self, samples: &[S]) -> Result<()> {
+        let quantizer = &mut self.quantizer;
+        self.pending
+            .extend(samples.iter().map(|&s| s.to_int(quantizer)));
+
+        // Every complete block is encoded and written as it comes
+        let block_len = self.block.0.size() * self.block.0.channels();
+        let mut pending = std::mem::take(&mut self.pending);
+        let complete = pending.len() - pending.len() % block_len;
+        for block in pending[..complete].chunks(block_len) {
+            self.encode(block)?;
+        }
+        pending.drain(..complete);
+        self.pending = pending;
+        Ok(())
+    }
+
+    /// Encode a block of interleaved samples into a frame, and write it.
+    fn encode(&mut self, samples: &[i32]) -> Result<()> {
+        use flacenc::component::BitRepr;
+        use flacenc::source::Fill;
+
+        self.block
+            .fill_interleaved(samples)
+            .map_err(|e| anyhow!("FLAC encoding failed: {:?}", e))?;
+        let (framebuf, context) = &self.block;
+        let frame = flacenc::encode_fixed_size_frame(
+            &self.config,
+            framebuf,
+            context.current_frame_number().unwrap_or(0),
+            &self.stream_info,
+        )
+        .map_err(|e| anyhow!("FLAC encoding failed: {:?}", e))?;
+        self.stream_info.update_frame_info(&frame);
+
+        self.bytes.clear();
+        frame
+            .write(&mut self.bytes)
+            .map_err(|e| anyhow!("Failed to serialize FLAC frame: {:?}", e))?;
+        self.sink.write_all(self.bytes.as_slice())?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        use flacenc::component::BitRepr;
+        use std::io::SeekFrom;
+
+        // The last block may be shorter than the others
+        if !self.pending.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            self.encode(&pending)?;
+        }
+        match self.sink {
+            FlacSink::Seekable(mut output, start) => {
+                // Blocks are all the same size but for the last one, which
+                // the minimum leaves out
+                let block_size = self.config.block_size;
+                self.stream_info
+                    .set_block_sizes(block_size, block_size)
+                    .map_err(|e| anyhow!("Invalid FLAC stream: {:?}", e))?;
+                self.stream_info.set_md5_digest(&self.block.1.md5_digest());
+                self.bytes.clear();
+                self.stream_info
+                    .write(&mut self.bytes)
+                    .map_err(|e| anyhow!("Failed to serialize FLAC header: {:?}", e))?;
+                output.seek(SeekFrom::Start(start))?;
+                output.write_all(self.bytes.as_slice())?;
+                output.seek(SeekFrom::End(0))?;
+                output.flush()?;
+            }
+            FlacSink::Stream(mut stream) => stream.flush()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "flac")]
+impl Write for FlacSink<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            FlacSink::Seekable(output, _) => output.write(buf),
+            FlacSink::Stream(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            FlacSink::Seekable(output, _) => output.flush(),
+            FlacSink::Stream(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(feature = "mp3")]
+struct Mp3Stream<'a> {
+    encoder: mp3lame_encoder::Encoder,
+    file: BufWriter<OutputStream<'a>>,
+    channels: usize,
+    /// Encoded output of the current chunk
+    buffer: Vec<u8>,
+}
+
+#[cfg(feature = "mp3")]
+impl<'a> Mp3Stream<'a> {
+    fn create(output: Output<'a>, options: &EncoderOptions) -> Result<Self> {
+        use mp3lame_encoder::{Bitrate, Builder, Quality, VbrMode};
+
+        let mut builder =
+            Builder::new().ok_or_else(|| anyhow!("Failed to initialize the LAME encoder"))?;
+        let lame_error = |e| anyhow!("Invalid MP3 encoder setting: {:?}", e);
+        builder
+            .set_num_channels(options.channels as u8)
+            .map_err(lame_error)?;
+        builder
+            .set_sample_rate(options.sample_rate)
+            .map_err(lame_error)?;
+
+        match options.mp3_vbr_quality {
+            Some(quality) => {
+                // Same scale as LAME's -V option
+                let quality = match quality {
+                    0 => Quality::Best,
+                    1 => Quality::SecondBest,
+                    2 => Quality::NearBest,
+                    3 => Quality::VeryNice,
+                    4 => Quality::Nice,
+                    5 => Quality::Good,
+                    6 => Quality::Decent,
+                    7 => Quality::Ok,
+                    8 => Quality::SecondWorst,
+                    _ => Quality::Worst,
+                };
+                builder.set_vbr_mode(VbrMode::Mtrh).map_err(lame_error)?;
+                builder.set_vbr_quality(quality).map_err(lame_error)?;
+            }
+            None => {
+                let bitrate = match options.mp3_bitrate {
+                    8 => Bitrate::Kbps8,
+                    16 => Bitrate::Kbps16,
+                    24 => Bitrate::Kbps24,
+                    32 => Bitrate::Kbps32,
+                    40 => Bitrate::Kbps40,
+                    48 => Bitrate::Kbps48,
+                    64 => Bitrate::Kbps64,
+                    80 => Bitrate::Kbps80,
+                    96 => Bitrate::Kbps96,
+                    112 => Bitrate::Kbps112,
+                    128 => Bitrate::Kbps128,
+                    160 => Bitrate::Kbps160,
+                    192 => Bitrate::Kbps192,
+                    224 => Bitrate::Kbps224,
+                    256 => Bitrate::Kbps256,
+                    320 => Bitrate::Kbps320,
+                    other => return Err(anyhow!("Unsupported MP3 bitrate: {} kbps", other)),
+                };
+                builder.set_brate(bitrate).map_err(lame_error)?;
+            }
+        }
+
+        Ok(Mp3Stream {
+            encoder: builder.build().map_err(lame_error)?,
+            file: BufWriter::new(output.into_stream()),
+            channels: options.channels as usize,
+            buffer: Vec::new(),
+        })
+    }
+
+    fn write(&mut self, samples: &[i16]) -> Result<()> {
+        use mp3lame_encoder::{InterleavedPcm, MonoPcm};
+
+        self.buffer.clear();
+        self.buffer
+            .reserve(mp3lame_encoder::max_required_buffer_size(samples.len()));
+        let encoded = if self.channels == 1 {
+            self.encoder
+                .encode_to_vec(MonoPcm(samples), &mut self.buffer)
+        } else {
+            self.encoder
+                .encode_to_vec(InterleavedPcm(samples), &mut self.buffer)
+        };
+        encoded.map_err(|e| anyhow!("MP3 encoding failed: {:?}", e))?;
+        self.file.write_all(&self.buffer)?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.buffer.clear();
+        self.buffer
+            .reserve(mp3lame_encoder::max_required_buffer_size(0));
+        self.encoder
+            .flush_to_vec::<mp3lame_encoder::FlushNoGap>(&mut self.buffer)
+            .map_err(|e| anyhow!("MP3 encoding failed: {:?}", e))?;
+        self.file.write_all(&self.buffer)?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(any(feature = "flac", feature = "vorbis", feature = "mp3"))]
+    fn decode(path: &Path) -> (u32, Vec<i32>) {
+        use symphonia::core::audio::SampleBuffer;
+        use symphonia::core::codecs::DecoderOptions;
+        use symphonia::core::formats::FormatOptions;
+        use symphonia::core::io::MediaSourceStream;
+        use symphonia::core::meta::MetadataOptions;
+        use symphonia::core::probe::Hint;
+
+        let file = std::fs::File::open(path).unwrap();
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut format = symphonia::default::get_probe()
+            .format(
+                &Hint::new(),
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .unwrap()
+            .format;
+        let track = format.default_track().unwrap();
+        let sample_rate = track.codec_params.sample_rate.unwrap();
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .unwrap();
+
+        let mut decoded = Vec::new();
+        while let Ok(packet) = format.next_packet() {
+            let audio = decoder.decode(&packet).unwrap();
+            let mut buffer = SampleBuffer::<i32>::new(audio.capacity() as u64, *audio.spec());
+            buffer.copy_interleaved_ref(audio);
+            decoded.extend_from_slice(buffer.samples());
+        }
+        (sample_rate, decoded)
+    }
+
+    fn test_signal() -> Vec<i16> {
+        (0..44100 * 2)
+            .map(|i| ((i as f32 * 0.01).sin() * 12000.0) as i16)
+            .collect()
+    }
+
+    #[test]
+    fn test_chunked_wav_matches_single_write() {
+        let samples = test_signal();
+        let dir = tempfile::tempdir().unwrap();
+        let options = EncoderOptions::default();
+
+        let whole = dir.path().join("whole.wav");
+        write_audio_file(&samples, &whole, &options).unwrap();
+
+        let chunked = dir.path().join("chunked.wav");
+        let mut writer = AudioWriter::create(&chunked, &options).unwrap();
+        for chunk in samples.chunks(1001) {
+            writer.write(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+
+        assert_eq!(
+            std::fs::read(&whole).unwrap(),
+            std::fs::read(&chunked).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_wav_in_memory() {
+        let samples = test_signal();
+        let dir = tempfile::tempdir().unwrap();
+        let options = EncoderOptions::default();
+
+        let file = dir.path().join("file.wav");
+        write_audio_file(&samples, &file, &options).unwrap();
+        let mut memory = Vec::new();
+        let name = Path::new("memory.wav");
+        write_audio(&samples, name, std::io::Cursor::new(&mut memory), &options).unwrap();
+        assert_eq!(memory, std::fs::read(&file).unwrap());
+    }
+
+    #[test]
+    fn test_streamed_wav() {
+        let samples = test_signal();
+        let dir = tempfile::tempdir().unwrap();
+        let options = EncoderOptions::default();
+
+        let file = dir.path().join("file.wav");
+        write_audio_file(&samples, &file, &options).unwrap();
+        let mut streamed = Vec::new();
+        let mut writer =
+            AudioWriter::create_stream(Path::new("streamed.wav"), &mut streamed, &options, &[])
+                .unwrap();
+        for chunk in samples.chunks(1001) {
+            writer.write(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+
+        // The same audio, after a header of unknown length
+        let file = std::fs::read(&file).unwrap();
+        assert_eq!(&streamed[..4], b"RIFF");
+        assert_eq!(&streamed[4..8], &u32::MAX.to_le_bytes());
+        assert_eq!(&streamed[36..44], b"data\xff\xff\xff\xff");
+        let data = file.windows(4).position(|w| w == b"data").unwrap() + 8;
+        assert_eq!(streamed[44..], file[data..]);
+    }
+
+    #[test]
+    #[cfg(feature = "vorbis")]
+    fn test_vorbis_round_trip() {
+        let samples = test_signal();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.ogg");
+        let options = EncoderOptions {
+            format: AudioFormat::Vorbis,
+            ..EncoderOptions::default()
+        };
+        write_audio_file(&samples, &path, &options).unwrap();
+
+        let (sample_rate, decoded) = decode(&path);
+        assert_eq!(sample_rate, 44100);
+        // The last block may be padded
+        assert!(decoded.len() >= samples.len() && decoded.len() < samples.len() + 8192);
+        // Lossy, so compare the error energy with the signal energy
+        let scale = 1.0 / 65536.0;
+        let signal: f64 = samples.iter().map(|&s| (s as f64).powi(2)).sum();
+        let error: f64 = samples
+            .iter()
+            .zip(&decoded)
+            .map(|(&s, &d)| (s as f64 - d as f64 * scale).powi(2))
+            .sum();
+        assert!(error < signal * 0.01);
+    }
+
+    #[test]
+    #[cfg(feature = "flac")]
+    fn test_flac_round_trip() {
+        let samples = test_signal();
+        let dir = tempfile::tempdir().unwrap();
+
+        for bit_depth in [16, 24] {
+            let path = dir.path().join(format!("test_{}.flac", bit_depth));
+            let options = EncoderOptions {
+                format: AudioFormat::Flac,
+                bit_depth,
+                ..EncoderOptions::default()
+            };
+            write_audio_file(&samples, &path, &options).unwrap();
+
+            let (sample_rate, decoded) = decode(&path);
+            assert_eq!(sample_rate, 44100);
+            // Decoded samples are scaled to the full i32 range
+            let expected: Vec<i32> = samples.iter().map(|&s| (s as i32) << 16).collect();
+            assert_eq!(decoded, expected);
+            assert!(std::fs::metadata(&path).unwrap().len() < samples.len() as u64 * 2);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "flac")]
+    fn test_streamed_flac() {
+        let samples = test_signal();
+        let dir = tempfile::tempdir().unwrap();
+        let options = EncoderOptions {
+            format: AudioFormat::Flac,
+            ..EncoderOptions::default()
+        };
+        let file = dir.path().join("file.flac");
+        write_audio_file(&samples, &file, &options).unwrap();
+
+        let chunked = dir.path().join("chunked.flac");
+        let mut writer = AudioWriter::create(&chunked, &options).unwrap();
+        for chunk in samples.chunks(1001) {
+            writer.write(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+        let file = std::fs::read(&file).unwrap();
+        assert_eq!(file, std::fs::read(&chunked).unwrap());
+
+        let mut streamed = Vec::new();
+        let mut writer =
+            AudioWriter::create_stream(Path::new("streamed.flac"), &mut streamed, &options, &[])
+                .unwrap();
+        for chunk in samples.chunks(1001) {
+            writer.write(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+        // The same frames, after a STREAMINFO block without the length, the
+        // frame sizes or the MD5 sum
+        assert_eq!(streamed[..8], file[..8]);
+        assert_eq!(&streamed[12..18], &[0; 6]);
+        assert_eq!(&streamed[26..42], &[0; 16]);
+        assert_eq!(streamed[42..], file[42..]);
+        let path = dir.path().join("streamed.flac");
+        std::fs::write(&path, &streamed).unwrap();
+        let (_, decoded) = decode(&path);
+        assert_eq!(decoded.len(), samples.len());
+    }
+
+    #[test]
+    #[cfg(feature = "flac")]
+    fn test_flac_tags() {
+        let samples = test_signal();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tagged.flac");
+        let options = EncoderOptions {
+            format: AudioFormat::Flac,
+            ..EncoderOptions::default()
+        };
+        let tags = [("REPLAYGAIN_TRACK_GAIN".to_string(), "-5.50 dB".to_string())];
+        let mut writer = AudioWriter::create_tagged(&path, &options, &tags).unwrap();
+        writer.write(&samples).unwrap();
+        writer.finish().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let tag = b"REPLAYGAIN_TRACK_GAIN=-5.50 dB";
+        assert!(bytes.windows(tag.len()).any(|window| window == tag));
+        // The comment block doesn't get in the way of the audio
+        let (_, decoded) = decode(&path);
+        assert_eq!(decoded.len(), samples.len());
+    }
+
+    /// A quiet sine, with detail below the 16-bit step.
+    fn float_signal() -> Vec<f32> {
+        (0..44100 * 2)
+            .map(|i| (i as f32 * 0.01).sin() * 0.001)
+            .collect()
+    }
+
+    /// Bits per sample in the STREAMINFO block of a FLAC file.
+    #[cfg(feature = "flac")]
+    fn flac_bits_per_sample(path: &Path) -> u32 {
+        let bytes = std::fs::read(path).unwrap();
+        assert_eq!(&bytes[..4], b"fLaC");
+        // After the block header come block and frame sizes (10 bytes), then
+        // 20 bits of sample rate, 3 of channels and 5 of bits per sample minus one
+        let info = &bytes[8..];
+        (((info[12] & 1) << 4 | info[13] >> 4) + 1) as u32
+    }
+
+    #[test]
+    #[cfg(feature = "flac")]
+    fn test_flac_from_float() {
+        let samples = float_signal();
+        let dir = tempfile::tempdir().unwrap();
+
+        for (bit_depth, dither) in [(16, false), (16, true), (24, false), (24, true)] {
+            let path = dir
+                .path()
+                .join(format!("test_{}_{}.flac", bit_depth, dither));
+            let options = EncoderOptions {
+                format: AudioFormat::Flac,
+                bit_depth,
+                dither,
+                ..EncoderOptions::default()
+            };
+            let mut writer = AudioWriter::create(&path, &options).unwrap();
+            writer.write_samples(&samples).unwrap();
+            writer.finish().unwrap();
+
+            assert_eq!(flac_bits_per_sample(&path), bit_depth);
+            let (_, decoded) = decode(&path);
+            let mut quantizer = Quantizer::new(bit_depth, dither);
+            let expected: Vec<i32> = samples
+                .iter()
+                .map(|&s| s.to_int(&mut quantizer) << (32 - bit_depth))
+                .collect();
+            assert_eq!(decoded, expected);
+        }
+    }
+
+    #[test]
+    fn test_wav_24_bit_from_float() {
+        let samples = float_signal();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.wav");
+        let options = EncoderOptions {
+            bit_depth: 24,
+            ..EncoderOptions::default()
+        };
+        let mut writer = AudioWriter::create(&path, &options).unwrap();
+        writer.write_samples(&samples).unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().bits_per_sample, 24);
+        let written: Vec<i32> = reader.samples::<i32>().map(Result::unwrap).collect();
+        // The bits below 16-bit precision are kept, not zero as for 16-bit audio
+        assert!(written.iter().any(|&s| s & 0xff != 0));
+        for (&sample, &float) in written.iter().zip(&samples) {
+            assert_eq!(sample, (float as f64 * 8388608.0).round() as i32);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "mp3")]
+    fn test_mp3_round_trip() {
+        let samples = test_signal();
+        let dir = tempfile::tempdir().unwrap();
+
+        for (mp3_bitrate, mp3_vbr_quality) in [(192, None), (192, Some(2))] {
+            let path = dir.path().join("test.mp3");
+            let options = EncoderOptions {
+                format: AudioFormat::Mp3,
+                mp3_bitrate,
+                mp3_vbr_quality,
+                ..EncoderOptions::default()
+            };
+            write_audio_file(&samples, &path, &options).unwrap();
+
+            let (sample_rate, decoded) = decode(&path);
+            assert_eq!(sample_rate, 44100);
+            // The encoder delay and the last frame's padding make it longer
+            assert!(decoded.len() >= samples.len() && decoded.len() < samples.len() + 8192);
+            assert!(std::fs::metadata(&path).unwrap().len() < samples.len() as u64 / 2);
+        }
+    }
+
+    #[test]
+    fn test_mp3_options_validation() {
+        let mut options = EncoderOptions {
+            mp3_bitrate: 100,
+            ..EncoderOptions::default()
+        };
+        assert!(options.validate().is_err());
+        options.mp3_bitrate = 320;
+        options.mp3_vbr_quality = Some(10);
+        assert!(options.validate().is_err());
+        options.mp3_vbr_quality = Some(0);
+        assert!(options.validate().is_ok());
+    }
+
+    #[test]
+    fn test_opus_options() {
+        assert_eq!(
+            "constrained".parse::<OpusVbr>().unwrap(),
+            OpusVbr::Constrained
+        );
+        assert!("maybe".parse::<OpusVbr>().is_err());
+        for application in [
+            OpusApplication::Audio,
+            OpusApplication::Voip,
+            OpusApplication::LowDelay,
+        ] {
+            assert_eq!(
+                application.name().parse::<OpusApplication>().unwrap(),
+                application
+            );
+        }
+
+        let mut options = EncoderOptions {
+            opus_complexity: 11,
+            ..EncoderOptions::default()
+        };
+        assert!(options.validate().is_err());
+        options.opus_complexity = 0;
+        assert!(options.validate().is_ok());
+
+        assert_eq!(opus_sample_rate(44100), 48000);
+        assert_eq!(opus_sample_rate(22050), 24000);
+        assert_eq!(opus_sample_rate(16000), 16000);
+        assert_eq!(opus_sample_rate(96000), 48000);
+    }
+}
@@ -0,0 +1,113 @@
+//! Files kept in memory rather than on disk, for builds without a
+//! filesystem to write to, such as WebAssembly in a browser.
+//!
+//! With the `memfs` feature, the audio writers use [`File`], [`read`] and
+//! [`write`] in place of those of `std::fs`: stems are written to the paths
+//! they always are, and the frontend takes them out of memory with [`take`].
+
+use std::collections::BTreeMap;
+use std::io::{self, Cursor, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+static FILES: Mutex<BTreeMap<PathBuf, Vec<u8>>> = Mutex::new(BTreeMap::new());
+
+fn files() -> MutexGuard<'static, BTreeMap<PathBuf, Vec<u8>>> {
+    // A writer panicking leaves no file half-stored, so the map is still whole
+    FILES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} is not in memory", path.display()),
+    )
+}
+
+/// A file being written in memory, stored under its path once dropped.
+#[derive(Debug)]
+pub struct File {
+    path: PathBuf,
+    data: Cursor<Vec<u8>>,
+}
+
+impl File {
+    /// Start an empty file at `path`, replacing the one there once written.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<File> {
+        Ok(File {
+            path: path.as_ref().to_path_buf(),
+            data: Cursor::new(Vec::new()),
+        })
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.data.seek(pos)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let data = std::mem::take(self.data.get_mut());
+        files().insert(std::mem::take(&mut self.path), data);
+    }
+}
+
+/// Contents of the file at `path`.
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    let path = path.as_ref();
+    files().get(path).cloned().ok_or_else(|| not_found(path))
+}
+
+/// Make `contents` the file at `path`.
+pub fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    files().insert(path.as_ref().to_path_buf(), contents.as_ref().to_vec());
+    Ok(())
+}
+
+/// Take the file at `path` out of memory, once the frontend has the stem it
+/// holds.
+pub fn take(path: impl AsRef<Path>) -> Option<Vec<u8>> {
+    files().remove(path.as_ref())
+}
+
+/// Paths of the files in memory, in order.
+pub fn paths() -> Vec<PathBuf> {
+    files().keys().cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{write_audio_file, EncoderOptions};
+
+    #[test]
+    fn test_write_to_memory() {
+        let samples: Vec<i16> = (0..44100 * 2).map(|i| (i % 256) as i16).collect();
+        let path = Path::new("memfs-test/song.wav");
+        write_audio_file(&samples, path, &EncoderOptions::default()).unwrap();
+
+        // Nothing was written to disk
+        assert!(!path.exists());
+        assert!(paths().iter().any(|p| p == path));
+        let wav = take(path).unwrap();
+        assert_eq!(&wav[..4], b"RIFF");
+        // The header was completed once the length was known
+        assert_eq!(
+            u32::from_le_bytes(wav[4..8].try_into().unwrap()) as usize,
+            wav.len() - 8
+        );
+        assert!(read(path).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+//! Sample-rate conversion of rendered audio, for encoders that only take some
+//! rates, such as Opus.
+//!
+//! libopenmpt renders at any rate, so the file's rate is normally the one it
+//! was rendered at. This is for the encoders that can't store that rate: the
+//! audio is rendered at the rate asked for, and converted on its way into the
+//! encoder with a windowed sinc filter.
+
+use anyhow::{anyhow, Result};
+use rubato::{
+    calculate_cutoff, Resampler as _, SincFixedIn, SincInterpolationParameters,
+    SincInterpolationType, WindowFunction,
+};
+
+/// Frames converted at a time.
+const CHUNK_FRAMES: usize = 1024;
+
+/// Length of the sinc filter, in frames of input.
+const SINC_LEN: usize = 256;
+
+/// Streaming converter of interleaved audio from one sample rate to another.
+///
+/// The output starts at the same instant as the input, and once
+/// [`Resampler::finish`] is called it has exactly as many frames as the input
+/// lasts at the new rate.
+pub struct Resampler {
+    inner: SincFixedIn<f32>,
+    channels: usize,
+    ratio: f64,
+    /// Input frames short of a whole chunk, one buffer per channel
+    input: Vec<Vec<f32>>,
+    output: Vec<Vec<f32>>,
+    frames_in: u64,
+    frames_out: u64,
+}
+
+impl Resampler {
+    /// A converter of `channels` channel audio from `from_rate` to `to_rate`.
+    pub fn new(from_rate: u32, to_rate: u32, channels: usize) -> Result<Self> {
+        let window = WindowFunction::BlackmanHarris2;
+        let parameters = SincInterpolationParameters {
+            sinc_len: SINC_LEN,
+            f_cutoff: calculate_cutoff(SINC_LEN, window),
+            oversampling_factor: 256,
+            interpolation: SincInterpolationType::Cubic,
+            window,
+        };
+        let ratio = to_rate as f64 / from_rate as f64;
+        let inner =
+            SincFixedIn::new(ratio, 1.0, parameters, CHUNK_FRAMES, channels).map_err(|e| {
+                anyhow!(
+                    "Cannot resample from {} to {} Hz: {}",
+                    from_rate,
+                    to_rate,
+                    e
+                )
+            })?;
+
+        Ok(Resampler {
+            channels,
+            ratio,
+            input: vec![Vec::with_capacity(CHUNK_FRAMES); channels],
+            output: inner.output_buffer_allocate(true),
+            frames_in: 0,
+            frames_out: 0,
+            inner,
+        })
+    }
+
+    /// Convert the next chunk of interleaved audio, which may be of any
+    /// length. Returns the audio converted so far, which lags behind the
+    /// input until [`Resampler::finish`].
+    pub fn process(&mut self, samples: &[f32]) -> Result<Vec<f32>> {
+        let mut converted = Vec::new();
+        for frame in samples.chunks_exact(self.channels) {
+            for (input, &sample) in self.input.iter_mut().zip(frame) {
+                input.push(sample);
+            }
+            if self.input[0].len() == CHUNK_FRAMES {
+                self.convert_chunk(false, &mut converted)?;
+            }
+        }
+        self.frames_in += (samples.len() / self.channels) as u64;
+        Ok(converted)
+    }
+
+    /// Convert the rest of the input, and return the end of the audio.
+    pub fn finish(mut self) -> Result<Vec<f32>> {
+        let mut converted = Vec::new();
+        let frames = (self.frames_in as f64 * self.ratio).round() as u64;
+        // The filter gives out the end of the input as silence follows it
+        while self.frames_out < frames {
+            self.convert_chunk(true, &mut converted)?;
+        }
+        let extra = (self.frames_out - frames) as usize * self.channels;
+        converted.truncate(converted.len().saturating_sub(extra));
+        Ok(converted)
+    }
+
+    /// Convert the buffered input, padded with silence if `partial`, and
+    /// append the result to `converted`.
+    fn convert_chunk(&mut self, partial: bool, converted: &mut Vec<f32>) -> Result<()> {
+        let result = if !partial {
+            self.inner
+                .process_into_buffer(&self.input, &mut self.output, None)
+        } else if self.input[0].is_empty() {
+            self.inner
+                .process_partial_into_buffer(None::<&[Vec<f32>]>, &mut self.output, None)
+        } else {
+            self.inner
+                .process_partial_into_buffer(Some(&self.input), &mut self.output, None)
+        };
+        let (_, frames) = result.map_err(|e| anyhow!("Cannot resample: {}", e))?;
+        for input in &mut self.input {
+            input.clear();
+        }
+
+        for i in 0..frames {
+            converted.extend(self.output.iter().map(|channel| channel[i]));
+        }
+        self.frames_out += frames as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resample(samples: &[f32], from_rate: u32, to_rate: u32, channels: usize) -> Vec<f32> {
+        let mut resampler = Resampler::new(from_rate, to_rate, channels).unwrap();
+        let mut converted = Vec::new();
+        // In uneven chunks, as they come out of a render
+        for chunk in samples.chunks(1000 * channels + channels) {
+            converted.extend(resampler.process(chunk).unwrap());
+        }
+        converted.extend(resampler.finish().unwrap());
+        converted
+    }
+
+    #[test]
+    fn test_resampler() {
+        let tone = |rate: u32, frames: usize| -> Vec<f32> {
+            (0..frames)
+                .flat_map(|i| {
+                    let s = (2.0 * std::f64::consts::PI * 1000.0 * i as f64 / rate as f64).sin()
+                        as f32
+                        * 0.5;
+                    [s, -s]
+                })
+                .collect()
+        };
+
+        let converted = resample(&tone(44100, 44100), 44100, 48000, 2);
+        assert_eq!(converted.len(), 48000 * 2);
+        // The same tone at the new rate, starting at the same instant
+        let expected = tone(48000, 48000);
+        let error = converted[2000..94000]
+            .iter()
+            .zip(&expected[2000..94000])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        assert!(error < 0.01, "{}", error);
+
+        let converted = resample(&tone(22050, 1234), 22050, 8000, 2);
+        assert_eq!(converted.len(), 448 * 2);
+        assert!(resample(&[], 44100, 48000, 1).is_empty());
+    }
+}
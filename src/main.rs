@@ -1,257 +1,3946 @@
 use anyhow::{anyhow, Result};
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use indicatif::{ProgressBar, ProgressStyle};
 use log::info;
 use openmpt::ext::ModuleExt;
-use openmpt::module::Logger;
+use openmpt::index::{InstrumentIndex, SampleIndex, SubsongIndex};
+use openmpt::module::{LogCapture, Logger, Module};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::io::{Read, Write};
-use std::path::Path;
-use untracker::{render_stem, AudioFormat, ExportOptions, ResampleMethod};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+use untracker_core::archive::{self, ModuleRecord};
+use untracker_core::automation::MuteAutomation;
+use untracker_core::cache::{parse_size, RenderCache};
+use untracker_core::ci::{CiReport, CiResult};
+use untracker_core::colors::{self, StemColors};
+use untracker_core::container::{output_base_name, ModuleContainer};
+use untracker_core::daw_session::{DawSession, SessionFormat, SessionTrack};
+use untracker_core::estimate::{format_bytes, format_duration, render_memory, RenderEstimate};
+use untracker_core::groups::GroupPreset;
+use untracker_core::interactive::{edit_plan, PlanEntry};
+use untracker_core::jobs::{self, Job, JobResult, JobResults, JOB_RESULTS_FILE};
+use untracker_core::listing::ModuleListing;
+use untracker_core::loudness::{
+    album_gain, AlbumReport, AlbumTrack, ALBUM_REPORT_FILE, ALBUM_TRUE_PEAK_CEILING_DBTP,
+};
+use untracker_core::manifest::{ManifestStem, StemManifest, STEM_MANIFEST_FILE};
+use untracker_core::markers::{track_markers, MarkerFormat};
+use untracker_core::mix_automation::MixAutomation;
+use untracker_core::null_test::null_test;
+use untracker_core::parallel::{self, MemoryBudget};
+use untracker_core::probe::ModuleProbe;
+use untracker_core::progress::ProgressSink;
+use untracker_core::rank::StemWorthiness;
+use untracker_core::remix_kit::{self, Key, KitStem, RemixKit, REMIX_KIT_FILE};
+use untracker_core::render_info::{InputFile, RenderInfo, RenderTimings, RENDER_INFO_FILE};
+use untracker_core::session::{load_module, CtlSetting};
+use untracker_core::single_pass::render_single_pass;
+use untracker_core::staging::{ExistingOutputs, StagingDir};
+use untracker_core::unpack::{self, module_stem, ArchiveFormat};
+use untracker_core::watch::{
+    replace_changed, DropFolder, ManifestEntry, ModuleWatcher, MANIFEST_FILE,
+};
+use untracker_core::{
+    can_isolate, group_file_name, measure_full_mix, mix_file_name, module_metadata,
+    render_full_mix, render_group, render_stem, select_subsong, stem_file_name, AnalysisReport,
+    AudioWriter, ExportOptions, GroupSpec, LoudnessMeter, ModuleDefaults, ModuleOverrides,
+    NameTemplate, NormalizeMode, OpusApplication, OpusVbr, RenderSession, ResampleMethod,
+    SampleFormat, SongRange, StemAnalysis, StemAnalysisEntry, StemGroup, StemLabels,
+    StemRenderResult, StemSelection, Tag, TrimSilence, STDOUT_PATH,
+};
+
+/// Set by --ci and for job files, which leaves out progress bars and the
+/// progress of each module.
+static CI: AtomicBool = AtomicBool::new(false);
+
+/// Print a line of progress, unless running with --ci.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !CI.load(Ordering::Relaxed) {
+            println!($($arg)*);
+        }
+    };
+}
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, long_version = long_version(), about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 /// Untracker: Stem extractor for tracker modules (MOD, S3M, XM, IT, etc.)
+///
+/// Without a command, extracts stems as `untracker extract` does.
 struct Args {
-    /// Input module file path
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    run: ExtractCommandArgs,
+}
+
+/// Stem extraction, for `untracker extract` and `untracker batch`, and for
+/// `untracker` without a command.
+#[derive(clap::Args)]
+struct ExtractCommandArgs {
+    /// Input module files, directories or glob patterns. With more than one
+    /// module, each one gets its own subdirectory of the output directory
+    #[arg(short, long, required_unless_present = "job_file", num_args = 1..)]
+    input: Vec<String>,
+
+    /// Run the jobs of a JSON file instead of extracting --input, for
+    /// `untracker batch`: each job names a module, the directory of its
+    /// stems, its own options and the stems to render. Jobs run on the
+    /// --jobs workers, and how each went is written to job-results.json in
+    /// the output directory
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["input", "recursive", "dry_run", "interactive", "ci"]
+    )]
+    job_file: Option<PathBuf>,
+
+    /// Also look for modules in the subdirectories of input directories
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Output directory for stem files, or - to stream a single stem (or
+    /// the full mix) to stdout
+    #[arg(
+        short,
+        long,
+        visible_alias = "output",
+        required_unless_present = "dry_run"
+    )]
+    output_dir: Option<String>,
+
+    /// Extract a single module into its own subdirectory too, as for
+    /// several, for `untracker batch`
+    #[arg(skip)]
+    batch: bool,
+
+    /// Run as a CI job: no progress bars or questions, one line per module in
+    /// a stable order, and a failing exit status if any module fails
+    #[arg(long, conflicts_with = "interactive")]
+    ci: bool,
+
+    /// Write the results of --ci to FILE: JUnit XML if it ends in .xml, JSON otherwise
+    #[arg(long, value_name = "FILE", requires = "ci")]
+    ci_report: Option<PathBuf>,
+
+    #[command(flatten)]
+    audio: AudioArgs,
+
+    #[command(flatten)]
+    extract: ExtractArgs,
+}
+
+/// Options for stem extraction, shared by extracting a single module and watching a folder.
+#[derive(Clone, clap::Args)]
+struct ExtractArgs {
+    /// What to extract: render stems of the song, or write the samples stored
+    /// in the module as they are
+    #[arg(long, value_enum, default_value = "render")]
+    mode: ExtractMode,
+
+    /// Only render these stems, numbered from 1 (e.g. 1,3,7-12)
+    #[arg(long)]
+    stems: Option<StemSelection>,
+
+    /// Render groups of stems together instead of one file per stem:
+    /// preset:standard (guessed from instrument names), a list such as "drums=1-4;bass=5,9",
+    /// or a TOML file of groups
+    #[arg(long, conflicts_with_all = ["stems", "analyze"])]
+    groups: Option<GroupSpec>,
+
+    /// Render guessed groups, and modules with more than --max-stems stems,
+    /// without asking for confirmation
+    #[arg(short, long)]
+    yes: bool,
+
+    /// Ask before rendering more stems than this from one module, and without
+    /// a terminal to ask on, refuse unless --yes is given
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u32).range(1..))]
+    max_stems: u32,
+
+    /// Set when nobody is around to answer questions
+    #[arg(skip)]
+    unattended: bool,
+
+    /// Review the planned stems in a table and pick which ones to render before starting
+    #[arg(long)]
+    interactive: bool,
+
+    /// Don't keep stems whose peak stays at or below this level in dBFS, such as
+    /// instruments the song never plays [default when given without a level: -80]
+    #[arg(
+        long,
+        value_name = "DBFS",
+        num_args = 0..=1,
+        default_missing_value = "-80",
+        allow_negative_numbers = true
+    )]
+    skip_silent: Option<f64>,
+
+    /// Render a sample pack: WAV stems trimmed of silence, matched in RMS level
+    /// and tagged with tempo and loop points, plus a `README.json` with the
+    /// tempo and key of the song. Silent stems are skipped unless --skip-silent says otherwise
+    #[arg(long, conflicts_with = "normalize")]
+    remix_kit: bool,
+
+    /// Extract every sub-song of the module into its own subdirectory (subsong_01, ...)
+    #[arg(long, conflicts_with = "subsong")]
+    all_subsongs: bool,
+
+    /// Also render the full mix of the module, as `<module name>.<ext>`
+    #[arg(long)]
+    full_mix: bool,
+
+    /// Check that all stems have the same number of samples, and pad the
+    /// shorter ones with silence so they line up
+    #[arg(long, conflicts_with_all = ["trim_silence", "remix_kit"])]
+    verify_alignment: bool,
+
+    /// Render the stems again, subtract their sum from the full mix and report
+    /// the level of what is left (a null test)
+    #[arg(long)]
+    verify_sum: bool,
+
+    /// Decode every stem written, with a decoder independent of the encoders,
+    /// and check its length, sample rate and channels
+    #[cfg(feature = "decode")]
+    #[arg(long)]
+    verify_output: bool,
+
+    /// Experimental: render all stems in one pass over the song, playing a
+    /// module instance per stem side by side, so they all end on the same
+    /// frame. With --parallel, the stems are shared among the workers. Stems
+    /// are streamed to their files, without the render cache
+    #[arg(
+        long,
+        conflicts_with_all = ["groups", "remix_kit", "retry_safe", "normalize", "replaygain", "loop_crossfade"]
+    )]
+    single_pass: bool,
+
+    /// Render stems that fail again as 16-bit WAV at 44100 Hz, and note it
+    /// in the manifest, rather than leaving them out
+    #[arg(long, conflicts_with = "verify_alignment")]
+    retry_safe: bool,
+
+    /// Write a marker file with a region per order position and the tempo
+    /// changes: reaper (CSV), audacity (labels) or cue (a cue sheet of the full mix)
+    #[arg(long)]
+    markers: Option<MarkerFormat>,
+
+    /// Write a DAW session opening the stems as aligned tracks, with the
+    /// tempo changes of the song: reaper (.RPP) or ardour
+    #[arg(long, value_name = "FORMAT")]
+    export_session: Option<SessionFormat>,
+
+    /// Add a tag to FLAC, Vorbis and Opus files (e.g. --tag GENRE=Chiptune),
+    /// replacing the one of the same name taken from the module. Can be repeated
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = untracker_core::tags::parse_tag)]
+    tags: Vec<Tag>,
+
+    /// Record where the module comes from and how it may be shared (e.g.
+    /// --meta license=CC-BY --meta source-url=https://...), in the manifest
+    /// and as a tag of FLAC, Vorbis and Opus files. Can be repeated
+    #[arg(long = "meta", value_name = "KEY=VALUE", value_parser = untracker_core::tags::parse_tag)]
+    meta: Vec<Tag>,
+
+    /// Words naming instrument and sample stems in file names, such as
+    /// "instr=ins,sample=smp", or "none" to leave them out
+    #[arg(long, value_name = "KIND=LABEL,...")]
+    labels: Option<StemLabels>,
+
+    /// Layout of the file names of stems, from the fields {module}, {type},
+    /// {index} (or {index:03} to pad it) and {name}, such as
+    /// "{module}_{type}_{index:03}_{name}"
+    #[arg(long, value_name = "TEMPLATE")]
+    name_template: Option<NameTemplate>,
+
+    /// Colors of the stem groups recorded in the manifest, such as
+    /// "drums=#ff0000,bass=#0000ff", replacing the built-in ones
+    #[arg(long, value_name = "GROUP=#RRGGBB,...")]
+    colors: Option<StemColors>,
+
+    #[command(flatten)]
+    parallel: ParallelArgs,
+
+    /// Write a `manifest.json` next to the stems describing the module, the
+    /// render settings and every output file
+    #[arg(long)]
+    write_metadata: bool,
+
+    /// Write a `render-info.json` next to the stems recording how they were
+    /// made: every option as resolved, a hash of the module and the timings
+    #[arg(long)]
+    render_info: bool,
+
+    /// List the contents of each module and the files extracting it would
+    /// write, without rendering: as text, or as JSON with one object per line
+    #[arg(
+        long,
+        visible_alias = "list",
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "text"
+    )]
+    dry_run: Option<ListFormat>,
+
+    /// Write an `analysis.json` with per-stem silence and storage estimates
+    #[arg(long)]
+    analyze: bool,
+
+    /// Ignore the `<module>.untracker.toml` override file next to the input
+    #[arg(long)]
+    no_overrides: bool,
+
+    /// Directory to stage stems in until all of them are rendered (default: next to the output directory)
+    #[arg(long)]
+    temp_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    cache: CacheArgs,
+
+    #[command(flatten)]
+    existing: ExistingArgs,
+}
+
+/// Parallel rendering options.
+#[derive(Clone, clap::Args)]
+struct ParallelArgs {
+    /// Render in parallel, with one worker per CPU core unless --jobs is given
+    #[arg(short, long)]
+    parallel: bool,
+
+    /// Number of worker threads (implies --parallel)
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+    jobs: Option<u32>,
+
+    /// Stop a batch at the first module that fails, instead of carrying on
+    /// with the others and failing once they are done
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Memory that parallel renders may take together (e.g. 2G), estimated
+    /// from their length; renders wait while it is used up
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    memory_budget: Option<u64>,
+}
+
+impl ParallelArgs {
+    /// The budget of --memory-budget, which only matters with several workers.
+    fn memory_budget(&self) -> Option<MemoryBudget> {
+        self.memory_budget
+            .filter(|_| self.jobs() > 1)
+            .map(MemoryBudget::new)
+    }
+
+    fn jobs(&self) -> usize {
+        match self.jobs {
+            Some(jobs) => jobs as usize,
+            None if self.parallel => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            None => 1,
+        }
+    }
+}
+
+/// Render cache options.
+#[derive(Clone, clap::Args)]
+struct CacheArgs {
+    /// Reuse renders of the same module with the same options from this directory
+    #[arg(long, env = "UNTRACKER_CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Size limit of the render cache (e.g. 500M, 10G); least recently used renders are evicted
+    #[arg(long, default_value = "1G", value_parser = parse_size)]
+    cache_size: u64,
+
+    /// Render everything again, without reading or filling the render cache
+    /// set by --cache-dir or UNTRACKER_CACHE_DIR
+    #[arg(long)]
+    no_cache: bool,
+}
+
+impl CacheArgs {
+    fn open(&self) -> Result<Option<RenderCache>> {
+        self.cache_dir
+            .as_deref()
+            .filter(|_| !self.no_cache)
+            .map(|dir| RenderCache::new(dir, self.cache_size))
+            .transpose()
+    }
+}
+
+/// What to do with output files that already exist.
+#[derive(Clone, clap::Args)]
+struct ExistingArgs {
+    /// Replace output files that already exist, instead of failing
+    #[arg(long, conflicts_with_all = ["skip_existing", "resume"])]
+    overwrite: bool,
+
+    /// Keep output files that already exist and don't render them again, instead of failing
+    #[arg(long)]
+    skip_existing: bool,
+
+    /// Carry on with an interrupted extraction: skip the modules the batch
+    /// manifest lists as done, reuse the stems an interrupted module had
+    /// rendered, and keep output files that already exist
+    #[arg(long)]
+    resume: bool,
+}
+
+impl ExistingArgs {
+    fn policy(&self) -> ExistingOutputs {
+        if self.overwrite {
+            ExistingOutputs::Overwrite
+        } else if self.skip_existing || self.resume {
+            ExistingOutputs::Skip
+        } else {
+            ExistingOutputs::Fail
+        }
+    }
+}
+
+impl ExtractArgs {
+    /// Add the --meta entries to the tags of encoded files, unless --tag
+    /// already sets a tag of the same name.
+    fn add_meta_tags(&mut self) {
+        for (key, value) in &self.meta {
+            if !self.tags.iter().any(|(tag, _)| tag == key) {
+                self.tags.push((key.clone(), value.clone()));
+            }
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Extract the stems of one or more modules, as `untracker` does without
+    /// a command
+    Extract(Box<ExtractCommandArgs>),
+    /// Extract the stems of a collection of modules, each into its own
+    /// subdirectory of the output directory and recorded in its batch
+    /// manifest, even when there is only one, or run the jobs of a job file
+    Batch(Box<ExtractCommandArgs>),
+    /// List the contents of modules and the files extracting them would
+    /// write, without rendering anything
+    List(Box<ListArgs>),
+    /// Render the full mix of one or more modules, without extracting stems
+    Mix(Box<MixArgs>),
+    /// Watch a drop folder and extract the stems of every module put into it,
+    /// or a module and extract its stems again every time it is saved
+    Watch(Box<WatchArgs>),
+    /// Combine and query the manifests of batch extractions
+    #[command(subcommand)]
+    Manifest(ManifestCommand),
+    /// Rank modules by how well they will split into stems, from their
+    /// pattern data, to pick which to extract first
+    Rank(RankArgs),
+    /// Check whether a file is a module untracker can extract, and report
+    /// its format, contents and length
+    Probe(ProbeArgs),
+    /// Join stem sets extracted from several modules, such as the parts of a
+    /// megamix, into one continuous stem set
+    Concat(ConcatArgs),
+    /// Compare an audio file to a reference, such as a render to a recording
+    /// of the same song, and report the level of their difference
+    #[cfg(feature = "decode")]
+    Diff(DiffArgs),
+    /// Play a module through the sound card, muting channels as it plays,
+    /// to hear which instrument is which before extracting
+    #[cfg(feature = "playback")]
+    Play(PlayArgs),
+    /// Browse the stems of a module in the terminal, with when they play and
+    /// how busy they are, and render the ones picked
+    #[cfg(feature = "tui")]
+    Tui(Box<TuiArgs>),
+    /// Serve a REST API to upload modules to, list their stems and download
+    /// them rendered
+    #[cfg(feature = "serve")]
+    Serve(Box<ServeArgs>),
+}
+
+#[derive(Subcommand)]
+enum ManifestCommand {
+    /// Combine batch manifests into one, with the stems of every module
+    Merge {
+        /// Batch manifests (`manifest.jsonl`) or the output directories holding them
+        #[arg(required = true, num_args = 1..)]
+        manifests: Vec<PathBuf>,
+
+        /// Merged manifest to write
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Answer a question about the modules in batch or merged manifests
+    Query {
+        /// What to list
+        query: ManifestQuery,
+
+        /// Batch or merged manifests, or the output directories holding them
+        #[arg(required = true, num_args = 1..)]
+        manifests: Vec<PathBuf>,
+
+        /// Print the answer as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum ManifestQuery {
+    /// Stems that reach full scale, from the modules' `manifest.json`
+    Clipping,
+    /// Number and total size of output files by format
+    SizeByFormat,
+    /// Modules whose latest extraction failed, with the error
+    Failed,
+}
+
+#[derive(clap::Args)]
+struct WatchArgs {
+    /// Module to extract again every time it is saved, replacing only the
+    /// stems that changed. Its stems are always rendered as with
+    /// --reproducible, so that those the save didn't touch stay the same
+    #[arg(conflicts_with_all = ["input", "done_dir", "failed_dir", "interval"])]
+    module: Option<PathBuf>,
+
+    /// Drop folder to watch for new modules
+    #[arg(short, long, required_unless_present = "module")]
+    input: Option<PathBuf>,
+
+    /// Output directory: the stems of the watched module, or one
+    /// subdirectory per module of the drop folder
+    #[arg(short, long)]
+    output_dir: PathBuf,
+
+    /// Where to move modules once their stems are extracted [default: <INPUT>/done]
+    #[arg(long)]
+    done_dir: Option<PathBuf>,
+
+    /// Where to move modules that could not be extracted [default: <INPUT>/failed]
+    #[arg(long)]
+    failed_dir: Option<PathBuf>,
+
+    /// How often to scan the drop folder (e.g. 5s, 1m) [default: 5s]
+    #[arg(long, value_parser = parse_positive_seconds)]
+    interval: Option<f64>,
+
+    /// Process the modules already in the drop folder, or extract the
+    /// watched module once, then exit
+    #[arg(long)]
+    once: bool,
+
+    #[command(flatten)]
+    audio: AudioArgs,
+
+    #[command(flatten)]
+    extract: ExtractArgs,
+}
+
+#[derive(clap::Args)]
+struct RankArgs {
+    /// Module files, directories or glob patterns (e.g. "music/*.xm")
+    #[arg(required = true, num_args = 1..)]
+    input: Vec<String>,
+
+    /// Also look for modules in the subdirectories of input directories
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// List only the N best modules
+    #[arg(long, value_name = "N")]
+    top: Option<usize>,
+
+    /// Print the ranking as JSON
+    #[arg(long)]
+    json: bool,
+
+    #[command(flatten)]
+    parallel: ParallelArgs,
+}
+
+#[derive(clap::Args)]
+struct ListArgs {
+    /// Module files, directories or glob patterns (e.g. "music/*.xm")
+    #[arg(required = true, num_args = 1..)]
+    input: Vec<String>,
+
+    /// Also look for modules in the subdirectories of input directories
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Print one JSON object per module instead of text
+    #[arg(long)]
+    json: bool,
+
+    #[command(flatten)]
+    audio: AudioArgs,
+
+    #[command(flatten)]
+    extract: ExtractArgs,
+}
+
+#[derive(clap::Args)]
+struct ProbeArgs {
+    /// Module file, or archive holding one
+    file: PathBuf,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args)]
+struct ConcatArgs {
+    /// Output directories of the modules to join, in order, each with the
+    /// manifest.json of --write-metadata and WAV stems
+    #[arg(required = true, num_args = 2..)]
+    sets: Vec<PathBuf>,
+
+    /// Directory to write the joined stems to
+    #[arg(short, long)]
+    output_dir: PathBuf,
+
+    /// Name the joined stems start with
+    #[arg(long, default_value = "concat")]
+    name: String,
+
+    /// Silence between sets (e.g. 2s)
+    #[arg(long, value_name = "DURATION", value_parser = parse_positive_seconds)]
+    gap: Option<f64>,
+
+    /// Crossfade each set into the next over this long (e.g. 500ms)
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_positive_seconds,
+        conflicts_with = "gap"
+    )]
+    crossfade: Option<f64>,
+
+    /// Replace joined stems already in the output directory
+    #[arg(long)]
+    overwrite: bool,
+}
+
+#[cfg(feature = "playback")]
+#[derive(clap::Args)]
+struct PlayArgs {
+    /// Module file, or archive holding one
+    file: PathBuf,
+
+    /// Only play these instruments (or samples for modules without
+    /// instruments), numbered as by --stems (e.g. 3 or 2,5)
+    #[arg(long, value_name = "STEMS")]
+    solo: Option<StemSelection>,
+}
+
+#[cfg(feature = "tui")]
+#[derive(clap::Args)]
+struct TuiArgs {
+    /// Module file, or archive holding one
+    file: PathBuf,
+
+    /// Output directory for the stems
+    #[arg(short, long)]
+    output_dir: String,
+
+    #[command(flatten)]
+    audio: AudioArgs,
+
+    /// Words naming instrument and sample stems in file names, such as
+    /// "instr=ins,sample=smp", or "none" to leave them out
+    #[arg(long, value_name = "KIND=LABEL,...")]
+    labels: Option<StemLabels>,
+
+    /// Layout of the file names of stems, as for extraction
+    #[arg(long, value_name = "TEMPLATE")]
+    name_template: Option<NameTemplate>,
+
+    /// Add a tag to FLAC, Vorbis and Opus files (e.g. --tag GENRE=Chiptune),
+    /// replacing the one of the same name taken from the module. Can be repeated
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = untracker_core::tags::parse_tag)]
+    tags: Vec<Tag>,
+
+    /// Ignore the `<module>.untracker.toml` override file next to the module
+    #[arg(long)]
+    no_overrides: bool,
+}
+
+#[cfg(feature = "serve")]
+#[derive(clap::Args)]
+struct ServeArgs {
+    /// Port to listen on
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+
+    /// Address to listen on, such as 0.0.0.0 for every interface
+    #[arg(long, default_value = "127.0.0.1")]
+    bind: String,
+
+    /// Renders running at once; requests for more stems wait their turn
+    /// [default: the number of CPUs]
+    #[arg(long, value_name = "N")]
+    max_renders: Option<usize>,
+
+    /// Options stems are rendered with, unless the request says otherwise
+    #[command(flatten)]
+    audio: AudioArgs,
+
+    #[command(flatten)]
+    cache: CacheArgs,
+}
+
+#[cfg(feature = "decode")]
+#[derive(clap::Args)]
+struct DiffArgs {
+    /// Audio file to compare
+    file: PathBuf,
+
+    /// Audio file to compare it to, at the same sample rate and channels
+    reference: PathBuf,
+
+    /// Fail if the difference is more than DB below the reference, such as
+    /// -60 to allow only what lossy encoding leaves
+    #[arg(long, value_name = "DB", allow_negative_numbers = true)]
+    max_difference: Option<f64>,
+
+    /// Print the comparison as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args)]
+struct MixArgs {
+    /// Input module files, directories or glob patterns (e.g. "music/*.xm")
+    #[arg(short, long, required = true, num_args = 1..)]
+    input: Vec<String>,
+
+    /// Also look for modules in the subdirectories of input directories
     #[arg(short, long)]
-    input: String,
+    recursive: bool,
 
-    /// Output directory for stem files
+    /// Output directory for the rendered mixes
     #[arg(short, long)]
     output_dir: String,
 
-    /// Sample rate
-    #[arg(long, default_value_t = 44100)]
-    sample_rate: u32,
+    #[command(flatten)]
+    audio: AudioArgs,
+
+    /// Treat the inputs as an album: apply one gain to all mixes so the album
+    /// reaches --album-target, and write its loudness summary to album.json
+    #[arg(long, conflicts_with = "normalize")]
+    album: bool,
+
+    /// Integrated loudness in LUFS used by --album
+    #[arg(long, default_value_t = -14.0, allow_negative_numbers = true)]
+    album_target: f64,
+
+    /// Add a tag to FLAC, Vorbis and Opus files (e.g. --tag GENRE=Chiptune),
+    /// replacing the one of the same name taken from the module. Can be repeated
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = untracker_core::tags::parse_tag)]
+    tags: Vec<Tag>,
+
+    #[command(flatten)]
+    parallel: ParallelArgs,
+
+    /// Ignore the `<module>.untracker.toml` override files next to the inputs
+    #[arg(long)]
+    no_overrides: bool,
+
+    #[command(flatten)]
+    cache: CacheArgs,
+}
+
+/// Audio options shared by all commands.
+#[derive(clap::Args)]
+struct AudioArgs {
+    /// Sample rate
+    #[arg(long, default_value_t = 44100)]
+    sample_rate: u32,
+
+    /// Number of channels (1 or 2)
+    #[arg(long, default_value_t = 2)]
+    channels: u32,
+
+    /// Channels libopenmpt renders (1, 2 or 4 for quad), downmixed or spread
+    /// to --channels [default: the same as --channels]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=4))]
+    render_channels: Option<u32>,
+
+    /// Resampling method
+    #[arg(long, default_value = "sinc")]
+    resample: ResampleMethodArg,
+
+    /// Output format: wav, vorbis, opus, flac, mp3, or a fallback list such as opus|vorbis|wav
+    #[arg(long, default_value = "wav")]
+    format: String,
+
+    /// Bit depth for lossless formats (16 or 24) [default: 16, or 32 for float samples]
+    #[arg(long)]
+    bit_depth: Option<u32>,
+
+    /// Sample format of WAV files: int, or float to keep levels above full scale
+    #[arg(long, default_value = "int")]
+    sample_format: SampleFormatArg,
+
+    /// Add TPDF dither when converting to integer samples, instead of rounding.
+    /// Renders in floating point, as 24-bit output always does
+    #[arg(long)]
+    dither: bool,
+
+    /// Bitrate for Opus format in kbps
+    #[arg(long, default_value_t = 128)]
+    opus_bitrate: u32,
+
+    /// Opus bitrate management: on (variable), off (constant), or constrained
+    /// variable bitrate for streaming
+    #[arg(long, default_value = "on")]
+    opus_vbr: OpusVbrArg,
+
+    /// Opus encoder complexity (0 fastest - 10 best)
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(0..=10))]
+    opus_complexity: u32,
+
+    /// What the Opus encoder tunes itself for: audio (music), voip (speech),
+    /// or low-delay
+    #[arg(long, default_value = "audio")]
+    opus_application: OpusApplicationArg,
+
+    /// Vorbis quality level (0-10)
+    #[arg(long, default_value_t = 5)]
+    vorbis_quality: u32,
+
+    /// Constant bitrate for MP3 format in kbps
+    #[arg(long, default_value_t = 192)]
+    mp3_bitrate: u32,
+
+    /// Encode MP3 with variable bitrate at this LAME quality (0 best - 9 worst) instead
+    #[arg(long, value_parser = clap::value_parser!(u32).range(0..=9))]
+    mp3_vbr_quality: Option<u32>,
+
+    /// Stereo separation in percent (0-200)
+    /// [default: 100 for 4-channel Amiga modules, 50 otherwise]
+    #[arg(long)]
+    stereo_separation: Option<u32>,
+
+    /// Gain of the libopenmpt mixer in dB (e.g. -6), applied while rendering,
+    /// so modules that clip can be rendered with headroom
+    #[arg(long, value_name = "DB", allow_negative_numbers = true)]
+    gain: Option<f64>,
+
+    /// Volume ramping strength of the libopenmpt mixer, from 0 (none, which
+    /// can click) to 10 (softest) [default: libopenmpt's]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(i32).range(0..=10))]
+    volume_ramping: Option<i32>,
+
+    /// Bring each file to the same level: its peak, or its RMS level with "rms",
+    /// reaches --normalize-target [default when given without a mode: peak]
+    #[arg(long, value_name = "MODE", num_args = 0..=1, default_missing_value = "peak")]
+    normalize: Option<NormalizeArg>,
+
+    /// Level in dBFS used by --normalize [default: -1 for peak, -20 for rms]
+    #[arg(long, allow_negative_numbers = true)]
+    normalize_target: Option<f64>,
+
+    /// Measure the loudness (EBU R128) and true peak of each file, and tag
+    /// FLAC, Vorbis and Opus files with their ReplayGain. Recorded in the
+    /// manifest of --write-metadata for every format
+    #[arg(long)]
+    replaygain: bool,
+
+    /// Remove the silence at the end of each file, or at both ends with "both",
+    /// in which case each stem starts at its own offset
+    #[arg(long, value_name = "END", num_args = 0..=1, default_missing_value = "end")]
+    trim_silence: Option<TrimSilenceArg>,
+
+    /// Render this sub-song of multi-song modules, numbered from 1
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    subsong: Option<i32>,
+
+    /// Only render the first part of each file (e.g. 30s, 1m, 1:30), with a short fade-out
+    #[arg(long, value_parser = parse_positive_seconds)]
+    preview: Option<f64>,
+
+    /// Repeat the song this many times after playing it once
+    #[arg(long, default_value_t = 0)]
+    loop_count: u32,
+
+    /// Stop rendering after this long (e.g. 600, 10m), for songs that loop for too long
+    #[arg(long, value_parser = parse_positive_seconds)]
+    max_duration: Option<f64>,
+
+    /// Make each file a seamless loop for games: render the song once, then
+    /// this long (e.g. 2s) past its end, crossfaded into where it loops back
+    /// to. The loop is written to WAV smpl chunks, tags and the manifest
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_positive_seconds,
+        conflicts_with_all = ["preview", "loop_count", "max_duration", "trim_silence"]
+    )]
+    loop_crossfade: Option<f64>,
+
+    /// Start rendering at this order, numbered from 0 as in trackers
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(i32).range(0..),
+        conflicts_with_all = ["start_time", "end_time"]
+    )]
+    start_order: Option<i32>,
+
+    /// Stop rendering at the end of this order
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(i32).range(0..),
+        conflicts_with_all = ["start_time", "end_time"]
+    )]
+    end_order: Option<i32>,
+
+    /// Start rendering at this time (e.g. 45, 1:30)
+    #[arg(long, value_parser = untracker_core::time::parse_seconds)]
+    start_time: Option<f64>,
+
+    /// Stop rendering at this time, counted from the start of the song
+    #[arg(long, value_parser = parse_positive_seconds)]
+    end_time: Option<f64>,
+
+    /// Mute and unmute channels at set times of the render, from a file of
+    /// lines such as "mute channel 3 from 1:00 to 1:30"
+    #[arg(long, value_name = "FILE")]
+    mute_automation: Option<PathBuf>,
+
+    /// Set the volume and panning of channels along curves, from a CSV or
+    /// JSON timeline of points such as "1:00, 3, volume, -6dB"
+    #[arg(long, value_name = "FILE")]
+    mix_automation: Option<PathBuf>,
+
+    /// Set a libopenmpt ctl before rendering (e.g. --ctl
+    /// render.resampler.emulate_amiga=1), can be repeated
+    #[arg(long = "ctl", value_name = "KEY=VALUE", value_parser = untracker_core::session::parse_ctl)]
+    ctls: Vec<CtlSetting>,
+
+    /// Produce byte-identical files across runs (no dithering, fixed Ogg serials, no version strings)
+    #[arg(long)]
+    reproducible: bool,
+
+    /// Print where the render options of each module come from
+    #[arg(long)]
+    explain: bool,
+}
+
+impl AudioArgs {
+    /// Section of the song selected with --start-order/--end-order or --start-time/--end-time.
+    fn range(&self) -> Option<SongRange> {
+        if self.start_order.is_some() || self.end_order.is_some() {
+            Some(SongRange::Orders {
+                start: self.start_order.unwrap_or(0),
+                end: self.end_order,
+            })
+        } else if self.start_time.is_some() || self.end_time.is_some() {
+            Some(SongRange::Seconds {
+                start: self.start_time.unwrap_or(0.0),
+                end: self.end_time,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Options left to each module, because they aren't given on the command line.
+    fn module_defaults(&self) -> ModuleDefaults {
+        ModuleDefaults {
+            stereo_separation: self.stereo_separation.is_none(),
+        }
+    }
+
+    fn to_options(&self) -> Result<ExportOptions> {
+        Ok(ExportOptions {
+            format: self.format.parse()?,
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            render_channels: self.render_channels,
+            bit_depth: self.bit_depth.unwrap_or(match self.sample_format {
+                SampleFormatArg::Int => 16,
+                SampleFormatArg::Float => 32,
+            }),
+            sample_format: self.sample_format.into(),
+            dither: self.dither,
+            opus_bitrate: self.opus_bitrate,
+            opus_vbr: self.opus_vbr.into(),
+            opus_complexity: self.opus_complexity,
+            opus_application: self.opus_application.into(),
+            vorbis_quality: self.vorbis_quality,
+            mp3_bitrate: self.mp3_bitrate,
+            mp3_vbr_quality: self.mp3_vbr_quality,
+            resample: self.resample.into(),
+            stereo_separation: self.stereo_separation.map_or(100, |s| s as i32),
+            master_gain_db: self.gain.unwrap_or(0.0),
+            volume_ramping: self.volume_ramping,
+            subsong: self.subsong.and_then(SubsongIndex::from_number),
+            preview: self.preview,
+            loop_count: self.loop_count,
+            max_duration: self.max_duration,
+            range: self.range(),
+            loop_crossfade: self.loop_crossfade,
+            mute_automation: self
+                .mute_automation
+                .as_deref()
+                .map(|path| MuteAutomation::load(path).map(Arc::new))
+                .transpose()?,
+            mix_automation: self
+                .mix_automation
+                .as_deref()
+                .map(|path| MixAutomation::load(path).map(Arc::new))
+                .transpose()?,
+            ctls: self.ctls.clone().into(),
+            normalize: self
+                .normalize
+                .map_or(NormalizeMode::Off, NormalizeMode::from),
+            normalize_target: self.normalize_target.unwrap_or(match self.normalize {
+                Some(NormalizeArg::Rms) => -20.0,
+                _ => -1.0,
+            }),
+            trim_silence: self
+                .trim_silence
+                .map_or(TrimSilence::Off, TrimSilence::from),
+            replaygain: self.replaygain,
+            reproducible: self.reproducible,
+            ..ExportOptions::default()
+        })
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum ResampleMethodArg {
+    Nearest,
+    Linear,
+    Cubic,
+    Sinc,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum OpusVbrArg {
+    On,
+    Off,
+    Constrained,
+}
+
+impl From<OpusVbrArg> for OpusVbr {
+    fn from(arg: OpusVbrArg) -> Self {
+        match arg {
+            OpusVbrArg::On => OpusVbr::On,
+            OpusVbrArg::Off => OpusVbr::Off,
+            OpusVbrArg::Constrained => OpusVbr::Constrained,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum OpusApplicationArg {
+    Audio,
+    Voip,
+    LowDelay,
+}
+
+impl From<OpusApplicationArg> for OpusApplication {
+    fn from(arg: OpusApplicationArg) -> Self {
+        match arg {
+            OpusApplicationArg::Audio => OpusApplication::Audio,
+            OpusApplicationArg::Voip => OpusApplication::Voip,
+            OpusApplicationArg::LowDelay => OpusApplication::LowDelay,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum SampleFormatArg {
+    Int,
+    Float,
+}
+
+impl From<SampleFormatArg> for SampleFormat {
+    fn from(arg: SampleFormatArg) -> Self {
+        match arg {
+            SampleFormatArg::Int => SampleFormat::Int,
+            SampleFormatArg::Float => SampleFormat::Float,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum ExtractMode {
+    /// Render the song once per stem, with everything else muted
+    Render,
+    /// Write each sample of MOD, S3M, XM and IT files to a 16-bit WAV file at
+    /// its own rate, with its loop
+    RawSamples,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum ListFormat {
+    Text,
+    Json,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum NormalizeArg {
+    /// Keep the level as rendered
+    Off,
+    /// Scale so the loudest sample reaches the target
+    Peak,
+    /// Scale so the RMS level reaches the target, as far as the peak allows
+    Rms,
+}
+
+impl From<NormalizeArg> for NormalizeMode {
+    fn from(arg: NormalizeArg) -> Self {
+        match arg {
+            NormalizeArg::Off => NormalizeMode::Off,
+            NormalizeArg::Peak => NormalizeMode::Peak,
+            NormalizeArg::Rms => NormalizeMode::Rms,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum TrimSilenceArg {
+    /// Only at the end, keeping stems aligned
+    #[value(alias = "end-only")]
+    End,
+    /// At the start and the end
+    Both,
+}
+
+impl From<TrimSilenceArg> for TrimSilence {
+    fn from(arg: TrimSilenceArg) -> Self {
+        match arg {
+            TrimSilenceArg::End => TrimSilence::End,
+            TrimSilenceArg::Both => TrimSilence::Both,
+        }
+    }
+}
+
+impl From<ResampleMethodArg> for ResampleMethod {
+    fn from(arg: ResampleMethodArg) -> Self {
+        match arg {
+            ResampleMethodArg::Nearest => ResampleMethod::Nearest,
+            ResampleMethodArg::Linear => ResampleMethod::Linear,
+            ResampleMethodArg::Cubic => ResampleMethod::Cubic,
+            ResampleMethodArg::Sinc => ResampleMethod::Sinc,
+        }
+    }
+}
+
+fn parse_positive_seconds(s: &str) -> Result<f64> {
+    let seconds = untracker_core::time::parse_seconds(s)?;
+    if seconds <= 0.0 {
+        return Err(anyhow!("Duration must be greater than zero"));
+    }
+    Ok(seconds)
+}
+
+fn main() -> Result<()> {
+    // Initialize logging with a nice format, but only in non-test mode
+    if !cfg!(test) {
+        env_logger::Builder::from_default_env()
+            .format(|buf, record| {
+                writeln!(
+                    buf,
+                    "{} [{}] - {}",
+                    chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                    record.level(),
+                    record.args()
+                )
+            })
+            .init();
+    }
+
+    let args = Args::parse();
+    match args.command {
+        Some(Command::Extract(extract_args)) => extract(*extract_args),
+        Some(Command::Batch(mut batch_args)) => {
+            batch_args.batch = true;
+            extract(*batch_args)
+        }
+        Some(Command::List(list_args)) => list(*list_args),
+        Some(Command::Mix(mix_args)) => mix(*mix_args),
+        Some(Command::Watch(mut watch_args)) => {
+            watch_args.extract.add_meta_tags();
+            watch(*watch_args)
+        }
+        Some(Command::Manifest(command)) => manifest(command),
+        Some(Command::Rank(rank_args)) => rank(rank_args),
+        Some(Command::Probe(probe_args)) => probe(probe_args),
+        Some(Command::Concat(concat_args)) => concat(concat_args),
+        #[cfg(feature = "decode")]
+        Some(Command::Diff(diff_args)) => diff(diff_args),
+        #[cfg(feature = "playback")]
+        Some(Command::Play(play_args)) => play(play_args),
+        #[cfg(feature = "tui")]
+        Some(Command::Tui(tui_args)) => tui(*tui_args),
+        #[cfg(feature = "serve")]
+        Some(Command::Serve(serve_args)) => serve(*serve_args),
+        None => extract(args.run),
+    }
+}
+
+/// Version with what went into the build, for `--version`.
+fn long_version() -> &'static str {
+    static LONG_VERSION: OnceLock<String> = OnceLock::new();
+    LONG_VERSION.get_or_init(|| {
+        // vergen puts this placeholder in place of what it couldn't find
+        let known = |value: &'static str| value != "VERGEN_IDEMPOTENT_OUTPUT";
+        let commit = match (env!("VERGEN_GIT_SHA"), env!("VERGEN_GIT_DIRTY")) {
+            (sha, "true") if known(sha) => format!("{} (modified)", sha),
+            (sha, _) if known(sha) => sha.to_string(),
+            _ => "unknown".to_string(),
+        };
+        let features: Vec<&str> = env!("VERGEN_CARGO_FEATURES")
+            .split(',')
+            .filter(|feature| !feature.is_empty() && *feature != "default")
+            .collect();
+        let libopenmpt = openmpt::info::get_string(&openmpt::info::InfoField::LibraryVersion)
+            .unwrap_or_else(|| "unknown".to_string());
+        format!(
+            "{}\ncommit: {}\ntarget: {}\nfeatures: {}\nlibopenmpt: {}",
+            env!("CARGO_PKG_VERSION"),
+            commit,
+            env!("VERGEN_CARGO_TARGET_TRIPLE"),
+            if features.is_empty() {
+                "none".to_string()
+            } else {
+                features.join(", ")
+            },
+            libopenmpt
+        )
+    })
+}
+
+/// Resolve the options of `input`: apply its per-module overrides (unless
+/// disabled), then the defaults chosen from the module for the options left
+/// to it, and validate the result.
+fn options_for(
+    input: &Path,
+    options: &ExportOptions,
+    audio: &AudioArgs,
+    no_overrides: bool,
+) -> Result<ExportOptions> {
+    options_for_job(
+        input,
+        options,
+        audio,
+        no_overrides,
+        &ModuleOverrides::default(),
+    )
+}
+
+/// Resolve the options of `input` as [`options_for`] does, with the options
+/// of its job in a job file applied over the per-module overrides.
+fn options_for_job(
+    input: &Path,
+    options: &ExportOptions,
+    audio: &AudioArgs,
+    no_overrides: bool,
+    job: &ModuleOverrides,
+) -> Result<ExportOptions> {
+    let mut explanation = Vec::new();
+
+    // Per-module overrides take precedence over the command line
+    let overrides = if no_overrides {
+        None
+    } else {
+        ModuleOverrides::load_for(input)?
+    };
+    let mut defaults = audio.module_defaults();
+    let mut options = match overrides {
+        Some(overrides) => {
+            explanation.push(format!(
+                "{} sets {}",
+                ModuleOverrides::sidecar_path(input).display(),
+                overrides.keys().join(", ")
+            ));
+            defaults = defaults.without(&overrides);
+            overrides.apply(options)?
+        }
+        None => options.clone(),
+    };
+    // The job file asks for this run what the override file asks for always
+    if !job.keys().is_empty() {
+        explanation.push(format!("the job sets {}", job.keys().join(", ")));
+        defaults = defaults.without(job);
+        options = job.apply(&options)?;
+    }
+
+    if defaults.any() {
+        let buffer = read_file_to_buffer(input)?;
+        let module_ext = ModuleExt::from_memory(&buffer, Logger::None, &[])
+            .map_err(|_| anyhow!("Failed to load module"))?;
+        explanation.extend(defaults.apply(&mut options, &mut module_ext.get_module()));
+    }
+
+    if audio.explain {
+        println!("Options for {}:", input.display());
+        if explanation.is_empty() {
+            println!("  all from the command line");
+        }
+        for line in &explanation {
+            println!("  {}", line);
+        }
+    }
+
+    // Input validation
+    options.validate()?;
+    Ok(options)
+}
+
+fn progress_bar(len: u64) -> ProgressBar {
+    if CI.load(Ordering::Relaxed) {
+        return ProgressBar::hidden();
+    }
+    let pb = ProgressBar::new(len);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) - {msg}")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    pb
+}
+
+/// The progress bar to draw on, which is none in unit tests.
+fn progress(pb: &ProgressBar) -> Option<&ProgressBar> {
+    if cfg!(test) {
+        None
+    } else {
+        Some(pb)
+    }
+}
+
+fn extract(mut args: ExtractCommandArgs) -> Result<()> {
+    args.extract.add_meta_tags();
+    // Listed outputs are relative to the output directory, which can be left out
+    if let Some(format) = args.extract.dry_run {
+        let single = single_input(&args.input).filter(|_| !args.batch);
+        return dry_run(
+            &args.input,
+            single,
+            args.recursive,
+            &args.audio,
+            &args.extract,
+            format,
+        );
+    }
+    let Some(output_dir) = args.output_dir.clone() else {
+        return Err(anyhow!("Both --input and --output-dir are required"));
+    };
+    let output_dir = Path::new(&output_dir);
+    if output_dir == Path::new(STDOUT_PATH) {
+        if args.batch {
+            return Err(anyhow!(
+                "untracker batch writes a directory per module, so it can't stream to stdout"
+            ));
+        }
+        return stream_to_stdout(&args);
+    }
+    if let Some(job_file) = &args.job_file {
+        if !args.batch {
+            return Err(anyhow!("--job-file is only for untracker batch"));
+        }
+        return run_jobs(&args, job_file, output_dir);
+    }
+    if args.ci {
+        CI.store(true, Ordering::Relaxed);
+        args.extract.unattended = true;
+    }
+    let mut report = CiReport::default();
+
+    // A single module file is extracted straight into the output directory
+    if let Some(input) = single_input(&args.input).filter(|_| !args.batch) {
+        let started = Instant::now();
+        let log = LogCapture::new();
+        let result = options_for(
+            input,
+            &args.audio.to_options()?,
+            &args.audio,
+            args.extract.no_overrides,
+        )
+        .and_then(|options| extract_input(input, output_dir, &options, &args.extract, &log));
+        let warnings = log.take();
+        for message in &warnings {
+            log::warn!("libopenmpt: {}", message);
+        }
+        if args.ci {
+            let stems = result
+                .as_ref()
+                .map(|extraction| extraction.stems.iter().filter(|r| !r.is_skipped()).count())
+                .map_err(|e| anyhow!("{:#}", e));
+            report_module(
+                &mut report,
+                CiResult::new(input, &stems, warnings.len(), started.elapsed()),
+            );
+            write_report(&args, &report)?;
+        }
+        result?;
+        return Ok(());
+    }
+
+    let inputs = expand_inputs(&args.input, args.recursive)?;
+    check_distinct_outputs(inputs.iter().map(|input| {
+        (
+            input.as_path(),
+            module_output_dir(output_dir, input, &args.input),
+        )
+    }))?;
+    let base_options = args.audio.to_options()?;
+    // Fail on bad global options now rather than on every module
+    base_options.validate()?;
+    status!("Extracting the stems of {} modules", inputs.len());
+    fs::create_dir_all(output_dir)?;
+    let manifest_path = output_dir.join(MANIFEST_FILE);
+    let extracted = if args.extract.existing.resume {
+        extracted_modules(&manifest_path)?
+    } else {
+        BTreeSet::new()
+    };
+
+    let mut failed = 0;
+    for input in &inputs {
+        let module_dir = module_output_dir(output_dir, input, &args.input);
+        if extracted.contains(&module_dir) {
+            status!("Skipping {}: already extracted", input.display());
+            if args.ci {
+                report_module(&mut report, CiResult::skipped(input));
+            }
+            continue;
+        }
+        status!("Processing {}", input.display());
+        let started = Instant::now();
+        let log = LogCapture::new();
+        let mut metadata = BTreeMap::new();
+        let result = options_for(input, &base_options, &args.audio, args.extract.no_overrides)
+            .and_then(|options| extract_input(input, &module_dir, &options, &args.extract, &log))
+            .map(|extraction| {
+                metadata = extraction.metadata;
+                extraction.stems.iter().filter(|r| !r.is_skipped()).count()
+            });
+        if let Err(e) = &result {
+            failed += 1;
+            status!("  Failed {}: {}", input.display(), e);
+        }
+
+        let warnings = log.take();
+        if !warnings.is_empty() {
+            status!("  {} libopenmpt warnings", warnings.len());
+        }
+        if args.ci {
+            let result = CiResult::new(input, &result, warnings.len(), started.elapsed());
+            report_module(&mut report, result);
+        }
+        ManifestEntry::new(input, &module_dir, &result, warnings)
+            .with_metadata(metadata)
+            .append_to(&manifest_path)?;
+        if result.is_err() && args.extract.parallel.fail_fast {
+            write_report(&args, &report)?;
+            return Err(anyhow!(
+                "Stopped at the first module that failed to extract ({})",
+                input.display()
+            ));
+        }
+    }
+    write_report(&args, &report)?;
+
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} of {} modules failed to extract",
+            failed,
+            inputs.len()
+        ));
+    }
+    status!("Completed extracting {} modules!", inputs.len());
+    Ok(())
+}
+
+/// Run the jobs of `job_file` for `untracker batch --job-file`, a module per
+/// worker, and write how each went to the results file in `output_dir`.
+fn run_jobs(args: &ExtractCommandArgs, job_file: &Path, output_dir: &Path) -> Result<()> {
+    let jobs = jobs::load(job_file)?;
+    if args.extract.groups.is_some() && jobs.iter().any(|job| job.stems.is_some()) {
+        return Err(anyhow!("Jobs can't select stems when --groups is given"));
+    }
+    // Every job gets a directory of its own, as they may run side by side
+    let jobs: Vec<(Job, PathBuf)> = jobs
+        .into_iter()
+        .map(|job| {
+            let module_dir = match &job.output {
+                Some(output) => output_dir.join(output),
+                None => module_output_dir(output_dir, &job.input, &[]),
+            };
+            (job, module_dir)
+        })
+        .collect();
+    check_distinct_outputs(
+        jobs.iter()
+            .map(|(job, module_dir)| (job.input.as_path(), module_dir.clone())),
+    )?;
+    let base_options = args.audio.to_options()?;
+    base_options.validate()?;
+    let workers = args.extract.parallel.jobs();
+    println!("Running {} jobs on {} workers", jobs.len(), workers);
+    fs::create_dir_all(output_dir)?;
+    let manifest_path = output_dir.join(MANIFEST_FILE);
+    let extracted = if args.extract.existing.resume {
+        extracted_modules(&manifest_path)?
+    } else {
+        BTreeSet::new()
+    };
+
+    // Nobody answers the questions of a worker, and each worker renders the
+    // stems of its module one after the other
+    let mut extract = args.extract.clone();
+    extract.unattended = true;
+    extract.parallel.parallel = false;
+    extract.parallel.jobs = Some(1);
+    // A line per job, rather than the progress of modules side by side
+    CI.store(true, Ordering::Relaxed);
+
+    let manifest = Mutex::new(());
+    let failed = AtomicBool::new(false);
+    let results = parallel::run(
+        &jobs,
+        workers,
+        None,
+        |(job, module_dir), _| -> Result<Option<JobResult>> {
+            if extracted.contains(module_dir) {
+                let result = JobResult::skipped(job, module_dir);
+                println!("{}", result.line());
+                return Ok(Some(result));
+            }
+            // With --fail-fast, the jobs not started once one fails are left out
+            if extract.parallel.fail_fast && failed.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+
+            let started = Instant::now();
+            let log = LogCapture::new();
+            let mut metadata = BTreeMap::new();
+            let job_args = ExtractArgs {
+                stems: job.stems.clone().or_else(|| extract.stems.clone()),
+                ..extract.clone()
+            };
+            let result = options_for_job(
+                &job.input,
+                &base_options,
+                &args.audio,
+                extract.no_overrides,
+                &job.options,
+            )
+            .and_then(|options| extract_input(&job.input, module_dir, &options, &job_args, &log))
+            .map(|extraction| {
+                metadata = extraction.metadata;
+                extraction.stems.iter().filter(|r| !r.is_skipped()).count()
+            });
+            if result.is_err() {
+                failed.store(true, Ordering::Relaxed);
+            }
+
+            let warnings = log.take();
+            let job_result =
+                JobResult::new(job, module_dir, &result, warnings.len(), started.elapsed());
+            println!("{}", job_result.line());
+            // Modules are recorded as they are done, for --resume
+            let _manifest = manifest.lock().unwrap();
+            ManifestEntry::new(&job.input, module_dir, &result, warnings)
+                .with_metadata(metadata)
+                .append_to(&manifest_path)?;
+            Ok(Some(job_result))
+        },
+    )?;
+
+    let mut job_results = JobResults::default();
+    for result in results {
+        if let Some(result) = result? {
+            job_results.push(result);
+        }
+    }
+    let results_path = output_dir.join(JOB_RESULTS_FILE);
+    job_results.write(&results_path)?;
+    println!("Wrote the results to {}", results_path.display());
+
+    if job_results.failed > 0 {
+        if extract.parallel.fail_fast {
+            return Err(anyhow!("Stopped once a job failed"));
+        }
+        return Err(anyhow!(
+            "{} of {} jobs failed",
+            job_results.failed,
+            jobs.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Print the line of a module extracted with --ci, and add it to the report.
+fn report_module(report: &mut CiReport, result: CiResult) {
+    println!("{}", result.line());
+    report.push(result);
+}
+
+/// Write the report of --ci, if asked to.
+fn write_report(args: &ExtractCommandArgs, report: &CiReport) -> Result<()> {
+    match &args.ci_report {
+        Some(path) => report
+            .write(path)
+            .map_err(|e| anyhow!("Cannot write {}: {}", path.display(), e)),
+        None => Ok(()),
+    }
+}
+
+/// Output directories of the modules the batch manifest at `path` lists as
+/// extracted, for --resume.
+fn extracted_modules(path: &Path) -> Result<BTreeSet<PathBuf>> {
+    if !path.exists() {
+        return Ok(BTreeSet::new());
+    }
+    // A module that failed after it was extracted keeps its latest record
+    Ok(archive::merge(archive::load(path)?)
+        .into_iter()
+        .filter(|record| record.entry.status == "done")
+        .map(|record| record.entry.output_dir)
+        .filter(|dir| dir.is_dir())
+        .collect())
+}
+
+/// The module file given on its own, which is extracted straight into the
+/// output directory rather than a subdirectory of its own.
+fn single_input(patterns: &[String]) -> Option<&Path> {
+    match patterns {
+        [pattern] if !Path::new(pattern).is_dir() && !pattern.contains(['*', '?', '[']) => {
+            Some(Path::new(pattern))
+        }
+        _ => None,
+    }
+}
+
+/// Render one stem, or the full mix, of a single module to stdout for
+/// `--output-dir -`, to be piped into another program. Nothing else is
+/// printed there, and nothing is written next to it.
+fn stream_to_stdout(args: &ExtractCommandArgs) -> Result<()> {
+    let Some(input) = single_input(&args.input) else {
+        return Err(anyhow!(
+            "--output-dir - streams a single module: give one module file"
+        ));
+    };
+    let extract = &args.extract;
+    #[allow(unused_mut)]
+    let mut unsupported = vec![
+        ("--ci", args.ci),
+        ("--explain", args.audio.explain),
+        ("--loop-crossfade", args.audio.loop_crossfade.is_some()),
+        (
+            "--mode raw-samples",
+            extract.mode == ExtractMode::RawSamples,
+        ),
+        ("--groups", extract.groups.is_some()),
+        ("--interactive", extract.interactive),
+        ("--remix-kit", extract.remix_kit),
+        ("--all-subsongs", extract.all_subsongs),
+        ("--verify-alignment", extract.verify_alignment),
+        ("--verify-sum", extract.verify_sum),
+        ("--markers", extract.markers.is_some()),
+        ("--export-session", extract.export_session.is_some()),
+        ("--write-metadata", extract.write_metadata),
+        ("--render-info", extract.render_info),
+    ];
+    #[cfg(feature = "decode")]
+    unsupported.push(("--verify-output", extract.verify_output));
+    if let Some((flag, _)) = unsupported.iter().find(|(_, given)| *given) {
+        return Err(anyhow!(
+            "{} can't be used with --output-dir -, which only streams audio",
+            flag
+        ));
+    }
+
+    let options = options_for(
+        input,
+        &args.audio.to_options()?,
+        &args.audio,
+        extract.no_overrides,
+    )?;
+    let buffer = read_file_to_buffer(input)?;
+    let module_ext = load_module(&buffer, Logger::StdErr, &options.ctls)?;
+    let mut module = module_ext.get_module();
+    let file_stem = module_stem(input).unwrap_or("stem");
+    let name = output_base_name(file_stem, &module_metadata(&mut module));
+
+    let pb = progress_bar(1);
+    let result = match (&extract.stems, extract.full_mix) {
+        (None, true) => render_full_mix(
+            &buffer,
+            STDOUT_PATH,
+            &name,
+            &options,
+            &extract.tags,
+            progress(&pb).map(|pb| pb as &dyn ProgressSink),
+        ),
+        (Some(selection), false) => {
+            let is_instrument = module.get_num_instruments() > 0;
+            let count = if is_instrument {
+                module.get_num_instruments()
+            } else {
+                module.get_num_samples()
+            };
+            let indices = selection.indices(count)?;
+            match indices[..] {
+                [index] => render_stem(
+                    &buffer,
+                    index,
+                    is_instrument,
+                    STDOUT_PATH,
+                    &name,
+                    &options,
+                    &NameTemplate::default(),
+                    &StemLabels::default(),
+                    &extract.tags,
+                    progress(&pb).map(|pb| pb as &dyn ProgressSink),
+                ),
+                _ => Err(anyhow!(
+                    "--output-dir - streams one stem, but --stems selects {} of them",
+                    indices.len()
+                )),
+            }
+        }
+        _ => Err(anyhow!(
+            "--output-dir - streams one render: select a stem with --stems, or the full mix with --full-mix"
+        )),
+    };
+    pb.finish_and_clear();
+    let result = result?;
+    info!(
+        "Streamed {:.1} seconds of {} to stdout",
+        result.duration,
+        input.display()
+    );
+    Ok(())
+}
+
+/// List the modules of `input`, and what extracting them would write, for
+/// `untracker list` and --dry-run. A `single` module is listed as extracted
+/// straight into the output directory.
+fn dry_run(
+    patterns: &[String],
+    single: Option<&Path>,
+    recursive: bool,
+    audio: &AudioArgs,
+    extract: &ExtractArgs,
+    format: ListFormat,
+) -> Result<()> {
+    let base_options = audio.to_options()?;
+    let inputs = match single {
+        Some(input) => vec![input.to_path_buf()],
+        None => {
+            let inputs = expand_inputs(patterns, recursive)?;
+            check_distinct_outputs(inputs.iter().map(|input| {
+                (
+                    input.as_path(),
+                    module_output_dir(Path::new(""), input, patterns),
+                )
+            }))?;
+            inputs
+        }
+    };
+
+    let mut failed = 0;
+    for input in &inputs {
+        let module_dir = match single {
+            Some(_) => PathBuf::new(),
+            None => module_output_dir(Path::new(""), input, patterns),
+        };
+        let listing = options_for(input, &base_options, audio, extract.no_overrides)
+            .and_then(|options| list_module(input, &module_dir, &options, extract));
+        match listing {
+            Ok(listing) => match format {
+                ListFormat::Text => print!("{}", listing.to_text()),
+                ListFormat::Json => println!("{}", serde_json::to_string(&listing)?),
+            },
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}: {}", input.display(), e);
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} of {} modules failed to load",
+            failed,
+            inputs.len()
+        ));
+    }
+    Ok(())
+}
+
+/// List modules, for `untracker list`.
+fn list(mut args: ListArgs) -> Result<()> {
+    args.extract.add_meta_tags();
+    let format = if args.json {
+        ListFormat::Json
+    } else {
+        args.extract.dry_run.unwrap_or(ListFormat::Text)
+    };
+    dry_run(
+        &args.input,
+        single_input(&args.input),
+        args.recursive,
+        &args.audio,
+        &args.extract,
+        format,
+    )
+}
+
+/// Names of the instruments of `module`, or of its samples if it has no
+/// instruments, in order.
+fn stem_names(module: &mut Module, is_instrument: bool, count: i32) -> Vec<String> {
+    if is_instrument {
+        InstrumentIndex::all(count)
+            .map(|i| module.get_instrument_name(i))
+            .collect()
+    } else {
+        SampleIndex::all(count)
+            .map(|i| module.get_sample_name(i))
+            .collect()
+    }
+}
+
+/// Files other than the stems that extracting a module named `stem_name`
+/// writes, as asked for by `args`.
+fn extra_output_files(stem_name: &str, options: &ExportOptions, args: &ExtractArgs) -> Vec<String> {
+    let mut files = Vec::new();
+    if args.analyze {
+        files.push("analysis.json".to_string());
+    }
+    if args.remix_kit {
+        files.push(REMIX_KIT_FILE.to_string());
+    }
+    if args.full_mix {
+        files.push(format!("{}.{}", stem_name, options.format.extension()));
+    }
+    if let Some(format) = args.markers {
+        files.push(format!("{}{}", stem_name, format.file_suffix()));
+    }
+    if let Some(format) = args.export_session {
+        files.push(format!("{}.{}", stem_name, format.extension()));
+    }
+    if args.write_metadata {
+        files.push(STEM_MANIFEST_FILE.to_string());
+    }
+    if args.render_info {
+        files.push(RENDER_INFO_FILE.to_string());
+    }
+    files
+}
+
+/// Load `input` and list its contents with the files [`extract_input`] would
+/// write for it into `module_dir`, without rendering anything.
+fn list_module(
+    input: &Path,
+    module_dir: &Path,
+    options: &ExportOptions,
+    args: &ExtractArgs,
+) -> Result<ModuleListing> {
+    let options = &if args.remix_kit {
+        remix_kit::kit_options(options)?
+    } else {
+        options.clone()
+    };
+    let labels = args.labels.clone().unwrap_or_default();
+    let template = args.name_template.clone().unwrap_or_default();
+
+    let buffer = read_file_to_buffer(input)?;
+    let module_ext = load_module(&buffer, Logger::None, &options.ctls)?;
+    let mut module = module_ext.get_module();
+    if let Some(subsong) = options.subsong {
+        select_subsong(&mut module, subsong)?;
+    }
+    let metadata = module_metadata(&mut module);
+    let file_stem = module_stem(input).unwrap_or("stem");
+    let stem_name = &output_base_name(file_stem, &metadata);
+
+    let num_instruments = module.get_num_instruments();
+    let is_instrument = num_instruments > 0;
+    let stem_count = if is_instrument {
+        num_instruments
+    } else {
+        module.get_num_samples()
+    };
+    let names = stem_names(&mut module, is_instrument, stem_count);
+    if let Some(automation) = &options.mute_automation {
+        automation.check_channels(module.get_num_channels())?;
+    }
+    if let Some(automation) = &options.mix_automation {
+        automation.check_channels(module.get_num_channels())?;
+    }
+
+    let mut files = Vec::new();
+    if !can_isolate(&module_ext) {
+        files.push(format!("{}.{}", stem_name, options.format.extension()));
+        if args.write_metadata {
+            files.push(STEM_MANIFEST_FILE.to_string());
+        }
+        if args.render_info {
+            files.push(RENDER_INFO_FILE.to_string());
+        }
+    } else {
+        match &args.groups {
+            Some(spec) => {
+                for group in spec.resolve(&names)? {
+                    files.push(group_file_name(stem_name, &group, options.format));
+                }
+            }
+            None => {
+                let indices: Vec<InstrumentIndex> = match &args.stems {
+                    Some(selection) => selection.indices(stem_count)?,
+                    None => InstrumentIndex::all(stem_count).collect(),
+                };
+                for i in indices {
+                    files.push(stem_file_name(
+                        &template,
+                        stem_name,
+                        is_instrument,
+                        i,
+                        &names[i.get() as usize],
+                        options.format,
+                        &labels,
+                    ));
+                }
+            }
+        }
+        files.extend(extra_output_files(stem_name, options, args));
+    }
+
+    let mut listing = ModuleListing::new(input, &mut module);
+    let subsong_dirs: Vec<PathBuf> = if args.all_subsongs {
+        listing
+            .subsongs
+            .iter()
+            .map(|subsong| module_dir.join(format!("subsong_{:02}", subsong.number)))
+            .collect()
+    } else {
+        vec![module_dir.to_path_buf()]
+    };
+    listing.outputs = subsong_dirs
+        .iter()
+        .flat_map(|dir| files.iter().map(move |file| dir.join(file)))
+        .collect();
+    Ok(listing)
+}
+
+/// Peak level in dBFS below which `--remix-kit` drops a stem, unless --skip-silent is given.
+const REMIX_KIT_SILENCE_DBFS: f64 = -80.0;
+
+/// Subdirectory of `output_dir` holding the stems of `input` when extracting
+/// several modules: named after the module, under the directories that lead
+/// to it from the input directory it was found in.
+fn module_output_dir(output_dir: &Path, input: &Path, patterns: &[String]) -> PathBuf {
+    let name = module_stem(input).unwrap_or("module");
+    output_dir.join(input_subdir(input, patterns)).join(name)
+}
+
+/// Directories between the input directory `input` was found in and the
+/// module, which outputs follow so that modules of the same name in
+/// different directories stay apart. Empty for modules given as files or
+/// glob patterns.
+fn input_subdir<'a>(input: &'a Path, patterns: &[String]) -> &'a Path {
+    let parent = input.parent().unwrap_or(Path::new(""));
+    // The closest input directory, should one hold another
+    patterns
+        .iter()
+        .map(Path::new)
+        .filter(|dir| dir.is_dir())
+        .filter_map(|dir| parent.strip_prefix(dir).ok())
+        .min_by_key(|subdir| subdir.components().count())
+        .unwrap_or(Path::new(""))
+}
+
+/// Fail if two inputs would be written to the same place, given as pairs of
+/// an input and where it is written.
+fn check_distinct_outputs<'a>(
+    outputs: impl IntoIterator<Item = (&'a Path, PathBuf)>,
+) -> Result<()> {
+    let mut seen = BTreeMap::new();
+    for (input, output) in outputs {
+        if let Some(other) = seen.insert(output.clone(), input) {
+            return Err(anyhow!(
+                "{} and {} would be written to {}, overwriting each other",
+                other.display(),
+                input.display(),
+                output.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Warn when the files kept in `output_dir` were rendered with other settings
+/// than `options`, going by the manifest written with them.
+fn warn_changed_settings(output_dir: &Path, options: &ExportOptions) {
+    let Some(manifest) = fs::read_to_string(output_dir.join(STEM_MANIFEST_FILE))
+        .ok()
+        .and_then(|json| serde_json::from_str::<StemManifest>(&json).ok())
+    else {
+        return;
+    };
+    let changes = manifest.settings.changes(&options.into());
+    if !changes.is_empty() {
+        status!(
+            "Warning: the stems kept were rendered with a different {}; pass --overwrite to render them again",
+            changes.join(", ")
+        );
+    }
+}
+
+/// Fail if one of `files` is already in `output_dir`, unless --overwrite or
+/// --skip-existing said what to do with it.
+fn check_existing(output_dir: &Path, files: &[String], existing: ExistingOutputs) -> Result<()> {
+    if existing != ExistingOutputs::Fail {
+        return Ok(());
+    }
+    match files
+        .iter()
+        .map(|file| output_dir.join(file))
+        .find(|path| path.exists())
+    {
+        Some(path) => Err(anyhow!(
+            "{} already exists; pass --overwrite to replace it, or --skip-existing to keep it",
+            path.display()
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Result standing for a stem kept from an earlier extraction with
+/// --skip-existing, which is not rendered again.
+fn kept_stem(path: PathBuf) -> StemRenderResult {
+    StemRenderResult {
+        path,
+        frames: 0,
+        duration: 0.0,
+        peak: 0.0,
+        start_offset: 0.0,
+        gain_db: 0.0,
+        loudness: None,
+        loop_start: None,
+        skipped_reason: Some("already exists".to_string()),
+        analysis: StemAnalysis::new(&[], 1, 16),
+    }
+}
+
+/// Write the samples stored in the module in `buffer` to WAV files, for
+/// `--mode raw-samples`.
+fn extract_raw_samples(
+    buffer: &[u8],
+    stem_name: &str,
+    output_dir: &Path,
+    staging: StagingDir,
+    existing: ExistingOutputs,
+) -> Result<Vec<StemRenderResult>> {
+    let samples = untracker_core::raw_samples::read_samples(buffer)?;
+    let files: Vec<String> = samples
+        .iter()
+        .map(|sample| format!("{}.wav", sample.file_name(stem_name)))
+        .collect();
+    check_existing(output_dir, &files, existing)?;
+    status!("Extracting {} raw samples", samples.len());
+
+    let mut results = Vec::new();
+    for (sample, file) in samples.iter().zip(&files) {
+        let path = staging.path().join(file);
+        sample.write_wav(&path)?;
+        let frames = sample.frames();
+        results.push(StemRenderResult {
+            path: staging.final_path(&path),
+            frames,
+            duration: frames as f64 / sample.sample_rate as f64,
+            peak: sample
+                .data
+                .iter()
+                .map(|&s| (s as f64 / 32768.0).abs())
+                .fold(0.0, f64::max),
+            start_offset: 0.0,
+            gain_db: 0.0,
+            loudness: None,
+            loop_start: sample.loop_frames.map(|(start, _)| start),
+            skipped_reason: None,
+            analysis: StemAnalysis::new(&sample.data, sample.channels as usize, 16),
+        });
+    }
+    staging.commit()?;
+    Ok(results)
+}
+
+/// What extracting a module produced.
+struct Extraction {
+    /// Metadata of the module, as returned by [`module_metadata`]
+    metadata: BTreeMap<String, String>,
+    stems: Vec<StemRenderResult>,
+}
+
+/// Extract the stems of `input` into `output_dir`, or with --all-subsongs
+/// those of each sub-song into `output_dir/subsong_NN`.
+fn extract_input(
+    input: &Path,
+    output_dir: &Path,
+    options: &ExportOptions,
+    args: &ExtractArgs,
+    log: &LogCapture,
+) -> Result<Extraction> {
+    if !args.all_subsongs {
+        return extract_module(input, output_dir, options, args, log);
+    }
+
+    let buffer = read_file_to_buffer(input)?;
+    let num_subsongs = ModuleExt::from_memory(&buffer, Logger::None, &[])
+        .map_err(|_| anyhow!("Failed to load module"))?
+        .get_module()
+        .get_num_subsongs();
+
+    let mut extraction = Extraction {
+        metadata: BTreeMap::new(),
+        stems: Vec::new(),
+    };
+    for subsong in SubsongIndex::all(num_subsongs) {
+        status!("Subsong {} of {}", subsong, num_subsongs);
+        let options = ExportOptions {
+            subsong: Some(subsong),
+            ..options.clone()
+        };
+        let subsong_dir = output_dir.join(format!("subsong_{:02}", subsong.number()));
+        let subsong_extraction = extract_module(input, &subsong_dir, &options, args, log)?;
+        extraction.metadata = subsong_extraction.metadata;
+        extraction.stems.extend(subsong_extraction.stems);
+    }
+    Ok(extraction)
+}
+
+/// Extract the stems of `input` into `output_dir`, collecting what libopenmpt
+/// logs while loading the module into `log`.
+fn extract_module(
+    input: &Path,
+    output_dir: &Path,
+    options: &ExportOptions,
+    args: &ExtractArgs,
+    log: &LogCapture,
+) -> Result<Extraction> {
+    let options = &if args.remix_kit {
+        remix_kit::kit_options(options)?
+    } else {
+        options.clone()
+    };
+    let labels = args.labels.clone().unwrap_or_default();
+    let template = args.name_template.clone().unwrap_or_default();
+    let started = chrono::Local::now();
+
+    // Stems are written to a staging directory and only moved into the output
+    // directory once all of them have been rendered
+    let existing = args.existing.policy();
+    let staging = if args.existing.resume {
+        StagingDir::resume(output_dir, args.temp_dir.as_deref())?
+    } else {
+        StagingDir::new(output_dir, args.temp_dir.as_deref())?
+    }
+    .keep_existing(existing == ExistingOutputs::Skip);
+    let stage_dir = staging
+        .path()
+        .to_str()
+        .ok_or_else(|| anyhow!("Staging directory path is not valid UTF-8"))?
+        .to_string();
+
+    info!("Loading module file: {}", input.display());
+
+    let buffer = read_file_to_buffer(input)?;
+    let module_ext = load_module(&buffer, Logger::Capture(log.clone()), &options.ctls)?;
+
+    let mut module = module_ext.get_module();
+    // The duration, tempo and notes read below depend on the sub-song
+    if let Some(subsong) = options.subsong {
+        select_subsong(&mut module, subsong)?;
+    }
+    let metadata = module_metadata(&mut module);
+    if let Some(title) = module.title() {
+        match module.artist() {
+            Some(artist) => status!("{} by {}", title, artist),
+            None => status!("{}", title),
+        }
+    }
+    if let Some(description) = ModuleContainer::from_metadata(&metadata).describe() {
+        status!("{}", description);
+    }
+    if let Some(subsong) = options.subsong {
+        match module.get_subsong_name(subsong).trim() {
+            "" => status!("Subsong {}", subsong),
+            name => status!("Subsong {}: {}", subsong, name),
+        }
+    }
+    let num_instruments = module.get_num_instruments();
+    let num_samples = module.get_num_samples();
+    if let Some(automation) = &options.mute_automation {
+        automation.check_channels(module.get_num_channels())?;
+    }
+    if let Some(automation) = &options.mix_automation {
+        automation.check_channels(module.get_num_channels())?;
+    }
+
+    let file_stem = module_stem(input).unwrap_or("stem");
+    let stem_name = &output_base_name(file_stem, &metadata);
+
+    if args.mode == ExtractMode::RawSamples {
+        let stems = extract_raw_samples(&buffer, stem_name, output_dir, staging, existing)?;
+        return Ok(Extraction { metadata, stems });
+    }
+
+    // Without a way to mute parts of the song, the full mix is all that can be rendered
+    if !can_isolate(&module_ext) {
+        status!(
+            "Warning: this build of libopenmpt can't mute instruments, so only the full mix is rendered"
+        );
+        let mut outputs = vec![format!("{}.{}", stem_name, options.format.extension())];
+        if args.write_metadata {
+            outputs.push(STEM_MANIFEST_FILE.to_string());
+        }
+        if args.render_info {
+            outputs.push(RENDER_INFO_FILE.to_string());
+        }
+        check_existing(output_dir, &outputs, existing)?;
+        let pb = progress_bar(1);
+        let cache = args.cache.open()?;
+        let result = render_mix(
+            cache.as_ref(),
+            &buffer,
+            &stage_dir,
+            stem_name,
+            options,
+            &args.tags,
+            if cfg!(test) { None } else { Some(&pb) },
+        )?;
+        if args.write_metadata {
+            let title = module.title().unwrap_or_else(|| stem_name.to_string());
+            let file_name = input
+                .file_name()
+                .map(|f| f.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let manifest = StemManifest::new(
+                &file_name,
+                &metadata,
+                module_ext.get_duration_seconds(),
+                options,
+                vec![ManifestStem::new(&result, "mix", &title, Vec::new())],
+            )
+            .with_meta(&args.meta);
+            let manifest_path = staging.path().join(STEM_MANIFEST_FILE);
+            fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+        }
+        if args.render_info {
+            write_render_info(&staging, input, &buffer, options, args, started)?;
+        }
+        let result = StemRenderResult {
+            path: staging.final_path(&result.path),
+            ..result
+        };
+        staging.commit()?;
+        if !cfg!(test) {
+            pb.finish_and_clear();
+        }
+        status!("Rendered full mix to {}", result.path.display());
+        return Ok(Extraction {
+            metadata,
+            stems: vec![result],
+        });
+    }
+
+    let is_instrument = num_instruments > 0;
+    let stem_count = if is_instrument {
+        num_instruments
+    } else {
+        num_samples
+    };
+    let mut indices: Vec<InstrumentIndex> = match &args.stems {
+        Some(selection) => selection.indices(stem_count)?,
+        None => InstrumentIndex::all(stem_count).collect(),
+    };
+
+    let names = stem_names(&mut module, is_instrument, stem_count);
+    let name = |i: InstrumentIndex| names[i.get() as usize].trim();
+
+    let mut groups = match &args.groups {
+        Some(spec) => {
+            let groups = spec.resolve(&names)?;
+            // Guessed groups can be wrong, so give the user a chance to stop
+            // (--interactive shows them in its own table)
+            if !args.interactive {
+                print_groups(&groups, &names);
+                if spec.is_preset()
+                    && !args.yes
+                    && !args.unattended
+                    && !confirm("Render these groups?", true)?
+                {
+                    return Err(anyhow!("Extraction cancelled"));
+                }
+            }
+            Some(groups)
+        }
+        None => None,
+    };
+
+    if args.interactive {
+        let type_label = if is_instrument {
+            "instrument"
+        } else {
+            "sample"
+        };
+        let describe = |i: InstrumentIndex| match name(i) {
+            "" => format!("{} {}", type_label, i),
+            name => format!("{} {} ({})", type_label, i, name),
+        };
+        let mut plan: Vec<PlanEntry> = match &groups {
+            Some(groups) => groups
+                .iter()
+                .map(|group| {
+                    let members: Vec<String> = group.members.iter().map(|&i| describe(i)).collect();
+                    PlanEntry::new(
+                        group_file_name(stem_name, group, options.format),
+                        members.join(", "),
+                    )
+                })
+                .collect(),
+            None => indices
+                .iter()
+                .map(|&i| {
+                    PlanEntry::new(
+                        stem_file_name(
+                            &template,
+                            stem_name,
+                            is_instrument,
+                            i,
+                            name(i),
+                            options.format,
+                            &labels,
+                        ),
+                        describe(i),
+                    )
+                })
+                .collect(),
+        };
+
+        let header = format!(
+            "Planned stems ({}, {} Hz, {} channel(s), {} bit{}{}):",
+            options.format.name(),
+            options.sample_rate,
+            options.channels,
+            options.bit_depth,
+            if options.sample_format == SampleFormat::Float {
+                " float"
+            } else {
+                ""
+            },
+            options
+                .preview
+                .map(|preview| format!(", first {:.1} seconds", preview))
+                .unwrap_or_default()
+        );
+        if !edit_plan(
+            &mut plan,
+            &header,
+            std::io::stdin().lock(),
+            std::io::stdout(),
+        )? {
+            return Err(anyhow!("Extraction cancelled"));
+        }
+
+        let enabled: Vec<bool> = plan.iter().map(|entry| entry.enabled).collect();
+        match &mut groups {
+            Some(groups) => {
+                let mut enabled = enabled.iter();
+                groups.retain(|_| *enabled.next().unwrap());
+            }
+            None => {
+                let mut enabled = enabled.iter();
+                indices.retain(|_| *enabled.next().unwrap());
+            }
+        }
+    }
+
+    let planned: Vec<String> = match &groups {
+        Some(groups) => groups
+            .iter()
+            .map(|group| group_file_name(stem_name, group, options.format))
+            .collect(),
+        None => indices
+            .iter()
+            .map(|&i| {
+                stem_file_name(
+                    &template,
+                    stem_name,
+                    is_instrument,
+                    i,
+                    name(i),
+                    options.format,
+                    &labels,
+                )
+            })
+            .collect(),
+    };
+    let mut outputs = planned.clone();
+    outputs.extend(extra_output_files(stem_name, options, args));
+    check_existing(output_dir, &outputs, existing)?;
+    // Stems kept from an earlier extraction are not rendered again
+    let kept: Vec<bool> = planned
+        .iter()
+        .map(|file| existing == ExistingOutputs::Skip && output_dir.join(file).exists())
+        .collect();
+    let kept_stems = kept.iter().filter(|&&kept| kept).count();
+
+    let total_stems = planned.len();
+    info!(
+        "Found {} {} to extract",
+        total_stems,
+        if is_instrument {
+            "instruments"
+        } else {
+            "samples"
+        }
+    );
+
+    // Every stem renders the whole song, so modules with a lot of instruments
+    // can keep the machine busy for hours
+    let to_render = total_stems - kept_stems;
+    let mut session = RenderSession::new(&buffer, options)?;
+    let estimate = RenderEstimate::measure(&mut session, to_render)?;
+    status!(
+        "Estimated: {} stems of {}, {}, about {} of rendering",
+        to_render,
+        format_duration(estimate.seconds),
+        format_bytes(estimate.bytes),
+        format_duration(estimate.render_time(args.parallel.jobs()))
+    );
+    if to_render > args.max_stems as usize && !args.yes {
+        let question = format!(
+            "That is more than --max-stems {}. Render them all?",
+            args.max_stems
+        );
+        if args.unattended || !confirm(&question, false)? {
+            return Err(anyhow!(
+                "{} stems is more than --max-stems {}; pass --yes to render them anyway, or pick some with --stems",
+                to_render,
+                args.max_stems
+            ));
+        }
+    }
+
+    // Create progress bar
+    let pb = progress_bar(total_stems as u64);
+
+    // Show initial summary message
+    let type_label = if is_instrument {
+        "instrument"
+    } else {
+        "sample"
+    };
+    let summary = if groups.is_some() {
+        format!("Extracting {} {} groups", total_stems, type_label)
+    } else if total_stems < stem_count as usize {
+        format!(
+            "Extracting {} of {} {} stems",
+            total_stems, stem_count, type_label
+        )
+    } else {
+        format!("Extracting {} {} stems", total_stems, type_label)
+    };
+    status!("{}", summary);
+    if kept_stems > 0 {
+        status!("Keeping {} stems that already exist", kept_stems);
+        warn_changed_settings(output_dir, options);
+    }
+    if let Some(preview) = options.preview {
+        status!("Preview mode: rendering the first {:.1} seconds", preview);
+    }
+
+    if !cfg!(test) {
+        pb.set_message(summary);
+    }
+
+    let cache = args.cache.open()?;
+    let render = |i: InstrumentIndex, options: &ExportOptions, pb: Option<&dyn ProgressSink>| {
+        let render = || {
+            render_stem(
+                &buffer,
+                i,
+                is_instrument,
+                &stage_dir,
+                stem_name,
+                options,
+                &template,
+                &labels,
+                &args.tags,
+                pb,
+            )
+        };
+        let label = cache_label(format!("{} {}", type_label, i), &args.tags);
+        let key = RenderCache::key(&buffer, &label, options);
+        staging.render_once(&key, || match &cache {
+            Some(cache) => {
+                let destination = staging.path().join(stem_file_name(
+                    &template,
+                    stem_name,
+                    is_instrument,
+                    i,
+                    name(i),
+                    options.format,
+                    &labels,
+                ));
+                cache.get_or_render(&key, &destination, render)
+            }
+            None => render(),
+        })
+    };
+
+    let render_grouped =
+        |group: &StemGroup, options: &ExportOptions, pb: Option<&dyn ProgressSink>| {
+            let render = || {
+                render_group(
+                    &buffer,
+                    group,
+                    is_instrument,
+                    &stage_dir,
+                    stem_name,
+                    options,
+                    &args.tags,
+                    pb,
+                )
+            };
+            let members: Vec<i32> = group.members.iter().map(|i| i.get()).collect();
+            let label = cache_label(
+                format!("{} group {} {:?}", type_label, group.name, members),
+                &args.tags,
+            );
+            let key = RenderCache::key(&buffer, &label, options);
+            staging.render_once(&key, || match &cache {
+                Some(cache) => {
+                    let destination =
+                        staging
+                            .path()
+                            .join(group_file_name(stem_name, group, options.format));
+                    cache.get_or_render(&key, &destination, render)
+                }
+                None => render(),
+            })
+        };
+
+    let jobs = args.parallel.jobs();
+    let budget = args.parallel.memory_budget();
+    let stem_memory = render_memory(
+        options,
+        options.render_duration(module_ext.get_duration_seconds()),
+        buffer.len() as u64,
+    );
+    if let Some(budget) = &budget {
+        info!(
+            "Rendering up to {} stems at a time within the memory budget, at {} each",
+            (budget.bytes() / stem_memory).clamp(1, jobs as u64),
+            format_bytes(stem_memory)
+        );
+    }
+    let stems: Vec<usize> = (0..total_stems).collect();
+    let render_nth = |n: usize, options: &ExportOptions, pb: Option<&dyn ProgressSink>| {
+        let _reservation = budget.as_ref().map(|budget| budget.reserve(stem_memory));
+        match &groups {
+            Some(groups) => render_grouped(&groups[n], options, pb),
+            None => render(indices[n], options, pb),
+        }
+    };
+    let (mut results, fallbacks): (Vec<_>, Vec<_>) = if args.single_pass && groups.is_none() {
+        // Stems kept from an earlier extraction stay out of the pass, which
+        // shares the others among the workers and pads them all to its end
+        let to_render: Vec<usize> = stems.iter().copied().filter(|&n| !kept[n]).collect();
+        let pass: Vec<InstrumentIndex> = to_render.iter().map(|&n| indices[n]).collect();
+        pb.set_length(1);
+        let rendered = render_single_pass(
+            &buffer,
+            &pass,
+            is_instrument,
+            &stage_dir,
+            stem_name,
+            options,
+            &template,
+            &labels,
+            &args.tags,
+            jobs,
+            progress(&pb).map(|pb| pb as &dyn ProgressSink),
+        )?;
+        pb.inc(1);
+        let mut rendered_stems: Vec<Option<StemRenderResult>> = vec![None; total_stems];
+        for (&n, result) in to_render.iter().zip(rendered) {
+            rendered_stems[n] = Some(result);
+        }
+        stems
+            .iter()
+            .map(|&n| {
+                let result = rendered_stems[n]
+                    .take()
+                    .unwrap_or_else(|| kept_stem(output_dir.join(&planned[n])));
+                (result, None)
+            })
+            .unzip()
+    } else {
+        parallel::run(&stems, jobs, progress(&pb), |&n, pb| {
+            if kept[n] {
+                return Ok((kept_stem(output_dir.join(&planned[n])), None));
+            }
+            match render_nth(n, options, pb) {
+                Err(e) if args.retry_safe => {
+                    log::warn!(
+                        "Rendering {} again as {}: {}",
+                        planned[n],
+                        ExportOptions::SAFE_FALLBACK,
+                        e
+                    );
+                    let result = render_nth(n, &options.safe_fallback(), pb)?;
+                    Ok((result, Some(e.to_string())))
+                }
+                result => Ok((result?, None)),
+            }
+        })?
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .unzip()
+    };
+    let fallback_count = fallbacks.iter().flatten().count();
+    if fallback_count > 0 {
+        status!(
+            "Rendered {} stems as {} after they failed",
+            fallback_count,
+            ExportOptions::SAFE_FALLBACK
+        );
+    }
+
+    let skip_silent = args
+        .skip_silent
+        .or(args.remix_kit.then_some(REMIX_KIT_SILENCE_DBFS));
+    if let Some(threshold) = skip_silent {
+        // Whether a stem is silent is only known once it has been rendered
+        for result in results
+            .iter_mut()
+            .filter(|r| !r.is_skipped() && r.peak_dbfs() <= threshold)
+        {
+            fs::remove_file(&result.path)?;
+            result.skipped_reason = Some(if result.peak > 0.0 {
+                format!("silent (peak {:.1} dBFS)", result.peak_dbfs())
+            } else {
+                "silent".to_string()
+            });
+        }
+    }
+
+    // Each render stops where libopenmpt detects the end of the song, which
+    // can come a few frames apart between stems
+    if args.verify_alignment {
+        let kept = results.iter().filter(|r| !r.is_skipped()).count();
+        let frames = results
+            .iter()
+            .filter(|r| !r.is_skipped())
+            .map(|r| r.frames)
+            .max()
+            .unwrap_or(0);
+        let short: Vec<usize> = (0..results.len())
+            .filter(|&n| !results[n].is_skipped() && results[n].frames < frames)
+            .collect();
+        if short.is_empty() {
+            status!("Alignment: all {} stems are {} samples long", kept, frames);
+        } else {
+            status!(
+                "Alignment: padding {} of {} stems to {} samples",
+                short.len(),
+                kept,
+                frames
+            );
+            let padded = ExportOptions {
+                pad_to_frames: Some(frames),
+                ..options.clone()
+            };
+            let padded_results =
+                parallel::run(&short, jobs, None, |&n, pb| render_nth(n, &padded, pb))?;
+            for (n, result) in short.into_iter().zip(padded_results) {
+                results[n] = result?;
+            }
+        }
+    }
+
+    if args.verify_sum {
+        let stems: Vec<Vec<InstrumentIndex>> = match &groups {
+            Some(groups) => groups.iter().map(|group| group.members.clone()).collect(),
+            None => indices.iter().map(|&i| vec![i]).collect(),
+        };
+        status!(
+            "Null test: subtracting {} stems from the full mix",
+            stems.len()
+        );
+        let null_pb = progress_bar(stems.len() as u64);
+        let test = null_test(
+            &buffer,
+            &stems,
+            is_instrument,
+            options,
+            jobs,
+            progress(&null_pb),
+        )?;
+        if !cfg!(test) {
+            null_pb.finish_and_clear();
+        }
+        if test.residual_rms == 0.0 {
+            status!("Null test: the stems cancel the mix out exactly");
+        } else {
+            status!(
+                "Null test: residual {:.1} dB below the mix, peaking at {:.1} dBFS",
+                -test.residual_db(),
+                test.residual_peak_dbfs()
+            );
+        }
+
+        let covered: BTreeSet<InstrumentIndex> = stems.into_iter().flatten().collect();
+        if covered.len() < stem_count as usize {
+            status!(
+                "  {} {}s are in no stem, and stay in the residual",
+                stem_count as usize - covered.len(),
+                type_label
+            );
+        } else if test.has_residual() {
+            log::warn!(
+                "The stems don't add up to the full mix: instruments may bleed into each other"
+            );
+        }
+    }
+
+    #[cfg(feature = "decode")]
+    if args.verify_output {
+        let written: Vec<_> = results
+            .iter()
+            .zip(&fallbacks)
+            .filter(|(r, _)| !r.is_skipped())
+            .collect();
+        for (result, fallback) in &written {
+            let options = match fallback {
+                Some(_) => options.safe_fallback(),
+                None => options.clone(),
+            };
+            untracker_core::decode::verify_output(&result.path, &options, result.frames)?;
+        }
+        status!("Verified {} stems by decoding them", written.len());
+    }
+
+    if args.analyze {
+        let entries = indices
+            .iter()
+            .zip(&results)
+            .map(|(&i, result)| StemAnalysisEntry {
+                file: result
+                    .path
+                    .file_name()
+                    .map(|f| f.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                index: i.number(),
+                kind: type_label.to_string(),
+                analysis: result.analysis,
+                flac_savings: result.analysis.flac_savings(),
+            })
+            .collect();
+        let report = AnalysisReport::new(
+            stem_name,
+            options.sample_rate,
+            options.channels,
+            options.bit_depth,
+            entries,
+        );
+
+        let report_path = staging.path().join("analysis.json");
+        fs::write(&report_path, serde_json::to_string_pretty(&report)?)?;
+        status!(
+            "Silence: {:.1}% of rendered audio, estimated FLAC savings over WAV: {:.1}% ({} -> {} bytes)",
+            report.silence_fraction * 100.0,
+            report.flac_savings() * 100.0,
+            report.total_wav_bytes,
+            report.total_estimated_flac_bytes
+        );
+        info!(
+            "Wrote analysis report to {}",
+            staging.final_path(&report_path).display()
+        );
+    }
+
+    if args.remix_kit {
+        let bpm = remix_kit::bpm(module.get_current_tempo2(), module.get_current_speed());
+        // Drums are left out of the key estimate
+        let histogram = remix_kit::note_histogram(&mut module, |instrument| {
+            (instrument as usize)
+                .checked_sub(1)
+                .and_then(|i| names.get(i))
+                .is_none_or(|name| GroupPreset::Standard.classify(name) != Some("drums"))
+        });
+        let key = Key::estimate(&histogram);
+
+        let mut stems = Vec::new();
+        let kept = match &groups {
+            Some(groups) => groups
+                .iter()
+                .map(|g| g.name.clone())
+                .zip(&results)
+                .collect::<Vec<_>>(),
+            None => indices
+                .iter()
+                .map(|&i| name(i).to_string())
+                .zip(&results)
+                .collect(),
+        };
+        for (name, result) in kept.into_iter().filter(|(_, r)| !r.is_skipped()) {
+            let (loop_end, bars) = remix_kit::loop_length(result.frames, options.sample_rate, bpm);
+            let beats = remix_kit::loop_beats(result.duration, bars, bpm);
+            remix_kit::tag_wav(&result.path, options.sample_rate, loop_end, bpm, beats, key)?;
+            stems.push(KitStem {
+                file: result
+                    .path
+                    .file_name()
+                    .map(|f| f.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                name,
+                start_offset: result.start_offset,
+                duration: result.duration,
+                loop_start: 0,
+                loop_end,
+                bars,
+            });
+        }
+
+        let kit = RemixKit {
+            module: input
+                .file_name()
+                .map(|f| f.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            title: module.title(),
+            artist: module.artist(),
+            bpm: (bpm * 100.0).round() / 100.0,
+            key: key.map(|key| key.to_string()),
+            sample_rate: options.sample_rate,
+            rms_target: remix_kit::RMS_TARGET,
+            stems,
+        };
+        let kit_path = staging.path().join(REMIX_KIT_FILE);
+        fs::write(&kit_path, serde_json::to_string_pretty(&kit)?)?;
+        status!(
+            "Remix kit: {:.2} BPM, {}",
+            kit.bpm,
+            kit.key.as_deref().unwrap_or("unknown key")
+        );
+    }
+
+    if args.write_metadata {
+        let colors = args.colors.clone().unwrap_or_default();
+        let stems = match &groups {
+            Some(groups) => groups
+                .iter()
+                .zip(results.iter().zip(&fallbacks))
+                .map(|(group, (result, fallback))| {
+                    let members = group.members.iter().map(|i| i.number()).collect();
+                    ManifestStem::new(result, "group", &group.name, members)
+                        .in_group(&group.name, &colors)
+                        .with_fallback(fallback.as_deref())
+                })
+                .collect(),
+            None => indices
+                .iter()
+                .zip(results.iter().zip(&fallbacks))
+                .map(|(&i, (result, fallback))| {
+                    ManifestStem::new(result, type_label, name(i), vec![i.number()])
+                        .in_group(colors::stem_group(name(i)), &colors)
+                        .with_fallback(fallback.as_deref())
+                })
+                .collect(),
+        };
+        let file_name = input
+            .file_name()
+            .map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let manifest = StemManifest::new(
+            &file_name,
+            &metadata,
+            module_ext.get_duration_seconds(),
+            options,
+            stems,
+        )
+        .with_meta(&args.meta);
+
+        let manifest_path = staging.path().join(STEM_MANIFEST_FILE);
+        fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+        info!(
+            "Wrote metadata to {}",
+            staging.final_path(&manifest_path).display()
+        );
+    }
+
+    let mix_path = output_dir.join(format!("{}.{}", stem_name, options.format.extension()));
+    let full_mix = if args.full_mix && !(existing == ExistingOutputs::Skip && mix_path.exists()) {
+        let result = render_mix(
+            cache.as_ref(),
+            &buffer,
+            &stage_dir,
+            stem_name,
+            options,
+            &args.tags,
+            if cfg!(test) { None } else { Some(&pb) },
+        )?;
+        Some(staging.final_path(&result.path))
+    } else {
+        None
+    };
+
+    let markers = if args.markers.is_some() || args.export_session.is_some() {
+        let mut session = RenderSession::new(&buffer, options)?;
+        Some(track_markers(&mut session, options.time_limit())?)
+    } else {
+        None
+    };
+
+    if let (Some(format), Some(markers)) = (args.markers, &markers) {
+        let markers_path = staging
+            .path()
+            .join(format!("{}{}", stem_name, format.file_suffix()));
+        let mix_file = format!("{}.{}", stem_name, options.format.extension());
+        fs::write(&markers_path, format.write(markers, &mix_file))?;
+        info!(
+            "Wrote {} regions and {} tempo changes to {}",
+            markers.regions.len(),
+            markers.tempo_changes.len(),
+            staging.final_path(&markers_path).display()
+        );
+    }
+
+    if let (Some(format), Some(markers)) = (args.export_session, markers) {
+        let colors = args.colors.clone().unwrap_or_default();
+        let tracks = match &groups {
+            Some(groups) => groups
+                .iter()
+                .map(|group| (group.name.clone(), colors.color(&group.name)))
+                .zip(&results)
+                .collect::<Vec<_>>(),
+            None => indices
+                .iter()
+                .map(|&i| {
+                    let color = colors.color(colors::stem_group(name(i)));
+                    (name(i).to_string(), color)
+                })
+                .zip(&results)
+                .collect(),
+        };
+        let session = DawSession {
+            title: module
+                .title()
+                .filter(|title| !title.trim().is_empty())
+                .unwrap_or_else(|| stem_name.to_string()),
+            artist: module.artist().unwrap_or_default(),
+            dir: std::path::absolute(output_dir)?,
+            format: options.format,
+            sample_rate: options.sample_rate,
+            channels: options.channels,
+            tracks: tracks
+                .into_iter()
+                .filter(|(_, result)| !result.is_skipped())
+                .map(|((name, color), result)| SessionTrack {
+                    name,
+                    file: result
+                        .path
+                        .file_name()
+                        .map(|f| f.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    start_offset: result.start_offset,
+                    frames: result.frames,
+                    color: Some(color),
+                })
+                .collect(),
+            tempo_changes: markers.tempo_changes,
+        };
+        let session_path = staging
+            .path()
+            .join(format!("{}.{}", stem_name, format.extension()));
+        fs::write(&session_path, session.write(format))?;
+        info!(
+            "Wrote a session of {} tracks to {}",
+            session.tracks.len(),
+            staging.final_path(&session_path).display()
+        );
+    }
+
+    if args.render_info {
+        write_render_info(&staging, input, &buffer, options, args, started)?;
+    }
+
+    let results: Vec<_> = results
+        .into_iter()
+        .map(|result| StemRenderResult {
+            path: staging.final_path(&result.path),
+            ..result
+        })
+        .collect();
+    staging.commit()?;
 
-    /// Number of channels (1 or 2)
-    #[arg(long, default_value_t = 2)]
-    channels: u32,
+    if let Some(path) = full_mix {
+        status!("Rendered full mix to {}", path.display());
+    }
 
-    /// Resampling method
-    #[arg(long, default_value = "sinc")]
-    resample: ResampleMethodArg,
+    for result in results.iter().filter(|r| r.is_skipped()) {
+        status!(
+            "  Skipped {}: {}",
+            result.path.display(),
+            result.skipped_reason.as_deref().unwrap_or_default()
+        );
+    }
+    for result in results.iter().filter(|r| r.start_offset > 0.0) {
+        status!(
+            "  {} starts {:.3}s into the song",
+            result.path.display(),
+            result.start_offset
+        );
+    }
+    for result in results.iter().filter(|r| r.peak >= 1.0) {
+        log::warn!(
+            "{} reaches full scale and may be clipped",
+            result.path.display()
+        );
+    }
 
-    /// Output format: wav, vorbis, opus, flac
-    #[arg(long, default_value = "wav")]
-    format: String,
+    let written = total_stems - results.iter().filter(|r| r.is_skipped()).count();
+    if written + kept_stems < total_stems {
+        status!(
+            "Skipped {} silent stems",
+            total_stems - written - kept_stems
+        );
+    }
+    if !cfg!(test) {
+        pb.finish_with_message(format!("Completed extracting {} stems!", written));
+    }
+    status!("Completed extracting {} stems!", written);
+    Ok(Extraction {
+        metadata,
+        stems: results,
+    })
+}
 
-    /// Bit depth for lossless formats (16 or 24)
-    #[arg(long, default_value_t = 16)]
-    bit_depth: u32,
+/// Write the `render-info.json` of the extraction of `input` that started at
+/// `started`, once everything else is in the staging directory.
+fn write_render_info(
+    staging: &StagingDir,
+    input: &Path,
+    buffer: &[u8],
+    options: &ExportOptions,
+    args: &ExtractArgs,
+    started: chrono::DateTime<chrono::Local>,
+) -> Result<()> {
+    let overrides = Some(ModuleOverrides::sidecar_path(input))
+        .filter(|path| !args.no_overrides && path.is_file());
+    let info = RenderInfo::new(
+        InputFile::new(
+            &input.canonicalize().unwrap_or_else(|_| input.to_path_buf()),
+            buffer,
+        ),
+        overrides,
+        options,
+        &args.tags,
+        RenderTimings::since(started),
+    );
+    let path = info.write(staging.path())?;
+    info!(
+        "Wrote render info to {}",
+        staging.final_path(&path).display()
+    );
+    Ok(())
+}
 
-    /// Bitrate for Opus format in kbps
-    #[arg(long, default_value_t = 128)]
-    opus_bitrate: u32,
+/// Print which instruments or samples ended up in which group.
+fn print_groups(groups: &[StemGroup], names: &[String]) {
+    status!("Stem groups:");
+    for group in groups {
+        let members: Vec<String> = group
+            .members
+            .iter()
+            .map(|&i| match names[i.get() as usize].trim() {
+                "" => i.to_string(),
+                name => format!("{} ({})", i, name),
+            })
+            .collect();
+        status!("  {:<8} {}", group.name, members.join(", "));
+    }
+}
 
-    /// Vorbis quality level (0-10)
-    #[arg(long, default_value_t = 5)]
-    vorbis_quality: u32,
+/// Ask a yes/no question on the terminal.
+///
+/// An empty answer, or having no terminal to ask on, gives `default`.
+fn confirm(question: &str, default: bool) -> Result<bool> {
+    use std::io::IsTerminal;
 
-    /// Stereo separation in percent (0-200)
-    #[arg(long, default_value_t = 100)]
-    stereo_separation: u32,
+    if !std::io::stdin().is_terminal() {
+        return Ok(default);
+    }
 
-    /// Render stems in parallel
-    #[arg(short, long)]
-    parallel: bool,
+    print!("{} {} ", question, if default { "[Y/n]" } else { "[y/N]" });
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(match answer.trim().to_lowercase().as_str() {
+        "" => default,
+        answer => matches!(answer, "y" | "yes"),
+    })
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-enum ResampleMethodArg {
-    Nearest,
-    Linear,
-    Cubic,
-    Sinc,
+/// Label of a render in the render cache: `--tag` changes the file, but not
+/// the render options the key is also made of.
+fn cache_label(label: String, tags: &[Tag]) -> String {
+    if tags.is_empty() {
+        label
+    } else {
+        format!("{} {:?}", label, tags)
+    }
 }
 
-impl From<ResampleMethodArg> for ResampleMethod {
-    fn from(arg: ResampleMethodArg) -> Self {
-        match arg {
-            ResampleMethodArg::Nearest => ResampleMethod::Nearest,
-            ResampleMethodArg::Linear => ResampleMethod::Linear,
-            ResampleMethodArg::Cubic => ResampleMethod::Cubic,
-            ResampleMethodArg::Sinc => ResampleMethod::Sinc,
+/// Render the full mix of a module, through the render cache if there is one.
+fn render_mix(
+    cache: Option<&RenderCache>,
+    buffer: &[u8],
+    output_dir: &str,
+    name: &str,
+    options: &ExportOptions,
+    tags: &[Tag],
+    pb: Option<&dyn ProgressSink>,
+) -> Result<StemRenderResult> {
+    let render = || render_full_mix(buffer, output_dir, name, options, tags, pb);
+    match cache {
+        Some(cache) => {
+            let key = RenderCache::key(buffer, &cache_label("mix".to_string(), tags), options);
+            let destination = Path::new(output_dir).join(mix_file_name(name, options.format));
+            cache.get_or_render(&key, &destination, render)
         }
+        None => render(),
     }
 }
 
-fn main() -> Result<()> {
-    // Initialize logging with a nice format, but only in non-test mode
-    if !cfg!(test) {
-        env_logger::Builder::from_default_env()
-            .format(|buf, record| {
-                writeln!(
-                    buf,
-                    "{} [{}] - {}",
-                    chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
-                    record.level(),
-                    record.args()
-                )
+fn mix(args: MixArgs) -> Result<()> {
+    let mut base_options = args.audio.to_options()?;
+
+    let inputs = expand_inputs(&args.input, args.recursive)?;
+    let mix_dir = |input: &Path| Path::new(&args.output_dir).join(input_subdir(input, &args.input));
+    check_distinct_outputs(inputs.iter().map(|input| {
+        let mix = mix_dir(input).join(module_stem(input).unwrap_or("mix"));
+        (input.as_path(), mix)
+    }))?;
+    fs::create_dir_all(&args.output_dir)?;
+
+    println!("Rendering {} mixes", inputs.len());
+    if let Some(preview) = base_options.preview {
+        println!("Preview mode: rendering the first {:.1} seconds", preview);
+    }
+
+    // Album gain needs the loudness of every track before any of them is written
+    let album = if args.album {
+        println!("Measuring loudness of {} modules", inputs.len());
+        let pb = progress_bar(inputs.len() as u64);
+        let meters = parallel::run_fallible(
+            &inputs,
+            args.parallel.jobs(),
+            progress(&pb),
+            args.parallel.fail_fast,
+            |input, pb| {
+                let options = options_for(input, &base_options, &args.audio, args.no_overrides)?;
+                measure_full_mix(&read_file_to_buffer(input)?, &options, pb)
+            },
+        )?;
+        if !cfg!(test) {
+            pb.finish_and_clear();
+        }
+        if args.parallel.fail_fast {
+            if let Some((input, Some(Err(e)))) = inputs
+                .iter()
+                .zip(&meters)
+                .find(|(_, meter)| matches!(meter, Some(Err(_))))
+            {
+                return Err(anyhow!(
+                    "Stopped at the first module that failed to measure ({}): {}",
+                    input.display(),
+                    e
+                ));
+            }
+        }
+
+        let mut album = LoudnessMeter::default();
+        for meter in meters.iter().flatten().flatten() {
+            album.merge(meter);
+        }
+        let album = album.loudness();
+        let (gain_db, peak_limited) =
+            album_gain(&album, args.album_target, ALBUM_TRUE_PEAK_CEILING_DBTP);
+        base_options.gain_db = gain_db;
+        match album.integrated_lufs {
+            Some(lufs) => println!(
+                "Album loudness: {:.1} LUFS, applying {:.2} dB of gain{}",
+                lufs,
+                gain_db,
+                if peak_limited {
+                    " (limited by true peak)"
+                } else {
+                    ""
+                }
+            ),
+            None => println!("Album is silent, no gain applied"),
+        }
+        Some((meters, album, peak_limited))
+    } else {
+        None
+    };
+
+    let pb = progress_bar(inputs.len() as u64);
+    let cache = args.cache.open()?;
+    let budget = args.parallel.memory_budget();
+    // A broken module shouldn't stop the rest of the collection from rendering
+    let results = parallel::run_fallible(
+        &inputs,
+        args.parallel.jobs(),
+        progress(&pb),
+        args.parallel.fail_fast,
+        |input, pb| {
+            let options = options_for(input, &base_options, &args.audio, args.no_overrides)?;
+            let buffer = read_file_to_buffer(input)?;
+            let name = module_stem(input).unwrap_or("mix");
+            let output_dir = mix_dir(input);
+            fs::create_dir_all(&output_dir)?;
+            let output_dir = output_dir
+                .to_str()
+                .ok_or_else(|| anyhow!("Output directory path is not valid UTF-8"))?;
+            let _reservation = match &budget {
+                Some(budget) => {
+                    let duration =
+                        load_module(&buffer, Logger::None, &options.ctls)?.get_duration_seconds();
+                    let bytes = render_memory(
+                        &options,
+                        options.render_duration(duration),
+                        buffer.len() as u64,
+                    );
+                    Some(budget.reserve(bytes))
+                }
+                None => None,
+            };
+            render_mix(
+                cache.as_ref(),
+                &buffer,
+                output_dir,
+                name,
+                &options,
+                &args.tags,
+                pb,
+            )
+        },
+    )?;
+
+    let mut failed = 0;
+    let mut not_rendered = 0;
+    for (input, result) in inputs.iter().zip(&results) {
+        match result {
+            Some(Ok(result)) if result.peak >= 1.0 => {
+                log::warn!(
+                    "{} reaches full scale and may be clipped",
+                    result.path.display()
+                );
+            }
+            Some(Ok(_)) => {}
+            Some(Err(e)) => {
+                failed += 1;
+                println!("  Failed {}: {}", input.display(), e);
+            }
+            None => not_rendered += 1,
+        }
+    }
+
+    if let Some((meters, album, peak_limited)) = album {
+        let tracks = inputs
+            .iter()
+            .zip(meters.iter().zip(&results))
+            .filter_map(|(input, track)| match track {
+                (Some(Ok(meter)), Some(Ok(result))) => {
+                    let measured = meter.loudness();
+                    Some(AlbumTrack {
+                        module: input.display().to_string(),
+                        path: result.path.clone(),
+                        measured,
+                        output: measured.with_gain(base_options.gain_db),
+                    })
+                }
+                _ => None,
             })
-            .init();
+            .collect();
+        let report = AlbumReport {
+            target_lufs: args.album_target,
+            true_peak_ceiling_dbtp: ALBUM_TRUE_PEAK_CEILING_DBTP,
+            album,
+            album_gain_db: base_options.gain_db,
+            peak_limited,
+            tracks,
+        };
+        let report_path = Path::new(&args.output_dir).join(ALBUM_REPORT_FILE);
+        fs::write(&report_path, serde_json::to_string_pretty(&report)?)?;
+        info!("Wrote album report to {}", report_path.display());
     }
 
-    let args = Args::parse();
-    let format: AudioFormat = args.format.parse()?;
+    let rendered = inputs.len() - failed - not_rendered;
+    if !cfg!(test) {
+        pb.finish_with_message(format!("Completed rendering {} mixes!", rendered));
+    }
+    println!("Completed rendering {} mixes!", rendered);
 
-    // Input validation
-    if args.channels != 1 && args.channels != 2 {
+    if not_rendered > 0 {
         return Err(anyhow!(
-            "Only 1 (mono) or 2 (stereo) channels are supported"
+            "Stopped at the first module that failed to render, leaving {} of {} modules unrendered",
+            not_rendered,
+            inputs.len()
         ));
     }
-
-    if args.bit_depth != 16 && args.bit_depth != 24 {
-        return Err(anyhow!("Only 16 or 24 bit depth is supported"));
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} of {} modules failed to render",
+            failed,
+            inputs.len()
+        ));
     }
+    Ok(())
+}
 
-    if args.sample_rate < 8000 || args.sample_rate > 192000 {
+fn watch(mut args: WatchArgs) -> Result<()> {
+    if args.extract.interactive {
         return Err(anyhow!(
-            "Sample rate must be between 8000 and 192000 Hz (got {})",
-            args.sample_rate
+            "--interactive can't be used when watching a folder"
         ));
     }
+    if args.extract.dry_run.is_some() {
+        return Err(anyhow!("--dry-run can't be used when watching a folder"));
+    }
+    // Nobody is around to confirm guessed groups or huge modules
+    args.extract.unattended = true;
+    // A module dropped in again replaces its stems
+    if args.extract.existing.policy() == ExistingOutputs::Fail {
+        args.extract.existing.overwrite = true;
+    }
+    if let Some(module) = args.module.take() {
+        return watch_module(&module, &args);
+    }
+    let Some(drop_dir) = args.input.take() else {
+        return Err(anyhow!("Give a module or a drop folder (-i) to watch"));
+    };
+    let base_options = args.audio.to_options()?;
+    // Fail on bad global options now rather than on every module
+    base_options.validate()?;
 
-    if args.stereo_separation > 200 {
-        return Err(anyhow!(
-            "Stereo separation must be between 0 and 200 percent (got {})",
-            args.stereo_separation
+    let mut folder = DropFolder::new(&drop_dir, args.done_dir.clone(), args.failed_dir.clone())?;
+    fs::create_dir_all(&args.output_dir)?;
+    let manifest_path = args.output_dir.join(MANIFEST_FILE);
+
+    if !args.once {
+        println!("Watching {} for new modules", drop_dir.display());
+    }
+
+    let mut failed = 0;
+    loop {
+        for input in folder.poll(!args.once)? {
+            println!("Processing {}", input.display());
+            let output_dir = module_output_dir(&args.output_dir, &input, &[]);
+            let log = LogCapture::new();
+            let mut metadata = BTreeMap::new();
+
+            // Sidecar overrides are read from the drop folder, before the module is moved away
+            let result = options_for(
+                &input,
+                &base_options,
+                &args.audio,
+                args.extract.no_overrides,
+            )
+            .and_then(|options| extract_input(&input, &output_dir, &options, &args.extract, &log))
+            .map(|extraction| {
+                metadata = extraction.metadata;
+                extraction.stems.iter().filter(|r| !r.is_skipped()).count()
+            });
+            if let Err(e) = &result {
+                failed += 1;
+                println!("  Failed {}: {}", input.display(), e);
+            }
+
+            // One module that can't be moved doesn't stop the service
+            match folder.finish(&input, result.is_ok()) {
+                Ok(destination) => {
+                    info!("Moved {} to {}", input.display(), destination.display())
+                }
+                Err(e) => log::error!("{:#}", e),
+            }
+            ManifestEntry::new(&input, &output_dir, &result, log.take())
+                .with_metadata(metadata)
+                .append_to(&manifest_path)?;
+            if result.is_err() && args.extract.parallel.fail_fast {
+                return Err(anyhow!(
+                    "Stopped watching at the first module that failed to extract ({})",
+                    input.display()
+                ));
+            }
+        }
+
+        if args.once {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_secs_f64(
+            args.interval.unwrap_or(5.0),
         ));
     }
 
-    let options = ExportOptions {
-        format,
-        sample_rate: args.sample_rate,
-        channels: args.channels,
-        bit_depth: args.bit_depth,
-        opus_bitrate: args.opus_bitrate,
-        vorbis_quality: args.vorbis_quality,
-        resample: args.resample.into(),
-        stereo_separation: args.stereo_separation as i32,
+    if failed > 0 {
+        return Err(anyhow!("{} modules failed to extract", failed));
+    }
+    Ok(())
+}
+
+/// Extract the stems of `module` into the output directory, then again every
+/// time it is saved until interrupted, replacing only the stems that changed.
+fn watch_module(module: &Path, args: &WatchArgs) -> Result<()> {
+    let watcher = ModuleWatcher::new(module)?;
+    let mut options = options_for(
+        module,
+        &args.audio.to_options()?,
+        &args.audio,
+        args.extract.no_overrides,
+    )?;
+    // Dither noise would make every stem differ from the last extraction, so
+    // the stems are reproducible whatever the command line asked for
+    if !options.reproducible {
+        status!("Rendering reproducibly, so that only the stems that change are replaced");
+        options.reproducible = true;
+    }
+    options.validate()?;
+
+    let extract = || -> Result<()> {
+        // Extracted aside first, so that the stems can be compared with those on disk
+        let staging = StagingDir::new(&args.output_dir, args.extract.temp_dir.as_deref())?;
+        let extraction = extract_input(
+            module,
+            staging.path(),
+            &options,
+            &args.extract,
+            &LogCapture::new(),
+        )?;
+        let changed = replace_changed(staging.path(), &args.output_dir)?;
+        let stems: Vec<PathBuf> = extraction
+            .stems
+            .iter()
+            .filter(|stem| !stem.is_skipped())
+            .map(|stem| staging.final_path(&stem.path))
+            .collect();
+        let changed_stems: Vec<&PathBuf> =
+            stems.iter().filter(|stem| changed.contains(stem)).collect();
+        println!(
+            "{} of {} stems changed{}",
+            changed_stems.len(),
+            stems.len(),
+            if changed_stems.is_empty() { "" } else { ":" }
+        );
+        for stem in changed_stems {
+            println!("  {}", stem.display());
+        }
+        Ok(())
     };
 
-    fs::create_dir_all(&args.output_dir)?;
+    extract()?;
+    if args.once {
+        return Ok(());
+    }
+    println!(
+        "Watching {} for changes, press Ctrl+C to stop",
+        module.display()
+    );
+    loop {
+        watcher.wait_for_save()?;
+        println!("{} saved, extracting", module.display());
+        // A save the tracker botched shouldn't stop the session
+        if let Err(e) = extract() {
+            println!("  Failed {}: {}", module.display(), e);
+        }
+    }
+}
 
-    info!("Loading module file: {}", args.input);
+fn manifest(command: ManifestCommand) -> Result<()> {
+    let load = |manifests: &[PathBuf]| -> Result<Vec<ModuleRecord>> {
+        let mut records = Vec::new();
+        for path in manifests {
+            records.extend(archive::load(path)?);
+        }
+        Ok(archive::merge(records))
+    };
 
-    let buffer = read_file_to_buffer(&args.input)?;
-    let module_ext = ModuleExt::from_memory(&buffer, Logger::None, &[])
-        .map_err(|_| anyhow!("Failed to load module"))?;
+    match command {
+        ManifestCommand::Merge { manifests, output } => {
+            let records = load(&manifests)?;
+            archive::write(&records, &output)?;
+            println!(
+                "Merged {} modules from {} manifests into {}",
+                records.len(),
+                manifests.len(),
+                output.display()
+            );
+        }
+        ManifestCommand::Query {
+            query,
+            manifests,
+            json,
+        } => {
+            let records = load(&manifests)?;
+            match query {
+                ManifestQuery::Clipping => {
+                    let stems = archive::clipping_stems(&records);
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&stems)?);
+                    } else {
+                        for stem in &stems {
+                            println!(
+                                "{}: {} ({:+.1} dBFS)",
+                                stem.module, stem.file, stem.peak_dbfs
+                            );
+                        }
+                    }
+                }
+                ManifestQuery::SizeByFormat => {
+                    let totals = archive::size_by_format(&records);
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&totals)?);
+                    } else {
+                        for total in &totals {
+                            println!(
+                                "{}: {} files, {} bytes",
+                                total.format, total.files, total.bytes
+                            );
+                        }
+                    }
+                }
+                ManifestQuery::Failed => {
+                    let failed = archive::failed_modules(&records);
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&failed)?);
+                    } else {
+                        for module in &failed {
+                            println!("{}: {}", module.module, module.error);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
 
-    let mut module = module_ext.get_module();
-    let num_instruments = module.get_num_instruments();
-    let num_samples = module.get_num_samples();
+/// Score modules by how well they will split into stems, for `untracker rank`.
+fn rank(args: RankArgs) -> Result<()> {
+    let inputs = expand_inputs(&args.input, args.recursive)?;
+    let pb = progress_bar(inputs.len() as u64);
+    let surveys = parallel::run_fallible(
+        &inputs,
+        args.parallel.jobs(),
+        progress(&pb),
+        args.parallel.fail_fast,
+        |input, _| {
+            let module_ext = load_module(&read_file_to_buffer(input)?, Logger::None, &[])?;
+            Ok(StemWorthiness::survey(input, &mut module_ext.get_module()))
+        },
+    )?;
+    if !cfg!(test) {
+        pb.finish_and_clear();
+    }
 
-    let stem_name = Path::new(&args.input)
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("stem");
+    let mut ranking = Vec::new();
+    for (input, survey) in inputs.iter().zip(surveys) {
+        match survey {
+            Some(Ok(survey)) => ranking.push(survey),
+            Some(Err(e)) if args.parallel.fail_fast => {
+                return Err(anyhow!(
+                    "Stopped at the first module that failed to load ({}): {}",
+                    input.display(),
+                    e
+                ))
+            }
+            Some(Err(e)) => log::warn!("Skipping {}: {}", input.display(), e),
+            None => {}
+        }
+    }
+    untracker_core::rank::rank(&mut ranking);
+    ranking.truncate(args.top.unwrap_or(ranking.len()));
 
-    let indices: Vec<i32> = (0..(if num_instruments > 0 {
-        num_instruments
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&ranking)?);
     } else {
-        num_samples
-    }))
-        .collect();
-    let is_instrument = num_instruments > 0;
+        for survey in &ranking {
+            println!("{}", survey.to_text());
+        }
+    }
+    Ok(())
+}
 
-    let total_stems = indices.len();
-    info!(
-        "Found {} {} to extract",
-        total_stems,
-        if is_instrument {
-            "instruments"
-        } else {
-            "samples"
+/// Report on a file, for `untracker probe`. Fails for files that aren't
+/// supported modules, after reporting why.
+fn probe(args: ProbeArgs) -> Result<()> {
+    let data = read_file_to_buffer(&args.file)?;
+    let probe = ModuleProbe::probe(&args.file, &data);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&probe)?);
+    } else {
+        print!("{}", probe.to_text());
+    }
+    if !probe.supported {
+        return Err(anyhow!("{} is not a supported module", args.file.display()));
+    }
+    Ok(())
+}
+
+/// Join stem sets, for `untracker concat`.
+fn concat(args: ConcatArgs) -> Result<()> {
+    use untracker_core::concat::{self, Join, StemSet};
+
+    let sets = args
+        .sets
+        .iter()
+        .map(|dir| StemSet::open(dir))
+        .collect::<Result<Vec<_>>>()?;
+    let options = concat::output_options(&sets)?;
+    let join = match (args.gap, args.crossfade) {
+        (_, Some(seconds)) => Join::Crossfade(seconds),
+        (Some(seconds), None) => Join::Gap(seconds),
+        (None, None) => Join::default(),
+    };
+
+    let names = concat::stem_names(&sets);
+    let files: Vec<PathBuf> = names
+        .iter()
+        .map(|name| {
+            args.output_dir
+                .join(concat::concat_file_name(&args.name, name))
+        })
+        .collect();
+    if !args.overwrite {
+        if let Some(existing) = files.iter().find(|file| file.exists()) {
+            return Err(anyhow!(
+                "{} already exists; use --overwrite to replace it",
+                existing.display()
+            ));
         }
-    );
+    }
+    fs::create_dir_all(&args.output_dir)?;
 
-    // Create progress bar
-    let pb = ProgressBar::new(total_stems as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) - {msg}")
-            .unwrap()
-            .progress_chars("#>-"),
+    for (set, start) in sets
+        .iter()
+        .zip(concat::part_starts(&sets, join, options.sample_rate))
+    {
+        println!(
+            "{:10.3} s  {}",
+            start as f64 / options.sample_rate as f64,
+            set.dir.display()
+        );
+    }
+    let pb = progress_bar(names.len() as u64);
+    for (name, file) in names.iter().zip(&files) {
+        let samples = concat::concat_stem(&sets, name, join, &options)?;
+        let mut writer = AudioWriter::create(file, &options.encoder())?;
+        writer.write_samples(&samples)?;
+        writer.finish()?;
+        pb.inc(1);
+    }
+    if !cfg!(test) {
+        pb.finish_and_clear();
+    }
+    println!(
+        "Joined {} stems from {} sets into {}",
+        names.len(),
+        sets.len(),
+        args.output_dir.display()
     );
+    Ok(())
+}
 
-    // Show initial summary message
-    if is_instrument {
-        println!("Extracting {} instrument stems", num_instruments);
+/// Compare two audio files, for `untracker diff`.
+#[cfg(feature = "decode")]
+fn diff(args: DiffArgs) -> Result<()> {
+    use untracker_core::decode::{AudioDiff, DecodedAudio};
+
+    let audio = DecodedAudio::read(&args.file)?;
+    let reference = DecodedAudio::read(&args.reference)?;
+    let diff = AudioDiff::between(&audio, &reference)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else if diff.is_identical() {
+        println!("The files are identical");
     } else {
-        println!("Extracting {} sample stems", num_samples);
+        if diff.frames != diff.reference_frames {
+            println!(
+                "Length: {} frames, against {} in the reference",
+                diff.frames, diff.reference_frames
+            );
+        }
+        println!(
+            "Difference: {:.1} dB below the reference, peaking at {:.1} dBFS",
+            -diff.difference_db(),
+            diff.difference_peak_dbfs()
+        );
     }
 
-    if !cfg!(test) {
-        if is_instrument {
-            pb.set_message(format!("Extracting {} instrument stems", num_instruments));
+    match args.max_difference {
+        Some(max) if diff.difference_db() > max => Err(anyhow!(
+            "{} differs from {} by {:.1} dB, more than the {:.1} dB allowed",
+            args.file.display(),
+            args.reference.display(),
+            diff.difference_db(),
+            max
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Play a module until it ends or the user quits, for `untracker play`.
+#[cfg(feature = "playback")]
+fn play(args: PlayArgs) -> Result<()> {
+    use std::io::BufRead;
+    use std::sync::mpsc;
+    use untracker_core::playback::{AudioOutput, ChannelMutes, PlayerAction};
+
+    let buffer = read_file_to_buffer(&args.file)?;
+    let module_ext = load_module(&buffer, Logger::None, &[])?;
+    let mut module = module_ext.get_module();
+    let title = module.get_metadata("title").unwrap_or_default();
+    println!(
+        "Playing {}, {}",
+        if title.trim().is_empty() {
+            args.file.display().to_string()
+        } else {
+            title
+        },
+        format_duration(module.get_duration_seconds())
+    );
+
+    if let Some(solo) = &args.solo {
+        let num_instruments = module.get_num_instruments();
+        let is_instrument = num_instruments > 0;
+        let count = if is_instrument {
+            num_instruments
         } else {
-            pb.set_message(format!("Extracting {} sample stems", num_samples));
+            module.get_num_samples()
+        };
+        let unmuted = solo.indices(count)?;
+        let interactive = module_ext
+            .get_interactive_interface()
+            .ok_or_else(|| anyhow!("This libopenmpt can't mute instruments"))?;
+        for i in InstrumentIndex::all(count) {
+            interactive.set_instrument_mute_status(&module_ext, i, !unmuted.contains(&i));
+        }
+        for i in unmuted {
+            let name = if is_instrument {
+                module.get_instrument_name(i)
+            } else {
+                module.get_sample_name(SampleIndex::new(i.get()))
+            };
+            println!(
+                "  Solo {} {}: {}",
+                if is_instrument {
+                    "instrument"
+                } else {
+                    "sample"
+                },
+                i.number(),
+                name
+            );
         }
     }
 
-    if args.parallel {
-        use rayon::prelude::*;
+    let mut mutes = ChannelMutes::new(module.get_num_channels());
+    println!("{}", mutes.to_text());
+    println!(
+        "Type channels to mute (e.g. 2,5-7), s and channels to solo, a = all, p = pause, q = quit"
+    );
 
-        if cfg!(test) {
-            // For tests, run without progress bar
-            indices.into_par_iter().try_for_each(|i| {
-                render_stem(
-                    &buffer,
-                    i,
-                    is_instrument,
-                    &args.output_dir,
-                    stem_name,
-                    &options,
-                    None,
-                )
-            })?;
-        } else {
-            use indicatif::ParallelProgressIterator;
-            // For normal execution, use progress bar
-            indices
-                .into_par_iter()
-                .progress_with(pb.clone())
-                .try_for_each(|i| {
-                    render_stem(
-                        &buffer,
-                        i,
-                        is_instrument,
-                        &args.output_dir,
-                        stem_name,
-                        &options,
-                        Some(&pb),
-                    )
-                })?;
+    // Commands are read on a thread of their own, so playing never waits for the keyboard
+    let (sender, commands) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if sender.send(line).is_err() {
+                break;
+            }
         }
-    } else {
-        for i in indices {
-            render_stem(
-                &buffer,
-                i,
-                is_instrument,
-                &args.output_dir,
-                stem_name,
-                &options,
-                if cfg!(test) { None } else { Some(&pb) },
-            )?;
-            if !cfg!(test) {
-                pb.inc(1);
+    });
+
+    let output = AudioOutput::open()?;
+    let sample_rate = output.sample_rate();
+    // About a fifth of a second queued, so that mutes are heard quickly
+    let queued = sample_rate as usize / 5;
+    let mut chunk = vec![0.0f32; 2 * 1024];
+    let mut paused = false;
+    loop {
+        if let Ok(line) = commands.try_recv() {
+            match mutes.command(&line) {
+                Ok(PlayerAction::Quit) => return Ok(()),
+                Ok(PlayerAction::TogglePause) => {
+                    paused = !paused;
+                    output.set_paused(paused)?;
+                }
+                Ok(PlayerAction::Continue) => {
+                    mutes.apply(&module_ext)?;
+                    println!("{}", mutes.to_text());
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        if paused || output.queued_frames() >= queued {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            continue;
+        }
+        let frames = module_ext.read_interleaved_float_stereo(sample_rate as i32, &mut chunk);
+        if frames == 0 {
+            break;
+        }
+        output.push(&chunk[..2 * frames]);
+    }
+
+    // Let the end of the song play out
+    while output.queued_frames() > 0 {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+/// Browse the stems of a module and render the ones picked, for `untracker tui`.
+#[cfg(feature = "tui")]
+fn tui(args: TuiArgs) -> Result<()> {
+    use untracker_core::browser::StemBrowser;
+    use untracker_core::tui::TuiExport;
+
+    let options = options_for(
+        &args.file,
+        &args.audio.to_options()?,
+        &args.audio,
+        args.no_overrides,
+    )?;
+    options.validate()?;
+    let buffer = read_file_to_buffer(&args.file)?;
+    let module_ext = load_module(&buffer, Logger::None, &options.ctls)?;
+    let mut module = module_ext.get_module();
+    let browser = StemBrowser::survey(&mut module);
+    let base_name = output_base_name(
+        module_stem(&args.file).unwrap_or("stem"),
+        &module_metadata(&mut module),
+    );
+    fs::create_dir_all(&args.output_dir)?;
+
+    let written = untracker_core::tui::run(
+        buffer,
+        browser,
+        TuiExport {
+            output_dir: args.output_dir.clone(),
+            base_name,
+            options,
+            template: args.name_template.unwrap_or_default(),
+            labels: args.labels.unwrap_or_default(),
+            tags: args.tags,
+        },
+    )?;
+    for path in &written {
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "serve")]
+fn serve(args: ServeArgs) -> Result<()> {
+    use untracker_core::service::StemService;
+
+    let options = args.audio.to_options()?;
+    options.validate()?;
+    let max_renders = args
+        .max_renders
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    let scratch_dir = std::env::temp_dir().join(format!("untracker-serve-{}", std::process::id()));
+    let service = StemService::new(options, max_renders, &scratch_dir, args.cache.open()?)?;
+    untracker_core::serve::run(&format!("{}:{}", args.bind, args.port), service)
+}
+
+/// Expand input arguments into module paths. Directories are scanned for
+/// files with an extension libopenmpt supports (including their
+/// subdirectories if `recursive`), arguments containing glob characters are
+/// matched against the filesystem, and others are used as is.
+fn expand_inputs(patterns: &[String], recursive: bool) -> Result<Vec<PathBuf>> {
+    let mut inputs = Vec::new();
+    for pattern in patterns {
+        let path = Path::new(pattern);
+        if path.is_dir() {
+            let count = inputs.len();
+            find_modules(path, recursive, &mut inputs)?;
+            if inputs.len() == count {
+                return Err(anyhow!("No modules found in {}", pattern));
             }
+            continue;
+        }
+        if !pattern.contains(['*', '?', '[']) {
+            inputs.push(PathBuf::from(pattern));
+            continue;
+        }
+
+        let matches = glob::glob(pattern)
+            .map_err(|e| anyhow!("Invalid input pattern {}: {}", pattern, e))?
+            .filter_map(|entry| entry.ok())
+            .filter(|path| path.is_file() && !is_sidecar(path))
+            .collect::<Vec<_>>();
+        if matches.is_empty() {
+            return Err(anyhow!("No files match {}", pattern));
         }
+        inputs.extend(matches);
     }
+    inputs.sort();
+    inputs.dedup();
+    Ok(inputs)
+}
 
-    if !cfg!(test) {
-        pb.finish_with_message(format!("Completed extracting {} stems!", total_stems));
+/// Add the module files in `dir` to `modules`, descending into subdirectories if `recursive`.
+fn find_modules(dir: &Path, recursive: bool, modules: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                find_modules(&path, recursive, modules)?;
+            }
+        } else if path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(openmpt::info::is_extension_supported)
+            || ArchiveFormat::from_path(&path).is_some_and(ArchiveFormat::is_supported)
+        {
+            modules.push(path);
+        }
     }
-    println!("Completed extracting {} stems!", total_stems);
     Ok(())
 }
 
-fn read_file_to_buffer(path: &str) -> Result<Vec<u8>> {
-    log::info!("Reading input file: {}", path);
+fn is_sidecar(path: &Path) -> bool {
+    path.to_string_lossy()
+        .ends_with(untracker_core::config::SIDECAR_SUFFIX)
+}
+
+fn read_file_to_buffer(path: &Path) -> Result<Vec<u8>> {
+    log::info!("Reading input file: {}", path.display());
     let mut file = fs::File::open(path)?;
 
     // Security: Check file size before reading to prevent OOM/DoS
@@ -269,6 +3958,10 @@ fn read_file_to_buffer(path: &str) -> Result<Vec<u8>> {
 
     let mut buffer = Vec::with_capacity(size as usize);
     let bytes_read = file.read_to_end(&mut buffer)?;
-    log::info!("Successfully read {} bytes from {}", bytes_read, path);
-    Ok(buffer)
+    log::info!(
+        "Successfully read {} bytes from {}",
+        bytes_read,
+        path.display()
+    );
+    unpack::unpack(path, buffer)
 }
@@ -2,7 +2,16 @@ use assert_cmd::Command as AssertCommand;
 use hound::WavReader;
 use predicates::prelude::*;
 use std::fs;
+use std::path::{Path, PathBuf};
 use tempfile::tempdir;
+use untracker_core::fixture::{FixtureInstrument, FixtureModule, FixtureNote, FixtureSample};
+
+/// Write a synthesized module to `dir`, returning its path.
+fn write_fixture(dir: &Path, name: &str, module: Vec<u8>) -> PathBuf {
+    let path = dir.join(name);
+    fs::write(&path, module).unwrap();
+    path
+}
 
 #[test]
 fn test_mod_extraction() -> Result<(), Box<dyn std::error::Error>> {
@@ -17,6 +26,7 @@ fn test_mod_extraction() -> Result<(), Box<dyn std::error::Error>> {
 
     cmd.assert()
         .success()
+        .stdout(predicate::str::contains("condom_corruption"))
         .stdout(predicate::str::contains("Extracting 31 sample stems"));
 
     let entries = fs::read_dir(out_path)?.count();
@@ -158,6 +168,206 @@ fn test_invalid_input() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn test_batch_directory() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    fs::copy(
+        "tests/modules/cndmcrrp.mod",
+        in_dir.path().join("cndmcrrp.mod"),
+    )?;
+    fs::write(in_dir.path().join("notes.txt"), "not a module")?;
+    fs::create_dir(in_dir.path().join("more"))?;
+    fs::copy(
+        "tests/modules/nova.s3m",
+        in_dir.path().join("more").join("nova.s3m"),
+    )?;
+
+    for (recursive, modules) in [(false, 1), (true, 2)] {
+        let out_dir = tempdir()?;
+        let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+        cmd.arg("-i")
+            .arg(in_dir.path())
+            .arg("-o")
+            .arg(out_dir.path())
+            .arg("--stems")
+            .arg("1-2")
+            .arg("--preview")
+            .arg("1s");
+        if recursive {
+            cmd.arg("--recursive");
+        }
+
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains(format!(
+                "Completed extracting {} modules!",
+                modules
+            )));
+
+        // One subdirectory per module, plus the manifest
+        assert_eq!(fs::read_dir(out_dir.path())?.count(), modules + 1);
+        let manifest = fs::read_to_string(out_dir.path().join("manifest.jsonl"))?;
+        assert_eq!(manifest.lines().count(), modules);
+        assert!(manifest.contains(r#""module":"cndmcrrp.mod","status":"done""#));
+        assert!(manifest.contains(r#""warnings":["#));
+        assert!(manifest.contains(r#""type":"mod""#));
+        let stems = out_dir.path().join("cndmcrrp");
+        assert!(stems.join("cndmcrrp_sample_002.wav").exists());
+        assert_eq!(fs::read_dir(&stems)?.count(), 2);
+        assert_eq!(out_dir.path().join("more/nova").exists(), recursive);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_batch_same_names() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    for dir in ["first", "second"] {
+        fs::create_dir(in_dir.path().join(dir))?;
+        write_fixture(
+            &in_dir.path().join(dir),
+            "song.mod",
+            FixtureModule::song(2, 1).to_mod(),
+        );
+    }
+    let out_dir = tempdir()?;
+
+    // Modules of the same name in different directories each get their own
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(in_dir.path())
+        .arg("-r")
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--preview")
+        .arg("1s");
+    cmd.assert().success();
+    assert!(out_dir
+        .path()
+        .join("first/song/song_sample_001.wav")
+        .exists());
+    assert!(out_dir
+        .path()
+        .join("second/song/song_sample_001.wav")
+        .exists());
+
+    // Only outputs that are the same path are refused
+    write_fixture(
+        &in_dir.path().join("first"),
+        "song.xm",
+        FixtureModule::song(2, 1).to_xm(),
+    );
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(in_dir.path())
+        .arg("-r")
+        .arg("-o")
+        .arg(out_dir.path());
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("overwriting each other"));
+
+    Ok(())
+}
+
+#[test]
+fn test_manifest_merge_and_query() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    fs::copy(
+        "tests/modules/cndmcrrp.mod",
+        in_dir.path().join("cndmcrrp.mod"),
+    )?;
+    fs::write(in_dir.path().join("broken.xm"), "not a module")?;
+
+    let out_dir = tempdir()?;
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(in_dir.path())
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--stems")
+        .arg("1-2")
+        .arg("--preview")
+        .arg("1s")
+        .arg("--write-metadata");
+    cmd.assert().failure();
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("manifest")
+        .arg("query")
+        .arg("failed")
+        .arg(out_dir.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("broken.xm: "));
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("manifest")
+        .arg("query")
+        .arg("size-by-format")
+        .arg(out_dir.path())
+        .arg("--json");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let totals: serde_json::Value = serde_json::from_slice(&output)?;
+    assert_eq!(totals[0]["format"], "wav");
+    assert_eq!(totals[0]["files"], 2);
+    assert!(totals[0]["bytes"].as_u64().unwrap() > 44100 * 4);
+
+    // The same modules from two manifests are only listed once
+    let merged = out_dir.path().join("merged.jsonl");
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("manifest")
+        .arg("merge")
+        .arg(out_dir.path())
+        .arg(out_dir.path().join("manifest.jsonl"))
+        .arg("-o")
+        .arg(&merged);
+    cmd.assert().success().stdout(predicate::str::contains(
+        "Merged 2 modules from 2 manifests",
+    ));
+    let text = fs::read_to_string(&merged)?;
+    assert_eq!(text.lines().count(), 2);
+    assert!(text.contains(r#""manifest":{"module":"cndmcrrp.mod""#));
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("manifest")
+        .arg("query")
+        .arg("clipping")
+        .arg(&merged);
+    cmd.assert().success();
+
+    Ok(())
+}
+
+#[test]
+fn test_skip_silent_stems() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    // Most samples don't play in the first second
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--preview")
+        .arg("1s")
+        .arg("--skip-silent");
+
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let stdout = String::from_utf8(output)?;
+    assert!(stdout.contains(": silent"));
+
+    let skipped = stdout.lines().filter(|l| l.contains("  Skipped ")).count();
+    let entries = fs::read_dir(out_path)?.count();
+    assert!(skipped > 0);
+    assert_eq!(entries, 31 - skipped);
+    assert!(stdout.contains(&format!("Completed extracting {} stems!", entries)));
+
+    Ok(())
+}
+
 #[test]
 #[cfg(feature = "vorbis")]
 fn test_vorbis_format() -> Result<(), Box<dyn std::error::Error>> {
@@ -218,6 +428,36 @@ fn test_opus_format() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+#[cfg(feature = "opus")]
+fn test_opus_resampled() -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = tempdir()?;
+    AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"))
+        .arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--format")
+        .arg("opus")
+        .arg("--sample-rate")
+        .arg("44100")
+        .arg("--stems")
+        .arg("1")
+        .assert()
+        .success();
+
+    // Encoded at 48 kHz, with the rate of the render in the header
+    let content = fs::read(out_dir.path().join("cndmcrrp_sample_001.opus"))?;
+    let head = content
+        .windows(8)
+        .position(|w| w == b"OpusHead")
+        .expect("OpusHead");
+    let rate = u32::from_le_bytes(content[head + 12..head + 16].try_into()?);
+    assert_eq!(rate, 44100);
+
+    Ok(())
+}
+
 #[test]
 #[cfg(feature = "flac")]
 fn test_flac_format() -> Result<(), Box<dyn std::error::Error>> {
@@ -300,6 +540,38 @@ fn test_invalid_channels() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn test_render_channels() -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = tempdir()?;
+    AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"))
+        .arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--channels")
+        .arg("1")
+        .arg("--render-channels")
+        .arg("4")
+        .arg("--stems")
+        .arg("1")
+        .assert()
+        .success();
+
+    let reader = WavReader::open(out_dir.path().join("cndmcrrp_sample_001.wav"))?;
+    assert_eq!(reader.spec().channels, 1);
+
+    AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"))
+        .arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--render-channels")
+        .arg("3")
+        .assert()
+        .failure();
+    Ok(())
+}
+
 #[test]
 fn test_invalid_bit_depth() -> Result<(), Box<dyn std::error::Error>> {
     let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
@@ -335,3 +607,2468 @@ fn test_parallel_rendering() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn test_bounded_jobs() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg("tests/modules/nova.s3m")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--preview")
+        .arg("2s")
+        .arg("--jobs")
+        .arg("2");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Completed extracting 31 stems!"));
+    assert_eq!(fs::read_dir(out_path)?.count(), 31);
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg("tests/modules/nova.s3m")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--jobs")
+        .arg("0");
+    cmd.assert().failure();
+
+    Ok(())
+}
+
+#[test]
+fn test_version_build_info() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("--version");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(env!("CARGO_PKG_VERSION")))
+        .stdout(predicate::str::contains("commit: "))
+        .stdout(predicate::str::contains("features: "))
+        .stdout(predicate::str::contains("libopenmpt: "));
+
+    Ok(())
+}
+
+#[test]
+fn test_verify_alignment() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = write_fixture(in_dir.path(), "song.xm", FixtureModule::song(3, 1).to_xm());
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_path)
+        .arg("--verify-alignment");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Alignment:"));
+
+    let lengths = fs::read_dir(out_dir.path())?
+        .map(|entry| Ok(WavReader::open(entry?.path())?.duration()))
+        .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+    assert!(lengths.len() > 1);
+    assert!(lengths.iter().all(|&length| length == lengths[0]));
+
+    Ok(())
+}
+
+#[test]
+fn test_verify_sum() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--preview")
+        .arg("5s")
+        .arg("--verify-sum");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Null test: subtracting"))
+        .stderr(predicate::str::contains("don't add up").not());
+
+    Ok(())
+}
+
+#[test]
+fn test_max_stems() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = write_fixture(
+        in_dir.path(),
+        "song.mod",
+        FixtureModule::song(12, 1).to_mod(),
+    );
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    // Without a terminal to confirm on, too many stems are refused
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_path)
+        .arg("--preview")
+        .arg("1s")
+        .arg("--max-stems")
+        .arg("10");
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("Estimated: 12 stems of 1s"))
+        .stderr(predicate::str::contains("more than --max-stems 10"));
+    assert!(!out_dir.path().join("song_sample_001.wav").exists());
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_path)
+        .arg("--preview")
+        .arg("1s")
+        .arg("--max-stems")
+        .arg("10")
+        .arg("--yes");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Completed extracting 12 stems!"));
+
+    Ok(())
+}
+
+#[test]
+fn test_stem_labels() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = write_fixture(
+        in_dir.path(),
+        "song.mod",
+        FixtureModule::song(2, 1).to_mod(),
+    );
+
+    for (labels, file_name) in [
+        ("instr=ins,sample=smp", "song_smp_002.wav"),
+        ("none", "song_002.wav"),
+    ] {
+        let out_dir = tempdir()?;
+        let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+        cmd.arg("-i")
+            .arg(&input)
+            .arg("-o")
+            .arg(out_dir.path())
+            .arg("--preview")
+            .arg("1s")
+            .arg("--labels")
+            .arg(labels);
+        cmd.assert().success();
+        assert!(out_dir.path().join(file_name).exists(), "{}", labels);
+        assert!(!out_dir.path().join("song_sample_002.wav").exists());
+    }
+
+    let out_dir = tempdir()?;
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--labels")
+        .arg("channel=ch");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown kind of stem 'channel'"));
+
+    Ok(())
+}
+
+#[test]
+fn test_name_template() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = write_fixture(
+        in_dir.path(),
+        "song.mod",
+        FixtureModule::song(2, 1).to_mod(),
+    );
+    let out_dir = tempdir()?;
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--preview")
+        .arg("1s")
+        .arg("--name-template")
+        .arg("{module}_{type}_{index:03}_{name}");
+    cmd.assert().success();
+    assert!(out_dir.path().join("song_sample_002_square_2.wav").exists());
+    assert!(!out_dir.path().join("song_sample_002.wav").exists());
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--name-template")
+        .arg("{module}_{name}");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("needs {index}"));
+
+    Ok(())
+}
+
+#[test]
+fn test_skip_empty_instrument() -> Result<(), Box<dyn std::error::Error>> {
+    // The second instrument has no sample, so its stem is silent
+    let module = FixtureModule::new("empty")
+        .instrument(FixtureInstrument::new(
+            "lead",
+            FixtureSample::square("lead", 32),
+        ))
+        .instrument(FixtureInstrument::empty("nothing"))
+        .instrument(FixtureInstrument::new(
+            "noise",
+            FixtureSample::noise("noise", 4096, 1),
+        ))
+        .pattern(vec![
+            FixtureNote::new(0, 0, 1),
+            FixtureNote::new(0, 1, 2),
+            FixtureNote::new(0, 2, 3).transpose(12),
+        ]);
+    let in_dir = tempdir()?;
+    let input = write_fixture(in_dir.path(), "empty.xm", module.to_xm());
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_path)
+        .arg("--preview")
+        .arg("2s")
+        .arg("--skip-silent");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("empty_instrument_002.wav: silent"))
+        .stdout(predicate::str::contains("Completed extracting 2 stems!"));
+
+    assert!(out_dir.path().join("empty_instrument_001.wav").exists());
+    assert!(!out_dir.path().join("empty_instrument_002.wav").exists());
+    assert!(out_dir.path().join("empty_instrument_003.wav").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_loop_count() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = write_fixture(
+        in_dir.path(),
+        "looped.mod",
+        FixtureModule::song(1, 1).looped().to_mod(),
+    );
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_path)
+        .arg("--sample-rate")
+        .arg("8000")
+        .arg("--loop-count")
+        .arg("1");
+    cmd.assert().success();
+
+    // The 7.68 second pattern plays twice
+    let reader = WavReader::open(out_dir.path().join("looped_sample_001.wav"))?;
+    let seconds = reader.duration() as f64 / reader.spec().sample_rate as f64;
+    assert!((seconds - 15.36).abs() < 0.1, "{}", seconds);
+
+    Ok(())
+}
+
+#[test]
+fn test_stem_groups() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--preview")
+        .arg("2s")
+        .arg("--groups")
+        .arg("low=1-10;high=11-31");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Extracting 2 sample groups"));
+    assert!(out_dir.path().join("cndmcrrp_low.wav").exists());
+    assert!(out_dir.path().join("cndmcrrp_high.wav").exists());
+    assert_eq!(fs::read_dir(out_path)?.count(), 2);
+
+    // Without a terminal, guessed groups are rendered without asking
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    cmd.arg("-i")
+        .arg("tests/modules/zalza-karate_muffins.xm")
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--preview")
+        .arg("2s")
+        .arg("--groups")
+        .arg("preset:standard");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Stem groups:"));
+    for entry in fs::read_dir(out_dir.path())? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        let group = name
+            .strip_prefix("zalza-karate_muffins_")
+            .and_then(|name| name.strip_suffix(".wav"))
+            .unwrap();
+        assert!(["drums", "bass", "chords", "lead", "fx", "other"].contains(&group));
+    }
+
+    // The same groups as the list above, from a file
+    let in_dir = tempdir()?;
+    let groups = in_dir.path().join("groups.toml");
+    fs::write(&groups, "low = \"1-10\"\nhigh = [11, 12, 13]\n")?;
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--preview")
+        .arg("2s")
+        .arg("--groups")
+        .arg(&groups);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Extracting 2 sample groups"));
+    assert!(out_dir.path().join("cndmcrrp_low.wav").exists());
+    assert!(out_dir.path().join("cndmcrrp_high.wav").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_interactive_plan() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    // Deselect everything, then pick two stems back
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--preview")
+        .arg("2s")
+        .arg("--interactive")
+        .write_stdin("n\n2,5\n\n");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Planned stems (wav, 44100 Hz"))
+        .stdout(predicate::str::contains("Extracting 2 of 31 sample stems"));
+    assert!(out_dir.path().join("cndmcrrp_sample_002.wav").exists());
+    assert!(out_dir.path().join("cndmcrrp_sample_005.wav").exists());
+    assert_eq!(fs::read_dir(out_path)?.count(), 2);
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_dir.path().join("stems"))
+        .arg("--interactive")
+        .write_stdin("q\n");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Extraction cancelled"));
+    assert!(!out_dir.path().join("stems").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_module_override_file() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = in_dir.path().join("cndmcrrp.mod");
+    fs::copy("tests/modules/cndmcrrp.mod", &input)?;
+    fs::write(
+        in_dir.path().join("cndmcrrp.untracker.toml"),
+        "sample-rate = 22050\nchannels = 1\n",
+    )?;
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i").arg(&input).arg("-o").arg(out_path);
+
+    cmd.assert().success();
+
+    // The sidecar values win over the command line defaults
+    let reader = WavReader::open(out_dir.path().join("cndmcrrp_sample_001.wav"))?;
+    let spec = reader.spec();
+    assert_eq!(spec.channels, 1);
+    assert_eq!(spec.sample_rate, 22050);
+
+    Ok(())
+}
+
+#[test]
+fn test_explain_options() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = in_dir.path().join("nova.s3m");
+    fs::copy("tests/modules/nova.s3m", &input)?;
+    fs::write(
+        in_dir.path().join("nova.untracker.toml"),
+        "sample-rate = 22050\n",
+    )?;
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_path)
+        .arg("--stems")
+        .arg("1")
+        .arg("--explain");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "nova.untracker.toml sets sample-rate",
+        ))
+        .stdout(predicate::str::contains("stereo separation 50%"));
+
+    Ok(())
+}
+
+#[test]
+fn test_preview_limits_length() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--preview")
+        .arg("2s");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Preview mode"));
+
+    let reader = WavReader::open(out_dir.path().join("cndmcrrp_sample_001.wav"))?;
+    assert_eq!(reader.duration(), 2 * 44100);
+
+    Ok(())
+}
+
+#[test]
+fn test_trim_silence() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--stems")
+        .arg("1-8")
+        .arg("--preview")
+        .arg("3s")
+        .arg("--trim-silence")
+        .arg("both");
+
+    cmd.assert().success();
+
+    // The preview fades out to silence, which is always trimmed
+    for entry in fs::read_dir(out_path)? {
+        let reader = WavReader::open(entry?.path())?;
+        assert!(reader.duration() < 3 * 44100);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_analysis_report() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--analyze");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("estimated FLAC savings"));
+
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(out_dir.path().join("analysis.json"))?)?;
+    let stems = report["stems"].as_array().unwrap();
+    assert_eq!(stems.len(), 31);
+    assert_eq!(stems[0]["file"], "cndmcrrp_sample_001.wav");
+    for stem in stems {
+        let silence = stem["silence_fraction"].as_f64().unwrap();
+        assert!((0.0..=1.0).contains(&silence));
+        assert!(stem["wav_bytes"].as_u64().unwrap() > 0);
+        assert!(stem["estimated_flac_bytes"].as_u64().unwrap() > 0);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_write_metadata() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--stems")
+        .arg("2,4")
+        .arg("--preview")
+        .arg("1s")
+        .arg("--write-metadata");
+
+    cmd.assert().success();
+
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(out_dir.path().join("manifest.json"))?)?;
+    assert_eq!(manifest["module"], "cndmcrrp.mod");
+    assert_eq!(manifest["format"], "mod");
+    assert!(manifest["duration"].as_f64().unwrap() > 1.0);
+    assert_eq!(manifest["settings"]["sample_rate"], 44100);
+    assert_eq!(manifest["settings"]["preview"], 1.0);
+
+    let stems = manifest["stems"].as_array().unwrap();
+    assert_eq!(stems.len(), 2);
+    assert_eq!(stems[1]["file"], "cndmcrrp_sample_004.wav");
+    assert_eq!(stems[1]["kind"], "sample");
+    assert_eq!(stems[1]["members"], serde_json::json!([4]));
+    for stem in stems {
+        assert!(out_dir.path().join(stem["file"].as_str().unwrap()).exists());
+        assert!(stem["group"].is_string());
+        assert_eq!(stem["color"].as_str().unwrap().len(), 7);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_render_info() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--stems")
+        .arg("2")
+        .arg("--preview")
+        .arg("1s")
+        .arg("--opus-bitrate")
+        .arg("96")
+        .arg("--write-metadata")
+        .arg("--render-info");
+    cmd.assert().success();
+
+    let info: serde_json::Value = serde_json::from_str(&fs::read_to_string(
+        out_dir.path().join("render-info.json"),
+    )?)?;
+    assert_eq!(info["untracker_version"], env!("CARGO_PKG_VERSION"));
+    assert!(info["command_line"]
+        .as_array()
+        .unwrap()
+        .contains(&serde_json::json!("--render-info")));
+    let module = fs::read("tests/modules/cndmcrrp.mod")?;
+    assert_eq!(info["input"]["size"], module.len());
+    assert_eq!(info["input"]["sha256"].as_str().unwrap().len(), 64);
+    assert_eq!(info["overrides"], serde_json::Value::Null);
+    assert_eq!(info["options"]["preview"], 1.0);
+    assert_eq!(info["options"]["opus_bitrate"], 96);
+    assert_eq!(
+        info["files"],
+        serde_json::json!(["cndmcrrp_sample_002.wav", "manifest.json"])
+    );
+    assert!(info["timings"]["elapsed_seconds"].as_f64().unwrap() > 0.0);
+
+    Ok(())
+}
+
+#[test]
+fn test_mix_automation() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let timeline = dir.path().join("mix.json");
+    fs::write(
+        &timeline,
+        r#"[{"seconds": 0, "channel": 1, "param": "volume", "value": 1},
+            {"seconds": 0.5, "channel": 1, "param": "volume", "value": 0},
+            {"seconds": 0, "channel": 2, "param": "pan", "value": -1}]"#,
+    )?;
+    let out_dir = dir.path().join("stems");
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(&out_dir)
+        .arg("--stems")
+        .arg("1")
+        .arg("--preview")
+        .arg("1s")
+        .arg("--mix-automation")
+        .arg(&timeline)
+        .arg("--render-info");
+    cmd.assert().success();
+    let info: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(out_dir.join("render-info.json"))?)?;
+    let points = info["options"]["mix_automation"].as_array().unwrap();
+    assert_eq!(points.len(), 3);
+    assert_eq!(points[1]["param"], "volume");
+    assert_eq!(points[1]["value"], 0.0);
+
+    let timeline = dir.path().join("mix.csv");
+    fs::write(&timeline, "0:10, 2, volume, 1.5\n")?;
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(&out_dir)
+        .arg("--mix-automation")
+        .arg(&timeline);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("is outside 0 to 1"));
+
+    Ok(())
+}
+
+#[test]
+fn test_existing_outputs() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = write_fixture(
+        in_dir.path(),
+        "song.mod",
+        FixtureModule::song(2, 1).to_mod(),
+    );
+    let out_dir = tempdir()?;
+    let extract = |flag: Option<&str>| {
+        let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+        cmd.arg("-i")
+            .arg(&input)
+            .arg("-o")
+            .arg(out_dir.path())
+            .arg("--preview")
+            .arg("1s")
+            .args(flag);
+        cmd.assert()
+    };
+    let first = out_dir.path().join("song_sample_001.wav");
+    let second = out_dir.path().join("song_sample_002.wav");
+
+    extract(None).success();
+    extract(None).failure().stderr(predicate::str::contains(
+        "song_sample_001.wav already exists",
+    ));
+
+    // Only the missing stem is rendered again
+    fs::write(&first, b"kept")?;
+    fs::remove_file(&second)?;
+    extract(Some("--skip-existing"))
+        .success()
+        .stdout(predicate::str::contains(
+            "Keeping 1 stems that already exist",
+        ));
+    assert_eq!(fs::read(&first)?, b"kept");
+    assert!(second.exists());
+
+    extract(Some("--overwrite")).success();
+    assert_eq!(&fs::read(&first)?[..4], b"RIFF");
+
+    Ok(())
+}
+
+#[test]
+fn test_resume_batch() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    for name in ["first.mod", "second.mod"] {
+        write_fixture(in_dir.path(), name, FixtureModule::song(2, 1).to_mod());
+    }
+    let out_dir = tempdir()?;
+    let extract = |resume: bool| {
+        let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+        cmd.arg("-i")
+            .arg(in_dir.path())
+            .arg("-o")
+            .arg(out_dir.path())
+            .arg("--preview")
+            .arg("1s");
+        if resume {
+            cmd.arg("--resume");
+        }
+        cmd.assert()
+    };
+
+    extract(false).success();
+    // As if the run had stopped while extracting the second module
+    fs::remove_dir_all(out_dir.path().join("second"))?;
+    extract(true)
+        .success()
+        .stdout(predicate::str::contains("first.mod: already extracted"))
+        .stdout(predicate::str::contains("Processing").count(1));
+    assert!(out_dir.path().join("second/second_sample_002.wav").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_dry_run() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let module = write_fixture(dir.path(), "song.mod", FixtureModule::song(3, 1).to_mod());
+    let out_dir = dir.path().join("stems");
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&module)
+        .arg("-o")
+        .arg(&out_dir)
+        .arg("--stems")
+        .arg("1,3")
+        .arg("--full-mix")
+        .arg("--list");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Would write 3 files:"))
+        .stdout(predicate::str::contains("song_sample_001.wav"))
+        .stdout(predicate::str::contains("song_sample_002.wav").not());
+    assert!(!out_dir.exists());
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&module)
+        .arg("--labels")
+        .arg("none")
+        .arg("--dry-run")
+        .arg("json");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let listing: serde_json::Value = serde_json::from_slice(&output)?;
+    assert_eq!(listing["samples"].as_array().unwrap().len(), 3);
+    assert_eq!(
+        listing["outputs"],
+        serde_json::json!(["song_001.wav", "song_002.wav", "song_003.wav"])
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_mute_automation() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let automation = dir.path().join("edit.txt");
+    fs::write(
+        &automation,
+        "# Silence the song\nmute channel 1 from 0:00 to 0:01\n",
+    )?;
+    let out_dir = tempdir()?;
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--stems")
+        .arg("1")
+        .arg("--preview")
+        .arg("1s")
+        .arg("--mute-automation")
+        .arg(&automation)
+        .arg("--render-info");
+    cmd.assert().success();
+    let info: serde_json::Value = serde_json::from_str(&fs::read_to_string(
+        out_dir.path().join("render-info.json"),
+    )?)?;
+    assert_eq!(info["options"]["mute_automation"][1]["seconds"], 1.0);
+    assert_eq!(info["options"]["mute_automation"][1]["mute"], false);
+
+    fs::write(&automation, "mute channel 9 at 0:10\n")?;
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--mute-automation")
+        .arg(&automation);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Channel 9 is automated"));
+
+    fs::write(&automation, "mute channel 2 soon\n")?;
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--mute-automation")
+        .arg(&automation);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("line 1: expected"));
+
+    Ok(())
+}
+
+#[test]
+fn test_normalize_stems() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--preview")
+        .arg("4s")
+        .arg("--normalize")
+        .arg("peak")
+        .arg("--normalize-target")
+        .arg("-3")
+        .arg("--write-metadata");
+
+    cmd.assert().success();
+
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(out_dir.path().join("manifest.json"))?)?;
+    assert_eq!(manifest["settings"]["normalize"], "peak");
+    assert_eq!(manifest["settings"]["normalize_target"], -3.0);
+
+    // Every stem that plays is brought to the target, and records the gain it took
+    let mut normalized = 0;
+    for stem in manifest["stems"].as_array().unwrap() {
+        if let Some(peak) = stem["peak_dbfs"].as_f64() {
+            assert!((peak + 3.0).abs() < 0.1, "{}", stem);
+            assert!(stem["gain_db"].as_f64().unwrap() != 0.0);
+            normalized += 1;
+        }
+    }
+    assert!(normalized > 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_replaygain() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = write_fixture(
+        in_dir.path(),
+        "song.mod",
+        FixtureModule::song(2, 1).to_mod(),
+    );
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_path)
+        .arg("--preview")
+        .arg("4s")
+        .arg("--replaygain")
+        .arg("--write-metadata");
+    cmd.assert().success();
+
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(out_dir.path().join("manifest.json"))?)?;
+    assert_eq!(manifest["settings"]["replaygain"], true);
+    for stem in manifest["stems"].as_array().unwrap() {
+        let lufs = stem["loudness_lufs"].as_f64().unwrap();
+        let replaygain = stem["replaygain_db"].as_f64().unwrap();
+        assert!((lufs + replaygain + 18.0).abs() < 1e-9, "{}", stem);
+        let sample_peak = stem["peak_dbfs"].as_f64().unwrap();
+        assert!(stem["true_peak_dbtp"].as_f64().unwrap() > sample_peak - 0.01);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_invalid_tag() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--tag")
+        .arg("GENRE");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Expected KEY=value"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn test_flac_tags() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = write_fixture(
+        in_dir.path(),
+        "song.mod",
+        FixtureModule::song(2, 1).to_mod(),
+    );
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--preview")
+        .arg("1s")
+        .arg("--format")
+        .arg("flac")
+        .arg("--tag")
+        .arg("genre=Chiptune")
+        .arg("--tag")
+        .arg("ALBUM=Fixtures");
+    cmd.assert().success();
+
+    let bytes = fs::read(out_dir.path().join("song_sample_002.flac"))?;
+    let has_tag = |tag: &str| {
+        bytes
+            .windows(tag.len())
+            .any(|window| window == tag.as_bytes())
+    };
+    assert!(has_tag("TITLE=square 2"));
+    assert!(has_tag("TRACKNUMBER=2"));
+    assert!(has_tag("GENRE=Chiptune"));
+    // Given tags replace the ones from the module
+    assert!(has_tag("ALBUM=Fixtures"));
+    assert!(!has_tag("ALBUM=fixture"));
+
+    Ok(())
+}
+
+#[test]
+fn test_remix_kit() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--preview")
+        .arg("4s")
+        .arg("--remix-kit");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Remix kit: "));
+
+    let kit: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(out_dir.path().join("README.json"))?)?;
+    assert_eq!(kit["module"], "cndmcrrp.mod");
+    assert!(kit["bpm"].as_f64().unwrap() > 0.0);
+    assert_eq!(kit["rms_target"], -18.0);
+
+    let stems = kit["stems"].as_array().unwrap();
+    assert!(!stems.is_empty());
+    for stem in stems {
+        let path = out_dir.path().join(stem["file"].as_str().unwrap());
+        let data = fs::read(&path)?;
+        assert!(data.windows(4).any(|w| w == b"smpl"));
+        assert!(data.windows(4).any(|w| w == b"acid"));
+
+        let frames = WavReader::open(&path)?.duration() as u64;
+        assert!(stem["loop_end"].as_u64().unwrap() <= frames);
+    }
+
+    // Loop points only go into WAV files
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--format")
+        .arg("flac")
+        .arg("--remix-kit");
+    cmd.assert().failure();
+
+    Ok(())
+}
+
+#[test]
+fn test_subsongs() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--stems")
+        .arg("2")
+        .arg("--preview")
+        .arg("1s")
+        .arg("--all-subsongs")
+        .arg("--write-metadata");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Subsong 1 of "));
+
+    let subsong_dir = out_dir.path().join("subsong_01");
+    assert!(subsong_dir.join("cndmcrrp_sample_002.wav").exists());
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(subsong_dir.join("manifest.json"))?)?;
+    assert_eq!(manifest["settings"]["subsong"], 1);
+
+    // Sub-songs past the end of the module are an error
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--subsong")
+        .arg("99");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Subsong 99 selected"));
+
+    Ok(())
+}
+
+#[test]
+fn test_time_range() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--stems")
+        .arg("1")
+        .arg("--start-time")
+        .arg("2")
+        .arg("--end-time")
+        .arg("3.5");
+    cmd.assert().success();
+
+    // The start lands on a row boundary at or before the requested time
+    let frames = WavReader::open(out_dir.path().join("cndmcrrp_sample_001.wav"))?.duration();
+    assert!((66150..=66150 + 44100 / 4).contains(&frames));
+
+    // Orders and times can't be mixed
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--start-order")
+        .arg("1")
+        .arg("--end-time")
+        .arg("3");
+    cmd.assert().failure();
+
+    Ok(())
+}
+
+#[test]
+fn test_float_wav() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--stems")
+        .arg("1")
+        .arg("--preview")
+        .arg("2")
+        .arg("--sample-format")
+        .arg("float");
+    cmd.assert().success();
+
+    let mut reader = WavReader::open(out_dir.path().join("cndmcrrp_sample_001.wav"))?;
+    let spec = reader.spec();
+    assert_eq!(spec.sample_format, hound::SampleFormat::Float);
+    assert_eq!(spec.bits_per_sample, 32);
+    assert!(reader.duration() <= 88200);
+    let samples = reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?;
+    assert!(samples.iter().any(|&s| s != 0.0));
+
+    // Only WAV stores floats
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--format")
+        .arg("flac")
+        .arg("--sample-format")
+        .arg("float");
+    cmd.assert().failure();
+
+    Ok(())
+}
+
+#[test]
+fn test_24_bit_wav() -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    for dither in [false, true] {
+        let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+        cmd.arg("-i")
+            .arg("tests/modules/cndmcrrp.mod")
+            .arg("-o")
+            .arg(out_path)
+            .arg("--stems")
+            .arg("1")
+            .arg("--preview")
+            .arg("2")
+            .arg("--bit-depth")
+            .arg("24");
+        if dither {
+            cmd.arg("--dither");
+        }
+        cmd.assert().success();
+
+        // Rendered in floating point, so the low bits carry audio
+        let mut reader = WavReader::open(out_dir.path().join("cndmcrrp_sample_001.wav"))?;
+        assert_eq!(reader.spec().bits_per_sample, 24);
+        let samples = reader.samples::<i32>().collect::<Result<Vec<_>, _>>()?;
+        assert!(samples.iter().any(|&s| s & 0xff != 0));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_mix_glob() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("mix")
+        .arg("-i")
+        .arg("tests/modules/*.mod")
+        .arg("tests/modules/nova.s3m")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--preview")
+        .arg("2s")
+        .arg("--normalize");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Rendering 2 mixes"))
+        .stdout(predicate::str::contains("Completed rendering 2 mixes!"));
+
+    let entries = fs::read_dir(out_path)?.count();
+    assert_eq!(entries, 2);
+
+    let mut reader = WavReader::open(out_dir.path().join("cndmcrrp.wav"))?;
+    assert_eq!(reader.duration(), 2 * 44100);
+    let peak = reader
+        .samples::<i16>()
+        .map(|s| s.unwrap().unsigned_abs())
+        .max()
+        .unwrap();
+    // -1 dBFS
+    assert!((29000..=29300).contains(&peak));
+    assert!(out_dir.path().join("nova.wav").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_mix_album() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("mix")
+        .arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("tests/modules/nova.s3m")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--preview")
+        .arg("5s")
+        .arg("--album")
+        .arg("--album-target")
+        .arg("-20");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Album loudness:"))
+        .stdout(predicate::str::contains("Completed rendering 2 mixes!"));
+
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(out_dir.path().join("album.json"))?)?;
+    assert_eq!(report["target_lufs"], -20.0);
+    let gain = report["album_gain_db"].as_f64().unwrap();
+    let tracks = report["tracks"].as_array().unwrap();
+    assert_eq!(tracks.len(), 2);
+    for track in tracks {
+        let measured = track["measured"]["integrated_lufs"].as_f64().unwrap();
+        let output = track["output"]["integrated_lufs"].as_f64().unwrap();
+        assert!((output - measured - gain).abs() < 1e-9);
+        assert!(track["output"]["true_peak_dbtp"].as_f64().unwrap() <= -1.0 + 1e-9);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_mix_no_matches() {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir().unwrap();
+
+    cmd.arg("mix")
+        .arg("-i")
+        .arg("tests/modules/*.nothing")
+        .arg("-o")
+        .arg(out_dir.path());
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("No files match"));
+}
+
+#[test]
+#[cfg(not(feature = "flac"))]
+fn test_format_fallback_chain() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--format")
+        .arg("flac|wav")
+        .arg("--preview")
+        .arg("1s");
+
+    cmd.assert().success();
+    assert!(out_dir.path().join("cndmcrrp_sample_001.wav").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_temp_dir_staging() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let root = tempdir()?;
+    let temp = tempdir()?;
+    let out_path = root.path().join("stems");
+
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(&out_path)
+        .arg("--preview")
+        .arg("1s")
+        .arg("--temp-dir")
+        .arg(temp.path());
+
+    cmd.assert().success();
+
+    assert_eq!(fs::read_dir(&out_path)?.count(), 31);
+    assert_eq!(fs::read_dir(temp.path())?.count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_failed_extraction_leaves_no_output() {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let root = tempdir().unwrap();
+    let out_path = root.path().join("stems");
+
+    // Fails after the staging directory exists, while loading the module
+    cmd.arg("-i").arg("Cargo.toml").arg("-o").arg(&out_path);
+
+    cmd.assert().failure();
+    assert!(!out_path.exists());
+    assert_eq!(fs::read_dir(root.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_reproducible_output() -> Result<(), Box<dyn std::error::Error>> {
+    let first = tempdir()?;
+    let second = tempdir()?;
+
+    for out_dir in [&first, &second] {
+        let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+        cmd.arg("-i")
+            .arg("tests/modules/zalza-karate_muffins.xm")
+            .arg("-o")
+            .arg(out_dir.path())
+            .arg("--preview")
+            .arg("2s")
+            .arg("--reproducible");
+        cmd.assert().success();
+    }
+
+    for entry in fs::read_dir(first.path())? {
+        let entry = entry?;
+        assert_eq!(
+            fs::read(entry.path())?,
+            fs::read(second.path().join(entry.file_name()))?,
+            "{} differs between runs",
+            entry.file_name().to_string_lossy()
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_stem_selection() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg("tests/modules/zalza-karate_muffins.xm")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--stems")
+        .arg("1,3,7-9")
+        .arg("--preview")
+        .arg("1s");
+
+    cmd.assert().success().stdout(predicate::str::contains(
+        "Extracting 5 of 47 instrument stems",
+    ));
+
+    let mut files: Vec<_> = fs::read_dir(out_path)?
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        [1, 3, 7, 8, 9]
+            .iter()
+            .map(|i| format!("zalza-karate_muffins_instrument_{:03}.wav", i))
+            .collect::<Vec<_>>()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_stem_selection_out_of_range() {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir().unwrap();
+
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--stems")
+        .arg("30-40");
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("only has 31 stems"));
+}
+
+#[test]
+fn test_watch_once() -> Result<(), Box<dyn std::error::Error>> {
+    let drop_dir = tempdir()?;
+    let out_dir = tempdir()?;
+    fs::copy(
+        "tests/modules/cndmcrrp.mod",
+        drop_dir.path().join("cndmcrrp.mod"),
+    )?;
+    fs::write(
+        drop_dir.path().join("cndmcrrp.untracker.toml"),
+        "channels = 1\n",
+    )?;
+    fs::write(drop_dir.path().join("broken.xm"), b"not a module")?;
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("watch")
+        .arg("-i")
+        .arg(drop_dir.path())
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--once")
+        .arg("--preview")
+        .arg("1s");
+
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("Failed"));
+
+    let reader = WavReader::open(out_dir.path().join("cndmcrrp/cndmcrrp_sample_001.wav"))?;
+    assert_eq!(reader.spec().channels, 1);
+    assert!(drop_dir.path().join("done/cndmcrrp.mod").exists());
+    assert!(drop_dir
+        .path()
+        .join("done/cndmcrrp.untracker.toml")
+        .exists());
+    assert!(drop_dir.path().join("failed/broken.xm").exists());
+
+    let manifest = fs::read_to_string(out_dir.path().join("manifest.jsonl"))?;
+    let entries: Vec<serde_json::Value> = manifest
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["module"], "broken.xm");
+    assert_eq!(entries[0]["status"], "failed");
+    assert_eq!(entries[1]["status"], "done");
+    assert_eq!(entries[1]["stems"], 31);
+
+    Ok(())
+}
+
+#[test]
+fn test_render_cache() -> Result<(), Box<dyn std::error::Error>> {
+    let cache_dir = tempdir()?;
+    let first = tempdir()?;
+    let second = tempdir()?;
+
+    for out_dir in [&first, &second] {
+        let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+        cmd.arg("-i")
+            .arg("tests/modules/cndmcrrp.mod")
+            .arg("-o")
+            .arg(out_dir.path())
+            .arg("--stems")
+            .arg("1-4")
+            .arg("--preview")
+            .arg("1s")
+            .arg("--cache-dir")
+            .arg(cache_dir.path());
+        cmd.assert().success();
+    }
+
+    // One audio file and one result file per stem
+    assert_eq!(fs::read_dir(cache_dir.path())?.count(), 8);
+    for entry in fs::read_dir(first.path())? {
+        let entry = entry?;
+        assert_eq!(
+            fs::read(entry.path())?,
+            fs::read(second.path().join(entry.file_name()))?
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_mix_cache_dotted_name() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = in_dir.path().join("foo.bar.mod");
+    fs::copy("tests/modules/cndmcrrp.mod", &input)?;
+    let cache_dir = tempdir()?;
+    let first = tempdir()?;
+    let second = tempdir()?;
+
+    // The second mix comes from the cache, and is named as the first was
+    for out_dir in [&first, &second] {
+        let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+        cmd.arg("mix")
+            .arg("-i")
+            .arg(&input)
+            .arg("-o")
+            .arg(out_dir.path())
+            .arg("--preview")
+            .arg("1s")
+            .arg("--cache-dir")
+            .arg(cache_dir.path());
+        cmd.assert().success();
+        assert!(out_dir.path().join("foo.bar.wav").exists());
+        assert!(!out_dir.path().join("foo.wav").exists());
+    }
+    assert_eq!(
+        fs::read(first.path().join("foo.bar.wav"))?,
+        fs::read(second.path().join("foo.bar.wav"))?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_full_mix_with_stems() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg("tests/modules/nova.s3m")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--preview")
+        .arg("1s")
+        .arg("--full-mix");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Rendered full mix"));
+
+    assert_eq!(fs::read_dir(out_path)?.count(), 32);
+    let reader = WavReader::open(out_dir.path().join("nova.wav"))?;
+    assert_eq!(reader.duration(), 44100);
+
+    Ok(())
+}
+
+#[test]
+fn test_marker_file() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    let out_dir = tempdir()?;
+    let out_path = out_dir.path().to_str().unwrap();
+
+    cmd.arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(out_path)
+        .arg("--stems")
+        .arg("1")
+        .arg("--markers")
+        .arg("reaper");
+
+    cmd.assert().success();
+
+    let markers = fs::read_to_string(out_dir.path().join("cndmcrrp_markers.csv"))?;
+    let mut lines = markers.lines();
+    assert_eq!(lines.next(), Some("#,Name,Start,End,Length"));
+    assert!(lines.next().unwrap().starts_with("R1,\"Order 0 - Pattern "));
+    assert!(lines.count() > 1);
+    assert!(markers.contains("\nM1,\""));
+
+    let cue_dir = tempdir()?;
+    AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"))
+        .arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg(cue_dir.path())
+        .arg("--stems")
+        .arg("1")
+        .arg("--full-mix")
+        .arg("--markers")
+        .arg("cue")
+        .assert()
+        .success();
+    let cue = fs::read_to_string(cue_dir.path().join("cndmcrrp.cue"))?;
+    assert!(cue.starts_with("FILE \"cndmcrrp.wav\" WAVE\n  TRACK 01 AUDIO\n"));
+    assert!(cue.contains("    INDEX 01 00:00:00\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_loop_crossfade() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = write_fixture(
+        in_dir.path(),
+        "song.mod",
+        FixtureModule::song(2, 1).to_mod(),
+    );
+    let out_dir = tempdir()?;
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--loop-crossfade")
+        .arg("0.1s")
+        .arg("--write-metadata");
+    cmd.assert().success();
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(out_dir.path().join("manifest.json"))?)?;
+    assert_eq!(manifest["settings"]["loop_crossfade"], 0.1);
+    let stem = &manifest["stems"][0];
+    assert_eq!(stem["file"], "song_sample_001.wav");
+    assert!(stem["loop_frames"].as_u64().unwrap() > 0);
+    let wav = fs::read(out_dir.path().join("song_sample_001.wav"))?;
+    assert!(wav.windows(4).any(|id| id == b"smpl"));
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--loop-crossfade")
+        .arg("2s")
+        .arg("--start-time")
+        .arg("1s");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be combined"));
+
+    Ok(())
+}
+
+#[test]
+fn test_ctl() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = write_fixture(
+        in_dir.path(),
+        "song.mod",
+        FixtureModule::song(1, 1).to_mod(),
+    );
+    let out_dir = tempdir()?;
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--ctl")
+        .arg("render.resampler.emulate_amiga=1")
+        .arg("--write-metadata");
+    cmd.assert().success();
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(out_dir.path().join("manifest.json"))?)?;
+    assert_eq!(
+        manifest["settings"]["ctls"]["render.resampler.emulate_amiga"],
+        "1"
+    );
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--ctl")
+        .arg("render.no_such_ctl=1");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "libopenmpt has no ctl 'render.no_such_ctl'",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_kept_stems_with_changed_settings() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = write_fixture(
+        in_dir.path(),
+        "song.mod",
+        FixtureModule::song(2, 1).to_mod(),
+    );
+    let out_dir = tempdir()?;
+    let extract = |args: &[&str]| {
+        let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+        cmd.arg("-i")
+            .arg(&input)
+            .arg("-o")
+            .arg(out_dir.path())
+            .arg("--write-metadata")
+            .args(args);
+        cmd.assert()
+    };
+
+    extract(&[]).success();
+    fs::remove_file(out_dir.path().join("song_sample_002.wav"))?;
+    extract(&["--skip-existing", "--normalize", "peak"])
+        .success()
+        .stdout(predicate::str::contains(
+            "rendered with a different normalize, normalize_target",
+        ));
+
+    // Nothing is said when the settings are those of the manifest
+    fs::remove_file(out_dir.path().join("song_sample_002.wav"))?;
+    extract(&["--skip-existing"])
+        .success()
+        .stdout(predicate::str::contains("rendered with a different").not());
+
+    Ok(())
+}
+
+#[test]
+fn test_meta() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = write_fixture(
+        in_dir.path(),
+        "song.mod",
+        FixtureModule::song(1, 1).to_mod(),
+    );
+    let out_dir = tempdir()?;
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--meta")
+        .arg("license=CC-BY")
+        .arg("--meta")
+        .arg("source-url=https://example.com/song.mod")
+        .arg("--write-metadata")
+        .arg("--render-info");
+    cmd.assert().success();
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(out_dir.path().join("manifest.json"))?)?;
+    assert_eq!(manifest["meta"]["license"], "CC-BY");
+    assert_eq!(
+        manifest["meta"]["source-url"],
+        "https://example.com/song.mod"
+    );
+    let info: serde_json::Value = serde_json::from_str(&fs::read_to_string(
+        out_dir.path().join("render-info.json"),
+    )?)?;
+    assert_eq!(info["tags"][0], serde_json::json!(["LICENSE", "CC-BY"]));
+
+    Ok(())
+}
+
+#[test]
+fn test_fail_fast() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    // Modules are processed in order of their paths, the broken one first
+    fs::write(in_dir.path().join("broken.xm"), "not a module")?;
+    write_fixture(
+        in_dir.path(),
+        "song.mod",
+        FixtureModule::song(1, 1).to_mod(),
+    );
+
+    let out_dir = tempdir()?;
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(in_dir.path())
+        .arg("-o")
+        .arg(out_dir.path());
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("1 of 2 modules failed to extract"));
+    assert!(out_dir.path().join("song/song_sample_001.wav").exists());
+
+    let out_dir = tempdir()?;
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(in_dir.path())
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--fail-fast");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Stopped at the first module that failed to extract",
+    ));
+    assert!(!out_dir.path().join("song").exists());
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("mix")
+        .arg("-i")
+        .arg(in_dir.path())
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--fail-fast");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "leaving 1 of 2 modules unrendered",
+    ));
+    assert!(!out_dir.path().join("song.wav").exists());
+
+    Ok(())
+}
+
+#[cfg(feature = "decode")]
+#[test]
+fn test_verify_output_and_diff() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    write_fixture(
+        in_dir.path(),
+        "song.mod",
+        FixtureModule::song(2, 1).to_mod(),
+    );
+    let out_dir = tempdir()?;
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(in_dir.path().join("song.mod"))
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--verify-output");
+    cmd.assert().success().stdout(predicate::str::contains(
+        "Verified 2 stems by decoding them",
+    ));
+
+    let first = out_dir.path().join("song/song_sample_001.wav");
+    let second = out_dir.path().join("song/song_sample_002.wav");
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("diff").arg(&first).arg(&first);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("The files are identical"));
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("diff")
+        .arg(&first)
+        .arg(&second)
+        .arg("--max-difference")
+        .arg("-60");
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("Difference:"))
+        .stderr(predicate::str::contains("more than the -60.0 dB allowed"));
+    Ok(())
+}
+
+#[test]
+fn test_rank() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    write_fixture(
+        in_dir.path(),
+        "eight.mod",
+        FixtureModule::song(8, 1).to_mod(),
+    );
+    write_fixture(in_dir.path(), "one.mod", FixtureModule::song(1, 1).to_mod());
+    fs::write(in_dir.path().join("broken.xm"), "not a module")?;
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("rank").arg(in_dir.path()).arg("--json");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let ranking: serde_json::Value = serde_json::from_slice(&output)?;
+    let ranking = ranking.as_array().unwrap();
+    assert_eq!(ranking.len(), 2);
+    assert!(ranking[0]["module"]
+        .as_str()
+        .unwrap()
+        .ends_with("eight.mod"));
+    assert_eq!(ranking[0]["played_stems"], 8);
+    assert!(ranking[0]["score"].as_f64() > ranking[1]["score"].as_f64());
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("rank").arg(in_dir.path()).arg("--top").arg("1");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("eight.mod: 8 of 8 stems played"))
+        .stdout(predicate::str::contains("one.mod").not());
+    Ok(())
+}
+
+#[test]
+fn test_raw_samples() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    write_fixture(
+        in_dir.path(),
+        "song.mod",
+        FixtureModule::song(3, 1).to_mod(),
+    );
+    let out_dir = tempdir()?;
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(in_dir.path().join("song.mod"))
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--mode")
+        .arg("raw-samples");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Extracting 3 raw samples"));
+
+    let reader = WavReader::open(out_dir.path().join("song_raw_002_square_2.wav"))?;
+    let spec = reader.spec();
+    assert_eq!(
+        (spec.sample_rate, spec.channels, spec.bits_per_sample),
+        (8363, 1, 16)
+    );
+    // The samples themselves, not a render of the song
+    assert!(!out_dir.path().join("song_sample_001.wav").exists());
+
+    let out_dir = tempdir()?;
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg("tests/modules/nova.s3m")
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--mode")
+        .arg("raw-samples");
+    cmd.assert().success();
+    assert!(fs::read_dir(out_dir.path())?.any(|entry| entry
+        .unwrap()
+        .file_name()
+        .to_string_lossy()
+        .starts_with("nova_raw_")));
+    Ok(())
+}
+
+#[test]
+fn test_concat() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let out_dir = tempdir()?;
+    let mut lengths = Vec::new();
+    for (name, patterns) in [("one", 1), ("two", 2)] {
+        let input = write_fixture(
+            in_dir.path(),
+            &format!("{}.mod", name),
+            FixtureModule::song(2, patterns).to_mod(),
+        );
+        let set_dir = out_dir.path().join(name);
+        let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+        cmd.arg("-i")
+            .arg(&input)
+            .arg("-o")
+            .arg(&set_dir)
+            .arg("--write-metadata");
+        cmd.assert().success();
+        let stem = set_dir.join(format!("{}_sample_001.wav", name));
+        lengths.push(WavReader::open(stem)?.duration());
+    }
+
+    let joined_dir = tempdir()?;
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("concat")
+        .arg(out_dir.path().join("one"))
+        .arg(out_dir.path().join("two"))
+        .arg("-o")
+        .arg(joined_dir.path())
+        .arg("--gap")
+        .arg("1s");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Joined 2 stems from 2 sets"));
+
+    let reader = WavReader::open(joined_dir.path().join("concat_square_1.wav"))?;
+    assert_eq!(reader.duration(), lengths[0] + 44100 + lengths[1]);
+
+    // The joined stems are already there
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("concat")
+        .arg(out_dir.path().join("one"))
+        .arg(out_dir.path().join("two"))
+        .arg("-o")
+        .arg(joined_dir.path());
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("--overwrite"));
+    Ok(())
+}
+
+#[test]
+fn test_export_session() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = write_fixture(
+        in_dir.path(),
+        "song.mod",
+        FixtureModule::song(2, 1).to_mod(),
+    );
+    let out_dir = tempdir()?;
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--export-session")
+        .arg("reaper");
+    cmd.assert().success();
+
+    let project = fs::read_to_string(out_dir.path().join("song.RPP"))?;
+    assert!(project.starts_with("<REAPER_PROJECT "));
+    assert!(project.contains("  TITLE \"fixture\"\n"));
+    assert!(project.contains("  TEMPO 125 4 4\n"));
+    assert_eq!(project.matches("<TRACK").count(), 2);
+    assert!(project.contains("        FILE \"song_sample_002.wav\"\n"));
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--overwrite")
+        .arg("--export-session")
+        .arg("ardour");
+    cmd.assert().success();
+    let session = fs::read_to_string(out_dir.path().join("song.ardour"))?;
+    assert!(session.contains("<Session version=\"6000\" name=\"fixture\""));
+    assert!(session.contains("song_sample_001.wav"));
+    Ok(())
+}
+
+#[test]
+fn test_memory_budget() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = write_fixture(
+        in_dir.path(),
+        "song.mod",
+        FixtureModule::song(4, 1).to_mod(),
+    );
+    let out_dir = tempdir()?;
+
+    // A budget smaller than one stem renders them one at a time
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--jobs")
+        .arg("4")
+        .arg("--memory-budget")
+        .arg("1K")
+        .arg("--normalize")
+        .arg("peak");
+    cmd.assert().success();
+    for n in 1..=4 {
+        assert!(out_dir
+            .path()
+            .join(format!("song_sample_{:03}.wav", n))
+            .exists());
+    }
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--memory-budget")
+        .arg("lots");
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_ci_mode() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    write_fixture(in_dir.path(), "b.mod", FixtureModule::song(2, 1).to_mod());
+    write_fixture(in_dir.path(), "a.mod", FixtureModule::song(3, 1).to_mod());
+    fs::write(in_dir.path().join("broken.xm"), "not a module")?;
+    let out_dir = tempdir()?;
+    let report = out_dir.path().join("results.xml");
+
+    let output = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"))
+        .arg("--ci")
+        .arg("--ci-report")
+        .arg(&report)
+        .arg("-i")
+        .arg(in_dir.path())
+        .arg("-o")
+        .arg(out_dir.path().join("stems"))
+        .assert()
+        .failure()
+        .get_output()
+        .stdout
+        .clone();
+
+    // One line per module, sorted by path, and nothing else
+    let stdout = String::from_utf8(output)?;
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", stdout);
+    assert!(lines[0].starts_with("passed  ") && lines[0].contains("a.mod: 3 stems"));
+    assert!(lines[1].starts_with("passed  ") && lines[1].contains("b.mod: 2 stems"));
+    assert!(lines[2].starts_with("failed  ") && lines[2].contains("broken.xm"));
+
+    let junit = fs::read_to_string(&report)?;
+    assert!(junit.contains("tests=\"3\" failures=\"1\""));
+    assert_eq!(junit.matches("<failure ").count(), 1);
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("--ci-report")
+        .arg(&report)
+        .arg("-i")
+        .arg(in_dir.path())
+        .arg("-o")
+        .arg(out_dir.path());
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_stream_to_stdout() -> Result<(), Box<dyn std::error::Error>> {
+    let output = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"))
+        .arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("--output")
+        .arg("-")
+        .arg("--stems")
+        .arg("1")
+        .arg("--preview")
+        .arg("1")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    // Nothing but a WAV stream of unknown length, holding the second rendered
+    assert_eq!(&output[..4], b"RIFF");
+    assert_eq!(&output[36..44], b"data\xff\xff\xff\xff");
+    assert_eq!(output.len(), 44 + 44100 * 4);
+
+    // One render at a time, and nothing written next to it
+    AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"))
+        .arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg("-")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--stems"));
+    AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"))
+        .arg("-i")
+        .arg("tests/modules/cndmcrrp.mod")
+        .arg("-o")
+        .arg("-")
+        .arg("--full-mix")
+        .arg("--write-metadata")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--write-metadata"));
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn test_gzipped_module() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::Write;
+
+    let dir = tempdir()?;
+    let input = dir.path().join("cndmcrrp.mod.gz");
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&fs::read("tests/modules/cndmcrrp.mod")?)?;
+    fs::write(&input, encoder.finish()?)?;
+
+    let out_dir = dir.path().join("stems");
+    AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"))
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(&out_dir)
+        .arg("--stems")
+        .arg("1")
+        .arg("--preview")
+        .arg("1")
+        .assert()
+        .success();
+
+    // Named after the module, not the archive
+    assert!(out_dir.join("cndmcrrp_sample_001.wav").exists());
+    Ok(())
+}
+
+#[test]
+fn test_probe() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    write_fixture(
+        in_dir.path(),
+        "song.mod",
+        FixtureModule::song(3, 1).to_mod(),
+    );
+    fs::write(in_dir.path().join("notes.txt"), "not a module")?;
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("probe")
+        .arg(in_dir.path().join("song.mod"))
+        .arg("--json");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let probe: serde_json::Value = serde_json::from_slice(&output)?;
+    assert_eq!(probe["supported"], true);
+    assert_eq!(probe["format"], "mod");
+    assert_eq!(probe["channels"], 4);
+    assert_eq!(probe["samples"], 3);
+    assert!(probe["duration"].as_f64().unwrap() > 0.0);
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("probe").arg(in_dir.path().join("notes.txt"));
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("not a supported module"));
+    Ok(())
+}
+
+#[test]
+fn test_subcommands() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let module = write_fixture(dir.path(), "song.mod", FixtureModule::song(3, 1).to_mod());
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("list").arg(&module).arg("--json");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let listing: serde_json::Value = serde_json::from_slice(&output)?;
+    assert_eq!(listing["samples"].as_array().unwrap().len(), 3);
+
+    let out_dir = dir.path().join("stems");
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("extract")
+        .arg("-i")
+        .arg(&module)
+        .arg("-o")
+        .arg(&out_dir)
+        .arg("--stems")
+        .arg("1");
+    cmd.assert().success();
+    assert!(out_dir.join("song_sample_001.wav").exists());
+
+    // A batch of one still gets its subdirectory and manifest line
+    let batch_dir = dir.path().join("batch");
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("batch")
+        .arg("-i")
+        .arg(&module)
+        .arg("-o")
+        .arg(&batch_dir)
+        .arg("--stems")
+        .arg("1");
+    cmd.assert().success();
+    assert!(batch_dir.join("song").join("song_sample_001.wav").exists());
+    assert!(batch_dir.join("manifest.jsonl").exists());
+    Ok(())
+}
+
+#[test]
+fn test_single_pass() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    let input = write_fixture(in_dir.path(), "song.xm", FixtureModule::song(3, 1).to_xm());
+    let out_dir = tempdir()?;
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--single-pass")
+        .arg("--jobs")
+        .arg("2");
+    cmd.assert().success();
+
+    let lengths = fs::read_dir(out_dir.path())?
+        .map(|entry| Ok(WavReader::open(entry?.path())?.duration()))
+        .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+    assert_eq!(lengths.len(), 3);
+    assert!(lengths.iter().all(|&length| length == lengths[0]));
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--single-pass")
+        .arg("--normalize")
+        .arg("peak");
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_render_cache_from_env() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let cache_dir = dir.path().join("cache");
+    let extract = |no_cache: bool| {
+        let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+        cmd.env("UNTRACKER_CACHE_DIR", &cache_dir)
+            .arg("-i")
+            .arg("tests/modules/cndmcrrp.mod")
+            .arg("-o")
+            .arg(dir.path().join("stems"))
+            .arg("--stems")
+            .arg("1-2")
+            .arg("--preview")
+            .arg("1s")
+            .arg("--overwrite");
+        if no_cache {
+            cmd.arg("--no-cache");
+        }
+        cmd.assert().success();
+    };
+
+    extract(true);
+    assert!(!cache_dir.exists());
+    extract(false);
+    // One audio file and one result file per stem
+    assert_eq!(fs::read_dir(&cache_dir)?.count(), 4);
+    Ok(())
+}
+
+#[test]
+fn test_watch_module() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let module = write_fixture(dir.path(), "song.mod", FixtureModule::song(2, 1).to_mod());
+    let out_dir = dir.path().join("stems");
+
+    let watch = || {
+        let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+        cmd.arg("watch")
+            .arg(&module)
+            .arg("-o")
+            .arg(&out_dir)
+            .arg("--once");
+        cmd
+    };
+    watch()
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2 of 2 stems changed"));
+    let stem = out_dir.join("song_sample_001.wav");
+    let modified = fs::metadata(&stem)?.modified()?;
+
+    // Extracting the same module again leaves the stems alone
+    watch()
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("0 of 2 stems changed"));
+    assert_eq!(fs::metadata(&stem)?.modified()?, modified);
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("watch")
+        .arg(&module)
+        .arg("-i")
+        .arg(dir.path())
+        .arg("-o")
+        .arg(&out_dir);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_job_file() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    write_fixture(
+        in_dir.path(),
+        "first.mod",
+        FixtureModule::song(3, 1).to_mod(),
+    );
+    write_fixture(
+        in_dir.path(),
+        "second.xm",
+        FixtureModule::song(2, 1).to_xm(),
+    );
+    let job_file = in_dir.path().join("jobs.json");
+    fs::write(
+        &job_file,
+        r#"[
+            {"input": "first.mod", "output": "one", "options": {"sample-rate": 22050}, "stems": "2"},
+            {"input": "second.xm"},
+            {"input": "missing.it"}
+        ]"#,
+    )?;
+    let out_dir = tempdir()?;
+
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("batch")
+        .arg("--job-file")
+        .arg(&job_file)
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--preview")
+        .arg("1s")
+        .arg("--jobs")
+        .arg("2");
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("first.mod: 1 stems"))
+        .stderr(predicate::str::contains("1 of 3 jobs failed"));
+
+    let stems: Vec<_> = fs::read_dir(out_dir.path().join("one"))?.collect();
+    assert_eq!(stems.len(), 1);
+    let reader = WavReader::open(out_dir.path().join("one/first_sample_002.wav"))?;
+    assert_eq!(reader.spec().sample_rate, 22050);
+    assert!(out_dir.path().join("second/second_sample_002.wav").exists());
+
+    let results: serde_json::Value = serde_json::from_str(&fs::read_to_string(
+        out_dir.path().join("job-results.json"),
+    )?)?;
+    assert_eq!(
+        (results["done"].as_u64(), results["failed"].as_u64()),
+        (Some(2), Some(1))
+    );
+    assert_eq!(results["jobs"][2]["status"], "failed");
+    assert!(results["jobs"][2]["error"].is_string());
+    assert!(out_dir.path().join("manifest.jsonl").exists());
+
+    // A job file is for batch only
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("extract")
+        .arg("--job-file")
+        .arg(&job_file)
+        .arg("-o")
+        .arg(out_dir.path());
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_job_file_same_output() -> Result<(), Box<dyn std::error::Error>> {
+    let in_dir = tempdir()?;
+    for dir in ["a", "b"] {
+        fs::create_dir(in_dir.path().join(dir))?;
+        write_fixture(
+            &in_dir.path().join(dir),
+            "song.mod",
+            FixtureModule::song(2, 1).to_mod(),
+        );
+    }
+    let job_file = in_dir.path().join("jobs.json");
+    fs::write(
+        &job_file,
+        r#"[{"input": "a/song.mod"}, {"input": "b/song.mod"}]"#,
+    )?;
+    let out_dir = tempdir()?;
+
+    // Both would be written to song/, so nothing is rendered
+    let mut cmd = AssertCommand::new(assert_cmd::cargo::cargo_bin("untracker"));
+    cmd.arg("batch")
+        .arg("--job-file")
+        .arg(&job_file)
+        .arg("-o")
+        .arg(out_dir.path())
+        .arg("--jobs")
+        .arg("2");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("overwriting each other"));
+    assert!(!out_dir.path().join("song").exists());
+    Ok(())
+}